@@ -0,0 +1,127 @@
+use crate::config::Config;
+use crate::index::Index;
+use crate::repository::Repository;
+use crate::write_tree::is_tree_dir;
+use anyhow::{Context, Result, bail};
+use ignore::WalkBuilder;
+use std::fs::symlink_metadata;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// `git add <pathspec>...`: stage the files named by `paths` as they are in the
+/// working directory. A directory (`.` for the whole tree) stands for all the files
+/// under it that aren't ignored, and files under a path that are in the index but
+/// gone from the working directory are removed from it, as `git add` has done since
+/// git 2.0.
+///
+/// Paths are relative to the top of the work tree. A file named explicitly is added
+/// even if it is ignored. Nothing is staged unless every path matches something.
+/// With `core.ignoreCase`, a file tracked under a name that differs only in case is
+/// updated under that name.
+pub fn git_add(repo: &Repository, paths: &[String]) -> Result<()> {
+    let ignore_case = Config::load(repo)?
+        .get_bool("core.ignoreCase")?
+        .unwrap_or(false);
+    let mut index = Index::load(repo)?;
+    for path in paths {
+        add_pathspec(repo, &mut index, path, ignore_case)?;
+    }
+    index.save(repo)
+}
+
+fn add_pathspec(
+    repo: &Repository,
+    index: &mut Index,
+    pathspec: &str,
+    ignore_case: bool,
+) -> Result<()> {
+    let path = pathspec.strip_prefix("./").unwrap_or(pathspec);
+    let path = path.trim_end_matches('/');
+    let whole_tree = matches!(path, "" | ".");
+    if !whole_tree
+        && !path
+            .split('/')
+            .all(|part| !matches!(part, "" | "." | ".." | ".git"))
+    {
+        bail!("invalid path '{pathspec}'");
+    }
+    let under = |file: &str| {
+        whole_tree
+            || file == path
+            || file
+                .strip_prefix(path)
+                .is_some_and(|rest| rest.starts_with('/'))
+    };
+
+    let mut matched = index.entries.iter().any(|entry| under(&entry.path));
+    match symlink_metadata(repo.path(path)) {
+        Ok(_) if whole_tree => {
+            add_dir(repo, index, &repo.work_tree, ignore_case)?;
+            matched = true;
+        }
+        Ok(metadata) if metadata.is_dir() && is_tree_dir(&repo.path(path)) => {
+            add_dir(repo, index, &repo.path(path), ignore_case)?;
+            matched = true;
+        }
+        Ok(_) => {
+            let path = tracked_name(repo, index, path, ignore_case);
+            index.add_path(repo, &path)?;
+            matched = true;
+        }
+        Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) => {}
+        Err(e) => return Err(e).with_context(|| format!("failed to stat {path}")),
+    }
+    if !matched {
+        bail!("pathspec '{pathspec}' did not match any files");
+    }
+
+    let gone: Vec<String> = index
+        .entries
+        .iter()
+        .filter(|entry| under(&entry.path) && symlink_metadata(repo.path(&entry.path)).is_err())
+        .map(|entry| entry.path.clone())
+        .collect();
+    for path in gone {
+        index.remove(&path);
+    }
+    Ok(())
+}
+
+/// Stage every file under the work tree's directory `dir` that isn't ignored, by the
+/// same rules as [`crate::write_tree::git_write_tree_with_path`]: submodules are added
+/// as the commit they have checked out rather than descended into.
+fn add_dir(repo: &Repository, index: &mut Index, dir: &Path, ignore_case: bool) -> Result<()> {
+    let walker = WalkBuilder::new(dir)
+        .max_depth(Some(1))
+        .hidden(false)
+        .build();
+    for entry in walker {
+        let entry = entry?;
+        if entry.depth() == 0 || entry.file_name() == ".git" {
+            continue;
+        }
+        let path = entry.path();
+        if is_tree_dir(path) {
+            add_dir(repo, index, path, ignore_case)?;
+            continue;
+        }
+        let path = path.strip_prefix(&repo.work_tree).unwrap_or(path);
+        let Some(path) = path.to_str() else {
+            bail!("path is not UTF-8: {}", path.display());
+        };
+        let path = tracked_name(repo, index, path, ignore_case);
+        index.add_path(repo, &path)?;
+    }
+    Ok(())
+}
+
+/// The name to stage the file at `path` under: with `ignore_case`, that of an entry
+/// differing from it only in case, as long as the filesystem finds the file under
+/// that name too (which a case-sensitive one with `core.ignoreCase` set wouldn't).
+fn tracked_name(repo: &Repository, index: &Index, path: &str, ignore_case: bool) -> String {
+    let name = index.existing_path(path, ignore_case);
+    match name != path && symlink_metadata(repo.path(name)).is_err() {
+        true => path.to_string(),
+        false => name.to_string(),
+    }
+}
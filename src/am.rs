@@ -0,0 +1,508 @@
+use crate::add::git_add;
+use crate::apply::{
+    Applied, FileContent, FilePatch, apply_file, apply_hunks, check_patch_paths, parse_patch,
+    preimage_blob, print_errors, read_worktree_file, write_applied,
+};
+use crate::checkout::reset_worktree;
+use crate::commit::CommitBuilder;
+use crate::config::Config;
+use crate::ident::{Ident, Role, parse_date, resolve_ident};
+use crate::mailinfo::{MailInfo, git_mailsplit, parse_mail};
+use crate::merge::{MergeResult, merge3};
+use crate::refs::{current_branch, delete_ref, resolve_ref, update_ref};
+use crate::repository::Repository;
+use crate::worktree::is_dirty;
+use crate::write_tree::git_write_tree;
+use anyhow::{Context, Result, bail};
+use std::fs::{read, read_to_string, remove_dir_all, remove_file, write};
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Where `am` keeps the mails it is applying and how far it got, so that it can stop
+/// at a patch that doesn't apply and carry on once the user has fixed things.
+///
+/// It holds the split mails (`0001`, `0002`, ...), `next` and `last` (the number of
+/// the mail being applied and of the final one), `orig-head` (HEAD before the first
+/// patch, empty on an unborn branch), the current mail's `patch`, `threeway` when
+/// `-3` was given, and after a failed merge the `unmerged` paths.
+fn state_dir(repo: &Repository) -> Result<PathBuf> {
+    Ok(repo.git_path("rebase-apply"))
+}
+
+const RESOLVE_HELP: &str = "When you have resolved this problem, run \"git am --continue\".\n\
+If you prefer to skip this patch, run \"git am --skip\" instead.\n\
+To restore the original branch and stop patching, run \"git am --abort\".";
+
+#[derive(Debug, Default)]
+pub struct AmOptions {
+    /// When a patch doesn't apply, merge it with HEAD starting from the blobs its
+    /// `index` lines name.
+    pub three_way: bool,
+}
+
+/// `git am`: commit each patch mailed in `mboxes` (stdin if none) on top of HEAD, with
+/// the mail's sender, date and subject as the commit's author and message.
+///
+/// Stops at the first patch that doesn't apply, leaving its state in
+/// `.git/rebase-apply` for [`git_am_continue`], [`git_am_skip`] or [`git_am_abort`].
+/// With no mboxes while patches are in progress, the current one is tried again.
+///
+/// Patches are applied to the working tree, which is staged as a whole for each
+/// commit, so it has to be clean to start with. What git reports about each patch
+/// goes to `out` and `err`.
+pub fn git_am(
+    repo: &Repository,
+    config: &Config,
+    out: &mut impl Write,
+    err: &mut impl Write,
+    mboxes: &[String],
+    options: &AmOptions,
+) -> Result<()> {
+    let state = state_dir(repo)?;
+    let state = state.as_path();
+    if state.exists() {
+        if !mboxes.is_empty() {
+            bail!(
+                "previous rebase directory {} still exists but mbox given.",
+                state.display()
+            );
+        }
+        return run(repo, config, out, err);
+    }
+    if is_dirty(repo)? {
+        bail!("Dirty working tree: cannot apply patches; commit or stash your changes first");
+    }
+    let last = match git_mailsplit(state, mboxes) {
+        Ok(last) => last,
+        Err(e) => {
+            let _ = remove_dir_all(state);
+            return Err(e);
+        }
+    };
+    if last == 0 {
+        remove_dir_all(state)?;
+        bail!("Patch format detection failed.");
+    }
+    let head = resolve_ref(repo, "HEAD")?;
+    if let Some(head) = &head {
+        write(repo.git_path("ORIG_HEAD"), format!("{head}\n"))?;
+    }
+    write_state(repo, "orig-head", head.as_deref().unwrap_or(""))?;
+    write_state(repo, "next", "1")?;
+    write_state(repo, "last", last.to_string())?;
+    if options.three_way {
+        write_state(repo, "threeway", "")?;
+    }
+    run(repo, config, out, err)
+}
+
+/// `git am --continue`: commit the current patch as the user has fixed it up in the
+/// working tree, then go on with the rest.
+pub fn git_am_continue(
+    repo: &Repository,
+    config: &Config,
+    out: &mut impl Write,
+    err: &mut impl Write,
+) -> Result<()> {
+    let next = in_progress(repo)?;
+    let info = current_mail(repo, next)?;
+    let unmerged: Vec<String> = read_state(repo, "unmerged")
+        .unwrap_or_default()
+        .lines()
+        .filter(|path| has_conflict_markers(repo, path))
+        .map(str::to_string)
+        .collect();
+    if !unmerged.is_empty() {
+        bail!(
+            "You still have unmerged paths: {}\n\
+             Resolve their conflicts before running \"git am --continue\".",
+            unmerged.join(" ")
+        );
+    }
+    writeln!(out, "Applying: {}", info.subject)?;
+    if !is_dirty(repo)? {
+        writeln!(
+            out,
+            "No changes - did you forget to use 'git add'?\n\
+             If there is nothing left to stage, chances are that something else\n\
+             already introduced the same changes; you might want to skip this patch."
+        )?;
+        bail!("{RESOLVE_HELP}");
+    }
+    commit_patch(repo, config, &info)?;
+    advance(repo, next)?;
+    run(repo, config, out, err)
+}
+
+/// `git am --skip`: drop whatever the current patch left in the working tree and go on
+/// with the next one.
+pub fn git_am_skip(
+    repo: &Repository,
+    config: &Config,
+    out: &mut impl Write,
+    err: &mut impl Write,
+) -> Result<()> {
+    let next = in_progress(repo)?;
+    reset_worktree(repo, resolve_ref(repo, "HEAD")?.as_deref())?;
+    advance(repo, next)?;
+    run(repo, config, out, err)
+}
+
+/// `git am --abort`: put the branch and the working tree back as they were before the
+/// first patch.
+pub fn git_am_abort(repo: &Repository, config: &Config) -> Result<()> {
+    in_progress(repo)?;
+    let orig_head = read_state(repo, "orig-head")?;
+    let orig_head = orig_head.trim();
+    if orig_head.is_empty() {
+        reset_worktree(repo, None)?;
+        if let Some(branch) = current_branch(repo)? {
+            delete_ref(repo, &format!("refs/heads/{branch}"))?;
+        }
+    } else {
+        reset_worktree(repo, Some(orig_head))?;
+        update_ref(repo, config, "HEAD", orig_head, "am --abort")?;
+    }
+    let state = state_dir(repo)?;
+    remove_dir_all(&state).with_context(|| format!("failed to remove {}", state.display()))
+}
+
+/// `git am --show-current-patch`: print the mail being applied (`raw`) or just its
+/// patch (`diff`).
+pub fn git_am_show_current_patch(repo: &Repository, format: &str) -> Result<Vec<u8>> {
+    let next = in_progress(repo)?;
+    match format {
+        "raw" => read_state_bytes(repo, &mail_name(next)),
+        "diff" => read_state_bytes(repo, "patch"),
+        _ => bail!("invalid value for --show-current-patch: {format}"),
+    }
+}
+
+/// Apply and commit the mails from `next` on, stopping at one that fails.
+fn run(
+    repo: &Repository,
+    config: &Config,
+    out: &mut impl Write,
+    err: &mut impl Write,
+) -> Result<()> {
+    let last: usize = read_state(repo, "last")?.trim().parse()?;
+    let three_way = state_path(repo, "threeway")?.exists();
+    loop {
+        let next: usize = read_state(repo, "next")?.trim().parse()?;
+        if next > last {
+            break;
+        }
+        let _ = remove_file(state_path(repo, "unmerged")?);
+        let info = current_mail(repo, next)?;
+        write_state(repo, "patch", &info.patch)?;
+        writeln!(out, "Applying: {}", info.subject)?;
+        if info.patch.is_empty() {
+            writeln!(out, "Patch is empty.")?;
+            bail!("{RESOLVE_HELP}");
+        }
+        if !apply_mail(repo, out, err, &info, three_way)? {
+            writeln!(out, "Patch failed at {:04} {}", next, info.subject)?;
+            writeln!(
+                err,
+                "hint: Use 'git am --show-current-patch=diff' to see the failed patch"
+            )?;
+            bail!("{RESOLVE_HELP}");
+        }
+        commit_patch(repo, config, &info)?;
+        advance(repo, next)?;
+    }
+    let state = state_dir(repo)?;
+    remove_dir_all(&state).with_context(|| format!("failed to remove {}", state.display()))
+}
+
+/// Apply the mail's patch to the working tree, falling back to a 3-way merge if asked
+/// to. Returns whether it went in cleanly.
+fn apply_mail(
+    repo: &Repository,
+    out: &mut impl Write,
+    err: &mut impl Write,
+    info: &MailInfo,
+    three_way: bool,
+) -> Result<bool> {
+    let patches = parse_patch(&info.patch)?;
+    let errors = check_patch_paths(repo, &patches);
+    if !errors.is_empty() {
+        print_errors(err, &errors)?;
+        return Ok(false);
+    }
+    let mut applied = Vec::new();
+    let mut failed = false;
+    for patch in &patches {
+        match apply_file(repo, patch, None)? {
+            Ok(result) => applied.push(result),
+            // With a merge to fall back on, a patch that doesn't apply isn't an error yet.
+            Err(errors) => {
+                if !three_way {
+                    print_errors(err, &errors)?;
+                }
+                failed = true;
+            }
+        }
+    }
+    if !failed {
+        write_applied(repo, &applied)?;
+        return Ok(true);
+    }
+    if !three_way {
+        return Ok(false);
+    }
+    merge_mail(repo, out, err, &patches, &info.subject)
+}
+
+/// The `--3way` fallback: apply each patch to the blob it was made against, then merge
+/// the result into the working tree's version. Conflicted files are written with
+/// conflict markers and listed in `unmerged`.
+fn merge_mail(
+    repo: &Repository,
+    out: &mut impl Write,
+    err: &mut impl Write,
+    patches: &[FilePatch],
+    subject: &str,
+) -> Result<bool> {
+    writeln!(err, "Using index info to reconstruct a base tree...")?;
+    let mut bases = Vec::new();
+    for patch in patches {
+        let ours = match &patch.old_path {
+            Some(old_path) => read_worktree_file(repo, old_path)?,
+            None => patch
+                .new_path
+                .as_deref()
+                .map(|path| read_worktree_file(repo, path))
+                .transpose()?
+                .flatten(),
+        };
+        // A patch that only changes the mode is made against the current file.
+        let base = match (&patch.old_path, &ours) {
+            (Some(_), Some(ours)) if !patch.changes_content() => Ok(Some(ours.data.clone())),
+            (Some(path), None) if !patch.changes_content() => Err(format!(
+                "mode change for {path}, which is not in current HEAD"
+            )),
+            _ => preimage_blob(repo, patch),
+        };
+        match base {
+            Ok(base) => bases.push((base, ours)),
+            Err(error) => {
+                print_errors(err, &[error, "could not build fake ancestor".to_string()])?;
+                return Ok(false);
+            }
+        }
+    }
+    let mut sides = Vec::new();
+    for (patch, (base, ours)) in patches.iter().zip(bases) {
+        if let (Some(base), Some(ours)) = (&base, &ours)
+            && *base != ours.data
+        {
+            writeln!(out, "M\t{}", patch.path())?;
+        }
+        let theirs = match apply_hunks(repo, patch, base.as_deref().unwrap_or_default()) {
+            Ok(theirs) => theirs,
+            Err(error) => {
+                print_errors(
+                    err,
+                    &[error, format!("{}: patch does not apply", patch.path())],
+                )?;
+                return Ok(false);
+            }
+        };
+        sides.push((patch, base, ours, theirs));
+    }
+    writeln!(err, "Falling back to patching base and 3-way merge...")?;
+
+    let mut applied = Vec::new();
+    let mut unmerged = Vec::new();
+    for (patch, base, ours, theirs) in sides {
+        let path = patch.path();
+        let mode = |ours: Option<&FileContent>| {
+            patch
+                .new_mode
+                .clone()
+                .or_else(|| ours.map(|ours| ours.mode.clone()))
+                .unwrap_or_else(|| "100644".to_string())
+        };
+        let remove = patch
+            .old_path
+            .clone()
+            .filter(|old_path| patch.new_path.as_ref() != Some(old_path));
+        let take_theirs = || Applied {
+            remove: remove.clone(),
+            new: patch.new_path.clone().map(|new_path| {
+                let mode = mode(ours.as_ref());
+                (
+                    new_path,
+                    FileContent {
+                        mode,
+                        data: theirs.clone(),
+                    },
+                )
+            }),
+        };
+        match (&base, &ours) {
+            // Unchanged here since the patch was made: the patch's version wins.
+            (Some(base), Some(our)) if *base == our.data => {
+                if patch.new_path.is_none() {
+                    writeln!(out, "Removing {path}")?;
+                }
+                applied.push(take_theirs());
+            }
+            (None, None) => applied.push(take_theirs()),
+            (Some(_), None) => {
+                writeln!(
+                    out,
+                    "CONFLICT (modify/delete): {path} deleted in HEAD and modified in {subject}. \
+                     Version {subject} of {path} left in tree."
+                )?;
+                unmerged.push(path.to_string());
+                applied.push(take_theirs());
+            }
+            (_, Some(our)) if patch.new_path.is_none() => {
+                writeln!(
+                    out,
+                    "CONFLICT (modify/delete): {path} deleted in {subject} and modified in HEAD. \
+                     Version HEAD of {path} left in tree."
+                )?;
+                unmerged.push(path.to_string());
+                applied.push(Applied {
+                    remove: None,
+                    new: Some((path.to_string(), our.clone())),
+                });
+            }
+            (base, Some(our)) => {
+                let base = base.as_deref().unwrap_or_default();
+                writeln!(out, "Auto-merging {path}")?;
+                let merged = if !patch.binary {
+                    merge3(base, &our.data, &theirs, "HEAD", subject)
+                } else if theirs == our.data {
+                    MergeResult {
+                        content: theirs,
+                        conflicts: 0,
+                    }
+                } else {
+                    writeln!(
+                        err,
+                        "warning: Cannot merge binary files: {path} (HEAD vs. {subject})"
+                    )?;
+                    MergeResult {
+                        content: our.data.clone(),
+                        conflicts: 1,
+                    }
+                };
+                if merged.conflicts > 0 {
+                    let kind = if patch.old_path.is_none() {
+                        "add/add"
+                    } else {
+                        "content"
+                    };
+                    writeln!(out, "CONFLICT ({kind}): Merge conflict in {path}")?;
+                    unmerged.push(path.to_string());
+                }
+                applied.push(Applied {
+                    remove,
+                    new: patch.new_path.clone().map(|new_path| {
+                        let mode = mode(Some(our));
+                        (
+                            new_path,
+                            FileContent {
+                                mode,
+                                data: merged.content,
+                            },
+                        )
+                    }),
+                });
+            }
+        }
+    }
+    write_applied(repo, &applied)?;
+    if !unmerged.is_empty() {
+        write_state(repo, "unmerged", format!("{}\n", unmerged.join("\n")))?;
+        writeln!(err, "error: Failed to merge in the changes.")?;
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// Stage the working tree, as the patch left it, and commit it on top of HEAD,
+/// authored by the patch's sender.
+fn commit_patch(repo: &Repository, config: &Config, info: &MailInfo) -> Result<()> {
+    let (timestamp, timezone) = match &info.date {
+        Some(date) => parse_date(date)?,
+        None => {
+            let now = resolve_ident(Role::Author, config)?;
+            (now.timestamp, now.timezone)
+        }
+    };
+    let author = Ident {
+        name: info.author_name.clone(),
+        email: info.author_email.clone(),
+        timestamp,
+        timezone,
+    };
+    git_add(repo, &[".".to_string()])?;
+    let tree = git_write_tree(repo)?.to_string();
+    let parent = resolve_ref(repo, "HEAD")?;
+    let commit = parent
+        .into_iter()
+        .fold(CommitBuilder::new(tree), CommitBuilder::parent)
+        .author(author)
+        .message(info.message())
+        .write(repo, config)?;
+    update_ref(
+        repo,
+        config,
+        "HEAD",
+        &commit.to_string(),
+        &format!("am: {}", info.subject),
+    )
+}
+
+fn has_conflict_markers(repo: &Repository, path: &str) -> bool {
+    read_to_string(repo.path(path)).is_ok_and(|content| {
+        content
+            .lines()
+            .any(|line| line.starts_with("<<<<<<< ") || line.starts_with(">>>>>>> "))
+    })
+}
+
+/// The number of the mail being applied, failing if no `am` is in progress.
+fn in_progress(repo: &Repository) -> Result<usize> {
+    if !state_dir(repo)?.exists() {
+        bail!("Resolve operation not in progress, we are not resuming.");
+    }
+    Ok(read_state(repo, "next")?.trim().parse()?)
+}
+
+fn advance(repo: &Repository, next: usize) -> Result<()> {
+    let _ = remove_file(state_path(repo, "unmerged")?);
+    write_state(repo, "next", (next + 1).to_string())
+}
+
+fn current_mail(repo: &Repository, next: usize) -> Result<MailInfo> {
+    parse_mail(&read_state_bytes(repo, &mail_name(next))?)
+}
+
+fn mail_name(number: usize) -> String {
+    format!("{number:04}")
+}
+
+fn state_path(repo: &Repository, name: &str) -> Result<PathBuf> {
+    Ok(state_dir(repo)?.join(name))
+}
+
+fn read_state(repo: &Repository, name: &str) -> Result<String> {
+    let path = state_path(repo, name)?;
+    read_to_string(&path).with_context(|| format!("could not read {}", path.display()))
+}
+
+fn read_state_bytes(repo: &Repository, name: &str) -> Result<Vec<u8>> {
+    let path = state_path(repo, name)?;
+    read(&path).with_context(|| format!("could not read {}", path.display()))
+}
+
+fn write_state(repo: &Repository, name: &str, content: impl AsRef<[u8]>) -> Result<()> {
+    let path = state_path(repo, name)?;
+    write(&path, content).with_context(|| format!("could not write {}", path.display()))
+}
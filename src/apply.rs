@@ -0,0 +1,1103 @@
+use crate::base85;
+use crate::checkout::{check_worktree_path, remove_worktree_file};
+use crate::delta::apply_delta;
+use crate::diff::split_lines;
+use crate::fsck::check_entry_name;
+use crate::index::has_symlinks;
+use crate::merge::merge3;
+use crate::object_read::{Object, ObjectKind, full_object_id, max_object_size};
+use crate::repository::Repository;
+use crate::whitespace::{WhitespaceErrors, WhitespaceRule, is_blank_line};
+use anyhow::{Context, Result, bail, ensure};
+use flate2::read::ZlibDecoder;
+use std::collections::HashMap;
+use std::fs::{OpenOptions, create_dir_all, read, read_link, remove_file, symlink_metadata};
+use std::io::{Cursor, ErrorKind, Read, Write, sink, stdin};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt, symlink};
+use std::path::Path;
+
+/// The changes a patch makes to one file, as parsed from a (git or traditional)
+/// unified diff.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilePatch {
+    /// The path before the change, `None` for a file the patch creates.
+    pub old_path: Option<String>,
+    /// The path after the change, `None` for a file the patch deletes.
+    pub new_path: Option<String>,
+    pub old_mode: Option<String>,
+    pub new_mode: Option<String>,
+    /// The (usually abbreviated) blob ids of the `index` line, if there is one.
+    pub old_blob: Option<String>,
+    pub new_blob: Option<String>,
+    pub hunks: Vec<PatchHunk>,
+    /// Whether the file is binary, changed by `binary_hunk` rather than `hunks`.
+    pub binary: bool,
+    /// The forward half of a `GIT binary patch`; `None` for `Binary files ... differ`,
+    /// which can only be applied if the new blob is in the repository.
+    pub binary_hunk: Option<BinaryHunk>,
+}
+
+/// The data of a binary patch, inflated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BinaryHunk {
+    /// The whole new content.
+    Literal(Vec<u8>),
+    /// A delta against the old content.
+    Delta(Vec<u8>),
+}
+
+/// One `@@ -old_start,old_len +new_start,new_len @@` hunk. Line numbers are 1-based
+/// as written; a side of length 0 starts after the given line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchHunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub lines: Vec<PatchLine>,
+}
+
+/// A line of a hunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchLine {
+    /// `b' '`, `b'-'` or `b'+'`.
+    pub prefix: u8,
+    /// The line without its prefix, with its newline unless a
+    /// `\ No newline at end of file` line follows it.
+    pub content: Vec<u8>,
+    /// Where in the patch input the line is (1-based), for messages.
+    pub number: usize,
+}
+
+impl FilePatch {
+    /// The path the patch is known by: the new one, or the old one for a deletion.
+    pub fn path(&self) -> &str {
+        self.new_path
+            .as_deref()
+            .or(self.old_path.as_deref())
+            .unwrap_or("")
+    }
+
+    /// Whether the patch changes the file's content, not just its mode or name.
+    pub fn changes_content(&self) -> bool {
+        !self.hunks.is_empty() || self.binary
+    }
+
+    /// Whether the patch is to a submodule, whose hunks change the commit it has
+    /// checked out (`Subproject commit <id>`) rather than any file.
+    pub fn is_gitlink(&self) -> bool {
+        self.old_mode.as_deref() == Some("160000") || self.new_mode.as_deref() == Some("160000")
+    }
+}
+
+/// A file's content and mode (`100644`, `100755` or `120000`, whose content is the
+/// link target).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileContent {
+    pub mode: String,
+    pub data: Vec<u8>,
+}
+
+/// What a patch turns one file into: `old_path` goes away (a deletion or rename),
+/// and `new` is written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Applied {
+    pub remove: Option<String>,
+    pub new: Option<(String, FileContent)>,
+}
+
+#[derive(Debug, Default)]
+pub struct ApplyOptions {
+    /// Only check that the patches apply.
+    pub check: bool,
+    /// Fall back to a 3-way merge using the blobs named on `index` lines for files
+    /// whose hunks don't apply.
+    pub three_way: bool,
+    pub whitespace: WhitespaceAction,
+    /// What counts as a whitespace error (`core.whitespace`).
+    pub whitespace_rule: WhitespaceRule,
+}
+
+/// What `apply` does about added lines with whitespace errors (`--whitespace`, or
+/// `apply.whitespace`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WhitespaceAction {
+    /// Don't look for errors.
+    NoWarn,
+    /// Report the first few errors and apply the patch as it is.
+    #[default]
+    Warn,
+    /// Report the first few errors and apply the patch with them fixed.
+    Fix,
+    /// Report the first few errors and refuse to apply the patch.
+    Error,
+    /// Like `Error`, but report every error.
+    ErrorAll,
+}
+
+impl WhitespaceAction {
+    pub fn parse(value: &str) -> Result<WhitespaceAction> {
+        Ok(match value {
+            "nowarn" => WhitespaceAction::NoWarn,
+            "warn" => WhitespaceAction::Warn,
+            "fix" | "strip" => WhitespaceAction::Fix,
+            "error" => WhitespaceAction::Error,
+            "error-all" => WhitespaceAction::ErrorAll,
+            _ => bail!("unrecognized whitespace option '{value}'"),
+        })
+    }
+
+    fn refuses(self) -> bool {
+        matches!(self, WhitespaceAction::Error | WhitespaceAction::ErrorAll)
+    }
+}
+
+/// The whitespace errors found in the lines patches add, reported as they're found:
+/// where in the patch input the line is, what's wrong with it, and the line itself.
+/// The report is collected in `report` until [`git_apply`] passes it on.
+pub struct WhitespaceCheck {
+    action: WhitespaceAction,
+    rule: WhitespaceRule,
+    /// The patch input being read, `<stdin>` for standard input.
+    input: String,
+    errors: usize,
+    /// Added lines that were changed to fix their errors.
+    fixed: usize,
+    report: String,
+}
+
+impl WhitespaceCheck {
+    /// After this many errors the rest are only counted, except with `ErrorAll`.
+    const SQUELCH: usize = 5;
+
+    fn record(&mut self, errors: WhitespaceErrors, number: usize, line: &[u8]) {
+        self.errors += 1;
+        if self.action != WhitespaceAction::ErrorAll && self.errors > Self::SQUELCH {
+            return;
+        }
+        self.report += &format!(
+            "{}:{number}: {}.\n{}\n",
+            self.input,
+            errors.description(),
+            String::from_utf8_lossy(line)
+        );
+    }
+
+    /// Check the lines `patches` add, and when fixing errors the context lines too,
+    /// as those are fixed where they differ from the file only in whitespace errors.
+    /// Blank lines added at the end of a file are only found once the hunks are
+    /// placed.
+    fn check_lines(&mut self, patches: &[FilePatch]) {
+        if self.action == WhitespaceAction::NoWarn {
+            return;
+        }
+        let fixing = self.action == WhitespaceAction::Fix;
+        for line in patches
+            .iter()
+            .flat_map(|patch| &patch.hunks)
+            .flat_map(|hunk| &hunk.lines)
+            .filter(|line| line.prefix == b'+' || (fixing && line.prefix == b' '))
+        {
+            let errors = self.rule.check(&line.content);
+            if !errors.is_empty() {
+                let shown = line.content.strip_suffix(b"\n").unwrap_or(&line.content);
+                self.record(errors, line.number, shown);
+            }
+        }
+    }
+
+    /// Write what was found so far to `err`.
+    fn write_report(&mut self, err: &mut impl Write) -> Result<()> {
+        err.write_all(self.report.as_bytes())?;
+        self.report.clear();
+        Ok(())
+    }
+
+    /// Report how many lines had errors, failing if they stopped the patches from
+    /// being applied.
+    fn finish(&mut self, err: &mut impl Write, applied: bool) -> Result<()> {
+        self.write_report(err)?;
+        let lines = |n: usize| match n {
+            1 => "1 line adds".to_string(),
+            n => format!("{n} lines add"),
+        };
+        if self.errors == 0 {
+            return Ok(());
+        }
+        if self.action != WhitespaceAction::ErrorAll && self.errors > Self::SQUELCH {
+            let squelched = self.errors - Self::SQUELCH;
+            let plural = if squelched == 1 { "" } else { "s" };
+            writeln!(
+                err,
+                "warning: squelched {squelched} whitespace error{plural}"
+            )?;
+        }
+        if self.action.refuses() {
+            bail!("{} whitespace errors.", lines(self.errors));
+        }
+        match self.fixed {
+            0 => writeln!(err, "warning: {} whitespace errors.", lines(self.errors))?,
+            1 if applied => writeln!(
+                err,
+                "warning: 1 line applied after fixing whitespace errors."
+            )?,
+            n if applied => writeln!(
+                err,
+                "warning: {n} lines applied after fixing whitespace errors."
+            )?,
+            _ => writeln!(err, "warning: {} whitespace errors.", lines(self.errors))?,
+        }
+        Ok(())
+    }
+}
+
+/// `git apply`: apply the patches in `paths` (stdin if none) to the working tree.
+///
+/// Either every file a patch input changes is changed or none is: if a hunk doesn't
+/// apply, or a file to be created already exists, nothing is written. With
+/// `three_way`, files that don't take the patch are merged instead, leaving conflict
+/// markers where both sides changed the same lines.
+///
+/// Added lines are checked for whitespace errors, which are reported, fixed, or
+/// stop the patch from being applied as `options.whitespace` says. They and what
+/// else git reports as it applies are written to `err`.
+pub fn git_apply(
+    repo: &Repository,
+    err: &mut impl Write,
+    paths: &[String],
+    options: &ApplyOptions,
+) -> Result<()> {
+    let inputs: Vec<&str> = match paths {
+        [] => vec!["-"],
+        _ => paths.iter().map(String::as_str).collect(),
+    };
+    let mut whitespace = WhitespaceCheck {
+        action: options.whitespace,
+        rule: options.whitespace_rule,
+        input: String::new(),
+        errors: 0,
+        fixed: 0,
+        report: String::new(),
+    };
+    let mut conflicted = Vec::new();
+    for input in inputs {
+        let patches = parse_patch(&read_patch(input)?)?;
+        if patches.is_empty() {
+            bail!("No valid patches in input");
+        }
+        whitespace.input = match input {
+            "-" => "<stdin>".to_string(),
+            _ => input.to_string(),
+        };
+        let errors = check_patch_paths(repo, &patches);
+        if !errors.is_empty() {
+            print_errors(err, &errors)?;
+            bail!("patch failed to apply");
+        }
+        whitespace.check_lines(&patches);
+        whitespace.write_report(err)?;
+        if options.whitespace.refuses() && whitespace.errors > 0 {
+            break;
+        }
+
+        let mut applied = Vec::new();
+        let mut failed = false;
+        for patch in &patches {
+            if options.three_way {
+                if let Some((result, conflicts)) = try_three_way(repo, err, patch)? {
+                    if conflicts == 0 {
+                        writeln!(err, "Applied patch to '{}' cleanly.", patch.path())?;
+                    } else {
+                        writeln!(err, "Applied patch to '{}' with conflicts.", patch.path())?;
+                        conflicted.push(patch.path().to_string());
+                    }
+                    applied.push(result);
+                    continue;
+                }
+                writeln!(err, "Falling back to direct application...")?;
+            }
+            let result = apply_file(repo, patch, Some(&mut whitespace))?;
+            whitespace.write_report(err)?;
+            match result {
+                Ok(result) => applied.push(result),
+                Err(errors) => {
+                    print_errors(err, &errors)?;
+                    failed = true;
+                }
+            }
+        }
+        if failed {
+            bail!("patch failed to apply");
+        }
+        if !options.check {
+            write_applied(repo, &applied)?;
+        }
+    }
+    whitespace.finish(err, !options.check)?;
+    for path in &conflicted {
+        writeln!(err, "U {path}")?;
+    }
+    if !conflicted.is_empty() {
+        bail!("patch applied with conflicts");
+    }
+    Ok(())
+}
+
+/// Write each of `errors` to `err` as git reports what stops a patch.
+pub fn print_errors(err: &mut impl Write, errors: &[String]) -> Result<()> {
+    for error in errors {
+        writeln!(err, "error: {error}")?;
+    }
+    Ok(())
+}
+
+/// What is wrong with the paths of `patches`, which git refuses before applying any of
+/// them: a path that isn't inside the working tree (absolute, or with an empty, `.`,
+/// `..` or `.git` component; see [`check_entry_name`]), and one that goes through a
+/// symlink, either in the working tree or made by an earlier patch of `patches`.
+pub fn check_patch_paths(repo: &Repository, patches: &[FilePatch]) -> Vec<String> {
+    let mut errors = Vec::new();
+    // The paths earlier patches leave behind, and whether each is then a symlink.
+    let mut patched: HashMap<&str, bool> = HashMap::new();
+    for patch in patches {
+        let paths = [patch.old_path.as_deref(), patch.new_path.as_deref()];
+        for path in paths.into_iter().flatten() {
+            if path
+                .split('/')
+                .any(|name| check_entry_name(name.as_bytes()).is_err())
+            {
+                errors.push(format!("invalid path '{path}'"));
+                continue;
+            }
+            let mut leading = 0;
+            while let Some(slash) = path[leading..].find('/') {
+                leading += slash;
+                let dir = &path[..leading];
+                let is_symlink = match patched.get(dir) {
+                    Some(&is_symlink) => is_symlink,
+                    None => symlink_metadata(repo.path(dir)).is_ok_and(|m| m.is_symlink()),
+                };
+                if is_symlink {
+                    errors.push(format!("affected file '{path}' is beyond a symbolic link"));
+                    break;
+                }
+                leading += 1;
+            }
+        }
+        if let Some(old_path) = &patch.old_path {
+            patched.insert(old_path, false);
+        }
+        if let Some(new_path) = &patch.new_path {
+            let is_symlink = match &patch.new_mode {
+                Some(mode) => mode == "120000",
+                None => symlink_metadata(repo.path(new_path)).is_ok_and(|m| m.is_symlink()),
+            };
+            patched.insert(new_path, is_symlink);
+        }
+    }
+    errors
+}
+
+/// Read a patch from a file, or stdin for `-`, exactly as it is: its lines may
+/// end in carriage returns that belong to the files it changes.
+fn read_patch(path: &str) -> Result<Vec<u8>> {
+    if path == "-" {
+        let mut data = Vec::new();
+        stdin()
+            .read_to_end(&mut data)
+            .context("failed to read stdin")?;
+        return Ok(data);
+    }
+    read(path).with_context(|| format!("can't open patch '{path}'"))
+}
+
+/// Parse every file patch in `data`, ignoring anything around them (mail text,
+/// diffstats, signatures).
+pub fn parse_patch(data: &[u8]) -> Result<Vec<FilePatch>> {
+    let lines = split_lines(data);
+    let text = |i: usize| -> String {
+        String::from_utf8_lossy(lines[i])
+            .trim_end_matches('\n')
+            .to_string()
+    };
+    let mut patches = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = text(i);
+        let mut patch = FilePatch::default();
+        if let Some(names) = line.strip_prefix("diff --git ") {
+            i += 1;
+            let (old_name, new_name) = split_git_names(names);
+            patch.old_path = old_name.clone();
+            patch.new_path = new_name;
+            while i < lines.len() {
+                let line = text(i);
+                if line.starts_with("diff --git ") || line.starts_with("@@ ") {
+                    break;
+                }
+                if let Some(mode) = line.strip_prefix("old mode ") {
+                    patch.old_mode = Some(mode.to_string());
+                } else if let Some(mode) = line.strip_prefix("new mode ") {
+                    patch.new_mode = Some(mode.to_string());
+                } else if let Some(mode) = line.strip_prefix("deleted file mode ") {
+                    patch.old_mode = Some(mode.to_string());
+                    patch.new_path = None;
+                } else if let Some(mode) = line.strip_prefix("new file mode ") {
+                    patch.new_mode = Some(mode.to_string());
+                    patch.old_path = None;
+                } else if let Some(path) = line
+                    .strip_prefix("rename from ")
+                    .or_else(|| line.strip_prefix("copy from "))
+                {
+                    patch.old_path = Some(unquote(path));
+                } else if let Some(path) = line
+                    .strip_prefix("rename to ")
+                    .or_else(|| line.strip_prefix("copy to "))
+                {
+                    patch.new_path = Some(unquote(path));
+                } else if let Some(index) = line.strip_prefix("index ") {
+                    let (blobs, mode) = index.split_once(' ').unwrap_or((index, ""));
+                    if let Some((old, new)) = blobs.split_once("..") {
+                        patch.old_blob = Some(old.to_string());
+                        patch.new_blob = Some(new.to_string());
+                    }
+                    if !mode.is_empty() {
+                        patch.old_mode.get_or_insert(mode.to_string());
+                        patch.new_mode.get_or_insert(mode.to_string());
+                    }
+                } else if line.starts_with("Binary files ") && line.ends_with(" differ") {
+                    patch.binary = true;
+                } else if line == "GIT binary patch" {
+                    let (hunk, next) = parse_binary_hunk(&lines, i + 1)?;
+                    // The reverse hunk, if there is one, isn't needed to apply forwards.
+                    i = match lines.get(next) {
+                        Some(line)
+                            if line.starts_with(b"literal ") || line.starts_with(b"delta ") =>
+                        {
+                            parse_binary_hunk(&lines, next)?.1
+                        }
+                        _ => next,
+                    };
+                    patch.binary = true;
+                    patch.binary_hunk = Some(hunk);
+                    break;
+                } else if let Some(name) = line.strip_prefix("--- ") {
+                    patch.old_path = patch_path(name).or_else(|| {
+                        (old_name.is_none() && patch.old_path.is_some())
+                            .then(|| patch.old_path.clone())
+                            .flatten()
+                    });
+                } else if let Some(name) = line.strip_prefix("+++ ") {
+                    patch.new_path = patch_path(name);
+                }
+                i += 1;
+            }
+        } else if line.starts_with("--- ") && i + 1 < lines.len() && text(i + 1).starts_with("+++ ")
+        {
+            patch.old_path = patch_path(&line[4..]);
+            patch.new_path = patch_path(&text(i + 1)[4..]);
+            i += 2;
+        } else {
+            i += 1;
+            continue;
+        }
+        while i < lines.len() && text(i).starts_with("@@ -") {
+            let (hunk, next) = parse_hunk(&lines, i)?;
+            patch.hunks.push(hunk);
+            i = next;
+        }
+        if patch.old_path.is_none() && patch.new_path.is_none() {
+            bail!("patch with neither an old nor a new file");
+        }
+        patches.push(patch);
+    }
+    Ok(patches)
+}
+
+/// The names on a `diff --git a/<old> b/<new>` line, when they can be told apart:
+/// quoted, or the same on both sides.
+fn split_git_names(names: &str) -> (Option<String>, Option<String>) {
+    if let Some(rest) = names.strip_prefix('"') {
+        let Some(end) = rest.find("\" ").map(|end| end + 2) else {
+            return (None, None);
+        };
+        let (old, new) = names.split_at(end);
+        return (patch_path(old), patch_path(new.trim_start()));
+    }
+    let len = names.len();
+    if len % 2 == 1 {
+        let (old, new) = (&names[..len / 2], &names[len / 2 + 1..]);
+        if old.get(2..) == new.get(2..) {
+            return (patch_path(old), patch_path(new));
+        }
+    }
+    (None, None)
+}
+
+/// The path a `---`/`+++` line names, without its first directory (`a/`, `b/`) and
+/// any timestamp after a tab; `None` for `/dev/null`.
+fn patch_path(name: &str) -> Option<String> {
+    let name = unquote(name.split('\t').next().unwrap_or(name).trim_end());
+    if name == "/dev/null" {
+        return None;
+    }
+    Some(match name.split_once('/') {
+        Some((_, path)) => path.to_string(),
+        None => name,
+    })
+}
+
+/// Undo the C-style quoting git uses for paths with unusual characters.
+fn unquote(name: &str) -> String {
+    let Some(quoted) = name.strip_prefix('"').and_then(|n| n.strip_suffix('"')) else {
+        return name.to_string();
+    };
+    let mut out = Vec::new();
+    let bytes = quoted.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' || i + 1 == bytes.len() {
+            out.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        i += 1;
+        match bytes[i] {
+            b'n' => out.push(b'\n'),
+            b't' => out.push(b'\t'),
+            b'"' => out.push(b'"'),
+            b'\\' => out.push(b'\\'),
+            b'0'..=b'7' if i + 2 < bytes.len() => {
+                let octal = std::str::from_utf8(&bytes[i..i + 3]).unwrap_or("0");
+                out.push(u8::from_str_radix(octal, 8).unwrap_or(b'?'));
+                i += 2;
+            }
+            other => out.push(other),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Parse the hunk starting at `lines[start]`, returning it and the index of the line
+/// after it.
+fn parse_hunk(lines: &[&[u8]], start: usize) -> Result<(PatchHunk, usize)> {
+    let header = String::from_utf8_lossy(lines[start]);
+    let range = |part: Option<&str>, sign: char| -> Result<(usize, usize)> {
+        let part = part
+            .and_then(|part| part.strip_prefix(sign))
+            .with_context(|| format!("corrupt patch: bad hunk header {}", header.trim_end()))?;
+        let (start, len) = part.split_once(',').unwrap_or((part, "1"));
+        Ok((start.parse()?, len.parse()?))
+    };
+    let mut parts = header.strip_prefix("@@ ").unwrap_or("").split(' ').take(2);
+    let (old_start, old_len) = range(parts.next(), '-')?;
+    let (new_start, new_len) = range(parts.next(), '+')?;
+
+    let mut hunk = PatchHunk {
+        old_start,
+        old_len,
+        new_start,
+        new_len,
+        lines: Vec::new(),
+    };
+    let (mut old_left, mut new_left) = (old_len, new_len);
+    let mut i = start + 1;
+    while old_left > 0 || new_left > 0 {
+        let Some(line) = lines.get(i) else {
+            bail!("corrupt patch: hunk at line {} is truncated", start + 1);
+        };
+        // Mailers may strip the space of an empty context line.
+        let (prefix, content) = match line.split_first() {
+            Some((b'\n', _)) => (b' ', &b"\n"[..]),
+            Some((&prefix, content)) => (prefix, content),
+            None => bail!("corrupt patch at line {}", i + 1),
+        };
+        match prefix {
+            b' ' if old_left > 0 && new_left > 0 => {
+                old_left -= 1;
+                new_left -= 1;
+            }
+            b'-' if old_left > 0 => old_left -= 1,
+            b'+' if new_left > 0 => new_left -= 1,
+            b'\\' => {
+                strip_newline(&mut hunk);
+                i += 1;
+                continue;
+            }
+            _ => bail!("corrupt patch at line {}", i + 1),
+        }
+        hunk.lines.push(PatchLine {
+            prefix,
+            content: content.to_vec(),
+            number: i + 1,
+        });
+        i += 1;
+    }
+    if lines.get(i).is_some_and(|line| line.starts_with(b"\\")) {
+        strip_newline(&mut hunk);
+        i += 1;
+    }
+    Ok((hunk, i))
+}
+
+/// Parse the `literal <size>` or `delta <size>` hunk of a binary patch starting at
+/// `lines[start]`: lines of base85 data, each starting with its length in bytes
+/// (`A`-`Z` for 1-26, `a`-`z` for 27-52), up to an empty line. Returns the hunk and
+/// the index of the line after the empty one.
+fn parse_binary_hunk(lines: &[&[u8]], start: usize) -> Result<(BinaryHunk, usize)> {
+    let corrupt = |i: usize| anyhow::anyhow!("corrupt binary patch at line {}", i + 1);
+    let header = lines.get(start).map(|line| String::from_utf8_lossy(line));
+    let header = header.as_deref().unwrap_or("").trim_end();
+    let (kind, size) = header.split_once(' ').ok_or_else(|| corrupt(start))?;
+    let size: u64 = size.parse().map_err(|_| corrupt(start))?;
+    let max_size = max_object_size()?;
+    ensure!(
+        size <= max_size,
+        "binary patch at line {} is {size} bytes, exceeding the maximum object size of \
+         {max_size} bytes",
+        start + 1
+    );
+
+    let mut deflated = Vec::new();
+    let mut i = start + 1;
+    loop {
+        let Some(line) = lines.get(i) else {
+            bail!(corrupt(i));
+        };
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        i += 1;
+        let Some((&length, data)) = line.split_first() else {
+            break;
+        };
+        let length = match length {
+            b'A'..=b'Z' => length - b'A' + 1,
+            b'a'..=b'z' => length - b'a' + 27,
+            _ => bail!(corrupt(i - 1)),
+        };
+        deflated.extend(base85::decode(data, length as usize).map_err(|_| corrupt(i - 1))?);
+    }
+    let mut data = Vec::new();
+    ZlibDecoder::new(deflated.as_slice())
+        .take(size + 1)
+        .read_to_end(&mut data)
+        .map_err(|_| corrupt(start))?;
+    if data.len() as u64 != size {
+        bail!(corrupt(start));
+    }
+    let hunk = match kind {
+        "literal" => BinaryHunk::Literal(data),
+        "delta" => BinaryHunk::Delta(data),
+        _ => bail!(corrupt(start)),
+    };
+    Ok((hunk, i))
+}
+
+fn strip_newline(hunk: &mut PatchHunk) {
+    if let Some(line) = hunk.lines.last_mut()
+        && line.content.ends_with(b"\n")
+    {
+        line.content.pop();
+    }
+}
+
+/// Apply `patch`'s hunks to `content`, each where its context matches exactly, as
+/// close as possible to the line it names. The error is git's message for the first
+/// hunk that doesn't fit.
+pub fn apply_hunks(
+    repo: &Repository,
+    patch: &FilePatch,
+    content: &[u8],
+) -> Result<Vec<u8>, String> {
+    apply_hunks_checked(repo, patch, content, None)
+}
+
+/// [`apply_hunks`], reporting blank lines added at the end of the file to
+/// `whitespace`. When fixing whitespace errors, added lines are fixed, and context
+/// matches lines that only differ from it in whitespace errors, which are fixed too.
+fn apply_hunks_checked(
+    repo: &Repository,
+    patch: &FilePatch,
+    content: &[u8],
+    mut whitespace: Option<&mut WhitespaceCheck>,
+) -> Result<Vec<u8>, String> {
+    if patch.binary {
+        return apply_binary(repo, patch, content);
+    }
+    let fix_rule = whitespace
+        .as_ref()
+        .filter(|whitespace| whitespace.action == WhitespaceAction::Fix)
+        .map(|whitespace| whitespace.rule);
+    let same = |target: &[u8], line: &[u8]| {
+        target == line || fix_rule.is_some_and(|rule| rule.fix(target) == rule.fix(line))
+    };
+    let lines = split_lines(content);
+    let mut out = Vec::new();
+    let mut cursor = 0;
+    for hunk in &patch.hunks {
+        let preimage: Vec<&[u8]> = hunk
+            .lines
+            .iter()
+            .filter(|line| line.prefix != b'+')
+            .map(|line| line.content.as_slice())
+            .collect();
+        let leading = hunk.lines.iter().take_while(|l| l.prefix == b' ').count();
+        let trailing = hunk
+            .lines
+            .iter()
+            .rev()
+            .take_while(|l| l.prefix == b' ')
+            .count();
+        // A hunk at the top of the file, or without context after it, has to be there.
+        let match_beginning = hunk.old_start <= 1 && (leading == 0 || hunk.old_start == 1);
+        let match_end = trailing == 0;
+        let expected = match hunk.old_len {
+            0 => hunk.old_start,
+            _ => hunk.old_start.saturating_sub(1),
+        };
+        let fits = |at: usize| {
+            at >= cursor
+                && at + preimage.len() <= lines.len()
+                && lines[at..at + preimage.len()]
+                    .iter()
+                    .zip(&preimage)
+                    .all(|(target, line)| same(target, line))
+                && (!match_beginning || at == 0)
+                && (!match_end || at + preimage.len() == lines.len())
+        };
+        let position = (0..=lines.len()).find_map(|offset| {
+            [expected.checked_add(offset), expected.checked_sub(offset)]
+                .into_iter()
+                .flatten()
+                .find(|&at| fits(at))
+        });
+        let Some(at) = position else {
+            return Err(format!("patch failed: {}:{}", patch.path(), hunk.old_start));
+        };
+        lines[cursor..at]
+            .iter()
+            .for_each(|line| out.extend_from_slice(line));
+
+        let mut postimage = Vec::new();
+        let mut target = at;
+        for line in &hunk.lines {
+            match (line.prefix, fix_rule) {
+                (b' ', Some(rule)) if lines[target] != line.content => {
+                    postimage.push(rule.fix(lines[target]));
+                    target += 1;
+                }
+                (b' ', _) => {
+                    postimage.push(lines[target].to_vec());
+                    target += 1;
+                }
+                (b'-', _) => target += 1,
+                (_, Some(rule)) => {
+                    let fixed = rule.fix(&line.content);
+                    if fixed != line.content
+                        && let Some(whitespace) = whitespace.as_deref_mut()
+                    {
+                        whitespace.fixed += 1;
+                    }
+                    postimage.push(fixed);
+                }
+                _ => postimage.push(line.content.clone()),
+            }
+        }
+        cursor = at + preimage.len();
+        if let Some(whitespace) = whitespace.as_deref_mut()
+            && cursor == lines.len()
+            && whitespace.rule.blank_at_eof
+            && whitespace.action != WhitespaceAction::NoWarn
+            && let Some((blank_lines, number)) = blank_lines_at_end(hunk)
+        {
+            let errors = WhitespaceErrors {
+                blank_at_eof: true,
+                ..WhitespaceErrors::default()
+            };
+            whitespace.record(errors, number, b"+");
+            if fix_rule.is_some() {
+                postimage.truncate(postimage.len() - blank_lines);
+            }
+        }
+        postimage
+            .iter()
+            .for_each(|line| out.extend_from_slice(line));
+    }
+    lines[cursor..]
+        .iter()
+        .for_each(|line| out.extend_from_slice(line));
+    Ok(out)
+}
+
+/// Apply a binary patch to `content`, which has to be the blob the patch names as
+/// its preimage, checking that the result is the blob it names as its postimage. If
+/// that blob is in the repository already it's used as it is.
+fn apply_binary(repo: &Repository, patch: &FilePatch, content: &[u8]) -> Result<Vec<u8>, String> {
+    let path = patch.path();
+    let algorithm = repo.hash_algorithm().map_err(|e| e.to_string())?;
+    let full = |blob: &Option<String>| blob.clone().filter(|blob| algorithm.is_hex_id(blob));
+    let null = algorithm.null_id().to_string();
+    let (Some(old), Some(new)) = (full(&patch.old_blob), full(&patch.new_blob)) else {
+        return Err(format!(
+            "cannot apply binary patch to '{path}' without full index line"
+        ));
+    };
+    if old == null {
+        if !content.is_empty() {
+            return Err(format!(
+                "the patch applies to an empty '{path}' but it is not empty"
+            ));
+        }
+    } else if blob_id(repo, content) != old {
+        return Err(format!(
+            "the patch applies to '{path}' ({old}), which does not match the current contents."
+        ));
+    }
+    if new == null {
+        return Ok(Vec::new());
+    }
+    if let Ok(data) = read_blob(repo, &new) {
+        return Ok(data);
+    }
+    let data = match &patch.binary_hunk {
+        Some(BinaryHunk::Literal(data)) => data.clone(),
+        Some(BinaryHunk::Delta(delta)) => apply_delta(content, delta)
+            .map_err(|_| format!("binary patch does not apply to '{path}'"))?,
+        None => return Err(format!("missing binary patch data for '{path}'")),
+    };
+    let got = blob_id(repo, &data);
+    if got != new {
+        return Err(format!(
+            "binary patch to '{path}' creates incorrect result (expecting {new}, got {got})"
+        ));
+    }
+    Ok(data)
+}
+
+fn blob_id(repo: &Repository, data: &[u8]) -> String {
+    let mut object = Object {
+        kind: ObjectKind::Blob,
+        expected_size: data.len() as u64,
+        reader: Cursor::new(data),
+    };
+    repo.hash_algorithm()
+        .and_then(|algorithm| object.write(algorithm, sink()))
+        .map(|hash| hash.to_string())
+        .unwrap_or_default()
+}
+
+fn read_blob(repo: &Repository, hash: &str) -> Result<Vec<u8>> {
+    let mut object = Object::read_git_object(repo, hash)?;
+    if object.kind != ObjectKind::Blob {
+        bail!("{hash} is not a blob");
+    }
+    object.read_all()
+}
+
+/// How many blank lines `hunk` adds at its end, ignoring blank context between them,
+/// and where the first of them is in the patch.
+fn blank_lines_at_end(hunk: &PatchHunk) -> Option<(usize, usize)> {
+    let mut count = 0;
+    let mut first = 0;
+    for line in &hunk.lines {
+        let blank = is_blank_line(&line.content);
+        match line.prefix {
+            b'+' if blank => {
+                if count == 0 {
+                    first = line.number;
+                }
+                count += 1;
+            }
+            b' ' if blank => {}
+            _ => count = 0,
+        }
+    }
+    (count > 0).then_some((count, first))
+}
+
+/// Work out what `patch` turns the working tree's copy of its file into, without
+/// writing anything. The inner error lists what's wrong, as git reports it.
+pub fn apply_file(
+    repo: &Repository,
+    patch: &FilePatch,
+    whitespace: Option<&mut WhitespaceCheck>,
+) -> Result<Result<Applied, Vec<String>>> {
+    let path = patch.path();
+    if patch.is_gitlink() {
+        // Without an index there is nothing to check the commit against or record it
+        // in, so, as in git, only the submodule's directory is created or removed.
+        return Ok(Ok(applied(patch, None, Vec::new())));
+    }
+    if let Some(new_path) = &patch.new_path
+        && patch.old_path.as_ref() != Some(new_path)
+        && read_worktree_file(repo, new_path)?.is_some()
+    {
+        return Ok(Err(vec![format!(
+            "{new_path}: already exists in working directory"
+        )]));
+    }
+    let old = match &patch.old_path {
+        Some(old_path) => match read_worktree_file(repo, old_path)? {
+            Some(old) => Some(old),
+            None => return Ok(Err(vec![format!("{old_path}: No such file or directory")])),
+        },
+        None => None,
+    };
+    let old_data = old.as_ref().map_or(&[][..], |old| old.data.as_slice());
+    let data = match apply_hunks_checked(repo, patch, old_data, whitespace) {
+        Ok(data) => data,
+        Err(error) => return Ok(Err(vec![error, format!("{path}: patch does not apply")])),
+    };
+    if patch.new_path.is_none() && !data.is_empty() {
+        return Ok(Err(vec![format!(
+            "removal patch leaves file contents: {path}"
+        )]));
+    }
+    Ok(Ok(applied(patch, old.as_ref(), data)))
+}
+
+fn applied(patch: &FilePatch, old: Option<&FileContent>, data: Vec<u8>) -> Applied {
+    let new = patch.new_path.as_ref().map(|new_path| {
+        let mode = patch
+            .new_mode
+            .clone()
+            .or_else(|| old.map(|old| old.mode.clone()))
+            .unwrap_or_else(|| "100644".to_string());
+        (new_path.clone(), FileContent { mode, data })
+    });
+    let remove = patch
+        .old_path
+        .clone()
+        .filter(|old_path| patch.new_path.as_ref() != Some(old_path));
+    Applied { remove, new }
+}
+
+/// The blob the patch was made against, from its `index` line: `Ok(None)` for a new
+/// file, `Err` if the patch doesn't say or the blob isn't in the repository.
+pub fn preimage_blob(repo: &Repository, patch: &FilePatch) -> Result<Option<Vec<u8>>, String> {
+    if patch.old_path.is_none() {
+        return Ok(None);
+    }
+    let lacking = || format!("sha1 information is lacking or useless ({}).", patch.path());
+    let blob = patch.old_blob.as_deref().ok_or_else(lacking)?;
+    full_object_id(repo, blob)
+        .and_then(|hash| read_blob(repo, &hash))
+        .map(Some)
+        .map_err(|_| lacking())
+}
+
+/// Apply `patch` to the blob it was made against and merge the result with the
+/// working tree's version of the file, returning the change along with the number of
+/// conflicts. `None` if that can't be done: the patch creates or deletes the file,
+/// doesn't name a blob the repository has, or doesn't apply to it.
+fn try_three_way(
+    repo: &Repository,
+    err: &mut impl Write,
+    patch: &FilePatch,
+) -> Result<Option<(Applied, usize)>> {
+    let Some(old_path) = &patch.old_path else {
+        return Ok(None);
+    };
+    if patch.new_path.is_none() || patch.binary || patch.is_gitlink() {
+        return Ok(None);
+    }
+    let Ok(Some(base)) = preimage_blob(repo, patch) else {
+        print_errors(
+            err,
+            &["repository lacks the necessary blob to perform 3-way merge.".to_string()],
+        )?;
+        return Ok(None);
+    };
+    let theirs = match apply_hunks(repo, patch, &base) {
+        Ok(theirs) => theirs,
+        Err(error) => {
+            print_errors(err, &[error])?;
+            return Ok(None);
+        }
+    };
+    let ours = read_worktree_file(repo, old_path)?;
+    let our_data = ours.as_ref().map_or(&[][..], |ours| ours.data.as_slice());
+    let merged = merge3(&base, our_data, &theirs, "ours", "theirs");
+    let result = applied(patch, ours.as_ref(), merged.content);
+    Ok(Some((result, merged.conflicts)))
+}
+
+/// The file at `path` in the working tree, `None` if there is none.
+pub fn read_worktree_file(repo: &Repository, path: &str) -> Result<Option<FileContent>> {
+    let file = repo.path(path);
+    let metadata = match symlink_metadata(&file) {
+        Ok(metadata) => metadata,
+        Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) => {
+            return Ok(None);
+        }
+        Err(e) => return Err(e).with_context(|| format!("failed to stat {path}")),
+    };
+    if metadata.is_symlink() {
+        let target = read_link(&file).with_context(|| format!("failed to read {path}"))?;
+        return Ok(Some(FileContent {
+            mode: "120000".to_string(),
+            data: target.as_os_str().as_encoded_bytes().to_vec(),
+        }));
+    }
+    if metadata.is_dir() {
+        return Ok(None);
+    }
+    let executable = metadata.permissions().mode() & 0o111 != 0;
+    Ok(Some(FileContent {
+        mode: if executable { "100755" } else { "100644" }.to_string(),
+        data: read(&file).with_context(|| format!("failed to read {path}"))?,
+    }))
+}
+
+/// Carry out the changes: removals first, so that a rename can take the place of
+/// another file, then the new contents.
+pub fn write_applied(repo: &Repository, applied: &[Applied]) -> Result<()> {
+    for change in applied {
+        if let Some(path) = &change.remove {
+            remove_worktree_file(repo, Path::new(path))?;
+        }
+    }
+    for change in applied {
+        if let Some((path, content)) = &change.new {
+            write_worktree_content(repo, Path::new(path), content)?;
+        }
+    }
+    Ok(())
+}
+
+/// Replace whatever is at `path` with `content`.
+pub fn write_worktree_content(repo: &Repository, path: &Path, content: &FileContent) -> Result<()> {
+    check_worktree_path(repo, path)?;
+    let file = repo.path(path);
+    if let Some(parent) = file.parent() {
+        create_dir_all(parent).with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    if content.mode == "160000" {
+        return create_dir_all(&file)
+            .with_context(|| format!("failed to create {}", path.display()));
+    }
+    match remove_file(&file) {
+        Err(e) if e.kind() != ErrorKind::NotFound => {
+            return Err(e).with_context(|| format!("failed to remove {}", path.display()));
+        }
+        _ => {}
+    }
+    if content.mode == "120000" && has_symlinks(repo) {
+        let target = String::from_utf8(content.data.clone())
+            .with_context(|| format!("symlink target of {} is not UTF-8", path.display()))?;
+        return symlink(target, &file)
+            .with_context(|| format!("failed to create symlink {}", path.display()));
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(if content.mode == "100755" {
+            0o777
+        } else {
+            0o666
+        })
+        .open(&file)
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    file.write_all(&content.data)
+        .with_context(|| format!("failed to write {}", path.display()))
+}
@@ -0,0 +1,229 @@
+use crate::config::{Config, xdg_config_path};
+use crate::repository::Repository;
+use crate::wildmatch::wildmatch;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The state a `.gitattributes` line gives an attribute of the paths it matches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttrValue {
+    /// `name`.
+    Set,
+    /// `-name`.
+    Unset,
+    /// `name=value`.
+    Value(String),
+}
+
+/// An attribute a line assigns: a state, or `None` for `!name`, which makes it
+/// unspecified again.
+type Assignment = (String, Option<AttrValue>);
+
+/// One line of an attributes file: a pattern and what it assigns.
+#[derive(Debug, Clone)]
+struct AttrRule {
+    pattern: String,
+    /// Whether the pattern has no `/`, so it matches the base name at any depth.
+    basename_only: bool,
+    assignments: Vec<Assignment>,
+}
+
+/// The attributes of paths in the working tree, as git-attributes(5) describes them.
+///
+/// They come from, lowest precedence first, the user's file (`core.attributesFile`,
+/// by default `$XDG_CONFIG_HOME/git/attributes`), the `.gitattributes` of the
+/// top-level directory and of each directory down to the path's own, and
+/// `.git/info/attributes`; within a file, later lines win. Patterns are matched as in
+/// `.gitignore`: one without a `/` against the path's base name, others against its
+/// path below the directory of the file, a leading `/` only anchoring it. Macros
+/// (`[attr]name <attributes>`) can be defined outside of subdirectories; the
+/// built-in `binary` stands for `-diff -merge -text`.
+///
+/// Per-directory files are read from the working tree, the first time a path under
+/// them is looked up.
+pub struct Attributes {
+    global: Vec<AttrRule>,
+    info: Vec<AttrRule>,
+    macros: HashMap<String, Vec<Assignment>>,
+    work_tree: PathBuf,
+    /// The rules of the `.gitattributes` of each directory read so far, by its path
+    /// (`""` for the top level).
+    directories: Mutex<HashMap<String, Arc<Vec<AttrRule>>>>,
+}
+
+impl Attributes {
+    pub fn load(repo: &Repository, config: &Config) -> Result<Attributes> {
+        let mut macros = HashMap::from([(
+            "binary".to_string(),
+            ["diff", "merge", "text"]
+                .map(|name| (name.to_string(), Some(AttrValue::Unset)))
+                .to_vec(),
+        )]);
+        let global_path = config
+            .get_path("core.attributesFile")
+            .or_else(|| xdg_config_path("attributes"));
+        let global = match global_path {
+            Some(path) => read_rules(&path, Some(&mut macros))?,
+            None => Vec::new(),
+        };
+        let top_level = read_rules(&repo.path(".gitattributes"), Some(&mut macros))?;
+        let info = read_rules(&repo.git_path("info/attributes"), Some(&mut macros))?;
+        Ok(Attributes {
+            global,
+            info,
+            macros,
+            work_tree: repo.work_tree.clone(),
+            directories: Mutex::new(HashMap::from([(String::new(), Arc::new(top_level))])),
+        })
+    }
+
+    /// The state of the attribute `name` for `path`, or `None` if it is unspecified.
+    pub fn get(&self, path: &str, name: &str) -> Result<Option<AttrValue>> {
+        Ok(self.check(path)?.remove(name))
+    }
+
+    /// The attributes specified for `path`, by name.
+    pub fn check(&self, path: &str) -> Result<HashMap<String, AttrValue>> {
+        let mut states = HashMap::new();
+        let basename = path.rsplit('/').next().unwrap_or(path);
+        for rule in &self.global {
+            self.apply(&mut states, rule, path, basename);
+        }
+        let mut dir = String::new();
+        let mut components = path.split('/').peekable();
+        while let Some(component) = components.next() {
+            let rules = self.directory_rules(&dir)?;
+            let relative = &path[dir.len()..];
+            for rule in rules.iter() {
+                self.apply(&mut states, rule, relative, basename);
+            }
+            if components.peek().is_none() {
+                break;
+            }
+            dir.push_str(component);
+            dir.push('/');
+        }
+        for rule in &self.info {
+            self.apply(&mut states, rule, path, basename);
+        }
+        Ok(states
+            .into_iter()
+            .filter_map(|(name, state)| Some((name, state?)))
+            .collect())
+    }
+
+    /// The rules of `<dir>.gitattributes`, read once.
+    fn directory_rules(&self, dir: &str) -> Result<Arc<Vec<AttrRule>>> {
+        let mut directories = self.directories.lock().unwrap();
+        if let Some(rules) = directories.get(dir) {
+            return Ok(rules.clone());
+        }
+        let rules = Arc::new(read_rules(
+            &self.work_tree.join(format!("{dir}.gitattributes")),
+            None,
+        )?);
+        directories.insert(dir.to_string(), rules.clone());
+        Ok(rules)
+    }
+
+    /// Apply `rule` to `states` if it matches `relative`, the path below the
+    /// directory of the rule's file.
+    fn apply(
+        &self,
+        states: &mut HashMap<String, Option<AttrValue>>,
+        rule: &AttrRule,
+        relative: &str,
+        basename: &str,
+    ) {
+        let matches = match rule.basename_only {
+            true => wildmatch(&rule.pattern, basename, false, false),
+            false => wildmatch(&rule.pattern, relative, true, false),
+        };
+        if matches {
+            for assignment in &rule.assignments {
+                self.assign(states, assignment, 0);
+            }
+        }
+    }
+
+    fn assign(
+        &self,
+        states: &mut HashMap<String, Option<AttrValue>>,
+        (name, state): &Assignment,
+        depth: usize,
+    ) {
+        states.insert(name.clone(), state.clone());
+        // A macro can't expand to itself, however indirectly.
+        if *state == Some(AttrValue::Set)
+            && depth < 8
+            && let Some(assignments) = self.macros.get(name)
+        {
+            for assignment in assignments {
+                self.assign(states, assignment, depth + 1);
+            }
+        }
+    }
+}
+
+/// The rules of the attributes file at `path` (none if there is no such file). With
+/// `macros`, the macros it defines are added to them; without, they are ignored, as
+/// git does in the files of subdirectories.
+fn read_rules(
+    path: &Path,
+    mut macros: Option<&mut HashMap<String, Vec<Assignment>>>,
+) -> Result<Vec<AttrRule>> {
+    let text = match read_to_string(path) {
+        Ok(text) => text,
+        Err(e)
+            if matches!(
+                e.kind(),
+                ErrorKind::NotFound | ErrorKind::NotADirectory | ErrorKind::IsADirectory
+            ) =>
+        {
+            return Ok(Vec::new());
+        }
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+    };
+    let mut rules = Vec::new();
+    for line in text.lines() {
+        let line = line.trim_start();
+        let mut words = line.split_ascii_whitespace();
+        let Some(pattern) = words.next().filter(|_| !line.starts_with('#')) else {
+            continue;
+        };
+        let assignments: Vec<Assignment> = words
+            .map(|word| match word.as_bytes()[0] {
+                b'-' => (word[1..].to_string(), Some(AttrValue::Unset)),
+                b'!' => (word[1..].to_string(), None),
+                _ => match word.split_once('=') {
+                    Some((name, value)) => {
+                        (name.to_string(), Some(AttrValue::Value(value.to_string())))
+                    }
+                    None => (word.to_string(), Some(AttrValue::Set)),
+                },
+            })
+            .collect();
+        if let Some(name) = pattern.strip_prefix("[attr]") {
+            if let Some(macros) = macros.as_deref_mut() {
+                macros.insert(name.to_string(), assignments);
+            }
+            continue;
+        }
+        // Negative patterns are reserved (git warns about them), and those ending in
+        // `/` only match directories, which have no attributes.
+        if pattern.starts_with('!') || pattern.ends_with('/') {
+            continue;
+        }
+        let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+        rules.push(AttrRule {
+            basename_only: !line.starts_with('/') && !pattern.contains('/'),
+            pattern: pattern.to_string(),
+            assignments,
+        });
+    }
+    Ok(rules)
+}
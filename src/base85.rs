@@ -0,0 +1,49 @@
+use anyhow::{Result, bail, ensure};
+
+/// The digits of git's base85, used for the data of binary patches.
+const DIGITS: &[u8; 85] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz!#$%&()*+-;<=>?@^_`{|}~";
+
+/// Decode `len` bytes from `text`, each group of five digits holding four bytes as a
+/// big-endian number; the last group is padded with zero bytes.
+pub fn decode(text: &[u8], len: usize) -> Result<Vec<u8>> {
+    ensure!(
+        text.len() == len.div_ceil(4) * 5,
+        "base85 data of {} characters can't hold {len} bytes",
+        text.len()
+    );
+    let mut out = Vec::with_capacity(len.div_ceil(4) * 4);
+    for group in text.chunks(5) {
+        let mut value: u32 = 0;
+        for &digit in group {
+            let Some(digit) = DIGITS.iter().position(|&d| d == digit) else {
+                bail!("invalid base85 digit {:?}", digit as char);
+            };
+            value = value
+                .checked_mul(85)
+                .and_then(|value| value.checked_add(digit as u32))
+                .ok_or_else(|| anyhow::anyhow!("invalid base85 sequence"))?;
+        }
+        out.extend_from_slice(&value.to_be_bytes());
+    }
+    out.truncate(len);
+    Ok(out)
+}
+
+/// Encode `data` as [`decode`] reads it: five digits for every four bytes, the last
+/// group padded with zero bytes.
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len().div_ceil(4) * 5);
+    for group in data.chunks(4) {
+        let mut bytes = [0; 4];
+        bytes[..group.len()].copy_from_slice(group);
+        let mut value = u32::from_be_bytes(bytes);
+        let mut digits = [0; 5];
+        for digit in digits.iter_mut().rev() {
+            *digit = DIGITS[(value % 85) as usize];
+            value /= 85;
+        }
+        out.extend_from_slice(&digits);
+    }
+    out
+}
@@ -0,0 +1,438 @@
+use crate::commit_read::Commit;
+use crate::ls_tree::read_tree_entries;
+use crate::object_id::{HashAlgorithm, ObjectId};
+use crate::object_read::{Object, ObjectKind};
+use crate::pack::Pack;
+use crate::repository::Repository;
+use crate::transfer::tag_target;
+use anyhow::{Context, Result, bail, ensure};
+use std::collections::HashMap;
+use std::fs::{Permissions, read, read_dir};
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+/// A set of the objects of a pack, by their position in it: bit `n` stands for the
+/// `n`th entry by offset.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bitmap {
+    words: Vec<u64>,
+}
+
+impl Bitmap {
+    pub fn get(&self, n: usize) -> bool {
+        self.words
+            .get(n / 64)
+            .is_some_and(|word| word & (1 << (n % 64)) != 0)
+    }
+
+    pub fn set(&mut self, n: usize) {
+        if self.words.len() <= n / 64 {
+            self.words.resize(n / 64 + 1, 0);
+        }
+        self.words[n / 64] |= 1 << (n % 64);
+    }
+
+    /// Add the objects of `other`.
+    pub fn union(&mut self, other: &Bitmap) {
+        if self.words.len() < other.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word |= other;
+        }
+    }
+
+    /// Take out the objects of `other`.
+    pub fn difference(&mut self, other: &Bitmap) {
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word &= !other;
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    /// The positions in the set, in ascending order.
+    pub fn positions(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.words.len() * 64).filter(|&n| self.get(n))
+    }
+
+    /// The bitmap in git's EWAH encoding: the number of bits, the number of 64-bit
+    /// words, the words, then the position of the last marker word. Each marker word
+    /// gives, from its low bit up, whether the run after it is of ones, how many words
+    /// the run is (32 bits) and how many literal words follow the run (31 bits).
+    fn to_ewah(&self) -> Vec<u8> {
+        let words = &self.words[..self
+            .words
+            .iter()
+            .rposition(|&w| w != 0)
+            .map_or(0, |n| n + 1)];
+        let bits = match words.last() {
+            Some(last) => (words.len() - 1) * 64 + (64 - last.leading_zeros() as usize),
+            None => 0,
+        };
+        let mut encoded: Vec<u64> = Vec::new();
+        let mut marker = 0;
+        let mut i = 0;
+        while i < words.len() || encoded.is_empty() {
+            let fill = words.get(i).is_some_and(|&word| word == u64::MAX);
+            let mut run = 0;
+            while i < words.len()
+                && run < u64::from(u32::MAX)
+                && words[i] == if fill { u64::MAX } else { 0 }
+            {
+                run += 1;
+                i += 1;
+            }
+            let literals = i;
+            while i < words.len()
+                && i - literals < (1 << 31) - 1
+                && words[i] != 0
+                && words[i] != u64::MAX
+            {
+                i += 1;
+            }
+            marker = encoded.len();
+            encoded.push(u64::from(fill && run > 0) | (run << 1) | (((i - literals) as u64) << 33));
+            encoded.extend(&words[literals..i]);
+        }
+        let mut out = Vec::with_capacity(12 + 8 * encoded.len());
+        out.extend((bits as u32).to_be_bytes());
+        out.extend((encoded.len() as u32).to_be_bytes());
+        for word in &encoded {
+            out.extend(word.to_be_bytes());
+        }
+        out.extend((marker as u32).to_be_bytes());
+        out
+    }
+
+    /// Read a bitmap in EWAH encoding (see [`Bitmap::to_ewah`]) from the start of
+    /// `data`, returning it with how many bytes it took up.
+    fn from_ewah(data: &[u8]) -> Result<(Bitmap, usize)> {
+        let truncated = || anyhow::anyhow!("bitmap is truncated");
+        let be32 = |at: usize| -> Result<usize> {
+            let bytes = data.get(at..at + 4).ok_or_else(truncated)?;
+            Ok(u32::from_be_bytes(bytes.try_into().unwrap()) as usize)
+        };
+        let bits = be32(0)?;
+        let count = be32(4)?;
+        let end = 8 + count.checked_mul(8).ok_or_else(truncated)?;
+        let encoded = data.get(8..end).ok_or_else(truncated)?;
+        be32(end)?;
+        let encoded: Vec<u64> = encoded
+            .chunks(8)
+            .map(|word| u64::from_be_bytes(word.try_into().unwrap()))
+            .collect();
+        let mut words = Vec::new();
+        let mut i = 0;
+        while i < encoded.len() {
+            let marker = encoded[i];
+            let fill = match marker & 1 {
+                1 => u64::MAX,
+                _ => 0,
+            };
+            let run = ((marker >> 1) & u64::from(u32::MAX)) as usize;
+            let literals = (marker >> 33) as usize;
+            ensure!(
+                words.len() + run <= bits.div_ceil(64),
+                "bitmap has more words than bits"
+            );
+            words.resize(words.len() + run, fill);
+            let literals = encoded.get(i + 1..i + 1 + literals).ok_or_else(truncated)?;
+            words.extend(literals);
+            i += 1 + literals.len();
+        }
+        ensure!(
+            words.len() <= bits.div_ceil(64),
+            "bitmap has more words than bits"
+        );
+        Ok((Bitmap { words }, end + 4))
+    }
+}
+
+/// The reachability bitmaps of a pack, from its `.bitmap` file: for some of its
+/// commits, the set of every object the commit reaches, all of which the pack has, and
+/// the sets of its commits, trees, blobs and tags.
+pub struct PackBitmaps {
+    /// The ids of the pack's objects in pack order, which bits are numbered by.
+    ids: Vec<ObjectId>,
+    positions: HashMap<ObjectId, usize>,
+    /// The pack's commits, trees, blobs and tags.
+    kinds: [Bitmap; 4],
+    commits: HashMap<ObjectId, Bitmap>,
+}
+
+/// The header of a `.bitmap` file, version 1 with the only option git requires, that
+/// each commit's bitmap covers all it reaches (`BITMAP_OPT_FULL_DAG`).
+const BITMAP_HEADER: &[u8] = b"BITM\0\x01\0\x01";
+
+impl PackBitmaps {
+    /// The bitmaps of the newest pack in `objects_dir` that has them, if one does.
+    pub fn open(objects_dir: &Path, algorithm: HashAlgorithm) -> Result<Option<PackBitmaps>> {
+        let dir = objects_dir.join("pack");
+        let entries = match read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", dir.display())),
+        };
+        let mut bitmaps: Vec<(std::time::SystemTime, PathBuf)> = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "bitmap")
+                && path.with_extension("idx").is_file()
+                && path.with_extension("pack").is_file()
+            {
+                bitmaps.push((path.metadata()?.modified()?, path));
+            }
+        }
+        bitmaps.sort();
+        match bitmaps.pop() {
+            Some((_, path)) => PackBitmaps::read(&path, algorithm).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Read the `.bitmap` file at `path`, checking it against its pack's index.
+    pub fn read(path: &Path, algorithm: HashAlgorithm) -> Result<PackBitmaps> {
+        let name = path.display();
+        let pack = Pack::open(&path.with_extension("idx"), algorithm)?;
+        let mut bitmaps = PackBitmaps::for_pack(&pack)?;
+        let data = read(path).with_context(|| format!("failed to read {name}"))?;
+        let hash_len = algorithm.len();
+        ensure!(
+            data.len() >= 12 + 2 * hash_len && data.starts_with(&BITMAP_HEADER[..6]),
+            "{name} is not a bitmap index"
+        );
+        ensure!(
+            u16::from_be_bytes([data[6], data[7]]) & 1 != 0,
+            "{name} doesn't cover all history (BITMAP_OPT_FULL_DAG)"
+        );
+        let (content, checksum) = data.split_at(data.len() - hash_len);
+        ensure!(
+            algorithm.digest(content).as_bytes() == checksum,
+            "{name} is corrupt: its checksum doesn't match"
+        );
+        let index_ids: Vec<ObjectId> = pack.ids().collect();
+        let pack_checksum = &data[12..12 + hash_len];
+        ensure!(
+            pack_checksum == pack.checksum().as_bytes(),
+            "{name} is for another pack"
+        );
+        let count = u32::from_be_bytes(data[8..12].try_into().unwrap()) as usize;
+
+        let mut at = 12 + hash_len;
+        for kind in &mut bitmaps.kinds {
+            let (bitmap, len) =
+                Bitmap::from_ewah(&content[at..]).with_context(|| format!("{name} is corrupt"))?;
+            *kind = bitmap;
+            at += len;
+        }
+        let mut read_order: Vec<Bitmap> = Vec::new();
+        for n in 0..count {
+            let header = content
+                .get(at..at + 6)
+                .with_context(|| format!("{name} is truncated"))?;
+            let position = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+            let xor_offset = usize::from(header[4]);
+            let (mut bitmap, len) = Bitmap::from_ewah(&content[at + 6..])
+                .with_context(|| format!("{name} is corrupt"))?;
+            at += 6 + len;
+            // A bitmap may be stored as its difference from one of the few before it.
+            if xor_offset > 0 {
+                ensure!(xor_offset <= n.min(160), "{name} has a bad xor offset");
+                let base = &read_order[n - xor_offset];
+                if bitmap.words.len() < base.words.len() {
+                    bitmap.words.resize(base.words.len(), 0);
+                }
+                for (word, base) in bitmap.words.iter_mut().zip(&base.words) {
+                    *word ^= base;
+                }
+            }
+            let Some(id) = index_ids.get(position) else {
+                bail!("{name} has a bitmap for object {position}, which its pack doesn't have");
+            };
+            bitmaps.commits.insert(*id, bitmap.clone());
+            read_order.push(bitmap);
+        }
+        Ok(bitmaps)
+    }
+
+    /// Bitmaps for `pack`, with the sets of its kinds of object but none for commits
+    /// yet.
+    fn for_pack(pack: &Pack) -> Result<PackBitmaps> {
+        let mut entries = pack.index_entries()?;
+        entries.sort_by_key(|(offset, _, _)| *offset);
+        let ids: Vec<ObjectId> = entries.into_iter().map(|(_, id, _)| id).collect();
+        let positions = ids.iter().enumerate().map(|(n, id)| (*id, n)).collect();
+        Ok(PackBitmaps {
+            ids,
+            positions,
+            kinds: Default::default(),
+            commits: HashMap::new(),
+        })
+    }
+
+    /// The objects reachable from `tips` but not from `hide`, as a bitmap of this
+    /// pack; `None` if some of them aren't in it, so that it can't tell.
+    pub fn reachable(
+        &self,
+        repo: &Repository,
+        tips: &[ObjectId],
+        hide: &[ObjectId],
+    ) -> Result<Option<Bitmap>> {
+        let mut reached = Bitmap::default();
+        for tip in tips {
+            if !self.fill(repo, tip, &mut reached)? {
+                return Ok(None);
+            }
+        }
+        let mut hidden = Bitmap::default();
+        for tip in hide {
+            if !self.fill(repo, tip, &mut hidden)? {
+                return Ok(None);
+            }
+        }
+        reached.difference(&hidden);
+        Ok(Some(reached))
+    }
+
+    /// Add to `bitmap` what `tip` reaches, using the bitmaps of the commits on the way
+    /// that have one. False if some object reached isn't in the pack.
+    fn fill(&self, repo: &Repository, tip: &ObjectId, bitmap: &mut Bitmap) -> Result<bool> {
+        let mut pending = vec![*tip];
+        while let Some(id) = pending.pop() {
+            let Some(&position) = self.positions.get(&id) else {
+                return Ok(false);
+            };
+            if bitmap.get(position) {
+                continue;
+            }
+            if let Some(reached) = self.commits.get(&id) {
+                bitmap.union(reached);
+                continue;
+            }
+            bitmap.set(position);
+            let hash = id.to_string();
+            let kind = match () {
+                _ if self.kinds[0].get(position) => ObjectKind::Commit,
+                _ if self.kinds[1].get(position) => ObjectKind::Tree,
+                _ if self.kinds[2].get(position) => ObjectKind::Blob,
+                _ if self.kinds[3].get(position) => ObjectKind::Tag,
+                _ => Object::read_git_object(repo, &hash)?.kind,
+            };
+            match kind {
+                ObjectKind::Commit => {
+                    let commit = Commit::read(repo, &hash)?;
+                    pending.push(commit.tree.parse()?);
+                    for parent in &commit.parents {
+                        pending.push(parent.parse()?);
+                    }
+                }
+                ObjectKind::Tree => {
+                    for entry in read_tree_entries(repo, Object::read_git_object(repo, &hash)?)? {
+                        if entry.mode != "160000" {
+                            pending.push(entry.hash);
+                        }
+                    }
+                }
+                ObjectKind::Tag => {
+                    let target = tag_target(&Object::read_git_object(repo, &hash)?.read_all()?);
+                    pending.push(
+                        target
+                            .with_context(|| format!("invalid tag {hash}"))?
+                            .parse()?,
+                    );
+                }
+                ObjectKind::Blob => {}
+            }
+        }
+        Ok(true)
+    }
+
+    /// The ids of the objects in `bitmap`, in pack order, with their kinds.
+    pub fn objects<'a>(
+        &'a self,
+        bitmap: &'a Bitmap,
+    ) -> impl Iterator<Item = (ObjectId, ObjectKind)> + 'a {
+        bitmap.positions().filter_map(|n| {
+            let kind = [
+                ObjectKind::Commit,
+                ObjectKind::Tree,
+                ObjectKind::Blob,
+                ObjectKind::Tag,
+            ]
+            .into_iter()
+            .zip(&self.kinds)
+            .find_map(|(kind, of_kind)| of_kind.get(n).then_some(kind))?;
+            Some((*self.ids.get(n)?, kind))
+        })
+    }
+}
+
+/// Write the `.bitmap` file of the pack whose index is `index_path`, with a bitmap
+/// for each of `commits` (ids of commits in the pack) of everything it reaches, which
+/// the pack must all have: a header with the pack's checksum, the sets of its commits,
+/// trees, blobs and tags, then each commit's position in the index with its bitmap,
+/// and the file's checksum. Every bitmap is in EWAH encoding, as git writes them, and
+/// none is stored as a difference from another.
+///
+/// Commits are filled in oldest first, so that each can start from the bitmaps of the
+/// ones it reaches.
+pub fn write_bitmap(
+    repo: &Repository,
+    index_path: &Path,
+    commits: &[ObjectId],
+    algorithm: HashAlgorithm,
+) -> Result<()> {
+    let pack = Pack::open(index_path, algorithm)?;
+    let mut bitmaps = PackBitmaps::for_pack(&pack)?;
+    for (n, id) in bitmaps.ids.iter().enumerate() {
+        let kind = Object::read_git_object(repo, &id.to_string())?.kind;
+        let of_kind = match kind {
+            ObjectKind::Commit => 0,
+            ObjectKind::Tree => 1,
+            ObjectKind::Blob => 2,
+            ObjectKind::Tag => 3,
+        };
+        bitmaps.kinds[of_kind].set(n);
+    }
+    for commit in commits.iter().rev() {
+        let mut reached = Bitmap::default();
+        if !bitmaps.fill(repo, commit, &mut reached)? {
+            bail!("pack doesn't have everything {commit} reaches, so it can't have bitmaps");
+        }
+        bitmaps.commits.insert(*commit, reached);
+    }
+
+    let index_positions: HashMap<ObjectId, usize> =
+        pack.ids().enumerate().map(|(n, id)| (id, n)).collect();
+    let mut data = BITMAP_HEADER.to_vec();
+    data.extend((commits.len() as u32).to_be_bytes());
+    data.extend(pack.checksum().as_bytes());
+    for kind in &bitmaps.kinds {
+        data.extend(kind.to_ewah());
+    }
+    for commit in commits {
+        data.extend((index_positions[commit] as u32).to_be_bytes());
+        data.extend([0, 0]);
+        data.extend(bitmaps.commits[commit].to_ewah());
+    }
+    let checksum = algorithm.digest(&data);
+    data.extend(checksum.as_bytes());
+
+    let path = index_path.with_extension("bitmap");
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut file = NamedTempFile::new_in(dir)?;
+    file.write_all(&data)?;
+    file.as_file()
+        .set_permissions(Permissions::from_mode(0o444))?;
+    file.persist(&path)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(())
+}
@@ -0,0 +1,418 @@
+use crate::commit_read::{Commit, ahead_behind};
+use crate::config::{Config, ValuePattern, set_config_value, unset_config_values};
+use crate::refs::{
+    current_branch, delete_ref, expand_ref, is_valid_ref_name, list_refs, resolve_ref, shorten_ref,
+    update_ref, upstream_ref,
+};
+use crate::repository::{Repository, common_dir};
+use anyhow::{Result, bail};
+use std::fs::{canonicalize, read_dir, read_to_string};
+use std::path::{Path, PathBuf};
+
+/// Check that a branch called `name` could be created: the name is valid and no
+/// branch has it yet.
+pub fn check_new_branch_name(repo: &Repository, name: &str) -> Result<()> {
+    if name == "HEAD" || !is_valid_ref_name(name) {
+        bail!("'{name}' is not a valid branch name");
+    }
+    if resolve_ref(repo, &format!("refs/heads/{name}"))?.is_some() {
+        bail!("a branch named '{name}' already exists");
+    }
+    Ok(())
+}
+
+/// Create the branch `name` at the commit `start_point` names, returning that commit,
+/// and the upstream it was set up to track (`origin/main`) if any.
+///
+/// When the start point is a remote-tracking branch (`origin/main`), the new branch is
+/// set up to track it, as git does by default (`branch.autoSetupMerge`).
+pub fn create_branch(
+    repo: &Repository,
+    config: &Config,
+    name: &str,
+    start_point: &str,
+) -> Result<(Commit, Option<String>)> {
+    check_new_branch_name(repo, name)?;
+    let commit = Commit::read(repo, start_point)?;
+    update_ref(
+        repo,
+        config,
+        &format!("refs/heads/{name}"),
+        &commit.hash,
+        &format!("branch: Created from {start_point}"),
+    )?;
+    let mut upstream = None;
+    if config.get_bool("branch.autoSetupMerge")?.unwrap_or(true)
+        && let Some((remote, remote_branch)) = remote_tracking_branch(repo, config, start_point)?
+    {
+        let config_path = repo.git_path("config");
+        let config_path = config_path.as_path();
+        set_config_value(config_path, &format!("branch.{name}.remote"), &remote)?;
+        set_config_value(
+            config_path,
+            &format!("branch.{name}.merge"),
+            &format!("refs/heads/{remote_branch}"),
+        )?;
+        upstream = Some(format!("{remote}/{remote_branch}"));
+    }
+    Ok((commit, upstream))
+}
+
+/// The configured remote and branch name on it, when `name` is a remote-tracking ref.
+fn remote_tracking_branch(
+    repo: &Repository,
+    config: &Config,
+    name: &str,
+) -> Result<Option<(String, String)>> {
+    let Some((full_name, _)) = expand_ref(repo, name)? else {
+        return Ok(None);
+    };
+    let Some((remote, branch)) = full_name
+        .strip_prefix("refs/remotes/")
+        .and_then(|rest| rest.split_once('/'))
+    else {
+        return Ok(None);
+    };
+    if branch == "HEAD"
+        || (config.get(&format!("remote.{remote}.url")).is_none()
+            && config.get(&format!("remote.{remote}.fetch")).is_none())
+    {
+        return Ok(None);
+    }
+    Ok(Some((remote.to_string(), branch.to_string())))
+}
+
+/// Where a branch stands relative to its upstream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamStatus {
+    /// The upstream as shown to users, e.g. `origin/main`.
+    pub name: String,
+    /// Commits the branch is ahead of and behind the upstream, or `None` if the
+    /// upstream ref no longer exists.
+    pub ahead_behind: Option<(usize, usize)>,
+}
+
+/// The upstream status of `branch`, or `None` if it has no upstream configured.
+pub fn upstream_status(
+    repo: &Repository,
+    config: &Config,
+    branch: &str,
+) -> Result<Option<UpstreamStatus>> {
+    let Some(upstream) = upstream_ref(config, branch) else {
+        return Ok(None);
+    };
+    let upstream_hash = resolve_ref(repo, &upstream)?;
+    let branch_hash = resolve_ref(repo, &format!("refs/heads/{branch}"))?;
+    let ahead_behind = match (branch_hash, upstream_hash) {
+        (Some(ours), Some(theirs)) => Some(ahead_behind(repo, &ours, &theirs)?),
+        _ => None,
+    };
+    Ok(Some(UpstreamStatus {
+        name: shorten_ref(&upstream).to_string(),
+        ahead_behind,
+    }))
+}
+
+/// The `Your branch is ahead of 'origin/main' by 2 commits.` paragraph that `status`
+/// and `checkout` print, with git's hints unless `advice.statusHints` is off.
+pub fn tracking_info(repo: &Repository, config: &Config, branch: &str) -> Result<Option<String>> {
+    let Some(UpstreamStatus {
+        name: upstream,
+        ahead_behind,
+    }) = upstream_status(repo, config, branch)?
+    else {
+        return Ok(None);
+    };
+    let commits = |n: usize| if n == 1 { "commit" } else { "commits" };
+    let (info, hint) = match ahead_behind {
+        None => (
+            format!("Your branch is based on '{upstream}', but the upstream is gone."),
+            "use \"git branch --unset-upstream\" to fixup",
+        ),
+        Some((0, 0)) => (format!("Your branch is up to date with '{upstream}'."), ""),
+        Some((ahead, 0)) => (
+            format!(
+                "Your branch is ahead of '{upstream}' by {ahead} {}.",
+                commits(ahead)
+            ),
+            "use \"git push\" to publish your local commits",
+        ),
+        Some((0, behind)) => (
+            format!(
+                "Your branch is behind '{upstream}' by {behind} {}, and can be fast-forwarded.",
+                commits(behind)
+            ),
+            "use \"git pull\" to update your local branch",
+        ),
+        Some((ahead, behind)) => (
+            format!(
+                "Your branch and '{upstream}' have diverged,\n\
+                 and have {ahead} and {behind} different commits each, respectively."
+            ),
+            "use \"git pull\" to merge the remote branch into yours",
+        ),
+    };
+    if hint.is_empty() || config.get_bool("advice.statusHints")? == Some(false) {
+        Ok(Some(info))
+    } else {
+        Ok(Some(format!("{info}\n  ({hint})")))
+    }
+}
+
+/// A branch as `git branch` lists it.
+#[derive(Debug, Clone)]
+pub struct BranchListing {
+    /// The branch's short name, or `(HEAD detached at <commit>)` for a detached HEAD.
+    pub name: String,
+    pub hash: String,
+    /// Whether HEAD is on it.
+    pub current: bool,
+    /// The other worktree that has it checked out, if one does.
+    pub worktree: Option<PathBuf>,
+    /// The subject of its commit, found only when listing verbosely.
+    pub subject: Option<String>,
+    /// Where it stands against its upstream, found only when listing verbosely.
+    pub upstream: Option<UpstreamStatus>,
+}
+
+/// `git branch [-v]`: the local branches by name, after HEAD itself when detached.
+/// With `verbose`, each has the subject of the commit it points at and how it stands
+/// against its upstream too, for [`format_branch`] to show.
+pub fn git_branch_list(
+    repo: &Repository,
+    config: &Config,
+    verbose: u8,
+) -> Result<Vec<BranchListing>> {
+    let current = current_branch(repo)?;
+    let checked_out = checked_out_branches(repo)?;
+    let mut branches: Vec<(String, String, bool)> = Vec::new();
+    if current.is_none()
+        && let Some(head) = resolve_ref(repo, "HEAD")?
+    {
+        branches.push((format!("(HEAD detached at {})", &head[..7]), head, true));
+    }
+    for (ref_name, hash) in list_refs(repo, "refs/heads/")? {
+        let name = shorten_ref(&ref_name).to_string();
+        let is_current = current.as_deref() == Some(name.as_str());
+        branches.push((name, hash, is_current));
+    }
+    let mut listings = Vec::new();
+    for (name, hash, current_branch) in branches {
+        let worktree = match current_branch {
+            true => None,
+            false => checked_out
+                .iter()
+                .find(|(branch, _)| *branch == name)
+                .map(|(_, path)| path.clone()),
+        };
+        let (subject, upstream) = match verbose {
+            0 => (None, None),
+            _ if current_branch && current.is_none() => {
+                (Some(Commit::read(repo, &hash)?.subject().to_string()), None)
+            }
+            _ => (
+                Some(Commit::read(repo, &hash)?.subject().to_string()),
+                upstream_status(repo, config, &name)?,
+            ),
+        };
+        listings.push(BranchListing {
+            name,
+            hash,
+            current: current_branch,
+            worktree,
+            subject,
+            upstream,
+        });
+    }
+    Ok(listings)
+}
+
+/// `branch` as `git branch` lists it, with its name padded to `width`: the one HEAD is
+/// on marked with `*` and those checked out in other worktrees with `+`. With
+/// `verbose`, it is shown with the commit it points at, how far it is ahead of or
+/// behind its upstream, and the commit's subject; twice, the upstream and other
+/// worktrees are named too.
+pub fn format_branch(branch: &BranchListing, width: usize, verbose: u8) -> String {
+    let name = &branch.name;
+    let marker = match (branch.current, &branch.worktree) {
+        (true, _) => '*',
+        (false, Some(_)) => '+',
+        (false, None) => ' ',
+    };
+    if verbose == 0 {
+        return format!("{marker} {name}\n");
+    }
+    let mut tracking = String::new();
+    if let Some(path) = branch.worktree.as_ref().filter(|_| verbose > 1) {
+        tracking = format!("({}) ", path.display());
+    }
+    if let Some(upstream) = &branch.upstream {
+        tracking += &branch_tracking(upstream, verbose > 1);
+    }
+    format!(
+        "{marker} {name:<width$} {} {tracking}{}\n",
+        &branch.hash[..7],
+        branch.subject.as_deref().unwrap_or("")
+    )
+}
+
+/// What `branch -v` shows of a branch's upstream: `[ahead 1, behind 2] `, `[gone] `
+/// or nothing when they are level; with `name`, always, as `[origin/main: ahead 1] `.
+fn branch_tracking(status: &UpstreamStatus, name: bool) -> String {
+    let track = match status.ahead_behind {
+        None => "gone".to_string(),
+        Some((0, 0)) => String::new(),
+        Some((ahead, 0)) => format!("ahead {ahead}"),
+        Some((0, behind)) => format!("behind {behind}"),
+        Some((ahead, behind)) => format!("ahead {ahead}, behind {behind}"),
+    };
+    match (name, track.is_empty()) {
+        (true, true) => format!("[{}] ", status.name),
+        (true, false) => format!("[{}: {track}] ", status.name),
+        (false, true) => String::new(),
+        (false, false) => format!("[{track}] "),
+    }
+}
+
+/// `git branch <name> [<start-point>]`: create a branch at the start point (HEAD by
+/// default) without switching to it. Returns the upstream the branch was set up to
+/// track, if any (see [`create_branch`]).
+pub fn git_branch_create(
+    repo: &Repository,
+    config: &Config,
+    name: &str,
+    start_point: Option<&str>,
+) -> Result<Option<String>> {
+    let start_point = start_point.unwrap_or("HEAD");
+    if start_point == "HEAD" && resolve_ref(repo, "HEAD")?.is_none() {
+        let unborn = current_branch(repo)?.unwrap_or_else(|| "HEAD".to_string());
+        bail!("not a valid object name: '{unborn}'");
+    }
+    let (_, upstream) = create_branch(repo, config, name, start_point)?;
+    Ok(upstream)
+}
+
+/// What became of a branch `git branch -d` was asked to delete.
+#[derive(Debug, Clone)]
+pub enum Deletion {
+    /// The branch was deleted; it was at `hash`. `warning` is why that may surprise.
+    Deleted {
+        hash: String,
+        warning: Option<String>,
+    },
+    /// The branch was left alone, for the reason given.
+    Refused(String),
+}
+
+/// `git branch -d <name>...`: delete the branches, with their reflogs and upstream
+/// configuration. Without `force` (`-D`), a branch must be merged: have no commits
+/// its upstream doesn't, or HEAD if it has none. A branch checked out in any
+/// worktree is never deleted.
+///
+/// Every branch is tried; returns what became of each, in order.
+pub fn git_branch_delete(
+    repo: &Repository,
+    config: &Config,
+    names: &[String],
+    force: bool,
+) -> Result<Vec<(String, Deletion)>> {
+    if names.is_empty() {
+        bail!("branch name required");
+    }
+    let head = resolve_ref(repo, "HEAD")?;
+    let checked_out = checked_out_branches(repo)?;
+    let mut deletions = Vec::new();
+    for name in names {
+        let ref_name = format!("refs/heads/{name}");
+        let Some(hash) = resolve_ref(repo, &ref_name)? else {
+            let reason = format!("branch '{name}' not found.");
+            deletions.push((name.clone(), Deletion::Refused(reason)));
+            continue;
+        };
+        if let Some((_, worktree)) = checked_out.iter().find(|(branch, _)| branch == name) {
+            let reason = format!(
+                "Cannot delete branch '{name}' checked out at '{}'",
+                worktree.display()
+            );
+            deletions.push((name.clone(), Deletion::Refused(reason)));
+            continue;
+        }
+        let mut warning = None;
+        if !force {
+            let upstream = match upstream_ref(config, name) {
+                Some(upstream) => resolve_ref(repo, &upstream)?.map(|hash| (upstream, hash)),
+                None => None,
+            };
+            let merged_into = |target: Option<&str>| -> Result<bool> {
+                match target {
+                    Some(target) => Ok(ahead_behind(repo, &hash, target)?.0 == 0),
+                    None => Ok(false),
+                }
+            };
+            let merged = match &upstream {
+                Some((upstream, upstream_hash)) => {
+                    let merged = merged_into(Some(upstream_hash))?;
+                    if merged && !merged_into(head.as_deref())? {
+                        warning = Some(format!(
+                            "deleting branch '{name}' that has been merged to\n         \
+                             '{upstream}', but not yet merged to HEAD."
+                        ));
+                    }
+                    merged
+                }
+                None => merged_into(head.as_deref())?,
+            };
+            if !merged {
+                let reason = format!(
+                    "The branch '{name}' is not fully merged.\n\
+                     If you are sure you want to delete it, run 'git branch -D {name}'."
+                );
+                deletions.push((name.clone(), Deletion::Refused(reason)));
+                continue;
+            }
+        }
+        delete_ref(repo, &ref_name)?;
+        let config_path = repo.git_path("config");
+        for key in ["remote", "merge"] {
+            unset_config_values(
+                &config_path,
+                &format!("branch.{name}.{key}"),
+                &ValuePattern::new(None, false)?,
+                true,
+            )?;
+        }
+        deletions.push((name.clone(), Deletion::Deleted { hash, warning }));
+    }
+    Ok(deletions)
+}
+
+/// The branches checked out in the worktrees of the repository, with where each
+/// worktree is: this one first, then the main worktree and the linked ones.
+fn checked_out_branches(repo: &Repository) -> Result<Vec<(String, PathBuf)>> {
+    let common = common_dir(&repo.git_dir);
+    let branch_of = |git_dir: &Path| {
+        let head = read_to_string(git_dir.join("HEAD")).ok()?;
+        Some(head.trim().strip_prefix("ref: refs/heads/")?.to_string())
+    };
+    let mut branches = Vec::new();
+    if let Some(branch) = current_branch(repo)? {
+        branches.push((branch, repo.work_tree.clone()));
+    }
+    if let Some(branch) = branch_of(&common) {
+        let common = canonicalize(&common)?;
+        let worktree = common.parent().map(Path::to_path_buf);
+        branches.push((branch, worktree.unwrap_or(common)));
+    }
+    if let Ok(entries) = read_dir(common.join("worktrees")) {
+        for entry in entries {
+            let dir = entry?.path();
+            if let Some(branch) = branch_of(&dir) {
+                // `gitdir` names the `.git` file in the worktree.
+                let gitdir = read_to_string(dir.join("gitdir")).unwrap_or_default();
+                let worktree = Path::new(gitdir.trim()).parent().map(Path::to_path_buf);
+                branches.push((branch, worktree.unwrap_or(dir)));
+            }
+        }
+    }
+    Ok(branches)
+}
@@ -1,31 +1,202 @@
-use crate::ls_tree::git_read_tree_content;
+use crate::ls_tree::{LsTreeOptions, format_tree_entry, read_tree_entries};
 use crate::object_read::{Object, ObjectKind};
-use anyhow::{Result, ensure};
-use std::io::{Read, copy, stdout};
-
-pub fn git_cat_file(pretty_print: bool, object_hash: &str) -> Result<()> {
-    ensure!(
-        pretty_print,
-        "type or -p need to be passed and we don't support type at the moment"
-    );
-    let object = Object::read_git_object(object_hash)?;
+use crate::repository::Repository;
+use crate::rev_parse::rev_parse;
+use anyhow::{Result, bail, ensure};
+use std::io::{BufRead, Write, copy, stdin, stdout};
+
+/// What `git cat-file` shows of a single object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatFileMode {
+    /// Its content, with trees listed as `ls-tree` does (`-p`).
+    Pretty,
+    /// Its type (`-t`).
+    Type,
+    /// Its size in bytes (`-s`).
+    Size,
+    /// Nothing; only whether it exists (`-e`).
+    Exists,
+}
+
+/// `git cat-file (-p | -t | -s | -e) <object>`. Returns the exit status: with `-e`, 1
+/// if the name is well-formed but no such object exists; 0 otherwise. What is shown
+/// is written to `out`.
+pub fn git_cat_file(
+    repo: &Repository,
+    out: &mut impl Write,
+    mode: CatFileMode,
+    verify: bool,
+    object_hash: &str,
+) -> Result<i32> {
+    let object_hash = rev_parse(repo, object_hash)?;
+    let object_hash = object_hash.as_str();
+    let read = if verify {
+        Object::read_git_object_verified(repo, object_hash)
+    } else {
+        Object::read_git_object(repo, object_hash)
+    };
+    let mut object = match (read, mode) {
+        (Ok(_), CatFileMode::Exists) => return Ok(0),
+        (Err(_), CatFileMode::Exists) => return Ok(1),
+        (read, _) => read?,
+    };
+    match mode {
+        CatFileMode::Type => {
+            writeln!(out, "{}", object.kind.to_str())?;
+            return Ok(0);
+        }
+        CatFileMode::Size => {
+            writeln!(out, "{}", object.expected_size)?;
+            return Ok(0);
+        }
+        CatFileMode::Pretty | CatFileMode::Exists => {}
+    }
     match object.kind {
-        ObjectKind::Blob | ObjectKind::Commit => {
-            let mut sout = stdout().lock();
-            // Read max of the size from the file.
-            // Protect against zipbomb.
-            // Ignore the content after the expected size.
-            let a_size = copy(&mut object.reader.take(object.expected_size), &mut sout)?;
+        ObjectKind::Blob | ObjectKind::Commit | ObjectKind::Tag => {
+            // The reader is bounded by the expected size, which protects against zipbombs.
+            let a_size = copy(&mut object.reader, out)?;
             ensure!(
                 a_size == object.expected_size,
                 "object size mismatch, expected {}, got {}",
                 object.expected_size,
                 a_size
             );
+            object.ensure_fully_read()?;
         }
         ObjectKind::Tree => {
-            git_read_tree_content(object, false)?;
+            for entry in read_tree_entries(repo, object)? {
+                let line = format_tree_entry(repo, &LsTreeOptions::default(), &entry)?;
+                out.write_all(line.as_bytes())?;
+            }
+        }
+    }
+    Ok(0)
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct CatFileBatchOptions {
+    /// The line printed for each object, with `%(objectname)`, `%(objecttype)`,
+    /// `%(objectsize)` and `%(rest)` filled in.
+    pub format: String,
+    /// Follow each line with the object's content, as `--batch` does.
+    pub contents: bool,
+    /// Go over every object in the store instead of reading names from stdin.
+    pub all_objects: bool,
+    /// List those objects in the order they are stored in rather than by id, as
+    /// `--unordered` does.
+    pub unordered: bool,
+}
+
+/// The default format of `--batch` and `--batch-check`.
+pub const DEFAULT_BATCH_FORMAT: &str = "%(objectname) %(objecttype) %(objectsize)";
+
+/// `git cat-file --batch[-check][=<format>]`: print a line in `options.format` (and
+/// then, for `--batch`, the object's content and a newline) for each object named on
+/// a line of stdin, or `<name> missing` for a name that isn't one. With
+/// `--batch-all-objects`, the objects are all those in the store instead, sorted by
+/// id as [`Repository::objects`] lists them, or with `--unordered` in the order they
+/// are stored in, as [`Repository::objects_in_storage_order`] lists them.
+///
+/// Only the part of a line before the first whitespace is the object name; the rest
+/// is available as `%(rest)`.
+pub fn git_cat_file_batch(repo: &Repository, options: &CatFileBatchOptions) -> Result<()> {
+    let format = parse_batch_format(&options.format)?;
+    let mut out = stdout().lock();
+    if options.all_objects {
+        let objects = match options.unordered {
+            true => repo.objects_in_storage_order()?,
+            false => repo.objects()?,
+        };
+        for info in objects {
+            let info = info?;
+            let hash = info.id.to_string();
+            write_batch_object(
+                repo, &mut out, options, &format, &hash, info.kind, info.size, "",
+            )?;
+        }
+        return Ok(out.flush()?);
+    }
+    for line in stdin().lock().lines() {
+        let line = line?;
+        let (name, rest) = match line.split_once(char::is_whitespace) {
+            Some((name, rest)) => (name, rest.trim_start()),
+            None => (line.as_str(), ""),
+        };
+        match rev_parse(repo, name)
+            .and_then(|hash| Ok((Object::read_git_object(repo, &hash)?, hash)))
+        {
+            Ok((object, hash)) => {
+                let (kind, size) = (object.kind, object.expected_size);
+                write_batch_object(repo, &mut out, options, &format, &hash, kind, size, rest)?;
+            }
+            Err(_) => writeln!(out, "{name} missing")?,
+        }
+        // Whoever feeds stdin may wait for each answer before asking the next.
+        out.flush()?;
+    }
+    Ok(())
+}
+
+/// A piece of a `--batch` format: literal text or a `%(<atom>)` placeholder.
+enum BatchFormatPart {
+    Text(String),
+    ObjectName,
+    ObjectType,
+    ObjectSize,
+    Rest,
+}
+
+fn parse_batch_format(format: &str) -> Result<Vec<BatchFormatPart>> {
+    let mut parts = Vec::new();
+    let mut rest = format;
+    while let Some(start) = rest.find("%(") {
+        let Some(len) = rest[start..].find(')') else {
+            break;
+        };
+        if start > 0 {
+            parts.push(BatchFormatPart::Text(rest[..start].to_string()));
         }
+        parts.push(match &rest[start + 2..start + len] {
+            "objectname" => BatchFormatPart::ObjectName,
+            "objecttype" => BatchFormatPart::ObjectType,
+            "objectsize" => BatchFormatPart::ObjectSize,
+            "rest" => BatchFormatPart::Rest,
+            atom => bail!("unknown format element: {atom}"),
+        });
+        rest = &rest[start + len + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(BatchFormatPart::Text(rest.to_string()));
+    }
+    Ok(parts)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn write_batch_object(
+    repo: &Repository,
+    out: &mut impl Write,
+    options: &CatFileBatchOptions,
+    format: &[BatchFormatPart],
+    hash: &str,
+    kind: ObjectKind,
+    size: u64,
+    rest: &str,
+) -> Result<()> {
+    for part in format {
+        match part {
+            BatchFormatPart::Text(text) => out.write_all(text.as_bytes())?,
+            BatchFormatPart::ObjectName => out.write_all(hash.as_bytes())?,
+            BatchFormatPart::ObjectType => out.write_all(kind.to_str().as_bytes())?,
+            BatchFormatPart::ObjectSize => write!(out, "{size}")?,
+            BatchFormatPart::Rest => out.write_all(rest.as_bytes())?,
+        }
+    }
+    writeln!(out)?;
+    if options.contents {
+        let mut object = Object::read_git_object(repo, hash)?;
+        copy(&mut object.reader, out)?;
+        object.ensure_fully_read()?;
+        writeln!(out)?;
     }
     Ok(())
 }
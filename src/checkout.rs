@@ -0,0 +1,547 @@
+use crate::branch::{check_new_branch_name, create_branch, tracking_info};
+use crate::commit_read::Commit;
+use crate::config::Config;
+use crate::diff::{DiffSide, diff_trees, diff_trees_to_check_out};
+use crate::fsck::check_entry_name;
+use crate::index::{Index, has_symlinks, read_symlink, trust_executable_bit};
+use crate::object_id::ObjectId;
+use crate::object_read::{Object, ObjectKind};
+use crate::parallel_checkout::write_worktree_files;
+use crate::refs::{attach_head, current_branch, detach_head, resolve_ref};
+use crate::repository::Repository;
+use anyhow::{Context, Result, bail};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs::{OpenOptions, create_dir_all, remove_dir, remove_file, symlink_metadata};
+use std::io::{ErrorKind, Write, copy, sink};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt, symlink};
+use std::path::Path;
+
+const DETACHED_HEAD_ADVICE: &str = "\
+You are in 'detached HEAD' state. You can look around, make experimental
+changes and commit them, and you can discard any commits you make in this
+state without impacting any branches by switching back to a branch.
+
+If you want to create a new branch to retain commits you create, you may
+do so (now or later) by using -c with the switch command. Example:
+
+  git switch -c <new-branch-name>
+
+Or undo this operation with:
+
+  git switch -
+
+Turn off this advice by setting config variable advice.detachedHead to false
+";
+
+/// How [`git_checkout`] treats its target.
+#[derive(Debug, Default)]
+pub struct CheckoutOptions {
+    /// Detach HEAD at the target even if it is a branch.
+    pub detach: bool,
+    /// Create this branch at the target and switch to it.
+    pub new_branch: Option<String>,
+    /// Refuse to detach HEAD unless asked to, as `switch` does.
+    pub require_branch: bool,
+}
+
+/// Switch to `target`: a branch name attaches HEAD to that branch, anything else
+/// that names a commit (or `detach` being set) detaches HEAD at that commit. With
+/// `new_branch`, that branch is created at `target` first.
+///
+/// The working directory is updated from the tree of the current HEAD to the tree of
+/// the new one. Files that differ between the two are rewritten or removed; local
+/// changes to other files are carried over. If a file that has to change has local
+/// modifications (or is untracked and would be overwritten), nothing is touched, and
+/// no branch is created.
+///
+/// What git reports about the switch goes to `out` and `err` as git has it on
+/// standard output and standard error.
+pub fn git_checkout(
+    repo: &Repository,
+    config: &Config,
+    out: &mut impl Write,
+    err: &mut impl Write,
+    target: &str,
+    options: &CheckoutOptions,
+) -> Result<()> {
+    let old_branch = current_branch(repo)?;
+    let old_commit = resolve_ref(repo, "HEAD")?
+        .map(|hash| Commit::read(repo, &hash))
+        .transpose()?;
+
+    let branch = format!("refs/heads/{target}");
+    let is_branch = resolve_ref(repo, &branch)?.is_some();
+    let new_branch = match &options.new_branch {
+        Some(name) => {
+            check_new_branch_name(repo, name)?;
+            Some(name.as_str())
+        }
+        None if is_branch && !options.detach => Some(target),
+        None => None,
+    };
+    let new_commit = if is_branch {
+        Commit::read(repo, &branch)?
+    } else {
+        let Ok((hash, _)) = Object::peel_to(repo, target, ObjectKind::Commit) else {
+            bail!("invalid reference: {target}");
+        };
+        Commit::read(repo, &hash)?
+    };
+    if new_branch.is_none() && options.require_branch && !options.detach {
+        bail!("a branch is expected, got '{target}'");
+    }
+
+    update_worktree(
+        repo,
+        err,
+        old_commit.as_ref().map(|c| c.tree.as_str()),
+        &new_commit.tree,
+    )?;
+
+    let moving_from = match (&old_branch, &old_commit) {
+        (Some(branch), _) => branch.clone(),
+        (None, Some(commit)) => commit.hash.clone(),
+        (None, None) => "HEAD".to_string(),
+    };
+    if let Some(name) = &options.new_branch
+        && let (_, Some(upstream)) = create_branch(repo, config, name, target)?
+    {
+        writeln!(out, "branch '{name}' set up to track '{upstream}'.")?;
+    }
+    let message = format!(
+        "checkout: moving from {moving_from} to {}",
+        new_branch.unwrap_or(target)
+    );
+    if let Some(old_commit) = &old_commit
+        && old_branch.is_none()
+        && old_commit.hash != new_commit.hash
+    {
+        writeln!(
+            err,
+            "Previous HEAD position was {}",
+            describe_commit(old_commit)
+        )?;
+    }
+    match new_branch {
+        Some(branch) => {
+            attach_head(repo, config, branch, &message)?;
+            if options.new_branch.is_some() {
+                writeln!(err, "Switched to a new branch '{branch}'")?;
+            } else if old_branch.as_deref() == Some(branch) {
+                writeln!(err, "Already on '{branch}'")?;
+            } else {
+                writeln!(err, "Switched to branch '{branch}'")?;
+            }
+            if let Some(info) = tracking_info(repo, config, branch)? {
+                writeln!(out, "{info}")?;
+            }
+        }
+        None => {
+            detach_head(repo, config, &new_commit.hash, &message)?;
+            if old_branch.is_some()
+                && !options.detach
+                && config.get_bool("advice.detachedHead")?.unwrap_or(true)
+            {
+                writeln!(
+                    err,
+                    "Note: switching to '{target}'.\n\n{DETACHED_HEAD_ADVICE}"
+                )?;
+            }
+            writeln!(err, "HEAD is now at {}", describe_commit(&new_commit))?;
+        }
+    }
+    Ok(())
+}
+
+fn describe_commit(commit: &Commit) -> String {
+    format!("{} {}", &commit.hash[..7], commit.subject())
+}
+
+/// Bring the working directory from `old_tree` (`None` for an unborn branch) to
+/// `new_tree`, refusing if any file that has to change has local modifications.
+///
+/// The index entries of the files that change are set to `new_tree`'s; those of
+/// other files are kept, so changes staged to them are carried over too.
+///
+/// With `core.ignoreCase`, a file being removed doesn't count as in the way of one
+/// whose name differs from it only in case, and paths of `new_tree` that differ only
+/// in case, of which the filesystem can hold only one, are warned about on `err`.
+pub fn update_worktree(
+    repo: &Repository,
+    err: &mut impl Write,
+    old_tree: Option<&str>,
+    new_tree: &str,
+) -> Result<()> {
+    let config = Config::load(repo)?;
+    let ignore_case = config.get_bool("core.ignoreCase")?.unwrap_or(false);
+    let old_tree = old_tree.map(parse_hash).transpose()?;
+    let new_tree = parse_hash(new_tree)?;
+    let changes = diff_trees_to_check_out(repo, old_tree.as_ref(), Some(&new_tree))?;
+    let removed: HashSet<String> = changes
+        .iter()
+        .filter(|change| ignore_case && change.new.is_none())
+        .map(|change| change.path.to_ascii_lowercase())
+        .collect();
+
+    check_path_lengths(
+        repo,
+        &config,
+        changes
+            .iter()
+            .filter(|change| change.new.is_some())
+            .map(|change| change.path.as_str()),
+    )?;
+
+    let mut modified = Vec::new();
+    let mut untracked = Vec::new();
+    for change in &changes {
+        let path = Path::new(&change.path);
+        if !worktree_matches(repo, path, change.old.as_ref())?
+            && !worktree_matches(repo, path, change.new.as_ref())?
+        {
+            if change.old.is_some() {
+                modified.push(change.path.as_str());
+            } else if !removed.contains(&change.path.to_ascii_lowercase()) {
+                untracked.push(change.path.as_str());
+            }
+        }
+    }
+    if !modified.is_empty() {
+        bail!(
+            "Your local changes to the following files would be overwritten by checkout:\n\t{}\n\
+             Please commit your changes or stash them before you switch branches.",
+            modified.join("\n\t")
+        );
+    }
+    if !untracked.is_empty() {
+        bail!(
+            "The following untracked working tree files would be overwritten by checkout:\n\t{}\n\
+             Please move or remove them before you switch branches.",
+            untracked.join("\n\t")
+        );
+    }
+
+    // Removals go first so that a file replaced by a directory (or the reverse) is out
+    // of the way before its replacement is written.
+    let mut index = Index::load(repo)?;
+    for change in changes.iter().filter(|change| change.new.is_none()) {
+        remove_worktree_file(repo, Path::new(&change.path))?;
+        index.remove(&change.path);
+    }
+    let mut files = Vec::new();
+    for change in &changes {
+        if let Some(new) = &change.new
+            && !worktree_matches(repo, Path::new(&change.path), Some(new))?
+        {
+            files.push((change.path.as_str(), new));
+        }
+    }
+    write_worktree_files(repo, &config, &files)?;
+    for change in &changes {
+        if let Some(new) = &change.new {
+            stage_side(repo, &mut index, &change.path, new)?;
+        }
+    }
+    index.save(repo)?;
+    if ignore_case {
+        warn_case_collisions(repo, err, &new_tree)?;
+    }
+    Ok(())
+}
+
+/// Bring the working tree and index to `commit`'s tree (no files at all for `None`),
+/// whatever state they are in, conflicts included, as `am --abort` and `cherry-pick
+/// --abort` need. Like `git reset --merge`, only tracked paths are touched: a file
+/// the index already has as the tree does keeps its changes in the working tree, and
+/// untracked files are left alone.
+pub fn reset_worktree(repo: &Repository, commit: Option<&str>) -> Result<()> {
+    let wanted: BTreeMap<String, DiffSide> = match commit {
+        Some(commit) => {
+            let (tree, _) = Object::peel_to(repo, commit, ObjectKind::Tree)?;
+            diff_trees_to_check_out(repo, None, Some(&parse_hash(&tree)?))?
+                .into_iter()
+                .filter_map(|file| Some((file.path, file.new?)))
+                .collect()
+        }
+        None => BTreeMap::new(),
+    };
+    let mut index = Index::load(repo)?;
+    let indexed: BTreeSet<String> = index.entries.iter().map(|e| e.path.clone()).collect();
+    // Removals go first, so that a directory can take a removed file's place.
+    for path in indexed.iter().filter(|path| !wanted.contains_key(*path)) {
+        remove_worktree_file(repo, Path::new(path))?;
+        index.remove(path);
+    }
+    for (path, side) in &wanted {
+        let current = index.entry(path).map(|entry| DiffSide {
+            mode: format!("{:o}", entry.mode),
+            hash: entry.id,
+        });
+        let unmerged = index
+            .entries
+            .iter()
+            .any(|e| &e.path == path && e.stage != 0);
+        if current.as_ref() == Some(side) && !unmerged {
+            continue;
+        }
+        index.remove(path);
+        write_worktree_file(repo, Path::new(path), side)?;
+        stage_side(repo, &mut index, path, side)?;
+    }
+    index.save(repo)
+}
+
+/// Check out `tree` into a working directory and index that are still empty, as
+/// `git clone` does: there is nothing to compare or remove, so the files are
+/// written straight from the object store, in parallel (see [`write_worktree_files`]).
+/// Paths only one of which the filesystem can hold are warned about on `err`.
+pub fn checkout_new_worktree(
+    repo: &Repository,
+    config: &Config,
+    err: &mut impl Write,
+    tree: &str,
+) -> Result<()> {
+    let tree = parse_hash(tree)?;
+    let files = diff_trees_to_check_out(repo, None, Some(&tree))?;
+    check_path_lengths(repo, config, files.iter().map(|file| file.path.as_str()))?;
+    let files: Vec<(&str, &DiffSide)> = files
+        .iter()
+        .filter_map(|file| Some((file.path.as_str(), file.new.as_ref()?)))
+        .collect();
+    write_worktree_files(repo, config, &files)?;
+    let mut index = Index::load(repo)?;
+    for (path, side) in files {
+        stage_side(repo, &mut index, path, side)?;
+    }
+    index.save(repo)?;
+    if config.get_bool("core.ignoreCase")?.unwrap_or(false) {
+        warn_case_collisions(repo, err, &tree)?;
+    }
+    Ok(())
+}
+
+/// Warn, as `git clone` does, about the paths of `tree` that only differ in case, of
+/// which a case-insensitive filesystem has only one in the working directory.
+fn warn_case_collisions(repo: &Repository, err: &mut impl Write, tree: &ObjectId) -> Result<()> {
+    let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for file in diff_trees(repo, None, Some(tree))? {
+        groups
+            .entry(file.path.to_ascii_lowercase())
+            .or_default()
+            .push(file.path);
+    }
+    let collided: Vec<String> = groups
+        .into_values()
+        .filter(|paths| paths.len() > 1)
+        .flatten()
+        .collect();
+    if !collided.is_empty() {
+        writeln!(
+            err,
+            "warning: the following paths have collided (e.g. case-sensitive paths\n\
+             on a case-insensitive filesystem) and only one from the same\n\
+             colliding group is in the working tree:\n"
+        )?;
+        for path in collided {
+            writeln!(err, "  '{path}'")?;
+        }
+    }
+    Ok(())
+}
+
+/// The longest file name and the longest absolute path, in bytes, that can be
+/// created: Linux's `NAME_MAX` and `PATH_MAX`, less the terminating NUL.
+const NAME_MAX: usize = 255;
+const PATH_MAX: usize = 4095;
+
+/// Refuse to check out `paths` if any of them has a name or is in a place too long
+/// to be created, before anything is written, so that a checkout fails as a whole
+/// rather than leaving the working directory half updated. `core.longPaths` skips
+/// the check, for filesystems known to take longer paths.
+pub fn check_path_lengths<'a>(
+    repo: &Repository,
+    config: &Config,
+    paths: impl Iterator<Item = &'a str>,
+) -> Result<()> {
+    if config.get_bool("core.longPaths")?.unwrap_or(false) {
+        return Ok(());
+    }
+    let work_tree = repo.work_tree.as_os_str().len();
+    let too_long: Vec<&str> = paths
+        .filter(|path| {
+            work_tree + 1 + path.len() > PATH_MAX
+                || path.split('/').any(|name| name.len() > NAME_MAX)
+        })
+        .collect();
+    if !too_long.is_empty() {
+        bail!(
+            "the following paths are too long to be checked out (names are limited to \
+             {NAME_MAX} bytes, and paths to {PATH_MAX} including the work tree's):\n\t{}\n\
+             Set core.longPaths if the filesystem allows longer ones.",
+            too_long.join("\n\t")
+        );
+    }
+    Ok(())
+}
+
+/// Record in `index` that the file at `path` is now `side`.
+pub fn stage_side(repo: &Repository, index: &mut Index, path: &str, side: &DiffSide) -> Result<()> {
+    let mode = u32::from_str_radix(&side.mode, 8)?;
+    index.add_checked_out(repo, path, mode, side.hash)
+}
+
+pub fn parse_hash(hash: &str) -> Result<ObjectId> {
+    hash.parse()
+}
+
+/// Whether the file at `path` is exactly `side` (or absent, for `None`).
+pub fn worktree_matches(repo: &Repository, path: &Path, side: Option<&DiffSide>) -> Result<bool> {
+    let file = repo.path(path);
+    let metadata = match symlink_metadata(&file) {
+        Ok(metadata) => metadata,
+        Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) => {
+            return Ok(side.is_none());
+        }
+        Err(e) => return Err(e).with_context(|| format!("failed to stat {}", path.display())),
+    };
+    let Some(side) = side else {
+        // An empty directory is as good as no file at all.
+        return Ok(metadata.is_dir() && file.read_dir().is_ok_and(|mut d| d.next().is_none()));
+    };
+    let matches = match side.mode.as_str() {
+        "160000" => metadata.is_dir(),
+        "120000" => {
+            (metadata.is_symlink() || metadata.is_file() && !has_symlinks(repo))
+                && read_symlink(repo, &file)? == read_blob(repo, &side.hash)?
+        }
+        mode => {
+            metadata.is_file()
+                && (!trust_executable_bit(repo)
+                    || (metadata.permissions().mode() & 0o111 != 0) == (mode == "100755"))
+                && Object::from_blob_file(&file)?.write(repo.hash_algorithm()?, sink())?
+                    == side.hash
+        }
+    };
+    Ok(matches)
+}
+
+fn read_blob(repo: &Repository, hash: &ObjectId) -> Result<Vec<u8>> {
+    Object::read_git_object(repo, &hash.to_string())?.read_all()
+}
+
+/// Check that `path` (relative to the top of the work tree) stays inside it, as git's
+/// `verify_path` and `has_symlink_leading_path` do before a file is written or
+/// removed: each component must be a name a tree entry may have (see
+/// [`check_entry_name`]), and no directory on the way may be a symlink, which would
+/// lead elsewhere.
+pub fn check_worktree_path(repo: &Repository, path: &Path) -> Result<()> {
+    let bytes = path.as_os_str().as_encoded_bytes();
+    if bytes
+        .split(|&b| b == b'/')
+        .any(|name| check_entry_name(name).is_err())
+    {
+        bail!("invalid path '{}'", path.display());
+    }
+    let mut leading = repo.work_tree.clone();
+    for dir in path.parent().into_iter().flat_map(Path::components) {
+        leading.push(dir);
+        if symlink_metadata(&leading).is_ok_and(|metadata| metadata.is_symlink()) {
+            bail!("'{}' is beyond a symbolic link", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Remove a tracked file, then any directories it leaves empty.
+pub fn remove_worktree_file(repo: &Repository, path: &Path) -> Result<()> {
+    check_worktree_path(repo, path)?;
+    let file = repo.path(path);
+    match symlink_metadata(&file) {
+        Ok(metadata) if metadata.is_dir() => {
+            // An empty submodule directory; non-empty ones are left alone.
+            let _ = remove_dir(&file);
+        }
+        Ok(_) => {
+            remove_file(&file).with_context(|| format!("failed to remove {}", path.display()))?
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e).with_context(|| format!("failed to stat {}", path.display())),
+    }
+    let mut dir = path.parent();
+    while let Some(parent) = dir.filter(|d| !d.as_os_str().is_empty()) {
+        if remove_dir(repo.path(parent)).is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+    Ok(())
+}
+
+/// Replace whatever is at `path` with the blob, symlink or submodule directory `side`.
+pub fn write_worktree_file(repo: &Repository, path: &Path, side: &DiffSide) -> Result<()> {
+    clear_worktree_path(repo, path)?;
+    create_worktree_entry(repo, path, side)
+}
+
+/// Make way for a file at `path`: create the directories it goes in, and remove a
+/// file or empty directory already there.
+pub fn clear_worktree_path(repo: &Repository, path: &Path) -> Result<()> {
+    check_worktree_path(repo, path)?;
+    let file = repo.path(path);
+    if let Some(parent) = file.parent() {
+        create_dir_all(parent).with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    match symlink_metadata(&file) {
+        Ok(metadata) if metadata.is_dir() => remove_dir(&file)
+            .with_context(|| format!("{} is a non-empty directory", path.display()))?,
+        Ok(_) => {
+            remove_file(&file).with_context(|| format!("failed to remove {}", path.display()))?
+        }
+        Err(_) => {}
+    }
+    Ok(())
+}
+
+/// Create the blob, symlink or submodule directory `side` at `path`, where
+/// [`clear_worktree_path`] has made way for it. Failing with
+/// [`ErrorKind::AlreadyExists`] says something was put there since. A path that
+/// [`check_worktree_path`] refuses is never written.
+pub fn create_worktree_entry(repo: &Repository, path: &Path, side: &DiffSide) -> Result<()> {
+    check_worktree_path(repo, path)?;
+    let file = repo.path(path);
+    match side.mode.as_str() {
+        "160000" => {
+            create_dir_all(&file).with_context(|| format!("failed to create {}", path.display()))?
+        }
+        "120000" if !has_symlinks(repo) => {
+            // The target, as a file, for a filesystem that can't hold the symlink.
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&file)
+                .with_context(|| format!("failed to create {}", path.display()))?;
+            file.write_all(&read_blob(repo, &side.hash)?)
+                .with_context(|| format!("failed to write {}", path.display()))?;
+        }
+        "120000" => {
+            let target = String::from_utf8(read_blob(repo, &side.hash)?)
+                .with_context(|| format!("symlink target of {} is not UTF-8", path.display()))?;
+            symlink(target, &file)
+                .with_context(|| format!("failed to create symlink {}", path.display()))?;
+        }
+        mode => {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(if mode == "100755" { 0o777 } else { 0o666 })
+                .open(&file)
+                .with_context(|| format!("failed to create {}", path.display()))?;
+            // Large blobs are copied as they are inflated rather than read whole first.
+            let mut blob = Object::read_git_object(repo, &side.hash.to_string())?;
+            copy(&mut blob.reader, &mut file)
+                .with_context(|| format!("failed to write {}", path.display()))?;
+            blob.ensure_fully_read()?;
+        }
+    }
+    Ok(())
+}
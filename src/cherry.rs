@@ -0,0 +1,58 @@
+use crate::commit_read::{Commit, walk_commits};
+use crate::config::Config;
+use crate::patch_id::commit_patch_id;
+use crate::refs::{current_branch, upstream_ref};
+use crate::repository::Repository;
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+
+/// Find commits in `head` that haven't been applied to `upstream`.
+///
+/// Considers the non-merge commits in `upstream..head` (or `limit..head` when given,
+/// which must itself lie on `head`'s history) and compares each one's patch ID with the
+/// commits in `head..upstream`. Returns them oldest first, paired with `true` if an
+/// equivalent change already exists upstream. Without `upstream`, the current branch's
+/// configured upstream is used.
+pub fn git_cherry(
+    repo: &Repository,
+    upstream: Option<&str>,
+    head: &str,
+    limit: Option<&str>,
+) -> Result<Vec<(Commit, bool)>> {
+    let upstream = match upstream {
+        Some(upstream) => upstream.to_string(),
+        None => {
+            let config = Config::load(repo)?;
+            let branch = current_branch(repo)?.context("HEAD is detached; specify an upstream")?;
+            upstream_ref(&config, &branch)
+                .with_context(|| format!("no upstream configured for branch '{branch}'"))?
+        }
+    };
+    let upstream = Commit::read(repo, &upstream)?.hash;
+    let head = Commit::read(repo, head)?.hash;
+    let head_side = {
+        let mut hide = vec![upstream.clone()];
+        hide.extend(limit.map(str::to_string));
+        walk_commits(repo, std::slice::from_ref(&head), &hide)?
+    };
+    let upstream_side = walk_commits(repo, &[upstream], &[head])?;
+
+    let mut upstream_ids = HashSet::new();
+    for commit in upstream_side.iter().filter(|c| c.parents.len() <= 1) {
+        if let Some(id) = commit_patch_id(repo, commit)? {
+            upstream_ids.insert(id);
+        }
+    }
+    let mut out = Vec::new();
+    for commit in head_side.into_iter().rev() {
+        if commit.parents.len() > 1 {
+            continue;
+        }
+        let applied = match commit_patch_id(repo, &commit)? {
+            Some(id) => upstream_ids.contains(&id),
+            None => false,
+        };
+        out.push((commit, applied));
+    }
+    Ok(out)
+}
@@ -0,0 +1,191 @@
+use crate::checkout::checkout_new_worktree;
+use crate::commit_read::Commit;
+use crate::config::{Config, set_config_value};
+use crate::init::create_git_dir;
+use crate::refs::{RefTransaction, detach_head, resolve_ref_in, update_ref, write_symbolic_ref};
+use crate::remote::{get_remote, local_git_dir};
+use crate::repository::{Repository, git_path, hash_algorithm_in};
+use anyhow::{Context, Result, bail};
+use std::env::current_dir;
+use std::fs::{self, canonicalize, copy, create_dir_all, hard_link};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// How [`git_clone`] makes the new repository.
+#[derive(Debug, Default)]
+pub struct CloneOptions {
+    /// The branch to check out instead of the one the source's HEAD points at.
+    pub branch: Option<String>,
+    /// Copy the source's object files rather than hard-linking them.
+    pub no_hardlinks: bool,
+}
+
+/// `git clone [-b <branch>] [--no-hardlinks] <repository> [<directory>]`: make a new
+/// repository in `directory` (by default named after the source, without `.git`)
+/// that has every object of the local repository `source`, with `origin` set up as a
+/// remote for it: its branches become remote-tracking branches `origin/<branch>`, its
+/// tags are copied, and `refs/remotes/origin/HEAD` points at the branch its HEAD
+/// does. That branch (or `branch`) is then created locally, tracking `origin`'s, and
+/// checked out.
+///
+/// As git does for a local clone, the source's object files are hard-linked (or
+/// copied, across filesystems or with `no_hardlinks`) as they are, packs included, so
+/// nothing is unpacked: the new working tree is written straight from the packs.
+/// Progress and warnings are written to `err`.
+pub fn git_clone(
+    err: &mut impl Write,
+    source: &str,
+    directory: Option<&str>,
+    options: &CloneOptions,
+) -> Result<()> {
+    let Some(source_git_dir) = local_git_dir(&current_dir()?, source) else {
+        match source.contains("://") {
+            true => bail!("cannot clone {source}: only local repositories are supported"),
+            false => bail!("repository '{source}' does not exist"),
+        }
+    };
+    let source_git_dir = canonicalize(&source_git_dir)
+        .with_context(|| format!("repository '{source}' does not exist"))?;
+    let url = match source.strip_prefix("file://") {
+        Some(_) => source.to_string(),
+        None => canonicalize(source)?.display().to_string(),
+    };
+    let directory = match directory {
+        Some(directory) => directory.to_string(),
+        None => default_directory(source),
+    };
+    let path = Path::new(&directory);
+    if path.exists() && path.read_dir().is_ok_and(|mut d| d.next().is_some()) {
+        bail!("destination path '{directory}' already exists and is not an empty directory.");
+    }
+    writeln!(err, "Cloning into '{directory}'...")?;
+    create_dir_all(path)
+        .with_context(|| format!("could not create work tree dir '{directory}'"))?;
+    let repo = &Repository::at(&canonicalize(path)?);
+
+    let source_objects = git_path(&source_git_dir, "objects");
+    create_git_dir(repo, Some(hash_algorithm_in(&source_objects)?))?;
+    let config_path = repo.git_path("config");
+    set_config_value(&config_path, "remote.origin.url", &url)?;
+    set_config_value(
+        &config_path,
+        "remote.origin.fetch",
+        "+refs/heads/*:refs/remotes/origin/*",
+    )?;
+    let config = Config::load(repo)?;
+    let remote_refs = get_remote(&config, "origin")?.query(repo)?;
+    link_objects(
+        &git_path(&source_git_dir, "objects"),
+        &repo.git_path("objects"),
+        !options.no_hardlinks,
+    )?;
+
+    let message = format!("clone: from {url}");
+    let mut transaction = RefTransaction::new(&repo.git_dir);
+    for (name, hash) in &remote_refs.refs {
+        if let Some(branch) = name.strip_prefix("refs/heads/") {
+            let tracking = format!("refs/remotes/origin/{branch}");
+            transaction.update(&tracking, None, Some(hash), &message);
+        } else if name.starts_with("refs/tags/") {
+            transaction.update(name, None, Some(hash), &message);
+        }
+    }
+    transaction.commit(&config)?;
+    if let Some(remote_head) = &remote_refs.head
+        && let Some(branch) = remote_head.strip_prefix("refs/heads/")
+        && remote_refs.refs.contains_key(remote_head)
+    {
+        write_symbolic_ref(
+            repo,
+            "refs/remotes/origin/HEAD",
+            &format!("refs/remotes/origin/{branch}"),
+        )?;
+    }
+
+    let head = match &options.branch {
+        Some(branch) => {
+            let ref_name = format!("refs/heads/{branch}");
+            if !remote_refs.refs.contains_key(&ref_name) {
+                bail!("Remote branch {branch} not found in upstream origin");
+            }
+            Some(ref_name)
+        }
+        None => remote_refs.head.clone(),
+    };
+    let Some(head) = head else {
+        // The source's HEAD is detached: so is the clone's, at the same commit.
+        let Some(hash) = resolve_ref_in(&source_git_dir, "HEAD")? else {
+            bail!("remote HEAD is detached at a missing commit");
+        };
+        detach_head(repo, &config, &hash, &message)?;
+        checkout_new_worktree(repo, &config, err, &Commit::read(repo, &hash)?.tree)?;
+        writeln!(err, "done.")?;
+        return Ok(());
+    };
+    let Some(branch) = head.strip_prefix("refs/heads/") else {
+        bail!("remote HEAD points at {head}, which isn't a branch");
+    };
+    write_symbolic_ref(repo, "HEAD", &head)?;
+    let Some(hash) = remote_refs.refs.get(&head) else {
+        if remote_refs.refs.is_empty() {
+            writeln!(
+                err,
+                "warning: You appear to have cloned an empty repository."
+            )?;
+        } else {
+            writeln!(
+                err,
+                "warning: remote HEAD refers to nonexistent ref, unable to checkout"
+            )?;
+        }
+        writeln!(err, "done.")?;
+        return Ok(());
+    };
+    update_ref(repo, &config, &head, hash, &message)?;
+    set_config_value(&config_path, &format!("branch.{branch}.remote"), "origin")?;
+    set_config_value(&config_path, &format!("branch.{branch}.merge"), &head)?;
+
+    checkout_new_worktree(repo, &config, err, &Commit::read(repo, hash)?.tree)?;
+    writeln!(err, "done.")?;
+    Ok(())
+}
+
+/// The directory `git clone` names after the repository at `source`: its last path
+/// component, without a `.git` directory or suffix.
+fn default_directory(source: &str) -> String {
+    let path = source.trim_end_matches('/');
+    let path = path.strip_suffix("/.git").unwrap_or(path);
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.strip_suffix(".git").unwrap_or(name).to_string()
+}
+
+/// Give `to` every file under the `objects` directory `from`, loose objects and packs
+/// alike, as hard links if `hardlinks` and the filesystem allows, else as copies.
+fn link_objects(from: &Path, to: &Path, hardlinks: bool) -> Result<()> {
+    let mut stack: Vec<PathBuf> = vec![PathBuf::new()];
+    while let Some(dir) = stack.pop() {
+        let entries = fs::read_dir(from.join(&dir))
+            .with_context(|| format!("failed to read {}", from.join(&dir).display()))?;
+        for entry in entries {
+            let entry = entry?;
+            let relative = dir.join(entry.file_name());
+            let (source, destination) = (from.join(&relative), to.join(&relative));
+            if entry.file_type()?.is_dir() {
+                create_dir_all(&destination)
+                    .with_context(|| format!("failed to create {}", destination.display()))?;
+                stack.push(relative);
+                continue;
+            }
+            if !hardlinks || hard_link(&source, &destination).is_err() {
+                copy(&source, &destination).with_context(|| {
+                    format!(
+                        "failed to copy {} to {}",
+                        source.display(),
+                        destination.display()
+                    )
+                })?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,10 +1,17 @@
+use crate::commit_read::Commit;
+use crate::config::Config;
+use crate::encoding::Encoding;
+use crate::gpg::sign_buffer;
+use crate::ident::{Ident, Role, resolve_ident};
+use crate::object_id::ObjectId;
 use crate::object_read::{Object, ObjectKind};
-use anyhow::{Context, Result};
-use chrono::Local;
-use std::env;
+use crate::refs::{resolve_ref, update_ref};
+use crate::repository::Repository;
+use crate::write_tree::git_write_tree;
+use anyhow::{Context, Result, bail};
 use std::fmt::Write;
-use std::fs::File;
-use std::io::{BufRead, BufReader, Cursor};
+use std::fs::{read_to_string, remove_file};
+use std::io::{Cursor, Read, stdin};
 
 /// A *commit object* in Git represents a snapshot of the repository at a point in time,
 /// along with metadata about the author, committer, and commit message.
@@ -21,8 +28,8 @@ use std::io::{BufRead, BufReader, Cursor};
 ///
 ///     <commit message>
 ///
-/// - `<tree-id>`: 40-character hex SHA-1 (or SHA-256) of the root tree object
-/// - `<parent-id>`: 40-character hex SHA-1 (or SHA-256) of a parent commit
+/// - `<tree-id>`: hex SHA-1 (40 characters) or SHA-256 (64) of the root tree object
+/// - `<parent-id>`: hex SHA-1 or SHA-256 of a parent commit
 /// - `<name>`: author/committer name
 /// - `<email>`: author/committer email
 /// - `<timestamp>`: seconds since Unix epoch
@@ -56,62 +63,296 @@ use std::io::{BufRead, BufReader, Cursor};
 ///   Stored at:
 ///     .git/objects/e6/9de29bb2d1d6434b8b29ae775ad8c2e48c5391
 ///
+/// Author and committer are resolved separately (see [`resolve_ident`]), so
+/// `GIT_AUTHOR_*` / `GIT_COMMITTER_*` and `author.*` / `committer.*` config apply.
+///
+/// The message is stored verbatim, so callers are responsible for its trailing newline;
+/// see [`message_from_paragraphs`] and [`message_from_stdin`].
+///
 /// Note: Commits form a chain — each commit references its parent(s), allowing
 ///       Git to track history and perform merges.
 ///
-pub fn git_write_commit(
-    tree_hash: String,
-    parent_hash: Option<&str>,
+/// Fields are set one call at a time, e.g. for a merge:
+///
+///     CommitBuilder::new(tree).parent(ours).parent(theirs).message("Merge\n").write(&config)
+///
+/// If `i18n.commitEncoding` names an encoding other than UTF-8, the commit is written
+/// in it, with an `encoding` header after the committer. Extra headers follow, in the
+/// order given. With [`CommitBuilder::sign`], the commit is signed with GPG, using
+/// `user.signingKey` or else the committer identity, and the signature stored in a
+/// `gpgsig` header.
+#[derive(Debug, Clone)]
+pub struct CommitBuilder {
+    tree: String,
+    parents: Vec<String>,
+    author: Option<Ident>,
+    committer: Option<Ident>,
+    extra_headers: Vec<(String, String)>,
     message: String,
-) -> Result<[u8; 20]> {
-    let mut out = String::new();
-    writeln!(out, "tree {}", tree_hash)?;
-    if let Some(parent_hash) = parent_hash {
-        writeln!(out, "parent {}", parent_hash)?;
+    sign: bool,
+}
+
+impl CommitBuilder {
+    /// A commit of the tree `tree`, without parents and with an empty message.
+    pub fn new(tree: impl Into<String>) -> CommitBuilder {
+        CommitBuilder {
+            tree: tree.into(),
+            parents: Vec::new(),
+            author: None,
+            committer: None,
+            extra_headers: Vec::new(),
+            message: String::new(),
+            sign: false,
+        }
+    }
+
+    /// Add a parent; a merge has several, a root commit none.
+    pub fn parent(mut self, parent: impl Into<String>) -> CommitBuilder {
+        self.parents.push(parent.into());
+        self
+    }
+
+    /// Record `author` instead of the resolved author identity, as `am` does with
+    /// the sender of a patch.
+    pub fn author(mut self, author: Ident) -> CommitBuilder {
+        self.author = Some(author);
+        self
+    }
+
+    /// Record `committer` instead of the resolved committer identity, as history
+    /// rewriting does to keep a commit as it was.
+    pub fn committer(mut self, committer: Ident) -> CommitBuilder {
+        self.committer = Some(committer);
+        self
+    }
+
+    /// Add a header after the committer; newlines in `value` become continuation
+    /// lines.
+    pub fn extra_header(
+        mut self,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> CommitBuilder {
+        self.extra_headers.push((key.into(), value.into()));
+        self
+    }
+
+    /// Set the message, which is stored verbatim.
+    pub fn message(mut self, message: impl Into<String>) -> CommitBuilder {
+        self.message = message.into();
+        self
+    }
+
+    /// Sign the commit with GPG.
+    pub fn sign(mut self, sign: bool) -> CommitBuilder {
+        self.sign = sign;
+        self
+    }
+
+    /// Write the commit object, resolving the identities not given from `config`
+    /// and the environment. The tree and the parents must be full ids of a tree and
+    /// of commits the repository has.
+    pub fn write(&self, repo: &Repository, config: &Config) -> Result<ObjectId> {
+        check_object(repo, &self.tree, ObjectKind::Tree)?;
+        for parent in &self.parents {
+            check_object(repo, parent, ObjectKind::Commit)?;
+        }
+        let author = match &self.author {
+            Some(author) => author.clone(),
+            None => resolve_ident(Role::Author, config)?,
+        };
+        let committer = match &self.committer {
+            Some(committer) => committer.clone(),
+            None => resolve_ident(Role::Committer, config)?,
+        };
+        let encoding = commit_encoding(config)?;
+        let mut headers = String::new();
+        writeln!(headers, "tree {}", self.tree)?;
+        for parent in &self.parents {
+            writeln!(headers, "parent {parent}")?;
+        }
+        writeln!(headers, "author {author}")?;
+        writeln!(headers, "committer {committer}")?;
+        if let Some((name, _)) = &encoding {
+            write_header(&mut headers, "encoding", name);
+        }
+        for (key, value) in &self.extra_headers {
+            write_header(&mut headers, key, value);
+        }
+        let encode = |text: String| match &encoding {
+            Some((name, encoding)) => encoding
+                .encode(&text)
+                .with_context(|| format!("the commit has characters that {name} can't represent")),
+            None => Ok(text.into_bytes()),
+        };
+        if self.sign {
+            let key = match config.get("user.signingKey") {
+                Some(key) => key.to_string(),
+                None => format!("{} <{}>", committer.name, committer.email),
+            };
+            let payload = encode(format!("{headers}\n{}", self.message))?;
+            let signature = sign_buffer(config, &payload, &key)?;
+            write_header(&mut headers, "gpgsig", signature.trim_end_matches('\n'));
+        }
+        let out = encode(format!("{headers}\n{}", self.message))?;
+        let mut object = Object {
+            kind: ObjectKind::Commit,
+            expected_size: out.len() as u64,
+            reader: Cursor::new(out),
+        };
+        object.write_as_object(repo)
     }
-    let (time, tz) = get_time_and_timezone();
-    let (name, email) = get_name_and_email_from_git_config()?;
-    writeln!(out, "author {} <{}> {} {}", name, email, time, tz)?;
-    writeln!(out, "committer {} <{}> {} {}", name, email, time, tz)?;
-    writeln!(out)?;
-    writeln!(out, "{}", message)?;
-    let mut object = Object {
-        kind: ObjectKind::Commit,
-        expected_size: out.len() as u64,
-        reader: Cursor::new(out),
-    };
-    let hash = object.write_as_object()?;
-    Ok(hash)
 }
 
-fn get_time_and_timezone() -> (i64, String) {
-    let now = Local::now();
-    let time = now.timestamp();
-    let offset_seconds = now.offset().local_minus_utc();
-    let hours = offset_seconds / 3600;
-    let minutes = offset_seconds.abs() % 3600 / 60;
-    let tz = format!("{:+03}{:02}", hours, minutes);
-    (time, tz)
+/// What [`git_commit`] records.
+#[derive(Debug, Default, Clone)]
+pub struct CommitOptions {
+    /// The message's paragraphs (`-m`); with `amend` and none, the message is kept.
+    pub message: Vec<String>,
+    /// Sign the commit with GPG.
+    pub sign: bool,
+    /// Replace the commit HEAD points at instead of adding one on top of it.
+    pub amend: bool,
+    /// Record the commit even if its tree is HEAD's.
+    pub allow_empty: bool,
 }
 
-fn get_name_and_email_from_git_config() -> Result<(String, String)> {
-    let mut path = env::home_dir().context("Couldn't determine home directory")?;
-    path.push(".gitconfig");
-    let file = File::open(&path)
-        .with_context(|| format!("Failed to open git config file at {:?}", path))?;
-    let reader = BufReader::new(file);
-    let mut name = String::new();
-    let mut email = String::new();
-    for line in reader.lines() {
-        let line = line.context("Failed to read git config file line")?;
-        if let Some(nm) = line.strip_prefix("name = ") {
-            name = nm.trim().to_string();
-        } else if let Some(em) = line.strip_prefix("email = ") {
-            email = em.trim().to_string();
+/// `git commit -m <message>`: record the index as a new commit on top of HEAD, and
+/// move the current branch (or a detached HEAD) to it. On an unborn branch, as in a
+/// fresh repository, it is a root commit, without parents, and creates the branch.
+///
+/// With `amend`, the commit replaces HEAD's own instead: it has the same parents
+/// and author, and its message unless a new one is given. Returns the new commit.
+///
+/// Otherwise a commit that would change nothing from HEAD is refused unless it
+/// concludes a merge or `allow_empty` is set.
+///
+/// A merge stopped at a conflict is concluded by it: the commits in `MERGE_HEAD`
+/// are parents too, and without `-m` the message is `MERGE_MSG`'s, less its comments.
+/// So is a cherry-pick, the commit keeping the author of the one in `CHERRY_PICK_HEAD`.
+pub fn git_commit(repo: &Repository, config: &Config, options: &CommitOptions) -> Result<ObjectId> {
+    let tree = git_write_tree(repo)?;
+    let head = resolve_ref(repo, "HEAD")?;
+    let message = (!options.message.is_empty()).then(|| message_from_paragraphs(&options.message));
+    let (builder, kind) = match (options.amend, head) {
+        (true, None) => bail!("You have nothing to amend."),
+        (true, Some(head)) => {
+            let amended = Commit::read(repo, &head)?;
+            let builder = amended
+                .parents
+                .iter()
+                .fold(CommitBuilder::new(tree.to_string()), CommitBuilder::parent)
+                .author(amended.author)
+                .message(message.unwrap_or(amended.message));
+            (builder, " (amend)")
         }
-        if !name.is_empty() && !email.is_empty() {
-            break;
+        (false, head) => {
+            // What a merge that stopped at a conflict left to conclude it with.
+            let merge_heads = read_to_string(repo.git_path("MERGE_HEAD")).unwrap_or_default();
+            if let Some(head) = &head
+                && !options.allow_empty
+                && merge_heads.is_empty()
+                && Commit::read(repo, head)?.tree == tree.to_string()
+            {
+                bail!("nothing to commit, working tree clean");
+            }
+            let message = match (message, read_to_string(repo.git_path("MERGE_MSG"))) {
+                (Some(message), _) => message,
+                (None, Ok(merge_message)) => strip_comments(&merge_message),
+                (None, Err(_)) => bail!("no commit message given"),
+            };
+            let mut builder = head
+                .iter()
+                .map(String::as_str)
+                .chain(merge_heads.lines())
+                .fold(CommitBuilder::new(tree.to_string()), CommitBuilder::parent)
+                .message(message);
+            if let Ok(picked) = read_to_string(repo.git_path("CHERRY_PICK_HEAD")) {
+                builder = builder.author(Commit::read(repo, picked.trim_end())?.author);
+            }
+            let kind = match (&head, merge_heads.is_empty()) {
+                (None, _) => " (initial)",
+                (Some(_), false) => " (merge)",
+                (Some(_), true) => "",
+            };
+            (builder, kind)
         }
+    };
+    let subject = builder.message.lines().next().unwrap_or("").to_string();
+    let commit = builder.sign(options.sign).write(repo, config)?;
+    update_ref(
+        repo,
+        config,
+        "HEAD",
+        &commit.to_string(),
+        &format!("commit{kind}: {subject}"),
+    )?;
+    for name in ["MERGE_HEAD", "MERGE_MSG", "CHERRY_PICK_HEAD", "REVERT_HEAD"] {
+        let _ = remove_file(repo.git_path(name));
+    }
+    Ok(commit)
+}
+
+/// A message as `MERGE_MSG` has it, without its `#` comment lines and the blank
+/// lines they leave at the end.
+pub fn strip_comments(message: &str) -> String {
+    let mut stripped: String = message
+        .split_inclusive('\n')
+        .filter(|line| !line.starts_with('#'))
+        .collect();
+    stripped.truncate(stripped.trim_end().len());
+    stripped.push('\n');
+    stripped
+}
+
+/// The encoding `i18n.commitEncoding` names, with the name as given, unless it is
+/// UTF-8, which commits are in when they don't say.
+fn commit_encoding(config: &Config) -> Result<Option<(String, Encoding)>> {
+    let Some(name) = config.get("i18n.commitEncoding") else {
+        return Ok(None);
+    };
+    match Encoding::from_name(name) {
+        Some(Encoding::Utf8) => Ok(None),
+        Some(encoding) => Ok(Some((name.to_string(), encoding))),
+        None => bail!("unsupported i18n.commitEncoding '{name}'"),
+    }
+}
+
+/// Append the header `key` with `value`, whose further lines are continuation lines
+/// starting with a space.
+/// Fail unless `hash` is a full, lowercase id of an object of kind `kind` in the
+/// repository.
+fn check_object(repo: &Repository, hash: &str, kind: ObjectKind) -> Result<()> {
+    if !repo.hash_algorithm()?.is_hex_id(hash) || hash.bytes().any(|b| b.is_ascii_uppercase()) {
+        bail!("not a valid object name {hash}");
+    }
+    let object = Object::read_git_object(repo, hash)?;
+    if object.kind != kind {
+        bail!("{hash} is not a valid '{}' object", kind.to_str());
+    }
+    Ok(())
+}
+
+fn write_header(headers: &mut String, key: &str, value: &str) {
+    let mut lines = value.split('\n');
+    headers.push_str(&format!("{key} {}\n", lines.next().unwrap_or("")));
+    for line in lines {
+        headers.push_str(&format!(" {line}\n"));
     }
-    Ok((name, email))
+}
+
+/// Join `-m` paragraphs with blank lines, as `git commit-tree -m a -m b` does.
+pub fn message_from_paragraphs(paragraphs: &[String]) -> String {
+    let mut message = paragraphs.join("\n\n");
+    message.push('\n');
+    message
+}
+
+/// Read a commit message from stdin, kept byte for byte like git's plumbing does.
+pub fn message_from_stdin() -> Result<String> {
+    let mut message = String::new();
+    stdin()
+        .read_to_string(&mut message)
+        .context("failed to read commit message from stdin")?;
+    Ok(message)
 }
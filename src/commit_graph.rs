@@ -0,0 +1,196 @@
+use crate::object_id::{HashAlgorithm, ObjectId};
+use crate::repository::hash_algorithm_in;
+use anyhow::{Context, Result, bail, ensure};
+use std::cmp::Ordering;
+use std::fs::read;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// The commit-graph file, `objects/info/commit-graph`, as `git gc` and `git
+/// commit-graph write` leave it: the repository's commits sorted by id, each with its
+/// tree, parents, commit date and generation number, in chunks named by a table after
+/// the header.
+///
+/// Only generation numbers are read from it, to order walks so that a commit comes
+/// before its ancestors whatever their dates say. Those of a `GDA2` chunk are
+/// corrected commit dates: a commit's date, or one more than its parents' corrected
+/// dates if that is later, kept as an offset from the commit date, with offsets that
+/// don't fit 31 bits in a `GDO2` chunk. A file without them has topological levels
+/// in its commit data, which order commits just as well but tell less apart. Split
+/// graphs (`objects/info/commit-graphs`) aren't read.
+pub struct CommitGraph {
+    data: Vec<u8>,
+    algorithm: HashAlgorithm,
+    count: usize,
+    fanout: usize,
+    lookup: usize,
+    commit_data: usize,
+    generation_data: Option<usize>,
+    generation_overflow: Option<(usize, usize)>,
+}
+
+const SIGNATURE: &[u8] = b"CGPH";
+
+impl CommitGraph {
+    /// The commit graph of the object store `objects_dir`, or `None` if it has none.
+    pub fn open(objects_dir: &Path) -> Result<Option<CommitGraph>> {
+        let path = objects_dir.join("info/commit-graph");
+        let data = match read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        let algorithm = hash_algorithm_in(objects_dir)?;
+        CommitGraph::parse(data, algorithm)
+            .map(Some)
+            .with_context(|| format!("invalid commit-graph {}", path.display()))
+    }
+
+    fn parse(data: Vec<u8>, algorithm: HashAlgorithm) -> Result<CommitGraph> {
+        ensure!(
+            data.len() >= 8 + algorithm.len() && data.starts_with(SIGNATURE),
+            "not a commit-graph file"
+        );
+        ensure!(
+            data[4] == 1,
+            "commit-graph version {} isn't supported",
+            data[4]
+        );
+        let hash_version = match algorithm {
+            HashAlgorithm::Sha1 => 1,
+            HashAlgorithm::Sha256 => 2,
+        };
+        ensure!(
+            data[5] == hash_version,
+            "commit-graph hash version {} does not match version {hash_version}",
+            data[5]
+        );
+        ensure!(data[7] == 0, "split commit-graphs aren't supported");
+        let chunk_count = data[6] as usize;
+        let table_end = 8 + 12 * (chunk_count + 1);
+        let end = data.len() - algorithm.len();
+        ensure!(table_end <= end, "chunk table is truncated");
+
+        let mut chunks = Vec::new();
+        for n in 0..=chunk_count {
+            let entry = &data[8 + 12 * n..8 + 12 * (n + 1)];
+            let offset = u64::from_be_bytes(entry[4..].try_into()?);
+            ensure!(
+                (table_end as u64..=end as u64).contains(&offset),
+                "chunk offset {offset} is out of bounds"
+            );
+            chunks.push((<[u8; 4]>::try_from(&entry[..4])?, offset as usize));
+        }
+        let chunk = |id: &[u8; 4]| -> Result<Option<(usize, usize)>> {
+            let Some(n) = chunks[..chunk_count]
+                .iter()
+                .position(|(name, _)| name == id)
+            else {
+                return Ok(None);
+            };
+            let (start, next) = (chunks[n].1, chunks[n + 1].1);
+            ensure!(
+                start <= next,
+                "chunk {} overlaps the next one",
+                String::from_utf8_lossy(id)
+            );
+            Ok(Some((start, next - start)))
+        };
+        let required = |id: &[u8; 4]| {
+            chunk(id)?.with_context(|| format!("missing {} chunk", String::from_utf8_lossy(id)))
+        };
+
+        let (fanout, fanout_len) = required(b"OIDF")?;
+        ensure!(fanout_len == 256 * 4, "OIDF chunk has the wrong size");
+        let count = u32::from_be_bytes(data[fanout + 255 * 4..fanout + 256 * 4].try_into()?);
+        let count = count as usize;
+        let (lookup, lookup_len) = required(b"OIDL")?;
+        ensure!(
+            lookup_len == count * algorithm.len(),
+            "OIDL chunk has the wrong size"
+        );
+        let (commit_data, commit_data_len) = required(b"CDAT")?;
+        ensure!(
+            commit_data_len == count * (algorithm.len() + 16),
+            "CDAT chunk has the wrong size"
+        );
+        let generation_data = match chunk(b"GDA2")? {
+            Some((start, len)) => {
+                ensure!(len == count * 4, "GDA2 chunk has the wrong size");
+                Some(start)
+            }
+            None => None,
+        };
+        let generation_overflow = chunk(b"GDO2")?;
+        Ok(CommitGraph {
+            data,
+            algorithm,
+            count,
+            fanout,
+            lookup,
+            commit_data,
+            generation_data,
+            generation_overflow,
+        })
+    }
+
+    fn u32_at(&self, offset: usize) -> u32 {
+        u32::from_be_bytes(self.data[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// Where `id` is in the sorted ids, if it is there.
+    fn position(&self, id: &ObjectId) -> Option<usize> {
+        let bytes = id.as_bytes();
+        if bytes.len() != self.algorithm.len() {
+            return None;
+        }
+        let first = bytes[0] as usize;
+        let start = match first {
+            0 => 0,
+            _ => self.u32_at(self.fanout + (first - 1) * 4) as usize,
+        };
+        let end = (self.u32_at(self.fanout + first * 4) as usize).min(self.count);
+        let len = self.algorithm.len();
+        let ids = &self.data[self.lookup..self.lookup + self.count * len];
+        let (mut low, mut high) = (start.min(end), end);
+        while low < high {
+            let middle = (low + high) / 2;
+            match ids[middle * len..(middle + 1) * len].cmp(bytes) {
+                Ordering::Less => low = middle + 1,
+                Ordering::Greater => high = middle,
+                Ordering::Equal => return Some(middle),
+            }
+        }
+        None
+    }
+
+    /// The generation number of the commit `id`, or `None` if the graph doesn't have
+    /// it. A commit's is always greater than its parents'.
+    pub fn generation(&self, id: &ObjectId) -> Result<Option<u64>> {
+        let Some(n) = self.position(id) else {
+            return Ok(None);
+        };
+        let record = self.commit_data + n * (self.algorithm.len() + 16) + self.algorithm.len();
+        let high = self.u32_at(record + 8);
+        let low = self.u32_at(record + 12);
+        let Some(generation_data) = self.generation_data else {
+            return Ok(Some(u64::from(high >> 2)));
+        };
+        let date = (u64::from(high & 3) << 32) | u64::from(low);
+        let offset = self.u32_at(generation_data + n * 4);
+        if offset & 0x8000_0000 == 0 {
+            return Ok(Some(date + u64::from(offset)));
+        }
+        let Some((overflow, len)) = self.generation_overflow else {
+            bail!("commit-graph requires overflow generation data but has none");
+        };
+        let index = (offset & 0x7fff_ffff) as usize;
+        ensure!(
+            (index + 1) * 8 <= len,
+            "commit-graph overflow generation data is too small"
+        );
+        let start = overflow + index * 8;
+        let offset = u64::from_be_bytes(self.data[start..start + 8].try_into()?);
+        Ok(Some(date.saturating_add(offset)))
+    }
+}
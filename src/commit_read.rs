@@ -0,0 +1,540 @@
+use crate::commit_graph::CommitGraph;
+use crate::config::Config;
+use crate::encoding::Encoding;
+use crate::ident::Ident;
+use crate::object_read::{Object, ObjectKind};
+use crate::odb::Odb;
+use crate::repository::Repository;
+use anyhow::{Context, Result, bail};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs::read_to_string;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// A parsed commit object; see [`crate::commit::CommitBuilder`] for the format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    pub hash: String,
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub author: Ident,
+    pub committer: Ident,
+    /// Headers other than the above, in order, e.g. `encoding` or `gpgsig`.
+    /// Continuation lines of multi-line values are joined with `\n`.
+    pub extra_headers: Vec<(String, String)>,
+    pub message: String,
+}
+
+impl Commit {
+    /// Read the commit named by `name`, peeling tags and resolving refs.
+    ///
+    /// Its parents are as history traversal sees them: replaced by the entry in
+    /// `.git/info/grafts` if there is one, and empty if the commit is a boundary of a
+    /// shallow clone (listed in `.git/shallow`), whose parents aren't present.
+    pub fn read(repo: &Repository, name: &str) -> Result<Commit> {
+        let (hash, mut object) = Object::peel_to(repo, name, ObjectKind::Commit)?;
+        let data = object.read_all()?;
+        let mut commit =
+            Commit::parse(&hash, &data).with_context(|| format!("invalid commit {hash}"))?;
+        if let Some(parents) = grafts(repo)?.get(&commit.hash) {
+            commit.parents = parents.clone();
+        }
+        Ok(commit)
+    }
+
+    pub fn parse(hash: &str, data: &[u8]) -> Result<Commit> {
+        let text = decode_commit(data);
+        let (headers, message) = text.split_once("\n\n").unwrap_or((&text, ""));
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut author = None;
+        let mut committer = None;
+        let mut extra_headers: Vec<(String, String)> = Vec::new();
+        for line in headers.lines() {
+            if let Some(continuation) = line.strip_prefix(' ') {
+                let Some((_, value)) = extra_headers.last_mut() else {
+                    bail!("continuation line without a header");
+                };
+                value.push('\n');
+                value.push_str(continuation);
+                continue;
+            }
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "tree" => tree = Some(value.to_string()),
+                "parent" => parents.push(value.to_string()),
+                "author" => author = Some(Ident::parse(value)?),
+                "committer" => committer = Some(Ident::parse(value)?),
+                _ => extra_headers.push((key.to_string(), value.to_string())),
+            }
+        }
+        Ok(Commit {
+            hash: hash.to_string(),
+            tree: tree.context("missing tree header")?,
+            parents,
+            author: author.context("missing author header")?,
+            committer: committer.context("missing committer header")?,
+            extra_headers,
+            message: message.to_string(),
+        })
+    }
+
+    /// The first line of the message.
+    pub fn subject(&self) -> &str {
+        self.message.lines().next().unwrap_or("")
+    }
+}
+
+/// The text of the commit object `data`, re-encoded to UTF-8 from the encoding its
+/// `encoding` header names, if that is one [`Encoding`] knows.
+fn decode_commit(data: &[u8]) -> Cow<'_, str> {
+    let headers = match data.windows(2).position(|pair| pair == b"\n\n") {
+        Some(end) => &data[..end],
+        None => data,
+    };
+    let encoding = headers
+        .split(|&byte| byte == b'\n')
+        .find_map(|line| line.strip_prefix(b"encoding "))
+        .and_then(|name| Encoding::from_name(str::from_utf8(name).ok()?));
+    match encoding {
+        Some(encoding) if encoding != Encoding::Utf8 => Cow::Owned(encoding.decode(data)),
+        _ => String::from_utf8_lossy(data),
+    }
+}
+
+/// The parents each grafted commit is given instead of its own.
+type Grafts = HashMap<String, Vec<String>>;
+
+/// The grafts of each repository looked at so far, by its git directory.
+static GRAFTS: OnceLock<Mutex<HashMap<PathBuf, Arc<Grafts>>>> = OnceLock::new();
+
+/// Parent overrides from `.git/info/grafts` (`<commit> <parent>...` per line) and
+/// `.git/shallow` (one commit per line, cut off from its parents), read once for each
+/// repository.
+fn grafts(repo: &Repository) -> Result<Arc<Grafts>> {
+    let cache = GRAFTS.get_or_init(Mutex::default);
+    if let Some(grafts) = cache.lock().unwrap().get(&repo.git_dir) {
+        return Ok(grafts.clone());
+    }
+    let algorithm = repo.hash_algorithm()?;
+    let is_full_hash = |id: &str| algorithm.is_hex_id(id);
+    let mut grafts = HashMap::new();
+    for line in read_optional(&repo.git_path("info/grafts"))?.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let ids: Vec<&str> = line.split_ascii_whitespace().collect();
+        if !ids.iter().all(|id| is_full_hash(id)) {
+            eprintln!("warning: bad graft data: {line}");
+            continue;
+        }
+        grafts.insert(
+            ids[0].to_string(),
+            ids[1..].iter().map(|id| id.to_string()).collect(),
+        );
+    }
+    for line in read_optional(&repo.git_path("shallow"))?.lines() {
+        let line = line.trim();
+        if is_full_hash(line) {
+            grafts.insert(line.to_string(), Vec::new());
+        } else if !line.is_empty() {
+            bail!("bad shallow line: {line}");
+        }
+    }
+    let grafts = Arc::new(grafts);
+    cache
+        .lock()
+        .unwrap()
+        .insert(repo.git_dir.clone(), grafts.clone());
+    Ok(grafts)
+}
+
+fn read_optional(path: &Path) -> Result<String> {
+    match read_to_string(path) {
+        Ok(text) => Ok(text),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+    }
+}
+
+/// The commit graph of each repository looked at so far, by its git directory.
+static COMMIT_GRAPHS: OnceLock<Mutex<HashMap<PathBuf, Option<Arc<CommitGraph>>>>> = OnceLock::new();
+
+/// The commit graph of `repo`, read once. There is none if `core.commitGraph`
+/// is false, or if grafts or a shallow clone rewrite history, which the generation
+/// numbers don't account for. One that can't be read is warned about and ignored, as
+/// git does, since it only makes walks faster.
+fn commit_graph(repo: &Repository) -> Result<Option<Arc<CommitGraph>>> {
+    let cache = COMMIT_GRAPHS.get_or_init(Mutex::default);
+    if let Some(graph) = cache.lock().unwrap().get(&repo.git_dir) {
+        return Ok(graph.clone());
+    }
+    let graph = if !grafts(repo)?.is_empty()
+        || Config::load(repo)?.get_bool("core.commitGraph")? == Some(false)
+    {
+        None
+    } else {
+        CommitGraph::open(&repo.objects_dir())
+            .unwrap_or_else(|e| {
+                eprintln!("warning: {e:#}");
+                None
+            })
+            .map(Arc::new)
+    };
+    cache
+        .lock()
+        .unwrap()
+        .insert(repo.git_dir.clone(), graph.clone());
+    Ok(graph)
+}
+
+/// The generation number the commit graph has for `hash`; commits it doesn't have
+/// (or all of them, without a graph) count as newer than any it does.
+fn generation(repo: &Repository, hash: &str) -> Result<u64> {
+    let Some(graph) = commit_graph(repo)? else {
+        return Ok(u64::MAX);
+    };
+    Ok(graph.generation(&hash.parse()?)?.unwrap_or(u64::MAX))
+}
+
+/// Commits waiting to be visited by a walk, handed out newest first by committer
+/// date and, among commits of the same date, in the order they were queued, as git's
+/// walks do. Commits queued with a generation number are handed out by that first,
+/// so that none comes before a descendant that is queued too.
+#[derive(Default)]
+struct DateQueue {
+    heap: BinaryHeap<ByDate>,
+    queued: usize,
+}
+
+impl DateQueue {
+    fn push(&mut self, commit: Commit) {
+        self.push_with_generation(commit, u64::MAX);
+    }
+
+    fn push_with_generation(&mut self, commit: Commit, generation: u64) {
+        self.heap.push(ByDate(commit, generation, self.queued));
+        self.queued += 1;
+    }
+
+    fn pop(&mut self) -> Option<Commit> {
+        self.heap.pop().map(|ByDate(commit, _, _)| commit)
+    }
+
+    fn peek(&self) -> Option<&Commit> {
+        self.heap.peek().map(|ByDate(commit, _, _)| commit)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Commit> {
+        self.heap.iter().map(|ByDate(commit, _, _)| commit)
+    }
+}
+
+/// A queued commit, its generation number and how many were queued before it,
+/// ordered for [`DateQueue`].
+struct ByDate(Commit, u64, usize);
+
+impl PartialEq for ByDate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ByDate {}
+
+impl PartialOrd for ByDate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ByDate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.1
+            .cmp(&other.1)
+            .then_with(|| self.0.committer.timestamp.cmp(&other.0.committer.timestamp))
+            .then_with(|| other.2.cmp(&self.2))
+    }
+}
+
+/// Commits reachable from `tips` but not from any of `hide`, newest first by committer
+/// date — what `git rev-list <tips> ^<hide>` lists.
+pub fn walk_commits(repo: &Repository, tips: &[String], hide: &[String]) -> Result<Vec<Commit>> {
+    let walk = walk_commits_with(repo, tips, hide, false, |_, _| Ok(()))?;
+    Ok(walk
+        .commits
+        .into_iter()
+        .filter(|commit| !walk.hidden.contains(&commit.hash))
+        .collect())
+}
+
+/// The best common ancestor of `ours` and `theirs`, as `git merge-base` finds it: one
+/// that no other common ancestor descends from, or `None` for unrelated histories.
+/// Where there are several, after criss-cross merges, the newest is taken, where git
+/// would merge them into one first.
+pub fn merge_base(repo: &Repository, ours: &str, theirs: &str) -> Result<Option<String>> {
+    let theirs: HashSet<String> = walk_commits(repo, &[theirs.to_string()], &[])?
+        .into_iter()
+        .map(|commit| commit.hash)
+        .collect();
+    let common: Vec<Commit> = walk_commits(repo, &[ours.to_string()], &[])?
+        .into_iter()
+        .filter(|commit| theirs.contains(&commit.hash))
+        .collect();
+    // The parents of a common ancestor are common ancestors too, so the best ones are
+    // those that aren't a parent of another.
+    let parents: HashSet<&str> = common
+        .iter()
+        .flat_map(|commit| commit.parents.iter().map(String::as_str))
+        .collect();
+    Ok(common
+        .iter()
+        .find(|commit| !parents.contains(commit.hash.as_str()))
+        .map(|commit| commit.hash.clone()))
+}
+
+/// How many commits a walk goes on for once only hidden ones are left to visit, in
+/// case one of them hides an older commit still queued. As in git, a hidden commit
+/// reached only after that, through skewed dates, isn't found to be hidden.
+const SLOP: usize = 5;
+
+/// The commits a walk visited and which of them turned out to be hidden, as
+/// [`walk_commits_with`] leaves them.
+pub struct Walk {
+    /// The commits visited while not yet known to be hidden, newest first.
+    pub commits: Vec<Commit>,
+    /// The commits found to be reachable from the hidden revisions.
+    pub hidden: HashSet<String>,
+    /// The hidden revisions themselves.
+    bottoms: HashSet<String>,
+}
+
+impl Walk {
+    /// Whether `hash` counts as relevant to history simplification: not hidden, or
+    /// one of the hidden revisions themselves, as git counts them.
+    pub fn is_relevant(&self, hash: &str) -> bool {
+        !self.hidden.contains(hash) || self.bottoms.contains(hash)
+    }
+}
+
+/// The walk behind [`walk_commits`], as git's revision walk does it: commits reachable
+/// from `hide` are marked hidden as they are reached, newest first along with the
+/// others, so one visited before it is known to be hidden is listed in
+/// [`Walk::commits`] and later in [`Walk::hidden`] too.
+///
+/// Each commit visited while not hidden is handed to `visit` before its parents are
+/// queued, with whether a commit is relevant by what is known so far (see
+/// [`Walk::is_relevant`]). `visit` can drop parents, as history simplification does;
+/// only the parents left are walked, or with `first_parent` only the first of them,
+/// and hidden commits are only marked along those.
+pub fn walk_commits_with(
+    repo: &Repository,
+    tips: &[String],
+    hide: &[String],
+    first_parent: bool,
+    mut visit: impl FnMut(&mut Commit, &dyn Fn(&str) -> bool) -> Result<()>,
+) -> Result<Walk> {
+    let mut hidden = HashSet::new();
+    let mut bottoms = HashSet::new();
+    // The parents of every commit read, as left by `visit`.
+    let mut parents_of: HashMap<String, Vec<String>> = HashMap::new();
+    let mut seen = HashSet::new();
+    let mut queue = DateQueue::default();
+    for (names, hiding) in [(hide, true), (tips, false)] {
+        for name in names {
+            let commit = Commit::read(repo, name)?;
+            parents_of.insert(commit.hash.clone(), commit.parents.clone());
+            if hiding {
+                hidden.insert(commit.hash.clone());
+                bottoms.insert(commit.hash.clone());
+                hide_ancestors(&commit.hash, &mut hidden, &parents_of);
+            }
+            if seen.insert(commit.hash.clone()) {
+                queue.push(commit);
+            }
+        }
+    }
+
+    let mut commits = Vec::new();
+    let mut last_date = i64::MAX;
+    let mut slop = SLOP;
+    while let Some(mut commit) = queue.pop() {
+        if hidden.contains(&commit.hash) {
+            for parent in &commit.parents {
+                hidden.insert(parent.clone());
+                if seen.insert(parent.clone()) {
+                    let parent = Commit::read(repo, parent)?;
+                    parents_of.insert(parent.hash.clone(), parent.parents.clone());
+                    queue.push(parent);
+                }
+                hide_ancestors(parent, &mut hidden, &parents_of);
+            }
+            // Stop once only hidden commits are left, and they are older than the
+            // last commit listed, or have been for a while.
+            slop = match queue.peek() {
+                None => 0,
+                Some(next) if last_date <= next.committer.timestamp => SLOP,
+                Some(_) if queue.iter().any(|c| !hidden.contains(&c.hash)) => SLOP,
+                Some(_) => slop - 1,
+            };
+            if slop == 0 {
+                break;
+            }
+            continue;
+        }
+        visit(&mut commit, &|hash: &str| {
+            !hidden.contains(hash) || bottoms.contains(hash)
+        })?;
+        parents_of.insert(commit.hash.clone(), commit.parents.clone());
+        let parents = match first_parent {
+            true => &commit.parents[..commit.parents.len().min(1)],
+            false => &commit.parents[..],
+        };
+        for parent in parents {
+            if seen.insert(parent.clone()) {
+                let parent = Commit::read(repo, parent)?;
+                parents_of.insert(parent.hash.clone(), parent.parents.clone());
+                queue.push(parent);
+            }
+        }
+        last_date = commit.committer.timestamp;
+        commits.push(commit);
+    }
+    Ok(Walk {
+        commits,
+        hidden,
+        bottoms,
+    })
+}
+
+/// Mark the ancestors of `hash` hidden as far as the commits read so far go.
+fn hide_ancestors(
+    hash: &str,
+    hidden: &mut HashSet<String>,
+    parents_of: &HashMap<String, Vec<String>>,
+) {
+    let mut pending = vec![hash];
+    while let Some(hash) = pending.pop() {
+        for parent in parents_of.get(hash).into_iter().flatten() {
+            if hidden.insert(parent.clone()) {
+                pending.push(parent);
+            }
+        }
+    }
+}
+
+/// Whether `ancestor` is `descendant` or one of its ancestors, reading commits from
+/// the first of `odbs` that has them. Parents are read as recorded, without grafts.
+pub fn is_ancestor_in(odbs: &[&dyn Odb], ancestor: &str, descendant: &str) -> Result<bool> {
+    let mut seen = HashSet::new();
+    let mut stack = vec![descendant.to_string()];
+    while let Some(hash) = stack.pop() {
+        if hash == ancestor {
+            return Ok(true);
+        }
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+        let odb = odbs
+            .iter()
+            .find(|odb| odb.contains(&hash))
+            .unwrap_or(&odbs[0]);
+        let mut object = odb.read(&hash)?;
+        if object.kind != ObjectKind::Commit {
+            return Ok(false);
+        }
+        let commit = Commit::parse(&hash, &object.read_all()?)
+            .with_context(|| format!("invalid commit {hash}"))?;
+        stack.extend(commit.parents);
+    }
+    Ok(false)
+}
+
+const OURS: u8 = 1;
+const THEIRS: u8 = 2;
+const BOTH: u8 = OURS | THEIRS;
+
+/// How many commits `ours` has that `theirs` doesn't, and the reverse: the counts of
+/// `git rev-list --left-right --count ours...theirs`.
+///
+/// Both histories are walked together newest first, marking each commit with the
+/// sides it is reachable from, and the walk stops as soon as everything left to visit
+/// is reachable from both, so only the divergent part of the graph is read. Commits
+/// in the commit graph are visited in order of their generation numbers, which never
+/// puts a commit before its descendants, however skewed the clocks that dated them.
+/// A commit that still turns out to be reachable from the other side only after it
+/// was visited (its date being no older than a descendant's) passes its new mark on
+/// to the ancestors already visited, so such commits are counted right.
+pub fn ahead_behind(repo: &Repository, ours: &str, theirs: &str) -> Result<(usize, usize)> {
+    let mut flags: HashMap<String, u8> = HashMap::new();
+    // Parents of the commits already visited.
+    let mut visited: HashMap<String, Vec<String>> = HashMap::new();
+    let mut queue = DateQueue::default();
+    // Queued commits not yet known to be reachable from both sides.
+    let mut not_stale = 0;
+
+    for (tip, side) in [(ours, OURS), (theirs, THEIRS)] {
+        let commit = Commit::read(repo, tip)?;
+        if flags.contains_key(&commit.hash) {
+            paint_ancestors(&commit.hash, side, &mut flags, &visited, &mut not_stale);
+        } else {
+            flags.insert(commit.hash.clone(), side);
+            let generation = generation(repo, &commit.hash)?;
+            queue.push_with_generation(commit, generation);
+            not_stale += 1;
+        }
+    }
+    while not_stale > 0 {
+        let Some(commit) = queue.pop() else {
+            break;
+        };
+        let side = flags[&commit.hash];
+        if side != BOTH {
+            not_stale -= 1;
+        }
+        visited.insert(commit.hash.clone(), commit.parents.clone());
+        for parent in &commit.parents {
+            if flags.contains_key(parent) {
+                paint_ancestors(parent, side, &mut flags, &visited, &mut not_stale);
+            } else {
+                flags.insert(parent.clone(), side);
+                queue.push_with_generation(Commit::read(repo, parent)?, generation(repo, parent)?);
+                if side != BOTH {
+                    not_stale += 1;
+                }
+            }
+        }
+    }
+    let count = |wanted: u8| flags.values().filter(|&&side| side == wanted).count();
+    Ok((count(OURS), count(THEIRS)))
+}
+
+/// Add `side` to the marks of the already seen commit `hash` and, if it was visited,
+/// of its visited ancestors, keeping `not_stale` up to date for those still queued.
+fn paint_ancestors(
+    hash: &str,
+    side: u8,
+    flags: &mut HashMap<String, u8>,
+    visited: &HashMap<String, Vec<String>>,
+    not_stale: &mut usize,
+) {
+    let mut pending = vec![hash.to_string()];
+    while let Some(hash) = pending.pop() {
+        let Some(entry) = flags.get_mut(&hash) else {
+            continue;
+        };
+        if *entry | side == *entry {
+            continue;
+        }
+        match visited.get(&hash) {
+            Some(parents) => pending.extend(parents.iter().cloned()),
+            None if *entry | side == BOTH => *not_stale -= 1,
+            None => {}
+        }
+        *entry |= side;
+    }
+}
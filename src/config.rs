@@ -0,0 +1,1059 @@
+use crate::refs::symbolic_ref_target_in;
+use crate::repository::{Repository, git_path};
+use crate::wildmatch::wildmatch;
+use anyhow::{Context, Result, anyhow, bail};
+use regex_automata::meta::Regex;
+use std::env;
+use std::fs::{canonicalize, read_to_string, rename, write};
+use std::io::{ErrorKind, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// A single `section[.subsection].name = value` setting.
+///
+/// Section and variable names are case-insensitive and stored lowercased; subsection
+/// names are case-sensitive and kept as written. A variable given without `=` is a
+/// boolean true and has no value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigEntry {
+    pub key: String,
+    pub value: Option<String>,
+}
+
+/// Git configuration merged from every source we read, in order of increasing
+/// precedence: system (`/etc/gitconfig`), global (`$XDG_CONFIG_HOME/git/config`, then
+/// `~/.gitconfig`), local (`.git/config`), then `GIT_CONFIG_KEY_<n>` variables from
+/// the environment. Later entries win. Included files take the place of the
+/// `include.path` that names them.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    entries: Vec<ConfigEntry>,
+    /// The repository the configuration is for, which `includeIf` conditions test.
+    git_dir: Option<PathBuf>,
+}
+
+/// How deeply included files may include others, which is how git catches cycles.
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+impl Config {
+    pub fn load(repo: &Repository) -> Result<Config> {
+        Config::load_repository(&repo.git_dir)
+    }
+
+    /// The configuration of the repository at `git_dir` (e.g. a local remote), on top
+    /// of the system and global configuration.
+    pub fn load_repository(git_dir: &Path) -> Result<Config> {
+        let mut config = Config {
+            git_dir: Some(git_dir.to_path_buf()),
+            ..Config::default()
+        };
+        for path in system_config_path()
+            .into_iter()
+            .chain(global_config_paths())
+        {
+            config.read_file(&path)?;
+        }
+        config.read_file(&git_path(git_dir, "config"))?;
+        config
+            .read_environment()
+            .context("unable to parse command-line config")?;
+        Ok(config)
+    }
+
+    /// The entries of the file at `path` alone, as `git config` reads a single scope:
+    /// without the files it includes.
+    pub fn load_file(path: &Path) -> Result<Config> {
+        let text = match read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {path:?}")),
+        };
+        Ok(Config {
+            entries: parse_config(&text).with_context(|| format!("bad config file {path:?}"))?,
+            git_dir: None,
+        })
+    }
+
+    /// The system and global configuration and that of the environment, leaving out
+    /// any repository's, for settings a repository mustn't be able to give itself,
+    /// such as `safe.directory`.
+    pub fn load_protected() -> Result<Config> {
+        let mut config = Config::default();
+        for path in system_config_path()
+            .into_iter()
+            .chain(global_config_paths())
+        {
+            config.read_file(&path)?;
+        }
+        config
+            .read_environment()
+            .context("unable to parse command-line config")?;
+        Ok(config)
+    }
+
+    /// Append the entries `GIT_CONFIG_COUNT` says the environment holds, each named by
+    /// `GIT_CONFIG_KEY_<n>` and set to `GIT_CONFIG_VALUE_<n>`, for `n` from 0.
+    fn read_environment(&mut self) -> Result<()> {
+        let count = match env::var("GIT_CONFIG_COUNT") {
+            Ok(count) if !count.is_empty() => count,
+            _ => return Ok(()),
+        };
+        let Ok(count) = count.parse::<usize>() else {
+            bail!("bogus count in GIT_CONFIG_COUNT");
+        };
+        for n in 0..count {
+            let Ok(key) = env::var(format!("GIT_CONFIG_KEY_{n}")) else {
+                bail!("missing config key GIT_CONFIG_KEY_{n}");
+            };
+            let Ok(value) = env::var(format!("GIT_CONFIG_VALUE_{n}")) else {
+                bail!("missing config value GIT_CONFIG_VALUE_{n}");
+            };
+            ConfigKey::parse(&key)?;
+            self.entries.push(ConfigEntry {
+                key: normalize_key(&key),
+                value: Some(value),
+            });
+        }
+        Ok(())
+    }
+
+    /// Append the entries of `path`, silently skipping it if it doesn't exist, and
+    /// those of the files it includes: `include.path`, and `includeIf.<condition>.path`
+    /// when the condition holds.
+    pub fn read_file(&mut self, path: &Path) -> Result<()> {
+        self.read_included_file(path, 0)
+    }
+
+    fn read_included_file(&mut self, path: &Path, depth: usize) -> Result<()> {
+        if depth > MAX_INCLUDE_DEPTH {
+            bail!(
+                "exceeded maximum include depth ({MAX_INCLUDE_DEPTH}) while including {path:?}; \
+                 this might be due to circular includes"
+            );
+        }
+        let text = match read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {path:?}")),
+        };
+        let entries = parse_config(&text).with_context(|| format!("bad config file {path:?}"))?;
+        for entry in entries {
+            let include = self.included_path(path, &entry)?;
+            self.entries.push(entry);
+            if let Some(include) = include {
+                self.read_included_file(&include, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The file `entry` includes, if it is an include whose condition holds. Relative
+    /// paths are relative to the directory of `from`, the file the entry is in.
+    fn included_path(&self, from: &Path, entry: &ConfigEntry) -> Result<Option<PathBuf>> {
+        let Some(value) = &entry.value else {
+            return Ok(None);
+        };
+        if entry.key != "include.path" {
+            let Some(condition) = entry
+                .key
+                .strip_prefix("includeif.")
+                .and_then(|key| key.strip_suffix(".path"))
+            else {
+                return Ok(None);
+            };
+            if !self.include_condition_holds(condition, from)? {
+                return Ok(None);
+            }
+        }
+        let path = match expand_home(value) {
+            Some(path) => path,
+            None => from.parent().unwrap_or(Path::new("")).join(value),
+        };
+        Ok(Some(path))
+    }
+
+    /// Whether an `includeIf` condition holds for this repository:
+    ///
+    /// - `gitdir:<pattern>` (or `gitdir/i:` to ignore case): the repository's `.git`
+    ///   directory matches the glob. A pattern not starting with `/`, `~/` or `./`
+    ///   (relative to `from`) matches at any depth, and one ending in `/` matches
+    ///   everything below it.
+    /// - `onbranch:<pattern>`: the checked out branch matches the glob, a trailing `/`
+    ///   again matching everything below it.
+    ///
+    /// Other conditions never hold.
+    fn include_condition_holds(&self, condition: &str, from: &Path) -> Result<bool> {
+        let Some(git_dir) = &self.git_dir else {
+            return Ok(false);
+        };
+        let (pattern, ignore_case) = if let Some(pattern) = condition.strip_prefix("gitdir:") {
+            (pattern, false)
+        } else if let Some(pattern) = condition.strip_prefix("gitdir/i:") {
+            (pattern, true)
+        } else if let Some(pattern) = condition.strip_prefix("onbranch:") {
+            let head = symbolic_ref_target_in(git_dir, "HEAD")?;
+            let Some(branch) = head.strip_prefix("refs/heads/") else {
+                return Ok(false);
+            };
+            let mut pattern = pattern.to_string();
+            if pattern.ends_with('/') {
+                pattern.push_str("**");
+            }
+            return Ok(wildmatch(&pattern, branch, true, false));
+        } else {
+            return Ok(false);
+        };
+
+        let mut pattern = if let Some(home) = expand_home(pattern) {
+            home.display().to_string()
+        } else if let Some(rest) = pattern.strip_prefix("./") {
+            let dir = from.parent().unwrap_or(Path::new("."));
+            let dir = canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+            format!("{}/{rest}", dir.display())
+        } else if !pattern.starts_with('/') {
+            format!("**/{pattern}")
+        } else {
+            pattern.to_string()
+        };
+        if pattern.ends_with('/') {
+            pattern.push_str("**");
+        }
+        let absolute = env::current_dir()?.join(git_dir);
+        let real = canonicalize(&absolute).unwrap_or_else(|_| absolute.clone());
+        Ok([absolute, real].iter().any(|dir| {
+            let dir = dir.display().to_string();
+            wildmatch(&pattern, dir.trim_end_matches("/."), true, ignore_case)
+        }))
+    }
+
+    pub fn entries(&self) -> &[ConfigEntry] {
+        &self.entries
+    }
+
+    /// The last value set for `key`. Boolean-style entries without a value read as "true".
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let key = normalize_key(key);
+        self.entries
+            .iter()
+            .rev()
+            .find(|e| e.key == key)
+            .map(|e| e.value.as_deref().unwrap_or("true"))
+    }
+
+    /// Every value set for `key`, in order, for multi-valued variables such as
+    /// `remote.<name>.fetch`.
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        let key = normalize_key(key);
+        self.entries
+            .iter()
+            .filter(|e| e.key == key)
+            .map(|e| e.value.as_deref().unwrap_or("true"))
+            .collect()
+    }
+
+    /// The last value set for `key` as a number of bytes, which like git's integer
+    /// settings may end in `k`, `m` or `g` for a multiple of 1024 of them.
+    pub fn get_size(&self, key: &str) -> Result<Option<u64>> {
+        let Some(value) = self.get(key) else {
+            return Ok(None);
+        };
+        let (digits, shift) = match value.as_bytes().last().map(u8::to_ascii_lowercase) {
+            Some(b'k') => (&value[..value.len() - 1], 10),
+            Some(b'm') => (&value[..value.len() - 1], 20),
+            Some(b'g') => (&value[..value.len() - 1], 30),
+            _ => (value, 0),
+        };
+        let number: u64 = digits
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("bad numeric config value '{value}' for '{key}': invalid unit"))?;
+        match number.checked_mul(1 << shift) {
+            Some(size) => Ok(Some(size)),
+            None => bail!("bad numeric config value '{value}' for '{key}': out of range"),
+        }
+    }
+
+    /// The last value set for `key` as a boolean, read the way git does: `true`, `yes`,
+    /// `on` and a key with no value at all are true, `false`, `no`, `off` and the empty
+    /// string are false, in any case, and an integer is true when it is not zero.
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>> {
+        let Some(value) = self.get(key) else {
+            return Ok(None);
+        };
+        match value.to_ascii_lowercase().as_str() {
+            "true" | "yes" | "on" => Ok(Some(true)),
+            "false" | "no" | "off" | "" => Ok(Some(false)),
+            _ => match value.trim().parse::<i64>() {
+                Ok(number) => Ok(Some(number != 0)),
+                Err(_) => bail!("bad boolean config value '{value}' for '{key}'"),
+            },
+        }
+    }
+
+    /// The last value set for `key` as a path, with a leading `~/` standing for the
+    /// home directory.
+    pub fn get_path(&self, key: &str) -> Option<PathBuf> {
+        let value = self.get(key)?;
+        Some(expand_home(value).unwrap_or_else(|| PathBuf::from(value)))
+    }
+}
+
+/// Lowercase the section and variable name of `key`, leaving any subsection alone.
+pub fn normalize_key(key: &str) -> String {
+    let (section, rest) = key.split_once('.').unwrap_or((key, ""));
+    match rest.rsplit_once('.') {
+        Some((subsection, name)) => format!(
+            "{}.{}.{}",
+            section.to_ascii_lowercase(),
+            subsection,
+            name.to_ascii_lowercase()
+        ),
+        None if rest.is_empty() => section.to_ascii_lowercase(),
+        None => format!(
+            "{}.{}",
+            section.to_ascii_lowercase(),
+            rest.to_ascii_lowercase()
+        ),
+    }
+}
+
+/// Parse the INI-like format of git config files.
+pub fn parse_config(text: &str) -> Result<Vec<ConfigEntry>> {
+    let mut entries = Vec::new();
+    let mut section: Option<String> = None;
+    let mut lines = text.lines().enumerate();
+    while let Some((number, line)) = lines.next() {
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        }
+        let mut rest = line;
+        if rest.starts_with('[') {
+            let (name, after) =
+                parse_section_header(rest).with_context(|| format!("line {}", number + 1))?;
+            section = Some(name);
+            rest = after.trim_start();
+            if rest.is_empty() || rest.starts_with(['#', ';']) {
+                continue;
+            }
+        }
+        let Some(section) = &section else {
+            bail!("line {}: variable outside of any section", number + 1);
+        };
+        let name_len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+            .unwrap_or(rest.len());
+        let name = &rest[..name_len];
+        if name.is_empty() || !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            bail!("line {}: invalid variable name", number + 1);
+        }
+        let after_name = rest[name_len..].trim_start();
+        let value = if let Some(raw) = after_name.strip_prefix('=') {
+            // A trailing backslash continues the value on the next line.
+            let mut raw = raw.to_string();
+            while raw.ends_with('\\') && !raw.ends_with("\\\\") {
+                raw.pop();
+                match lines.next() {
+                    Some((_, next)) => raw.push_str(next),
+                    None => break,
+                }
+            }
+            Some(parse_value(&raw).with_context(|| format!("line {}", number + 1))?)
+        } else if after_name.is_empty() || after_name.starts_with(['#', ';']) {
+            None
+        } else {
+            bail!("line {}: expected '=' after variable name", number + 1);
+        };
+        entries.push(ConfigEntry {
+            key: format!("{section}.{}", name.to_ascii_lowercase()),
+            value,
+        });
+    }
+    Ok(entries)
+}
+
+/// Parse `[section]`, `[section "subsection"]` or the legacy `[section.subsection]`,
+/// returning the normalized section prefix and the remainder of the line.
+fn parse_section_header(line: &str) -> Result<(String, &str)> {
+    let inner = &line[1..];
+    if let Some((name, sub)) = inner.split_once(" \"") {
+        let mut subsection = String::new();
+        let mut chars = sub.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some((_, escaped)) => subsection.push(escaped),
+                    None => bail!("unterminated subsection name"),
+                },
+                '"' => {
+                    let Some(after) = sub[i + 1..].strip_prefix(']') else {
+                        bail!("expected ']' after subsection name");
+                    };
+                    return Ok((
+                        format!("{}.{}", name.to_ascii_lowercase(), subsection),
+                        after,
+                    ));
+                }
+                _ => subsection.push(c),
+            }
+        }
+        bail!("unterminated subsection name");
+    }
+    let Some((name, after)) = inner.split_once(']') else {
+        bail!("unterminated section header");
+    };
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+    {
+        bail!("invalid section name {name:?}");
+    }
+    Ok((name.to_ascii_lowercase(), after))
+}
+
+/// Unquote a raw value: strip surrounding whitespace and comments outside of quotes
+/// and expand the `\n`, `\t`, `\b`, `\"` and `\\` escapes.
+fn parse_value(raw: &str) -> Result<String> {
+    let mut value = String::new();
+    let mut in_quotes = false;
+    // Whitespace is only kept when something non-blank follows it.
+    let mut pending_space = String::new();
+    let mut chars = raw.trim_start().chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                in_quotes = !in_quotes;
+            }
+            '\\' => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('b') => {
+                        value.pop();
+                    }
+                    Some(c @ ('"' | '\\')) => value.push(c),
+                    Some(c) => bail!("invalid escape sequence \\{c}"),
+                    None => {}
+                }
+            }
+            '#' | ';' if !in_quotes => break,
+            c if c.is_whitespace() && !in_quotes => pending_space.push(c),
+            c => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                value.push(c);
+            }
+        }
+    }
+    if in_quotes {
+        bail!("unterminated quoted value");
+    }
+    Ok(value)
+}
+
+/// A config file `git config` can be told to read or change on its own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigScope {
+    /// `--system`: `$GIT_CONFIG_SYSTEM`, or `/etc/gitconfig`.
+    System,
+    /// `--global`: `$GIT_CONFIG_GLOBAL`, or `~/.gitconfig` unless only
+    /// `$XDG_CONFIG_HOME/git/config` exists.
+    Global,
+    /// `--local`: the repository's `.git/config`.
+    Local,
+    /// `--file <path>`.
+    File(PathBuf),
+}
+
+impl ConfigScope {
+    /// The file of the scope.
+    pub fn path(&self, repo: &Repository) -> Result<PathBuf> {
+        Ok(match self {
+            ConfigScope::System => env::var_os("GIT_CONFIG_SYSTEM")
+                .map_or_else(|| PathBuf::from("/etc/gitconfig"), PathBuf::from),
+            ConfigScope::Global => {
+                if let Some(path) = env::var_os("GIT_CONFIG_GLOBAL") {
+                    return Ok(PathBuf::from(path));
+                }
+                let home = env::home_dir().context("$HOME not set")?.join(".gitconfig");
+                match xdg_config_path("config") {
+                    Some(xdg) if !home.exists() && xdg.exists() => xdg,
+                    _ => home,
+                }
+            }
+            ConfigScope::Local => repo.git_path("config"),
+            ConfigScope::File(path) => path.clone(),
+        })
+    }
+}
+
+/// What `git config` does with its arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigAction {
+    /// `<key> [<value-pattern>]`: the last value.
+    Get,
+    /// `<key> [<value-pattern>]`: every value.
+    GetAll,
+    /// `<name-regex> [<value-pattern>]`: every matching variable, with its key.
+    GetRegexp,
+    /// `<key> <value> [<value-pattern>]`: replace the one (matching) value.
+    Set,
+    /// `<key> <value>`: add another value.
+    Add,
+    /// `<key> <value> [<value-pattern>]`: replace every (matching) value.
+    ReplaceAll,
+    /// `<key> [<value-pattern>]`: remove the one (matching) value.
+    Unset,
+    /// `<key> [<value-pattern>]`: remove every (matching) value.
+    UnsetAll,
+    /// Every variable, as `key=value`.
+    List,
+}
+
+/// `git config`: query the configuration or change the file at `path`, returning the
+/// exit status git would: 1 when a query finds nothing, 5 when there is nothing to
+/// unset or several values where only one may change, and 6 for a bad pattern.
+/// What a query finds is written to `out`, and errors and warnings to `err`.
+pub fn git_config(
+    config: &Config,
+    out: &mut impl Write,
+    err: &mut impl Write,
+    path: &Path,
+    action: ConfigAction,
+    args: &[String],
+    fixed_value: bool,
+) -> Result<i32> {
+    let (min, max) = match action {
+        ConfigAction::List => (0, 0),
+        ConfigAction::Get | ConfigAction::GetAll | ConfigAction::GetRegexp => (1, 2),
+        ConfigAction::Unset | ConfigAction::UnsetAll => (1, 2),
+        ConfigAction::Add => (2, 2),
+        ConfigAction::Set | ConfigAction::ReplaceAll => (2, 3),
+    };
+    if args.len() < min || args.len() > max {
+        match min == max {
+            true => bail!("wrong number of arguments, should be {min}"),
+            false => bail!("wrong number of arguments, should be from {min} to {max}"),
+        }
+    }
+    let pattern_arg = match action {
+        ConfigAction::Set | ConfigAction::ReplaceAll => args.get(2),
+        _ => args.get(1),
+    };
+    let pattern = match ValuePattern::new(pattern_arg.map(String::as_str), fixed_value) {
+        Ok(pattern) => pattern,
+        Err(e) => {
+            writeln!(err, "error: {e}")?;
+            return Ok(6);
+        }
+    };
+    let entry_matches = |entry: &ConfigEntry| pattern.matches(entry.value.as_deref());
+    match action {
+        ConfigAction::List => {
+            for entry in config.entries() {
+                match &entry.value {
+                    Some(value) => writeln!(out, "{}={value}", entry.key)?,
+                    None => writeln!(out, "{}", entry.key)?,
+                }
+            }
+        }
+        ConfigAction::Get | ConfigAction::GetAll => {
+            ConfigKey::parse(&args[0])?;
+            let key = normalize_key(&args[0]);
+            let mut values = config
+                .entries()
+                .iter()
+                .filter(|entry| entry.key == key && entry_matches(entry))
+                .map(|entry| entry.value.as_deref().unwrap_or(""))
+                .collect::<Vec<_>>();
+            if action == ConfigAction::Get {
+                values.drain(..values.len().saturating_sub(1));
+            }
+            if values.is_empty() {
+                return Ok(1);
+            }
+            for value in values {
+                writeln!(out, "{value}")?;
+            }
+        }
+        ConfigAction::GetRegexp => {
+            let Ok(key_regex) = Regex::new(&lowercase_key_pattern(&args[0])) else {
+                writeln!(err, "error: invalid key pattern: {}", args[0])?;
+                return Ok(6);
+            };
+            let mut found = false;
+            for entry in config.entries() {
+                if !key_regex.is_match(&entry.key) || !entry_matches(entry) {
+                    continue;
+                }
+                found = true;
+                match &entry.value {
+                    Some(value) => writeln!(out, "{} {value}", entry.key)?,
+                    None => writeln!(out, "{}", entry.key)?,
+                }
+            }
+            if !found {
+                return Ok(1);
+            }
+        }
+        ConfigAction::Add => add_config_value(path, &args[0], &args[1])?,
+        ConfigAction::Set | ConfigAction::ReplaceAll => {
+            let all = action == ConfigAction::ReplaceAll;
+            if replace_config_values(path, &args[0], &args[1], &pattern, all)? > 1 && !all {
+                writeln!(err, "warning: {} has multiple values", args[0])?;
+                writeln!(
+                    err,
+                    "error: cannot overwrite multiple values with a single value"
+                )?;
+                writeln!(
+                    err,
+                    "       Use a regexp, --add or --replace-all to change {}.",
+                    args[0]
+                )?;
+                return Ok(5);
+            }
+        }
+        ConfigAction::Unset | ConfigAction::UnsetAll => {
+            let all = action == ConfigAction::UnsetAll;
+            match unset_config_values(path, &args[0], &pattern, all)? {
+                0 => return Ok(5),
+                1 => {}
+                _ if all => {}
+                _ => {
+                    writeln!(err, "warning: {} has multiple values", args[0])?;
+                    return Ok(5);
+                }
+            }
+        }
+    }
+    Ok(0)
+}
+
+/// A `--get-regexp` pattern with its section and variable name parts lowercased, as
+/// keys are stored, leaving the subsection part as it is.
+fn lowercase_key_pattern(pattern: &str) -> String {
+    let first_dot = pattern.find('.').unwrap_or(pattern.len());
+    let last_dot = pattern.rfind('.').map_or(0, |dot| dot + 1);
+    if last_dot <= first_dot {
+        return pattern.to_ascii_lowercase();
+    }
+    format!(
+        "{}{}{}",
+        pattern[..first_dot].to_ascii_lowercase(),
+        &pattern[first_dot..last_dot],
+        pattern[last_dot..].to_ascii_lowercase()
+    )
+}
+
+/// How `--get`, `--unset` and friends choose among the values of a multi-valued
+/// variable: every value, those matching a regular expression (or, with a leading
+/// `!`, those that don't), or with `--fixed-value` those equal to a string.
+pub enum ValuePattern {
+    Any,
+    Regex { regex: Regex, negate: bool },
+    Fixed(String),
+}
+
+impl ValuePattern {
+    pub fn new(pattern: Option<&str>, fixed: bool) -> Result<ValuePattern> {
+        let Some(pattern) = pattern else {
+            return Ok(ValuePattern::Any);
+        };
+        if fixed {
+            return Ok(ValuePattern::Fixed(pattern.to_string()));
+        }
+        let (pattern, negate) = match pattern.strip_prefix('!') {
+            Some(pattern) => (pattern, true),
+            None => (pattern, false),
+        };
+        let regex = Regex::new(pattern).map_err(|_| anyhow!("invalid pattern: {pattern}"))?;
+        Ok(ValuePattern::Regex { regex, negate })
+    }
+
+    /// Whether `value` is chosen; a variable without a value only matches a negated
+    /// pattern.
+    pub fn matches(&self, value: Option<&str>) -> bool {
+        match self {
+            ValuePattern::Any => true,
+            ValuePattern::Regex { regex, negate } => {
+                *negate != value.is_some_and(|value| regex.is_match(value))
+            }
+            ValuePattern::Fixed(fixed) => value == Some(fixed),
+        }
+    }
+}
+
+/// Set `key` to `value` in the config file at `path`, creating the file if needed.
+///
+/// Like `git config <key> <value>`, the last existing assignment of the key is
+/// rewritten in place; otherwise the variable is added to the section (see
+/// [`add_config_value`]). Other lines, comments and formatting are preserved.
+pub fn set_config_value(path: &Path, key: &str, value: &str) -> Result<()> {
+    let mut file = ConfigFile::read(path)?;
+    let key = ConfigKey::parse(key)?;
+    let found = file.find(&key)?;
+    match found.assignments.last() {
+        Some(last) => file.replace(last, &key, value),
+        None => file.insert(&found, &key, value),
+    }
+    file.write(path)
+}
+
+/// Add another value for `key`, as `git config --add` does: after the last variable
+/// of the last section it belongs in, or in a new section at the end of the file.
+pub fn add_config_value(path: &Path, key: &str, value: &str) -> Result<()> {
+    let mut file = ConfigFile::read(path)?;
+    let key = ConfigKey::parse(key)?;
+    let found = file.find(&key)?;
+    file.insert(&found, &key, value);
+    file.write(path)
+}
+
+/// Set the values of `key` that `pattern` matches to `value`, adding it if none
+/// does. With `all`, every matching assignment but the first is removed; without,
+/// only a single match may be replaced.
+///
+/// Returns how many values matched. If several did but `all` wasn't given, nothing
+/// is changed and the caller should complain.
+pub fn replace_config_values(
+    path: &Path,
+    key: &str,
+    value: &str,
+    pattern: &ValuePattern,
+    all: bool,
+) -> Result<usize> {
+    let mut file = ConfigFile::read(path)?;
+    let key = ConfigKey::parse(key)?;
+    let found = file.find(&key)?;
+    let matching: Vec<&Assignment> = found
+        .assignments
+        .iter()
+        .filter(|a| pattern.matches(a.value.as_deref()))
+        .collect();
+    match matching.as_slice() {
+        [] => file.insert(&found, &key, value),
+        [_, _, ..] if !all => return Ok(matching.len()),
+        [first, rest @ ..] => {
+            file.replace(first, &key, value);
+            file.remove(&found, rest);
+        }
+    }
+    file.write(path)?;
+    Ok(matching.len())
+}
+
+/// Remove the values of `key` that `pattern` matches: every one with `all`, otherwise
+/// only a single match. Sections left with nothing in them go too.
+///
+/// Returns how many values matched; nothing is changed if that was several without
+/// `all`.
+pub fn unset_config_values(
+    path: &Path,
+    key: &str,
+    pattern: &ValuePattern,
+    all: bool,
+) -> Result<usize> {
+    let mut file = ConfigFile::read(path)?;
+    let key = ConfigKey::parse(key)?;
+    let found = file.find(&key)?;
+    let matching: Vec<&Assignment> = found
+        .assignments
+        .iter()
+        .filter(|a| pattern.matches(a.value.as_deref()))
+        .collect();
+    if matching.is_empty() || (matching.len() > 1 && !all) {
+        return Ok(matching.len());
+    }
+    file.remove(&found, &matching);
+    file.write(path)?;
+    Ok(matching.len())
+}
+
+/// A key to write, split into the section prefix and variable name as the user typed
+/// them (git keeps their case in new lines), plus the normalized section to match.
+struct ConfigKey {
+    section: String,
+    name: String,
+    normalized_section: String,
+}
+
+impl ConfigKey {
+    fn parse(key: &str) -> Result<ConfigKey> {
+        let Some((section, name)) = key.rsplit_once('.') else {
+            bail!("key does not contain a section: {key}");
+        };
+        if section.is_empty() {
+            bail!("key does not contain a section: {key}");
+        }
+        if name.is_empty() {
+            bail!("key does not contain variable name: {key}");
+        }
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic())
+            || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            bail!("invalid key: {key}");
+        }
+        let normalized = normalize_key(key);
+        let normalized_section = normalized.rsplit_once('.').unwrap().0.to_string();
+        Ok(ConfigKey {
+            section: section.to_string(),
+            name: name.to_string(),
+            normalized_section,
+        })
+    }
+}
+
+/// A config file kept as its lines, so that edits leave the comments and layout of
+/// everything else alone.
+struct ConfigFile {
+    lines: Vec<String>,
+}
+
+/// Where a variable is assigned in a [`ConfigFile`].
+struct Assignment {
+    /// More than one line when the value is continued with a trailing `\`.
+    lines: Range<usize>,
+    /// The section header the assignment shares its first line with, if any, as in
+    /// `[core] bare = false`.
+    header: Option<String>,
+    value: Option<String>,
+}
+
+/// What a [`ConfigFile`] holds for one key.
+struct Found {
+    assignments: Vec<Assignment>,
+    /// Every header of the key's section (which may appear more than once).
+    headers: Vec<usize>,
+    /// Where a new assignment goes: after the last header or variable of the
+    /// section's last occurrence, or `None` if the section isn't there.
+    insert_at: Option<usize>,
+}
+
+impl ConfigFile {
+    fn read(path: &Path) -> Result<ConfigFile> {
+        let text = match read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {path:?}")),
+        };
+        Ok(ConfigFile {
+            lines: text.lines().map(str::to_string).collect(),
+        })
+    }
+
+    fn write(&self, path: &Path) -> Result<()> {
+        let lock_path = path.with_extension("lock");
+        let mut text = String::new();
+        for line in self.lines.iter().filter(|line| *line != REMOVED) {
+            text.push_str(line);
+            text.push('\n');
+        }
+        write(&lock_path, text).with_context(|| format!("failed to write {lock_path:?}"))?;
+        rename(&lock_path, path).with_context(|| format!("failed to update {path:?}"))
+    }
+
+    fn find(&self, key: &ConfigKey) -> Result<Found> {
+        let mut found = Found {
+            assignments: Vec::new(),
+            headers: Vec::new(),
+            insert_at: None,
+        };
+        let mut current_section = None;
+        let mut index = 0;
+        while index < self.lines.len() {
+            let start = index;
+            index += 1;
+            let trimmed = self.lines[start].trim_start();
+            let mut header = None;
+            let rest = if trimmed.starts_with('[') {
+                let (name, after) = parse_section_header(trimmed)
+                    .with_context(|| format!("bad config line {}", start + 1))?;
+                let in_section = name == key.normalized_section;
+                current_section = Some(name);
+                if in_section {
+                    found.headers.push(start);
+                    found.insert_at = Some(index);
+                }
+                header = Some(trimmed[..trimmed.len() - after.len()].to_string());
+                after.trim_start()
+            } else {
+                trimmed
+            };
+            if rest.is_empty() || rest.starts_with(['#', ';']) {
+                continue;
+            }
+            let name_len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+                .unwrap_or(rest.len());
+            let (name, after_name) = (&rest[..name_len], rest[name_len..].trim_start());
+            let value = match after_name.strip_prefix('=') {
+                Some(raw) => {
+                    let mut raw = raw.to_string();
+                    while raw.ends_with('\\') && !raw.ends_with("\\\\") && index < self.lines.len()
+                    {
+                        raw.pop();
+                        raw.push_str(&self.lines[index]);
+                        index += 1;
+                    }
+                    Some(
+                        parse_value(&raw)
+                            .with_context(|| format!("bad config line {}", start + 1))?,
+                    )
+                }
+                None => None,
+            };
+            if current_section.as_deref() != Some(&key.normalized_section) {
+                continue;
+            }
+            found.insert_at = Some(index);
+            if name.eq_ignore_ascii_case(&key.name) {
+                found.assignments.push(Assignment {
+                    lines: start..index,
+                    header,
+                    value,
+                });
+            }
+        }
+        Ok(found)
+    }
+
+    fn assignment_line(key: &ConfigKey, value: &str) -> String {
+        format!("\t{} = {}", key.name, quote_value(value))
+    }
+
+    /// Put `key = value` where `found` says new assignments go. This must be the last
+    /// change made with `found`, as it shifts the lines after it.
+    fn insert(&mut self, found: &Found, key: &ConfigKey, value: &str) {
+        let line = ConfigFile::assignment_line(key, value);
+        match found.insert_at {
+            Some(at) => self.lines.insert(at, line),
+            None => {
+                self.lines.push(section_header(&key.section));
+                self.lines.push(line);
+            }
+        }
+    }
+
+    /// Rewrite `assignment` as `key = value` on its first line, dropping any
+    /// continuation lines.
+    fn replace(&mut self, assignment: &Assignment, key: &ConfigKey, value: &str) {
+        let line = ConfigFile::assignment_line(key, value);
+        let first = assignment.lines.start;
+        self.lines[first] = match &assignment.header {
+            Some(header) => format!("{header}{line}"),
+            None => line,
+        };
+        for index in first + 1..assignment.lines.end {
+            self.lines[index] = REMOVED.to_string();
+        }
+    }
+
+    /// Remove `assignments`, and the headers of sections left empty by that.
+    fn remove(&mut self, found: &Found, assignments: &[&Assignment]) {
+        for assignment in assignments {
+            let first = assignment.lines.start;
+            for index in assignment.lines.clone() {
+                self.lines[index] = REMOVED.to_string();
+            }
+            if let Some(header) = &assignment.header {
+                self.lines[first] = header.clone();
+            }
+        }
+        for &header in &found.headers {
+            let rest = &self.lines[header + 1..];
+            let end = rest
+                .iter()
+                .position(|line| line.trim_start().starts_with('['))
+                .unwrap_or(rest.len());
+            let had_removed = rest[..end].iter().any(|line| line == REMOVED);
+            if had_removed && rest[..end].iter().all(|line| line == REMOVED) {
+                self.lines[header] = REMOVED.to_string();
+            }
+        }
+    }
+}
+
+/// Stands in for a removed line until the file is written, so that the line numbers
+/// [`ConfigFile::find`] returned stay valid. Config files never contain NUL bytes.
+const REMOVED: &str = "\0";
+
+/// `[section]` or `[section "subsection"]` for a section prefix.
+fn section_header(section: &str) -> String {
+    match section.split_once('.') {
+        Some((name, subsection)) => format!(
+            "[{name} \"{}\"]",
+            subsection.replace('\\', "\\\\").replace('"', "\\\"")
+        ),
+        None => format!("[{section}]"),
+    }
+}
+
+/// Quote and escape `value` so that [`parse_value`] reads it back unchanged.
+fn quote_value(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    if value.starts_with(char::is_whitespace)
+        || value.ends_with(char::is_whitespace)
+        || value.contains(['#', ';'])
+    {
+        format!("\"{escaped}\"")
+    } else {
+        escaped
+    }
+}
+
+/// `path` with a leading `~/` replaced by the home directory; `None` if it has none.
+fn expand_home(path: &str) -> Option<PathBuf> {
+    let rest = path.strip_prefix("~/")?;
+    env::home_dir().map(|home| home.join(rest))
+}
+
+/// The system-wide config file: `$GIT_CONFIG_SYSTEM`, or `/etc/gitconfig`. `None`
+/// when `GIT_CONFIG_NOSYSTEM` is set to a true value.
+fn system_config_path() -> Option<PathBuf> {
+    let no_system = env::var("GIT_CONFIG_NOSYSTEM").unwrap_or_default();
+    if matches!(
+        no_system.to_ascii_lowercase().as_str(),
+        "true" | "yes" | "on" | "1"
+    ) {
+        return None;
+    }
+    match env::var_os("GIT_CONFIG_SYSTEM") {
+        Some(path) => (!path.is_empty()).then(|| PathBuf::from(path)),
+        None => Some(PathBuf::from("/etc/gitconfig")),
+    }
+}
+
+/// The user's config files, lowest precedence first: `$GIT_CONFIG_GLOBAL` alone if it
+/// is set, otherwise `$XDG_CONFIG_HOME/git/config` (by default under `~/.config`) and
+/// `~/.gitconfig`.
+fn global_config_paths() -> Vec<PathBuf> {
+    if let Some(path) = env::var_os("GIT_CONFIG_GLOBAL") {
+        return match path.is_empty() {
+            true => Vec::new(),
+            false => vec![PathBuf::from(path)],
+        };
+    }
+    xdg_config_path("config")
+        .into_iter()
+        .chain(env::home_dir().map(|home| home.join(".gitconfig")))
+        .collect()
+}
+
+/// `$XDG_CONFIG_HOME/git/<name>` (by default under `~/.config`), where git keeps the
+/// user's `config`, `attributes` and `ignore` files.
+pub fn xdg_config_path(name: &str) -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => env::home_dir()?.join(".config"),
+    };
+    Some(dir.join("git").join(name))
+}
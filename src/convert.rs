@@ -0,0 +1,138 @@
+use crate::attr::{AttrValue, Attributes};
+use anyhow::Result;
+
+/// What a file's content looks like to git's end-of-line conversion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEol {
+    /// Not text: it has NULs or lone carriage returns, or too many control
+    /// characters.
+    Binary,
+    /// Text whose lines all end in `\n`.
+    Lf,
+    /// Text whose lines all end in `\r\n`.
+    Crlf,
+    /// Text with both kinds of line endings.
+    Mixed,
+    /// Text without line endings, e.g. empty.
+    None,
+}
+
+impl ContentEol {
+    /// Classify `data` as git does before converting line endings: it is binary if
+    /// it has a NUL or a `\r` not followed by `\n`, or if fewer than 128 characters are
+    /// printable for each one that isn't (backspace, tab, escape and form feed count as
+    /// printable, and so does a final Ctrl-Z, the end-of-file mark of old DOS files).
+    pub fn of(data: &[u8]) -> ContentEol {
+        let (mut nul, mut lone_cr, mut lone_lf, mut crlf) = (0, 0, 0, 0);
+        let (mut printable, mut nonprintable) = (0usize, 0usize);
+        let mut bytes = data.iter().peekable();
+        while let Some(&byte) = bytes.next() {
+            match byte {
+                b'\r' if bytes.peek() == Some(&&b'\n') => {
+                    bytes.next();
+                    crlf += 1;
+                }
+                b'\r' => lone_cr += 1,
+                b'\n' => lone_lf += 1,
+                0x7f => nonprintable += 1,
+                b'\x08' | b'\t' | b'\x1b' | b'\x0c' => printable += 1,
+                0 => {
+                    nul += 1;
+                    nonprintable += 1;
+                }
+                1..0x20 => nonprintable += 1,
+                _ => printable += 1,
+            }
+        }
+        if data.last() == Some(&0x1a) {
+            nonprintable -= 1;
+        }
+        if nul > 0 || lone_cr > 0 || (printable >> 7) < nonprintable {
+            return ContentEol::Binary;
+        }
+        match (lone_lf > 0, crlf > 0) {
+            (true, false) => ContentEol::Lf,
+            (false, true) => ContentEol::Crlf,
+            (true, true) => ContentEol::Mixed,
+            (false, false) => ContentEol::None,
+        }
+    }
+
+    /// The name `git ls-files --eol` shows for it.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ContentEol::Binary => "-text",
+            ContentEol::Lf => "lf",
+            ContentEol::Crlf => "crlf",
+            ContentEol::Mixed => "mixed",
+            ContentEol::None => "none",
+        }
+    }
+}
+
+/// How the `text`, `eol` (and old `crlf`) attributes say a path's line endings are
+/// converted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EolAttr {
+    /// Nothing is said; the config decides.
+    Undefined,
+    /// `-text`: never converted.
+    Binary,
+    /// `text`: `\r\n` becomes `\n` when storing.
+    Text,
+    /// `text eol=lf`: and stays `\n` when checked out.
+    TextInput,
+    /// `text eol=crlf`: and becomes `\r\n` when checked out.
+    TextCrlf,
+    /// `text=auto`: like `text` if the content is text.
+    Auto,
+    /// `text=auto eol=lf`.
+    AutoInput,
+    /// `text=auto eol=crlf`.
+    AutoCrlf,
+}
+
+impl EolAttr {
+    /// What the attributes of `path` say. An `eol` attribute implies `text`, unless
+    /// it is `-text` or `text=auto`; `crlf` only counts when `text` is unspecified.
+    pub fn of(attributes: &Attributes, path: &str) -> Result<EolAttr> {
+        let states = attributes.check(path)?;
+        let action = |name| match states.get(name) {
+            Some(AttrValue::Set) => EolAttr::Text,
+            Some(AttrValue::Unset) => EolAttr::Binary,
+            Some(AttrValue::Value(value)) if value == "input" => EolAttr::TextInput,
+            Some(AttrValue::Value(value)) if value == "auto" => EolAttr::Auto,
+            _ => EolAttr::Undefined,
+        };
+        let action = match action("text") {
+            EolAttr::Undefined => action("crlf"),
+            action => action,
+        };
+        let eol = match states.get("eol") {
+            Some(AttrValue::Value(value)) => value.as_str(),
+            _ => "",
+        };
+        Ok(match (action, eol) {
+            (EolAttr::Auto, "lf") => EolAttr::AutoInput,
+            (EolAttr::Auto, "crlf") => EolAttr::AutoCrlf,
+            (EolAttr::Binary, _) => EolAttr::Binary,
+            (_, "lf") => EolAttr::TextInput,
+            (_, "crlf") => EolAttr::TextCrlf,
+            (action, _) => action,
+        })
+    }
+
+    /// How `git ls-files --eol` shows it (empty for `Undefined`).
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EolAttr::Undefined => "",
+            EolAttr::Binary => "-text",
+            EolAttr::Text => "text",
+            EolAttr::TextInput => "text eol=lf",
+            EolAttr::TextCrlf => "text eol=crlf",
+            EolAttr::Auto => "text=auto",
+            EolAttr::AutoInput => "text=auto eol=lf",
+            EolAttr::AutoCrlf => "text=auto eol=crlf",
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use anyhow::{Result, bail, ensure};
+use std::collections::HashMap;
+
+/// Rebuild an object from `base` and a git delta, the format used by binary patches
+/// and packfiles: the sizes of the base and the result as little-endian base-128
+/// numbers, then instructions that either copy a range of the base or insert the
+/// bytes that follow them. The result isn't set aside at the size the delta claims,
+/// which may come from a peer or a patch, but grows as the instructions fill it, and the
+/// delta is refused as soon as they go past that size.
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let mut pos = 0;
+    let base_size = read_size(delta, &mut pos)?;
+    ensure!(
+        base_size == base.len(),
+        "delta is for a base of {base_size} bytes, not {}",
+        base.len()
+    );
+    let result_size = read_size(delta, &mut pos)?;
+    let mut out = Vec::new();
+    while pos < delta.len() {
+        let command = delta[pos];
+        pos += 1;
+        if command & 0x80 != 0 {
+            // Which of the four offset and three size bytes follow is given by the
+            // command's low bits; missing ones are zero, and a zero size means 64k.
+            let mut field = |bits: u8, shift: usize| -> Result<usize> {
+                let mut value = 0;
+                for i in 0..shift {
+                    if bits & (1 << i) != 0 {
+                        let Some(&byte) = delta.get(pos) else {
+                            bail!("delta is truncated");
+                        };
+                        value |= (byte as usize) << (8 * i);
+                        pos += 1;
+                    }
+                }
+                Ok(value)
+            };
+            let offset = field(command & 0x0f, 4)?;
+            let size = match field((command >> 4) & 0x07, 3)? {
+                0 => 0x10000,
+                size => size,
+            };
+            let Some(copied) = offset
+                .checked_add(size)
+                .and_then(|end| base.get(offset..end))
+            else {
+                bail!("delta copies past the end of its base");
+            };
+            out.extend_from_slice(copied);
+        } else if command != 0 {
+            let size = command as usize;
+            let Some(inserted) = delta.get(pos..pos + size) else {
+                bail!("delta is truncated");
+            };
+            out.extend_from_slice(inserted);
+            pos += size;
+        } else {
+            bail!("unexpected delta opcode 0");
+        }
+        ensure!(
+            out.len() <= result_size,
+            "delta produced more than {result_size} bytes"
+        );
+    }
+    ensure!(
+        out.len() == result_size,
+        "delta produced {} bytes instead of {result_size}",
+        out.len()
+    );
+    Ok(out)
+}
+
+/// The sizes of the base and of the result a delta starts with.
+pub fn delta_sizes(delta: &[u8]) -> Result<(usize, usize)> {
+    let mut pos = 0;
+    let base_size = read_size(delta, &mut pos)?;
+    Ok((base_size, read_size(delta, &mut pos)?))
+}
+
+fn read_size(delta: &[u8], pos: &mut usize) -> Result<usize> {
+    let mut size = 0;
+    let mut shift = 0;
+    loop {
+        let Some(&byte) = delta.get(*pos) else {
+            bail!("delta header is truncated");
+        };
+        *pos += 1;
+        size |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(size);
+        }
+        shift += 7;
+        ensure!(shift < usize::BITS, "delta header is too large");
+    }
+}
+
+/// How many bytes of the base [`create_delta`] indexes as one block.
+const BLOCK: usize = 16;
+
+/// How many places of the base [`create_delta`] remembers for the same block, so that
+/// repetitive content doesn't make matching slow.
+const MAX_BLOCK_PLACES: usize = 64;
+
+/// The most a single copy instruction of [`create_delta`] copies, as in git.
+const MAX_COPY: usize = 0x10000;
+
+/// A delta that rebuilds `target` from `base`, for [`apply_delta`]; see
+/// [`DeltaIndex::delta`].
+pub fn create_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    DeltaIndex::new(base)
+        .delta(target, usize::MAX)
+        .unwrap_or_default()
+}
+
+/// A base indexed in blocks of 16 bytes, to make deltas against it, as one base is
+/// tried for many targets when packing.
+pub struct DeltaIndex<'a> {
+    base: &'a [u8],
+    blocks: HashMap<&'a [u8], Vec<usize>>,
+}
+
+impl<'a> DeltaIndex<'a> {
+    pub fn new(base: &'a [u8]) -> DeltaIndex<'a> {
+        let mut blocks: HashMap<&[u8], Vec<usize>> = HashMap::new();
+        for start in (0..base.len().saturating_sub(BLOCK - 1)).step_by(BLOCK) {
+            let places = blocks.entry(&base[start..start + BLOCK]).or_default();
+            if places.len() < MAX_BLOCK_PLACES {
+                places.push(start);
+            }
+        }
+        DeltaIndex { base, blocks }
+    }
+
+    /// A delta that rebuilds `target` from the base: at each position of the target the
+    /// longest match starting with a block found there (extended back over bytes not
+    /// yet copied) is copied, while what matches nothing is inserted as it is. `None`
+    /// once the delta would be `max_size` bytes or more.
+    pub fn delta(&self, target: &[u8], max_size: usize) -> Option<Vec<u8>> {
+        let base = self.base;
+        let mut delta = Vec::new();
+        write_size(&mut delta, base.len());
+        write_size(&mut delta, target.len());
+        let mut inserted: Vec<u8> = Vec::new();
+        let mut position = 0;
+        while position < target.len() {
+            if delta.len() + inserted.len() >= max_size {
+                return None;
+            }
+            let candidates = target
+                .get(position..position + BLOCK)
+                .and_then(|block| self.blocks.get(block));
+            let best = candidates.into_iter().flatten().map(|&start| {
+                let length = base[start..]
+                    .iter()
+                    .zip(&target[position..])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                (length, start)
+            });
+            let Some((mut length, mut start)) =
+                best.max_by_key(|&(length, start)| (length, !start))
+            else {
+                inserted.push(target[position]);
+                position += 1;
+                continue;
+            };
+            position += length;
+            while start > 0 && inserted.last() == Some(&base[start - 1]) {
+                inserted.pop();
+                start -= 1;
+                length += 1;
+            }
+            write_insert(&mut delta, &mut inserted);
+            while length > 0 {
+                let size = length.min(MAX_COPY);
+                write_copy(&mut delta, start, size);
+                start += size;
+                length -= size;
+            }
+        }
+        write_insert(&mut delta, &mut inserted);
+        (delta.len() < max_size).then_some(delta)
+    }
+}
+
+/// Append `size` as a little-endian base-128 number.
+fn write_size(delta: &mut Vec<u8>, mut size: usize) {
+    while size >= 0x80 {
+        delta.push(size as u8 | 0x80);
+        size >>= 7;
+    }
+    delta.push(size as u8);
+}
+
+/// Append instructions inserting `bytes`, at most 127 at a time, and clear them.
+fn write_insert(delta: &mut Vec<u8>, bytes: &mut Vec<u8>) {
+    for chunk in bytes.chunks(0x7f) {
+        delta.push(chunk.len() as u8);
+        delta.extend(chunk);
+    }
+    bytes.clear();
+}
+
+/// Append an instruction copying `size` (at most 64k) bytes at `offset` of the base,
+/// with only the nonzero bytes of each given.
+fn write_copy(delta: &mut Vec<u8>, offset: usize, size: usize) {
+    let command = delta.len();
+    delta.push(0x80);
+    for i in 0..4 {
+        let byte = (offset >> (8 * i)) as u8;
+        if byte != 0 {
+            delta[command] |= 1 << i;
+            delta.push(byte);
+        }
+    }
+    // A size of 64k is written as no size bytes at all.
+    let size = if size == MAX_COPY { 0 } else { size };
+    for i in 0..3 {
+        let byte = (size >> (8 * i)) as u8;
+        if byte != 0 {
+            delta[command] |= 0x10 << i;
+            delta.push(byte);
+        }
+    }
+}
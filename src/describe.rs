@@ -0,0 +1,135 @@
+use crate::commit_read::{Commit, walk_commits};
+use crate::ident::Ident;
+use crate::object_read::{Object, ObjectKind};
+use crate::refs::list_refs;
+use crate::repository::Repository;
+use crate::worktree::is_dirty;
+use anyhow::{Result, bail};
+use std::collections::HashMap;
+
+/// How many tags are considered before picking the closest one, as in git.
+const MAX_CANDIDATES: usize = 10;
+
+#[derive(Debug, Default)]
+pub struct DescribeOptions {
+    /// Consider lightweight tags too, not just annotated ones.
+    pub tags: bool,
+    /// Fall back to the abbreviated commit id when no tag describes the commit.
+    pub always: bool,
+    /// Always print `<tag>-<n>-g<hash>`, even for a tagged commit.
+    pub long: bool,
+    /// Number of hex digits in the abbreviated commit id; 0 prints just the tag.
+    pub abbrev: usize,
+    /// Append this suffix (e.g. `-dirty`) when the working directory has changes.
+    /// Only meaningful when describing HEAD.
+    pub dirty: Option<String>,
+}
+
+/// A tag that points (possibly through tag objects) at a commit.
+struct TagName {
+    name: String,
+    annotated: bool,
+    /// Tagger date of an annotated tag, to prefer the newest of several.
+    date: i64,
+}
+
+/// Name `commit_ish` after the closest tag reachable from it, like `git describe`:
+/// the tag itself for a tagged commit, otherwise `<tag>-<n>-g<abbreviated id>` where
+/// `n` counts the commits since the tag.
+pub fn git_describe(
+    repo: &Repository,
+    commit_ish: &str,
+    options: &DescribeOptions,
+) -> Result<String> {
+    let suffix = match &options.dirty {
+        Some(mark) if is_dirty(repo)? => mark.as_str(),
+        _ => "",
+    };
+    let commit = Commit::read(repo, commit_ish)?;
+    let names = tag_names(repo, options.tags)?;
+    let abbrev = &commit.hash[..options.abbrev.clamp(4, commit.hash.len())];
+
+    if let Some(tag) = names.get(&commit.hash)
+        && (!options.long || options.abbrev == 0)
+    {
+        return Ok(format!("{}{suffix}", tag.name));
+    }
+
+    let mut candidates = Vec::new();
+    for walked in walk_commits(repo, std::slice::from_ref(&commit.hash), &[])? {
+        if let Some(tag) = names.get(&walked.hash) {
+            candidates.push((tag, walked.hash));
+            if candidates.len() == MAX_CANDIDATES {
+                break;
+            }
+        }
+    }
+    // The closest tag is the one leaving the fewest commits unaccounted for; among
+    // equals the one found first, which is the newest.
+    let mut best = None;
+    for (tag, tagged) in candidates {
+        let depth = walk_commits(repo, std::slice::from_ref(&commit.hash), &[tagged])?.len();
+        if best
+            .as_ref()
+            .is_none_or(|(_, best_depth)| depth < *best_depth)
+        {
+            best = Some((tag, depth));
+        }
+    }
+    match best {
+        Some((tag, _)) if options.abbrev == 0 => Ok(format!("{}{suffix}", tag.name)),
+        Some((tag, depth)) => Ok(format!("{}-{depth}-g{abbrev}{suffix}", tag.name)),
+        None if options.always => Ok(format!("{abbrev}{suffix}")),
+        None if !options.tags && !tag_names(repo, true)?.is_empty() => bail!(
+            "No annotated tags can describe '{}'.\n\
+             However, there were unannotated tags: try --tags.",
+            commit.hash
+        ),
+        None => bail!("No names found, cannot describe anything."),
+    }
+}
+
+/// The tag describing each tagged commit. An annotated tag beats a lightweight one,
+/// and a newer annotated tag an older one.
+fn tag_names(repo: &Repository, include_lightweight: bool) -> Result<HashMap<String, TagName>> {
+    let mut names: HashMap<String, TagName> = HashMap::new();
+    for (ref_name, hash) in list_refs(repo, "refs/tags/")? {
+        let name = ref_name.trim_start_matches("refs/tags/").to_string();
+        let mut object = Object::read_git_object(repo, &hash)?;
+        let (annotated, date) = match object.kind {
+            ObjectKind::Tag => {
+                let data = object.read_all()?;
+                (true, tagger_date(&data).unwrap_or(0))
+            }
+            ObjectKind::Commit => (false, 0),
+            _ => continue,
+        };
+        if !annotated && !include_lightweight {
+            continue;
+        }
+        let Ok((commit, _)) = Object::peel_to(repo, &hash, ObjectKind::Commit) else {
+            continue;
+        };
+        let tag = TagName {
+            name,
+            annotated,
+            date,
+        };
+        match names.get(&commit) {
+            Some(existing) if (existing.annotated, existing.date) >= (tag.annotated, tag.date) => {}
+            _ => {
+                names.insert(commit, tag);
+            }
+        }
+    }
+    Ok(names)
+}
+
+fn tagger_date(data: &[u8]) -> Option<i64> {
+    let text = std::str::from_utf8(data).ok()?;
+    let tagger = text
+        .lines()
+        .take_while(|line| !line.is_empty())
+        .find_map(|line| line.strip_prefix("tagger "))?;
+    Ident::parse(tagger).ok().map(|ident| ident.timestamp)
+}
@@ -0,0 +1,2397 @@
+use crate::attr::Attributes;
+use crate::base85;
+use crate::config::Config;
+use crate::delta::DeltaIndex;
+use crate::fsck::check_entry_name;
+use crate::index::{Index, IndexEntry, read_symlink, worktree_mode};
+use crate::ls_tree::{TreeEntry, read_tree_entries};
+use crate::object_id::ObjectId;
+use crate::object_read::{Object, ObjectKind};
+use crate::refs::resolve_ref;
+use crate::repository::Repository;
+use crate::submodule::{submodule_git_dir, submodule_head, subproject_content};
+use crate::userdiff::DiffDriver;
+use crate::whitespace::{WhitespaceRule, is_blank_line};
+use crate::write_tree::compare_tree_entry_names;
+use anyhow::{Context, Result, bail};
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::env::var;
+use std::fs::{self, read, symlink_metadata};
+use std::io::{BufWriter, Cursor, Write, sink, stdout};
+use std::ops::Range;
+use std::process::Command;
+
+/// One step of an edit script turning `old` into `new`; indices point into each side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffOp {
+    Equal { old: usize, new: usize },
+    Delete { old: usize },
+    Insert { new: usize },
+}
+
+/// Which differences in whitespace don't count as changes, as `git diff -w`, `-b`,
+/// `--ignore-space-at-eol` and `--ignore-blank-lines` (or a merge's
+/// `-Xignore-space-change` and its like) say. Line endings are whitespace too.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct IgnoreWhitespace {
+    /// Compare lines without any of their whitespace.
+    pub all_space: bool,
+    /// Take runs of whitespace to be alike, and ignore them at the end of lines.
+    pub space_change: bool,
+    /// Ignore whitespace at the end of lines.
+    pub space_at_eol: bool,
+    /// Leave out changes whose lines are all blank, unless they are close enough to
+    /// other changes to fall in the context of their hunk.
+    pub blank_lines: bool,
+}
+
+impl IgnoreWhitespace {
+    /// Whether `--ignore-blank-lines` takes `line` for a blank line: an empty one, or
+    /// one of only whitespace when whitespace is ignored otherwise too.
+    pub fn is_blank(&self, line: &[u8]) -> bool {
+        match self.all_space || self.space_change || self.space_at_eol {
+            true => is_blank_line(line),
+            false => line.len() <= 1,
+        }
+    }
+
+    /// `line` as it is compared: with all whitespace removed, with runs of it made one
+    /// space and none at the end, or with none at the end.
+    pub fn normalize<'a>(&self, line: &'a [u8]) -> Cow<'a, [u8]> {
+        if self.all_space {
+            Cow::Owned(
+                line.iter()
+                    .copied()
+                    .filter(|b| !b.is_ascii_whitespace())
+                    .collect(),
+            )
+        } else if self.space_change {
+            let mut key = Vec::with_capacity(line.len());
+            let mut space = false;
+            for &b in line {
+                if b.is_ascii_whitespace() {
+                    space = true;
+                } else {
+                    if space {
+                        key.push(b' ');
+                    }
+                    space = false;
+                    key.push(b);
+                }
+            }
+            Cow::Owned(key)
+        } else if self.space_at_eol {
+            let end = line
+                .iter()
+                .rposition(|b| !b.is_ascii_whitespace())
+                .map_or(0, |end| end + 1);
+            Cow::Borrowed(&line[..end])
+        } else {
+            Cow::Borrowed(line)
+        }
+    }
+}
+
+/// Compute an edit script between two sequences with Myers' algorithm, as git's
+/// xdiff does: lines that can't match are set aside first (see [`cleanup_records`]),
+/// the search takes xdiff's shortcuts on costly inputs (see [`Search::split`]), and
+/// runs of changes are then positioned as it positions them (see
+/// [`compact_changes`]), so hunks come out as they would from `git diff`.
+pub fn diff_lines(old: &[&[u8]], new: &[&[u8]]) -> Vec<DiffOp> {
+    diff_lines_ignoring(old, new, &IgnoreWhitespace::default())
+}
+
+/// Like [`diff_lines`], with lines that differ only in the whitespace `ignore`
+/// names taken to be equal.
+pub fn diff_lines_ignoring(old: &[&[u8]], new: &[&[u8]], ignore: &IgnoreWhitespace) -> Vec<DiffOp> {
+    let old_keys: Vec<Cow<[u8]>> = old.iter().map(|line| ignore.normalize(line)).collect();
+    let new_keys: Vec<Cow<[u8]>> = new.iter().map(|line| ignore.normalize(line)).collect();
+    let (old_keys, new_keys) = (&old_keys[..], &new_keys[..]);
+    let mut old_changed = vec![false; old.len()];
+    let mut new_changed = vec![false; new.len()];
+    let (old_kept, new_kept) =
+        cleanup_records(old_keys, new_keys, &mut old_changed, &mut new_changed);
+    let old_classes: Vec<usize> = old_kept.iter().map(|&(_, class)| class).collect();
+    let new_classes: Vec<usize> = new_kept.iter().map(|&(_, class)| class).collect();
+    let mut old_kept_changed = vec![false; old_kept.len()];
+    let mut new_kept_changed = vec![false; new_kept.len()];
+    conquer(
+        &old_classes,
+        0..old_kept.len(),
+        &new_classes,
+        0..new_kept.len(),
+        &mut Search::new(old_kept.len(), new_kept.len()),
+        false,
+        &mut old_kept_changed,
+        &mut new_kept_changed,
+    );
+    for ((line, _), changed) in old_kept.iter().zip(old_kept_changed) {
+        old_changed[*line] |= changed;
+    }
+    for ((line, _), changed) in new_kept.iter().zip(new_kept_changed) {
+        new_changed[*line] |= changed;
+    }
+    let pad = |changed: Vec<bool>| [vec![false], changed, vec![false]].concat();
+    let mut old_side = Side {
+        lines: old,
+        keys: old_keys,
+        changed: pad(old_changed),
+    };
+    let mut new_side = Side {
+        lines: new,
+        keys: new_keys,
+        changed: pad(new_changed),
+    };
+    compact_changes(&mut old_side, &mut new_side);
+    compact_changes(&mut new_side, &mut old_side);
+    let old_changed = &old_side.changed[1..=old.len()];
+    let new_changed = &new_side.changed[1..=new.len()];
+
+    let mut ops = Vec::with_capacity(old.len().max(new.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && old_changed[i] {
+            ops.push(DiffOp::Delete { old: i });
+            i += 1;
+        } else if j < new.len() && new_changed[j] {
+            ops.push(DiffOp::Insert { new: j });
+            j += 1;
+        } else {
+            ops.push(DiffOp::Equal { old: i, new: j });
+            i += 1;
+            j += 1;
+        }
+    }
+    ops
+}
+
+/// A line left for the search by [`cleanup_records`]: its index, and its class of
+/// equal lines.
+type KeptLine = (usize, usize);
+
+/// How far [`cleanup_records`] looks each way from a line with many matches.
+const SIMSCAN_WINDOW: usize = 100;
+
+/// The most matches a line can have before [`cleanup_records`] counts it as having
+/// many, however long the file.
+const MAX_EQLIMIT: usize = 1024;
+
+/// Set aside the lines that can't be part of an edit path worth finding before the
+/// search (`xdl_cleanup_records` of git's xdiff), marking them changed in
+/// `old_changed` and `new_changed`: past the lines both sides start and end with,
+/// a line with no match on the other side is changed whatever the search finds, and so
+/// is one with many matches (about the square root of its side's length) in the middle
+/// of a run of such lines. Returns the lines left for the search on each side, with
+/// the class of equal lines each is in.
+///
+/// Besides making the search cheaper, this decides which of several equally short
+/// edit scripts comes out, as it does in git.
+fn cleanup_records(
+    old: &[Cow<[u8]>],
+    new: &[Cow<[u8]>],
+    old_changed: &mut [bool],
+    new_changed: &mut [bool],
+) -> (Vec<KeptLine>, Vec<KeptLine>) {
+    let mut classes: HashMap<&[u8], usize> = HashMap::new();
+    let mut counts: Vec<[usize; 2]> = Vec::new();
+    let mut sides = [Vec::with_capacity(old.len()), Vec::with_capacity(new.len())];
+    for (side, lines) in [old, new].into_iter().enumerate() {
+        for line in lines {
+            let next = classes.len();
+            let class = *classes.entry(line).or_insert(next);
+            if class == counts.len() {
+                counts.push([0, 0]);
+            }
+            counts[class][side] += 1;
+            sides[side].push(class);
+        }
+    }
+    let [old_classes, new_classes] = sides;
+
+    let common = old_classes.len().min(new_classes.len());
+    let start = (0..common)
+        .find(|&i| old_classes[i] != new_classes[i])
+        .unwrap_or(common);
+    let end = (0..common - start)
+        .find(|&i| old_classes[old_classes.len() - 1 - i] != new_classes[new_classes.len() - 1 - i])
+        .unwrap_or(common - start);
+
+    let keep = |classes: &[usize], other: usize, changed: &mut [bool]| {
+        let limit = bogosqrt(classes.len()).min(MAX_EQLIMIT);
+        let range = start..classes.len() - end;
+        // 0 for no match on the other side, 1 for some, 2 for many.
+        let matches: Vec<u8> = classes[range.clone()]
+            .iter()
+            .map(|&class| match counts[class][other] {
+                0 => 0,
+                n if n >= limit => 2,
+                _ => 1,
+            })
+            .collect();
+        let mut kept = Vec::new();
+        for (i, line) in range.enumerate() {
+            match matches[i] {
+                1 => kept.push((line, classes[line])),
+                2 if !in_unmatched_run(&matches, i) => kept.push((line, classes[line])),
+                _ => changed[line] = true,
+            }
+        }
+        kept
+    };
+    let old_kept = keep(&old_classes, 1, old_changed);
+    let new_kept = keep(&new_classes, 0, new_changed);
+    (old_kept, new_kept)
+}
+
+/// Whether the line `i` with many matches stands among lines without any, going by
+/// `matches` as [`cleanup_records`] gives them (`xdl_clean_mmatch`): the lines with
+/// many matches run up against either side of it, including it, must number less than
+/// a quarter of all those, when each side has at least one line without any.
+fn in_unmatched_run(matches: &[u8], i: usize) -> bool {
+    let start = i.saturating_sub(SIMSCAN_WINDOW);
+    let end = (i + SIMSCAN_WINDOW).min(matches.len() - 1);
+    let count = |lines: &mut dyn Iterator<Item = usize>| {
+        let (mut unmatched, mut many) = (0, 1);
+        for line in lines {
+            match matches[line] {
+                0 => unmatched += 1,
+                2 => many += 1,
+                _ => break,
+            }
+        }
+        (unmatched, many)
+    };
+    let (unmatched_before, many_before) = count(&mut (start..i).rev());
+    if unmatched_before == 0 {
+        return false;
+    }
+    let (unmatched_after, many_after) = count(&mut (i + 1..=end));
+    if unmatched_after == 0 {
+        return false;
+    }
+    let many = many_before + many_after;
+    many * 4 < many + unmatched_before + unmatched_after
+}
+
+/// xdiff's stand-in for a square root: the power of two with about half as many
+/// bits as `n`.
+fn bogosqrt(mut n: usize) -> usize {
+    let mut root = 1;
+    while n > 0 {
+        root <<= 1;
+        n >>= 2;
+    }
+    root
+}
+
+/// Mark what changed between `old_range` of `old` and `new_range` of `new`, as
+/// `xdl_recs_cmp` of git's xdiff does: the lines both start and end with are passed
+/// over, and what is left is split (see [`Search::split`]) and each half done alike.
+#[allow(clippy::too_many_arguments)]
+fn conquer<T: PartialEq>(
+    old: &[T],
+    mut old_range: Range<usize>,
+    new: &[T],
+    mut new_range: Range<usize>,
+    search: &mut Search,
+    minimal: bool,
+    old_changed: &mut [bool],
+    new_changed: &mut [bool],
+) {
+    while !old_range.is_empty()
+        && !new_range.is_empty()
+        && old[old_range.start] == new[new_range.start]
+    {
+        old_range.start += 1;
+        new_range.start += 1;
+    }
+    while !old_range.is_empty()
+        && !new_range.is_empty()
+        && old[old_range.end - 1] == new[new_range.end - 1]
+    {
+        old_range.end -= 1;
+        new_range.end -= 1;
+    }
+    if old_range.is_empty() {
+        new_changed[new_range].fill(true);
+    } else if new_range.is_empty() {
+        old_changed[old_range].fill(true);
+    } else {
+        let split = search.split(old, old_range.clone(), new, new_range.clone(), minimal);
+        conquer(
+            old,
+            old_range.start..split.old,
+            new,
+            new_range.start..split.new,
+            search,
+            split.minimal_before,
+            old_changed,
+            new_changed,
+        );
+        conquer(
+            old,
+            split.old..old_range.end,
+            new,
+            split.new..new_range.end,
+            search,
+            split.minimal_after,
+            old_changed,
+            new_changed,
+        );
+    }
+}
+
+/// How many equal lines in a row make a snake worth cutting the search short at.
+const SNAKE_CNT: isize = 20;
+
+/// The edit cost past which a search may stop at a good enough snake.
+const HEUR_MIN_COST: isize = 256;
+
+/// The least edit cost past which a search settles for the furthest reaching path.
+const MAX_COST_MIN: isize = 256;
+
+/// How far along, for each unit of edit cost, a path has to be to count as good
+/// enough.
+const K_HEUR: isize = 4;
+
+/// The state of the search for an edit script: the furthest reaching path on each
+/// diagonal (`old - new`, offset to index the vectors), forward and backward, and the
+/// edit cost at which a search gives up on finding the shortest.
+struct Search {
+    forward: Vec<isize>,
+    backward: Vec<isize>,
+    offset: isize,
+    max_cost: isize,
+}
+
+/// Where [`Search::split`] divides a problem, and whether each half still has to be
+/// searched for its shortest script or may take shortcuts too.
+struct Split {
+    old: usize,
+    new: usize,
+    minimal_before: bool,
+    minimal_after: bool,
+}
+
+impl Search {
+    fn new(old_len: usize, new_len: usize) -> Search {
+        let diagonals = old_len + new_len + 3;
+        Search {
+            forward: vec![0; diagonals],
+            backward: vec![0; diagonals],
+            offset: new_len as isize + 1,
+            max_cost: (bogosqrt(diagonals) as isize).max(MAX_COST_MIN),
+        }
+    }
+
+    /// Find where to split `old_range` and `new_range` (which don't start or end
+    /// with equal lines) on an edit path between them, as `xdl_split` of git's xdiff
+    /// does, by searching forward from the start and backward from the end until the
+    /// paths meet. Unless `minimal`, a costly search stops early: at a long snake far
+    /// enough along once the cost passes [`HEUR_MIN_COST`], or at the furthest
+    /// reaching path once it passes the search's maximum.
+    fn split<T: PartialEq>(
+        &mut self,
+        old: &[T],
+        old_range: Range<usize>,
+        new: &[T],
+        new_range: Range<usize>,
+        minimal: bool,
+    ) -> Split {
+        let Search {
+            forward: kvdf,
+            backward: kvdb,
+            offset,
+            max_cost,
+        } = self;
+        let at = |d: isize| (d + *offset) as usize;
+        let same = |i1: isize, i2: isize| old[i1 as usize] == new[i2 as usize];
+        let split = |i1: isize, i2: isize, minimal_before, minimal_after| Split {
+            old: i1 as usize,
+            new: i2 as usize,
+            minimal_before,
+            minimal_after,
+        };
+        let (off1, lim1) = (old_range.start as isize, old_range.end as isize);
+        let (off2, lim2) = (new_range.start as isize, new_range.end as isize);
+        let (dmin, dmax) = (off1 - lim2, lim1 - off2);
+        let (fmid, bmid) = (off1 - off2, lim1 - lim2);
+        let odd = (fmid - bmid) & 1 != 0;
+        let (mut fmin, mut fmax) = (fmid, fmid);
+        let (mut bmin, mut bmax) = (bmid, bmid);
+        kvdf[at(fmid)] = off1;
+        kvdb[at(bmid)] = lim1;
+
+        for ec in 1.. {
+            let mut got_snake = false;
+
+            // Extend the diagonals searched by one each way, or if that leaves the
+            // box, shrink them, so that they keep the parity of the cost.
+            match fmin > dmin {
+                true => {
+                    fmin -= 1;
+                    kvdf[at(fmin - 1)] = -1;
+                }
+                false => fmin += 1,
+            }
+            match fmax < dmax {
+                true => {
+                    fmax += 1;
+                    kvdf[at(fmax + 1)] = -1;
+                }
+                false => fmax -= 1,
+            }
+            for d in (fmin..=fmax).rev().step_by(2) {
+                let mut i1 = match kvdf[at(d - 1)] >= kvdf[at(d + 1)] {
+                    true => kvdf[at(d - 1)] + 1,
+                    false => kvdf[at(d + 1)],
+                };
+                let prev1 = i1;
+                let mut i2 = i1 - d;
+                while i1 < lim1 && i2 < lim2 && same(i1, i2) {
+                    i1 += 1;
+                    i2 += 1;
+                }
+                got_snake |= i1 - prev1 > SNAKE_CNT;
+                kvdf[at(d)] = i1;
+                if odd && bmin <= d && d <= bmax && kvdb[at(d)] <= i1 {
+                    return split(i1, i2, true, true);
+                }
+            }
+
+            match bmin > dmin {
+                true => {
+                    bmin -= 1;
+                    kvdb[at(bmin - 1)] = isize::MAX;
+                }
+                false => bmin += 1,
+            }
+            match bmax < dmax {
+                true => {
+                    bmax += 1;
+                    kvdb[at(bmax + 1)] = isize::MAX;
+                }
+                false => bmax -= 1,
+            }
+            for d in (bmin..=bmax).rev().step_by(2) {
+                let mut i1 = match kvdb[at(d - 1)] < kvdb[at(d + 1)] {
+                    true => kvdb[at(d - 1)],
+                    false => kvdb[at(d + 1)] - 1,
+                };
+                let prev1 = i1;
+                let mut i2 = i1 - d;
+                while i1 > off1 && i2 > off2 && same(i1 - 1, i2 - 1) {
+                    i1 -= 1;
+                    i2 -= 1;
+                }
+                got_snake |= prev1 - i1 > SNAKE_CNT;
+                kvdb[at(d)] = i1;
+                if !odd && fmin <= d && d <= fmax && i1 <= kvdf[at(d)] {
+                    return split(i1, i2, true, true);
+                }
+            }
+
+            if minimal {
+                continue;
+            }
+
+            // Past the trigger, a path that has come far for its cost (measured from
+            // the corner it started at, less how far it strayed from the middle
+            // diagonal) and ends in a snake is taken.
+            if got_snake && ec > HEUR_MIN_COST {
+                let mut best = None;
+                let mut best_v = 0;
+                for d in (fmin..=fmax).rev().step_by(2) {
+                    let dd = (d - fmid).abs();
+                    let i1 = kvdf[at(d)];
+                    let i2 = i1 - d;
+                    let v = (i1 - off1) + (i2 - off2) - dd;
+                    if v > K_HEUR * ec
+                        && v > best_v
+                        && off1 + SNAKE_CNT <= i1
+                        && i1 < lim1
+                        && off2 + SNAKE_CNT <= i2
+                        && i2 < lim2
+                        && (1..=SNAKE_CNT).all(|k| same(i1 - k, i2 - k))
+                    {
+                        best_v = v;
+                        best = Some((i1, i2));
+                    }
+                }
+                if let Some((i1, i2)) = best {
+                    return split(i1, i2, true, false);
+                }
+
+                let mut best_v = 0;
+                for d in (bmin..=bmax).rev().step_by(2) {
+                    let dd = (d - bmid).abs();
+                    let i1 = kvdb[at(d)];
+                    let i2 = i1 - d;
+                    let v = (lim1 - i1) + (lim2 - i2) - dd;
+                    if v > K_HEUR * ec
+                        && v > best_v
+                        && off1 < i1
+                        && i1 <= lim1 - SNAKE_CNT
+                        && off2 < i2
+                        && i2 <= lim2 - SNAKE_CNT
+                        && (0..SNAKE_CNT).all(|k| same(i1 + k, i2 + k))
+                    {
+                        best_v = v;
+                        best = Some((i1, i2));
+                    }
+                }
+                if let Some((i1, i2)) = best {
+                    return split(i1, i2, false, true);
+                }
+            }
+
+            // Enough is enough: split at whichever path, forward or backward, has
+            // come furthest.
+            if ec >= *max_cost {
+                let (mut fbest, mut fbest1) = (-1, -1);
+                for d in (fmin..=fmax).rev().step_by(2) {
+                    let mut i1 = kvdf[at(d)].min(lim1);
+                    let mut i2 = i1 - d;
+                    if lim2 < i2 {
+                        i1 = lim2 + d;
+                        i2 = lim2;
+                    }
+                    if fbest < i1 + i2 {
+                        fbest = i1 + i2;
+                        fbest1 = i1;
+                    }
+                }
+                let (mut bbest, mut bbest1) = (isize::MAX, isize::MAX);
+                for d in (bmin..=bmax).rev().step_by(2) {
+                    let mut i1 = kvdb[at(d)].max(off1);
+                    let mut i2 = i1 - d;
+                    if i2 < off2 {
+                        i1 = off2 + d;
+                        i2 = off2;
+                    }
+                    if i1 + i2 < bbest {
+                        bbest = i1 + i2;
+                        bbest1 = i1;
+                    }
+                }
+                return match (lim1 + lim2) - bbest < fbest - (off1 + off2) {
+                    true => split(fbest1, fbest - fbest1, true, false),
+                    false => split(bbest1, bbest - bbest1, false, true),
+                };
+            }
+        }
+        unreachable!("the search always ends")
+    }
+}
+
+/// Changed-line flags of one side, padded with an unchanged sentinel at each end so
+/// group scans never run off the edges. Lines are compared by their `keys` (see
+/// [`IgnoreWhitespace::normalize`]), and indented as they are.
+struct Side<'a> {
+    lines: &'a [&'a [u8]],
+    keys: &'a [Cow<'a, [u8]>],
+    changed: Vec<bool>,
+}
+
+impl Side<'_> {
+    fn is_changed(&self, i: isize) -> bool {
+        self.changed[(i + 1) as usize]
+    }
+
+    fn set_changed(&mut self, i: usize, value: bool) {
+        self.changed[i + 1] = value;
+    }
+}
+
+/// A maximal run `start..end` of changed lines; empty between two unchanged lines.
+#[derive(Clone, Copy)]
+struct Group {
+    start: usize,
+    end: usize,
+}
+
+impl Group {
+    fn first(side: &Side) -> Group {
+        let mut end = 0;
+        while side.is_changed(end as isize) {
+            end += 1;
+        }
+        Group { start: 0, end }
+    }
+
+    /// Move to the next group, skipping the one unchanged line after this one.
+    fn next(&mut self, side: &Side) -> bool {
+        if self.end == side.lines.len() {
+            return false;
+        }
+        self.start = self.end + 1;
+        self.end = self.start;
+        while side.is_changed(self.end as isize) {
+            self.end += 1;
+        }
+        true
+    }
+
+    fn previous(&mut self, side: &Side) -> bool {
+        if self.start == 0 {
+            return false;
+        }
+        self.end = self.start - 1;
+        self.start = self.end;
+        while side.is_changed(self.start as isize - 1) {
+            self.start -= 1;
+        }
+        true
+    }
+
+    fn slide_down(&mut self, side: &mut Side) -> bool {
+        if self.end < side.lines.len() && side.keys[self.start] == side.keys[self.end] {
+            side.set_changed(self.start, false);
+            side.set_changed(self.end, true);
+            self.start += 1;
+            self.end += 1;
+            while side.is_changed(self.end as isize) {
+                self.end += 1;
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    fn slide_up(&mut self, side: &mut Side) -> bool {
+        if self.start > 0 && side.keys[self.start - 1] == side.keys[self.end - 1] {
+            self.start -= 1;
+            self.end -= 1;
+            side.set_changed(self.start, true);
+            side.set_changed(self.end, false);
+            while side.is_changed(self.start as isize - 1) {
+                self.start -= 1;
+            }
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Slide groups of changed lines in `side` to where they read best, as git's xdiff
+/// does; `other` is the opposite side of the diff, which is kept in sync. This never
+/// alters what changed, only which of several identical lines is reported as changed.
+///
+/// Each group is first slid as far up and then down as identical lines allow, merging
+/// with groups it runs into. If somewhere in that range it lines up with a change on
+/// the other side, it is moved there; otherwise git's indent heuristic picks the
+/// position whose boundaries fall at blank lines and block edges.
+fn compact_changes(side: &mut Side, other: &mut Side) {
+    let mut g = Group::first(side);
+    let mut go = Group::first(other);
+    loop {
+        if g.end != g.start {
+            let mut earliest_end;
+            let mut end_matching_other;
+            let mut group_size;
+            loop {
+                group_size = g.end - g.start;
+                end_matching_other = None;
+                while g.slide_up(side) {
+                    go.previous(other);
+                }
+                earliest_end = g.end;
+                if go.end > go.start {
+                    end_matching_other = Some(g.end);
+                }
+                while g.slide_down(side) {
+                    go.next(other);
+                    if go.end > go.start {
+                        end_matching_other = Some(g.end);
+                    }
+                }
+                if group_size == g.end - g.start {
+                    break;
+                }
+            }
+            if g.end == earliest_end {
+                // The group can't move.
+            } else if end_matching_other.is_some() {
+                while go.end == go.start {
+                    g.slide_up(side);
+                    go.previous(other);
+                }
+            } else {
+                let mut shift = earliest_end
+                    .max((g.end - group_size).saturating_sub(1))
+                    .max(g.end.saturating_sub(INDENT_HEURISTIC_MAX_SLIDING));
+                let mut best: Option<(usize, SplitScore)> = None;
+                while shift <= g.end {
+                    let mut score = SplitScore::default();
+                    score.add(&measure_split(side.lines, shift as isize));
+                    score.add(&measure_split(
+                        side.lines,
+                        shift as isize - group_size as isize,
+                    ));
+                    if best.is_none_or(|(_, best_score)| score.cmp(&best_score) <= 0) {
+                        best = Some((shift, score));
+                    }
+                    shift += 1;
+                }
+                if let Some((best_shift, _)) = best {
+                    while g.end > best_shift {
+                        g.slide_up(side);
+                        go.previous(other);
+                    }
+                }
+            }
+        }
+        if !g.next(side) {
+            break;
+        }
+        go.next(other);
+    }
+}
+
+const MAX_INDENT: i32 = 200;
+const MAX_BLANKS: i32 = 20;
+const INDENT_HEURISTIC_MAX_SLIDING: usize = 100;
+
+/// Indentation of a line in columns (tabs to multiples of 8), or -1 if it is blank.
+fn get_indent(line: &[u8]) -> i32 {
+    let mut indent = 0;
+    for &c in line {
+        if !c.is_ascii_whitespace() && c != 0x0b {
+            return indent;
+        } else if c == b' ' {
+            indent += 1;
+        } else if c == b'\t' {
+            indent += 8 - indent % 8;
+        }
+        if indent >= MAX_INDENT {
+            return MAX_INDENT;
+        }
+    }
+    -1
+}
+
+/// What surrounds a split between lines `split - 1` and `split`.
+struct SplitMeasurement {
+    end_of_file: bool,
+    /// Indent of the line after the split, -1 if blank.
+    indent: i32,
+    /// Blank lines directly above the split.
+    pre_blank: i32,
+    /// Indent of the nearest non-blank line above, -1 if none.
+    pre_indent: i32,
+    /// Blank lines after the line following the split.
+    post_blank: i32,
+    /// Indent of the nearest non-blank line after that, -1 if none.
+    post_indent: i32,
+}
+
+fn measure_split(lines: &[&[u8]], split: isize) -> SplitMeasurement {
+    let n = lines.len() as isize;
+    let (end_of_file, indent) = if split >= n {
+        (true, -1)
+    } else {
+        (false, get_indent(lines[split as usize]))
+    };
+    let mut pre_blank = 0;
+    let mut pre_indent = -1;
+    let mut i = split - 1;
+    while i >= 0 {
+        pre_indent = get_indent(lines[i as usize]);
+        if pre_indent != -1 {
+            break;
+        }
+        pre_blank += 1;
+        if pre_blank == MAX_BLANKS {
+            pre_indent = 0;
+            break;
+        }
+        i -= 1;
+    }
+    let mut post_blank = 0;
+    let mut post_indent = -1;
+    let mut i = split + 1;
+    while i < n {
+        post_indent = get_indent(lines[i as usize]);
+        if post_indent != -1 {
+            break;
+        }
+        post_blank += 1;
+        if post_blank == MAX_BLANKS {
+            post_indent = 0;
+            break;
+        }
+        i += 1;
+    }
+    SplitMeasurement {
+        end_of_file,
+        indent,
+        pre_blank,
+        pre_indent,
+        post_blank,
+        post_indent,
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct SplitScore {
+    effective_indent: i32,
+    penalty: i32,
+}
+
+impl SplitScore {
+    /// Git's weights, tuned on a corpus of human-judged diffs.
+    fn add(&mut self, m: &SplitMeasurement) {
+        if m.pre_indent == -1 && m.pre_blank == 0 {
+            self.penalty += 1;
+        }
+        if m.end_of_file {
+            self.penalty += 21;
+        }
+        let post_blank = if m.indent == -1 { 1 + m.post_blank } else { 0 };
+        let total_blank = m.pre_blank + post_blank;
+        self.penalty += -30 * total_blank;
+        self.penalty += 6 * post_blank;
+        let indent = if m.indent != -1 {
+            m.indent
+        } else {
+            m.post_indent
+        };
+        let any_blanks = total_blank != 0;
+        self.effective_indent += indent;
+        if indent == -1 || m.pre_indent == -1 || indent == m.pre_indent {
+            // No adjustment.
+        } else if indent > m.pre_indent {
+            self.penalty += if any_blanks { 10 } else { -4 };
+        } else if m.post_indent != -1 && m.post_indent > indent {
+            // Less indented than before but more after: likely the start of a block.
+            self.penalty += if any_blanks { 17 } else { 24 };
+        } else {
+            // Likely the end of a block.
+            self.penalty += if any_blanks { 17 } else { 23 };
+        }
+    }
+
+    fn cmp(&self, other: &SplitScore) -> i32 {
+        let indents = (self.effective_indent > other.effective_indent) as i32
+            - (self.effective_indent < other.effective_indent) as i32;
+        60 * indents + (self.penalty - other.penalty)
+    }
+}
+
+/// A unified diff hunk: 0-based start and length on each side plus the ops it covers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub ops: Vec<DiffOp>,
+}
+
+/// Group an edit script into hunks with `context` unchanged lines around each change.
+/// Changes separated by no more than `2 * context` unchanged lines share a hunk.
+pub fn make_hunks(ops: &[DiffOp], context: usize) -> Vec<Hunk> {
+    let options = HunkOptions {
+        context,
+        ..HunkOptions::default()
+    };
+    make_hunks_with(ops, &[], &[], &options, |_| false, |_| false)
+}
+
+/// How [`make_hunks_with`] groups changes into hunks: `git diff -U<n>`,
+/// `--inter-hunk-context=<n>` and `-W`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HunkOptions {
+    /// The unchanged lines shown before and after each change.
+    pub context: usize,
+    /// How many more unchanged lines than the `2 * context` that would be shown
+    /// anyway can separate changes sharing a hunk.
+    pub inter_hunk_context: usize,
+    /// Show the whole function each change is in as its context.
+    pub function_context: bool,
+}
+
+impl Default for HunkOptions {
+    fn default() -> HunkOptions {
+        HunkOptions {
+            context: 3,
+            inter_hunk_context: 0,
+            function_context: false,
+        }
+    }
+}
+
+/// A run of changed lines in an edit script: the ops `ops`, which delete `deleted`
+/// lines from line `old_start` of the old side and insert `inserted` at line
+/// `new_start` of the new.
+struct Change {
+    ops: Range<usize>,
+    old_start: usize,
+    deleted: usize,
+    new_start: usize,
+    inserted: usize,
+    ignorable: bool,
+}
+
+impl Change {
+    fn old_end(&self) -> usize {
+        self.old_start + self.deleted
+    }
+
+    fn new_end(&self) -> usize {
+        self.new_start + self.inserted
+    }
+}
+
+/// Group an edit script turning `old` into `new` into hunks, as git's xdiff does.
+///
+/// The changes (runs of ops other than [`DiffOp::Equal`]) that `ignorable` picks, as
+/// `--ignore-blank-lines` does, are left out: one is only shown when it is less than
+/// `context` lines from a change that isn't, as part of that change's hunk, and
+/// doesn't join hunks otherwise.
+///
+/// With function context, a hunk starts at the function line (one `is_function`
+/// picks, and the lines above it up to a blank one) of the function its first change
+/// is in, and ends before the next function line after its last change, without the
+/// blank lines leading up to it; changes in the same function share the hunk. A
+/// change at the end of the old side that adds a whole function gets no more context
+/// than usual. `old` and `new` are only looked at for function context.
+pub fn make_hunks_with(
+    ops: &[DiffOp],
+    old: &[&[u8]],
+    new: &[&[u8]],
+    options: &HunkOptions,
+    ignorable: impl Fn(&[DiffOp]) -> bool,
+    is_function: impl Fn(&[u8]) -> bool,
+) -> Vec<Hunk> {
+    let mut changes = Vec::new();
+    let (mut i, mut old_line, mut new_line) = (0, 0, 0);
+    while i < ops.len() {
+        if matches!(ops[i], DiffOp::Equal { .. }) {
+            i += 1;
+            old_line += 1;
+            new_line += 1;
+            continue;
+        }
+        let start = i;
+        while i < ops.len() && !matches!(ops[i], DiffOp::Equal { .. }) {
+            i += 1;
+        }
+        let deleted = ops[start..i]
+            .iter()
+            .filter(|op| matches!(op, DiffOp::Delete { .. }))
+            .count();
+        let change = Change {
+            ops: start..i,
+            old_start: old_line,
+            deleted,
+            new_start: new_line,
+            inserted: i - start - deleted,
+            ignorable: ignorable(&ops[start..i]),
+        };
+        old_line = change.old_end();
+        new_line = change.new_end();
+        changes.push(change);
+    }
+    let (old_len, new_len) = (old_line, new_line);
+
+    let context = options.context;
+    let max_common = 2 * context + options.inter_hunk_context;
+    // The nearest function line to `start` towards `limit` (exclusive), on the old side.
+    let function_line = |start: isize, limit: isize| {
+        let step = if start > limit { -1 } else { 1 };
+        let mut line = start;
+        while line != limit && 0 <= line && line < old_len as isize {
+            if is_function(old[line as usize]) {
+                return line;
+            }
+            line += step;
+        }
+        -1
+    };
+    let mut hunks = Vec::new();
+    let mut next = 0;
+    loop {
+        // Ignorable changes too far before the next change aren't shown.
+        let candidate = next;
+        let mut first = next;
+        while next < changes.len() && changes[next].ignorable {
+            let end = changes[next].ops.end;
+            if changes
+                .get(next + 1)
+                .is_none_or(|following| following.ops.start - end >= context)
+            {
+                first = next + 1;
+            }
+            next += 1;
+        }
+        if first == changes.len() {
+            break;
+        }
+        // The last change of the hunk, and the lines inserted by ignorable changes
+        // since it.
+        let mut last = first;
+        let mut ignored = 0;
+        for k in first + 1..changes.len() {
+            let change = &changes[k];
+            let distance = change.ops.start - changes[k - 1].ops.end;
+            if distance > max_common {
+                break;
+            }
+            if distance < context && (!change.ignorable || last == k - 1) {
+                last = k;
+                ignored = 0;
+            } else if distance < context {
+                ignored += change.inserted;
+            } else if last != k - 1
+                && change.old_start + ignored - changes[last].old_end() > max_common
+            {
+                break;
+            } else if !change.ignorable {
+                last = k;
+                ignored = 0;
+            } else {
+                ignored += change.inserted;
+            }
+        }
+
+        let (mut old_start, mut new_start);
+        loop {
+            old_start = changes[first].old_start.saturating_sub(context);
+            new_start = changes[first].new_start.saturating_sub(context);
+            if !options.function_context {
+                break;
+            }
+            let mut at = changes[first].old_start;
+            if at >= old_len {
+                // A whole function added at the end needs nothing above it.
+                if (changes[first].new_start..new_len).any(|line| is_function(new[line])) {
+                    break;
+                }
+                at = old_len.saturating_sub(1);
+            }
+            let mut function_start = function_line(at as isize, -1);
+            while function_start > 0
+                && !is_blank_line(old[function_start as usize - 1])
+                && !is_function(old[function_start as usize - 1])
+            {
+                function_start -= 1;
+            }
+            let function_start = function_start.max(0) as usize;
+            if function_start >= old_start {
+                break;
+            }
+            new_start = new_start.saturating_sub(old_start - function_start);
+            old_start = function_start;
+            // Context reaching up into an ignored change shows it after all.
+            let mut shown = candidate;
+            while shown != first
+                && changes[shown].old_end() <= old_start
+                && changes[shown].new_end() <= new_start
+            {
+                shown += 1;
+            }
+            if shown == first {
+                break;
+            }
+            first = shown;
+        }
+
+        let (mut old_end, mut new_end);
+        loop {
+            let change = &changes[last];
+            let after = context
+                .min(old_len - change.old_end())
+                .min(new_len - change.new_end());
+            old_end = change.old_end() + after;
+            new_end = change.new_end() + after;
+            if !options.function_context {
+                break;
+            }
+            let mut function_end = function_line(change.old_end() as isize, old_len as isize);
+            while function_end > 0 && is_blank_line(old[function_end as usize - 1]) {
+                function_end -= 1;
+            }
+            let function_end = match function_end {
+                ..0 => old_len,
+                end => end as usize,
+            };
+            if function_end > old_end {
+                new_end = (new_end + function_end - old_end).min(new_len);
+                old_end = function_end;
+            }
+            // A change in the same function joins the hunk.
+            let Some(following) = changes.get(last + 1) else {
+                break;
+            };
+            let line = following.old_start.min(old_len.saturating_sub(1)) as isize;
+            if line - (context as isize) <= old_end as isize
+                || function_line(line, old_end as isize) < 0
+            {
+                last += 1;
+                continue;
+            }
+            break;
+        }
+        next = last + 1;
+
+        let start = changes[first].ops.start - (changes[first].old_start - old_start);
+        let end = changes[last].ops.end + (old_end - changes[last].old_end());
+        hunks.push(Hunk {
+            old_start,
+            old_len: old_end - old_start,
+            new_start,
+            new_len: new_end - new_start,
+            ops: ops[start..end].to_vec(),
+        });
+    }
+    hunks
+}
+
+impl Hunk {
+    /// The hunk body as (prefix, line) pairs, the prefix being ' ', '-' or '+'.
+    /// Context lines are the new side's, which differ from the old only in whitespace
+    /// that was ignored.
+    pub fn lines<'a>(&self, old: &[&'a [u8]], new: &[&'a [u8]]) -> Vec<(u8, &'a [u8])> {
+        self.ops
+            .iter()
+            .map(|op| match *op {
+                DiffOp::Equal { new: j, .. } => (b' ', new[j]),
+                DiffOp::Delete { old: i } => (b'-', old[i]),
+                DiffOp::Insert { new: j } => (b'+', new[j]),
+            })
+            .collect()
+    }
+}
+
+/// Split into lines, each keeping its trailing `\n` (the last may have none).
+pub fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Git's heuristic: a blob is binary if its first 8000 bytes contain a NUL.
+pub fn is_binary(data: &[u8]) -> bool {
+    data.iter().take(8000).any(|&b| b == 0)
+}
+
+/// Whether a change from `old` to `new` is shown as binary: as the file's diff driver
+/// says (`-diff`, as the `binary` macro sets, for binary), or, if it doesn't, whether
+/// either side [`is_binary`].
+fn is_binary_change(driver: &DiffDriver, old: &[u8], new: &[u8]) -> bool {
+    driver
+        .binary
+        .unwrap_or_else(|| is_binary(old) || is_binary(new))
+}
+
+/// One side of a changed path: its mode (as written in trees) and object id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffSide {
+    pub mode: String,
+    pub hash: ObjectId,
+}
+
+/// A path whose entry differs between two trees. `old` is `None` for an addition and
+/// `new` is `None` for a deletion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeChange {
+    pub path: String,
+    pub old: Option<DiffSide>,
+    pub new: Option<DiffSide>,
+}
+
+/// Recursively compare two trees (either may be absent, i.e. empty), yielding changed
+/// non-tree entries in Git's path order. Identical subtrees are skipped without being
+/// read. An entry that turns from a file into a directory (or back) is reported as a
+/// deletion plus additions, as in git.
+pub fn diff_trees(
+    repo: &Repository,
+    old: Option<&ObjectId>,
+    new: Option<&ObjectId>,
+) -> Result<Vec<TreeChange>> {
+    let mut changes = Vec::new();
+    diff_trees_into(repo, old, new, "", false, &mut changes)?;
+    Ok(changes)
+}
+
+/// [`diff_trees`], for writing `new` into the working tree: an entry of `new` read on
+/// the way whose name [`check_entry_name`] refuses is an error, as `git checkout`
+/// refuses an invalid path, whatever other trees hold.
+pub fn diff_trees_to_check_out(
+    repo: &Repository,
+    old: Option<&ObjectId>,
+    new: Option<&ObjectId>,
+) -> Result<Vec<TreeChange>> {
+    let mut changes = Vec::new();
+    diff_trees_into(repo, old, new, "", true, &mut changes)?;
+    Ok(changes)
+}
+
+fn read_tree(repo: &Repository, hash: Option<&ObjectId>) -> Result<Vec<TreeEntry>> {
+    match hash {
+        Some(hash) => {
+            let object = Object::read_git_object(repo, &hash.to_string())?;
+            anyhow::ensure!(object.kind == ObjectKind::Tree, "{hash} is not a tree");
+            read_tree_entries(repo, object)
+        }
+        None => Ok(Vec::new()),
+    }
+}
+
+fn diff_trees_into(
+    repo: &Repository,
+    old: Option<&ObjectId>,
+    new: Option<&ObjectId>,
+    prefix: &str,
+    check_names: bool,
+    changes: &mut Vec<TreeChange>,
+) -> Result<()> {
+    if old.is_some() && old == new {
+        return Ok(());
+    }
+    let old_entries = read_tree(repo, old)?;
+    let new_entries = read_tree(repo, new)?;
+    if check_names {
+        for entry in &new_entries {
+            if check_entry_name(entry.name.as_bytes()).is_err() {
+                bail!("invalid path '{prefix}{}'", entry.name);
+            }
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < old_entries.len() || j < new_entries.len() {
+        let order = match (old_entries.get(i), new_entries.get(j)) {
+            (Some(a), Some(b)) => compare_tree_entry_names(
+                a.name.as_bytes(),
+                a.kind()? == ObjectKind::Tree,
+                b.name.as_bytes(),
+                b.kind()? == ObjectKind::Tree,
+            ),
+            (Some(_), None) => Ordering::Less,
+            _ => Ordering::Greater,
+        };
+        let old_entry = (order != Ordering::Greater).then(|| &old_entries[i]);
+        let new_entry = (order != Ordering::Less).then(|| &new_entries[j]);
+        if old_entry.is_some() {
+            i += 1;
+        }
+        if new_entry.is_some() {
+            j += 1;
+        }
+        let name = old_entry.or(new_entry).map(|e| e.name.as_str()).unwrap();
+        let path = format!("{prefix}{name}");
+        let is_tree = |e: Option<&TreeEntry>| -> Result<bool> {
+            Ok(match e {
+                Some(e) => e.kind()? == ObjectKind::Tree,
+                None => false,
+            })
+        };
+        let (old_tree, new_tree) = (is_tree(old_entry)?, is_tree(new_entry)?);
+        // A file and a directory of the same name never compare equal, so at most
+        // one side is present when either is a tree.
+        if old_tree || new_tree {
+            diff_trees_into(
+                repo,
+                old_entry.map(|e| &e.hash),
+                new_entry.map(|e| &e.hash),
+                &format!("{path}/"),
+                check_names,
+                changes,
+            )?;
+        }
+        if !old_tree && !new_tree {
+            let side = |e: &TreeEntry| DiffSide {
+                mode: e.mode.clone(),
+                hash: e.hash,
+            };
+            let old_side = old_entry.map(side);
+            let new_side = new_entry.map(side);
+            if old_side != new_side {
+                changes.push(TreeChange {
+                    path,
+                    old: old_side,
+                    new: new_side,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether anything under `paths` (files or directories, from the top of the tree)
+/// differs between two trees, either of which may be absent. Only the subtrees on the
+/// way to the paths are read.
+pub fn trees_differ_under(
+    repo: &Repository,
+    old: Option<&ObjectId>,
+    new: Option<&ObjectId>,
+    paths: &[String],
+) -> Result<bool> {
+    for path in paths {
+        let path = path
+            .strip_prefix("./")
+            .unwrap_or(path)
+            .trim_end_matches('/');
+        if path.is_empty() || path == "." {
+            if old != new {
+                return Ok(true);
+            }
+        } else if tree_entry_at(repo, old, path)? != tree_entry_at(repo, new, path)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// The mode and id of the entry at `path` in the tree `tree`, if it has one.
+pub fn tree_entry_at(
+    repo: &Repository,
+    tree: Option<&ObjectId>,
+    path: &str,
+) -> Result<Option<(String, ObjectId)>> {
+    let Some(mut tree) = tree.copied() else {
+        return Ok(None);
+    };
+    let mut components = path.split('/').peekable();
+    while let Some(name) = components.next() {
+        let Some(entry) = read_tree(repo, Some(&tree))?
+            .into_iter()
+            .find(|e| e.name == name)
+        else {
+            return Ok(None);
+        };
+        if components.peek().is_none() {
+            return Ok(Some((entry.mode, entry.hash)));
+        }
+        if entry.kind()? != ObjectKind::Tree {
+            return Ok(None);
+        }
+        tree = entry.hash;
+    }
+    Ok(None)
+}
+
+/// How [`git_diff`] shows what it compares.
+#[derive(Debug, Default, Clone)]
+pub struct DiffOptions {
+    /// Compare the index with a commit (HEAD unless one is given) instead of the
+    /// working directory with the index.
+    pub cached: bool,
+    /// Show changes to binary files as patches `git apply` can apply, rather than
+    /// only saying that they differ.
+    pub binary: bool,
+    /// List the whitespace errors added lines introduce instead of the diff.
+    pub check: bool,
+    /// Always show patches, never running the external diff program of
+    /// `GIT_EXTERNAL_DIFF`, `diff.external` or a diff driver.
+    pub no_ext_diff: bool,
+    /// The differences in whitespace that aren't shown as changes.
+    pub ignore_whitespace: IgnoreWhitespace,
+    /// The unchanged lines around each change (`-U`); `diff.context`, or 3, if `None`.
+    pub context: Option<usize>,
+    /// How many more unchanged lines can separate changes in a hunk (see
+    /// [`HunkOptions`]); `diff.interHunkContext`, or 0, if `None`.
+    pub inter_hunk_context: Option<usize>,
+    /// Show the whole function around each change.
+    pub function_context: bool,
+    /// List the changed files with how many lines were added and deleted in each
+    /// (`--stat`), instead of the patches.
+    pub stat: bool,
+    /// With `stat`, leave out the line per file and keep only the totals
+    /// (`--shortstat`), as `git commit` shows them.
+    pub shortstat: bool,
+    /// With `stat`, show the patches too, after the list.
+    pub patch: bool,
+    /// With `stat`, follow the list with the files created or deleted and the modes
+    /// changed (`--summary`), as `git merge` shows them.
+    pub summary: bool,
+}
+
+/// One side of a file in a patch: its mode, its id, and what it holds if that was
+/// read from the working directory; what is in the object store is read only once
+/// the file turns out to have changed.
+struct PatchSide {
+    mode: String,
+    hash: ObjectId,
+    data: Option<Vec<u8>>,
+}
+
+impl PatchSide {
+    /// The side `side` of a tree or the index.
+    fn stored(side: &DiffSide) -> PatchSide {
+        PatchSide {
+            mode: side.mode.clone(),
+            hash: side.hash,
+            data: None,
+        }
+    }
+
+    /// What the side of a patch holds; nothing for a missing one.
+    fn content<'a>(repo: &Repository, side: Option<&'a PatchSide>) -> Result<Cow<'a, [u8]>> {
+        Ok(match side {
+            None => Cow::Borrowed(&[]),
+            Some(side) if side.mode == "160000" => Cow::Owned(subproject_content(&side.hash)),
+            Some(PatchSide {
+                data: Some(data), ..
+            }) => Cow::Borrowed(data),
+            Some(side) => {
+                Cow::Owned(Object::read_git_object(repo, &side.hash.to_string())?.read_all()?)
+            }
+        })
+    }
+
+    /// The file at `path` in the working directory, or `None` if there is none. If
+    /// its stat data matches `entry`, it is taken to be the entry without being read.
+    fn from_worktree(
+        repo: &Repository,
+        index: &Index,
+        path: &str,
+        entry: Option<&IndexEntry>,
+    ) -> Result<Option<PatchSide>> {
+        let file = repo.path(path);
+        let Ok(metadata) = symlink_metadata(&file) else {
+            return Ok(None);
+        };
+        if metadata.is_dir() && submodule_git_dir(&file).is_none() {
+            return Ok(None);
+        }
+        if let Some(entry) = entry.filter(|entry| index.is_stat_clean(repo, entry, &metadata)) {
+            return Ok(Some(PatchSide::stored(&index_side(entry))));
+        }
+        let mode = worktree_mode(repo, &metadata, entry.map(|entry| entry.mode));
+        let (hash, data) = match mode {
+            0o160000 => match submodule_head(&file)? {
+                Some(head) => (head, None),
+                // A submodule that isn't checked out is taken to be what is recorded.
+                None => match entry {
+                    Some(entry) => (entry.id, None),
+                    None => return Ok(None),
+                },
+            },
+            _ => {
+                let data = match mode {
+                    0o120000 => read_symlink(repo, &file)?,
+                    _ => read(&file).with_context(|| format!("failed to read {path}"))?,
+                };
+                let mut object = Object {
+                    reader: Cursor::new(&data),
+                    kind: ObjectKind::Blob,
+                    expected_size: data.len() as u64,
+                };
+                (object.write(repo.hash_algorithm()?, sink())?, Some(data))
+            }
+        };
+        Ok(Some(PatchSide {
+            mode: format!("{mode:o}"),
+            hash,
+            data,
+        }))
+    }
+
+    /// `"100644"` for a file, as opposed to a symlink or submodule: sides of
+    /// different types are shown as one file deleted and another added.
+    fn file_type(&self) -> &str {
+        &self.mode[..self.mode.len().saturating_sub(4)]
+    }
+}
+
+/// What [`git_diff`] shows for one path.
+enum FileDiff {
+    Changed {
+        path: String,
+        old: Option<PatchSide>,
+        new: Option<PatchSide>,
+    },
+    /// A path with conflicts in the index, which isn't compared.
+    Unmerged(String),
+}
+
+/// `git diff [<commit> [<commit>]] [-- <path>...]`: show, as a unified diff, the
+/// changes in the working directory not yet staged in the index, with `cached` those
+/// staged compared to HEAD or the given commit, with one commit those in the working
+/// directory since that commit, and with two (or `A..B`) the changes between them.
+///
+/// `args` are revisions until one that isn't, which with the rest counts as a path if
+/// the file exists, as git guesses; `paths` are what comes after `--`. Only files
+/// under the given paths are compared. Returns the exit status: with `check`, 2 if an
+/// added line has a whitespace error.
+pub fn git_diff(
+    repo: &Repository,
+    config: &Config,
+    args: &[String],
+    paths: &[String],
+    options: &DiffOptions,
+) -> Result<i32> {
+    let mut revisions = Vec::new();
+    let mut pathspecs = Vec::new();
+    for arg in args {
+        if pathspecs.is_empty() && is_revision(repo, arg) {
+            revisions.push(arg.as_str());
+            continue;
+        }
+        let path = repo.pathspec(arg)?;
+        if !repo.path(&path).exists() {
+            bail!(
+                "ambiguous argument '{arg}': unknown revision or path not in the working tree.\n\
+                 Use '--' to separate paths from revisions, like this:\n\
+                 'git <command> [<revision>...] -- [<file>...]'"
+            );
+        }
+        pathspecs.push(path);
+    }
+    pathspecs.extend(paths.iter().cloned());
+    let wanted = |path: &str| {
+        pathspecs.is_empty()
+            || pathspecs.iter().any(|spec| {
+                let spec = spec
+                    .strip_prefix("./")
+                    .unwrap_or(spec)
+                    .trim_end_matches('/');
+                spec == "." || path == spec || path.starts_with(&format!("{spec}/"))
+            })
+    };
+
+    if let [range] = revisions[..]
+        && let Some((from, to)) = range.split_once("..")
+    {
+        if to.starts_with('.') {
+            bail!("A...B (changes since the merge base) isn't supported");
+        }
+        revisions = vec![
+            if from.is_empty() { "HEAD" } else { from },
+            if to.is_empty() { "HEAD" } else { to },
+        ];
+    }
+    let diffs = match (revisions.as_slice(), options.cached) {
+        ([], false) => worktree_diffs(repo, &wanted)?,
+        ([], true) => match resolve_ref(repo, "HEAD")? {
+            Some(_) => index_diffs(repo, Some("HEAD"), &wanted)?,
+            None => index_diffs(repo, None, &wanted)?,
+        },
+        ([commit], true) => index_diffs(repo, Some(commit), &wanted)?,
+        ([commit], false) => commit_worktree_diffs(repo, commit, &wanted)?,
+        ([old, new], false) => tree_diffs(repo, old, new, &wanted)?,
+        ([_, _], true) => bail!("--cached compares the index with one commit, not two"),
+        _ => bail!("comparing more than two commits isn't supported"),
+    };
+    print_diffs(repo, config, diffs, options)
+}
+
+fn is_revision(repo: &Repository, arg: &str) -> bool {
+    arg.contains("..") || Object::peel_to(repo, arg, ObjectKind::Tree).is_ok()
+}
+
+/// The files of the tree `tree_ish` names, by path.
+fn tree_files(repo: &Repository, tree_ish: &str) -> Result<BTreeMap<String, DiffSide>> {
+    let (tree, _) = Object::peel_to(repo, tree_ish, ObjectKind::Tree)?;
+    Ok(diff_trees(repo, None, Some(&tree.parse()?))?
+        .into_iter()
+        .filter_map(|change| Some((change.path, change.new?)))
+        .collect())
+}
+
+fn index_side(entry: &IndexEntry) -> DiffSide {
+    DiffSide {
+        mode: format!("{:o}", entry.mode),
+        hash: entry.id,
+    }
+}
+
+/// Show the changes between the commits (or trees) `old` and `new`, as [`git_diff`]
+/// shows them, to print the changes another command recorded in commits.
+pub fn print_tree_diff(
+    repo: &Repository,
+    config: &Config,
+    old: &str,
+    new: &str,
+    options: &DiffOptions,
+) -> Result<i32> {
+    print_diffs(
+        repo,
+        config,
+        tree_diffs(repo, old, new, &|_| true)?,
+        options,
+    )
+}
+
+/// The changes between the commits `old` and `new`.
+fn tree_diffs(
+    repo: &Repository,
+    old: &str,
+    new: &str,
+    wanted: &dyn Fn(&str) -> bool,
+) -> Result<Vec<FileDiff>> {
+    let (old, _) = Object::peel_to(repo, old, ObjectKind::Tree)?;
+    let (new, _) = Object::peel_to(repo, new, ObjectKind::Tree)?;
+    diff_trees(repo, Some(&old.parse()?), Some(&new.parse()?))?
+        .into_iter()
+        .filter(|change| wanted(&change.path))
+        .map(|change| {
+            Ok(FileDiff::Changed {
+                old: change.old.as_ref().map(PatchSide::stored),
+                new: change.new.as_ref().map(PatchSide::stored),
+                path: change.path,
+            })
+        })
+        .collect()
+}
+
+/// The changes in the working directory to the files in the index.
+fn worktree_diffs(repo: &Repository, wanted: &dyn Fn(&str) -> bool) -> Result<Vec<FileDiff>> {
+    let index = Index::load(repo)?;
+    let mut diffs = Vec::new();
+    for (n, entry) in index.entries.iter().enumerate() {
+        if !wanted(&entry.path) {
+            continue;
+        }
+        if entry.stage != 0 {
+            if n == 0 || index.entries[n - 1].path != entry.path {
+                diffs.push(FileDiff::Unmerged(entry.path.clone()));
+            }
+            continue;
+        }
+        let new = PatchSide::from_worktree(repo, &index, &entry.path, Some(entry))?;
+        if new
+            .as_ref()
+            .is_some_and(|new| new.hash == entry.id && new.mode == format!("{:o}", entry.mode))
+        {
+            continue;
+        }
+        diffs.push(FileDiff::Changed {
+            path: entry.path.clone(),
+            old: Some(PatchSide::stored(&index_side(entry))),
+            new,
+        });
+    }
+    Ok(diffs)
+}
+
+/// The changes staged in the index since the commit `tree_ish` (or, for `None`, an
+/// unborn branch, since nothing).
+fn index_diffs(
+    repo: &Repository,
+    tree_ish: Option<&str>,
+    wanted: &dyn Fn(&str) -> bool,
+) -> Result<Vec<FileDiff>> {
+    let index = Index::load(repo)?;
+    let mut tree = match tree_ish {
+        Some(tree_ish) => tree_files(repo, tree_ish)?,
+        None => BTreeMap::new(),
+    };
+    let mut diffs: BTreeMap<String, FileDiff> = BTreeMap::new();
+    for entry in &index.entries {
+        let old = tree.remove(&entry.path);
+        if !wanted(&entry.path) {
+            continue;
+        }
+        if entry.stage != 0 {
+            diffs.insert(entry.path.clone(), FileDiff::Unmerged(entry.path.clone()));
+            continue;
+        }
+        let new = index_side(entry);
+        if old.as_ref() == Some(&new) {
+            continue;
+        }
+        diffs.insert(
+            entry.path.clone(),
+            FileDiff::Changed {
+                path: entry.path.clone(),
+                old: old.as_ref().map(PatchSide::stored),
+                new: Some(PatchSide::stored(&new)),
+            },
+        );
+    }
+    for (path, old) in tree.into_iter().filter(|(path, _)| wanted(path)) {
+        diffs.insert(
+            path.clone(),
+            FileDiff::Changed {
+                path,
+                old: Some(PatchSide::stored(&old)),
+                new: None,
+            },
+        );
+    }
+    Ok(diffs.into_values().collect())
+}
+
+/// The changes in the working directory since the commit `tree_ish`, to the files it
+/// or the index has.
+fn commit_worktree_diffs(
+    repo: &Repository,
+    tree_ish: &str,
+    wanted: &dyn Fn(&str) -> bool,
+) -> Result<Vec<FileDiff>> {
+    let index = Index::load(repo)?;
+    let mut tree = tree_files(repo, tree_ish)?;
+    let mut diffs: BTreeMap<String, FileDiff> = BTreeMap::new();
+    for entry in &index.entries {
+        let old = tree.remove(&entry.path);
+        if !wanted(&entry.path) || diffs.contains_key(&entry.path) {
+            continue;
+        }
+        // A conflicted file is compared as it is, with no entry to trust its stat data.
+        let tracked = Some(entry).filter(|entry| entry.stage == 0);
+        let new = PatchSide::from_worktree(repo, &index, &entry.path, tracked)?;
+        let unchanged = match (&old, &new) {
+            (Some(old), Some(new)) => old.hash == new.hash && old.mode == new.mode,
+            (None, None) => true,
+            _ => false,
+        };
+        if unchanged {
+            continue;
+        }
+        diffs.insert(
+            entry.path.clone(),
+            FileDiff::Changed {
+                path: entry.path.clone(),
+                old: old.as_ref().map(PatchSide::stored),
+                new,
+            },
+        );
+    }
+    // What the index doesn't have counts as deleted, whatever is in its place.
+    for (path, old) in tree.into_iter().filter(|(path, _)| wanted(path)) {
+        diffs.insert(
+            path.clone(),
+            FileDiff::Changed {
+                path,
+                old: Some(PatchSide::stored(&old)),
+                new: None,
+            },
+        );
+    }
+    Ok(diffs.into_values().collect())
+}
+
+fn print_diffs(
+    repo: &Repository,
+    config: &Config,
+    diffs: Vec<FileDiff>,
+    options: &DiffOptions,
+) -> Result<i32> {
+    let mut out = BufWriter::new(stdout().lock());
+    let rule = WhitespaceRule::from_config(config)?;
+    let attributes = Attributes::load(repo, config)?;
+    let external = var("GIT_EXTERNAL_DIFF")
+        .ok()
+        .or_else(|| config.get("diff.external").map(str::to_string))
+        .filter(|command| !command.is_empty());
+    let hunk_options = HunkOptions {
+        context: match options.context {
+            Some(context) => context,
+            None => config_lines(config, "diff.context")?.unwrap_or(3),
+        },
+        inter_hunk_context: match options.inter_hunk_context {
+            Some(lines) => lines,
+            None => config_lines(config, "diff.interHunkContext")?.unwrap_or(0),
+        },
+        function_context: options.function_context,
+    };
+    if options.stat && !options.check {
+        let mut stats = Vec::new();
+        for diff in &diffs {
+            let (path, old, new) = match diff {
+                FileDiff::Unmerged(path) => {
+                    stats.push(FileStat {
+                        path: path.clone(),
+                        change: StatChange::Unmerged,
+                    });
+                    continue;
+                }
+                FileDiff::Changed { path, old, new } => (path, old, new),
+            };
+            let driver = DiffDriver::for_path(config, &attributes, path)?;
+            for (old, new) in split_type_change(old.as_ref(), new.as_ref()) {
+                let change = stat_change(repo, &driver, old, new, &options.ignore_whitespace)?;
+                stats.push(FileStat {
+                    path: path.clone(),
+                    change,
+                });
+            }
+        }
+        write_stat(&mut out, &stats, options.shortstat)?;
+        if options.summary {
+            write_summary(&mut out, &diffs)?;
+        }
+        if !options.patch {
+            out.flush()?;
+            return Ok(0);
+        }
+        if !stats.is_empty() {
+            writeln!(out)?;
+        }
+    }
+    let total = diffs.len();
+    let mut counter = 0;
+    let mut status = 0;
+    for diff in &diffs {
+        let (path, old, new) = match diff {
+            FileDiff::Unmerged(path) => {
+                if !options.check {
+                    writeln!(out, "* Unmerged path {path}")?;
+                }
+                continue;
+            }
+            FileDiff::Changed { path, old, new } => (path, old, new),
+        };
+        let driver = DiffDriver::for_path(config, &attributes, path)?;
+        let command = match options.no_ext_diff {
+            true => None,
+            false => driver.command.as_deref().or(external.as_deref()),
+        };
+        for (old, new) in split_type_change(old.as_ref(), new.as_ref()) {
+            if options.check {
+                if check_whitespace(repo, &mut out, &rule, &driver, path, old, new)? {
+                    status = 2;
+                }
+            } else if let Some(command) = command {
+                counter += 1;
+                // What the program prints goes between the patches around it.
+                out.flush()?;
+                run_external_diff(repo, command, path, old, new, counter, total)?;
+            } else {
+                write_patch(
+                    repo,
+                    &mut out,
+                    &driver,
+                    path,
+                    old,
+                    new,
+                    options,
+                    &hunk_options,
+                )?;
+            }
+        }
+    }
+    out.flush()?;
+    Ok(status)
+}
+
+/// The sides of a change as patches show it: a file whose type changes (e.g. to a
+/// symlink) is deleted, then one of the new type added.
+fn split_type_change<'a>(
+    old: Option<&'a PatchSide>,
+    new: Option<&'a PatchSide>,
+) -> Vec<(Option<&'a PatchSide>, Option<&'a PatchSide>)> {
+    match (old, new) {
+        (Some(old), Some(new)) if old.file_type() != new.file_type() => {
+            vec![(Some(old), None), (None, Some(new))]
+        }
+        sides => vec![sides],
+    }
+}
+
+/// How many columns `--stat` fills, as git's does when not writing to a terminal.
+const STAT_WIDTH: usize = 80;
+
+/// `--summary`: a line for each file created or deleted or whose mode changed.
+fn write_summary(out: &mut impl Write, diffs: &[FileDiff]) -> Result<()> {
+    for diff in diffs {
+        let FileDiff::Changed { path, old, new } = diff else {
+            continue;
+        };
+        match (old, new) {
+            (None, Some(new)) => writeln!(out, " create mode {} {path}", new.mode)?,
+            (Some(old), None) => writeln!(out, " delete mode {} {path}", old.mode)?,
+            (Some(old), Some(new)) if old.mode != new.mode => {
+                writeln!(out, " mode change {} => {} {path}", old.mode, new.mode)?
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// One line of `--stat`.
+struct FileStat {
+    path: String,
+    change: StatChange,
+}
+
+enum StatChange {
+    /// The lines added and deleted.
+    Text {
+        added: usize,
+        deleted: usize,
+    },
+    /// The sizes of the old and new content.
+    Binary {
+        old_size: usize,
+        new_size: usize,
+    },
+    Unmerged,
+}
+
+/// What `--stat` counts of the change from `old` to `new`.
+fn stat_change(
+    repo: &Repository,
+    driver: &DiffDriver,
+    old: Option<&PatchSide>,
+    new: Option<&PatchSide>,
+    ignore: &IgnoreWhitespace,
+) -> Result<StatChange> {
+    let null = repo.hash_algorithm()?.null_id();
+    if old.map_or(null, |side| side.hash) == new.map_or(null, |side| side.hash) {
+        return Ok(StatChange::Text {
+            added: 0,
+            deleted: 0,
+        });
+    }
+    let old_data = PatchSide::content(repo, old)?;
+    let new_data = PatchSide::content(repo, new)?;
+    if is_binary_change(driver, &old_data, &new_data) {
+        return Ok(StatChange::Binary {
+            old_size: old_data.len(),
+            new_size: new_data.len(),
+        });
+    }
+    let ops = diff_lines_ignoring(&split_lines(&old_data), &split_lines(&new_data), ignore);
+    let count = |f: fn(&DiffOp) -> bool| ops.iter().filter(|op| f(op)).count();
+    Ok(StatChange::Text {
+        added: count(|op| matches!(op, DiffOp::Insert { .. })),
+        deleted: count(|op| matches!(op, DiffOp::Delete { .. })),
+    })
+}
+
+/// Write `stats` as `git diff --stat` does: a line per file with its name, the number
+/// of lines changed and a graph of `+` and `-`, then a summary. Names and graphs
+/// share [`STAT_WIDTH`] columns as in git: a long name is cut at a `/` behind `...`
+/// and graphs are scaled to fit. With `short`, only the summary is written.
+fn write_stat(out: &mut impl Write, stats: &[FileStat], short: bool) -> Result<()> {
+    if stats.is_empty() {
+        return Ok(());
+    }
+    let decimal_width = |n: usize| n.to_string().len();
+    let mut max_len = 0;
+    let mut max_change = 0;
+    let mut bin_width = 0;
+    let mut number_width = 0;
+    for stat in stats {
+        max_len = max_len.max(stat.path.chars().count());
+        match stat.change {
+            StatChange::Text { added, deleted } => max_change = max_change.max(added + deleted),
+            StatChange::Binary { old_size, new_size } => {
+                // "Bin XXX -> YYY bytes"
+                bin_width = bin_width.max(14 + decimal_width(old_size) + decimal_width(new_size));
+                number_width = 3;
+            }
+            StatChange::Unmerged => {}
+        }
+    }
+    let number_width = number_width.max(decimal_width(max_change)) as isize;
+    let width = (STAT_WIDTH as isize).max(16 + 6 + number_width);
+    let mut graph_width = match max_change + 4 > bin_width {
+        true => max_change,
+        false => bin_width - 4,
+    } as isize;
+    let mut name_width = max_len as isize;
+    if name_width + number_width + 6 + graph_width > width {
+        if graph_width > width * 3 / 8 - number_width - 6 {
+            graph_width = (width * 3 / 8 - number_width - 6).max(6);
+        }
+        if name_width > width - number_width - 6 - graph_width {
+            name_width = width - number_width - 6 - graph_width;
+        } else {
+            graph_width = width - number_width - 6 - name_width;
+        }
+    }
+    let (name_width, number_width) = (name_width as usize, number_width as usize);
+    let graph_width = graph_width as usize;
+    let scale = |n: usize| match n {
+        0 => 0,
+        n => 1 + n * (graph_width - 1) / max_change,
+    };
+
+    let (mut files, mut insertions, mut deletions) = (0, 0, 0);
+    for stat in stats {
+        if short {
+            match stat.change {
+                StatChange::Unmerged => continue,
+                StatChange::Binary { .. } => {}
+                StatChange::Text { added, deleted } => {
+                    insertions += added;
+                    deletions += deleted;
+                }
+            }
+            files += 1;
+            continue;
+        }
+        let mut name = stat.path.as_str();
+        let mut len = name_width;
+        let mut prefix = "";
+        if name.chars().count() > name_width {
+            prefix = "...";
+            len = name_width.saturating_sub(3);
+            let skip = name.chars().count() - len;
+            name = &name[name.char_indices().nth(skip).map_or(name.len(), |(i, _)| i)..];
+            if let Some(slash) = name.find('/') {
+                name = &name[slash..];
+            }
+        }
+        write!(out, " {prefix}{name:<len$} |")?;
+        match stat.change {
+            StatChange::Unmerged => {
+                writeln!(out, " Unmerged")?;
+                continue;
+            }
+            StatChange::Binary { old_size, new_size } => {
+                write!(out, " {:>number_width$}", "Bin")?;
+                match old_size == 0 && new_size == 0 {
+                    true => writeln!(out)?,
+                    false => writeln!(out, " {old_size} -> {new_size} bytes")?,
+                }
+            }
+            StatChange::Text { added, deleted } => {
+                let (mut plus, mut minus) = (added, deleted);
+                if graph_width <= max_change {
+                    let mut total = scale(added + deleted);
+                    if total < 2 && added > 0 && deleted > 0 {
+                        total = 2;
+                    }
+                    if added < deleted {
+                        plus = scale(added);
+                        minus = total - plus;
+                    } else {
+                        minus = scale(deleted);
+                        plus = total - minus;
+                    }
+                }
+                let changed = added + deleted;
+                let space = if changed > 0 { " " } else { "" };
+                writeln!(
+                    out,
+                    " {changed:>number_width$}{space}{}{}",
+                    "+".repeat(plus),
+                    "-".repeat(minus)
+                )?;
+                insertions += added;
+                deletions += deleted;
+            }
+        }
+        files += 1;
+    }
+    let plural = |n: usize| if n == 1 { "" } else { "s" };
+    write!(out, " {files} file{} changed", plural(files))?;
+    if insertions > 0 || deletions == 0 {
+        write!(out, ", {insertions} insertion{}(+)", plural(insertions))?;
+    }
+    if deletions > 0 || insertions == 0 {
+        write!(out, ", {deletions} deletion{}(-)", plural(deletions))?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// A count of lines set in `key`, such as `diff.context`, which can't be negative.
+fn config_lines(config: &Config, key: &str) -> Result<Option<usize>> {
+    let Some(value) = config.get(key) else {
+        return Ok(None);
+    };
+    match value.parse::<i64>() {
+        Ok(lines) if lines >= 0 => Ok(Some(lines as usize)),
+        Ok(_) => bail!("bad config variable '{key}'"),
+        Err(_) => bail!("bad numeric config value '{value}' for '{key}': invalid unit"),
+    }
+}
+
+/// Write the `git diff` form of the change to `path` from `old` to `new`.
+#[allow(clippy::too_many_arguments)]
+fn write_patch(
+    repo: &Repository,
+    out: &mut impl Write,
+    driver: &DiffDriver,
+    path: &str,
+    old: Option<&PatchSide>,
+    new: Option<&PatchSide>,
+    options: &DiffOptions,
+    hunk_options: &HunkOptions,
+) -> Result<()> {
+    // The header is only shown with a hunk, unless it says more than that the
+    // content changed: ignoring whitespace can leave nothing to show.
+    let mut header = Vec::new();
+    writeln!(header, "diff --git a/{path} b/{path}")?;
+    let must_show_header = match (old, new) {
+        (None, Some(new)) => {
+            writeln!(header, "new file mode {}", new.mode)?;
+            true
+        }
+        (Some(old), None) => {
+            writeln!(header, "deleted file mode {}", old.mode)?;
+            true
+        }
+        (Some(old), Some(new)) if old.mode != new.mode => {
+            writeln!(header, "old mode {}", old.mode)?;
+            writeln!(header, "new mode {}", new.mode)?;
+            true
+        }
+        _ => false,
+    };
+    let null = repo.hash_algorithm()?.null_id();
+    let old_hash = old.map_or(null, |side| side.hash);
+    let new_hash = new.map_or(null, |side| side.hash);
+    if old_hash == new_hash {
+        out.write_all(&header)?;
+        return Ok(());
+    }
+    let old_data = PatchSide::content(repo, old)?;
+    let new_data = PatchSide::content(repo, new)?;
+    let binary = is_binary_change(driver, &old_data, &new_data);
+    // A binary patch is applied only to exactly the blob it was made from.
+    let abbrev = |hash: &ObjectId| match binary && options.binary {
+        true => hash.to_string(),
+        false => hash.short(7),
+    };
+    write!(header, "index {}..{}", abbrev(&old_hash), abbrev(&new_hash))?;
+    match (old, new) {
+        (Some(old), Some(new)) if old.mode == new.mode => writeln!(header, " {}", old.mode)?,
+        _ => writeln!(header)?,
+    }
+    let old_name = old.map_or("/dev/null".to_string(), |_| format!("a/{path}"));
+    let new_name = new.map_or("/dev/null".to_string(), |_| format!("b/{path}"));
+    if binary {
+        out.write_all(&header)?;
+        if options.binary {
+            writeln!(out, "GIT binary patch")?;
+            write_binary_hunk(out, &old_data, &new_data)?;
+            write_binary_hunk(out, &new_data, &old_data)?;
+        } else {
+            writeln!(out, "Binary files {old_name} and {new_name} differ")?;
+        }
+        return Ok(());
+    }
+    let old_lines = split_lines(&old_data);
+    let new_lines = split_lines(&new_data);
+    let ignore = &options.ignore_whitespace;
+    let ops = diff_lines_ignoring(&old_lines, &new_lines, ignore);
+    let hunks = make_hunks_with(
+        &ops,
+        &old_lines,
+        &new_lines,
+        hunk_options,
+        |change| {
+            ignore.blank_lines
+                && change.iter().all(|op| match *op {
+                    DiffOp::Delete { old } => ignore.is_blank(old_lines[old]),
+                    DiffOp::Insert { new } => ignore.is_blank(new_lines[new]),
+                    DiffOp::Equal { .. } => true,
+                })
+        },
+        |line| driver.is_function_line(line),
+    );
+    // Nothing follows for an empty file added or deleted.
+    if hunks.is_empty() {
+        if must_show_header {
+            out.write_all(&header)?;
+        }
+        return Ok(());
+    }
+    out.write_all(&header)?;
+    writeln!(out, "--- {old_name}")?;
+    writeln!(out, "+++ {new_name}")?;
+    for hunk in hunks {
+        write!(
+            out,
+            "@@ -{} +{} @@",
+            hunk_range(hunk.old_start, hunk.old_len),
+            hunk_range(hunk.new_start, hunk.new_len)
+        )?;
+        if let Some(line) = driver.function_line(&old_lines[..hunk.old_start]) {
+            out.write_all(b" ")?;
+            out.write_all(line)?;
+        }
+        writeln!(out)?;
+        for (prefix, line) in hunk.lines(&old_lines, &new_lines) {
+            out.write_all(&[prefix])?;
+            out.write_all(line)?;
+            if !line.ends_with(b"\n") {
+                out.write_all(b"\n\\ No newline at end of file\n")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A hunk header's `<start>,<length>` for one side, `start` being 0-based: the
+/// length is left out when it is 1, and an empty range starts at the line before.
+fn hunk_range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{start},0"),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{len}", start + 1),
+    }
+}
+
+/// Show the change to `path` from `old` to `new` with the external diff program
+/// `command`, as git does: run by the shell with seven arguments, the path, then for
+/// each side a file holding it, its id and its mode (`/dev/null . .` for a missing
+/// one). A side that is in the working directory is passed as the file itself, with
+/// the null id as git has not hashed it; others are written to temporary files,
+/// `git-blob-XXXXXX/<basename>`. `GIT_DIFF_PATH_COUNTER` and `GIT_DIFF_PATH_TOTAL`
+/// say which of how many paths this is.
+fn run_external_diff(
+    repo: &Repository,
+    command: &str,
+    path: &str,
+    old: Option<&PatchSide>,
+    new: Option<&PatchSide>,
+    counter: usize,
+    total: usize,
+) -> Result<()> {
+    if let (Some(old), Some(new)) = (old, new)
+        && old.hash == new.hash
+        && old.mode == new.mode
+    {
+        return Ok(());
+    }
+    let null = repo.hash_algorithm()?.null_id();
+    let basename = path.rsplit('/').next().unwrap_or(path);
+    let mut temp_dirs = Vec::new();
+    let mut args = vec![path.to_string()];
+    for side in [old, new] {
+        let Some(side) = side else {
+            args.extend(["/dev/null", ".", "."].map(str::to_string));
+            continue;
+        };
+        if side.data.is_some() && side.mode != "120000" {
+            args.extend([path.to_string(), null.to_string(), side.mode.clone()]);
+            continue;
+        }
+        let dir = tempfile::Builder::new()
+            .prefix("git-blob-")
+            .tempdir()
+            .context("unable to create temp-file")?;
+        let file = dir.path().join(basename);
+        fs::write(&file, PatchSide::content(repo, Some(side))?)
+            .with_context(|| format!("unable to write temp-file {}", file.display()))?;
+        args.extend([
+            file.display().to_string(),
+            side.hash.to_string(),
+            side.mode.clone(),
+        ]);
+        temp_dirs.push(dir);
+    }
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{command} \"$@\""))
+        .arg(command)
+        .args(&args)
+        .current_dir(&repo.work_tree)
+        .env("GIT_DIFF_PATH_COUNTER", counter.to_string())
+        .env("GIT_DIFF_PATH_TOTAL", total.to_string())
+        .status()
+        .with_context(|| format!("failed to run {command}"))?;
+    if !status.success() {
+        bail!("external diff died, stopping at {path}");
+    }
+    Ok(())
+}
+
+/// The hunk of a binary patch that rebuilds `target` from `base`: a `delta` against
+/// it when that deflates smaller than the `literal` target, as git picks, which needs
+/// both to be non-empty. The header gives the size before deflating.
+fn write_binary_hunk(out: &mut impl Write, base: &[u8], target: &[u8]) -> Result<()> {
+    let literal = deflate(target)?;
+    if !base.is_empty() && !target.is_empty() {
+        // Like git, give up on a delta that grows past the deflated literal.
+        if let Some(delta) = DeltaIndex::new(base).delta(target, literal.len()) {
+            let deflated = deflate(&delta)?;
+            if deflated.len() < literal.len() {
+                return write_binary_data(out, "delta", delta.len(), &deflated);
+            }
+        }
+    }
+    write_binary_data(out, "literal", target.len(), &literal)
+}
+
+/// Like git, binary patches are compressed for speed.
+fn deflate(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// A hunk of a binary patch: its `kind` and `size`, then the `compressed` data in lines
+/// of base85, each led by a letter for how many bytes it holds (`A`-`Z` for 1 to 26,
+/// `a`-`z` for 27 to 52), and a blank line.
+fn write_binary_data(
+    out: &mut impl Write,
+    kind: &str,
+    size: usize,
+    compressed: &[u8],
+) -> Result<()> {
+    writeln!(out, "{kind} {size}")?;
+    for chunk in compressed.chunks(52) {
+        let len = chunk.len() as u8;
+        let letter = match len {
+            1..=26 => b'A' + len - 1,
+            _ => b'a' + len - 27,
+        };
+        out.write_all(&[letter])?;
+        out.write_all(&base85::encode(chunk))?;
+        writeln!(out)?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// `--check`: report each line the change adds with a whitespace error `rule` counts,
+/// as `<path>:<line>: <problems>.` followed by the line, and blank lines added at the
+/// end of the file. Binary files aren't checked. Returns whether there were any.
+fn check_whitespace(
+    repo: &Repository,
+    out: &mut impl Write,
+    rule: &WhitespaceRule,
+    driver: &DiffDriver,
+    path: &str,
+    old: Option<&PatchSide>,
+    new: Option<&PatchSide>,
+) -> Result<bool> {
+    if new.is_none_or(|new| new.mode == "160000") {
+        return Ok(false);
+    }
+    let old_data = PatchSide::content(repo, old)?;
+    let new_data = PatchSide::content(repo, new)?;
+    if is_binary_change(driver, &old_data, &new_data) {
+        return Ok(false);
+    }
+    let old_lines = split_lines(&old_data);
+    let new_lines = split_lines(&new_data);
+    let mut found = false;
+    for op in diff_lines(&old_lines, &new_lines) {
+        let DiffOp::Insert { new: n } = op else {
+            continue;
+        };
+        let errors = rule.check(new_lines[n]);
+        if errors.is_empty() {
+            continue;
+        }
+        found = true;
+        writeln!(out, "{path}:{}: {}.", n + 1, errors.description())?;
+        out.write_all(b"+")?;
+        out.write_all(new_lines[n])?;
+        if !new_lines[n].ends_with(b"\n") {
+            writeln!(out)?;
+        }
+    }
+    if rule.blank_at_eof {
+        let trailing_blank = |lines: &[&[u8]]| {
+            lines
+                .iter()
+                .rev()
+                .take_while(|line| is_blank_line(line))
+                .count()
+        };
+        let (old_blank, new_blank) = (trailing_blank(&old_lines), trailing_blank(&new_lines));
+        if new_blank > old_blank {
+            found = true;
+            let line = new_lines.len() - new_blank + 1;
+            writeln!(out, "{path}:{line}: new blank line at EOF.")?;
+        }
+    }
+    Ok(found)
+}
@@ -0,0 +1,139 @@
+/// A character encoding commit messages can be stored in, as named by a commit's
+/// `encoding` header and by `i18n.commitEncoding`.
+///
+/// Only UTF-8 and the single-byte Western European encodings are known; a commit in
+/// any other encoding is read as if it were UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Ascii,
+    /// ISO-8859-1, whose bytes are the first 256 code points.
+    Latin1,
+    /// ISO-8859-15, Latin-1 with the euro sign and a few letters in place of
+    /// rarely used symbols.
+    Latin9,
+    /// Windows-1252, Latin-1 with punctuation and letters where it has control
+    /// characters (0x80 to 0x9f).
+    Windows1252,
+}
+
+/// The characters of Windows-1252's 0x80 to 0x9f; `None` for the five unassigned
+/// bytes.
+const WINDOWS_1252_HIGH: [Option<char>; 32] = [
+    Some('€'),
+    None,
+    Some('‚'),
+    Some('ƒ'),
+    Some('„'),
+    Some('…'),
+    Some('†'),
+    Some('‡'),
+    Some('ˆ'),
+    Some('‰'),
+    Some('Š'),
+    Some('‹'),
+    Some('Œ'),
+    None,
+    Some('Ž'),
+    None,
+    None,
+    Some('‘'),
+    Some('’'),
+    Some('“'),
+    Some('”'),
+    Some('•'),
+    Some('–'),
+    Some('—'),
+    Some('˜'),
+    Some('™'),
+    Some('š'),
+    Some('›'),
+    Some('œ'),
+    None,
+    Some('ž'),
+    Some('Ÿ'),
+];
+
+/// The bytes where ISO-8859-15 differs from ISO-8859-1.
+const LATIN9_CHANGES: [(u8, char); 8] = [
+    (0xa4, '€'),
+    (0xa6, 'Š'),
+    (0xa8, 'š'),
+    (0xb4, 'Ž'),
+    (0xb8, 'ž'),
+    (0xbc, 'Œ'),
+    (0xbd, 'œ'),
+    (0xbe, 'Ÿ'),
+];
+
+impl Encoding {
+    /// The encoding called `name`, ignoring case, `-` and `_` (`utf8`, `ISO-8859-1`,
+    /// `latin1`, `cp1252`...).
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        let name: String = name
+            .chars()
+            .filter(|c| !matches!(c, '-' | '_'))
+            .collect::<String>()
+            .to_ascii_uppercase();
+        Some(match name.as_str() {
+            "UTF8" => Encoding::Utf8,
+            "ASCII" | "USASCII" => Encoding::Ascii,
+            "ISO88591" | "LATIN1" => Encoding::Latin1,
+            "ISO885915" | "LATIN9" => Encoding::Latin9,
+            "WINDOWS1252" | "CP1252" => Encoding::Windows1252,
+            _ => return None,
+        })
+    }
+
+    /// `data` as UTF-8 text; bytes the encoding has no character for become U+FFFD.
+    pub fn decode(self, data: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(data).into_owned(),
+            _ => data.iter().map(|&byte| self.decode_byte(byte)).collect(),
+        }
+    }
+
+    fn decode_byte(self, byte: u8) -> char {
+        let latin1 = char::from(byte);
+        match self {
+            Encoding::Utf8 | Encoding::Ascii if byte >= 0x80 => char::REPLACEMENT_CHARACTER,
+            Encoding::Latin9 => LATIN9_CHANGES
+                .iter()
+                .find(|(b, _)| *b == byte)
+                .map_or(latin1, |(_, c)| *c),
+            Encoding::Windows1252 if (0x80..0xa0).contains(&byte) => {
+                WINDOWS_1252_HIGH[usize::from(byte - 0x80)].unwrap_or(char::REPLACEMENT_CHARACTER)
+            }
+            _ => latin1,
+        }
+    }
+
+    /// `text` in this encoding, or `None` if it has a character the encoding can't
+    /// represent.
+    pub fn encode(self, text: &str) -> Option<Vec<u8>> {
+        if self == Encoding::Utf8 {
+            return Some(text.as_bytes().to_vec());
+        }
+        text.chars().map(|c| self.encode_char(c)).collect()
+    }
+
+    fn encode_char(self, c: char) -> Option<u8> {
+        if c.is_ascii() {
+            return Some(c as u8);
+        }
+        match self {
+            Encoding::Utf8 | Encoding::Ascii => None,
+            Encoding::Latin1 => u8::try_from(u32::from(c)).ok(),
+            Encoding::Latin9 => match LATIN9_CHANGES.iter().find(|(_, changed)| *changed == c) {
+                Some((byte, _)) => Some(*byte),
+                None => u8::try_from(u32::from(c))
+                    .ok()
+                    .filter(|byte| LATIN9_CHANGES.iter().all(|(b, _)| b != byte)),
+            },
+            Encoding::Windows1252 => match WINDOWS_1252_HIGH.iter().position(|&h| h == Some(c)) {
+                Some(position) => Some(0x80 + position as u8),
+                None => u8::try_from(u32::from(c)).ok().filter(|&byte| byte >= 0xa0),
+            },
+        }
+    }
+}
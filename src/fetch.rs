@@ -0,0 +1,490 @@
+use crate::commit_read::{Commit, ahead_behind, walk_commits};
+use crate::config::Config;
+use crate::fsck::check_object;
+use crate::http::fetch_pack;
+use crate::index_pack::store_pack;
+use crate::object_id::HashAlgorithm;
+use crate::object_read::{Object, ObjectKind, forget_packs, has_object, packs};
+use crate::odb::Odb;
+use crate::refs::{current_branch, list_refs, resolve_ref, shorten_ref, update_ref};
+use crate::remote::{Remote, RemoteRefs, get_remote, remote_names};
+use crate::repository::{Repository, git_path};
+use crate::transfer::{copy_objects, find_missing_object, tag_target};
+use anyhow::{Context, Result, bail};
+use std::fmt::Write;
+use std::fs::{create_dir_all, rename};
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+#[derive(Debug, Default)]
+pub struct FetchOptions {
+    /// Fetch every configured remote, except those with `remote.<name>.skipFetchAll`.
+    pub all: bool,
+    /// Treat every argument as a remote or group rather than a remote and refspecs.
+    pub multiple: bool,
+    /// How many remotes to fetch at once; `fetch.parallel` or 1 if not given, and 0
+    /// picks a number based on the available CPUs.
+    pub jobs: Option<usize>,
+    /// Prefix of the reflog messages, e.g. `fetch --all`.
+    pub reflog_action: String,
+}
+
+/// An update of a local ref to what a remote ref points at.
+struct RefUpdate {
+    /// The remote ref, e.g. `refs/heads/main`.
+    src: String,
+    /// The local ref it's stored in, e.g. `refs/remotes/origin/main`.
+    dst: String,
+    force: bool,
+    hash: String,
+}
+
+/// What fetching a single remote printed, and whether any ref was rejected.
+struct FetchReport {
+    output: String,
+    rejected: bool,
+}
+
+/// `git fetch`: copy the objects of the named remotes (or groups of remotes from
+/// `remotes.<group>`) and update their remote-tracking refs as their fetch refspecs
+/// say. Tags pointing into the fetched history are fetched too, as in git.
+///
+/// Several remotes are fetched `jobs` at a time, each one's report written to `err`
+/// as a whole once it is done, after a `Fetching <remote>` line on `out` when it
+/// starts.
+///
+/// A remote that is a local repository has the objects copied straight from it; one
+/// with an `http://` URL is fetched from with protocol version 2 of the smart HTTP
+/// protocol, the objects coming in a pack that is stored whole (see
+/// [`fetch_over_http`]).
+pub fn git_fetch(
+    repo: &Repository,
+    config: &Config,
+    out: &mut (impl io::Write + Send),
+    err: &mut (impl io::Write + Send),
+    names: &[String],
+    options: &FetchOptions,
+) -> Result<()> {
+    let remotes = fetch_targets(repo, config, names, options)?;
+    if let [name] = remotes.as_slice() {
+        let report = fetch_remote(repo, config, name, &options.reflog_action)?;
+        err.write_all(report.output.as_bytes())?;
+        if report.rejected {
+            bail!("some refs from '{name}' could not be updated");
+        }
+        return Ok(());
+    }
+
+    let jobs = match options.jobs {
+        Some(jobs) => jobs,
+        None => match config.get("fetch.parallel") {
+            Some(value) => value
+                .parse()
+                .with_context(|| format!("bad fetch.parallel value '{value}'"))?,
+            None => 1,
+        },
+    };
+    let jobs = if jobs == 0 {
+        thread::available_parallelism().map_or(1, |n| n.get())
+    } else {
+        jobs
+    };
+
+    let next = AtomicUsize::new(0);
+    let failed = Mutex::new(Vec::new());
+    let output = Mutex::new((out, err));
+    thread::scope(|scope| -> Result<()> {
+        let workers: Vec<_> = (0..jobs.min(remotes.len()))
+            .map(|_| {
+                scope.spawn(|| -> Result<()> {
+                    while let Some(name) = remotes.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        writeln!(output.lock().unwrap().0, "Fetching {name}")?;
+                        let result = fetch_remote(repo, config, name, &options.reflog_action);
+                        let mut failed = failed.lock().unwrap();
+                        let (_, err) = &mut *output.lock().unwrap();
+                        match result {
+                            Ok(report) => {
+                                err.write_all(report.output.as_bytes())?;
+                                if report.rejected {
+                                    failed.push(name.as_str());
+                                }
+                            }
+                            Err(e) => {
+                                writeln!(err, "error: {e:#}")?;
+                                failed.push(name.as_str());
+                            }
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap()?;
+        }
+        Ok(())
+    })?;
+    let failed = failed.into_inner().unwrap();
+    if !failed.is_empty() {
+        bail!("could not fetch {}", failed.join(", "));
+    }
+    Ok(())
+}
+
+/// The remotes a `fetch` command line names, in order and without duplicates.
+fn fetch_targets(
+    repo: &Repository,
+    config: &Config,
+    names: &[String],
+    options: &FetchOptions,
+) -> Result<Vec<String>> {
+    let names: Vec<String> = if options.all {
+        if !names.is_empty() {
+            bail!("fetch --all does not take a repository argument");
+        }
+        let mut all = Vec::new();
+        for name in remote_names(config) {
+            if !config
+                .get_bool(&format!("remote.{name}.skipFetchAll"))?
+                .unwrap_or(false)
+            {
+                all.push(name);
+            }
+        }
+        all
+    } else if options.multiple {
+        names.to_vec()
+    } else {
+        match names {
+            [] => {
+                let branch_remote = current_branch(repo)?.and_then(|branch| {
+                    config
+                        .get(&format!("branch.{branch}.remote"))
+                        .map(str::to_string)
+                });
+                vec![branch_remote.unwrap_or_else(|| "origin".to_string())]
+            }
+            [name] => vec![name.clone()],
+            _ => bail!("fetching refspecs given on the command line isn't supported yet"),
+        }
+    };
+
+    let mut remotes: Vec<String> = Vec::new();
+    for name in names {
+        let group = config.get_all(&format!("remotes.{name}"));
+        let members = if group.is_empty() {
+            vec![name]
+        } else {
+            group
+                .iter()
+                .flat_map(|members| members.split_whitespace())
+                .map(str::to_string)
+                .collect()
+        };
+        for member in members {
+            if !remotes.contains(&member) {
+                remotes.push(member);
+            }
+        }
+    }
+    Ok(remotes)
+}
+
+fn fetch_remote(
+    repo: &Repository,
+    config: &Config,
+    name: &str,
+    reflog_action: &str,
+) -> Result<FetchReport> {
+    let remote = get_remote(config, name)?;
+    let remote_refs = remote.query(repo)?;
+    let url = remote.urls.first().map(String::as_str).unwrap_or("");
+    let local_objects = repo.objects_dir();
+    let local_objects = local_objects.as_path();
+    let fetch_objects = |tips: &[String]| match &remote_refs.git_dir {
+        Some(git_dir) => {
+            copy_objects(repo, &git_path(git_dir, "objects"), local_objects, tips).map(drop)
+        }
+        None => fetch_over_http(repo, config, url, local_objects, tips),
+    };
+
+    let mut updates: Vec<RefUpdate> = Vec::new();
+    for (src, hash) in &remote_refs.refs {
+        if let Some((force, dst)) = remote
+            .fetch
+            .iter()
+            .find_map(|spec| Some((spec.force, spec.map_to_destination(src)?)))
+        {
+            updates.push(RefUpdate {
+                src: src.clone(),
+                dst,
+                force,
+                hash: hash.clone(),
+            });
+        }
+    }
+    let tips: Vec<String> = updates.iter().map(|update| update.hash.clone()).collect();
+    fetch_objects(&tips)?;
+
+    if config.get(&format!("remote.{name}.tagOpt")) != Some("--no-tags") {
+        let tags = followed_tags(repo, &remote, &remote_refs, &updates)?;
+        let tips: Vec<String> = tags.iter().map(|update| update.hash.clone()).collect();
+        fetch_objects(&tips)?;
+        updates.extend(tags);
+    }
+
+    let mut lines = Vec::new();
+    let mut rejected = false;
+    for update in &updates {
+        let old_hash = resolve_ref(repo, &update.dst)?;
+        let Some((code, summary, note)) = classify_update(repo, update, old_hash.as_deref()) else {
+            continue;
+        };
+        if code == '!' {
+            rejected = true;
+        } else {
+            update_ref(
+                repo,
+                config,
+                &update.dst,
+                &update.hash,
+                &format!("{reflog_action}: {}", note.as_deref().unwrap_or("")),
+            )?;
+        }
+        lines.push((code, summary, update, note));
+    }
+
+    let mut output = String::new();
+    if lines.is_empty() {
+        return Ok(FetchReport { output, rejected });
+    }
+    let url = remote.urls.first().map(String::as_str).unwrap_or("");
+    writeln!(output, "From {url}")?;
+    let width = lines
+        .iter()
+        .map(|(_, _, update, _)| shorten_ref(&update.src).len())
+        .max()
+        .unwrap_or(0)
+        .max(10);
+    for (code, summary, update, note) in lines {
+        let src = shorten_ref(&update.src);
+        let dst = shorten_ref(&update.dst);
+        write!(output, " {code} {summary:<17} {src:<width$} -> {dst}")?;
+        match (code, note) {
+            ('+', _) => writeln!(output, "  (forced update)")?,
+            ('!', Some(reason)) => writeln!(output, "  ({reason})")?,
+            _ => writeln!(output)?,
+        }
+    }
+    Ok(FetchReport { output, rejected })
+}
+
+/// Decide how `update` changes its local ref, currently at `old_hash`: the status
+/// code and summary column of git's fetch output, and the reflog note, or for a
+/// rejected update (code `!`) the reason. `None` if the ref is already up to date.
+fn classify_update(
+    repo: &Repository,
+    update: &RefUpdate,
+    old_hash: Option<&str>,
+) -> Option<(char, String, Option<String>)> {
+    let new_hash = update.hash.as_str();
+    let Some(old_hash) = old_hash else {
+        let (summary, note) = if update.src.starts_with("refs/tags/") {
+            ("[new tag]", "storing tag")
+        } else if update.src.starts_with("refs/heads/") {
+            ("[new branch]", "storing head")
+        } else {
+            ("[new ref]", "storing ref")
+        };
+        return Some(('*', summary.to_string(), Some(note.to_string())));
+    };
+    if old_hash == new_hash {
+        return None;
+    }
+    if update.dst.starts_with("refs/tags/") {
+        return Some(if update.force {
+            (
+                't',
+                "[tag update]".to_string(),
+                Some("updating tag".to_string()),
+            )
+        } else {
+            (
+                '!',
+                "[rejected]".to_string(),
+                Some("would clobber existing tag".to_string()),
+            )
+        });
+    }
+    let range = |separator: &str| format!("{}{separator}{}", &old_hash[..7], &new_hash[..7]);
+    Some(match ahead_behind(repo, new_hash, old_hash) {
+        Ok((_, 0)) => (' ', range(".."), Some("fast-forward".to_string())),
+        _ if update.force => ('+', range("..."), Some("forced-update".to_string())),
+        _ => (
+            '!',
+            "[rejected]".to_string(),
+            Some("non-fast-forward".to_string()),
+        ),
+    })
+}
+
+/// The remote's tags that point at objects the local repository has (now that the
+/// fetch copied them) and that don't exist locally yet, as git fetches by default.
+fn followed_tags(
+    repo: &Repository,
+    remote: &Remote,
+    remote_refs: &RemoteRefs,
+    updates: &[RefUpdate],
+) -> Result<Vec<RefUpdate>> {
+    let mut tags = Vec::new();
+    for (src, hash) in &remote_refs.refs {
+        if !src.starts_with("refs/tags/")
+            || updates.iter().any(|update| update.src == *src)
+            || resolve_ref(repo, src)?.is_some()
+        {
+            continue;
+        }
+        let target = match &remote_refs.git_dir {
+            Some(git_dir) => peel_tag(remote, &git_path(git_dir, "objects"), hash)?,
+            None => remote_refs.peeled.get(src).unwrap_or(hash).clone(),
+        };
+        if has_object(&repo.objects_dir(), &target) {
+            tags.push(RefUpdate {
+                src: src.clone(),
+                dst: src.clone(),
+                force: false,
+                hash: hash.clone(),
+            });
+        }
+    }
+    Ok(tags)
+}
+
+/// What the object `hash` in the remote's `objects_dir` peels to: the object at the
+/// end of its chain of tags.
+fn peel_tag(remote: &Remote, objects_dir: &Path, hash: &str) -> Result<String> {
+    let mut target = hash.to_string();
+    loop {
+        let mut object = Object::read_git_object_in(objects_dir, &target)?;
+        if object.kind != ObjectKind::Tag {
+            return Ok(target);
+        }
+        let Some(peeled) = tag_target(&object.read_all()?) else {
+            bail!("invalid tag {target} in remote '{}'", remote.name);
+        };
+        target = peeled;
+    }
+}
+
+/// Fetch what is reachable from `tips` into `objects_dir` from the repository at
+/// `url` over smart HTTP, unless it has them all already: the server is told every
+/// commit [`negotiation_haves`] finds so that it can leave out what they reach, and
+/// the pack it sends is stored as it is.
+///
+/// The pack is indexed into a quarantine directory first, and only moved into
+/// `objects_dir` once everything `tips` reach is known to be either in it or there
+/// already, so that a remote sending too little can't leave dangling history behind.
+/// With `fetch.fsckObjects` (or else `transfer.fsckObjects`) every object in the pack
+/// has to be well-formed too (see [`check_object`]).
+fn fetch_over_http(
+    repo: &Repository,
+    config: &Config,
+    url: &str,
+    objects_dir: &Path,
+    tips: &[String],
+) -> Result<()> {
+    let mut wants: Vec<String> = tips
+        .iter()
+        .filter(|tip| !has_object(objects_dir, tip))
+        .cloned()
+        .collect();
+    wants.sort();
+    wants.dedup();
+    if wants.is_empty() {
+        return Ok(());
+    }
+    let algorithm = repo.hash_algorithm()?;
+    let pack = fetch_pack(url, algorithm, &wants, &negotiation_haves(repo)?)?;
+
+    let quarantine = tempfile::Builder::new()
+        .prefix("tmp_objdir-incoming-")
+        .tempdir_in(objects_dir)
+        .with_context(|| {
+            format!(
+                "unable to create temporary object directory in {}",
+                objects_dir.display()
+            )
+        })?;
+    let incoming = quarantine.path();
+    let checksum = store_pack(incoming, &pack, Some(objects_dir), algorithm)?;
+    let fsck_objects = match config.get_bool("fetch.fsckObjects")? {
+        Some(fsck_objects) => fsck_objects,
+        None => config.get_bool("transfer.fsckObjects")?.unwrap_or(false),
+    };
+    let checked = match fsck_objects {
+        true => check_pack(incoming, algorithm),
+        false => Ok(()),
+    };
+    let missing = checked.and_then(|()| find_missing_object(repo, incoming, objects_dir, &wants));
+    forget_packs(incoming);
+    if let Some(missing) = missing? {
+        bail!("remote did not send all necessary objects: {missing} is missing");
+    }
+
+    let pack_dir = objects_dir.join("pack");
+    create_dir_all(&pack_dir)
+        .with_context(|| format!("failed to create {}", pack_dir.display()))?;
+    // The index goes last, so that the pack is only looked in once it is whole.
+    for extension in ["pack", "idx"] {
+        let name = format!("pack-{checksum}.{extension}");
+        let destination = pack_dir.join(&name);
+        rename(incoming.join("pack").join(&name), &destination)
+            .with_context(|| format!("failed to move pack to {}", destination.display()))?;
+    }
+    forget_packs(objects_dir);
+    Ok(())
+}
+
+/// Check every object of the packs in `incoming` with [`check_object`].
+fn check_pack(incoming: &Path, algorithm: HashAlgorithm) -> Result<()> {
+    let packs = packs(incoming)?;
+    for id in packs.ids()? {
+        let Some((kind, data)) = packs.read_object(&id)? else {
+            bail!("object {id} is missing from the pack");
+        };
+        check_object(&kind, &data, algorithm)
+            .with_context(|| format!("fsck error in packed object {id}"))?;
+    }
+    Ok(())
+}
+
+/// How many commits a fetch over HTTP tells the server it has, at most.
+const MAX_HAVES: usize = 256;
+
+/// The objects a fetch over HTTP tells the server the repository has: what its refs
+/// point at, then the commits they reach, newest first, up to [`MAX_HAVES`] of them.
+fn negotiation_haves(repo: &Repository) -> Result<Vec<String>> {
+    let mut haves: Vec<String> = list_refs(repo, "refs/")?
+        .into_iter()
+        .map(|(_, hash)| hash)
+        .collect();
+    let commits: Vec<String> = haves
+        .iter()
+        .filter(|hash| Commit::read(repo, hash).is_ok())
+        .cloned()
+        .collect();
+    for commit in walk_commits(repo, &commits, &[])? {
+        if haves.len() >= MAX_HAVES {
+            break;
+        }
+        if !haves.contains(&commit.hash) {
+            haves.push(commit.hash);
+        }
+    }
+    haves.sort();
+    haves.dedup();
+    Ok(haves)
+}
@@ -0,0 +1,465 @@
+use crate::commit_read::{Commit, ahead_behind, walk_commits};
+use crate::config::Config;
+use crate::gpg::PGP_SIGNATURE_START;
+use crate::ident::{Ident, Role, resolve_ident};
+use crate::mailmap::Mailmap;
+use crate::object_read::{Object, ObjectKind};
+use crate::refs::{resolve_ref, symbolic_ref_target};
+use crate::repository::Repository;
+use crate::wildmatch::wildmatch;
+use anyhow::{Result, bail};
+use std::collections::HashSet;
+use std::fmt::Write;
+
+/// How many merged commits `--log` (or `merge.log = true`) lists per merged ref.
+pub const DEFAULT_MERGE_LOG_LEN: usize = 20;
+
+/// What `git fmt-merge-msg` puts in the message.
+#[derive(Debug, Clone, Default)]
+pub struct FmtMergeMsgOptions {
+    /// The message to start with instead of the generated title (`-m`).
+    pub message: Option<String>,
+    /// List up to this many of the commits each ref brings in; 0 lists none.
+    pub log: usize,
+    /// The branch named as merged into, instead of the current one (`--into-name`).
+    pub into_name: Option<String>,
+}
+
+impl FmtMergeMsgOptions {
+    /// How many commits to list by default, from `merge.log` (or its old name
+    /// `merge.summary`): a count, or a boolean for [`DEFAULT_MERGE_LOG_LEN`] or none.
+    pub fn log_from_config(config: &Config) -> Result<usize> {
+        let Some(value) = config
+            .get("merge.log")
+            .or_else(|| config.get("merge.summary"))
+        else {
+            return Ok(0);
+        };
+        Ok(match value.to_ascii_lowercase().as_str() {
+            "true" | "yes" | "on" => DEFAULT_MERGE_LOG_LEN,
+            "false" | "no" | "off" | "" => 0,
+            _ => match value.parse::<i64>() {
+                Ok(count) if count < 0 => bail!("negative merge.log value '{value}'"),
+                Ok(count) => count as usize,
+                Err(_) => bail!("bad boolean config value '{value}' for 'merge.log'"),
+            },
+        })
+    }
+}
+
+/// One ref being merged, as a line of the input names it.
+struct Origin {
+    /// How the ref is named in the log, e.g. `topic` or `branch 'topic' of url`.
+    name: String,
+    hash: String,
+    /// Whether it is a branch of this repository, whose description may be shown.
+    is_local_branch: bool,
+}
+
+/// The refs merged from one repository (`.` for this one), by kind.
+#[derive(Default)]
+struct Source {
+    name: String,
+    branches: Vec<String>,
+    remote_branches: Vec<String>,
+    tags: Vec<String>,
+    generic: Vec<String>,
+    /// Whether the repository's HEAD is merged (the line has no ` of `).
+    pulls_head: bool,
+    /// Whether any named ref of it is merged.
+    pulls_refs: bool,
+}
+
+/// `git fmt-merge-msg [-m <message>] [--log[=<n>]] [--into-name <branch>]`: the
+/// message of a commit merging what `input` lists, in the format `git fetch` writes
+/// `FETCH_HEAD`:
+///
+/// ```text
+/// <object id> TAB TAB branch 'topic' of https://example.com/repo.git
+/// <object id> TAB not-for-merge TAB branch 'main' of https://example.com/repo.git
+/// ```
+///
+/// The title names the refs grouped by repository, e.g. `Merge branches 'a' and 'b'
+/// of url into main`, without `into` for the branches `merge.suppressDest` matches
+/// (`main` and `master` by default). Refs already merged into HEAD or into another of
+/// them are left out. The messages of annotated tags follow (their signatures are
+/// dropped but not checked), and with `log` the subjects of the commits each ref
+/// brings in, crediting their authors and the committers of the merges among them,
+/// as `merge` and `pull` summarize a merge.
+pub fn git_fmt_merge_msg(
+    repo: &Repository,
+    config: &Config,
+    input: &str,
+    options: &FmtMergeMsgOptions,
+) -> Result<String> {
+    if let Some(message) = &options.message
+        && options.log == 0
+    {
+        return Ok(format!("{message}\n"));
+    }
+    let Some(head) = resolve_ref(repo, "HEAD")? else {
+        bail!("No current branch");
+    };
+    let current_branch = match &options.into_name {
+        Some(name) => name.clone(),
+        None => match symbolic_ref_target(repo, "HEAD") {
+            Ok(target) => target
+                .strip_prefix("refs/heads/")
+                .unwrap_or(&target)
+                .to_string(),
+            Err(_) => "HEAD".to_string(),
+        },
+    };
+
+    let merge_parents = merge_parents(repo, input, &head)?;
+    let mut sources: Vec<Source> = Vec::new();
+    let mut origins = Vec::new();
+    for (number, line) in input.split_inclusive('\n').enumerate() {
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        if !parse_line(line, &merge_parents, &mut sources, &mut origins) {
+            bail!("error in line {}: {line}", number + 1);
+        }
+    }
+
+    let mut out = options.message.clone().unwrap_or_default();
+    if options.message.is_none() && !sources.is_empty() {
+        write_title(&mut out, &sources, &current_branch, config);
+    }
+    write_tag_messages(repo, &mut out, &origins)?;
+    if options.log > 0 {
+        complete_line(&mut out);
+        let use_description = config.get_bool("merge.branchdesc")?.unwrap_or(false);
+        let mailmap = Mailmap::load(repo, config);
+        for origin in &origins {
+            write_shortlog(
+                repo,
+                &mut out,
+                origin,
+                &head,
+                options.log,
+                use_description,
+                config,
+                &mailmap,
+            )?;
+        }
+    }
+    complete_line(&mut out);
+    Ok(out)
+}
+
+/// The commits the for-merge lines of `input` name (tags peeled) that aren't already
+/// merged into HEAD or into one of the others, by the ids the lines give.
+fn merge_parents(repo: &Repository, input: &str, head: &str) -> Result<HashSet<String>> {
+    let mut parents = Vec::new();
+    for line in input.lines() {
+        let Some((hash, rest)) = line.split_once('\t') else {
+            continue;
+        };
+        if !rest.starts_with('\t') {
+            continue;
+        }
+        if let Ok(commit) = Commit::read(repo, hash) {
+            parents.push((hash.to_string(), commit.hash));
+        }
+    }
+    let mut heads: Vec<&str> = std::iter::once(head)
+        .chain(parents.iter().map(|(_, commit)| commit.as_str()))
+        .collect();
+    let mut seen = HashSet::new();
+    heads.retain(|commit| seen.insert(*commit));
+    let mut independent = Vec::new();
+    for &commit in &heads {
+        let mut merged = false;
+        for &other in &heads {
+            if other != commit && ahead_behind(repo, commit, other)?.0 == 0 {
+                merged = true;
+                break;
+            }
+        }
+        if !merged {
+            independent.push(commit);
+        }
+    }
+    Ok(parents
+        .iter()
+        .filter(|(_, commit)| independent.contains(&commit.as_str()))
+        .map(|(given, _)| given.clone())
+        .collect())
+}
+
+/// Record the ref one line of the input merges, unless it is not for merge or already
+/// merged. Returns whether the line is well-formed.
+fn parse_line(
+    line: &str,
+    merge_parents: &HashSet<String>,
+    sources: &mut Vec<Source>,
+    origins: &mut Vec<Origin>,
+) -> bool {
+    let Some((hash, rest)) = line.split_once('\t') else {
+        return false;
+    };
+    if rest.starts_with("not-for-merge") {
+        return true;
+    }
+    let Some(description) = rest.strip_prefix('\t') else {
+        return false;
+    };
+    if !hash.bytes().all(|byte| byte.is_ascii_hexdigit()) || description.is_empty() {
+        return false;
+    }
+    if !merge_parents.contains(hash) {
+        return true;
+    }
+
+    let (what, source_name, pulls_head) = match description.split_once(" of ") {
+        Some((what, source_name)) => (what, source_name, false),
+        None => (description, description, true),
+    };
+    let index = match sources.iter().position(|source| source.name == source_name) {
+        Some(index) => index,
+        None => {
+            sources.push(Source {
+                name: source_name.to_string(),
+                ..Source::default()
+            });
+            sources.len() - 1
+        }
+    };
+    let source = &mut sources[index];
+    let mut is_local_branch = false;
+    let origin = if pulls_head {
+        source.pulls_head = true;
+        source_name
+    } else {
+        source.pulls_refs = true;
+        if let Some(branch) = what.strip_prefix("branch ") {
+            is_local_branch = true;
+            source.branches.push(branch.to_string());
+            branch
+        } else if let Some(tag) = what.strip_prefix("tag ") {
+            source.tags.push(tag.to_string());
+            what
+        } else if let Some(branch) = what.strip_prefix("remote-tracking branch ") {
+            source.remote_branches.push(branch.to_string());
+            branch
+        } else {
+            source.generic.push(what.to_string());
+            source_name
+        }
+    };
+    let name = if source_name == "." || source_name == origin {
+        match origin.strip_prefix('\'').and_then(|o| o.strip_suffix('\'')) {
+            Some(unquoted) => unquoted.to_string(),
+            None => origin.to_string(),
+        }
+    } else {
+        format!("{origin} of {source_name}")
+    };
+    origins.push(Origin {
+        name,
+        hash: hash.to_string(),
+        is_local_branch: is_local_branch && source_name == ".",
+    });
+    true
+}
+
+/// `Merge <refs> [of <repository>]; ... [into <branch>]`.
+fn write_title(out: &mut String, sources: &[Source], current_branch: &str, config: &Config) {
+    out.push_str("Merge ");
+    for (i, source) in sources.iter().enumerate() {
+        if i > 0 {
+            out.push_str("; ");
+        }
+        if !source.pulls_refs {
+            out.push_str(&source.name);
+            continue;
+        }
+        let mut parts = Vec::new();
+        if source.pulls_head {
+            parts.push("HEAD".to_string());
+        }
+        for (names, singular, plural) in [
+            (&source.branches, "branch", "branches"),
+            (
+                &source.remote_branches,
+                "remote-tracking branch",
+                "remote-tracking branches",
+            ),
+            (&source.tags, "tag", "tags"),
+            (&source.generic, "commit", "commits"),
+        ] {
+            match names.as_slice() {
+                [] => {}
+                [name] => parts.push(format!("{singular} {name}")),
+                [rest @ .., last] => parts.push(format!("{plural} {} and {last}", rest.join(", "))),
+            }
+        }
+        out.push_str(&parts.join(", "));
+        if source.name != "." {
+            write!(out, " of {}", source.name).unwrap();
+        }
+    }
+    if !dest_suppressed(config, current_branch) {
+        write!(out, " into {current_branch}").unwrap();
+    }
+    out.push('\n');
+}
+
+/// Whether `merge.suppressDest` (`main` and `master` if it isn't set; an empty value
+/// clears the list) says not to name `branch` as merged into.
+fn dest_suppressed(config: &Config, branch: &str) -> bool {
+    let values = config.get_all("merge.suppressDest");
+    let patterns: Vec<&str> = match values.iter().rposition(|value| value.is_empty()) {
+        Some(cleared) => values[cleared + 1..].to_vec(),
+        None if values.is_empty() => vec!["main", "master"],
+        None => values,
+    };
+    patterns
+        .iter()
+        .any(|pattern| wildmatch(pattern, branch, true, false))
+}
+
+/// The messages of the annotated tags merged, each headed by a `#` comment naming it
+/// when there are several.
+fn write_tag_messages(repo: &Repository, out: &mut String, origins: &[Origin]) -> Result<()> {
+    let mut messages = String::new();
+    let mut first_tag = None;
+    let mut tags = 0;
+    for origin in origins {
+        let Ok(mut object) = Object::read_git_object(repo, &origin.hash) else {
+            continue;
+        };
+        if object.kind != ObjectKind::Tag {
+            continue;
+        }
+        let data = String::from_utf8_lossy(&object.read_all()?).into_owned();
+        let payload = match data.find(&format!("\n{PGP_SIGNATURE_START}")) {
+            Some(start) => &data[..start + 1],
+            None => &data,
+        };
+        tags += 1;
+        match first_tag {
+            None => first_tag = Some(&origin.name),
+            Some(first) => {
+                if tags == 2 {
+                    messages.insert_str(0, &format!("\n# {first}\n"));
+                }
+                write!(messages, "\n# {}\n", origin.name)?;
+            }
+        }
+        if let Some((_, body)) = payload.split_once("\n\n") {
+            messages.push_str(body);
+        }
+        complete_line(&mut messages);
+    }
+    if !messages.is_empty() {
+        out.push('\n');
+        out.push_str(&messages);
+    }
+    Ok(())
+}
+
+/// `* <name>:` and the subjects of the commits the ref brings into HEAD, at most
+/// `limit` of them, merges left out, after `# By` and `# Via` lines crediting their
+/// authors and the committers of the merges and of the tip, by the names `mailmap`
+/// gives them.
+#[allow(clippy::too_many_arguments)]
+fn write_shortlog(
+    repo: &Repository,
+    out: &mut String,
+    origin: &Origin,
+    head: &str,
+    limit: usize,
+    use_description: bool,
+    config: &Config,
+    mailmap: &Mailmap,
+) -> Result<()> {
+    let Ok(tip) = Commit::read(repo, &origin.hash) else {
+        return Ok(());
+    };
+    let mut authors: Vec<(String, usize)> = Vec::new();
+    let mut committers: Vec<(String, usize)> = Vec::new();
+    let mut subjects = Vec::new();
+    let mut count = 0;
+    for commit in walk_commits(repo, &[tip.hash], &[head.to_string()])? {
+        if commit.parents.len() > 1 {
+            record_person(&mut committers, &commit.committer, mailmap);
+            continue;
+        }
+        if count == 0 {
+            record_person(&mut committers, &commit.committer, mailmap);
+        }
+        record_person(&mut authors, &commit.author, mailmap);
+        count += 1;
+        if subjects.len() > limit {
+            continue;
+        }
+        let subject = commit.subject().trim_start();
+        subjects.push(match subject.is_empty() {
+            true => commit.hash.clone(),
+            false => subject.to_string(),
+        });
+    }
+
+    credit_people(out, authors, "By", Role::Author, config);
+    credit_people(out, committers, "Via", Role::Committer, config);
+    match count > limit {
+        true => write!(out, "\n* {}: ({count} commits)\n", origin.name)?,
+        false => write!(out, "\n* {}:\n", origin.name)?,
+    }
+    if origin.is_local_branch
+        && use_description
+        && let Some(description) = config.get(&format!("branch.{}.description", origin.name))
+    {
+        for line in description.split_inclusive('\n') {
+            write!(out, "  : {line}")?;
+        }
+        complete_line(out);
+    }
+    for (i, subject) in subjects.iter().enumerate() {
+        match i >= limit {
+            true => out.push_str("  ...\n"),
+            false => writeln!(out, "  {subject}")?,
+        }
+    }
+    Ok(())
+}
+
+fn record_person(people: &mut Vec<(String, usize)>, person: &Ident, mailmap: &Mailmap) {
+    let (name, _) = mailmap.map(&person.name, &person.email);
+    match people.iter_mut().find(|(person, _)| person == name) {
+        Some((_, count)) => *count += 1,
+        None => people.push((name.to_string(), 1)),
+    }
+}
+
+/// `# <label> <people>`: the person alone, the two with their counts, or the one
+/// with the most and "others" — unless it would only credit whoever is merging.
+fn credit_people(
+    out: &mut String,
+    mut people: Vec<(String, usize)>,
+    label: &str,
+    role: Role,
+    config: &Config,
+) {
+    // Most credited first, ties by name.
+    people.sort();
+    people.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    let me = resolve_ident(role, config).ok();
+    match people.as_slice() {
+        [] => {}
+        [(name, _)] if me.is_some_and(|me| me.name == *name) => {}
+        [(name, _)] => write!(out, "\n# {label} {name}").unwrap(),
+        [(a, a_count), (b, b_count)] => {
+            write!(out, "\n# {label} {a} ({a_count}) and {b} ({b_count})").unwrap()
+        }
+        [(name, count), ..] => write!(out, "\n# {label} {name} ({count}) and others").unwrap(),
+    }
+}
+
+/// End `out` with a newline unless it is empty or already does.
+fn complete_line(out: &mut String) {
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
@@ -0,0 +1,149 @@
+use crate::ident::Ident;
+use crate::object_id::HashAlgorithm;
+use crate::object_read::ObjectKind;
+use crate::write_tree::compare_tree_entry_names;
+use anyhow::{Result, bail, ensure};
+use std::cmp::Ordering;
+
+/// Check that `data` is a well-formed object of `kind`, as `git fsck` checks objects
+/// coming from another repository (`transfer.fsckObjects`):
+///
+/// - a commit starts with its `tree`, then any `parent`s, then an `author` and a
+///   `committer` ident, each `Name <email> <timestamp> <timezone>`;
+/// - a tag starts with the `object` it points at, its `type` and its `tag` name;
+/// - a tree has entries with known modes and names that are neither empty nor hold a
+///   `/`, aren't `.`, `..` or `.git` (in any case, so that checking it out can't
+///   write into a repository), in git's order and given once.
+///
+/// Blobs can hold anything. The error says what is wrong, in git's terms.
+pub fn check_object(kind: &ObjectKind, data: &[u8], algorithm: HashAlgorithm) -> Result<()> {
+    match kind {
+        ObjectKind::Blob => Ok(()),
+        ObjectKind::Commit => check_commit(data, algorithm),
+        ObjectKind::Tag => check_tag(data, algorithm),
+        ObjectKind::Tree => check_tree(data, algorithm),
+    }
+}
+
+fn check_commit(data: &[u8], algorithm: HashAlgorithm) -> Result<()> {
+    let text = String::from_utf8_lossy(data);
+    let mut lines = headers(&text);
+    match lines.next().and_then(|line| line.strip_prefix("tree ")) {
+        Some(tree) => ensure!(
+            algorithm.is_hex_id(tree),
+            "badTree: invalid 'tree' line format"
+        ),
+        None => bail!("missingTree: invalid format - expected 'tree' line"),
+    }
+    let mut line = lines.next();
+    while let Some(parent) = line.and_then(|line| line.strip_prefix("parent ")) {
+        ensure!(
+            algorithm.is_hex_id(parent),
+            "badParentSha1: invalid 'parent' line format"
+        );
+        line = lines.next();
+    }
+    match line.and_then(|line| line.strip_prefix("author ")) {
+        Some(author) => check_ident(author)?,
+        None => bail!("missingAuthor: invalid format - expected 'author' line"),
+    }
+    match lines
+        .next()
+        .and_then(|line| line.strip_prefix("committer "))
+    {
+        Some(committer) => check_ident(committer),
+        None => bail!("missingCommitter: invalid format - expected 'committer' line"),
+    }
+}
+
+fn check_tag(data: &[u8], algorithm: HashAlgorithm) -> Result<()> {
+    let text = String::from_utf8_lossy(data);
+    let mut lines = headers(&text);
+    match lines.next().and_then(|line| line.strip_prefix("object ")) {
+        Some(object) => ensure!(
+            algorithm.is_hex_id(object),
+            "badObjectSha1: invalid 'object' line format"
+        ),
+        None => bail!("missingObject: invalid format - expected 'object' line"),
+    }
+    match lines.next().and_then(|line| line.strip_prefix("type ")) {
+        Some(kind) => ensure!(
+            kind.parse::<ObjectKind>().is_ok(),
+            "badType: invalid 'type' value"
+        ),
+        None => bail!("missingTypeEntry: invalid format - expected 'type' line"),
+    }
+    ensure!(
+        lines.next().is_some_and(|line| line.starts_with("tag ")),
+        "missingTagEntry: invalid format - expected 'tag' line"
+    );
+    match lines.next().and_then(|line| line.strip_prefix("tagger ")) {
+        Some(tagger) => check_ident(tagger),
+        None => Ok(()),
+    }
+}
+
+fn check_tree(data: &[u8], algorithm: HashAlgorithm) -> Result<()> {
+    let mut rest = data;
+    let mut previous: Option<(&[u8], bool)> = None;
+    while !rest.is_empty() {
+        let Some(space) = rest.iter().position(|&b| b == b' ') else {
+            bail!("badTree: cannot be parsed as a tree");
+        };
+        let Some(nul) = rest.iter().position(|&b| b == 0).filter(|&nul| nul > space) else {
+            bail!("badTree: cannot be parsed as a tree");
+        };
+        let (mode, name) = (&rest[..space], &rest[space + 1..nul]);
+        ensure!(
+            rest.len() >= nul + 1 + algorithm.len(),
+            "badTree: cannot be parsed as a tree"
+        );
+        rest = &rest[nul + 1 + algorithm.len()..];
+
+        let is_dir = match mode {
+            b"40000" | b"040000" => true,
+            b"100644" | b"100755" | b"100664" | b"120000" | b"160000" => false,
+            _ => bail!("badFilemode: contains bad file modes"),
+        };
+        check_entry_name(name)?;
+        if let Some((previous_name, previous_is_dir)) = previous {
+            match compare_tree_entry_names(previous_name, previous_is_dir, name, is_dir) {
+                Ordering::Less if previous_name != name => {}
+                Ordering::Greater => bail!("treeNotSorted: not properly sorted"),
+                _ => bail!("duplicateEntries: contains duplicate file entries"),
+            }
+        }
+        previous = Some((name, is_dir));
+    }
+    Ok(())
+}
+
+/// Check that `name` can be a tree entry's: not empty, without a `/`, and not `.`,
+/// `..` or `.git` in any case, which a checkout would write outside the working tree
+/// or into the repository.
+pub fn check_entry_name(name: &[u8]) -> Result<()> {
+    ensure!(!name.is_empty(), "emptyName: contains empty pathname");
+    ensure!(
+        !name.contains(&b'/'),
+        "fullPathname: contains full pathnames"
+    );
+    ensure!(name != b".", "hasDot: contains '.'");
+    ensure!(name != b"..", "hasDotdot: contains '..'");
+    ensure!(
+        !name.eq_ignore_ascii_case(b".git"),
+        "hasDotgit: contains '.git'"
+    );
+    Ok(())
+}
+
+/// The header lines of a commit or tag, up to the blank line before its message.
+fn headers(text: &str) -> impl Iterator<Item = &str> {
+    text.split('\n').take_while(|line| !line.is_empty())
+}
+
+fn check_ident(ident: &str) -> Result<()> {
+    match Ident::parse(ident) {
+        Ok(_) => Ok(()),
+        Err(e) => bail!("badIdent: {e}"),
+    }
+}
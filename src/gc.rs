@@ -0,0 +1,472 @@
+use crate::bitmap::write_bitmap;
+use crate::config::Config;
+use crate::ident::{now_and_timezone, parse_date};
+use crate::index::Index;
+use crate::index_pack::{write_index, write_pack_files};
+use crate::object_id::{HashAlgorithm, ObjectId};
+use crate::object_read::{Object, ObjectKind, forget_packs, has_object};
+use crate::odb::{LooseOdb, Odb};
+use crate::pack::Pack;
+use crate::pack_objects::{PackObjectsOptions, build_pack};
+use crate::pack_refs::git_pack_refs;
+use crate::refs::list_refs;
+use crate::repository::Repository;
+use crate::revwalk::RevWalk;
+use crate::transfer::loose_object_path;
+use anyhow::{Context, Result, bail};
+use chrono::{Local, NaiveDate};
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, Permissions, create_dir_all, read, read_dir, remove_dir, remove_file};
+use std::io::{Cursor, ErrorKind, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tempfile::NamedTempFile;
+
+/// How long unreachable objects are kept by default, as git's `gc.pruneExpire` is.
+const DEFAULT_PRUNE_EXPIRE: &str = "2.weeks.ago";
+
+/// How many objects each is tried as a delta against with `--aggressive`, as git's
+/// `gc.aggressiveWindow` defaults to.
+const DEFAULT_AGGRESSIVE_WINDOW: u64 = 250;
+
+/// How long chains of deltas may get with `--aggressive`, as git's
+/// `gc.aggressiveDepth` defaults to.
+const DEFAULT_AGGRESSIVE_DEPTH: u64 = 50;
+
+/// How `git gc` repacks and prunes.
+#[derive(Debug, Clone, Default)]
+pub struct GcOptions {
+    /// Look for deltas much harder, within `gc.aggressiveWindow` (250) and
+    /// `gc.aggressiveDepth` (50) (`--aggressive`).
+    pub aggressive: bool,
+    /// Prune unreachable objects older than this instead of `gc.pruneExpire`
+    /// (`--prune=<date>`; `never` with `--no-prune`).
+    pub prune: Option<String>,
+    /// Keep the unreachable objects that aren't pruned in a cruft pack, each with its
+    /// modification time, instead of loose (`--cruft`, or `gc.cruftPacks`).
+    pub cruft: bool,
+}
+
+/// `git gc [--aggressive] [--prune=<date> | --no-prune] [--cruft]`: pack the refs,
+/// then repack the repository into a single pack of everything reachable, and prune
+/// the unreachable objects older than `prune`, or `gc.pruneExpire` (two weeks by
+/// default; `never` keeps them all).
+///
+/// What is reachable is what a [`RevWalk`] reaches from every ref, `HEAD` and the
+/// reflogs, and the blobs in the index. It is all packed afresh, deltas found as
+/// `git pack-objects` finds them within `pack.window` and `pack.depth` (or the larger
+/// aggressive ones), and the old packs and loose objects are then removed. A pack with
+/// a `.keep` file is left as it is, and what it has isn't packed again.
+///
+/// An unreachable object is kept unless it is older than the grace period: a loose one
+/// by its file's modification time, a packed one by its pack's, or in a cruft pack by
+/// the time that records for it. With `cruft` the survivors are packed into a new
+/// cruft pack, whose `.mtimes` file keeps those times; otherwise they are left loose,
+/// a packed one written out with its time, so that either way it expires when it
+/// would have.
+pub fn git_gc(repo: &Repository, config: &Config, options: &GcOptions) -> Result<()> {
+    let expire = match &options.prune {
+        Some(prune) => match parse_expiry(prune) {
+            Some(expire) => expire,
+            None => bail!("failed to parse prune expiry value {prune}"),
+        },
+        None => {
+            let prune_expire = config.get("gc.pruneExpire").unwrap_or(DEFAULT_PRUNE_EXPIRE);
+            match parse_expiry(prune_expire) {
+                Some(expire) => expire,
+                None => bail!("invalid gc.pruneExpire: '{prune_expire}'"),
+            }
+        }
+    };
+    let pack_options = match options.aggressive {
+        true => {
+            let window = config.get_size("gc.aggressiveWindow")?;
+            let depth = config.get_size("gc.aggressiveDepth")?;
+            PackObjectsOptions {
+                window: Some(window.unwrap_or(DEFAULT_AGGRESSIVE_WINDOW) as usize),
+                depth: Some(depth.unwrap_or(DEFAULT_AGGRESSIVE_DEPTH) as usize),
+                ..PackObjectsOptions::default()
+            }
+        }
+        false => PackObjectsOptions::default(),
+    };
+    git_pack_refs(repo, true, true)?;
+    repack(repo, config, &pack_options, expire, options.cruft)
+}
+
+/// The time `date` names, as `gc.pruneExpire` gives it: `never` for no time at all
+/// (`None` inside), `now`, a relative time like `2.weeks.ago` or `3 days ago`, or a
+/// date such as `2024-01-31` or any that [`parse_date`] understands. `None` if it is
+/// none of those.
+pub fn parse_expiry(date: &str) -> Option<Option<i64>> {
+    let (now, _) = now_and_timezone();
+    match date.trim() {
+        "never" | "false" => return Some(None),
+        "now" | "all" => return Some(Some(now)),
+        _ => {}
+    }
+    if let Some(ago) = parse_relative(date) {
+        return Some(Some(now - ago));
+    }
+    if let Ok((timestamp, _)) = parse_date(date) {
+        return Some(Some(timestamp));
+    }
+    let day = NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d").ok()?;
+    let midnight = day
+        .and_hms_opt(0, 0, 0)?
+        .and_local_timezone(Local)
+        .earliest()?;
+    Some(Some(midnight.timestamp()))
+}
+
+/// How many seconds ago a relative time like `2.weeks.ago`, `1.week.3.days` or
+/// `90 minutes ago` is: numbers each followed by a unit, then `ago` if wanted.
+fn parse_relative(date: &str) -> Option<i64> {
+    let mut words: Vec<&str> = date
+        .split(['.', ' ', '_'])
+        .filter(|word| !word.is_empty())
+        .collect();
+    if words.last() == Some(&"ago") {
+        words.pop();
+    }
+    if words.is_empty() || !words.len().is_multiple_of(2) {
+        return None;
+    }
+    let mut seconds = 0;
+    for pair in words.chunks(2) {
+        let count: i64 = pair[0].parse().ok()?;
+        let unit = match pair[1].strip_suffix('s').unwrap_or(pair[1]) {
+            "second" => 1,
+            "minute" => 60,
+            "hour" => 60 * 60,
+            "day" => 24 * 60 * 60,
+            "week" => 7 * 24 * 60 * 60,
+            "month" => 30 * 24 * 60 * 60,
+            "year" => 365 * 24 * 60 * 60,
+            _ => return None,
+        };
+        seconds += count.checked_mul(unit)?;
+    }
+    Some(seconds)
+}
+
+/// Pack everything reachable into one new pack, as `pack_options` say, replacing the
+/// old packs but those kept and the loose objects, and keep only the unreachable
+/// objects last written after `expire` (all of them if it is `None`): in a cruft
+/// pack with `cruft`, else loose.
+fn repack(
+    repo: &Repository,
+    config: &Config,
+    pack_options: &PackObjectsOptions,
+    expire: Option<i64>,
+    cruft: bool,
+) -> Result<()> {
+    let objects_dir = repo.objects_dir();
+    let pack_dir = objects_dir.join("pack");
+    let algorithm = repo.hash_algorithm()?;
+    let (kept_packs, old_packs): (Vec<PathBuf>, Vec<PathBuf>) = pack_indexes(&pack_dir)?
+        .into_iter()
+        .partition(|path| path.with_extension("keep").exists());
+    let mut kept = HashSet::new();
+    for index_path in &kept_packs {
+        kept.extend(
+            Pack::open(index_path, algorithm)?
+                .ids()
+                .map(|id| id.to_string()),
+        );
+    }
+    let (mut listed, commits) = reachable_objects(repo)?;
+    let reachable: HashSet<String> = listed.iter().map(|(hash, _)| hash.clone()).collect();
+    listed.retain(|(hash, _)| !kept.contains(hash));
+    // Bitmaps need everything reachable in the one pack, which a kept pack prevents.
+    let write_bitmaps = match config.get_bool("repack.writeBitmaps")? {
+        Some(write_bitmaps) => write_bitmaps,
+        None => config.get_bool("core.bare")?.unwrap_or(false),
+    };
+    if write_bitmaps && !kept.is_empty() && config.get("repack.writeBitmaps").is_some() {
+        eprintln!("warning: disabling bitmap writing, as some objects are not being packed");
+    }
+
+    let mut new_index = None;
+    if !listed.is_empty() {
+        let (pack, entries, checksum) = build_pack(repo, config, listed, pack_options)?;
+        create_dir_all(&pack_dir)
+            .with_context(|| format!("failed to create {}", pack_dir.display()))?;
+        write_pack_files(
+            &pack_dir.join("pack"),
+            &pack,
+            &write_index(&entries, &checksum),
+            &checksum,
+        )?;
+        forget_packs(&objects_dir);
+        let index_path = pack_dir.join(format!("pack-{checksum}.idx"));
+        if write_bitmaps && kept.is_empty() {
+            write_bitmap(
+                repo,
+                &index_path,
+                &bitmap_commits(repo, &commits)?,
+                algorithm,
+            )?;
+        }
+        new_index = Some(index_path);
+    }
+
+    // The unreachable objects still within the grace period, each with when it was
+    // last written: by its loose file, or the pack it is in, or the time a cruft pack
+    // records for it.
+    let loose = LooseOdb::new(&objects_dir);
+    let old_packs: Vec<&PathBuf> = old_packs
+        .iter()
+        .filter(|path| Some(*path) != new_index.as_ref())
+        .collect();
+    let unreachable = |hash: &str| !reachable.contains(hash) && !kept.contains(hash);
+    let mut survivors: HashMap<String, i64> = HashMap::new();
+    let mut survive = |hash: String, mtime: i64| {
+        if !expired(mtime, expire) {
+            let latest = survivors.entry(hash).or_insert(mtime);
+            *latest = mtime.max(*latest);
+        }
+    };
+    for index_path in &old_packs {
+        let pack = Pack::open(index_path, algorithm)?;
+        let pack_mtime = seconds(index_path.with_extension("pack").metadata()?.modified()?);
+        let mtimes = read_mtimes(&index_path.with_extension("mtimes"), pack.ids().count())?;
+        for (n, id) in pack.ids().enumerate() {
+            let hash = id.to_string();
+            if unreachable(&hash) {
+                let mtime = mtimes
+                    .as_ref()
+                    .map_or(pack_mtime, |mtimes| i64::from(mtimes[n]));
+                survive(hash, mtime);
+            }
+        }
+    }
+    for id in loose.ids()? {
+        let hash = id.to_string();
+        if unreachable(&hash) {
+            let path = loose_object_path(&objects_dir, &hash);
+            survive(hash, seconds(path.metadata()?.modified()?));
+        }
+    }
+
+    // They go into a cruft pack, or else the packed ones are written loose, with the
+    // time they were last written so that they expire when they would have.
+    let mut cruft_index = None;
+    match cruft {
+        true if !survivors.is_empty() => {
+            let mut survivors: Vec<(&String, &i64)> = survivors.iter().collect();
+            survivors.sort();
+            let listed = survivors
+                .iter()
+                .map(|(hash, _)| (hash.to_string(), None))
+                .collect();
+            let (pack, entries, checksum) = build_pack(repo, config, listed, pack_options)?;
+            create_dir_all(&pack_dir)
+                .with_context(|| format!("failed to create {}", pack_dir.display()))?;
+            let mtimes: Vec<u32> = survivors
+                .iter()
+                .map(|(_, mtime)| (**mtime).clamp(0, i64::from(u32::MAX)) as u32)
+                .collect();
+            let mtimes_path = pack_dir.join(format!("pack-{checksum}.mtimes"));
+            write_mtimes(&mtimes_path, &mtimes, &checksum, algorithm)?;
+            write_pack_files(
+                &pack_dir.join("pack"),
+                &pack,
+                &write_index(&entries, &checksum),
+                &checksum,
+            )?;
+            cruft_index = Some(pack_dir.join(format!("pack-{checksum}.idx")));
+        }
+        true => {}
+        false => {
+            for (hash, mtime) in &survivors {
+                if loose.contains(hash) {
+                    continue;
+                }
+                let mut object = Object::read_git_object(repo, hash)?;
+                let data = object.read_all()?;
+                loose.write(object.kind, data.len() as u64, &mut Cursor::new(data))?;
+                let modified = UNIX_EPOCH + Duration::from_secs((*mtime).max(0) as u64);
+                File::open(loose_object_path(&objects_dir, hash))?.set_modified(modified)?;
+            }
+        }
+    }
+    for index_path in old_packs
+        .iter()
+        .filter(|path| Some(**path) != cruft_index.as_ref())
+    {
+        for extension in ["pack", "idx", "rev", "bitmap", "mtimes"] {
+            remove_if_present(&index_path.with_extension(extension))?;
+        }
+    }
+    forget_packs(&objects_dir);
+
+    // What is reachable is in the new pack now, or a kept one, and what survives is in
+    // the cruft pack if there is one.
+    for id in loose.ids()? {
+        let hash = id.to_string();
+        if cruft || !survivors.contains_key(&hash) {
+            let path = loose_object_path(&objects_dir, &hash);
+            remove_file(&path).with_context(|| format!("failed to remove {}", path.display()))?;
+            // Remove the fan-out directory too once it is empty, as git does.
+            if let Some(dir) = path.parent() {
+                let _ = remove_dir(dir);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether something last written at `mtime` is older than `expire`, and so may go.
+fn expired(mtime: i64, expire: Option<i64>) -> bool {
+    expire.is_some_and(|expire| mtime <= expire)
+}
+
+/// `time` in seconds since the epoch.
+fn seconds(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs() as i64
+}
+
+/// The modification times a cruft pack's `.mtimes` file at `path` records for its
+/// `count` objects, in the order of its index; `None` if there is no such file, as for
+/// any pack that isn't a cruft pack.
+fn read_mtimes(path: &Path, count: usize) -> Result<Option<Vec<u32>>> {
+    let data = match read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+    };
+    let table = data.get(12..12 + 4 * count);
+    let Some(table) = table.filter(|_| data.starts_with(b"MTME\0\0\0\x01")) else {
+        bail!("{} is not a valid mtimes file", path.display());
+    };
+    Ok(Some(
+        table
+            .chunks(4)
+            .map(|mtime| u32::from_be_bytes(mtime.try_into().unwrap()))
+            .collect(),
+    ))
+}
+
+/// Write the `.mtimes` file of the cruft pack `pack_checksum` to `path`, unless it is
+/// there already: a `MTME` header with the version and the hash function (1 for SHA-1,
+/// 2 for SHA-256), each object's time in the order of the pack's index, then the
+/// pack's checksum and one of the file itself.
+fn write_mtimes(
+    path: &Path,
+    mtimes: &[u32],
+    pack_checksum: &ObjectId,
+    algorithm: HashAlgorithm,
+) -> Result<()> {
+    if path.is_file() {
+        return Ok(());
+    }
+    let mut data = b"MTME\0\0\0\x01".to_vec();
+    let hash_function: u32 = match algorithm {
+        HashAlgorithm::Sha1 => 1,
+        HashAlgorithm::Sha256 => 2,
+    };
+    data.extend(hash_function.to_be_bytes());
+    for mtime in mtimes {
+        data.extend(mtime.to_be_bytes());
+    }
+    data.extend(pack_checksum.as_bytes());
+    let checksum = algorithm.digest(&data);
+    data.extend(checksum.as_bytes());
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut file = NamedTempFile::new_in(dir)?;
+    file.write_all(&data)?;
+    file.as_file()
+        .set_permissions(Permissions::from_mode(0o444))?;
+    file.persist(path)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(())
+}
+
+/// Objects to pack, each with the path it was reached at if any.
+type ListedObjects = Vec<(String, Option<String>)>;
+
+/// Every object reachable in `repo`, with the path it was reached at if any: what a
+/// [`RevWalk`] of the refs, `HEAD` and the objects in the reflogs reaches, then the
+/// blobs in the index that those don't have. A ref or reflog entry whose object is
+/// missing is passed over. The commits among them come second, newest first.
+fn reachable_objects(repo: &Repository) -> Result<(ListedObjects, Vec<String>)> {
+    let objects_dir = repo.objects_dir();
+    let mut walk = RevWalk {
+        objects: true,
+        ..RevWalk::default()
+    };
+    walk.push_refs(repo)?;
+    walk.push_reflogs(repo)?;
+    let reached = walk.walk(repo)?;
+    let commits = reached
+        .iter()
+        .filter(|object| object.kind == ObjectKind::Commit)
+        .map(|object| object.hash.clone())
+        .collect();
+    let mut listed: Vec<(String, Option<String>)> = reached
+        .into_iter()
+        .map(|object| (object.hash, object.name))
+        .collect();
+    let mut seen: HashSet<String> = listed.iter().map(|(hash, _)| hash.clone()).collect();
+    for entry in Index::load(repo)?.entries {
+        let hash = entry.id.to_string();
+        if entry.mode != 0o160000 && has_object(&objects_dir, &hash) && seen.insert(hash.clone()) {
+            listed.push((hash, Some(entry.path)));
+        }
+    }
+    Ok((listed, commits))
+}
+
+/// How many commits apart, along history newest first, [`bitmap_commits`] picks them.
+const BITMAP_INTERVAL: usize = 100;
+
+/// The commits of `commits` (all those reachable, newest first) to store bitmaps for:
+/// those the refs point at, so that whatever is reachable from refs is counted at
+/// once, and every [`BITMAP_INTERVAL`]th one, so that from any other commit only a
+/// short walk reaches one with a bitmap. They come newest first.
+fn bitmap_commits(repo: &Repository, commits: &[String]) -> Result<Vec<ObjectId>> {
+    let mut tips = HashSet::new();
+    for (name, _) in list_refs(repo, "refs")? {
+        if let Ok((hash, _)) = Object::peel_to(repo, &name, ObjectKind::Commit) {
+            tips.insert(hash);
+        }
+    }
+    commits
+        .iter()
+        .enumerate()
+        .filter(|(n, hash)| tips.contains(*hash) || n % BITMAP_INTERVAL == BITMAP_INTERVAL - 1)
+        .map(|(_, hash)| hash.parse())
+        .collect()
+}
+
+/// The index files of the packs in `pack_dir`, each with its `.pack`.
+fn pack_indexes(pack_dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = match read_dir(pack_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", pack_dir.display())),
+    };
+    let mut indexes = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "idx") && path.with_extension("pack").is_file()
+        {
+            indexes.push(path);
+        }
+    }
+    indexes.sort();
+    Ok(indexes)
+}
+
+fn remove_if_present(path: &Path) -> Result<()> {
+    match remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("failed to remove {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,122 @@
+use crate::config::Config;
+use anyhow::{Context, Result, bail};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use tempfile::NamedTempFile;
+
+/// Where an armored OpenPGP signature appended to a payload starts.
+pub const PGP_SIGNATURE_START: &str = "-----BEGIN PGP SIGNATURE-----";
+
+/// The outcome of checking a signature, with git's one-letter codes: `G` good, `B`
+/// bad, `U` good but from an untrusted key, `X` good but expired, `Y` good but made by
+/// an expired key, `R` good but made by a revoked key, `E` impossible to check (e.g.
+/// missing key), `N` no signature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignatureCheck {
+    pub status: char,
+    /// The signer's user id, e.g. `A U Thor <author@example.com>`.
+    pub signer: Option<String>,
+    /// The id of the key that made the signature.
+    pub key: Option<String>,
+}
+
+fn gpg_program(config: &Config) -> String {
+    config
+        .get("gpg.program")
+        .or_else(|| config.get("gpg.openpgp.program"))
+        .unwrap_or("gpg")
+        .to_string()
+}
+
+/// Make a detached, armored signature of `payload` with `key` (a key id or user id).
+pub fn sign_buffer(config: &Config, payload: &[u8], key: &str) -> Result<String> {
+    let program = gpg_program(config);
+    let mut child = Command::new(&program)
+        .args(["--status-fd=2", "-bsau", key])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to run {program}"))?;
+    child
+        .stdin
+        .take()
+        .context("gpg has no stdin")?
+        .write_all(payload)
+        .context("failed to write to gpg")?;
+    let output = child.wait_with_output().context("failed to wait for gpg")?;
+    let status = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success()
+        || !status
+            .lines()
+            .any(|line| line.starts_with("[GNUPG:] SIG_CREATED "))
+    {
+        bail!("gpg failed to sign the data:\n{}", status.trim_end());
+    }
+    String::from_utf8(output.stdout).context("gpg produced a signature that isn't UTF-8")
+}
+
+/// Check `signature`, a detached signature of `payload`.
+pub fn verify_signed_buffer(
+    config: &Config,
+    payload: &str,
+    signature: &str,
+) -> Result<SignatureCheck> {
+    let mut signature_file = NamedTempFile::new()?;
+    signature_file.write_all(signature.as_bytes())?;
+    let program = gpg_program(config);
+    let mut child = Command::new(&program)
+        .args(["--status-fd=1", "--keyid-format=long", "--verify"])
+        .arg(signature_file.path())
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("failed to run {program}"))?;
+    child
+        .stdin
+        .take()
+        .context("gpg has no stdin")?
+        .write_all(payload.as_bytes())
+        .context("failed to write to gpg")?;
+    let output = child.wait_with_output().context("failed to wait for gpg")?;
+    Ok(parse_gpg_status(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Interpret the `[GNUPG:]` status lines of `gpg --verify`.
+fn parse_gpg_status(output: &str) -> SignatureCheck {
+    let mut check = SignatureCheck {
+        status: 'N',
+        signer: None,
+        key: None,
+    };
+    let mut untrusted = false;
+    for line in output.lines() {
+        let Some(line) = line.strip_prefix("[GNUPG:] ") else {
+            continue;
+        };
+        let (keyword, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let status = match keyword {
+            "GOODSIG" => 'G',
+            "BADSIG" => 'B',
+            "EXPSIG" => 'X',
+            "EXPKEYSIG" => 'Y',
+            "REVKEYSIG" => 'R',
+            "ERRSIG" => 'E',
+            "TRUST_NEVER" => {
+                untrusted = true;
+                continue;
+            }
+            _ => continue,
+        };
+        check.status = status;
+        let (key, signer) = rest.split_once(' ').unwrap_or((rest, ""));
+        check.key = Some(key.to_string());
+        check.signer = (status != 'E' && !signer.is_empty()).then(|| signer.to_string());
+    }
+    if untrusted && check.status == 'G' {
+        check.status = 'U';
+    }
+    check
+}
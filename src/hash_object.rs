@@ -1,6 +1,11 @@
-use crate::object_read::Object;
-use anyhow::Result;
-use std::io::sink;
+use crate::commit_read::Commit;
+use crate::ls_tree::read_tree_entries;
+use crate::object_id::ObjectId;
+use crate::object_read::{Object, ObjectKind};
+use crate::repository::Repository;
+use anyhow::{Context, Result, anyhow, bail};
+use std::fs;
+use std::io::{Cursor, Read, sink, stdin};
 use std::path::Path;
 
 /// In Git, each file is stored as a *blob object*.
@@ -29,10 +34,85 @@ use std::path::Path;
 /// Note: Git only stores the file *contents* in the blob —
 ///       file names and permissions are stored in *tree objects*.
 ///
-pub fn git_hash_object(file: &Path, write: bool) -> Result<[u8; 20]> {
+/// Other kinds of objects (`-t`) are hashed the same way, with their own type in the
+/// header; their content must be well-formed, as [`check_format`] checks it.
+pub fn git_hash_object(
+    repo: &Repository,
+    file: &Path,
+    kind: ObjectKind,
+    write: bool,
+) -> Result<ObjectId> {
+    if kind != ObjectKind::Blob {
+        let data = fs::read(file).map_err(|e| anyhow!("error reading provided file path: {e}"))?;
+        return hash_data(repo, kind, data, write);
+    }
     if write {
-        Object::from_blob_file(file)?.write_as_object()
+        Object::from_blob_file(file)?.write_as_object(repo)
     } else {
-        Object::from_blob_file(file)?.write(sink())
+        Object::from_blob_file(file)?.write(repo.hash_algorithm()?, sink())
+    }
+}
+
+/// `git hash-object --stdin`: [`git_hash_object`] for what standard input holds.
+pub fn git_hash_object_stdin(repo: &Repository, kind: ObjectKind, write: bool) -> Result<ObjectId> {
+    let mut data = Vec::new();
+    stdin()
+        .read_to_end(&mut data)
+        .context("failed to read from stdin")?;
+    hash_data(repo, kind, data, write)
+}
+
+fn hash_data(repo: &Repository, kind: ObjectKind, data: Vec<u8>, write: bool) -> Result<ObjectId> {
+    check_format(repo, &kind, &data)?;
+    let mut object = Object {
+        kind,
+        expected_size: data.len() as u64,
+        reader: Cursor::new(data),
+    };
+    if write {
+        object.write_as_object(repo)
+    } else {
+        object.write(repo.hash_algorithm()?, sink())
+    }
+}
+
+/// Refuse `data` as an object of kind `kind` if git couldn't parse it as one: a tree
+/// must be a sequence of entries, a commit must have its headers, and a tag must
+/// start with its `object` and `type` lines. Blobs can hold anything.
+fn check_format(repo: &Repository, kind: &ObjectKind, data: &[u8]) -> Result<()> {
+    match kind {
+        ObjectKind::Blob => {}
+        ObjectKind::Tree => {
+            let object = Object {
+                kind: ObjectKind::Tree,
+                expected_size: data.len() as u64,
+                reader: Cursor::new(data).take(data.len() as u64),
+            };
+            if read_tree_entries(repo, object).is_err() {
+                bail!("corrupt tree file");
+            }
+        }
+        ObjectKind::Commit => {
+            let null = repo.hash_algorithm()?.null_id().to_string();
+            if Commit::parse(&null, data).is_err() {
+                bail!("corrupt commit");
+            }
+        }
+        ObjectKind::Tag => {
+            let algorithm = repo.hash_algorithm()?;
+            let mut lines = data.split(|&b| b == b'\n');
+            let object = lines.next().and_then(|line| line.strip_prefix(b"object "));
+            let kind = lines.next().and_then(|line| line.strip_prefix(b"type "));
+            let valid = object
+                .and_then(|id| std::str::from_utf8(id).ok())
+                .is_some_and(|id| algorithm.is_hex_id(id))
+                && kind
+                    .and_then(|kind| std::str::from_utf8(kind).ok())
+                    .is_some_and(|kind| kind.parse::<ObjectKind>().is_ok());
+            if !valid {
+                bail!("corrupt tag");
+            }
+        }
     }
+    Ok(())
 }
@@ -0,0 +1,538 @@
+use crate::commit_read::{Commit, Walk, walk_commits_with};
+use crate::diff::trees_differ_under;
+use crate::repository::Repository;
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+
+/// How a path-limited history is simplified, as git-log(1)'s "History Simplification"
+/// describes. A commit is TREESAME to a parent if the two have the same content under
+/// the paths, and a commit without parents if it has nothing under them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Simplification {
+    /// Show the commits that aren't TREESAME, following only the first parent a merge
+    /// is TREESAME to, if any: the history of the content, without the side branches
+    /// that didn't contribute to it.
+    #[default]
+    Default,
+    /// Follow every parent, and show the commits that aren't TREESAME to all of theirs
+    /// (`--full-history`).
+    FullHistory,
+    /// Follow every parent, then drop the merges that became redundant once the
+    /// commits that aren't shown are passed over (`--simplify-merges`). Commits come
+    /// in topological order.
+    SimplifyMerges,
+}
+
+impl Simplification {
+    /// The simplification `--full-history` and `--simplify-merges` ask for; the
+    /// latter wins when both are given.
+    pub fn from_flags(full_history: bool, simplify_merges: bool) -> Simplification {
+        match (simplify_merges, full_history) {
+            (true, _) => Simplification::SimplifyMerges,
+            (false, true) => Simplification::FullHistory,
+            (false, false) => Simplification::Default,
+        }
+    }
+}
+
+/// The commits of `git log <tips> ^<hide> -- <paths>`: those reachable from `tips`
+/// but not `hide`, newest first, simplified to the paths as `simplification` says.
+/// Without paths every commit is shown. With `first_parent`, only the first parent of
+/// merges is followed, and merges are compared with it alone.
+///
+/// Parents are rewritten as simplification passes over commits, so that a shown merge
+/// lists the parents that are shown in their place.
+pub fn simplified_history(
+    repo: &Repository,
+    tips: &[String],
+    hide: &[String],
+    paths: &[String],
+    simplification: Simplification,
+    first_parent: bool,
+) -> Result<Vec<Commit>> {
+    let simplify_history = simplification == Simplification::Default;
+    let mut treesame: HashSet<String> = HashSet::new();
+    // Which parents each merge is TREESAME to, unless it was simplified away.
+    let mut parent_treesame: HashMap<String, Vec<bool>> = HashMap::new();
+    let mut walk = walk_commits_with(repo, tips, hide, first_parent, |commit, relevant| {
+        if paths.is_empty() {
+            return Ok(());
+        }
+        let tree = commit.tree.parse()?;
+        if commit.parents.is_empty() {
+            if !trees_differ_under(repo, None, Some(&tree), paths)? {
+                treesame.insert(commit.hash.clone());
+            }
+            return Ok(());
+        }
+        let (mut relevant_parents, mut relevant_change, mut irrelevant_change) = (0, false, false);
+        let mut same: Option<Vec<bool>> = None;
+        for n in 0..commit.parents.len() {
+            let parent = commit.parents[n].clone();
+            if relevant(&parent) {
+                relevant_parents += 1;
+            }
+            if n == 1 {
+                if first_parent {
+                    break;
+                }
+                if !simplify_history {
+                    let mut initial = vec![false; commit.parents.len()];
+                    initial[0] = !relevant_change && !irrelevant_change;
+                    same = Some(initial);
+                }
+            }
+            let parent_tree = Commit::read(repo, &parent)?.tree.parse()?;
+            if !trees_differ_under(repo, Some(&parent_tree), Some(&tree), paths)? {
+                // Don't follow a merge into a hidden side branch that brought the
+                // paths in, losing its other parents.
+                if !simplify_history || !relevant(&parent) {
+                    if let Some(same) = &mut same {
+                        same[n] = true;
+                    }
+                    continue;
+                }
+                commit.parents = vec![parent];
+                treesame.insert(commit.hash.clone());
+                return Ok(());
+            }
+            match relevant(&parent) {
+                true => relevant_change = true,
+                false => irrelevant_change = true,
+            }
+        }
+        // Irrelevant parents only count if there are no relevant ones.
+        let unchanged = match relevant_parents {
+            0 => !irrelevant_change,
+            _ => !relevant_change,
+        };
+        if unchanged {
+            treesame.insert(commit.hash.clone());
+        }
+        if let Some(same) = same {
+            parent_treesame.insert(commit.hash.clone(), same);
+        }
+        Ok(())
+    })?;
+
+    let commits = std::mem::take(&mut walk.commits);
+    let mut simplifier = Simplifier {
+        paths,
+        first_parent,
+        trees: commits
+            .iter()
+            .map(|commit| (commit.hash.clone(), commit.tree.clone()))
+            .collect(),
+        parents: commits
+            .iter()
+            .map(|commit| (commit.hash.clone(), commit.parents.clone()))
+            .collect(),
+        treesame,
+        parent_treesame,
+        walk,
+        simplified: HashMap::new(),
+    };
+    // Merges can become TREESAME once parents they differ from turn out to be hidden.
+    if !paths.is_empty() && !simplify_history && !first_parent {
+        for commit in &commits {
+            if !simplifier.is_hidden(&commit.hash) && !simplifier.treesame.contains(&commit.hash) {
+                simplifier.update_treesame(&commit.hash);
+            }
+        }
+    }
+    if simplification != Simplification::SimplifyMerges {
+        return Ok(commits
+            .into_iter()
+            .filter(|commit| {
+                !simplifier.is_hidden(&commit.hash) && !simplifier.treesame.contains(&commit.hash)
+            })
+            .collect());
+    }
+
+    let commits = topological_order(commits);
+    if !paths.is_empty() {
+        simplifier.simplify(repo, &commits)?;
+    }
+    let mut shown = Vec::new();
+    for mut commit in commits {
+        if simplifier.is_hidden(&commit.hash)
+            || !paths.is_empty() && simplifier.simplified[&commit.hash] != commit.hash
+        {
+            continue;
+        }
+        let mut parents = simplifier.parents[&commit.hash].clone();
+        // A TREESAME commit is kept only to join the history of two or more parents.
+        if simplifier.treesame.contains(&commit.hash)
+            && parents.iter().filter(|p| simplifier.is_relevant(p)).count() < 2
+        {
+            continue;
+        }
+        if !paths.is_empty() {
+            parents = simplifier.rewrite_parents(parents);
+        }
+        commit.parents = parents;
+        shown.push(commit);
+    }
+    Ok(shown)
+}
+
+/// The commits to walk from and to hide for `revisions`, as `git rev-list` reads
+/// them: `^A` hides the commits reachable from `A`, and `A..B` stands for `^A B`,
+/// either side defaulting to HEAD.
+pub fn revision_range(revisions: &[String]) -> (Vec<String>, Vec<String>) {
+    let mut tips = Vec::new();
+    let mut hide = Vec::new();
+    for revision in revisions {
+        if let Some((from, to)) = revision.split_once("..") {
+            hide.push(if from.is_empty() { "HEAD" } else { from }.to_string());
+            tips.push(if to.is_empty() { "HEAD" } else { to }.to_string());
+        } else if let Some(hidden) = revision.strip_prefix('^') {
+            hide.push(hidden.to_string());
+        } else {
+            tips.push(revision.clone());
+        }
+    }
+    (tips, hide)
+}
+
+/// Emit `commits` so that every commit comes before its parents, keeping to the
+/// order they were walked in otherwise: each shown commit's parents follow as soon as
+/// all their children have been, depth first, as `git log --topo-order` does.
+fn topological_order(commits: Vec<Commit>) -> Vec<Commit> {
+    let mut children: HashMap<String, usize> = commits
+        .iter()
+        .map(|commit| (commit.hash.clone(), 0))
+        .collect();
+    for commit in &commits {
+        for parent in &commit.parents {
+            if let Some(count) = children.get_mut(parent) {
+                *count += 1;
+            }
+        }
+    }
+    let mut by_hash: HashMap<String, Commit> = HashMap::new();
+    let mut stack = Vec::new();
+    for commit in commits.into_iter().rev() {
+        if children[&commit.hash] == 0 {
+            stack.push(commit.hash.clone());
+        }
+        by_hash.insert(commit.hash.clone(), commit);
+    }
+    let mut sorted = Vec::new();
+    while let Some(hash) = stack.pop() {
+        let commit = by_hash.remove(&hash).unwrap();
+        for parent in &commit.parents {
+            if let Some(count) = children.get_mut(parent)
+                && *count > 0
+            {
+                *count -= 1;
+                if *count == 0 {
+                    stack.push(parent.clone());
+                }
+            }
+        }
+        sorted.push(commit);
+    }
+    sorted
+}
+
+/// What simplification knows of the walked commits: their parents as they are
+/// rewritten, which are TREESAME (and to which parents), which are hidden, and for
+/// `--simplify-merges` the commit each simplifies to once known.
+struct Simplifier<'a> {
+    paths: &'a [String],
+    first_parent: bool,
+    trees: HashMap<String, String>,
+    parents: HashMap<String, Vec<String>>,
+    treesame: HashSet<String>,
+    parent_treesame: HashMap<String, Vec<bool>>,
+    walk: Walk,
+    simplified: HashMap<String, String>,
+}
+
+impl Simplifier<'_> {
+    fn is_hidden(&self, hash: &str) -> bool {
+        self.walk.hidden.contains(hash)
+    }
+
+    fn is_relevant(&self, hash: &str) -> bool {
+        self.walk.is_relevant(hash)
+    }
+
+    /// Work out what each of `commits` simplifies to, oldest first, putting a commit
+    /// off until its parents are done.
+    fn simplify(&mut self, repo: &Repository, commits: &[Commit]) -> Result<()> {
+        let mut pending: Vec<String> = commits.iter().rev().map(|c| c.hash.clone()).collect();
+        while !pending.is_empty() {
+            let mut later = Vec::new();
+            for hash in pending {
+                self.simplify_one(repo, &hash, &mut later)?;
+            }
+            pending = later;
+        }
+        Ok(())
+    }
+
+    fn simplify_one(
+        &mut self,
+        repo: &Repository,
+        hash: &str,
+        later: &mut Vec<String>,
+    ) -> Result<()> {
+        if self.simplified.contains_key(hash) {
+            return Ok(());
+        }
+        // Hidden commits and roots simplify to themselves.
+        let parents = self.parents.get(hash).filter(|parents| !parents.is_empty());
+        let Some(parents) = parents.filter(|_| !self.is_hidden(hash)) else {
+            self.simplified.insert(hash.to_string(), hash.to_string());
+            return Ok(());
+        };
+        let considered = if self.first_parent { 1 } else { parents.len() };
+        let waiting: Vec<String> = parents[..considered]
+            .iter()
+            .filter(|parent| !self.simplified.contains_key(*parent))
+            .cloned()
+            .collect();
+        if !waiting.is_empty() {
+            later.extend(waiting);
+            later.push(hash.to_string());
+            return Ok(());
+        }
+
+        let mut parents = parents.clone();
+        for parent in &mut parents[..considered] {
+            *parent = self.simplified[parent.as_str()].clone();
+        }
+        self.parents.insert(hash.to_string(), parents);
+        let mut count = match self.first_parent {
+            true => 1,
+            false => self.remove_duplicate_parents(repo, hash)?,
+        };
+        // A side branch that doesn't touch the paths now has the merge base (or a
+        // root without the paths) as its parent, which the merge needn't keep.
+        if count > 1 {
+            let mut marks = self.redundant_parents(repo, hash)?;
+            let mut marked = marks.iter().filter(|&&mark| mark).count();
+            for (n, parent) in self.parents[hash].iter().enumerate() {
+                let is_root = self.parents.get(parent).is_some_and(Vec::is_empty);
+                if is_root && self.treesame.contains(parent) {
+                    marks[n] = true;
+                    marked += 1;
+                }
+            }
+            if marked > 0 && self.leave_one_treesame_parent(hash, &mut marks) {
+                marked -= 1;
+            }
+            if marked > 0 {
+                count = self.remove_marked_parents(repo, hash, &marks)?;
+            }
+        }
+
+        let simplified = match self.one_relevant_parent(hash) {
+            Some(parent) if count > 0 && self.treesame.contains(hash) => {
+                self.simplified[&parent].clone()
+            }
+            _ => hash.to_string(),
+        };
+        self.simplified.insert(hash.to_string(), simplified);
+        Ok(())
+    }
+
+    /// Drop the repeats of parents rewritten to the same commit; returns how many
+    /// parents are left.
+    fn remove_duplicate_parents(&mut self, repo: &Repository, hash: &str) -> Result<usize> {
+        let mut seen = HashSet::new();
+        let mut n = 0;
+        while n < self.parents[hash].len() {
+            if seen.insert(self.parents[hash][n].clone()) {
+                n += 1;
+            } else {
+                self.parents.get_mut(hash).unwrap().remove(n);
+                self.compact_treesame(repo, hash, n)?;
+            }
+        }
+        Ok(n)
+    }
+
+    /// Which parents of `hash` are ancestors of another of its parents.
+    fn redundant_parents(&self, repo: &Repository, hash: &str) -> Result<Vec<bool>> {
+        let parents = &self.parents[hash];
+        let mut marks = vec![false; parents.len()];
+        for (n, parent) in parents.iter().enumerate() {
+            for other in parents {
+                if other != parent && self.is_ancestor(repo, parent, other)? {
+                    marks[n] = true;
+                    break;
+                }
+            }
+        }
+        Ok(marks)
+    }
+
+    /// Whether `ancestor` is reachable from `hash` through parents as they have been
+    /// rewritten so far, as git sees them: passing over a merge drops its irrelevant
+    /// parents from the history below it.
+    fn is_ancestor(&self, repo: &Repository, ancestor: &str, hash: &str) -> Result<bool> {
+        let mut seen = HashSet::from([hash.to_string()]);
+        let mut stack = vec![hash.to_string()];
+        while let Some(hash) = stack.pop() {
+            if hash == ancestor {
+                return Ok(true);
+            }
+            let parents = match self.parents.get(&hash) {
+                Some(parents) => parents.clone(),
+                None => Commit::read(repo, &hash)?.parents,
+            };
+            for parent in parents {
+                if seen.insert(parent.clone()) {
+                    stack.push(parent);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// If `hash` is TREESAME to some of the parents about to be removed but none of
+    /// the others, keep the first of them: it is the parent the default
+    /// simplification would have followed. Returns whether one was kept.
+    fn leave_one_treesame_parent(&self, hash: &str, marks: &mut [bool]) -> bool {
+        let Some(same) = self.parent_treesame.get(hash) else {
+            return false;
+        };
+        let mut first_marked = None;
+        for n in 0..marks.len() {
+            if same[n] {
+                if !marks[n] {
+                    return false;
+                }
+                first_marked = first_marked.or(Some(n));
+            }
+        }
+        match first_marked {
+            Some(n) => {
+                marks[n] = false;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove the marked parents of `hash`, which can make it TREESAME; returns how
+    /// many parents are left.
+    fn remove_marked_parents(
+        &mut self,
+        repo: &Repository,
+        hash: &str,
+        marks: &[bool],
+    ) -> Result<usize> {
+        let mut n = 0;
+        for &marked in marks {
+            if marked {
+                self.parents.get_mut(hash).unwrap().remove(n);
+                self.compact_treesame(repo, hash, n)?;
+            } else {
+                n += 1;
+            }
+        }
+        if marks.contains(&true) && !self.treesame.contains(hash) {
+            self.update_treesame(hash);
+        }
+        Ok(n)
+    }
+
+    /// Forget whether `hash` is TREESAME to its `n`th parent, just removed. A merge
+    /// left with one parent is TREESAME if it was to that one, and a commit left
+    /// without any if it has nothing under the paths.
+    fn compact_treesame(&mut self, repo: &Repository, hash: &str, n: usize) -> Result<()> {
+        let same = if self.parents[hash].is_empty() {
+            let tree = self.trees[hash].parse()?;
+            !trees_differ_under(repo, None, Some(&tree), self.paths)?
+        } else {
+            let Some(same) = self.parent_treesame.get_mut(hash) else {
+                return Ok(());
+            };
+            same.remove(n);
+            if same.len() > 1 {
+                return Ok(());
+            }
+            let same = same[0];
+            self.parent_treesame.remove(hash);
+            same
+        };
+        match same {
+            true => self.treesame.insert(hash.to_string()),
+            false => self.treesame.remove(hash),
+        };
+        Ok(())
+    }
+
+    /// Work out again whether the merge `hash` is TREESAME, from the parents it has
+    /// left; as when it was walked, irrelevant ones only count if all are.
+    fn update_treesame(&mut self, hash: &str) {
+        let parents = &self.parents[hash];
+        let Some(same) = self.parent_treesame.get(hash).filter(|_| parents.len() > 1) else {
+            return;
+        };
+        let (mut relevant_parents, mut relevant_change, mut irrelevant_change) = (0, false, false);
+        for (parent, &same) in parents.iter().zip(same) {
+            if self.is_relevant(parent) {
+                relevant_parents += 1;
+                relevant_change |= !same;
+            } else {
+                irrelevant_change |= !same;
+            }
+        }
+        let changed = match relevant_parents {
+            0 => irrelevant_change,
+            _ => relevant_change,
+        };
+        match changed {
+            true => self.treesame.remove(hash),
+            false => self.treesame.insert(hash.to_string()),
+        };
+    }
+
+    /// The parent `hash` simplifies through if it is TREESAME: its only parent (or
+    /// first, with `first_parent`), or its only relevant one.
+    fn one_relevant_parent(&self, hash: &str) -> Option<String> {
+        let parents = &self.parents[hash];
+        if self.first_parent || parents.len() == 1 {
+            return parents.first().cloned();
+        }
+        let mut relevant = parents.iter().filter(|parent| self.is_relevant(parent));
+        match (relevant.next(), relevant.next()) {
+            (Some(parent), None) => Some(parent.clone()),
+            _ => None,
+        }
+    }
+
+    /// The parents a shown commit is listed with: each passed over to the closest
+    /// ancestor that isn't TREESAME (roots without the paths being dropped), without
+    /// repeats.
+    fn rewrite_parents(&self, parents: Vec<String>) -> Vec<String> {
+        let mut rewritten: Vec<String> = Vec::new();
+        for mut parent in parents {
+            loop {
+                if self.is_hidden(&parent) || !self.treesame.contains(&parent) {
+                    break;
+                }
+                let Some(grandparents) = self.parents.get(&parent) else {
+                    break;
+                };
+                if grandparents.is_empty() {
+                    parent.clear();
+                    break;
+                }
+                match self.one_relevant_parent(&parent) {
+                    Some(next) => parent = next,
+                    None => break,
+                }
+            }
+            if !parent.is_empty() && !rewritten.contains(&parent) {
+                rewritten.push(parent);
+            }
+        }
+        rewritten
+    }
+}
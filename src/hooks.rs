@@ -0,0 +1,132 @@
+use crate::config::Config;
+use crate::repository::{Repository, git_path};
+use anyhow::{Context, Result};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Where and how the hooks of a repository run.
+pub struct Hooks {
+    /// `core.hooksPath`, or the `hooks` directory of the repository.
+    dir: PathBuf,
+    /// The directory hooks run in: the top of the working tree, or for the receiving
+    /// end of a push the repository itself.
+    cwd: PathBuf,
+    /// Prefix for lines the hooks print, `remote: ` on the receiving end of a push.
+    output_prefix: Option<&'static str>,
+    advise_ignored: bool,
+}
+
+impl Hooks {
+    /// The hooks of `repo`, run from the top of its working tree.
+    pub fn new(config: &Config, repo: &Repository) -> Hooks {
+        Hooks::with_cwd(config, &repo.git_dir, &repo.work_tree, None)
+    }
+
+    /// The hooks `receive-pack` runs in the repository at `git_dir`, from within it
+    /// and with their output shown as coming from the remote.
+    pub fn for_receive(config: &Config, git_dir: &Path) -> Hooks {
+        Hooks::with_cwd(config, git_dir, git_dir, Some("remote: "))
+    }
+
+    fn with_cwd(
+        config: &Config,
+        git_dir: &Path,
+        cwd: &Path,
+        output_prefix: Option<&'static str>,
+    ) -> Hooks {
+        // A relative core.hooksPath is relative to where the hooks run.
+        let dir = match config.get("core.hooksPath") {
+            Some(path) => cwd.join(path),
+            None => git_path(git_dir, "hooks"),
+        };
+        Hooks {
+            dir,
+            cwd: cwd.to_path_buf(),
+            output_prefix,
+            advise_ignored: config.get_bool("advice.ignoredHook").ok().flatten() != Some(false),
+        }
+    }
+
+    /// Run the hook `name` with `args`, feeding it `stdin` and adding `env` to its
+    /// environment, and return whether it allows the operation to go on: true if it
+    /// exited successfully or there is no such hook.
+    ///
+    /// A hook file that isn't executable is ignored, with a hint, as in git.
+    pub fn run(
+        &self,
+        name: &str,
+        args: &[&str],
+        stdin: &str,
+        env: &[(String, String)],
+    ) -> Result<bool> {
+        let path = self.dir.join(name);
+        let Ok(metadata) = path.metadata() else {
+            return Ok(true);
+        };
+        if metadata.permissions().mode() & 0o111 == 0 {
+            if self.advise_ignored {
+                eprintln!(
+                    "hint: The '{}' hook was ignored because it's not set as executable.",
+                    path.strip_prefix(&self.cwd).unwrap_or(&path).display()
+                );
+                eprintln!(
+                    "hint: You can disable this warning with `git config advice.ignoredHook false`."
+                );
+            }
+            return Ok(true);
+        }
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("failed to resolve {}", path.display()))?;
+
+        let mut command = Command::new(&path);
+        command
+            .args(args)
+            .current_dir(&self.cwd)
+            .envs(env.iter().cloned())
+            .stdin(Stdio::piped());
+        // Like git, hooks' standard output goes to standard error, so that it can't be
+        // mistaken for the output of the command running them.
+        let reader = match self.output_prefix {
+            Some(_) => {
+                let (reader, writer) = io::pipe()?;
+                command.stdout(writer.try_clone()?).stderr(writer);
+                Some(reader)
+            }
+            None => {
+                command.stdout(io::stderr());
+                None
+            }
+        };
+        let mut child = command
+            .spawn()
+            .with_context(|| format!("cannot run {}", path.display()))?;
+        // The pipe's write ends are held by `command` until it's dropped, and the
+        // output is only complete once every one of them is closed.
+        drop(command);
+
+        let mut input = child.stdin.take().context("hook has no stdin")?;
+        let stdin = stdin.to_string();
+        let feeder = thread::spawn(move || match input.write_all(stdin.as_bytes()) {
+            // A hook doesn't have to read what it's given.
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+            result => result,
+        });
+        if let (Some(reader), Some(prefix)) = (reader, self.output_prefix) {
+            for line in BufReader::new(reader).lines() {
+                eprintln!("{prefix}{}", line?);
+            }
+        }
+        let status = child
+            .wait()
+            .with_context(|| format!("failed to wait for {}", path.display()))?;
+        feeder
+            .join()
+            .expect("hook input thread panicked")
+            .with_context(|| format!("failed to write to {}", path.display()))?;
+        Ok(status.success())
+    }
+}
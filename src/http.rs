@@ -0,0 +1,490 @@
+use crate::object_id::HashAlgorithm;
+use crate::pkt_line::{Packet, PacketReader, PacketWriter, SidebandReader};
+use crate::receive::ReceiveRequest;
+use crate::remote::RemoteRefs;
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+
+/// What requests say they come from. Servers only speak the smart protocol to
+/// agents whose name starts with `git/`.
+const USER_AGENT: &str = concat!("git/git-rs-", env!("CARGO_PKG_VERSION"));
+
+/// A response to an HTTP request.
+struct Response {
+    status: u16,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+
+/// Whether `url` is one the smart HTTP transport fetches from, as opposed to a
+/// local repository.
+pub fn is_http_url(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+/// Make an HTTP/1.1 request of `method` for `url`, with `headers` and `body`, over
+/// a connection of its own, and read the whole response. Only `http://` URLs can
+/// be reached: there is no TLS. Errors don't name the URL.
+fn request(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: Option<&[u8]>,
+) -> Result<Response> {
+    let Some(rest) = url.strip_prefix("http://") else {
+        bail!("https isn't supported, only http");
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    if authority.contains('@') {
+        bail!("credentials in URLs aren't supported");
+    }
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => {
+            let port = port
+                .parse::<u16>()
+                .with_context(|| format!("bad port '{port}'"))?;
+            (host, port)
+        }
+        _ => (authority, 80),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let mut stream = TcpStream::connect((host, port))
+        .with_context(|| format!("failed to connect to {authority}"))?;
+
+    let mut head = format!(
+        "{method} {path} HTTP/1.1\r\nHost: {authority}\r\nUser-Agent: {USER_AGENT}\r\n\
+         Connection: close\r\n"
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    if let Some(body) = body {
+        head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    if let Some(body) = body {
+        stream.write_all(body)?;
+    }
+
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let status = line
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .with_context(|| format!("bad HTTP response '{}'", line.trim()))?;
+    let mut content_type = None;
+    let mut content_length = None;
+    let mut chunked = false;
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.to_ascii_lowercase().as_str() {
+            "content-type" => content_type = Some(value.to_string()),
+            "content-length" => content_length = value.parse::<u64>().ok(),
+            "transfer-encoding" => chunked = value.eq_ignore_ascii_case("chunked"),
+            _ => {}
+        }
+    }
+
+    let mut body = Vec::new();
+    if chunked {
+        loop {
+            line.clear();
+            reader.read_line(&mut line)?;
+            let size = line.trim().split(';').next().unwrap_or("");
+            let size = usize::from_str_radix(size, 16)
+                .with_context(|| format!("bad chunk size '{size}'"))?;
+            if size == 0 {
+                break;
+            }
+            let start = body.len();
+            body.resize(start + size, 0);
+            reader.read_exact(&mut body[start..])?;
+            line.clear();
+            reader.read_line(&mut line)?;
+        }
+    } else if let Some(length) = content_length {
+        reader.take(length).read_to_end(&mut body)?;
+        ensure!(body.len() as u64 == length, "the response was cut short");
+    } else {
+        reader.read_to_end(&mut body)?;
+    }
+    Ok(Response {
+        status,
+        content_type,
+        body,
+    })
+}
+
+/// Make a request of the smart HTTP protocol to `service` (`git-upload-pack` or
+/// `git-receive-pack`) of the repository at `url`: without a `body`, get the
+/// service's advertisement from `info/refs`, else post `body` to it. Fails the way
+/// git does unless the server answers with the content type of a git server.
+///
+/// Requests to `git-upload-pack` ask for protocol version 2; `git-receive-pack` only
+/// speaks version 0.
+fn smart_request(url: &str, service: &str, body: Option<&[u8]>) -> Result<Vec<u8>> {
+    let base = url.trim_end_matches('/');
+    let (method, path, content_type) = match body {
+        None => (
+            "GET",
+            format!("{base}/info/refs?service={service}"),
+            format!("application/x-{service}-advertisement"),
+        ),
+        Some(_) => (
+            "POST",
+            format!("{base}/{service}"),
+            format!("application/x-{service}-result"),
+        ),
+    };
+    let request_type = format!("application/x-{service}-request");
+    let mut headers = vec![("Accept", content_type.as_str())];
+    if service == "git-upload-pack" {
+        headers.push(("Git-Protocol", "version=2"));
+    }
+    if body.is_some() {
+        headers.push(("Content-Type", &request_type));
+    }
+    let response = request(method, &path, &headers, body)
+        .map_err(|e| anyhow!("unable to access '{base}/': {e:#}"))?;
+    match response.status {
+        200 => {}
+        404 => bail!("repository '{base}/' not found"),
+        401 | 403 => bail!("Authentication failed for '{base}/'"),
+        status => bail!("unable to access '{base}/': The requested URL returned error: {status}"),
+    }
+    if response.content_type.as_deref() != Some(content_type.as_str()) {
+        bail!("repository '{base}/' is not a smart HTTP repository: its server isn't git");
+    }
+    Ok(response.body)
+}
+
+/// What a protocol version 2 server said it can do, from its capability
+/// advertisement: each capability with its value, if it has one.
+struct Capabilities(BTreeMap<String, Option<String>>);
+
+impl Capabilities {
+    /// Ask the server at `url` what its `git-upload-pack` can do.
+    fn read(url: &str) -> Result<Capabilities> {
+        let body = smart_request(url, "git-upload-pack", None)?;
+        let mut packets = PacketReader::new(body.as_slice());
+        let mut lines = Vec::new();
+        while let Some(packet) = packets.read_packet()? {
+            if let Some(line) = packet.text()? {
+                lines.push(line.to_string());
+            }
+        }
+        // A server may announce the service first, as for protocol version 0.
+        if lines
+            .first()
+            .is_some_and(|line| line.starts_with("# service="))
+        {
+            lines.remove(0);
+        }
+        if lines.first().map(String::as_str) != Some("version 2") {
+            bail!("the server at '{url}' doesn't speak protocol version 2");
+        }
+        Ok(Capabilities(
+            lines[1..]
+                .iter()
+                .map(|line| match line.split_once('=') {
+                    Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                    None => (line.to_string(), None),
+                })
+                .collect(),
+        ))
+    }
+
+    fn has(&self, name: &str) -> bool {
+        self.0.contains_key(name)
+    }
+
+    /// Whether the capability `name` has `feature` among the words of its value.
+    fn has_feature(&self, name: &str, feature: &str) -> bool {
+        self.0
+            .get(name)
+            .and_then(Option::as_deref)
+            .is_some_and(|value| value.split(' ').any(|word| word == feature))
+    }
+
+    /// A request for `command` with `arguments`, with the capabilities everything
+    /// sends before its delimiter.
+    fn command(
+        &self,
+        command: &str,
+        algorithm: HashAlgorithm,
+        arguments: &[String],
+    ) -> Result<Vec<u8>> {
+        let mut body = PacketWriter::new(Vec::new());
+        body.write_line(&format!("command={command}\n"))?;
+        body.write_line(&format!("agent={USER_AGENT}\n"))?;
+        if self.has("object-format") {
+            body.write_line(&format!("object-format={}\n", algorithm.name()))?;
+        }
+        body.delim()?;
+        for argument in arguments {
+            body.write_line(&format!("{argument}\n"))?;
+        }
+        body.flush()?;
+        Ok(body.into_inner())
+    }
+}
+
+/// List the refs of the repository at `url` with the `ls-refs` command of
+/// protocol version 2, with what its HEAD points at and what its tags peel to.
+pub fn ls_refs(url: &str, algorithm: HashAlgorithm) -> Result<RemoteRefs> {
+    let capabilities = Capabilities::read(url)?;
+    ensure!(
+        capabilities.has("ls-refs"),
+        "the server at '{url}' can't list its refs"
+    );
+    let mut arguments = vec!["symrefs".to_string(), "peel".to_string()];
+    if capabilities.has_feature("ls-refs", "unborn") {
+        arguments.push("unborn".to_string());
+    }
+    let response = smart_request(
+        url,
+        "git-upload-pack",
+        Some(&capabilities.command("ls-refs", algorithm, &arguments)?),
+    )?;
+    let mut refs = BTreeMap::new();
+    let mut peeled = BTreeMap::new();
+    let mut head = None;
+    let mut packets = PacketReader::new(response.as_slice());
+    while let Some(line) = packets.read_line()? {
+        let mut words = line.split(' ');
+        let (Some(hash), Some(name)) = (words.next(), words.next()) else {
+            bail!("protocol error: bad ls-refs line");
+        };
+        for attribute in words {
+            if let Some(target) = attribute.strip_prefix("symref-target:")
+                && name == "HEAD"
+            {
+                head = Some(target.to_string());
+            } else if let Some(target) = attribute.strip_prefix("peeled:") {
+                peeled.insert(name.to_string(), target.to_string());
+            }
+        }
+        if name.starts_with("refs/") && hash != "unborn" {
+            refs.insert(name.to_string(), hash.to_string());
+        }
+    }
+    Ok(RemoteRefs {
+        git_dir: None,
+        head,
+        refs,
+        peeled,
+        capabilities: Vec::new(),
+    })
+}
+
+/// Fetch a pack of the objects reachable from `wants` but not from `haves` from
+/// the repository at `url`, with the `fetch` command of protocol version 2. All the
+/// haves are sent at once with `done`, so there is a single round of negotiation.
+/// Annotated tags of objects in the pack come in it too. The pack may be thin, with
+/// deltas against objects of `haves`, for the caller to complete.
+///
+/// What the server's side-band says of its progress is printed as `remote: ...`.
+pub fn fetch_pack(
+    url: &str,
+    algorithm: HashAlgorithm,
+    wants: &[String],
+    haves: &[String],
+) -> Result<Vec<u8>> {
+    let capabilities = Capabilities::read(url)?;
+    ensure!(
+        capabilities.has("fetch"),
+        "the server at '{url}' can't send objects"
+    );
+    let mut arguments = vec![
+        "no-progress".to_string(),
+        "thin-pack".to_string(),
+        "ofs-delta".to_string(),
+        "include-tag".to_string(),
+    ];
+    arguments.extend(wants.iter().map(|want| format!("want {want}")));
+    arguments.extend(haves.iter().map(|have| format!("have {have}")));
+    arguments.push("done".to_string());
+    let response = smart_request(
+        url,
+        "git-upload-pack",
+        Some(&capabilities.command("fetch", algorithm, &arguments)?),
+    )?;
+
+    // Sections other than the packfile (acknowledgments, say) come first, and are
+    // skipped; the packfile section is the last.
+    let mut packets = PacketReader::new(response.as_slice());
+    loop {
+        match packets.read_packet()? {
+            Some(Packet::Data(line)) if line == b"packfile\n" => break,
+            Some(_) => continue,
+            None => bail!("protocol error: the server sent no packfile"),
+        }
+    }
+    let mut pack = Vec::new();
+    SidebandReader::new(packets).read_to_end(&mut pack)?;
+    Ok(pack)
+}
+
+/// List the refs of the repository at `url` as its `git-receive-pack` advertises
+/// them, with the capabilities it has for pushes, which protocol version 0 gives
+/// after the first ref.
+pub fn receive_pack_refs(url: &str) -> Result<RemoteRefs> {
+    let body = smart_request(url, "git-receive-pack", None)?;
+    let mut refs = BTreeMap::new();
+    let mut capabilities = Vec::new();
+    let mut packets = PacketReader::new(body.as_slice());
+    while let Some(packet) = packets.read_packet()? {
+        let Some(line) = packet.text()? else {
+            continue;
+        };
+        if line.starts_with("# service=") {
+            continue;
+        }
+        let line = match line.split_once('\0') {
+            Some((line, advertised)) => {
+                capabilities = advertised.split(' ').map(str::to_string).collect();
+                line
+            }
+            None => line,
+        };
+        let Some((hash, name)) = line.split_once(' ') else {
+            bail!("protocol error: bad ref advertisement line");
+        };
+        // An empty repository advertises its capabilities on a made-up ref.
+        if name.starts_with("refs/") && !name.ends_with("^{}") {
+            refs.insert(name.to_string(), hash.to_string());
+        }
+    }
+    Ok(RemoteRefs {
+        git_dir: None,
+        head: None,
+        refs,
+        peeled: BTreeMap::new(),
+        capabilities,
+    })
+}
+
+/// Send `request` to the `git-receive-pack` of the repository at `url`, which
+/// advertised `capabilities`, with `pack` holding the objects its commands need,
+/// and return for each command whether it was applied or why it was refused, as
+/// the server's `report-status` says.
+///
+/// A certificate is sent as a `push-cert` block listing the commands, which the
+/// server reads from it. The server is asked not to report its progress, but what
+/// its side-band says (of its hooks, say) is printed as `remote: ...`.
+pub fn send_pack(
+    url: &str,
+    capabilities: &[String],
+    request: &ReceiveRequest,
+    pack: Option<&[u8]>,
+    algorithm: HashAlgorithm,
+) -> Result<Vec<Result<(), String>>> {
+    let side_band = capabilities.iter().any(|c| c == "side-band-64k");
+    let mut wanted = vec!["report-status"];
+    if side_band {
+        wanted.push("side-band-64k");
+    }
+    if request.atomic {
+        wanted.push("atomic");
+    }
+    if !request.push_options.is_empty() {
+        wanted.push("push-options");
+    }
+    if capabilities.iter().any(|c| c == "quiet") {
+        wanted.push("quiet");
+    }
+    let agent = format!("agent={USER_AGENT}");
+    wanted.push(&agent);
+    let wanted = wanted.join(" ");
+
+    let mut body = PacketWriter::new(Vec::new());
+    match &request.push_cert {
+        Some(cert) => {
+            body.write_line(&format!("push-cert\0{wanted}\n"))?;
+            for line in cert.split_inclusive('\n') {
+                body.write_line(line)?;
+            }
+            body.write_line("push-cert-end\n")?;
+        }
+        None => {
+            for (n, command) in request.commands.iter().enumerate() {
+                let line = command.line(algorithm);
+                body.write_line(&match n {
+                    0 => format!("{line}\0{wanted}\n"),
+                    _ => format!("{line}\n"),
+                })?;
+            }
+        }
+    }
+    body.flush()?;
+    if !request.push_options.is_empty() {
+        for option in &request.push_options {
+            body.write_line(&format!("{option}\n"))?;
+        }
+        body.flush()?;
+    }
+    if let Some(pack) = pack {
+        body.write_raw(pack)?;
+    }
+    let response = smart_request(url, "git-receive-pack", Some(&body.into_inner()))?;
+
+    let report = match side_band {
+        false => response,
+        true => {
+            let mut report = Vec::new();
+            SidebandReader::new(PacketReader::new(response.as_slice())).read_to_end(&mut report)?;
+            report
+        }
+    };
+
+    let mut statuses = BTreeMap::new();
+    let mut unpack = None;
+    let mut packets = PacketReader::new(report.as_slice());
+    while let Some(line) = packets.read_line()? {
+        if let Some(status) = line.strip_prefix("unpack ") {
+            unpack = Some(status.to_string());
+        } else if let Some(name) = line.strip_prefix("ok ") {
+            statuses.insert(name.to_string(), Ok(()));
+        } else if let Some(rest) = line.strip_prefix("ng ") {
+            let (name, reason) = rest.split_once(' ').unwrap_or((rest, "failed"));
+            statuses.insert(name.to_string(), Err(reason.to_string()));
+        }
+    }
+    match unpack.as_deref() {
+        Some("ok") => {}
+        Some(reason) => {
+            eprintln!("error: remote unpack failed: {reason}");
+            return Ok(vec![
+                Err("unpacker error".to_string());
+                request.commands.len()
+            ]);
+        }
+        None => bail!("protocol error: the server sent no report of the push"),
+    }
+    Ok(request
+        .commands
+        .iter()
+        .map(|command| {
+            statuses
+                .remove(&command.ref_name)
+                .unwrap_or_else(|| Err("remote failed to report status".to_string()))
+        })
+        .collect())
+}
@@ -0,0 +1,250 @@
+use crate::config::Config;
+use anyhow::{Context, Result, bail, ensure};
+use chrono::{DateTime, FixedOffset, Local};
+use std::env;
+use std::fmt;
+use std::fs::read_to_string;
+
+/// Whose identity is being resolved; each has its own environment variables and
+/// config overrides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Author,
+    Committer,
+}
+
+impl Role {
+    fn env_prefix(self) -> &'static str {
+        match self {
+            Role::Author => "GIT_AUTHOR",
+            Role::Committer => "GIT_COMMITTER",
+        }
+    }
+
+    fn config_section(self) -> &'static str {
+        match self {
+            Role::Author => "author",
+            Role::Committer => "committer",
+        }
+    }
+}
+
+/// `Name <email> <timestamp> <timezone>`, as recorded in commit and tag headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ident {
+    pub name: String,
+    pub email: String,
+    pub timestamp: i64,
+    pub timezone: String,
+}
+
+impl fmt::Display for Ident {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} <{}> {} {}",
+            self.name, self.email, self.timestamp, self.timezone
+        )
+    }
+}
+
+impl Ident {
+    /// Parse `Name <email> <timestamp> <timezone>` as found in object headers.
+    pub fn parse(ident: &str) -> Result<Ident> {
+        let Some((name, rest)) = ident.split_once('<') else {
+            bail!("missing '<' before email in {ident:?}");
+        };
+        let Some((email, date)) = rest.split_once('>') else {
+            bail!("missing '>' after email in {ident:?}");
+        };
+        ensure!(!email.contains('<'), "bad email in {ident:?}");
+        let Some((timestamp, timezone)) = date.trim().split_once(' ') else {
+            bail!("missing timezone in {ident:?}");
+        };
+        let timestamp = timestamp
+            .parse()
+            .with_context(|| format!("bad timestamp {timestamp:?}"))?;
+        ensure!(
+            timezone.len() == 5
+                && matches!(timezone.as_bytes()[0], b'+' | b'-')
+                && timezone[1..].bytes().all(|b| b.is_ascii_digit()),
+            "bad timezone {timezone:?}"
+        );
+        Ok(Ident {
+            name: name.trim().to_string(),
+            email: email.to_string(),
+            timestamp,
+            timezone: timezone.to_string(),
+        })
+    }
+
+    /// The date in git's default format, in the ident's own timezone: `Thu Oct 14
+    /// 12:00:00 2026 +0200`.
+    pub fn date(&self) -> String {
+        match self.local_time() {
+            Some(date) => format!("{} {}", date.format("%a %b %-d %H:%M:%S %Y"), self.timezone),
+            None => format!("{} {}", self.timestamp, self.timezone),
+        }
+    }
+
+    /// The day of the date in the ident's own timezone, as `--date=short` shows it:
+    /// `2026-10-14`.
+    pub fn short_date(&self) -> String {
+        match self.local_time() {
+            Some(date) => date.format("%Y-%m-%d").to_string(),
+            None => self.timestamp.to_string(),
+        }
+    }
+
+    fn local_time(&self) -> Option<DateTime<FixedOffset>> {
+        let sign = if self.timezone.starts_with('-') {
+            -1
+        } else {
+            1
+        };
+        let hours: i32 = self.timezone[1..3].parse().unwrap_or(0);
+        let minutes: i32 = self.timezone[3..].parse().unwrap_or(0);
+        let offset = FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+            .unwrap_or(FixedOffset::east_opt(0).unwrap());
+        Some(DateTime::from_timestamp(self.timestamp, 0)?.with_timezone(&offset))
+    }
+}
+
+/// Resolve the identity for `role` the way git does, first match wins:
+///
+/// - name: `GIT_<ROLE>_NAME`, `<role>.name`, `user.name`, then the account's full name
+///   from `/etc/passwd`, falling back to the login name
+/// - email: `GIT_<ROLE>_EMAIL`, `<role>.email`, `user.email`, `EMAIL`, then
+///   `<login>@<hostname>`
+/// - date: `GIT_<ROLE>_DATE` (either `<timestamp> <tz>`, `@<timestamp> <tz>`, RFC 2822
+///   or ISO 8601), otherwise now
+pub fn resolve_ident(role: Role, config: &Config) -> Result<Ident> {
+    let prefix = role.env_prefix();
+    let section = role.config_section();
+    let name = env::var(format!("{prefix}_NAME"))
+        .ok()
+        .or_else(|| config.get(&format!("{section}.name")).map(str::to_string))
+        .or_else(|| config.get("user.name").map(str::to_string))
+        .or_else(system_full_name)
+        .unwrap_or_default();
+    let email = env::var(format!("{prefix}_EMAIL"))
+        .ok()
+        .or_else(|| config.get(&format!("{section}.email")).map(str::to_string))
+        .or_else(|| config.get("user.email").map(str::to_string))
+        .or_else(|| env::var("EMAIL").ok())
+        .or_else(system_email)
+        .unwrap_or_default();
+    let name = name.trim().to_string();
+    let email = email.trim().to_string();
+    ensure!(
+        !name.is_empty(),
+        "{} identity unknown: set user.name with `git config user.name \"Your Name\"`",
+        if role == Role::Author {
+            "Author"
+        } else {
+            "Committer"
+        }
+    );
+    ensure!(
+        !name.contains(['<', '>', '\n']) && !email.contains(['<', '>', '\n']),
+        "name and email can't contain '<', '>' or newlines"
+    );
+    let (timestamp, timezone) = match env::var(format!("{prefix}_DATE")) {
+        Ok(date) => parse_date(&date).with_context(|| format!("invalid {prefix}_DATE"))?,
+        Err(_) => now_and_timezone(),
+    };
+    Ok(Ident {
+        name,
+        email,
+        timestamp,
+        timezone,
+    })
+}
+
+/// The current time and the local timezone offset formatted like `+0530`.
+pub fn now_and_timezone() -> (i64, String) {
+    let now = Local::now();
+    let offset_seconds = now.offset().local_minus_utc();
+    (now.timestamp(), format_timezone(offset_seconds))
+}
+
+fn format_timezone(offset_seconds: i32) -> String {
+    let sign = if offset_seconds < 0 { '-' } else { '+' };
+    let offset = offset_seconds.abs();
+    format!("{}{:02}{:02}", sign, offset / 3600, offset % 3600 / 60)
+}
+
+/// Parse the date formats git accepts in `GIT_AUTHOR_DATE` / `GIT_COMMITTER_DATE`.
+pub fn parse_date(date: &str) -> Result<(i64, String)> {
+    let date = date.trim();
+    if let Some((timestamp, tz)) = date.split_once(' ') {
+        let timestamp = timestamp.strip_prefix('@').unwrap_or(timestamp);
+        if let Ok(timestamp) = timestamp.parse::<i64>() {
+            let tz_ok = tz.len() == 5
+                && matches!(tz.as_bytes()[0], b'+' | b'-')
+                && tz[1..].bytes().all(|b| b.is_ascii_digit());
+            ensure!(tz_ok, "bad timezone {tz:?}");
+            return Ok((timestamp, tz.to_string()));
+        }
+    }
+    let parsed = DateTime::parse_from_rfc2822(date)
+        .or_else(|_| DateTime::parse_from_rfc3339(date))
+        .or_else(|_| DateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S %z"));
+    match parsed {
+        Ok(parsed) => Ok((
+            parsed.timestamp(),
+            format_timezone(parsed.offset().local_minus_utc()),
+        )),
+        Err(_) => bail!("unrecognized date format {date:?}"),
+    }
+}
+
+fn login_name() -> Option<String> {
+    env::var("USER")
+        .or_else(|_| env::var("LOGNAME"))
+        .ok()
+        .filter(|name| !name.is_empty())
+}
+
+/// The GECOS full name of the current user, or the login name if it has none.
+fn system_full_name() -> Option<String> {
+    let login = login_name()?;
+    let passwd = read_to_string("/etc/passwd").unwrap_or_default();
+    let gecos = passwd.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        (fields.next() == Some(login.as_str()))
+            .then(|| fields.nth(3))
+            .flatten()
+            .and_then(|gecos| gecos.split(',').next())
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+    });
+    Some(gecos.unwrap_or(login))
+}
+
+fn system_email() -> Option<String> {
+    let login = login_name()?;
+    let host = read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| read_to_string("/etc/hostname"))
+        .ok()?;
+    Some(format!("{login}@{}", host.trim()))
+}
+
+/// The editor git would launch: `GIT_EDITOR`, `core.editor`, `VISUAL`, `EDITOR`, then `vi`.
+pub fn git_editor(config: &Config) -> String {
+    env::var("GIT_EDITOR")
+        .ok()
+        .or_else(|| config.get("core.editor").map(str::to_string))
+        .or_else(|| env::var("VISUAL").ok())
+        .or_else(|| env::var("EDITOR").ok())
+        .unwrap_or_else(|| "vi".to_string())
+}
+
+/// The pager git would use: `GIT_PAGER`, `core.pager`, `PAGER`, then `less`.
+pub fn git_pager(config: &Config) -> String {
+    env::var("GIT_PAGER")
+        .ok()
+        .or_else(|| config.get("core.pager").map(str::to_string))
+        .or_else(|| env::var("PAGER").ok())
+        .unwrap_or_else(|| "less".to_string())
+}
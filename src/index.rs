@@ -0,0 +1,735 @@
+use crate::config::Config;
+use crate::diff::DiffSide;
+use crate::hash_object::git_hash_object;
+use crate::object_id::{HashAlgorithm, ObjectId};
+use crate::object_read::{Object, ObjectKind};
+use crate::repository::Repository;
+use crate::submodule::submodule_head;
+use crate::write_tree::compare_tree_entry_names;
+use anyhow::{Context, Result, bail, ensure};
+use std::collections::HashMap;
+use std::env;
+use std::fs::{
+    Metadata, OpenOptions, metadata, read, read_link, remove_file, rename, symlink_metadata,
+};
+use std::io::{Cursor, ErrorKind, Write, sink};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The index (`.git/index`), or staging area: the list of files that the next commit
+/// will record, each with its mode, blob and the stat data the file had when it was
+/// added, so a file whose stat data hasn't changed needn't be hashed again.
+///
+/// The on-disk format is:
+///
+///     "DIRC" <version: u32> <entry count: u32>
+///     <entries, sorted by path and then stage>
+///     <extensions: 4-byte signature, u32 size, data>
+///     <hash of everything before it>
+///
+/// where the hash, like the object ids, is of the repository's hash function (SHA-1
+/// or SHA-256), and each entry is its stat data (ctime, mtime, dev, ino, mode, uid,
+/// gid, size, all 32 bits), the raw object id, 16 bits of flags (assume-valid, extended,
+/// 2-bit stage, 12-bit path length), in version 3 and later 16 more flags if the
+/// extended bit is set (skip-worktree, intent-to-add), and the path. Versions 2 and 3
+/// NUL-pad the path so that each entry is a multiple of 8 bytes; version 4 instead
+/// stores how many bytes to drop from the end of the previous path and the
+/// NUL-terminated rest.
+///
+/// Versions 2 to 4 are read and written back in the version they were read in. A
+/// new index is written in the version `GIT_INDEX_VERSION` or `index.version` asks
+/// for, 4 with `feature.manyFiles`, and otherwise 2.
+/// Extensions this code doesn't know are kept as they are, unless git marks them as
+/// required (a lowercase signature), in which case the index can't be used.
+#[derive(Debug, Clone)]
+pub struct Index {
+    version: u32,
+    /// The entries, sorted by path and then stage.
+    pub entries: Vec<IndexEntry>,
+    extensions: Vec<([u8; 4], Vec<u8>)>,
+    /// The modification time of the index file when it was read, as (seconds, nsec).
+    timestamp: Option<(u32, u32)>,
+}
+
+/// One file of the [`Index`], at stage 0 normally or at stages 1 to 3 (base, ours,
+/// theirs) while a conflict is unresolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub stat: StatData,
+    /// `0o100644`, `0o100755`, `0o120000` (symlink) or `0o160000` (submodule).
+    pub mode: u32,
+    pub id: ObjectId,
+    pub stage: u8,
+    pub assume_valid: bool,
+    pub skip_worktree: bool,
+    pub intent_to_add: bool,
+    pub path: String,
+}
+
+/// What `stat` said about a file when it was added, each field truncated to 32 bits
+/// as the index stores it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatData {
+    pub ctime: (u32, u32),
+    pub mtime: (u32, u32),
+    pub dev: u32,
+    pub ino: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+}
+
+impl StatData {
+    pub fn from_metadata(metadata: &Metadata) -> StatData {
+        StatData {
+            ctime: (metadata.ctime() as u32, metadata.ctime_nsec() as u32),
+            mtime: (metadata.mtime() as u32, metadata.mtime_nsec() as u32),
+            dev: metadata.dev() as u32,
+            ino: metadata.ino() as u32,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            size: metadata.len() as u32,
+        }
+    }
+}
+
+const SIGNATURE: &[u8; 4] = b"DIRC";
+
+/// Extensions that describe the entries or the working tree as they were when the
+/// index was written: cached trees, the untracked cache, fsmonitor's dirty bits and
+/// the entry offset tables. They would be wrong once entries change, so they are
+/// dropped rather than kept; git rebuilds them when it wants them.
+const DERIVED_EXTENSIONS: [&[u8; 4]; 5] = [b"TREE", b"UNTR", b"FSMN", b"EOIE", b"IEOT"];
+
+impl Default for Index {
+    fn default() -> Self {
+        Index {
+            version: 2,
+            entries: Vec::new(),
+            extensions: Vec::new(),
+            timestamp: None,
+        }
+    }
+}
+
+impl Index {
+    /// Where the index of `repo` is: the file named by `GIT_INDEX_FILE` (from the top
+    /// of the work tree) if that is set, otherwise the worktree's own `index`.
+    pub fn path(repo: &Repository) -> Result<PathBuf> {
+        match env::var_os("GIT_INDEX_FILE") {
+            Some(path) => Ok(repo.path(path)),
+            None => Ok(repo.git_path("index")),
+        }
+    }
+
+    /// Read the index of `repo`; a repository without one has an empty index, of the
+    /// version [`new_index_version`] picks.
+    pub fn load(repo: &Repository) -> Result<Index> {
+        Index::read_from(repo, &Index::path(repo)?)
+    }
+
+    pub fn read_from(repo: &Repository, path: &Path) -> Result<Index> {
+        let data = match read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Ok(Index {
+                    version: new_index_version(repo)?,
+                    ..Index::default()
+                });
+            }
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        let mut index =
+            Index::parse(&data, repo.hash_algorithm()?).context("index file corrupt")?;
+        index.timestamp = metadata(path)
+            .ok()
+            .map(|metadata| (metadata.mtime() as u32, metadata.mtime_nsec() as u32));
+        Ok(index)
+    }
+
+    fn parse(data: &[u8], algorithm: HashAlgorithm) -> Result<Index> {
+        let hash_len = algorithm.len();
+        ensure!(
+            data.len() >= 12 + hash_len,
+            "index file smaller than expected"
+        );
+        let (body, checksum) = data.split_at(data.len() - hash_len);
+        // An all-zero checksum is what index.skipHash writes.
+        if checksum.iter().any(|&b| b != 0) && algorithm.digest(body).as_bytes() != checksum {
+            bail!("bad index file sha1 signature");
+        }
+        let mut input = Input { data: body, pos: 0 };
+        ensure!(input.bytes(4)? == SIGNATURE, "bad signature");
+        let version = input.u32()?;
+        ensure!((2..=4).contains(&version), "bad index version {version}");
+        let count = input.u32()?;
+
+        let mut entries: Vec<IndexEntry> = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let start = input.pos;
+            let stat = StatData {
+                ctime: (input.u32()?, input.u32()?),
+                mtime: (input.u32()?, input.u32()?),
+                dev: input.u32()?,
+                ino: input.u32()?,
+                uid: 0,
+                gid: 0,
+                size: 0,
+            };
+            let mode = input.u32()?;
+            let stat = StatData {
+                uid: input.u32()?,
+                gid: input.u32()?,
+                size: input.u32()?,
+                ..stat
+            };
+            let id = ObjectId::from_bytes(algorithm, input.bytes(hash_len)?)?;
+            let flags = input.u16()?;
+            let extended = flags & 0x4000 != 0;
+            let extended_flags = match extended {
+                true if version >= 3 => input.u16()?,
+                true => bail!("extended entry flags in a version {version} index"),
+                false => 0,
+            };
+            let path = if version == 4 {
+                let strip = input.varint()? as usize;
+                let previous = entries.last().map_or("", |entry| entry.path.as_str());
+                ensure!(strip <= previous.len(), "malformed name field in the index");
+                let mut path = previous.as_bytes()[..previous.len() - strip].to_vec();
+                path.extend_from_slice(input.until_nul()?);
+                path
+            } else {
+                let name = input.until_nul()?.to_vec();
+                // Entries are padded with NULs to a multiple of 8 bytes; before the
+                // name come 40 bytes of stat data, the id and the flags.
+                let fixed = 40 + hash_len + if extended { 4 } else { 2 };
+                input.pos = start + (fixed + name.len() + 8) / 8 * 8;
+                ensure!(
+                    input.pos <= input.data.len(),
+                    "index entry runs past the end"
+                );
+                name
+            };
+            let path = String::from_utf8(path).context("index entry path is not UTF-8")?;
+            entries.push(IndexEntry {
+                stat,
+                mode,
+                id,
+                stage: ((flags >> 12) & 3) as u8,
+                assume_valid: flags & 0x8000 != 0,
+                skip_worktree: extended_flags & 0x4000 != 0,
+                intent_to_add: extended_flags & 0x2000 != 0,
+                path,
+            });
+        }
+
+        let mut extensions = Vec::new();
+        while input.pos < input.data.len() {
+            let signature: [u8; 4] = input.bytes(4)?.try_into()?;
+            let size = input.u32()? as usize;
+            let data = input.bytes(size)?.to_vec();
+            if !signature[0].is_ascii_uppercase() {
+                bail!(
+                    "index uses {} extension, which we do not understand",
+                    String::from_utf8_lossy(&signature)
+                );
+            }
+            if !DERIVED_EXTENSIONS.contains(&&signature) {
+                extensions.push((signature, data));
+            }
+        }
+        Ok(Index {
+            version,
+            entries,
+            extensions,
+            timestamp: None,
+        })
+    }
+
+    /// Write the index back to where [`Index::load`] reads it from, through an
+    /// `index.lock` file as git does.
+    ///
+    /// Entries whose files were modified in the same second the index is written
+    /// could change again without their stat data changing, so their recorded size is
+    /// cleared, which makes the next check hash them.
+    pub fn save(&self, repo: &Repository) -> Result<()> {
+        let path = Index::path(repo)?;
+        let lock = PathBuf::from(format!("{}.lock", path.display()));
+        let mut file = match OpenOptions::new().write(true).create_new(true).open(&lock) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                bail!("unable to create '{}': File exists", lock.display())
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to create {}", lock.display()));
+            }
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32;
+        let result = file
+            .write_all(&self.serialize(repo.hash_algorithm()?, now))
+            .map_err(anyhow::Error::from)
+            .and_then(|()| Ok(rename(&lock, &path)?));
+        if result.is_err() {
+            let _ = remove_file(&lock);
+        }
+        result.with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    fn serialize(&self, algorithm: HashAlgorithm, now: u32) -> Vec<u8> {
+        let needs_extended = self
+            .entries
+            .iter()
+            .any(|entry| entry.skip_worktree || entry.intent_to_add);
+        let version = match self.version {
+            2 if needs_extended => 3,
+            version => version,
+        };
+        let mut out = Vec::new();
+        out.extend_from_slice(SIGNATURE);
+        out.extend_from_slice(&version.to_be_bytes());
+        out.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        let mut previous = "";
+        for entry in &self.entries {
+            let start = out.len();
+            let StatData {
+                ctime,
+                mtime,
+                dev,
+                ino,
+                uid,
+                gid,
+                mut size,
+            } = entry.stat;
+            if mtime.0 >= now {
+                size = 0;
+            }
+            for field in [
+                ctime.0, ctime.1, mtime.0, mtime.1, dev, ino, entry.mode, uid, gid, size,
+            ] {
+                out.extend_from_slice(&field.to_be_bytes());
+            }
+            out.extend_from_slice(entry.id.as_bytes());
+            let extended = version >= 3 && (entry.skip_worktree || entry.intent_to_add);
+            let flags = (u16::from(entry.assume_valid) << 15)
+                | (u16::from(extended) << 14)
+                | (u16::from(entry.stage & 3) << 12)
+                | entry.path.len().min(0xfff) as u16;
+            out.extend_from_slice(&flags.to_be_bytes());
+            if extended {
+                let flags =
+                    (u16::from(entry.skip_worktree) << 14) | (u16::from(entry.intent_to_add) << 13);
+                out.extend_from_slice(&flags.to_be_bytes());
+            }
+            if version == 4 {
+                let common = previous
+                    .bytes()
+                    .zip(entry.path.bytes())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                write_varint(&mut out, (previous.len() - common) as u64);
+                out.extend_from_slice(&entry.path.as_bytes()[common..]);
+                out.push(0);
+            } else {
+                out.extend_from_slice(entry.path.as_bytes());
+                let len = (out.len() - start + 8) / 8 * 8;
+                out.resize(start + len, 0);
+            }
+            previous = &entry.path;
+        }
+        for (signature, data) in &self.extensions {
+            out.extend_from_slice(signature);
+            out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            out.extend_from_slice(data);
+        }
+        let checksum = algorithm.digest(&out);
+        out.extend_from_slice(checksum.as_bytes());
+        out
+    }
+
+    /// The stage 0 entry for `path`.
+    pub fn entry(&self, path: &str) -> Option<&IndexEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.path == path && entry.stage == 0)
+    }
+
+    /// How the index spells `path`: with `ignore_case` (`core.ignoreCase`, for
+    /// filesystems that don't tell `README` from `readme`), the path of an entry that
+    /// differs from it only in ASCII case, so that the file is updated under the name
+    /// it is tracked as rather than added a second time. Otherwise `path` itself.
+    pub fn existing_path<'a>(&'a self, path: &'a str, ignore_case: bool) -> &'a str {
+        if !ignore_case || self.entry(path).is_some() {
+            return path;
+        }
+        self.entries
+            .iter()
+            .find(|entry| entry.path.eq_ignore_ascii_case(path))
+            .map_or(path, |entry| entry.path.as_str())
+    }
+
+    /// Put `entry` in the index in place of whatever is at its path, along with the
+    /// entries it can't coexist with: the files under it if the path was a directory,
+    /// and the file at any leading directory of it.
+    pub fn add(&mut self, entry: IndexEntry) {
+        let path = entry.path.as_str();
+        let under = format!("{path}/");
+        self.entries.retain(|existing| {
+            existing.path != path
+                && !existing.path.starts_with(&under)
+                && !path
+                    .strip_prefix(existing.path.as_str())
+                    .is_some_and(|rest| rest.starts_with('/'))
+        });
+        let position = self.entries.partition_point(|existing| {
+            (existing.path.as_bytes(), existing.stage) < (path.as_bytes(), entry.stage)
+        });
+        self.entries.insert(position, entry);
+    }
+
+    /// Put the versions of a conflicted `path` in the index in place of whatever is
+    /// at it: `sides` are the base, our and their versions, staged as 1 to 3, an
+    /// absent one being left out.
+    pub fn add_conflict(&mut self, path: &str, sides: &[Option<DiffSide>; 3]) -> Result<()> {
+        self.remove(path);
+        for (stage, side) in (1..).zip(sides) {
+            let Some(side) = side else {
+                continue;
+            };
+            let mode = u32::from_str_radix(&side.mode, 8)?;
+            let entry = IndexEntry {
+                stage,
+                ..IndexEntry::new(path, mode, side.hash, StatData::default())
+            };
+            let position = self.entries.partition_point(|existing| {
+                (existing.path.as_bytes(), existing.stage) < (path.as_bytes(), stage)
+            });
+            self.entries.insert(position, entry);
+        }
+        Ok(())
+    }
+
+    /// Remove every stage of `path`; false if it wasn't in the index.
+    pub fn remove(&mut self, path: &str) -> bool {
+        let count = self.entries.len();
+        self.entries.retain(|entry| entry.path != path);
+        self.entries.len() != count
+    }
+
+    /// Add the file, symlink or submodule at `path` (relative to the top of the work
+    /// tree) as it is in the working directory, storing its blob. A file already in
+    /// the index whose stat data is unchanged isn't hashed again.
+    pub fn add_path(&mut self, repo: &Repository, path: &str) -> Result<()> {
+        let metadata =
+            symlink_metadata(repo.path(path)).with_context(|| format!("failed to stat {path}"))?;
+        let mode = worktree_mode(repo, &metadata, self.entry(path).map(|entry| entry.mode));
+        if let Some(entry) = self.entry(path)
+            && entry.mode == mode
+            && self.is_stat_clean(repo, entry, &metadata)
+        {
+            return Ok(());
+        }
+        let id = worktree_id(repo, path, mode, true)?;
+        self.add(IndexEntry::new(
+            path,
+            mode,
+            id,
+            StatData::from_metadata(&metadata),
+        ));
+        Ok(())
+    }
+
+    /// Record that the file at `path` was just written with the object `id` and
+    /// `mode`, taking its stat data from the file.
+    pub fn add_checked_out(
+        &mut self,
+        repo: &Repository,
+        path: &str,
+        mode: u32,
+        id: ObjectId,
+    ) -> Result<()> {
+        let metadata =
+            symlink_metadata(repo.path(path)).with_context(|| format!("failed to stat {path}"))?;
+        self.add(IndexEntry::new(
+            path,
+            mode,
+            id,
+            StatData::from_metadata(&metadata),
+        ));
+        Ok(())
+    }
+
+    /// Whether the file `metadata` describes is unchanged since `entry` was added,
+    /// going by its stat data alone.
+    ///
+    /// A file modified no earlier than the index was written may have changed again
+    /// within the same timestamp, so it never counts as unchanged; nor does a
+    /// submodule, whose checked-out commit its directory's stat data doesn't show.
+    /// A recorded size of 0 says nothing when it was cleared for such a file, so it
+    /// is only trusted for an empty blob.
+    pub fn is_stat_clean(
+        &self,
+        repo: &Repository,
+        entry: &IndexEntry,
+        metadata: &Metadata,
+    ) -> bool {
+        let racy = self
+            .timestamp
+            .is_none_or(|timestamp| entry.stat.mtime >= timestamp);
+        entry.mode != 0o160000
+            && !racy
+            && (entry.stat.size != 0 || entry.id == entry.id.algorithm().digest(b"blob 0\0"))
+            && entry.mode == worktree_mode(repo, metadata, Some(entry.mode))
+            && entry.stat == StatData::from_metadata(metadata)
+    }
+
+    /// Write the tree objects for the stage 0 entries under the directory `prefix`
+    /// (everything for an empty prefix) and return the id of its tree, or `None` if
+    /// no entry is under it. Intent-to-add entries aren't recorded.
+    pub fn write_tree(&self, repo: &Repository, prefix: &str) -> Result<Option<ObjectId>> {
+        if let Some(entry) = self.entries.iter().find(|entry| entry.stage != 0) {
+            bail!("{}: unmerged ({})", entry.path, entry.id);
+        }
+        let prefix = match prefix {
+            "" => String::new(),
+            prefix => format!("{prefix}/"),
+        };
+        let entries: Vec<&IndexEntry> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.path.starts_with(&prefix) && !entry.intent_to_add)
+            .collect();
+        if entries.is_empty() && !prefix.is_empty() {
+            return Ok(None);
+        }
+        write_tree_entries(repo, &entries, prefix.len()).map(Some)
+    }
+}
+
+impl IndexEntry {
+    /// A stage 0 entry. An entry for a file that isn't in the working directory as
+    /// recorded gets the default (zero) stat data, which never matches a file's.
+    pub fn new(path: &str, mode: u32, id: ObjectId, stat: StatData) -> IndexEntry {
+        IndexEntry {
+            stat,
+            mode,
+            id,
+            stage: 0,
+            assume_valid: false,
+            skip_worktree: false,
+            intent_to_add: false,
+            path: path.to_string(),
+        }
+    }
+}
+
+/// The id of the file at `path` as it is in the working directory, recorded with
+/// `mode`: the blob of a file's content or a symlink's target, or the commit a
+/// submodule has checked out. With `write`, the blob is stored too.
+pub fn worktree_id(repo: &Repository, path: &str, mode: u32, write: bool) -> Result<ObjectId> {
+    let file = repo.path(path);
+    match mode {
+        0o120000 => {
+            let target = read_symlink(repo, &file)?;
+            let mut object = Object {
+                reader: Cursor::new(&target),
+                kind: ObjectKind::Blob,
+                expected_size: target.len() as u64,
+            };
+            match write {
+                true => object.write_as_object(repo),
+                false => object.write(repo.hash_algorithm()?, sink()),
+            }
+        }
+        0o160000 => match submodule_head(&file)? {
+            Some(hash) => Ok(hash),
+            None => bail!("'{path}/' does not have a commit checked out"),
+        },
+        _ => git_hash_object(repo, &file, ObjectKind::Blob, write),
+    }
+}
+
+/// The target of the symlink at `path`; without `core.symlinks`, the content of a
+/// plain file standing in for one (see [`has_symlinks`]).
+pub fn read_symlink(repo: &Repository, path: &Path) -> Result<Vec<u8>> {
+    match read_link(path) {
+        Ok(target) => Ok(target.into_os_string().into_encoded_bytes()),
+        Err(e) if e.kind() == ErrorKind::InvalidInput && !has_symlinks(repo) => {
+            read(path).with_context(|| format!("failed to read {}", path.display()))
+        }
+        Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+    }
+}
+
+/// The `core.symlinks` and `core.fileMode` of each repository looked up so far, by
+/// its git directory.
+static WORKTREE_FLAGS: OnceLock<Mutex<HashMap<PathBuf, (bool, bool)>>> = OnceLock::new();
+
+/// The version to write an index in that doesn't exist yet: `GIT_INDEX_VERSION`,
+/// else `index.version`, else 4 if `feature.manyFiles` is set, as git picks it.
+fn new_index_version(repo: &Repository) -> Result<u32> {
+    let config = Config::load(repo)?;
+    let (source, value) = match env::var("GIT_INDEX_VERSION") {
+        Ok(value) => ("GIT_INDEX_VERSION", value),
+        Err(_) => match config.get("index.version") {
+            Some(value) => ("index.version", value.to_string()),
+            None if config.get_bool("feature.manyFiles")? == Some(true) => return Ok(4),
+            None => return Ok(2),
+        },
+    };
+    match value.trim().parse() {
+        Ok(version @ 2..=4) => Ok(version),
+        _ => bail!("bad {source} '{value}': index versions 2 to 4 are supported"),
+    }
+}
+
+/// The repository's `core.symlinks` and `core.fileMode`, read once; both are true
+/// unless set to `false`.
+fn worktree_flags(repo: &Repository) -> (bool, bool) {
+    let mut cache = WORKTREE_FLAGS.get_or_init(Mutex::default).lock().unwrap();
+    *cache.entry(repo.git_dir.clone()).or_insert_with(|| {
+        Config::load(repo).map_or((true, true), |config| {
+            (
+                config.get_bool("core.symlinks").ok().flatten() != Some(false),
+                config.get_bool("core.fileMode").ok().flatten() != Some(false),
+            )
+        })
+    })
+}
+
+/// The repository's `core.symlinks`, read once: whether the filesystem of its working
+/// tree can hold symlinks, which `init` finds out. Where it can't, symlinks are
+/// checked out as plain files holding their targets, as git does, and such a file
+/// counts as the symlink the index records rather than as a change to a file.
+pub fn has_symlinks(repo: &Repository) -> bool {
+    worktree_flags(repo).0
+}
+
+/// The repository's `core.fileMode`, read once: whether the filesystem of its working
+/// tree keeps the executable bit of files, which `init` finds out. Where it doesn't, a
+/// file keeps the mode the index records (and a new one is `100644`), as git does, so
+/// that a file doesn't count as changed for its bits alone.
+pub fn trust_executable_bit(repo: &Repository) -> bool {
+    worktree_flags(repo).1
+}
+
+/// The mode a file with `metadata` is recorded with, given the one the index has for
+/// it, `recorded`; any directory that gets this far is a submodule.
+pub fn worktree_mode(repo: &Repository, metadata: &Metadata, recorded: Option<u32>) -> u32 {
+    if metadata.is_symlink() {
+        0o120000
+    } else if metadata.is_dir() {
+        0o160000
+    } else if recorded == Some(0o120000) && !has_symlinks(repo) {
+        0o120000
+    } else if !trust_executable_bit(repo) {
+        recorded
+            .filter(|mode| matches!(mode, 0o100644 | 0o100755))
+            .unwrap_or(0o100644)
+    } else if metadata.permissions().mode() & 0o111 != 0 {
+        0o100755
+    } else {
+        0o100644
+    }
+}
+
+/// Write the tree of `entries`, which all share the first `base` bytes of their
+/// paths (the directory the tree is for, with its trailing `/`).
+fn write_tree_entries(repo: &Repository, entries: &[&IndexEntry], base: usize) -> Result<ObjectId> {
+    let mut records: Vec<(&str, u32, ObjectId)> = Vec::new();
+    let mut rest = entries;
+    while let Some(first) = rest.first() {
+        let name = &first.path[base..];
+        match name.split_once('/') {
+            Some((dir, _)) => {
+                let under = &first.path[..base + dir.len() + 1];
+                let count = rest
+                    .iter()
+                    .take_while(|entry| entry.path.starts_with(under))
+                    .count();
+                let id = write_tree_entries(repo, &rest[..count], under.len())?;
+                records.push((dir, 0o40000, id));
+                rest = &rest[count..];
+            }
+            None => {
+                records.push((name, first.mode, first.id));
+                rest = &rest[1..];
+            }
+        }
+    }
+    records.sort_by(|a, b| {
+        compare_tree_entry_names(
+            a.0.as_bytes(),
+            a.1 == 0o40000,
+            b.0.as_bytes(),
+            b.1 == 0o40000,
+        )
+    });
+    let mut out = Vec::new();
+    for (name, mode, id) in records {
+        out.extend_from_slice(format!("{mode:o} {name}\0").as_bytes());
+        out.extend_from_slice(id.as_bytes());
+    }
+    Object {
+        kind: ObjectKind::Tree,
+        expected_size: out.len() as u64,
+        reader: Cursor::new(out),
+    }
+    .write_as_object(repo)
+}
+
+/// Git's variable-length integer, as used for version 4 path prefixes: 7 bits per
+/// byte, most significant first, with each continuation adding one so that every
+/// number has a single encoding.
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    while value >= 0x80 {
+        value = (value >> 7) - 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+    }
+    out.extend(bytes.iter().rev());
+}
+
+/// A cursor over the index file's bytes.
+struct Input<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Input<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let Some(bytes) = self.data.get(self.pos..self.pos + len) else {
+            bail!("index file ends unexpectedly");
+        };
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    fn until_nul(&mut self) -> Result<&'a [u8]> {
+        let Some(len) = self.data[self.pos..].iter().position(|&b| b == 0) else {
+            bail!("index entry path is not terminated");
+        };
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len + 1;
+        Ok(bytes)
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut byte = self.bytes(1)?[0];
+        let mut value = u64::from(byte & 0x7f);
+        while byte & 0x80 != 0 {
+            byte = self.bytes(1)?[0];
+            value = ((value + 1) << 7) | u64::from(byte & 0x7f);
+        }
+        Ok(value)
+    }
+}
@@ -0,0 +1,461 @@
+use crate::delta::{apply_delta, delta_sizes};
+use crate::object_id::{HashAlgorithm, ObjectId};
+use crate::object_read::{Object, ObjectKind, forget_packs, has_object, max_object_size};
+use crate::pack::{be32, entry_kind, read_byte};
+use crate::pack_write::{entry_header, entry_type};
+use crate::repository::Repository;
+use anyhow::{Context, Result, bail, ensure};
+use flate2::bufread::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+use std::collections::HashMap;
+use std::fs::{Permissions, create_dir_all, read, write};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+/// An object of a pack being indexed, as [`index_pack`] finds it: where its entry
+/// starts, the CRC32 of the entry, and its id.
+pub struct IndexEntry {
+    pub offset: u64,
+    pub crc: u32,
+    pub id: ObjectId,
+}
+
+/// What an entry of the pack holds before deltas are applied.
+enum Content {
+    Base(ObjectKind, Vec<u8>),
+    /// A delta against the entry at this index in the pack.
+    OffsetDelta(usize, Vec<u8>),
+    RefDelta(ObjectId, Vec<u8>),
+}
+
+/// An object rebuilt from a pack: its kind, its data and its id.
+type Resolved = (ObjectKind, Vec<u8>, ObjectId);
+
+/// Read every entry of the pack `data` (a whole `.pack` file, trailer included) and
+/// work out the id of each object, resolving deltas against entries of the same
+/// pack, as `git index-pack` does. The entries are returned in pack order, with the
+/// pack's checksum, once it is checked against the trailer.
+///
+/// A reference delta must have its base in the pack; see [`fix_thin_pack`] for a
+/// pack that deltas against objects the repository has.
+pub fn index_pack(data: &[u8], algorithm: HashAlgorithm) -> Result<(Vec<IndexEntry>, ObjectId)> {
+    let (entries, checksum, _) = resolve_pack(data, algorithm, None)?;
+    Ok((entries, checksum))
+}
+
+/// Complete the thin pack `data`, as `git index-pack --fix-thin` does: each base of a
+/// reference delta that isn't in the pack is read from `objects_dir` and appended to
+/// it whole, and the object count and the trailer are rewritten to match. Returns the
+/// completed pack (`data` itself if it wasn't thin) with its entries in pack order and
+/// its checksum.
+pub fn fix_thin_pack(
+    data: &[u8],
+    objects_dir: &Path,
+    algorithm: HashAlgorithm,
+) -> Result<(Vec<u8>, Vec<IndexEntry>, ObjectId)> {
+    let (mut entries, checksum, bases) = resolve_pack(data, algorithm, Some(objects_dir))?;
+    if bases.is_empty() {
+        return Ok((data.to_vec(), entries, checksum));
+    }
+    let mut pack = data[..data.len() - algorithm.len()].to_vec();
+    let count = entries.len() + bases.len();
+    let count = u32::try_from(count).context("pack has too many objects")?;
+    pack[8..12].copy_from_slice(&count.to_be_bytes());
+    for (kind, object, id) in bases {
+        let offset = pack.len() as u64;
+        pack.extend(entry_header(entry_type(&kind), object.len()));
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&object)?;
+        pack.extend(encoder.finish()?);
+        let mut crc = Crc::new();
+        crc.update(&pack[offset as usize..]);
+        entries.push(IndexEntry {
+            offset,
+            crc: crc.sum(),
+            id,
+        });
+    }
+    let checksum = algorithm.digest(&pack);
+    pack.extend(checksum.as_bytes());
+    Ok((pack, entries, checksum))
+}
+
+/// The entries of the pack `data` and its checksum, as [`index_pack`] finds them, and
+/// the bases taken from `bases_dir` for reference deltas outside the pack, in the
+/// order they were needed. Without `bases_dir`, such a delta is an error.
+fn resolve_pack(
+    data: &[u8],
+    algorithm: HashAlgorithm,
+    bases_dir: Option<&Path>,
+) -> Result<(Vec<IndexEntry>, ObjectId, Vec<Resolved>)> {
+    let hash_len = algorithm.len();
+    ensure!(
+        data.len() >= 12 + hash_len && data.starts_with(b"PACK"),
+        "pack is not a packfile"
+    );
+    let version = be32(data, 4);
+    ensure!(
+        matches!(version, 2 | 3),
+        "pack is version {version}, which we do not understand"
+    );
+    let (content, trailer) = data.split_at(data.len() - hash_len);
+    let checksum = algorithm.digest(content);
+    ensure!(
+        checksum.as_bytes() == trailer,
+        "pack is corrupt: its checksum is {}, but its content hashes to {checksum}",
+        hex::encode(trailer)
+    );
+
+    // The count comes from the peer, so it is only believed as far as the pack could
+    // hold that many entries, each at least a byte of header and two of deflated data,
+    // and nothing is set aside for the entries before they are read.
+    let count = be32(data, 8) as usize;
+    ensure!(
+        count <= (content.len() - 12) / 3,
+        "pack claims {count} objects, more than its {} bytes can hold",
+        data.len()
+    );
+    let max_size = max_object_size()?;
+    let mut offsets = HashMap::new();
+    let mut entries = Vec::new();
+    let mut position = 12;
+    for n in 0..count {
+        ensure!(position < content.len(), "pack is truncated at entry {n}");
+        let (content, end) = read_entry(content, position, algorithm, &offsets, max_size)?;
+        let mut crc = Crc::new();
+        crc.update(&data[position..end]);
+        offsets.insert(position as u64, n);
+        entries.push((position as u64, crc.sum(), content));
+        position = end;
+    }
+    ensure!(
+        position == content.len(),
+        "pack has {} bytes of garbage after its {count} objects",
+        content.len() - position
+    );
+
+    // Objects are rebuilt in passes, each applying the deltas whose bases are known,
+    // until all of them are or a pass makes no progress.
+    let mut objects: Vec<Option<Resolved>> = vec![None; count];
+    let mut by_id: HashMap<ObjectId, usize> = HashMap::new();
+    let mut bases: Vec<Resolved> = Vec::new();
+    let mut left = count;
+    while left > 0 {
+        let before = left;
+        for n in 0..count {
+            if objects[n].is_some() {
+                continue;
+            }
+            let (kind, object) = match &mut entries[n].2 {
+                Content::Base(kind, data) => (kind.clone(), mem::take(data)),
+                Content::OffsetDelta(base, delta) => match &objects[*base] {
+                    Some((kind, base, _)) => (kind.clone(), apply(base, delta, n, max_size)?),
+                    None => continue,
+                },
+                Content::RefDelta(base, delta) => match by_id.get(base) {
+                    Some(&base) if base >= count => {
+                        let (kind, base, _) = &bases[base - count];
+                        (kind.clone(), apply(base, delta, n, max_size)?)
+                    }
+                    Some(&base) => {
+                        let (kind, base, _) = objects[base].as_ref().unwrap();
+                        (kind.clone(), apply(base, delta, n, max_size)?)
+                    }
+                    None => continue,
+                },
+            };
+            let mut hasher = algorithm.hasher();
+            hasher.update(format!("{} {}\0", kind.to_str(), object.len()).as_bytes());
+            hasher.update(&object);
+            let id = hasher.finish();
+            by_id.insert(id, n);
+            objects[n] = Some((kind, object, id));
+            left -= 1;
+        }
+        if left == before {
+            let missing: Vec<&ObjectId> = entries
+                .iter()
+                .zip(&objects)
+                .filter_map(|((_, _, content), object)| match (content, object) {
+                    (Content::RefDelta(base, _), None) if !by_id.contains_key(base) => Some(base),
+                    _ => None,
+                })
+                .collect();
+            // For a thin pack, a base that none of the pack's objects turned out to be
+            // may be one the repository has (rather than one of the pack's own deltas
+            // waiting on it); it is stored after the pack's objects.
+            let external = bases_dir.and_then(|bases_dir| {
+                missing
+                    .iter()
+                    .find(|base| has_object(bases_dir, &base.to_string()))
+                    .map(|base| (bases_dir, *base))
+            });
+            let base = match (external, missing.first()) {
+                (Some((bases_dir, base)), _) => {
+                    let mut object = Object::read_git_object_in(bases_dir, &base.to_string())?;
+                    let data = object.read_all()?;
+                    (object.kind, data, *base)
+                }
+                (None, Some(base)) => match bases_dir {
+                    Some(_) => bail!("pack has a delta against {base}, which we don't have"),
+                    None => bail!("pack has a delta against {base}, which isn't in it"),
+                },
+                (None, None) => bail!("pack has a cycle of deltas"),
+            };
+            by_id.insert(base.2, count + bases.len());
+            bases.push(base);
+        }
+    }
+
+    let entries = entries
+        .into_iter()
+        .zip(objects)
+        .map(|((offset, crc, _), object)| IndexEntry {
+            offset,
+            crc,
+            id: object.unwrap().2,
+        })
+        .collect();
+    Ok((entries, checksum, bases))
+}
+
+/// Apply the delta of the `n`th entry to `base`, refusing a result larger than
+/// `max_size`.
+fn apply(base: &[u8], delta: &[u8], n: usize, max_size: u64) -> Result<Vec<u8>> {
+    let (_, result_size) =
+        delta_sizes(delta).with_context(|| format!("bad delta in entry {n} of pack"))?;
+    ensure!(
+        result_size as u64 <= max_size,
+        "entry {n} of pack is {result_size} bytes, exceeding the maximum object size of \
+         {max_size} bytes"
+    );
+    apply_delta(base, delta).with_context(|| format!("bad delta in entry {n} of pack"))
+}
+
+/// Read the entry starting at `position` of `pack`: what it holds, and where the
+/// next one starts. `offsets` has the index of each entry before it by offset. An
+/// entry whose header gives a size over `max_size` is refused before it is inflated.
+fn read_entry(
+    pack: &[u8],
+    position: usize,
+    algorithm: HashAlgorithm,
+    offsets: &HashMap<u64, usize>,
+    max_size: u64,
+) -> Result<(Content, usize)> {
+    let name = || format!("entry at offset {position} of pack");
+    let mut reader = &pack[position..];
+    let mut byte = read_byte(&mut reader)?;
+    let kind = (byte >> 4) & 0x07;
+    let mut size = u64::from(byte & 0x0f);
+    let mut shift = 4;
+    while byte & 0x80 != 0 {
+        ensure!(shift < 64, "{} has a bad size", name());
+        byte = read_byte(&mut reader)?;
+        size |= u64::from(byte & 0x7f) << shift;
+        shift += 7;
+    }
+    ensure!(
+        size <= max_size,
+        "{} is {size} bytes, exceeding the maximum object size of {max_size} bytes",
+        name()
+    );
+    enum Base {
+        None,
+        Offset(usize),
+        Id(ObjectId),
+    }
+    let base = match kind {
+        6 => {
+            let mut byte = read_byte(&mut reader)?;
+            let mut distance = u64::from(byte & 0x7f);
+            while byte & 0x80 != 0 {
+                ensure!(distance < 1 << 56, "{} has a bad base", name());
+                byte = read_byte(&mut reader)?;
+                distance = ((distance + 1) << 7) | u64::from(byte & 0x7f);
+            }
+            let base = (position as u64)
+                .checked_sub(distance)
+                .filter(|_| distance > 0);
+            let Some(&base) = base.and_then(|base| offsets.get(&base)) else {
+                bail!("{} has a base that isn't an entry of the pack", name());
+            };
+            Base::Offset(base)
+        }
+        7 => {
+            let mut id = vec![0; algorithm.len()];
+            reader
+                .read_exact(&mut id)
+                .with_context(|| format!("{} is truncated", name()))?;
+            Base::Id(ObjectId::from_bytes(algorithm, &id)?)
+        }
+        1..=4 => Base::None,
+        _ => bail!("{} has unknown type {kind}", name()),
+    };
+
+    let header_len = pack.len() - position - reader.len();
+    let mut decoder = ZlibDecoder::new(reader);
+    let mut data = Vec::new();
+    (&mut decoder)
+        .take(size + 1)
+        .read_to_end(&mut data)
+        .with_context(|| format!("failed to inflate {}", name()))?;
+    ensure!(
+        data.len() as u64 == size,
+        "{} is {} bytes, but its header says {size}",
+        name(),
+        data.len()
+    );
+    let end = position + header_len + decoder.total_in() as usize;
+    let content = match base {
+        Base::None => Content::Base(entry_kind(kind), data),
+        Base::Offset(base) => Content::OffsetDelta(base, data),
+        Base::Id(id) => Content::RefDelta(id, data),
+    };
+    Ok((content, end))
+}
+
+/// A version 2 pack index (`.idx`) of `entries`, for the pack whose checksum is
+/// `pack_checksum`: the fan-out table, then the ids in ascending order with their
+/// entries' CRC32s and offsets, offsets of 2 GiB and over in a table of their own,
+/// then the pack's checksum and the index's.
+pub fn write_index(entries: &[IndexEntry], pack_checksum: &ObjectId) -> Vec<u8> {
+    let mut sorted: Vec<&IndexEntry> = entries.iter().collect();
+    sorted.sort_by(|a, b| a.id.as_bytes().cmp(b.id.as_bytes()));
+    let mut index = b"\xfftOc\0\0\0\x02".to_vec();
+    let mut fanout = [0u32; 256];
+    for entry in &sorted {
+        fanout[usize::from(entry.id.as_bytes()[0])] += 1;
+    }
+    let mut total = 0;
+    for count in fanout {
+        total += count;
+        index.extend(total.to_be_bytes());
+    }
+    for entry in &sorted {
+        index.extend(entry.id.as_bytes());
+    }
+    for entry in &sorted {
+        index.extend(entry.crc.to_be_bytes());
+    }
+    let mut large = Vec::new();
+    for entry in &sorted {
+        match u32::try_from(entry.offset) {
+            Ok(offset) if offset & 0x8000_0000 == 0 => index.extend(offset.to_be_bytes()),
+            _ => {
+                index.extend((0x8000_0000 | large.len() as u32).to_be_bytes());
+                large.push(entry.offset);
+            }
+        }
+    }
+    for offset in large {
+        index.extend(offset.to_be_bytes());
+    }
+    index.extend(pack_checksum.as_bytes());
+    let checksum = pack_checksum.algorithm().digest(&index);
+    index.extend(checksum.as_bytes());
+    index
+}
+
+/// Index the pack `data` and store it in `objects_dir` as `pack/pack-<checksum>.pack`
+/// with its `.idx`, read-only as git leaves them, the index written last so that the
+/// pack is only looked in once it is whole. With `bases_dir`, a thin pack is completed
+/// first with the bases it lacks from there (see [`fix_thin_pack`]). Returns the
+/// pack's checksum.
+pub fn store_pack(
+    objects_dir: &Path,
+    data: &[u8],
+    bases_dir: Option<&Path>,
+    algorithm: HashAlgorithm,
+) -> Result<ObjectId> {
+    let (data, entries, checksum) = match bases_dir {
+        Some(bases_dir) => fix_thin_pack(data, bases_dir, algorithm)?,
+        None => {
+            let (entries, checksum) = index_pack(data, algorithm)?;
+            (data.to_vec(), entries, checksum)
+        }
+    };
+    let dir = objects_dir.join("pack");
+    create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    write_pack_files(
+        &dir.join("pack"),
+        &data,
+        &write_index(&entries, &checksum),
+        &checksum,
+    )?;
+    forget_packs(objects_dir);
+    Ok(checksum)
+}
+
+/// `git index-pack`: index the pack `pack_file`, writing its `.idx` beside it, or with
+/// `stdin` the pack read from standard input, stored in the repository's object store
+/// with its index (see [`store_pack`]). With `fix_thin` (only with `stdin`, as in git)
+/// a thin pack is completed with the bases the repository has. Returns the pack's
+/// checksum.
+pub fn git_index_pack(
+    repo: &Repository,
+    pack_file: Option<&Path>,
+    stdin: bool,
+    fix_thin: bool,
+) -> Result<ObjectId> {
+    let algorithm = repo.hash_algorithm()?;
+    let Some(pack_file) = pack_file.filter(|_| !stdin) else {
+        ensure!(
+            stdin,
+            "usage: git index-pack [--stdin [--fix-thin]] <pack-file>"
+        );
+        let mut data = Vec::new();
+        io::stdin()
+            .read_to_end(&mut data)
+            .context("failed to read the pack from stdin")?;
+        let objects_dir = repo.objects_dir();
+        let bases_dir = Some(objects_dir.as_path()).filter(|_| fix_thin);
+        return store_pack(&objects_dir, &data, bases_dir, algorithm);
+    };
+    ensure!(!fix_thin, "--fix-thin cannot be used without --stdin");
+    let data =
+        read(pack_file).with_context(|| format!("failed to read {}", pack_file.display()))?;
+    let (entries, checksum) = index_pack(&data, algorithm)?;
+    let index_path = pack_file.with_extension("idx");
+    ensure!(
+        index_path != pack_file,
+        "packfile name '{}' does not end with '.pack'",
+        pack_file.display()
+    );
+    write(&index_path, write_index(&entries, &checksum))
+        .with_context(|| format!("failed to write {}", index_path.display()))?;
+    Ok(checksum)
+}
+
+/// Write the pack `data` and its `index` as `<base_name>-<checksum>.pack` and `.idx`,
+/// read-only, the index last, leaving alone files of those names that are already
+/// there (they hold the same pack, by its checksum).
+pub fn write_pack_files(
+    base_name: &Path,
+    data: &[u8],
+    index: &[u8],
+    checksum: &ObjectId,
+) -> Result<()> {
+    let dir = match base_name.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    for (extension, content) in [("pack", data), ("idx", index)] {
+        let mut path = base_name.as_os_str().to_owned();
+        path.push(format!("-{checksum}.{extension}"));
+        let path = PathBuf::from(path);
+        if path.is_file() {
+            continue;
+        }
+        let mut file = NamedTempFile::new_in(dir)?;
+        file.write_all(content)?;
+        file.as_file()
+            .set_permissions(Permissions::from_mode(0o444))?;
+        file.persist(&path)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    Ok(())
+}
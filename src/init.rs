@@ -1,12 +1,60 @@
+use crate::config::set_config_value;
+use crate::object_id::HashAlgorithm;
+use crate::repository::Repository;
 use anyhow::Result;
-use std::fs;
+use std::env;
+use std::fs::{self, Permissions};
+use std::os::unix::fs::{PermissionsExt, symlink};
 
 /// Create required directory for git
-pub fn git_init() -> Result<()> {
-    fs::create_dir(".git")?;
-    fs::create_dir(".git/objects")?;
-    fs::create_dir(".git/refs")?;
-    fs::write(".git/HEAD", "ref: refs/heads/main\n")?;
+///
+/// On a filesystem that doesn't tell names apart by case, `core.ignoreCase` is set,
+/// on one that can't hold symlinks `core.symlinks=false`, and on one that doesn't keep
+/// executable bits `core.fileMode=false`, as git does, so that the index and working
+/// directory are compared accordingly.
+///
+/// Objects are named with `object_format`, or else the algorithm `GIT_DEFAULT_HASH`
+/// names, or SHA-1. Any other than SHA-1 is recorded as `extensions.objectFormat`,
+/// which needs `core.repositoryFormatVersion` 1 so that tools without the extension
+/// refuse the repository rather than misread it.
+pub fn git_init(repo: &Repository, object_format: Option<HashAlgorithm>) -> Result<()> {
+    create_git_dir(repo, object_format)?;
     println!("Initialized git directory");
     Ok(())
 }
+
+/// Create the git directory of the new repository `repo`, as [`git_init`] does,
+/// without reporting it.
+pub fn create_git_dir(repo: &Repository, object_format: Option<HashAlgorithm>) -> Result<()> {
+    let algorithm = match (object_format, env::var("GIT_DEFAULT_HASH")) {
+        (Some(algorithm), _) => algorithm,
+        (None, Ok(name)) if !name.is_empty() => name.parse()?,
+        _ => HashAlgorithm::Sha1,
+    };
+    let git_dir = repo.git_dir.as_path();
+    let config = &git_dir.join("config");
+    let head_path = &git_dir.join("HEAD");
+    fs::create_dir(git_dir)?;
+    fs::create_dir(git_dir.join("objects"))?;
+    fs::create_dir(git_dir.join("refs"))?;
+    fs::write(head_path, "ref: refs/heads/main\n")?;
+    if fs::symlink_metadata(git_dir.join("head")).is_ok() {
+        set_config_value(config, "core.ignoreCase", "true")?;
+    }
+    let symlink_test = git_dir.join("symlink-test");
+    if symlink("HEAD", &symlink_test).is_err() {
+        set_config_value(config, "core.symlinks", "false")?;
+    }
+    let _ = fs::remove_file(&symlink_test);
+    let head = fs::metadata(head_path)?.permissions();
+    fs::set_permissions(head_path, Permissions::from_mode(head.mode() ^ 0o100))?;
+    if fs::metadata(head_path)?.permissions().mode() == head.mode() {
+        set_config_value(config, "core.fileMode", "false")?;
+    }
+    fs::set_permissions(head_path, head)?;
+    if algorithm != HashAlgorithm::Sha1 {
+        set_config_value(config, "core.repositoryFormatVersion", "1")?;
+        set_config_value(config, "extensions.objectFormat", algorithm.name())?;
+    }
+    Ok(())
+}
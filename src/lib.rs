@@ -0,0 +1,88 @@
+//! A reimplementation of git's commands and on-disk formats: objects loose and
+//! packed, the index, refs, config, diffs, merges and the transfer protocols.
+//!
+//! Each command lives in its own module as a `git_<command>` function taking the
+//! arguments and an options struct, which the `git-rs` binary calls; the modules
+//! they build on (e.g. [`object_read`], [`refs`], [`diff`]) can be used directly.
+//! Each works on the [`repository::Repository`] it is given, which
+//! [`Repository::discover`](repository::Repository::discover) finds from the current
+//! directory as git's commands do.
+
+pub mod add;
+pub mod am;
+pub mod apply;
+pub mod attr;
+pub mod base85;
+pub mod bitmap;
+pub mod branch;
+pub mod cat_file;
+pub mod checkout;
+pub mod cherry;
+pub mod clone;
+pub mod commit;
+pub mod commit_graph;
+pub mod commit_read;
+pub mod config;
+pub mod convert;
+pub mod delta;
+pub mod describe;
+pub mod diff;
+pub mod encoding;
+pub mod fetch;
+pub mod fmt_merge_msg;
+pub mod fsck;
+pub mod gc;
+pub mod gpg;
+pub mod hash_object;
+pub mod history;
+pub mod hooks;
+pub mod http;
+pub mod ident;
+pub mod index;
+pub mod index_pack;
+pub mod init;
+pub mod log;
+pub mod ls_files;
+pub mod ls_tree;
+pub mod mailinfo;
+pub mod mailmap;
+pub mod merge;
+pub mod mktag;
+pub mod mktree;
+pub mod object_id;
+pub mod object_read;
+pub mod object_write;
+pub mod odb;
+pub mod pack;
+pub mod pack_objects;
+pub mod pack_refs;
+pub mod pack_write;
+pub mod parallel_checkout;
+pub mod patch_id;
+pub mod pkt_line;
+pub mod push;
+pub mod receive;
+pub mod refs;
+pub mod remote;
+pub mod rename;
+pub mod repo_stats;
+pub mod repository;
+pub mod restore;
+pub mod rev_list;
+pub mod rev_parse;
+pub mod revwalk;
+pub mod sequencer;
+pub mod show_index;
+pub mod stash;
+pub mod status;
+pub mod submodule;
+pub mod subtree;
+pub mod tag;
+pub mod transfer;
+pub mod update_ref;
+pub mod userdiff;
+pub mod var;
+pub mod whitespace;
+pub mod wildmatch;
+pub mod worktree;
+pub mod write_tree;
@@ -0,0 +1,170 @@
+use crate::commit_read::Commit;
+use crate::diff::diff_trees;
+use crate::history::{Simplification, revision_range, simplified_history};
+use crate::mailmap::Mailmap;
+use crate::refs::{current_branch, resolve_ref};
+use crate::rename::rename_source;
+use crate::repository::Repository;
+use anyhow::{Result, bail};
+use std::collections::HashSet;
+use std::fmt::Write;
+
+/// How `git log` shows the commits it walks.
+#[derive(Debug, Default)]
+pub struct LogOptions {
+    /// Stop after this many commits (`-n`).
+    pub max_count: Option<usize>,
+    /// One `<abbreviated id> <subject>` line per commit.
+    pub oneline: bool,
+    /// Show only the commits that change the one path given, following it back
+    /// across renames (`--follow`).
+    pub follow: bool,
+    /// How history limited to paths is simplified.
+    pub simplification: Simplification,
+    /// Follow only the first parent of merges (`--first-parent`).
+    pub first_parent: bool,
+    /// Show authors as this canonicalizes them (`--use-mailmap`, or `log.mailmap`).
+    pub mailmap: Option<Mailmap>,
+}
+
+/// `git log [<revision>...]`: the commits reachable from the revisions (HEAD if
+/// there are none), newest first by committer date, up to `options.max_count` of
+/// them, each for [`format_commit`] to show.
+///
+/// A revision starting with `^` hides the commits reachable from it, and `A..B`
+/// stands for `^A B`, as for `git rev-list`. Messages are shown in UTF-8 whatever
+/// encoding they were committed in, and authors as `options.mailmap` has them.
+///
+/// With `paths`, only the commits that change something under them are shown, with
+/// history simplified as `options.simplification` says. With `--follow`, `paths` must
+/// name one file instead: the commits that change it are shown, and where one adds
+/// it as a rename of another file, the history of that file is followed from there
+/// on, without simplification.
+pub fn git_log(
+    repo: &Repository,
+    revisions: &[String],
+    paths: &[String],
+    options: &LogOptions,
+) -> Result<Vec<Commit>> {
+    let mut follow = match (options.follow, paths) {
+        (false, []) => None,
+        (true, [path]) => Some(path.strip_prefix("./").unwrap_or(path).to_string()),
+        (true, _) => bail!("--follow requires exactly one pathspec"),
+        (false, _) => None,
+    };
+    let limiting = if follow.is_some() { &[][..] } else { paths };
+    let (mut tips, hide) = revision_range(revisions);
+    if revisions.is_empty() {
+        if resolve_ref(repo, "HEAD")?.is_none() {
+            match current_branch(repo)? {
+                Some(branch) => {
+                    bail!("your current branch '{branch}' does not have any commits yet")
+                }
+                None => bail!("HEAD does not point to a commit"),
+            }
+        }
+        tips.push("HEAD".to_string());
+    }
+
+    let mut commits = simplified_history(
+        repo,
+        &tips,
+        &hide,
+        limiting,
+        options.simplification,
+        options.first_parent,
+    )?;
+    if let Some(path) = &mut follow {
+        let mut touching = Vec::new();
+        for commit in commits {
+            if follows_into(repo, &commit, path)? {
+                touching.push(commit);
+            }
+        }
+        commits = touching;
+    }
+    commits.truncate(options.max_count.unwrap_or(usize::MAX));
+    Ok(commits)
+}
+
+/// Whether `commit` changes `path` (a file or directory) compared to its parent, and
+/// if it adds it as a rename, switch `path` to the file it was renamed from. Merges
+/// are never shown in followed history, nor followed through.
+fn follows_into(repo: &Repository, commit: &Commit, path: &mut String) -> Result<bool> {
+    if commit.parents.len() > 1 {
+        return Ok(false);
+    }
+    let parent_tree = match commit.parents.first() {
+        Some(parent) => Some(Commit::read(repo, parent)?.tree.parse()?),
+        None => None,
+    };
+    let changes = diff_trees(repo, parent_tree.as_ref(), Some(&commit.tree.parse()?))?;
+    let under = |changed: &str| {
+        changed == path.as_str()
+            || changed
+                .strip_prefix(path.as_str())
+                .is_some_and(|rest| rest.starts_with('/'))
+    };
+    let touched: Vec<_> = changes
+        .iter()
+        .filter(|change| under(&change.path))
+        .collect();
+    if touched.is_empty() {
+        return Ok(false);
+    }
+    let added = changes
+        .iter()
+        .find(|change| change.path == *path && change.old.is_none());
+    if touched.iter().any(|change| change.old.is_none())
+        && let Some(added) = added.and_then(|change| change.new.as_ref())
+    {
+        // Any file of the parent may be the source, as for `--find-copies-harder`.
+        let deleted: HashSet<&str> = changes
+            .iter()
+            .filter(|change| change.new.is_none())
+            .map(|change| change.path.as_str())
+            .collect();
+        let sources: Vec<_> = diff_trees(repo, None, parent_tree.as_ref())?
+            .into_iter()
+            .filter_map(|change| {
+                let deleted = deleted.contains(change.path.as_str());
+                Some((change.path, change.new?, deleted))
+            })
+            .collect();
+        if let Some(source) = rename_source(repo, &sources, path, added)? {
+            *path = source;
+        }
+    }
+    Ok(true)
+}
+
+/// `commit` as `git log` shows it: with `options.oneline`, its abbreviated id and
+/// subject on one line; otherwise in git's default "medium" format, its id, the
+/// parents of a merge, author (through `options.mailmap`, if given), author date, then
+/// the message indented by four spaces. Commits in the medium format are separated by
+/// a blank line, which is left to the caller.
+pub fn format_commit(commit: &Commit, options: &LogOptions) -> String {
+    if options.oneline {
+        return format!("{} {}\n", &commit.hash[..7], commit.subject());
+    }
+    let mut out = format!("commit {}\n", commit.hash);
+    if commit.parents.len() > 1 {
+        let parents: Vec<&str> = commit.parents.iter().map(|p| &p[..7]).collect();
+        writeln!(out, "Merge: {}", parents.join(" ")).unwrap();
+    }
+    let (name, email) = match &options.mailmap {
+        Some(mailmap) => mailmap.map(&commit.author.name, &commit.author.email),
+        None => (commit.author.name.as_str(), commit.author.email.as_str()),
+    };
+    writeln!(out, "Author: {name} <{email}>").unwrap();
+    writeln!(out, "Date:   {}\n", commit.author.date()).unwrap();
+    let lines: Vec<&str> = commit.message.lines().collect();
+    let start = lines.iter().position(|line| !line.trim().is_empty());
+    let end = lines.iter().rposition(|line| !line.trim().is_empty());
+    if let (Some(start), Some(end)) = (start, end) {
+        for line in &lines[start..=end] {
+            writeln!(out, "    {line}").unwrap();
+        }
+    }
+    out
+}
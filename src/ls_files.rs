@@ -0,0 +1,120 @@
+use crate::attr::Attributes;
+use crate::config::Config;
+use crate::convert::{ContentEol, EolAttr};
+use crate::index::{Index, IndexEntry};
+use crate::object_read::Object;
+use crate::repository::Repository;
+use crate::status::{UntrackedFiles, untracked_files};
+use anyhow::Result;
+use std::fmt::Write;
+use std::fs::{read, symlink_metadata};
+
+/// What `git ls-files` shows of each file.
+#[derive(Debug, Clone, Default)]
+pub struct LsFilesOptions {
+    /// List the files in the index (`--cached`, the default without `--others`).
+    pub cached: bool,
+    /// Precede each file in the index with its mode, object id and stage (`--stage`),
+    /// which lists them even with `--others`.
+    pub stage: bool,
+    /// List the untracked files (`--others`).
+    pub others: bool,
+    /// Leave out the untracked files ignored by `.gitignore`, `.git/info/exclude` and
+    /// `core.excludesFile` (`--exclude-standard`).
+    pub exclude_standard: bool,
+    /// Show the line endings of the index and working tree versions and the
+    /// attributes that convert them (`--eol`).
+    pub eol: bool,
+    /// Terminate entries with NUL instead of newline (`-z`).
+    pub null_terminated: bool,
+}
+
+/// `git ls-files [-c] [-s] [-o [--exclude-standard]] [--eol] [-z] [<path>...]`: the
+/// untracked files with `others`, then the paths in the index, each stage of an
+/// unmerged one, all limited to those under `paths` if any are given. With `stage`,
+/// an index entry is shown as
+///
+/// ```text
+/// 100644 <object id> <stage><TAB>path
+/// ```
+///
+/// With `eol`, each path is preceded by what its index and working tree contents look
+/// like (see [`ContentEol`]; nothing for a symlink or submodule, or for an untracked
+/// file's index contents) and how its attributes convert line endings (see
+/// [`EolAttr`]):
+///
+/// ```text
+/// i/lf    w/crlf  attr/text eol=crlf    <TAB>crlf.txt
+/// ```
+pub fn git_ls_files(
+    repo: &Repository,
+    config: &Config,
+    paths: &[String],
+    options: &LsFilesOptions,
+) -> Result<String> {
+    let index = Index::load(repo)?;
+    let attributes = options
+        .eol
+        .then(|| Attributes::load(repo, config))
+        .transpose()?;
+    let wanted = |path: &str| {
+        paths.is_empty()
+            || paths.iter().any(|spec| {
+                let spec = spec
+                    .strip_prefix("./")
+                    .unwrap_or(spec)
+                    .trim_end_matches('/');
+                spec == "." || path == spec || path.starts_with(&format!("{spec}/"))
+            })
+    };
+    let eol_info = |entry: Option<&IndexEntry>, path: &str| -> Result<String> {
+        let Some(attributes) = &attributes else {
+            return Ok(String::new());
+        };
+        let index_eol = match entry {
+            Some(entry) if entry.mode & 0o170000 == 0o100000 => {
+                let data = Object::read_git_object(repo, &entry.id.to_string())?.read_all()?;
+                ContentEol::of(&data).as_str()
+            }
+            _ => "",
+        };
+        let file = repo.path(path);
+        let worktree_eol = match symlink_metadata(&file) {
+            Ok(metadata) if metadata.is_file() => ContentEol::of(&read(&file)?).as_str(),
+            _ => "",
+        };
+        let attr = EolAttr::of(attributes, path)?.as_str();
+        Ok(format!(
+            "i/{index_eol:<5} w/{worktree_eol:<5} attr/{attr:<17}\t"
+        ))
+    };
+    let terminator = if options.null_terminated { '\0' } else { '\n' };
+
+    let mut out = String::new();
+    if options.others {
+        let ignore_case = config.get_bool("core.ignoreCase")?.unwrap_or(false);
+        let others = untracked_files(
+            repo,
+            &index,
+            UntrackedFiles::All,
+            ignore_case,
+            options.exclude_standard,
+        );
+        for path in others.iter().filter(|path| wanted(path)) {
+            out.push_str(&eol_info(None, path)?);
+            out.push_str(path);
+            out.push(terminator);
+        }
+    }
+    if options.cached || options.stage || !options.others {
+        for entry in index.entries.iter().filter(|entry| wanted(&entry.path)) {
+            if options.stage {
+                write!(out, "{:06o} {} {}\t", entry.mode, entry.id, entry.stage)?;
+            }
+            out.push_str(&eol_info(Some(entry), &entry.path)?);
+            out.push_str(&entry.path);
+            out.push(terminator);
+        }
+    }
+    Ok(out)
+}
@@ -1,21 +1,101 @@
+use crate::object_id::ObjectId;
 use crate::object_read::{Object, ObjectKind};
-use anyhow::{Context, Result, anyhow, bail};
+use crate::repository::Repository;
+use anyhow::{Context, Result, bail};
 use std::ffi::CStr;
-use std::io::{BufRead, Write, stdout};
+use std::io::{BufRead, Read, Take};
 
-pub fn git_ls_tree(name_only: bool, tree_hash: &str) -> Result<()> {
-    let object = Object::read_git_object(tree_hash)?;
-    match object.kind {
-        ObjectKind::Tree => git_read_tree_content(object, name_only),
-        _ => Err(anyhow!("not a tree object")),
+#[derive(Debug, Default, Clone)]
+pub struct LsTreeOptions {
+    /// Print only the entry names.
+    pub name_only: bool,
+    /// Show the size of blob entries.
+    pub long: bool,
+    /// Abbreviate object ids to this many hex digits.
+    pub abbrev: Option<usize>,
+    /// Terminate entries with NUL instead of newline.
+    pub null_terminated: bool,
+}
+
+/// A single `<mode> <name>\0<hash>` record of a tree object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeEntry {
+    pub mode: String,
+    pub name: String,
+    pub hash: ObjectId,
+}
+
+impl TreeEntry {
+    pub fn kind(&self) -> Result<ObjectKind> {
+        ObjectKind::from_mode(&self.mode)
+    }
+}
+
+/// The entries of the tree named by `tree_ish`, which may also be a commit, a tag or
+/// a ref like `HEAD`; these are peeled to the tree they point at. When `paths` is
+/// non-empty only matching entries are listed: `dir` lists the entry itself, `dir/`
+/// its contents, and `dir/file` descends into `dir` to list just that file. Only
+/// subtrees on the way to a requested path are read. Entries are named by their path
+/// from the top of the tree; see [`format_tree_entry`] for how `git ls-tree` shows
+/// them.
+pub fn git_ls_tree(repo: &Repository, tree_ish: &str, paths: &[String]) -> Result<Vec<TreeEntry>> {
+    let (_, object) = Object::peel_to(repo, tree_ish, ObjectKind::Tree)?;
+    let entries = read_tree_entries(repo, object)?;
+    if paths.is_empty() {
+        return Ok(entries);
     }
+    let paths: Vec<&str> = paths
+        .iter()
+        .map(|p| if p == "." { "" } else { p.as_str() })
+        .collect();
+    let mut listed = Vec::new();
+    ls_tree_filtered(repo, &mut listed, entries, "", &paths)?;
+    Ok(listed)
 }
 
-pub fn git_read_tree_content<R: BufRead>(mut object: Object<R>, name_only: bool) -> Result<()> {
+fn ls_tree_filtered(
+    repo: &Repository,
+    listed: &mut Vec<TreeEntry>,
+    entries: Vec<TreeEntry>,
+    prefix: &str,
+    paths: &[&str],
+) -> Result<()> {
+    for mut entry in entries {
+        let full_name = format!("{prefix}{}", entry.name);
+        let kind = entry.kind()?;
+        let hash = entry.hash;
+        if paths.iter().any(|p| *p == prefix || *p == full_name) {
+            entry.name = full_name.clone();
+            listed.push(entry);
+        }
+        if kind != ObjectKind::Tree {
+            continue;
+        }
+        let dir_prefix = format!("{full_name}/");
+        let sub_paths: Vec<&str> = paths
+            .iter()
+            .copied()
+            .filter(|p| p.starts_with(&dir_prefix))
+            .collect();
+        if !sub_paths.is_empty() {
+            let subtree = Object::read_git_object(repo, &hash.to_string())?;
+            let sub_entries = read_tree_entries(repo, subtree)?;
+            ls_tree_filtered(repo, listed, sub_entries, &dir_prefix, &sub_paths)?;
+        }
+    }
+    Ok(())
+}
+
+/// Parse every entry of a tree object of `repo`, whose hash function says how long
+/// the ids in it are.
+pub fn read_tree_entries<R: BufRead>(
+    repo: &Repository,
+    mut object: Object<Take<R>>,
+) -> Result<Vec<TreeEntry>> {
+    let algorithm = repo.hash_algorithm()?;
     let mut buf = Vec::new();
-    let mut sout = stdout().lock();
-    let mut hash_buf = [0; 20];
-    // TODO: read only expected size
+    let mut hash = vec![0; algorithm.len()];
+    let mut entries = Vec::new();
     loop {
         buf.clear();
         let n = object
@@ -35,21 +115,59 @@ pub fn git_read_tree_content<R: BufRead>(mut object: Object<R>, name_only: bool)
         };
         object
             .reader
-            .read_exact(&mut hash_buf)
+            .read_exact(&mut hash)
             .context("invalid tree entry format")?;
-        let kind = ObjectKind::from_mode(mode)?;
-        let out_entry = if name_only {
-            format!("{name}\n")
+        entries.push(TreeEntry {
+            mode: mode.to_string(),
+            name: name.to_string(),
+            hash: ObjectId::from_bytes(algorithm, &hash)?,
+        });
+    }
+    object.ensure_fully_read()?;
+    Ok(entries)
+}
+
+/// `entry` as `git ls-tree` shows it, with its terminator: its mode, type, id and
+/// name, also the size of blobs with `long`, or just the name with `name_only`.
+pub fn format_tree_entry(
+    repo: &Repository,
+    options: &LsTreeOptions,
+    entry: &TreeEntry,
+) -> Result<String> {
+    let name = &entry.name;
+    let terminator = if options.null_terminated { '\0' } else { '\n' };
+    let kind = entry.kind()?;
+    if options.name_only {
+        return Ok(format!("{name}{terminator}"));
+    }
+    let id = entry.hash;
+    let hash = id.to_string();
+    let shown_hash = match options.abbrev {
+        Some(abbrev) => id.short(abbrev),
+        None => hash.clone(),
+    };
+    if options.long {
+        // Only blobs have a meaningful size; trees and submodule commits show "-".
+        let size = if kind == ObjectKind::Blob {
+            Object::read_git_object(repo, &hash)?
+                .expected_size
+                .to_string()
         } else {
-            format!(
-                "{:0>6} {} {}    {name}\n",
-                mode,
-                kind.to_str(),
-                hex::encode(hash_buf)
-            )
+            "-".to_string()
         };
-        sout.write_all(out_entry.as_bytes())
-            .context("write to stdout failed")?;
+        Ok(format!(
+            "{:0>6} {} {} {:>7}    {name}{terminator}",
+            entry.mode,
+            kind.to_str(),
+            shown_hash,
+            size
+        ))
+    } else {
+        Ok(format!(
+            "{:0>6} {} {}    {name}{terminator}",
+            entry.mode,
+            kind.to_str(),
+            shown_hash
+        ))
     }
-    Ok(())
 }
@@ -0,0 +1,467 @@
+use crate::encoding::Encoding;
+use anyhow::{Context, Result, bail};
+use std::fs::{create_dir_all, read, write};
+use std::io::{Read, stdin};
+use std::path::Path;
+
+/// What `git mailinfo` extracts from an email carrying a patch.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MailInfo {
+    pub author_name: String,
+    pub author_email: String,
+    /// The `Date` header, as written (RFC 2822 for mails from `format-patch`).
+    pub date: Option<String>,
+    /// The subject, with `Re:` and `[PATCH ...]` prefixes removed.
+    pub subject: String,
+    /// The commit message body, i.e. everything before the patch, without the
+    /// subject.
+    pub body: String,
+    /// Everything from the first line that starts a patch (`---`, `diff -` or
+    /// `Index: `) on, byte for byte, as it may change binary or non-UTF-8 files.
+    pub patch: Vec<u8>,
+}
+
+impl MailInfo {
+    /// The commit message: the subject, then the body as a separate paragraph.
+    pub fn message(&self) -> String {
+        let mut message = self.subject.clone();
+        message.push('\n');
+        if !self.body.is_empty() {
+            message.push('\n');
+            message.push_str(&self.body);
+        }
+        message
+    }
+
+    /// `Author`, `Email`, `Subject` and `Date` lines, as `git mailinfo` prints them.
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "Author: {}\nEmail: {}\nSubject: {}\n",
+            self.author_name, self.author_email, self.subject
+        );
+        if let Some(date) = &self.date {
+            summary.push_str(&format!("Date: {date}\n"));
+        }
+        summary.push('\n');
+        summary
+    }
+}
+
+/// `git mailsplit`: write each message of the mboxes at `paths` (stdin for `-`, or if
+/// there are none) to `<dir>/0001`, `<dir>/0002` and so on, returning how many
+/// there were.
+pub fn git_mailsplit(dir: &Path, paths: &[String]) -> Result<usize> {
+    create_dir_all(dir).with_context(|| format!("cannot create directory {}", dir.display()))?;
+    let mut count = 0;
+    let inputs: Vec<&str> = match paths {
+        [] => vec!["-"],
+        _ => paths.iter().map(String::as_str).collect(),
+    };
+    for input in inputs {
+        for message in split_mbox(&read_input(input)?) {
+            count += 1;
+            let path = dir.join(format!("{count:04}"));
+            write(&path, message).with_context(|| format!("cannot write {}", path.display()))?;
+        }
+    }
+    Ok(count)
+}
+
+/// `git mailinfo`: parse the email on stdin, write its message body to `msg_path` and
+/// its patch to `patch_path`, and return the summary to print.
+pub fn git_mailinfo(msg_path: &Path, patch_path: &Path) -> Result<String> {
+    let info = parse_mail(&read_input("-")?)?;
+    write(msg_path, &info.body).with_context(|| format!("cannot write {}", msg_path.display()))?;
+    write(patch_path, &info.patch)
+        .with_context(|| format!("cannot write {}", patch_path.display()))?;
+    Ok(info.summary())
+}
+
+/// Read a file, or stdin for `-`, with CRLF line endings turned into LF as
+/// `mailsplit` does unless told to keep them.
+pub fn read_input(path: &str) -> Result<Vec<u8>> {
+    let data = if path == "-" {
+        let mut data = Vec::new();
+        stdin()
+            .read_to_end(&mut data)
+            .context("failed to read stdin")?;
+        data
+    } else {
+        read(path).with_context(|| format!("could not open '{path}'"))?
+    };
+    let mut out = Vec::with_capacity(data.len());
+    for line in data.split_inclusive(|&b| b == b'\n') {
+        match line.strip_suffix(b"\r\n") {
+            Some(line) => {
+                out.extend_from_slice(line);
+                out.push(b'\n');
+            }
+            None => out.extend_from_slice(line),
+        }
+    }
+    Ok(out)
+}
+
+/// Split an mbox into its messages at the `From ` lines that start each one. Input
+/// that doesn't start with one is a single message.
+pub fn split_mbox(mbox: &[u8]) -> Vec<Vec<u8>> {
+    let lines = mbox.split_inclusive(|&b| b == b'\n');
+    if !lines.clone().next().is_some_and(is_from_line) {
+        return if mbox.iter().all(u8::is_ascii_whitespace) {
+            Vec::new()
+        } else {
+            vec![mbox.to_vec()]
+        };
+    }
+    let mut messages: Vec<Vec<u8>> = Vec::new();
+    let mut previous_blank = true;
+    for line in lines {
+        if previous_blank && is_from_line(line) {
+            messages.push(Vec::new());
+        }
+        previous_blank = line == b"\n";
+        if let Some(message) = messages.last_mut() {
+            message.extend_from_slice(line);
+        }
+    }
+    messages
+}
+
+/// Whether `line` separates messages in an mbox: `From <sender> <date>`, the date
+/// containing a time.
+fn is_from_line(line: &[u8]) -> bool {
+    line.strip_prefix(b"From ")
+        .and_then(|rest| {
+            let space = rest.iter().position(|&b| b == b' ')?;
+            Some(&rest[space + 1..])
+        })
+        .is_some_and(|date| date.contains(&b':'))
+}
+
+/// Parse an email into its author, subject, message and patch, as `git mailinfo`
+/// does: headers may be RFC 2047 encoded, the body quoted-printable or base64, and
+/// `From:`, `Subject:` or `Date:` lines at the start of the body override the
+/// headers. The message is converted to UTF-8 from the charset `Content-Type` names;
+/// the patch is kept as it is.
+pub fn parse_mail(mail: &[u8]) -> Result<MailInfo> {
+    let mail = match mail.iter().position(|&b| b == b'\n') {
+        Some(end) if is_from_line(&mail[..end]) => &mail[end + 1..],
+        _ => mail,
+    };
+    let (headers, body) = split_headers(mail);
+    let mut info = MailInfo::default();
+    let mut encoding = None;
+    let mut charset = Encoding::Utf8;
+    for (name, value) in &headers {
+        apply_header(&mut info, name, value);
+        if name.eq_ignore_ascii_case("Content-Transfer-Encoding") {
+            encoding = Some(value.trim().to_ascii_lowercase());
+        } else if name.eq_ignore_ascii_case("Content-Type")
+            && let Some(named) = content_charset(value).and_then(Encoding::from_name)
+        {
+            charset = named;
+        }
+    }
+    if headers.iter().any(|(name, value)| {
+        name.eq_ignore_ascii_case("Content-Type") && value.contains("multipart/")
+    }) {
+        bail!("multipart messages aren't supported yet");
+    }
+    let body = match encoding.as_deref() {
+        Some("quoted-printable") => decode_quoted_printable(body),
+        Some("base64") => decode_base64(&String::from_utf8_lossy(body))?,
+        _ => body.to_vec(),
+    };
+
+    // Headers repeated at the top of the body, e.g. to credit another author.
+    let skip_blank_lines =
+        |body: &[u8]| -> usize { body.iter().take_while(|&&b| b == b'\n').count() };
+    let mut body = &body[skip_blank_lines(&body)..];
+    let mut overridden = false;
+    while let Some(end) = body.iter().position(|&b| b == b'\n') {
+        let line = charset.decode(&body[..end]);
+        let Some((name, value)) = line.split_once(':') else {
+            break;
+        };
+        if !["From", "Subject", "Date"].contains(&name) {
+            break;
+        }
+        apply_header(&mut info, name, value.trim());
+        overridden = true;
+        body = &body[end + 1..];
+    }
+    if overridden {
+        body = &body[skip_blank_lines(body)..];
+    }
+
+    let mut message = Vec::new();
+    let mut lines = body.split_inclusive(|&b| b == b'\n');
+    for line in lines.by_ref() {
+        if is_patch_start(line.strip_suffix(b"\n").unwrap_or(line)) {
+            info.patch.extend_from_slice(line);
+            break;
+        }
+        message.extend_from_slice(line);
+    }
+    lines.for_each(|line| info.patch.extend_from_slice(line));
+    info.body = clean_message(&charset.decode(&message));
+    if info.author_email.is_empty() {
+        bail!("no author found in the mail's From header");
+    }
+    Ok(info)
+}
+
+/// The `charset` parameter of a `Content-Type` header, without any quotes.
+fn content_charset(content_type: &str) -> Option<&str> {
+    content_type.split(';').skip(1).find_map(|parameter| {
+        let (name, value) = parameter.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("charset")
+            .then(|| value.trim().trim_matches('"'))
+    })
+}
+
+fn apply_header(info: &mut MailInfo, name: &str, value: &str) {
+    let value = decode_rfc2047(value);
+    if name.eq_ignore_ascii_case("From") {
+        let (author_name, author_email) = parse_address(&value);
+        info.author_name = author_name;
+        info.author_email = author_email;
+    } else if name.eq_ignore_ascii_case("Subject") {
+        info.subject = clean_subject(&value);
+    } else if name.eq_ignore_ascii_case("Date") {
+        info.date = Some(value.trim().to_string());
+    }
+}
+
+/// The unfolded headers and the body after the blank line that ends them.
+fn split_headers(mail: &[u8]) -> (Vec<(String, String)>, &[u8]) {
+    let mut headers: Vec<(String, String)> = Vec::new();
+    let mut rest = mail;
+    while !rest.is_empty() {
+        let (line, next) = match rest.iter().position(|&b| b == b'\n') {
+            Some(end) => (&rest[..end], &rest[end + 1..]),
+            None => (rest, &[][..]),
+        };
+        let line = String::from_utf8_lossy(line);
+        if line.is_empty() {
+            return (headers, next);
+        }
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push_str(&line);
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.to_string(), value.trim_start().to_string()));
+        } else {
+            // Not a header; the body starts without a separating blank line.
+            return (headers, rest);
+        }
+        rest = next;
+    }
+    (headers, rest)
+}
+
+/// Split `Name <email>`, `"Name" <email>`, `email (Name)` or a bare address. Without
+/// a name, the address stands in for it.
+fn parse_address(value: &str) -> (String, String) {
+    let value = value.trim();
+    let (name, email) = if let Some((name, rest)) = value.rsplit_once('<') {
+        (name, rest.split('>').next().unwrap_or(""))
+    } else if let Some((email, rest)) = value.split_once('(') {
+        (rest.trim_end_matches(')'), email)
+    } else {
+        ("", value)
+    };
+    let name = name.trim().trim_matches('"').replace("\\\"", "\"");
+    let email = email.trim().to_string();
+    if name.is_empty() {
+        (email.clone(), email)
+    } else {
+        (name, email)
+    }
+}
+
+/// Remove what mail programs and `format-patch` put before the real subject: `Re:`,
+/// bracketed tags like `[PATCH 2/3]`, and the whitespace around them.
+fn clean_subject(subject: &str) -> String {
+    let mut subject = subject.trim();
+    loop {
+        if subject.len() >= 3 && subject[..3].eq_ignore_ascii_case("re:") {
+            subject = subject[3..].trim_start();
+        } else if let Some(rest) = subject.strip_prefix(':') {
+            subject = rest.trim_start();
+        } else if subject.starts_with('[')
+            && let Some(end) = subject.find(']')
+        {
+            subject = subject[end + 1..].trim_start();
+        } else {
+            break;
+        }
+    }
+    subject.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Whether `line` starts the patch part of a mail body.
+fn is_patch_start(line: &[u8]) -> bool {
+    if line.starts_with(b"diff -") || line.starts_with(b"Index: ") {
+        return true;
+    }
+    match line.strip_prefix(b"---") {
+        // `--- a/file`, or the `---` line before the diffstat.
+        Some(rest) => {
+            rest.iter().all(u8::is_ascii_whitespace)
+                || (rest.starts_with(b" ") && !rest[1..].starts_with(b" "))
+        }
+        None => false,
+    }
+}
+
+/// Trailing whitespace and surrounding blank lines removed, runs of blank lines
+/// collapsed, as `git stripspace` does; empty or ending in a newline.
+pub fn clean_message(message: &str) -> String {
+    let mut out = String::new();
+    let mut blank = false;
+    for line in message.lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank = !out.is_empty();
+            continue;
+        }
+        if blank {
+            out.push('\n');
+            blank = false;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+/// Decode RFC 2047 encoded words (`=?UTF-8?q?...?=` and `=?UTF-8?b?...?=`), dropping
+/// the whitespace between adjacent ones. Only UTF-8, ASCII and Latin-1 are known;
+/// words in other charsets are left as they are.
+fn decode_rfc2047(value: &str) -> String {
+    let mut out = String::new();
+    let mut rest = value;
+    let mut after_word = false;
+    while let Some(start) = rest.find("=?") {
+        let (before, word) = rest.split_at(start);
+        let decoded = word[2..].split_once('?').and_then(|(charset, word)| {
+            let (encoding, word) = word.split_once('?')?;
+            let end = word.find("?=")?;
+            let bytes = match encoding {
+                "q" | "Q" => Some(decode_q(&word[..end])),
+                "b" | "B" => decode_base64(&word[..end]).ok(),
+                _ => None,
+            }?;
+            let text = match charset.to_ascii_lowercase().as_str() {
+                "utf-8" | "utf8" | "us-ascii" => String::from_utf8_lossy(&bytes).into_owned(),
+                "iso-8859-1" | "latin1" => bytes.iter().map(|&b| b as char).collect(),
+                _ => return None,
+            };
+            Some((text, charset.len() + encoding.len() + end + 6))
+        });
+        match decoded {
+            Some((text, len)) => {
+                if !(after_word && before.trim().is_empty()) {
+                    out.push_str(before);
+                }
+                out.push_str(&text);
+                rest = &word[len..];
+                after_word = true;
+            }
+            None => {
+                out.push_str(before);
+                out.push_str("=?");
+                rest = &word[2..];
+                after_word = false;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The `Q` encoding of RFC 2047: quoted-printable with `_` for a space.
+fn decode_q(word: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    let bytes = word.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'_' => out.push(b' '),
+            b'=' if let Some(byte) = hex_pair(&bytes[i + 1..]) => {
+                out.push(byte);
+                i += 2;
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    out
+}
+
+fn hex_pair(bytes: &[u8]) -> Option<u8> {
+    let pair = std::str::from_utf8(bytes.get(..2)?).ok()?;
+    u8::from_str_radix(pair, 16).ok()
+}
+
+fn decode_quoted_printable(body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for line in body.split_inclusive(|&b| b == b'\n') {
+        let (line, newline) = match line.strip_suffix(b"\n") {
+            Some(line) => (line.trim_ascii_end(), true),
+            None => (line, false),
+        };
+        let bytes = line;
+        let mut i = 0;
+        let mut soft_break = false;
+        while i < bytes.len() {
+            if bytes[i] == b'=' {
+                if i + 1 == bytes.len() {
+                    soft_break = true;
+                } else if let Some(byte) = hex_pair(&bytes[i + 1..]) {
+                    out.push(byte);
+                    i += 3;
+                    continue;
+                } else {
+                    out.push(b'=');
+                }
+            } else {
+                out.push(bytes[i]);
+            }
+            i += 1;
+        }
+        if newline && !soft_break {
+            out.push(b'\n');
+        }
+    }
+    out
+}
+
+/// Decode base64, ignoring whitespace and stopping at padding.
+pub fn decode_base64(text: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for byte in text.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            b' ' | b'\t' | b'\n' | b'\r' => continue,
+            _ => bail!("invalid base64 character {:?}", byte as char),
+        };
+        buffer = buffer << 6 | u32::from(value);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Ok(out)
+}
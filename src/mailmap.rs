@@ -0,0 +1,218 @@
+use crate::config::Config;
+use crate::object_read::{Object, ObjectKind};
+use crate::repository::Repository;
+use crate::rev_parse::resolve_revision;
+use anyhow::{Result, bail};
+use std::collections::HashMap;
+use std::fs::{read_to_string, symlink_metadata};
+use std::io::{BufRead, stdin};
+use std::path::Path;
+
+/// The name and email a mailmap entry gives in place of an identity's, either of
+/// which it may leave alone.
+#[derive(Debug, Clone, Default)]
+struct Replacement {
+    name: Option<String>,
+    email: Option<String>,
+}
+
+/// What a mailmap has for one email address: the replacement for any identity
+/// with it, and those for identities with it and a particular name, by lowercased
+/// name.
+#[derive(Debug, Default)]
+struct Entry {
+    any_name: Replacement,
+    by_name: HashMap<String, Replacement>,
+}
+
+/// The canonical names and emails of people, read from `.mailmap` files as
+/// gitmailmap(5) describes them, which `log`, `fmt-merge-msg` and `check-mailmap` show
+/// identities through.
+#[derive(Debug, Default)]
+pub struct Mailmap {
+    /// Entries by lowercased email: emails, like names, match whatever their case.
+    entries: HashMap<String, Entry>,
+}
+
+impl Mailmap {
+    /// The repository's mailmap, read as git reads it: `.mailmap` at the top of the
+    /// work tree (unless the repository is bare; a symlink is refused, as it could
+    /// point outside the repository), then the blob `mailmap.blob` names
+    /// (`HEAD:.mailmap` by default in a bare repository), then the file
+    /// `mailmap.file`, later entries for an identity replacing earlier ones. A file or
+    /// blob that doesn't exist is skipped; one that can't be read is reported and
+    /// skipped too.
+    pub fn load(repo: &Repository, config: &Config) -> Mailmap {
+        let mut mailmap = Mailmap::default();
+        let bare = config.get_bool("core.bare").ok().flatten() == Some(true);
+        if !bare {
+            mailmap.read_file(&repo.path(".mailmap"), false);
+        }
+        let blob = match config.get("mailmap.blob") {
+            Some(blob) => Some(blob),
+            None if bare => Some("HEAD:.mailmap"),
+            None => None,
+        };
+        if let Some(blob) = blob
+            && let Err(e) = mailmap.read_blob(repo, blob)
+        {
+            eprintln!("error: {e:#}");
+        }
+        if let Some(path) = config.get_path("mailmap.file") {
+            mailmap.read_file(&path, true);
+        }
+        mailmap
+    }
+
+    fn read_blob(&mut self, repo: &Repository, blob: &str) -> Result<()> {
+        let Some(hash) = resolve_revision(repo, blob).ok().flatten() else {
+            return Ok(());
+        };
+        let mut object = Object::read_git_object(repo, &hash)?;
+        if object.kind != ObjectKind::Blob {
+            bail!("mailmap is not a blob: {blob}");
+        }
+        self.read(&String::from_utf8_lossy(&object.read_all()?));
+        Ok(())
+    }
+
+    fn read_file(&mut self, path: &Path, follow_symlinks: bool) {
+        let text = match symlink_metadata(path) {
+            Err(_) => return,
+            Ok(metadata) if metadata.is_symlink() && !follow_symlinks => {
+                Err("Too many levels of symbolic links".to_string())
+            }
+            Ok(_) => read_to_string(path).map_err(|e| e.to_string()),
+        };
+        match text {
+            Ok(text) => self.read(&text),
+            Err(e) => eprintln!("error: unable to open mailmap at {}: {e}", path.display()),
+        }
+    }
+
+    /// Add the entries of the mailmap `text`, one per line, with `#` starting a
+    /// comment line:
+    ///
+    /// ```text
+    /// Proper Name <commit@email>
+    /// <proper@email> <commit@email>
+    /// Proper Name <proper@email> <commit@email>
+    /// Proper Name <proper@email> Commit Name <commit@email>
+    /// ```
+    ///
+    /// A line that names the identity it replaces by email alone only replaces what
+    /// it gives of the replacement for that email; one that names it by name too adds
+    /// a replacement for that identity alone.
+    pub fn read(&mut self, text: &str) {
+        for line in text.lines() {
+            if line.starts_with('#') {
+                continue;
+            }
+            let Some((new_name, new_email, rest)) = parse_name_and_email(line, false) else {
+                continue;
+            };
+            let (old_name, old_email, new_email) =
+                match rest.and_then(|rest| parse_name_and_email(rest, true)) {
+                    Some((old_name, old_email, _)) => (old_name, old_email, Some(new_email)),
+                    None => (None, new_email, None),
+                };
+            let entry = self
+                .entries
+                .entry(old_email.to_ascii_lowercase())
+                .or_default();
+            match old_name {
+                None => {
+                    if let Some(name) = new_name {
+                        entry.any_name.name = Some(name.to_string());
+                    }
+                    if let Some(email) = new_email {
+                        entry.any_name.email = Some(email.to_string());
+                    }
+                }
+                Some(old_name) => {
+                    let replacement = Replacement {
+                        name: new_name.map(str::to_string),
+                        email: new_email.map(str::to_string),
+                    };
+                    entry
+                        .by_name
+                        .insert(old_name.to_ascii_lowercase(), replacement);
+                }
+            }
+        }
+    }
+
+    /// Whether the mailmap has no entries, so that mapping through it changes
+    /// nothing.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The canonical name and email of the identity `name <email>`: the replacement
+    /// given for its name and email if there is one, else that for its email.
+    pub fn map<'a>(&'a self, name: &'a str, email: &'a str) -> (&'a str, &'a str) {
+        let Some(entry) = self.entries.get(&email.to_ascii_lowercase()) else {
+            return (name, email);
+        };
+        let replacement = entry
+            .by_name
+            .get(&name.to_ascii_lowercase())
+            .unwrap_or(&entry.any_name);
+        (
+            replacement.name.as_deref().unwrap_or(name),
+            replacement.email.as_deref().unwrap_or(email),
+        )
+    }
+}
+
+/// The name (if any) and email of the `Name <email>` at the start of `text`, with the
+/// rest of it after the `>` if anything follows. An empty email is only allowed with
+/// `allow_empty_email`.
+fn parse_name_and_email(
+    text: &str,
+    allow_empty_email: bool,
+) -> Option<(Option<&str>, &str, Option<&str>)> {
+    let (name, rest) = text.split_once('<')?;
+    let (email, rest) = rest.split_once('>')?;
+    if email.is_empty() && !allow_empty_email {
+        return None;
+    }
+    let name = name.trim();
+    let name = (!name.is_empty()).then_some(name);
+    Some((name, email, (!rest.is_empty()).then_some(rest)))
+}
+
+/// `git check-mailmap [--stdin] <contact>...`: print each contact, `Name <email>` or
+/// `<email>`, as the repository's mailmap canonicalizes it, then, with `stdin`, each
+/// line read from stdin, as soon as it is read.
+pub fn git_check_mailmap(
+    repo: &Repository,
+    config: &Config,
+    contacts: &[String],
+    stdin_contacts: bool,
+) -> Result<()> {
+    let mailmap = Mailmap::load(repo, config);
+    for contact in contacts {
+        println!("{}", check_contact(&mailmap, contact)?);
+    }
+    if stdin_contacts {
+        for line in stdin().lock().lines() {
+            println!("{}", check_contact(&mailmap, &line?)?);
+        }
+    }
+    Ok(())
+}
+
+fn check_contact(mailmap: &Mailmap, contact: &str) -> Result<String> {
+    let parsed = contact
+        .split_once('<')
+        .and_then(|(name, rest)| Some((name.trim_end(), rest.split_once('>')?.0)));
+    let Some((name, email)) = parsed else {
+        bail!("unable to parse contact: {contact}");
+    };
+    let (name, email) = mailmap.map(name, email);
+    Ok(match name.is_empty() {
+        true => format!("<{email}>"),
+        false => format!("{name} <{email}>"),
+    })
+}
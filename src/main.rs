@@ -1,23 +1,73 @@
-use anyhow::{Context, Result, bail, ensure};
+use anyhow::{Context, Result, anyhow, bail};
 use clap::{Parser, Subcommand};
-use std::fs::{read_to_string, write};
+use std::env;
+use std::io::Write;
 use std::path::PathBuf;
 
-use crate::cat_file::git_cat_file;
-use crate::commit::git_write_commit;
-use crate::hash_object::git_hash_object;
-use crate::init::git_init;
-use crate::ls_tree::git_ls_tree;
-use crate::write_tree::git_write_tree;
-
-mod cat_file;
-mod commit;
-mod hash_object;
-mod init;
-mod ls_tree;
-mod object_read;
-mod object_write;
-mod write_tree;
+use git_rs::add::git_add;
+use git_rs::am::{
+    AmOptions, git_am, git_am_abort, git_am_continue, git_am_show_current_patch, git_am_skip,
+};
+use git_rs::apply::{ApplyOptions, WhitespaceAction, git_apply};
+use git_rs::branch::{
+    Deletion, format_branch, git_branch_create, git_branch_delete, git_branch_list,
+};
+use git_rs::cat_file::{
+    CatFileBatchOptions, CatFileMode, DEFAULT_BATCH_FORMAT, git_cat_file, git_cat_file_batch,
+};
+use git_rs::checkout::{CheckoutOptions, git_checkout};
+use git_rs::cherry::git_cherry;
+use git_rs::clone::{CloneOptions, git_clone};
+use git_rs::commit::{
+    CommitBuilder, CommitOptions, git_commit, message_from_paragraphs, message_from_stdin,
+};
+use git_rs::config::{Config, ConfigAction, ConfigScope, git_config};
+use git_rs::describe::{DescribeOptions, git_describe};
+use git_rs::diff::{DiffOptions, IgnoreWhitespace, git_diff};
+use git_rs::fetch::{FetchOptions, git_fetch};
+use git_rs::fmt_merge_msg::{FmtMergeMsgOptions, git_fmt_merge_msg};
+use git_rs::gc::{GcOptions, git_gc};
+use git_rs::hash_object::{git_hash_object, git_hash_object_stdin};
+use git_rs::history::Simplification;
+use git_rs::index_pack::git_index_pack;
+use git_rs::init::git_init;
+use git_rs::log::{LogOptions, format_commit, git_log};
+use git_rs::ls_files::{LsFilesOptions, git_ls_files};
+use git_rs::ls_tree::{LsTreeOptions, format_tree_entry, git_ls_tree};
+use git_rs::mailinfo::{git_mailinfo, git_mailsplit};
+use git_rs::mailmap::{Mailmap, git_check_mailmap};
+use git_rs::mktag::git_mktag;
+use git_rs::mktree::git_mktree;
+use git_rs::object_id::HashAlgorithm;
+use git_rs::object_read::{ObjectKind, default_abbrev};
+use git_rs::pack_objects::{PackObjectsOptions, git_pack_objects};
+use git_rs::pack_refs::git_pack_refs;
+use git_rs::patch_id::patch_ids_from_diff;
+use git_rs::push::{PushOptions, git_push};
+use git_rs::remote::{
+    format_remote_show, format_remote_verbose, get_remote, git_remote_list, git_remote_prune,
+    git_remote_show,
+};
+use git_rs::repo_stats::{RepoStatsOptions, format_repo_stats, git_repo_stats};
+use git_rs::repository::Repository;
+use git_rs::restore::{git_checkout_paths, git_restore};
+use git_rs::rev_list::{DiskUsage, RevListOptions, git_rev_list};
+use git_rs::rev_parse::{git_rev_parse, rev_parse};
+use git_rs::sequencer::{
+    SequencerOptions, git_cherry_pick, git_revert, git_sequencer_abort, git_sequencer_continue,
+    git_sequencer_skip,
+};
+use git_rs::show_index::git_show_index;
+use git_rs::stash::git_stash_show;
+use git_rs::status::{
+    StatusOptions, UntrackedFiles, format_branch_header, format_long, format_short, git_status,
+};
+use git_rs::subtree::{git_subtree_add, git_subtree_merge, git_subtree_split};
+use git_rs::tag::{git_tag_create, git_tag_delete, git_tag_list};
+use git_rs::update_ref::{git_update_ref, git_update_ref_stdin};
+use git_rs::var::{git_var, git_var_list};
+use git_rs::whitespace::WhitespaceRule;
+use git_rs::write_tree::{git_write_tree, git_write_tree_prefix};
 
 #[derive(Parser, Debug)]
 pub struct Args {
@@ -27,88 +77,1845 @@ pub struct Args {
 
 #[derive(Debug, Subcommand)]
 enum Command {
-    Init,
+    Init {
+        /// Name objects with this hash algorithm (sha1 or sha256)
+        #[clap(long, value_name = "format")]
+        object_format: Option<HashAlgorithm>,
+    },
+    /// Copy a local repository into a new directory and check out its HEAD branch
+    Clone {
+        /// Check out this branch instead of the one the repository's HEAD points at
+        #[clap(short = 'b', long, value_name = "name")]
+        branch: Option<String>,
+        /// Copy object files instead of hard-linking them
+        #[clap(long)]
+        no_hardlinks: bool,
+        repository: String,
+        /// Defaults to the repository's name, without `.git`
+        directory: Option<String>,
+    },
     CatFile {
-        #[clap(short = 'p')]
+        /// Print the object's content, listing trees
+        #[clap(short = 'p', group = "mode")]
         pretty_print: bool,
-        hash_object: String,
+        /// Print the object's type
+        #[clap(short = 't', group = "mode")]
+        show_type: bool,
+        /// Print the object's size
+        #[clap(short = 's', group = "mode")]
+        size: bool,
+        /// Exit with status 1 if the object doesn't exist, printing nothing
+        #[clap(short = 'e', group = "mode")]
+        exists: bool,
+        /// Recompute the object's hash while reading and fail on mismatch
+        #[clap(long)]
+        verify: bool,
+        /// Print the objects named on stdin with their content, each after a line
+        /// in the given format
+        #[clap(long, value_name = "format", num_args = 0..=1, require_equals = true,
+               default_missing_value = DEFAULT_BATCH_FORMAT, conflicts_with = "batch_check")]
+        batch: Option<String>,
+        /// Print a line in the given format for each object named on stdin
+        #[clap(long, value_name = "format", num_args = 0..=1, require_equals = true,
+               default_missing_value = DEFAULT_BATCH_FORMAT)]
+        batch_check: Option<String>,
+        /// With --batch or --batch-check, go over every object in the store
+        /// instead of reading names from stdin
+        #[clap(long)]
+        batch_all_objects: bool,
+        /// With --batch-all-objects, list objects in the order they are stored in
+        /// (loose ones, then each pack's) rather than by id
+        #[clap(long, requires = "batch_all_objects")]
+        unordered: bool,
+        #[clap(required_unless_present_any = ["batch", "batch_check"],
+               conflicts_with_all = ["batch", "batch_check"], requires = "mode")]
+        hash_object: Option<String>,
     },
     HashObject {
         #[clap(short = 'w')]
         write: bool,
-        file: PathBuf,
+        /// Hash the data as an object of this type (blob, tree, commit or tag)
+        #[clap(short = 't', value_name = "type", default_value = "blob")]
+        kind: String,
+        /// Hash what standard input holds, before any files
+        #[clap(long)]
+        stdin: bool,
+        #[clap(required_unless_present = "stdin")]
+        files: Vec<PathBuf>,
+    },
+    /// Stage files' current contents for the next commit
+    Add {
+        /// Files or directories to stage (`.` for everything)
+        #[clap(required = true)]
+        pathspec: Vec<String>,
+    },
+    /// Show staged, unstaged and untracked changes
+    Status {
+        /// Give the output in the short format
+        #[clap(short, long)]
+        short: bool,
+        /// Start the short format with the branch and how it compares to its upstream
+        #[clap(short, long)]
+        branch: bool,
+        /// Show untracked files: no, normal or all
+        #[clap(short = 'u', long, value_name = "mode", num_args = 0..=1,
+               default_missing_value = "all")]
+        untracked_files: Option<String>,
+    },
+    /// List, create or delete branches
+    Branch {
+        /// Delete the branches, which have to be merged
+        #[clap(short = 'd', long)]
+        delete: bool,
+        /// Delete the branches even if they aren't merged
+        #[clap(short = 'D')]
+        force_delete: bool,
+        /// Show each branch's commit and how it compares to its upstream; twice, name
+        /// the upstream too
+        #[clap(short, long, action = clap::ArgAction::Count)]
+        verbose: u8,
+        /// The branch to create and its start point (default HEAD), or the branches
+        /// to delete
+        args: Vec<String>,
+    },
+    /// List, create or delete tags
+    Tag {
+        /// Make an annotated tag object; needs a message
+        #[clap(short = 'a')]
+        annotate: bool,
+        /// Message paragraph of an annotated tag; may be repeated, and implies -a
+        #[clap(short = 'm')]
+        message: Vec<String>,
+        /// Replace an existing tag
+        #[clap(short = 'f', long)]
+        force: bool,
+        /// Delete the tags
+        #[clap(short = 'd', long)]
+        delete: bool,
+        /// List the tags, matching the patterns if any are given
+        #[clap(short = 'l', long)]
+        list: bool,
+        /// The tag to create and the object it tags (default HEAD), the tags to
+        /// delete, or patterns to list
+        args: Vec<String>,
+    },
+    /// Show changes between the working directory, the index and commits
+    Diff {
+        /// Compare the index with HEAD or the given commit
+        #[clap(long, visible_alias = "staged")]
+        cached: bool,
+        /// Show changes to binary files as patches that can be applied
+        #[clap(long)]
+        binary: bool,
+        /// Report whitespace errors in added lines instead of the diff
+        #[clap(long)]
+        check: bool,
+        /// Don't run external diff programs, from GIT_EXTERNAL_DIFF, diff.external
+        /// or diff drivers
+        #[clap(long)]
+        no_ext_diff: bool,
+        /// Ignore whitespace when comparing lines
+        #[clap(short = 'w', long)]
+        ignore_all_space: bool,
+        /// Ignore changes in the amount of whitespace
+        #[clap(short = 'b', long)]
+        ignore_space_change: bool,
+        /// Ignore changes in whitespace at the end of lines
+        #[clap(long)]
+        ignore_space_at_eol: bool,
+        /// Ignore changes whose lines are all blank
+        #[clap(long)]
+        ignore_blank_lines: bool,
+        /// Show <n> lines of context around changes (default diff.context, or 3)
+        #[clap(short = 'U', long = "unified", value_name = "n")]
+        context: Option<usize>,
+        /// Join hunks separated by up to <n> more unchanged lines
+        #[clap(long, value_name = "n")]
+        inter_hunk_context: Option<usize>,
+        /// Show the whole function around each change as context
+        #[clap(short = 'W', long)]
+        function_context: bool,
+        /// List the changed files with the number of lines changed, instead of patches
+        #[clap(long)]
+        stat: bool,
+        /// Show only the totals line of --stat
+        #[clap(long)]
+        shortstat: bool,
+        /// Show patches, after the list with --stat
+        #[clap(short = 'p', long)]
+        patch: bool,
+        /// With --stat, list the files created or deleted and modes changed
+        #[clap(long)]
+        summary: bool,
+        /// Up to two commits to compare (or <commit>..<commit>), then paths
+        args: Vec<String>,
+        /// Limit the diff to these paths
+        #[clap(last = true)]
+        paths: Vec<String>,
     },
     LsTree {
         #[clap(long)]
         name_only: bool,
-        tree_hash: String,
+        /// Show object size of blob entries
+        #[clap(short = 'l', long)]
+        long: bool,
+        /// Abbreviate object ids to <n> hex digits (default 7)
+        #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "7")]
+        abbrev: Option<usize>,
+        /// Terminate entries with NUL instead of newline
+        #[clap(short = 'z')]
+        null_terminated: bool,
+        /// A tree, or a commit, tag or ref that is peeled to its tree
+        tree_ish: String,
+        /// Only show entries matching these paths
+        paths: Vec<String>,
+    },
+    /// List the files in the index
+    LsFiles {
+        /// List the files in the index (the default without --others)
+        #[clap(short, long)]
+        cached: bool,
+        /// Show each index entry's mode, object id and stage
+        #[clap(short, long)]
+        stage: bool,
+        /// List untracked files
+        #[clap(short, long)]
+        others: bool,
+        /// Leave out untracked files that .gitignore and the other exclude files ignore
+        #[clap(long)]
+        exclude_standard: bool,
+        /// Show the line endings of the index and working tree files and the
+        /// attributes that convert them
+        #[clap(long)]
+        eol: bool,
+        /// Terminate entries with NUL instead of newline
+        #[clap(short = 'z')]
+        null_terminated: bool,
+        /// Only list files under these paths
+        paths: Vec<String>,
+    },
+    WriteTree {
+        /// Write the tree of this subdirectory instead of the whole working directory
+        #[clap(long)]
+        prefix: Option<String>,
+    },
+    /// Build a tree object from ls-tree formatted text on stdin
+    Mktree {
+        /// Read NUL-terminated entries
+        #[clap(short = 'z')]
+        null_terminated: bool,
+        /// Allow entries referencing objects that don't exist
+        #[clap(long)]
+        missing: bool,
+    },
+    /// Validate an annotated tag on stdin and write it as a tag object
+    Mktag {
+        /// Require a well-formed tagger, ref-safe tag name and header terminator
+        #[clap(long)]
+        strict: bool,
     },
-    WriteTree,
     CommitTree {
+        /// Message paragraph; may be repeated. Read from stdin when absent
         #[clap(short = 'm')]
-        message: String,
+        message: Vec<String>,
+        /// Parent commit; may be repeated for a merge
         #[clap(short = 'p')]
-        parent_hash: Option<String>,
+        parent_hashes: Vec<String>,
+        /// Sign the commit with GPG (user.signingKey, or the committer identity)
+        #[clap(short = 'S', long = "gpg-sign")]
+        sign: bool,
         tree_hash: String,
     },
+    /// Move loose refs into the packed-refs file
+    PackRefs {
+        /// Pack every ref, not only tags
+        #[clap(long)]
+        all: bool,
+        /// Keep the loose ref files that were packed
+        #[clap(long)]
+        no_prune: bool,
+    },
+    /// Set, delete or check refs, one at a time or in transactions read from stdin
+    UpdateRef {
+        /// Reason for the update, recorded in the reflog
+        #[clap(short = 'm')]
+        message: Option<String>,
+        /// Delete the ref instead of setting it
+        #[clap(short = 'd')]
+        delete: bool,
+        /// Read update commands from stdin and apply them atomically
+        #[clap(long, conflicts_with = "delete")]
+        stdin: bool,
+        /// <ref> <new-value> [<old-value>], or <ref> [<old-value>] with -d
+        #[clap(required_unless_present = "stdin", conflicts_with = "stdin")]
+        args: Vec<String>,
+    },
+    /// Find commits not yet applied upstream
+    Cherry {
+        /// Show commit subjects
+        #[clap(short = 'v')]
+        verbose: bool,
+        /// Defaults to the current branch's upstream
+        upstream: Option<String>,
+        #[clap(default_value = "HEAD")]
+        head: String,
+        /// Don't report commits up to (and including) this one
+        limit: Option<String>,
+    },
+    /// Switch HEAD to a branch, or detach it at a commit, updating the working directory
+    Checkout {
+        /// Detach HEAD at the commit even if a branch is given
+        #[clap(long)]
+        detach: bool,
+        /// Create a branch at the target (default HEAD) and switch to it
+        #[clap(short = 'b', conflicts_with = "detach")]
+        new_branch: Option<String>,
+        /// Branch to switch to, or commit to detach at (defaults to HEAD with --detach);
+        /// with paths, the tree-ish to check them out from
+        #[clap(required_unless_present_any = ["detach", "new_branch", "paths"])]
+        target: Option<String>,
+        /// Check out these files from the target (default HEAD) without moving HEAD
+        #[clap(last = true, conflicts_with_all = ["detach", "new_branch"])]
+        paths: Vec<String>,
+    },
+    /// Switch to a branch, optionally creating it
+    Switch {
+        /// Create a branch at the start point (default HEAD) and switch to it
+        #[clap(short = 'c', long = "create")]
+        create: Option<String>,
+        /// Detach HEAD at a commit instead of switching to a branch
+        #[clap(short = 'd', long, conflicts_with = "create")]
+        detach: bool,
+        /// Branch to switch to, or start point for -c and --detach
+        #[clap(required_unless_present_any = ["detach", "create"])]
+        target: Option<String>,
+    },
+    /// Restore files in the working directory from a commit
+    Restore {
+        /// Restore the index instead of the working directory
+        #[clap(short = 'S', long)]
+        staged: bool,
+        /// Restore the working directory too (the default without --staged)
+        #[clap(short = 'W', long)]
+        worktree: bool,
+        /// Tree-ish to restore from (defaults to HEAD)
+        #[clap(short, long)]
+        source: Option<String>,
+        #[clap(required = true)]
+        paths: Vec<String>,
+    },
+    /// Show the commits reachable from HEAD or the given revisions, newest first
+    Log {
+        /// Show at most this many commits
+        #[clap(short = 'n', long)]
+        max_count: Option<usize>,
+        /// Show each commit as its abbreviated id and subject
+        #[clap(long)]
+        oneline: bool,
+        /// Show the history of a single file, following it across renames
+        #[clap(long)]
+        follow: bool,
+        /// Follow every parent of merges when limiting history to paths
+        #[clap(long)]
+        full_history: bool,
+        /// Like --full-history, but drop merges that don't join two shown histories;
+        /// shows commits in topological order
+        #[clap(long)]
+        simplify_merges: bool,
+        /// Follow only the first parent of merges
+        #[clap(long)]
+        first_parent: bool,
+        /// Show authors as the mailmap canonicalizes them (the default, unless
+        /// log.mailmap is false)
+        #[clap(long, alias = "mailmap", overrides_with = "no_use_mailmap")]
+        use_mailmap: bool,
+        /// Show authors as they are recorded
+        #[clap(long, alias = "no-mailmap", overrides_with = "use_mailmap")]
+        no_use_mailmap: bool,
+        /// Commits to start from; ^<rev> and <rev>..<rev> exclude history
+        revisions: Vec<String>,
+        /// Show only commits that change these paths (one file, with --follow)
+        #[clap(last = true)]
+        paths: Vec<String>,
+    },
+    /// List the ids of the commits reachable from the given revisions, newest first
+    RevList {
+        /// List at most this many commits
+        #[clap(short = 'n', long)]
+        max_count: Option<usize>,
+        /// Print the number of commits (or objects) instead
+        #[clap(long)]
+        count: bool,
+        /// Also list the tags, trees and blobs the commits need, with their paths
+        #[clap(long)]
+        objects: bool,
+        /// List objects without their paths
+        #[clap(long, requires = "objects")]
+        no_object_names: bool,
+        /// Print how many bytes the listed commits (or objects) take up instead;
+        /// =human for KiB, MiB or GiB
+        #[clap(long, num_args = 0..=1, require_equals = true, value_name = "format")]
+        disk_usage: Option<Option<String>>,
+        /// Follow every parent of merges when limiting history to paths
+        #[clap(long)]
+        full_history: bool,
+        /// Like --full-history, but drop merges that don't join two listed histories;
+        /// lists commits in topological order
+        #[clap(long)]
+        simplify_merges: bool,
+        /// Follow only the first parent of merges
+        #[clap(long)]
+        first_parent: bool,
+        /// Start from every ref and HEAD too
+        #[clap(long)]
+        all: bool,
+        /// Start from every object the reflogs record too
+        #[clap(long)]
+        reflog: bool,
+        /// Count or list what is reachable from a pack's reachability bitmaps where
+        /// they cover it, in pack order and without paths
+        #[clap(long)]
+        use_bitmap_index: bool,
+        /// Commits to start from; ^<rev> and <rev>..<rev> exclude history
+        revisions: Vec<String>,
+        /// List only commits that change these paths
+        #[clap(last = true)]
+        paths: Vec<String>,
+    },
+    /// Print the object ids revisions name
+    RevParse {
+        /// Require exactly one revision that names an object
+        #[clap(long)]
+        verify: bool,
+        /// Abbreviate the id to <n> hex digits (core.abbrev by default); implies --verify
+        #[clap(long, num_args = 0..=1, require_equals = true, value_name = "n")]
+        short: Option<Option<usize>>,
+        /// Revisions; ^<rev> and <rev>..<rev> print the ids to exclude as ^<id>
+        revisions: Vec<String>,
+    },
+    /// Name a commit after the closest tag reachable from it
+    Describe {
+        /// Use lightweight tags too, not just annotated ones
+        #[clap(long)]
+        tags: bool,
+        /// Show the abbreviated commit id when no tag is found
+        #[clap(long)]
+        always: bool,
+        /// Always use the long format, even for a tagged commit
+        #[clap(long)]
+        long: bool,
+        /// Number of hex digits of the commit id to show; 0 shows only the tag
+        #[clap(long, default_value_t = 7)]
+        abbrev: usize,
+        /// Append a mark (default "-dirty") if the working directory has changes
+        #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "-dirty")]
+        dirty: Option<String>,
+        #[clap(conflicts_with = "dirty")]
+        commit_ish: Option<String>,
+    },
+    /// Write the message of a commit merging the refs listed as in FETCH_HEAD
+    FmtMergeMsg {
+        /// Use this message instead of the generated title
+        #[clap(short = 'm', long)]
+        message: Option<String>,
+        /// List up to this many (default 20) of the commits each ref brings in
+        #[clap(long, value_name = "n", num_args = 0..=1, require_equals = true,
+               default_missing_value = "20", overrides_with = "no_log")]
+        log: Option<usize>,
+        /// Don't list the merged commits, whatever merge.log says
+        #[clap(long, overrides_with = "log")]
+        no_log: bool,
+        /// Name this branch as merged into instead of the current one
+        #[clap(long, value_name = "branch")]
+        into_name: Option<String>,
+        /// Read the list from this file instead of stdin
+        #[clap(short = 'F', long = "file")]
+        file: Option<PathBuf>,
+    },
+    /// Compute patch IDs of diffs read from stdin
+    PatchId {
+        /// Hash files independently so their order doesn't matter (used by cherry)
+        #[clap(long, conflicts_with = "unstable")]
+        stable: bool,
+        /// Hash the whole patch as one stream (the default)
+        #[clap(long)]
+        unstable: bool,
+        /// Don't strip whitespace before hashing; implies --stable
+        #[clap(long)]
+        verbatim: bool,
+    },
+    /// Write the index of a pack, or store a pack read from stdin with its index
+    IndexPack {
+        /// Read the pack from stdin and store it in the repository
+        #[clap(long)]
+        stdin: bool,
+        /// Complete a thin pack with the delta bases the repository has
+        #[clap(long, requires = "stdin")]
+        fix_thin: bool,
+        #[clap(required_unless_present = "stdin")]
+        pack_file: Option<PathBuf>,
+    },
+    /// Show the offset, id and CRC32 of each object in a pack index read from stdin
+    ShowIndex {
+        /// The hash algorithm of the index's ids (sha1 or sha256)
+        #[clap(long, value_name = "format")]
+        object_format: Option<HashAlgorithm>,
+    },
+    /// Write a pack (and its index) of the objects, or revisions, listed on stdin
+    PackObjects {
+        /// Read revisions from stdin and pack the objects they need
+        #[clap(long)]
+        revs: bool,
+        /// Write the pack to stdout instead of <base-name>-<checksum>.pack
+        #[clap(long, conflicts_with = "base_name")]
+        stdout: bool,
+        /// How many objects each is tried as a delta against
+        #[clap(long, value_name = "n")]
+        window: Option<usize>,
+        /// How long chains of deltas may get
+        #[clap(long, value_name = "n")]
+        depth: Option<usize>,
+        #[clap(required_unless_present = "stdout")]
+        base_name: Option<String>,
+    },
+    /// Report object counts and sizes by kind, loose against packed storage, and the
+    /// largest blobs, trees and paths over history
+    RepoStats {
+        /// How many of the largest blobs, trees and paths to list
+        #[clap(short = 'n', long, value_name = "n", default_value_t = 10)]
+        top: usize,
+    },
+    /// Pack everything reachable into one pack and prune old unreachable objects
+    Gc {
+        /// Look much harder for deltas, within a larger window and depth
+        #[clap(long)]
+        aggressive: bool,
+        /// Prune unreachable objects older than this date (by default gc.pruneExpire,
+        /// two weeks ago)
+        #[clap(
+            long,
+            num_args = 0..=1,
+            require_equals = true,
+            value_name = "date",
+            overrides_with = "no_prune"
+        )]
+        prune: Option<Option<String>>,
+        /// Don't prune any unreachable objects
+        #[clap(long, overrides_with = "prune")]
+        no_prune: bool,
+        /// Keep the unreachable objects not pruned in a cruft pack instead of loose
+        /// (the default if gc.cruftPacks is true)
+        #[clap(long, overrides_with = "no_cruft")]
+        cruft: bool,
+        /// Keep the unreachable objects not pruned loose
+        #[clap(long, overrides_with = "cruft")]
+        no_cruft: bool,
+    },
+    /// Show the canonical names and emails the mailmap gives contacts
+    CheckMailmap {
+        /// Also read contacts from stdin, one per line
+        #[clap(long)]
+        stdin: bool,
+        /// Contacts, as `Name <email>` or `<email>`
+        #[clap(required_unless_present = "stdin")]
+        contacts: Vec<String>,
+    },
+    /// Show a logical variable such as GIT_AUTHOR_IDENT or GIT_EDITOR
+    Var {
+        /// List all config entries and logical variables
+        #[clap(short = 'l', conflicts_with = "variable")]
+        list: bool,
+        #[clap(required_unless_present = "list")]
+        variable: Option<String>,
+    },
+    /// Get and set options in .git/config, including multi-valued ones
+    Config {
+        /// Print the last value of a key (the default with one argument)
+        #[clap(long, group = "action")]
+        get: bool,
+        /// Print every value of a key
+        #[clap(long, group = "action")]
+        get_all: bool,
+        /// Print the keys matching a regular expression and their values
+        #[clap(long, group = "action")]
+        get_regexp: bool,
+        /// Add a value without touching the existing ones
+        #[clap(long, group = "action")]
+        add: bool,
+        /// Replace every value, or every one matching the value pattern
+        #[clap(long, group = "action")]
+        replace_all: bool,
+        /// Remove the value, or the one matching the value pattern
+        #[clap(long, group = "action")]
+        unset: bool,
+        /// Remove every value, or every one matching the value pattern
+        #[clap(long, group = "action")]
+        unset_all: bool,
+        /// List every variable
+        #[clap(short, long, group = "action")]
+        list: bool,
+        /// Compare values with the value pattern as a string, not a regular expression
+        #[clap(long)]
+        fixed_value: bool,
+        /// Read and write only the system config file
+        #[clap(long, group = "scope")]
+        system: bool,
+        /// Read and write only the user's config file
+        #[clap(long, group = "scope")]
+        global: bool,
+        /// Read and write only the repository's config file
+        #[clap(long, group = "scope")]
+        local: bool,
+        /// Read and write only this file
+        #[clap(short, long, group = "scope", value_name = "path")]
+        file: Option<PathBuf>,
+        /// <key> [<value>] [<value-pattern>], depending on the action
+        args: Vec<String>,
+    },
+    /// List configured remotes, or inspect one
+    Remote {
+        /// Show URLs after the names
+        #[clap(short, long)]
+        verbose: bool,
+        #[command(subcommand)]
+        command: Option<RemoteCommand>,
+    },
+    /// Download objects and refs from remotes
+    Fetch {
+        /// Fetch all remotes
+        #[clap(long)]
+        all: bool,
+        /// Allow several remotes or groups to be given
+        #[clap(long)]
+        multiple: bool,
+        /// Number of remotes fetched in parallel (0 for a CPU-based default)
+        #[clap(short, long)]
+        jobs: Option<usize>,
+        /// Remotes or groups of remotes (`remotes.<group>` in config)
+        names: Vec<String>,
+    },
+    /// Update a remote's refs from local refs, sending the objects they need
+    Push {
+        /// Allow updates that aren't fast-forwards
+        #[clap(short, long)]
+        force: bool,
+        /// Update either all refs on the remote or none of them
+        #[clap(long)]
+        atomic: bool,
+        /// A string for the remote's hooks; may be repeated
+        #[clap(short = 'o', long = "push-option")]
+        push_options: Vec<String>,
+        /// Sign the push with GPG: true, false or if-asked (if the remote supports it)
+        #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+        signed: Option<String>,
+        /// Don't sign the push, whatever push.gpgSign says
+        #[clap(long, conflicts_with = "signed")]
+        no_signed: bool,
+        /// Don't run the pre-push hook
+        #[clap(long)]
+        no_verify: bool,
+        remote: Option<String>,
+        refspecs: Vec<String>,
+    },
     Commit {
+        /// Message paragraph; may be repeated. Needed unless amending or concluding a
+        /// merge, whose MERGE_MSG is used
         #[clap(short = 'm')]
-        message: String,
+        message: Vec<String>,
+        /// Sign the commit with GPG (user.signingKey, or the committer identity)
+        #[clap(short = 'S', long = "gpg-sign")]
+        sign: bool,
+        /// Replace the tip of the current branch, keeping its parents, author and
+        /// (without -m) message
+        #[clap(long)]
+        amend: bool,
+        /// Record the commit even if it changes nothing from HEAD
+        #[clap(long)]
+        allow_empty: bool,
+    },
+    /// Split mboxes into one file per message, named 0001, 0002, ...
+    Mailsplit {
+        /// Directory to write the messages to
+        #[clap(short = 'o')]
+        dir: PathBuf,
+        /// Mbox files; stdin if none
+        mboxes: Vec<String>,
+    },
+    /// Extract author, subject, message and patch from the email on stdin
+    Mailinfo {
+        /// File to write the commit message body to
+        msg: PathBuf,
+        /// File to write the patch to
+        patch: PathBuf,
+    },
+    /// Apply patches to the working directory
+    Apply {
+        /// Only check whether the patches apply
+        #[clap(long)]
+        check: bool,
+        /// Merge files whose hunks don't apply, starting from the blobs the patch names
+        #[clap(short = '3', long = "3way")]
+        three_way: bool,
+        /// What to do about added lines with whitespace errors: nowarn, warn, fix,
+        /// error or error-all (default apply.whitespace, or warn)
+        #[clap(long, require_equals = true)]
+        whitespace: Option<String>,
+        /// Patch files; stdin if none
+        patches: Vec<String>,
+    },
+    /// Commit patches from mailboxes, as sent by format-patch
+    Am {
+        /// Fall back to a 3-way merge when a patch doesn't apply
+        #[clap(short = '3', long = "3way")]
+        three_way: bool,
+        /// Commit the current patch once its problems are fixed, and go on
+        #[clap(long = "continue", visible_alias = "resolved", short = 'r')]
+        continue_: bool,
+        /// Skip the current patch
+        #[clap(long, conflicts_with = "continue_")]
+        skip: bool,
+        /// Restore the branch as it was before am started
+        #[clap(long, conflicts_with_all = ["continue_", "skip"])]
+        abort: bool,
+        /// Show the mail (raw) or patch (diff) am stopped at
+        #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "raw")]
+        show_current_patch: Option<String>,
+        /// Mailboxes; stdin if none
+        mboxes: Vec<String>,
+    },
+    /// Commit the changes other commits made on top of HEAD
+    CherryPick {
+        /// Commit a commit that made no changes itself
+        #[clap(long)]
+        allow_empty: bool,
+        /// Commit a commit whose changes HEAD already has, as an empty commit
+        #[clap(long)]
+        keep_redundant_commits: bool,
+        /// For a merge, the parent (from 1) to take its changes against
+        #[clap(short, long, value_name = "parent-number")]
+        mainline: Option<usize>,
+        /// Commit the current commit once its conflicts are resolved, and go on
+        #[clap(long = "continue")]
+        continue_: bool,
+        /// Skip the current commit
+        #[clap(long, conflicts_with = "continue_")]
+        skip: bool,
+        /// Restore the branch as it was before the cherry-pick started
+        #[clap(long, conflicts_with_all = ["continue_", "skip"])]
+        abort: bool,
+        /// Commits, or ranges A..B
+        #[clap(required_unless_present_any = ["continue_", "skip", "abort"])]
+        commits: Vec<String>,
+    },
+    /// Commit the reverse of the changes other commits made on top of HEAD
+    Revert {
+        /// For a merge, the parent (from 1) to go back to
+        #[clap(short, long, value_name = "parent-number")]
+        mainline: Option<usize>,
+        /// Commit the current revert once its conflicts are resolved, and go on
+        #[clap(long = "continue")]
+        continue_: bool,
+        /// Skip the current commit
+        #[clap(long, conflicts_with = "continue_")]
+        skip: bool,
+        /// Restore the branch as it was before the revert started
+        #[clap(long, conflicts_with_all = ["continue_", "skip"])]
+        abort: bool,
+        /// Commits, or ranges A..B
+        #[clap(required_unless_present_any = ["continue_", "skip", "abort"])]
+        commits: Vec<String>,
+    },
+    /// Keep another project's history in a subdirectory, and split it back out
+    Subtree {
+        #[command(subcommand)]
+        command: SubtreeCommand,
+    },
+    /// Inspect stashed changes
+    Stash {
+        #[command(subcommand)]
+        command: StashCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum StashCommand {
+    /// Show the changes a stash entry records, from the commit it was made on
+    Show {
+        /// Show the patch (only the patch, without --stat)
+        #[clap(short = 'p', long)]
+        patch: bool,
+        /// List the changed files with the number of lines changed
+        #[clap(long)]
+        stat: bool,
+        /// The entry: stash@{<n>}, or just <n> (default stash@{0})
+        stash: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum SubtreeCommand {
+    /// Add a commit's tree as a new subdirectory, joining its history to HEAD's
+    Add {
+        /// The subdirectory to add
+        #[clap(short = 'P', long, required = true)]
+        prefix: String,
+        /// Message for the merge commit
+        #[clap(short, long)]
+        message: Option<String>,
+        commit: String,
+    },
+    /// Merge a later commit of a subdirectory's history into it
+    Merge {
+        /// The subdirectory to merge into
+        #[clap(short = 'P', long, required = true)]
+        prefix: String,
+        /// Message for the merge commit
+        #[clap(short, long)]
+        message: Option<String>,
+        commit: String,
+    },
+    /// Extract a subdirectory's history into commits that have it at their root
+    Split {
+        /// The subdirectory to split out
+        #[clap(short = 'P', long, required = true)]
+        prefix: String,
+        /// Create or fast-forward this branch to the split history
+        #[clap(short, long)]
+        branch: Option<String>,
+        #[clap(default_value = "HEAD")]
+        commit: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum RemoteCommand {
+    /// Show a remote's URLs, branches and how local branches track them
+    Show {
+        /// Don't query the remote; show only what is known locally
+        #[clap(short = 'n')]
+        no_query: bool,
+        #[clap(required = true)]
+        names: Vec<String>,
+    },
+    /// Delete remote-tracking branches whose branch is gone from the remote
+    Prune {
+        /// Only report what would be pruned
+        #[clap(short = 'n', long)]
+        dry_run: bool,
+        #[clap(required = true)]
+        names: Vec<String>,
     },
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
+    // Every command but these works on the repository the current directory is in,
+    // its path arguments taken relative to where it was run.
+    let repo = &match args.command {
+        Command::Init { .. } | Command::Clone { .. } => Repository::at(&env::current_dir()?),
+        _ => Repository::discover()?,
+    };
+    let prefix_pathspecs = |paths: Vec<String>| -> Result<Vec<String>> {
+        paths.iter().map(|p| repo.pathspec(p)).collect()
+    };
     match args.command {
-        Command::Init => {
-            git_init()?;
+        Command::Init { object_format } => {
+            git_init(repo, object_format)?;
+        }
+        Command::Clone {
+            branch,
+            no_hardlinks,
+            repository,
+            directory,
+        } => {
+            let options = CloneOptions {
+                branch,
+                no_hardlinks,
+            };
+            git_clone(
+                &mut std::io::stderr().lock(),
+                &repository,
+                directory.as_deref(),
+                &options,
+            )?;
         }
         Command::CatFile {
-            pretty_print,
+            pretty_print: _,
+            show_type,
+            size,
+            exists,
+            verify,
+            batch,
+            batch_check,
+            batch_all_objects,
+            unordered,
             hash_object,
+        } => match (hash_object, batch, batch_check) {
+            (Some(hash_object), _, _) => {
+                let mode = match (show_type, size, exists) {
+                    (true, _, _) => CatFileMode::Type,
+                    (_, true, _) => CatFileMode::Size,
+                    (_, _, true) => CatFileMode::Exists,
+                    _ => CatFileMode::Pretty,
+                };
+                let status = git_cat_file(
+                    repo,
+                    &mut std::io::stdout().lock(),
+                    mode,
+                    verify,
+                    &hash_object,
+                )?;
+                if status != 0 {
+                    std::process::exit(status);
+                }
+            }
+            (None, batch, batch_check) => {
+                let contents = batch.is_some();
+                let Some(format) = batch.or(batch_check) else {
+                    bail!(
+                        "usage: git-rs cat-file ((-p | -t | -s | -e) <object> | --batch[=<format>] | --batch-check[=<format>])"
+                    );
+                };
+                git_cat_file_batch(
+                    repo,
+                    &CatFileBatchOptions {
+                        format,
+                        contents,
+                        all_objects: batch_all_objects,
+                        unordered,
+                    },
+                )?;
+            }
+        },
+        Command::HashObject {
+            write,
+            kind,
+            stdin,
+            files,
         } => {
-            git_cat_file(pretty_print, hash_object.as_str())?;
+            let kind: ObjectKind = kind
+                .parse()
+                .map_err(|_| anyhow!("invalid object type \"{kind}\""))?;
+            if stdin {
+                println!("{}", git_hash_object_stdin(repo, kind.clone(), write)?);
+            }
+            for file in files {
+                println!("{}", git_hash_object(repo, &file, kind.clone(), write)?);
+            }
         }
-        Command::HashObject { write, file } => {
-            let hash = git_hash_object(&file, write)?;
-            println!("{}", hex::encode(hash));
+        Command::Add { pathspec } => git_add(repo, &prefix_pathspecs(pathspec)?)?,
+        Command::Status {
+            short,
+            branch,
+            untracked_files,
+        } => {
+            let config = Config::load(repo)?;
+            let branch = branch || config.get_bool("status.branch")?.unwrap_or(false);
+            let untracked_files = match untracked_files
+                .as_deref()
+                .or(config.get("status.showUntrackedFiles"))
+            {
+                Some(mode) => UntrackedFiles::parse(mode)?,
+                None => UntrackedFiles::default(),
+            };
+            let status = git_status(
+                repo,
+                &config,
+                &StatusOptions {
+                    short,
+                    branch,
+                    untracked_files,
+                },
+            )?;
+            match short {
+                true if branch => print!(
+                    "{}{}",
+                    format_branch_header(&status),
+                    format_short(repo, &status)
+                ),
+                true => print!("{}", format_short(repo, &status)),
+                false => {
+                    let hints = config.get_bool("advice.statusHints")?.unwrap_or(true);
+                    print!("{}", format_long(repo, &status, hints)?);
+                }
+            }
+        }
+        Command::Branch {
+            delete,
+            force_delete,
+            verbose,
+            args,
+        } => {
+            let config = Config::load(repo)?;
+            if delete || force_delete {
+                let mut status = 0;
+                for (name, deletion) in git_branch_delete(repo, &config, &args, force_delete)? {
+                    match deletion {
+                        Deletion::Deleted { hash, warning } => {
+                            if let Some(warning) = warning {
+                                eprintln!("warning: {warning}");
+                            }
+                            println!("Deleted branch {name} (was {}).", &hash[..7]);
+                        }
+                        Deletion::Refused(reason) => {
+                            eprintln!("error: {reason}");
+                            status = 1;
+                        }
+                    }
+                }
+                if status != 0 {
+                    std::process::exit(status);
+                }
+            } else {
+                let (name, start_point) = match args.as_slice() {
+                    [] => {
+                        let branches = git_branch_list(repo, &config, verbose)?;
+                        let width = branches.iter().map(|b| b.name.len()).max().unwrap_or(0);
+                        for branch in &branches {
+                            print!("{}", format_branch(branch, width, verbose));
+                        }
+                        return Ok(());
+                    }
+                    [name] => (name, None),
+                    [name, start_point] => (name, Some(start_point.as_str())),
+                    _ => bail!("too many arguments for a create operation"),
+                };
+                if let Some(upstream) = git_branch_create(repo, &config, name, start_point)? {
+                    println!("branch '{name}' set up to track '{upstream}'.");
+                }
+            }
+        }
+        Command::Tag {
+            annotate,
+            message,
+            force,
+            delete,
+            list,
+            args,
+        } => {
+            if delete {
+                let status = git_tag_delete(repo, &args)?;
+                if status != 0 {
+                    std::process::exit(status);
+                }
+            } else if list || args.is_empty() {
+                for name in git_tag_list(repo, &args)? {
+                    println!("{name}");
+                }
+            } else {
+                let config = Config::load(repo)?;
+                let message = match (annotate, message.is_empty()) {
+                    (_, false) => Some(message_from_paragraphs(&message)),
+                    (true, true) => bail!("annotated tags need a message (-m)"),
+                    (false, true) => None,
+                };
+                match args.as_slice() {
+                    [name] => git_tag_create(repo, &config, name, None, message.as_deref(), force)?,
+                    [name, object] => git_tag_create(
+                        repo,
+                        &config,
+                        name,
+                        Some(object),
+                        message.as_deref(),
+                        force,
+                    )?,
+                    _ => bail!("too many arguments"),
+                }
+            }
+        }
+        Command::Diff {
+            cached,
+            binary,
+            check,
+            no_ext_diff,
+            ignore_all_space,
+            ignore_space_change,
+            ignore_space_at_eol,
+            ignore_blank_lines,
+            context,
+            inter_hunk_context,
+            function_context,
+            stat,
+            shortstat,
+            patch,
+            summary,
+            args,
+            paths,
+        } => {
+            let options = DiffOptions {
+                cached,
+                binary,
+                check,
+                no_ext_diff,
+                ignore_whitespace: IgnoreWhitespace {
+                    all_space: ignore_all_space,
+                    space_change: ignore_space_change,
+                    space_at_eol: ignore_space_at_eol,
+                    blank_lines: ignore_blank_lines,
+                },
+                context,
+                inter_hunk_context,
+                function_context,
+                stat: stat || shortstat,
+                shortstat,
+                patch,
+                summary,
+            };
+            let status = git_diff(
+                repo,
+                &Config::load(repo)?,
+                &args,
+                &prefix_pathspecs(paths)?,
+                &options,
+            )?;
+            if status != 0 {
+                std::process::exit(status);
+            }
         }
         Command::LsTree {
             name_only,
-            tree_hash,
+            long,
+            abbrev,
+            null_terminated,
+            tree_ish,
+            paths,
+        } => {
+            let options = LsTreeOptions {
+                name_only,
+                long,
+                abbrev,
+                null_terminated,
+            };
+            let mut out = std::io::stdout().lock();
+            for entry in git_ls_tree(repo, tree_ish.as_str(), &prefix_pathspecs(paths)?)? {
+                out.write_all(format_tree_entry(repo, &options, &entry)?.as_bytes())?;
+            }
+        }
+        Command::LsFiles {
+            cached,
+            stage,
+            others,
+            exclude_standard,
+            eol,
+            null_terminated,
+            paths,
+        } => {
+            let config = Config::load(repo)?;
+            let options = LsFilesOptions {
+                cached,
+                stage,
+                others,
+                exclude_standard,
+                eol,
+                null_terminated,
+            };
+            let out = git_ls_files(repo, &config, &prefix_pathspecs(paths)?, &options)?;
+            std::io::stdout().lock().write_all(out.as_bytes())?;
+        }
+        Command::WriteTree { prefix } => {
+            let hash = match prefix {
+                Some(prefix) => git_write_tree_prefix(repo, &prefix)?,
+                None => git_write_tree(repo)?,
+            };
+            println!("{hash}");
+        }
+        Command::Mktree {
+            null_terminated,
+            missing,
         } => {
-            git_ls_tree(name_only, tree_hash.as_str())?;
+            let hash = git_mktree(repo, null_terminated, missing)?;
+            println!("{hash}");
         }
-        Command::WriteTree => {
-            let hash = git_write_tree()?;
-            println!("{}", hex::encode(hash));
+        Command::Mktag { strict } => {
+            let hash = git_mktag(repo, strict)?;
+            println!("{hash}");
         }
         Command::CommitTree {
             message,
-            parent_hash,
+            parent_hashes,
+            sign,
             tree_hash,
         } => {
-            let hash = git_write_commit(tree_hash, parent_hash.as_deref(), message)?;
-            println!("{}", hex::encode(hash));
-        }
-        Command::Commit { message } => {
-            let tree_hash = git_write_tree()?;
-            let head_ref =
-                read_to_string(".git/HEAD").with_context(|| "failed to read .git/HEAD")?;
-            let head_ref = head_ref.trim();
-            let Some(branch_path) = head_ref.strip_prefix("ref: ") else {
-                bail!("you can't commit in a headless state");
-            };
-            let parent_hash = read_to_string(format!(".git/{}", branch_path.trim()))
-                .with_context(|| format!("failed to read .git/{}", branch_path.trim()))?;
-            let parent_hash = parent_hash.trim();
-            ensure!(parent_hash.len() == 40, "bad parent hash");
-            let commit_hash = git_write_commit(hex::encode(tree_hash), Some(parent_hash), message)?;
-            let commit_hash = hex::encode(commit_hash);
-            write(format!(".git/{}", branch_path), &commit_hash)
-                .with_context(|| format!("failed to write .git/{}", branch_path))?;
-            println!("{commit_hash}");
+            let message = if message.is_empty() {
+                message_from_stdin()?
+            } else {
+                message_from_paragraphs(&message)
+            };
+            // As in git, the names must resolve to a tree and to commits themselves;
+            // the builder checks their types rather than peeling.
+            let mut builder = CommitBuilder::new(rev_parse(repo, &tree_hash)?);
+            for parent in &parent_hashes {
+                builder = builder.parent(rev_parse(repo, parent)?);
+            }
+            let hash = builder
+                .message(message)
+                .sign(sign)
+                .write(repo, &Config::load(repo)?)?;
+            println!("{hash}");
+        }
+        Command::PackRefs { all, no_prune } => git_pack_refs(repo, all, !no_prune)?,
+        Command::UpdateRef {
+            message,
+            delete,
+            stdin,
+            args,
+        } => {
+            let config = Config::load(repo)?;
+            let message = message.unwrap_or_default();
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+            match (stdin, delete, args.as_slice()) {
+                (true, _, _) => git_update_ref_stdin(repo, &config, &message)?,
+                (_, true, [ref_name, old_value @ ..]) if old_value.len() <= 1 => git_update_ref(
+                    repo,
+                    &config,
+                    ref_name,
+                    None,
+                    old_value.first().copied(),
+                    &message,
+                )?,
+                (_, false, [ref_name, new_value, old_value @ ..]) if old_value.len() <= 1 => {
+                    git_update_ref(
+                        repo,
+                        &config,
+                        ref_name,
+                        Some(new_value),
+                        old_value.first().copied(),
+                        &message,
+                    )?
+                }
+                _ => bail!(
+                    "usage: update-ref [-m <reason>] (-d <ref> [<old-value>] | <ref> <new-value> [<old-value>] | --stdin)"
+                ),
+            }
+        }
+        Command::Cherry {
+            verbose,
+            upstream,
+            head,
+            limit,
+        } => {
+            for (commit, applied) in git_cherry(repo, upstream.as_deref(), &head, limit.as_deref())?
+            {
+                let sign = if applied { '-' } else { '+' };
+                if verbose {
+                    println!("{sign} {} {}", commit.hash, commit.subject());
+                } else {
+                    println!("{sign} {}", commit.hash);
+                }
+            }
+        }
+        Command::Checkout { target, paths, .. } if !paths.is_empty() => {
+            git_checkout_paths(repo, target.as_deref(), &prefix_pathspecs(paths)?)?;
+        }
+        Command::Checkout {
+            detach,
+            new_branch,
+            target,
+            ..
+        } => {
+            let config = Config::load(repo)?;
+            let options = CheckoutOptions {
+                detach,
+                new_branch,
+                require_branch: false,
+            };
+            git_checkout(
+                repo,
+                &config,
+                &mut std::io::stdout().lock(),
+                &mut std::io::stderr().lock(),
+                target.as_deref().unwrap_or("HEAD"),
+                &options,
+            )?;
+        }
+        Command::Switch {
+            create,
+            detach,
+            target,
+        } => {
+            let config = Config::load(repo)?;
+            let options = CheckoutOptions {
+                detach,
+                new_branch: create,
+                require_branch: true,
+            };
+            git_checkout(
+                repo,
+                &config,
+                &mut std::io::stdout().lock(),
+                &mut std::io::stderr().lock(),
+                target.as_deref().unwrap_or("HEAD"),
+                &options,
+            )?;
+        }
+        Command::Restore {
+            staged,
+            worktree,
+            source,
+            paths,
+        } => git_restore(
+            repo,
+            source.as_deref(),
+            staged,
+            worktree,
+            &prefix_pathspecs(paths)?,
+        )?,
+        Command::Log {
+            max_count,
+            oneline,
+            follow,
+            full_history,
+            simplify_merges,
+            first_parent,
+            use_mailmap,
+            no_use_mailmap,
+            revisions,
+            paths,
+        } => {
+            let config = Config::load(repo)?;
+            let mailmap =
+                use_mailmap || (!no_use_mailmap && config.get_bool("log.mailmap")?.unwrap_or(true));
+            let options = LogOptions {
+                max_count,
+                oneline,
+                follow,
+                simplification: Simplification::from_flags(full_history, simplify_merges),
+                first_parent,
+                mailmap: match mailmap {
+                    true => Some(Mailmap::load(repo, &config)),
+                    false => None,
+                },
+            };
+            let commits = git_log(repo, &revisions, &prefix_pathspecs(paths)?, &options)?;
+            let mut out = std::io::stdout().lock();
+            for (n, commit) in commits.iter().enumerate() {
+                if n > 0 && !oneline {
+                    writeln!(out)?;
+                }
+                out.write_all(format_commit(commit, &options).as_bytes())?;
+            }
+        }
+        Command::RevList {
+            max_count,
+            count,
+            objects,
+            no_object_names,
+            disk_usage,
+            full_history,
+            simplify_merges,
+            first_parent,
+            all,
+            reflog,
+            use_bitmap_index,
+            revisions,
+            paths,
+        } => {
+            let disk_usage = match disk_usage.as_ref().map(Option::as_deref) {
+                None => None,
+                Some(None) => Some(DiskUsage::Bytes),
+                Some(Some("human")) => Some(DiskUsage::Human),
+                Some(Some(format)) => bail!(
+                    "invalid value for '--disk-usage=<format>': '{format}', the only allowed format is 'human'"
+                ),
+            };
+            let options = RevListOptions {
+                max_count,
+                count,
+                simplification: Simplification::from_flags(full_history, simplify_merges),
+                first_parent,
+                objects,
+                no_object_names,
+                disk_usage,
+                all,
+                reflog,
+                use_bitmap_index,
+            };
+            git_rev_list(
+                repo,
+                &mut std::io::stdout().lock(),
+                &revisions,
+                &prefix_pathspecs(paths)?,
+                &options,
+            )?
+        }
+        Command::RevParse {
+            verify,
+            short,
+            revisions,
+        } => {
+            let short = match short {
+                Some(len) => {
+                    Some(len.map_or_else(|| default_abbrev(repo, &Config::load(repo)?), Ok)?)
+                }
+                None => None,
+            };
+            git_rev_parse(repo, &revisions, verify, short)?
+        }
+        Command::Describe {
+            tags,
+            always,
+            long,
+            abbrev,
+            dirty,
+            commit_ish,
+        } => {
+            let options = DescribeOptions {
+                tags,
+                always,
+                long,
+                abbrev,
+                dirty,
+            };
+            println!(
+                "{}",
+                git_describe(repo, commit_ish.as_deref().unwrap_or("HEAD"), &options)?
+            );
+        }
+        Command::FmtMergeMsg {
+            message,
+            log,
+            no_log,
+            into_name,
+            file,
+        } => {
+            let config = Config::load(repo)?;
+            let input = match file {
+                Some(path) if path.as_os_str() != "-" => std::fs::read_to_string(&path)
+                    .with_context(|| format!("could not open '{}'", path.display()))?,
+                _ => std::io::read_to_string(std::io::stdin())
+                    .context("could not read input file")?,
+            };
+            let log = match (log, no_log) {
+                (_, true) => 0,
+                (Some(log), false) => log,
+                (None, false) => FmtMergeMsgOptions::log_from_config(&config)?,
+            };
+            let options = FmtMergeMsgOptions {
+                message,
+                log,
+                into_name,
+            };
+            print!("{}", git_fmt_merge_msg(repo, &config, &input, &options)?);
+        }
+        Command::PatchId {
+            stable,
+            unstable: _,
+            verbatim,
+        } => {
+            for (id, commit) in
+                patch_ids_from_diff(repo, std::io::stdin().lock(), stable, verbatim)?
+            {
+                println!("{id} {commit}");
+            }
+        }
+        Command::IndexPack {
+            stdin,
+            fix_thin,
+            pack_file,
+        } => {
+            let checksum = git_index_pack(repo, pack_file.as_deref(), stdin, fix_thin)?;
+            match stdin {
+                true => println!("pack\t{checksum}"),
+                false => println!("{checksum}"),
+            }
+        }
+        Command::ShowIndex { object_format } => git_show_index(repo, object_format)?,
+        Command::PackObjects {
+            revs,
+            stdout,
+            window,
+            depth,
+            base_name,
+        } => {
+            let options = PackObjectsOptions {
+                revs,
+                stdout,
+                window,
+                depth,
+            };
+            let (pack, checksum) =
+                git_pack_objects(repo, &Config::load(repo)?, base_name.as_deref(), &options)?;
+            match stdout {
+                true => {
+                    let mut out = std::io::stdout().lock();
+                    out.write_all(&pack)?;
+                    out.flush()?;
+                }
+                false => println!("{checksum}"),
+            }
+        }
+        Command::RepoStats { top } => {
+            let stats = git_repo_stats(repo, &Config::load(repo)?, &RepoStatsOptions { top })?;
+            print!("{}", format_repo_stats(&stats));
+        }
+        Command::Gc {
+            aggressive,
+            prune,
+            no_prune,
+            cruft,
+            no_cruft,
+        } => {
+            let config = Config::load(repo)?;
+            let prune = match no_prune {
+                true => Some("never".to_string()),
+                false => prune.flatten(),
+            };
+            let cruft = cruft || (!no_cruft && config.get_bool("gc.cruftPacks")?.unwrap_or(false));
+            let options = GcOptions {
+                aggressive,
+                prune,
+                cruft,
+            };
+            git_gc(repo, &config, &options)?
+        }
+        Command::CheckMailmap { stdin, contacts } => {
+            git_check_mailmap(repo, &Config::load(repo)?, &contacts, stdin)?
+        }
+        Command::Var { list, variable } => {
+            let config = Config::load(repo)?;
+            if list {
+                for line in git_var_list(&config) {
+                    println!("{line}");
+                }
+            } else if let Some(variable) = variable {
+                println!("{}", git_var(&config, &variable)?);
+            }
+        }
+        Command::Config {
+            get,
+            get_all,
+            get_regexp,
+            add,
+            replace_all,
+            unset,
+            unset_all,
+            list,
+            fixed_value,
+            system,
+            global,
+            local,
+            file,
+            args,
+        } => {
+            let actions = [
+                (get, ConfigAction::Get),
+                (get_all, ConfigAction::GetAll),
+                (get_regexp, ConfigAction::GetRegexp),
+                (add, ConfigAction::Add),
+                (replace_all, ConfigAction::ReplaceAll),
+                (unset, ConfigAction::Unset),
+                (unset_all, ConfigAction::UnsetAll),
+                (list, ConfigAction::List),
+            ];
+            let action = match actions.iter().find(|(given, _)| *given) {
+                Some(&(_, action)) => action,
+                None if args.len() == 1 => ConfigAction::Get,
+                None => ConfigAction::Set,
+            };
+            let scope = match (system, global, local, file) {
+                (true, ..) => Some(ConfigScope::System),
+                (_, true, ..) => Some(ConfigScope::Global),
+                (_, _, true, _) => Some(ConfigScope::Local),
+                (.., Some(file)) => Some(ConfigScope::File(file)),
+                _ => None,
+            };
+            let (config, path) = match scope {
+                Some(scope) => {
+                    let path = scope.path(repo)?;
+                    (Config::load_file(&path)?, path)
+                }
+                None => (Config::load(repo)?, ConfigScope::Local.path(repo)?),
+            };
+            let status = git_config(
+                &config,
+                &mut std::io::stdout().lock(),
+                &mut std::io::stderr().lock(),
+                &path,
+                action,
+                &args,
+                fixed_value,
+            )?;
+            if status != 0 {
+                std::process::exit(status);
+            }
+        }
+        Command::Remote { verbose, command } => {
+            let config = Config::load(repo)?;
+            match command {
+                None => {
+                    for remote in git_remote_list(&config)? {
+                        match verbose {
+                            true => print!("{}", format_remote_verbose(&remote)),
+                            false => println!("{}", remote.name),
+                        }
+                    }
+                }
+                Some(RemoteCommand::Show { no_query, names }) => {
+                    for name in names {
+                        print!(
+                            "{}",
+                            format_remote_show(&git_remote_show(repo, &config, &name, no_query)?)
+                        );
+                    }
+                }
+                Some(RemoteCommand::Prune { dry_run, names }) => {
+                    for name in names {
+                        let pruned = git_remote_prune(repo, &config, &name, dry_run)?;
+                        if pruned.is_empty() {
+                            continue;
+                        }
+                        let remote = get_remote(&config, &name)?;
+                        println!("Pruning {name}");
+                        println!("URL: {}", remote.urls.first().map_or("", String::as_str));
+                        for tracking_ref in pruned {
+                            match dry_run {
+                                true => println!(" * [would prune] {tracking_ref}"),
+                                false => println!(" * [pruned] {tracking_ref}"),
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Command::Fetch {
+            all,
+            multiple,
+            jobs,
+            names,
+        } => {
+            let config = Config::load(repo)?;
+            let options = FetchOptions {
+                all,
+                multiple,
+                jobs,
+                reflog_action: std::env::args().skip(1).collect::<Vec<_>>().join(" "),
+            };
+            git_fetch(
+                repo,
+                &config,
+                &mut std::io::stdout(),
+                &mut std::io::stderr(),
+                &names,
+                &options,
+            )?;
+        }
+        Command::Push {
+            force,
+            atomic,
+            push_options,
+            signed,
+            no_signed,
+            no_verify,
+            remote,
+            refspecs,
+        } => {
+            let config = Config::load(repo)?;
+            let options = PushOptions {
+                force,
+                atomic,
+                push_options,
+                signed: if no_signed {
+                    Some("false".to_string())
+                } else {
+                    signed
+                },
+                no_verify,
+            };
+            git_push(
+                repo,
+                &config,
+                &mut std::io::stderr().lock(),
+                remote.as_deref(),
+                &refspecs,
+                &options,
+            )?;
+        }
+        Command::Commit {
+            message,
+            sign,
+            amend,
+            allow_empty,
+        } => {
+            let options = CommitOptions {
+                message,
+                sign,
+                amend,
+                allow_empty,
+            };
+            println!("{}", git_commit(repo, &Config::load(repo)?, &options)?);
+        }
+        Command::Mailsplit { dir, mboxes } => {
+            println!("{}", git_mailsplit(&dir, &mboxes)?);
+        }
+        Command::Mailinfo { msg, patch } => {
+            print!("{}", git_mailinfo(&msg, &patch)?);
+        }
+        Command::Apply {
+            check,
+            three_way,
+            whitespace,
+            patches,
+        } => {
+            let config = Config::load(repo)?;
+            let whitespace = match whitespace.as_deref().or(config.get("apply.whitespace")) {
+                Some(action) => WhitespaceAction::parse(action)?,
+                None => WhitespaceAction::default(),
+            };
+            let options = ApplyOptions {
+                check,
+                three_way,
+                whitespace,
+                whitespace_rule: WhitespaceRule::from_config(&config)?,
+            };
+            git_apply(repo, &mut std::io::stderr().lock(), &patches, &options)?;
+        }
+        Command::Am {
+            three_way,
+            continue_,
+            skip,
+            abort,
+            show_current_patch,
+            mboxes,
+        } => {
+            let config = Config::load(repo)?;
+            let (mut out, mut err) = (std::io::stdout().lock(), std::io::stderr().lock());
+            if let Some(format) = show_current_patch {
+                out.write_all(&git_am_show_current_patch(repo, &format)?)?;
+            } else if continue_ {
+                git_am_continue(repo, &config, &mut out, &mut err)?;
+            } else if skip {
+                git_am_skip(repo, &config, &mut out, &mut err)?;
+            } else if abort {
+                git_am_abort(repo, &config)?;
+            } else {
+                git_am(
+                    repo,
+                    &config,
+                    &mut out,
+                    &mut err,
+                    &mboxes,
+                    &AmOptions { three_way },
+                )?;
+            }
+        }
+        Command::CherryPick {
+            allow_empty,
+            keep_redundant_commits,
+            mainline,
+            continue_,
+            skip,
+            abort,
+            commits,
+        } => {
+            let config = Config::load(repo)?;
+            let options = SequencerOptions {
+                allow_empty,
+                keep_redundant_commits,
+                mainline,
+            };
+            let (mut out, mut err) = (std::io::stdout().lock(), std::io::stderr().lock());
+            let status = if continue_ {
+                git_sequencer_continue(repo, &config, &mut out, &mut err)?
+            } else if skip {
+                git_sequencer_skip(repo, &config, &mut out, &mut err)?
+            } else if abort {
+                git_sequencer_abort(repo, &config)?;
+                0
+            } else {
+                git_cherry_pick(repo, &config, &mut out, &mut err, &commits, &options)?
+            };
+            if status != 0 {
+                std::process::exit(status);
+            }
+        }
+        Command::Revert {
+            mainline,
+            continue_,
+            skip,
+            abort,
+            commits,
+        } => {
+            let config = Config::load(repo)?;
+            let options = SequencerOptions {
+                mainline,
+                ..SequencerOptions::default()
+            };
+            let (mut out, mut err) = (std::io::stdout().lock(), std::io::stderr().lock());
+            let status = if continue_ {
+                git_sequencer_continue(repo, &config, &mut out, &mut err)?
+            } else if skip {
+                git_sequencer_skip(repo, &config, &mut out, &mut err)?
+            } else if abort {
+                git_sequencer_abort(repo, &config)?;
+                0
+            } else {
+                git_revert(repo, &config, &mut out, &mut err, &commits, &options)?
+            };
+            if status != 0 {
+                std::process::exit(status);
+            }
+        }
+        Command::Subtree { command } => {
+            let config = Config::load(repo)?;
+            match command {
+                SubtreeCommand::Add {
+                    prefix,
+                    message,
+                    commit,
+                } => git_subtree_add(
+                    repo,
+                    &config,
+                    &mut std::io::stderr().lock(),
+                    &prefix,
+                    &commit,
+                    message.as_deref(),
+                )?,
+                SubtreeCommand::Merge {
+                    prefix,
+                    message,
+                    commit,
+                } => {
+                    let status = git_subtree_merge(
+                        repo,
+                        &config,
+                        &mut std::io::stdout().lock(),
+                        &prefix,
+                        &commit,
+                        message.as_deref(),
+                    )?;
+                    if status != 0 {
+                        std::process::exit(status);
+                    }
+                }
+                SubtreeCommand::Split {
+                    prefix,
+                    branch,
+                    commit,
+                } => git_subtree_split(
+                    repo,
+                    &config,
+                    &mut std::io::stdout().lock(),
+                    &mut std::io::stderr().lock(),
+                    &prefix,
+                    &commit,
+                    branch.as_deref(),
+                )?,
+            }
         }
+        Command::Stash { command } => match command {
+            StashCommand::Show { patch, stat, stash } => {
+                let options = DiffOptions {
+                    stat,
+                    patch,
+                    ..DiffOptions::default()
+                };
+                let status =
+                    git_stash_show(repo, &Config::load(repo)?, stash.as_deref(), &options)?;
+                if status != 0 {
+                    std::process::exit(status);
+                }
+            }
+        },
     }
     Ok(())
 }
@@ -0,0 +1,389 @@
+use crate::checkout::{remove_worktree_file, stage_side, worktree_matches, write_worktree_file};
+use crate::diff::{
+    DiffOp, DiffSide, IgnoreWhitespace, diff_lines_ignoring, diff_trees, diff_trees_to_check_out,
+    is_binary, split_lines,
+};
+use crate::index::Index;
+use crate::object_id::ObjectId;
+use crate::object_read::{Object, ObjectKind};
+use crate::repository::Repository;
+use anyhow::{Result, bail};
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::Path;
+
+/// The outcome of merging two changed versions of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeResult {
+    pub content: Vec<u8>,
+    /// How many regions were changed differently on both sides and are left between
+    /// conflict markers.
+    pub conflicts: usize,
+}
+
+/// Merge the changes `ours` and `theirs` made to `base`, line by line, like
+/// `git merge-file`: a region changed on one side only takes that side's version,
+/// one changed the same way on both takes it once, and one changed differently is
+/// a conflict, written as
+///
+/// ```text
+/// <<<<<<< ours_label
+/// our lines
+/// =======
+/// their lines
+/// >>>>>>> theirs_label
+/// ```
+///
+/// Lines both sides agree on at the edges of a conflict are moved out of it, as
+/// git's default merge level does.
+pub fn merge3(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    ours_label: &str,
+    theirs_label: &str,
+) -> MergeResult {
+    merge3_ignoring(
+        base,
+        ours,
+        theirs,
+        ours_label,
+        theirs_label,
+        &IgnoreWhitespace::default(),
+    )
+}
+
+/// Like [`merge3`], with changes only to the whitespace `ignore` names not counting
+/// as changes, as the merge options `-Xignore-space-change`, `-Xignore-all-space`
+/// and `-Xignore-space-at-eol` have it: where their side only changed whitespace our
+/// version is kept, and where ours did, theirs is taken.
+pub fn merge3_ignoring(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    ours_label: &str,
+    theirs_label: &str,
+    ignore: &IgnoreWhitespace,
+) -> MergeResult {
+    let base_lines = split_lines(base);
+    let our_lines = split_lines(ours);
+    let their_lines = split_lines(theirs);
+    let diff = |new| diff_lines_ignoring(&base_lines, new, ignore);
+    let ours_at = equal_lines(&diff(&our_lines), base_lines.len());
+    let theirs_at = equal_lines(&diff(&their_lines), base_lines.len());
+    let same = |a: &[&[u8]], b: &[&[u8]]| {
+        a.len() == b.len()
+            && a.iter()
+                .zip(b)
+                .all(|(a, b)| ignore.normalize(a) == ignore.normalize(b))
+    };
+
+    let mut out = Vec::new();
+    let mut conflicts = 0;
+    let (mut i, mut a, mut b) = (0, 0, 0);
+    loop {
+        // Lines unchanged on both sides are copied as we have them.
+        while i < base_lines.len() && ours_at[i] == Some(a) && theirs_at[i] == Some(b) {
+            out.extend_from_slice(our_lines[a]);
+            i += 1;
+            a += 1;
+            b += 1;
+        }
+        if i == base_lines.len() && a == our_lines.len() && b == their_lines.len() {
+            break;
+        }
+        // The changed region runs up to the next line both sides kept.
+        let mut end = i;
+        while end < base_lines.len() && (ours_at[end].is_none() || theirs_at[end].is_none()) {
+            end += 1;
+        }
+        let (a_end, b_end) = match end < base_lines.len() {
+            true => (ours_at[end].unwrap(), theirs_at[end].unwrap()),
+            false => (our_lines.len(), their_lines.len()),
+        };
+        let base_part = &base_lines[i..end];
+        let our_part = &our_lines[a..a_end];
+        let their_part = &their_lines[b..b_end];
+        if same(our_part, base_part) {
+            their_part
+                .iter()
+                .for_each(|line| out.extend_from_slice(line));
+        } else if same(their_part, base_part) || same(our_part, their_part) {
+            our_part.iter().for_each(|line| out.extend_from_slice(line));
+        } else {
+            conflicts += 1;
+            write_conflict(&mut out, our_part, their_part, ours_label, theirs_label);
+        }
+        (i, a, b) = (end, a_end, b_end);
+    }
+    MergeResult {
+        content: out,
+        conflicts,
+    }
+}
+
+/// For each line of the old side of `ops`, the line of the new side it was kept as.
+fn equal_lines(ops: &[DiffOp], old_len: usize) -> Vec<Option<usize>> {
+    let mut at = vec![None; old_len];
+    for op in ops {
+        if let DiffOp::Equal { old, new } = *op {
+            at[old] = Some(new);
+        }
+    }
+    at
+}
+
+fn write_conflict(
+    out: &mut Vec<u8>,
+    ours: &[&[u8]],
+    theirs: &[&[u8]],
+    ours_label: &str,
+    theirs_label: &str,
+) {
+    let prefix = ours
+        .iter()
+        .zip(theirs)
+        .take_while(|(our, their)| our == their)
+        .count();
+    let suffix = ours[prefix..]
+        .iter()
+        .rev()
+        .zip(theirs[prefix..].iter().rev())
+        .take_while(|(our, their)| our == their)
+        .count();
+    ours[..prefix]
+        .iter()
+        .for_each(|line| out.extend_from_slice(line));
+    out.extend_from_slice(format!("<<<<<<< {ours_label}\n").as_bytes());
+    write_lines(out, &ours[prefix..ours.len() - suffix]);
+    out.extend_from_slice(b"=======\n");
+    write_lines(out, &theirs[prefix..theirs.len() - suffix]);
+    out.extend_from_slice(format!(">>>>>>> {theirs_label}\n").as_bytes());
+    ours[ours.len() - suffix..]
+        .iter()
+        .for_each(|line| out.extend_from_slice(line));
+}
+
+/// Write `lines` as one side of a conflict, which must end in a newline for the
+/// marker after it to start a line.
+fn write_lines(out: &mut Vec<u8>, lines: &[&[u8]]) {
+    for line in lines {
+        out.extend_from_slice(line);
+    }
+    if !out.ends_with(b"\n") {
+        out.push(b'\n');
+    }
+}
+
+/// What [`merge_trees`] leaves at a path where the merge differs from our tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergedPath {
+    pub path: String,
+    /// What goes in the working tree: the merged file (with conflict markers if it
+    /// conflicted), the side kept, or nothing for a path the merge removes.
+    pub result: Option<DiffSide>,
+    /// For a conflict, the base, our and their versions, which are staged as 1, 2 and
+    /// 3 in place of `result`.
+    pub conflict: Option<[Option<DiffSide>; 3]>,
+}
+
+/// What [`merge_trees`] comes to: the paths that differ from our tree, and what
+/// git's merge says about them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeMerge {
+    pub paths: Vec<MergedPath>,
+    /// The lines of `Auto-merging`, `CONFLICT` and warnings, in order.
+    pub messages: Vec<String>,
+}
+
+/// Merge the changes the trees `ours` and `theirs` made to `base` (any of which may
+/// be absent, i.e. empty), path by path: a path changed on one side only takes that
+/// side's version, and a file changed on both is merged with [`merge3`], its mode
+/// taken from whichever side changed it. Returns the paths that end up differing
+/// from `ours`, in git's order.
+///
+/// A file deleted on one side and modified on the other, one both sides added or
+/// modified differently that [`merge3`] can't merge, and a binary file, symlink or
+/// submodule changed differently on both, are conflicts. The lines of `Auto-merging`
+/// and `CONFLICT` git's merge prints are kept in [`TreeMerge::messages`];
+/// `ours_label` and `theirs_label` name the sides in them and in conflict markers.
+///
+/// Renames aren't detected, so a file renamed on one side and modified on the other
+/// comes out as a deletion and an addition, in conflict with the modification.
+pub fn merge_trees(
+    repo: &Repository,
+    base: Option<&ObjectId>,
+    ours: Option<&ObjectId>,
+    theirs: Option<&ObjectId>,
+    ours_label: &str,
+    theirs_label: &str,
+) -> Result<TreeMerge> {
+    let our_changes: HashMap<String, Option<DiffSide>> = diff_trees(repo, base, ours)?
+        .into_iter()
+        .map(|change| (change.path, change.new))
+        .collect();
+    let mut merged = Vec::new();
+    let mut messages = Vec::new();
+    for change in diff_trees_to_check_out(repo, base, theirs)? {
+        let path = change.path;
+        let (base, theirs) = (change.old, change.new);
+        let ours = match our_changes.get(&path) {
+            Some(ours) => ours.clone(),
+            None => base.clone(),
+        };
+        if ours == theirs {
+            continue;
+        }
+        if ours == base {
+            merged.push(MergedPath {
+                path,
+                result: theirs,
+                conflict: None,
+            });
+            continue;
+        }
+        let conflict = |result: Option<DiffSide>, sides: [&Option<DiffSide>; 3]| MergedPath {
+            path: path.clone(),
+            result,
+            conflict: Some(sides.map(Clone::clone)),
+        };
+        let (our_side, their_side) = match (&ours, &theirs) {
+            (Some(our_side), Some(their_side)) => (our_side, their_side),
+            (None, _) => {
+                messages.push(format!(
+                    "CONFLICT (modify/delete): {path} deleted in {ours_label} and modified in \
+                     {theirs_label}.  Version {theirs_label} of {path} left in tree."
+                ));
+                merged.push(conflict(theirs.clone(), [&base, &ours, &theirs]));
+                continue;
+            }
+            (_, None) => {
+                messages.push(format!(
+                    "CONFLICT (modify/delete): {path} deleted in {theirs_label} and modified in \
+                     {ours_label}.  Version {ours_label} of {path} left in tree."
+                ));
+                merged.push(conflict(ours.clone(), [&base, &ours, &theirs]));
+                continue;
+            }
+        };
+        let is_file = |side: &DiffSide| side.mode == "100644" || side.mode == "100755";
+        let read = |side: &Option<DiffSide>| match side {
+            Some(side) => Object::read_git_object(repo, &side.hash.to_string())?.read_all(),
+            None => Ok(Vec::new()),
+        };
+        let kind = if base.is_none() { "add/add" } else { "content" };
+        messages.push(format!("Auto-merging {path}"));
+        if !is_file(our_side) || !is_file(their_side) {
+            messages.push(format!("CONFLICT ({kind}): Merge conflict in {path}"));
+            merged.push(conflict(ours.clone(), [&base, &ours, &theirs]));
+            continue;
+        }
+        let (base_data, our_data, their_data) = (read(&base)?, read(&ours)?, read(&theirs)?);
+        if [&base_data, &our_data, &their_data]
+            .into_iter()
+            .any(|data| is_binary(data))
+        {
+            messages.push(format!(
+                "warning: Cannot merge binary files: {path} ({ours_label} vs. {theirs_label})"
+            ));
+            messages.push(format!("CONFLICT ({kind}): Merge conflict in {path}"));
+            merged.push(conflict(ours.clone(), [&base, &ours, &theirs]));
+            continue;
+        }
+        let result = merge3(&base_data, &our_data, &their_data, ours_label, theirs_label);
+        let mode = match &base {
+            Some(base) if base.mode == our_side.mode => their_side.mode.clone(),
+            _ => our_side.mode.clone(),
+        };
+        let hash = Object {
+            kind: ObjectKind::Blob,
+            expected_size: result.content.len() as u64,
+            reader: Cursor::new(result.content),
+        }
+        .write_as_object(repo)?;
+        let side = Some(DiffSide { mode, hash });
+        if result.conflicts > 0 {
+            messages.push(format!("CONFLICT ({kind}): Merge conflict in {path}"));
+            merged.push(conflict(side, [&base, &ours, &theirs]));
+        } else if side != ours {
+            merged.push(MergedPath {
+                path,
+                result: side,
+                conflict: None,
+            });
+        }
+    }
+    Ok(TreeMerge {
+        paths: merged,
+        messages,
+    })
+}
+
+/// Bring the working tree and index, which are at the `ours` of a [`merge_trees`],
+/// to its result, whose messages are the caller's to show: each path is written as
+/// `merged` has it and staged, or, for a conflict, staged as its three versions.
+/// Returns the paths left in conflict.
+///
+/// Nothing is touched if a file the merge changes has changes of its own in the
+/// working tree, or is untracked where the merge would write one, as git refuses
+/// then; other changes to the working tree are left alone.
+pub fn checkout_merge(repo: &Repository, merge: &TreeMerge) -> Result<Vec<String>> {
+    let merged = &merge.paths;
+    let mut index = Index::load(repo)?;
+    let (mut modified, mut untracked) = (Vec::new(), Vec::new());
+    for path in merged {
+        let file = Path::new(&path.path);
+        let ours = index.entry(&path.path).map(|entry| DiffSide {
+            mode: format!("{:o}", entry.mode),
+            hash: entry.id,
+        });
+        if worktree_matches(repo, file, ours.as_ref())?
+            || worktree_matches(repo, file, path.result.as_ref())?
+        {
+            continue;
+        }
+        match ours {
+            Some(_) => modified.push(path.path.as_str()),
+            None => untracked.push(path.path.as_str()),
+        }
+    }
+    let mut refusals = Vec::new();
+    if !modified.is_empty() {
+        refusals.push(format!(
+            "Your local changes to the following files would be overwritten by merge:\n\t{}\n\
+             Please commit your changes or stash them before you merge.",
+            modified.join("\n\t")
+        ));
+    }
+    if !untracked.is_empty() {
+        refusals.push(format!(
+            "The following untracked working tree files would be overwritten by merge:\n\t{}\n\
+             Please move or remove them before you merge.",
+            untracked.join("\n\t")
+        ));
+    }
+    if !refusals.is_empty() {
+        bail!("{}\nAborting", refusals.join("\nerror: "));
+    }
+    // Removals go first, so that a directory can take a removed file's place.
+    for path in merged.iter().filter(|path| path.result.is_none()) {
+        remove_worktree_file(repo, Path::new(&path.path))?;
+        index.remove(&path.path);
+    }
+    let mut conflicts = Vec::new();
+    for path in merged {
+        if let Some(result) = &path.result {
+            write_worktree_file(repo, Path::new(&path.path), result)?;
+        }
+        match (&path.conflict, &path.result) {
+            (Some(sides), _) => {
+                index.add_conflict(&path.path, sides)?;
+                conflicts.push(path.path.clone());
+            }
+            (None, Some(result)) => stage_side(repo, &mut index, &path.path, result)?,
+            (None, None) => {}
+        }
+    }
+    index.save(repo)?;
+    Ok(conflicts)
+}
@@ -0,0 +1,92 @@
+use crate::ident::Ident;
+use crate::object_id::ObjectId;
+use crate::object_read::{Object, ObjectKind};
+use crate::refs::is_valid_ref_name;
+use crate::repository::Repository;
+use anyhow::{Context, Result, bail, ensure};
+use std::io::{Cursor, Read, stdin};
+
+/// An *annotated tag object* names another object and carries a message, usually
+/// alongside who created it and when.
+///
+/// The raw (uncompressed) format of a tag object is:
+///
+///     object <object-id>
+///     type <object-type>
+///     tag <tag-name>
+///     tagger <name> <email> <timestamp> <timezone>
+///
+///     <tag message>
+///
+/// This reads such a payload from stdin, validates it and writes it as a tag object.
+/// The referenced object must exist and be of the stated type. With `strict`, the
+/// tagger line becomes mandatory and must be a well-formed identity, the tag name must
+/// be usable as a ref name, and the headers must be followed by a blank line.
+pub fn git_mktag(repo: &Repository, strict: bool) -> Result<ObjectId> {
+    let mut payload = String::new();
+    stdin()
+        .read_to_string(&mut payload)
+        .context("failed to read tag from stdin")?;
+    validate_tag(repo, &payload, strict)?;
+    let mut object = Object {
+        kind: ObjectKind::Tag,
+        expected_size: payload.len() as u64,
+        reader: Cursor::new(payload),
+    };
+    object.write_as_object(repo)
+}
+
+fn validate_tag(repo: &Repository, payload: &str, strict: bool) -> Result<()> {
+    let mut lines = payload.split_inclusive('\n');
+    let mut header = |name: &str| -> Result<&str> {
+        let Some(line) = lines.next() else {
+            bail!("tag is missing the '{name}' header");
+        };
+        let Some(value) = line.strip_suffix('\n') else {
+            bail!("'{name}' header is not terminated by a newline");
+        };
+        value
+            .strip_prefix(name)
+            .and_then(|v| v.strip_prefix(' '))
+            .with_context(|| format!("expected '{name} ' header, got {value:?}"))
+    };
+    let object = header("object")?;
+    let kind = header("type")?;
+    let tag = header("tag")?;
+    ensure!(
+        repo.hash_algorithm()?.is_hex_id(object),
+        "invalid object id {object:?}"
+    );
+    let kind: ObjectKind = kind.parse()?;
+    let target = Object::read_git_object(repo, object)
+        .with_context(|| format!("tagged object {object} does not exist"))?;
+    ensure!(
+        target.kind == kind,
+        "tagged object {} is a {}, not a {}",
+        object,
+        target.kind.to_str(),
+        kind.to_str()
+    );
+    ensure!(!tag.is_empty(), "tag name is empty");
+
+    let rest: Vec<&str> = lines.collect();
+    let tagger = rest.first().and_then(|line| line.strip_prefix("tagger "));
+    if strict {
+        ensure!(
+            is_valid_ref_name(tag),
+            "tag name {tag:?} is not a valid ref name"
+        );
+        let Some(tagger) = tagger else {
+            bail!("tag is missing the 'tagger' header");
+        };
+        Ident::parse(tagger.trim_end_matches('\n')).context("invalid tagger")?;
+        let after_headers = rest.get(1).copied().unwrap_or("\n");
+        ensure!(
+            after_headers == "\n",
+            "tag headers must be followed by a blank line"
+        );
+    } else if let Some(tagger) = tagger {
+        Ident::parse(tagger.trim_end_matches('\n')).context("invalid tagger")?;
+    }
+    Ok(())
+}
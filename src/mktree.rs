@@ -0,0 +1,129 @@
+use crate::object_id::ObjectId;
+use crate::object_read::{Object, ObjectKind};
+use crate::repository::Repository;
+use crate::write_tree::compare_tree_entry_names;
+use anyhow::{Context, Result, bail, ensure};
+use std::collections::HashSet;
+use std::io::{BufRead, Cursor, stdin};
+
+/// Build a tree object from `ls-tree` formatted entries read from stdin, one per line:
+///
+///     <mode> SP <type> SP <object-id> TAB <name>
+///
+/// The separator before the name may also be the four spaces our own `ls-tree` prints,
+/// so `ls-tree <tree> | mktree` round-trips.
+///
+/// Every entry is validated: the mode must be one Git writes, the type must agree with
+/// it, the name can't be empty or contain `/`, names must be unique, and referenced
+/// objects must exist with the claimed type (unless `allow_missing` is set; submodule
+/// commits are never checked as they live in another repository). Entries are then
+/// sorted into Git's tree order, so input order doesn't matter.
+pub fn git_mktree(
+    repo: &Repository,
+    null_terminated: bool,
+    allow_missing: bool,
+) -> Result<ObjectId> {
+    let separator = if null_terminated { 0 } else { b'\n' };
+    let mut entries = Vec::new();
+    let mut names = HashSet::new();
+    for line in stdin().lock().split(separator) {
+        let line = line.context("failed to read stdin")?;
+        let line = String::from_utf8(line).context("input contains invalid UTF-8")?;
+        if line.is_empty() {
+            continue;
+        }
+        let entry = parse_entry(repo, &line, allow_missing)
+            .with_context(|| format!("invalid mktree entry: {line:?}"))?;
+        ensure!(
+            names.insert(entry.name.clone()),
+            "duplicate entry name: {}",
+            entry.name
+        );
+        entries.push(entry);
+    }
+    entries.sort_unstable_by(|a, b| {
+        compare_tree_entry_names(
+            a.name.as_bytes(),
+            a.kind == ObjectKind::Tree,
+            b.name.as_bytes(),
+            b.kind == ObjectKind::Tree,
+        )
+    });
+    let mut out = Vec::new();
+    for entry in entries {
+        out.extend_from_slice(entry.mode.as_bytes());
+        out.push(b' ');
+        out.extend_from_slice(entry.name.as_bytes());
+        out.push(0);
+        out.extend(entry.hash.as_bytes());
+    }
+    let mut object = Object {
+        kind: ObjectKind::Tree,
+        expected_size: out.len() as u64,
+        reader: Cursor::new(out),
+    };
+    object.write_as_object(repo)
+}
+
+struct MktreeEntry {
+    mode: &'static str,
+    kind: ObjectKind,
+    name: String,
+    hash: ObjectId,
+}
+
+fn parse_entry(repo: &Repository, line: &str, allow_missing: bool) -> Result<MktreeEntry> {
+    let Some((meta, name)) = line.split_once('\t').or_else(|| line.split_once("    ")) else {
+        bail!("missing tab before entry name");
+    };
+    let mut fields = meta.split(' ');
+    let (Some(mode), Some(kind), Some(hash), None) =
+        (fields.next(), fields.next(), fields.next(), fields.next())
+    else {
+        bail!("expected '<mode> <type> <object-id>'");
+    };
+    let (mode, expected_kind) = match mode {
+        "100644" => ("100644", ObjectKind::Blob),
+        "100755" => ("100755", ObjectKind::Blob),
+        "120000" => ("120000", ObjectKind::Blob),
+        "40000" | "040000" => ("40000", ObjectKind::Tree),
+        "160000" => ("160000", ObjectKind::Commit),
+        other => bail!("unsupported mode {other}"),
+    };
+    let kind: ObjectKind = kind.parse()?;
+    ensure!(
+        kind == expected_kind,
+        "mode {} requires a {} but entry claims a {}",
+        mode,
+        expected_kind.to_str(),
+        kind.to_str()
+    );
+    ensure!(
+        !name.is_empty() && !name.contains('/') && name != "." && name != "..",
+        "invalid entry name {name:?}"
+    );
+    ensure!(
+        repo.hash_algorithm()?.is_hex_id(hash),
+        "invalid object id {hash}"
+    );
+    let raw_hash = hash.parse()?;
+    if kind != ObjectKind::Commit {
+        match Object::read_git_object(repo, hash) {
+            Ok(object) => ensure!(
+                object.kind == kind,
+                "object {} is a {}, not a {}",
+                hash,
+                object.kind.to_str(),
+                kind.to_str()
+            ),
+            Err(_) if allow_missing => {}
+            Err(e) => return Err(e).with_context(|| format!("object {hash} is missing")),
+        }
+    }
+    Ok(MktreeEntry {
+        mode,
+        kind,
+        name: name.to_string(),
+        hash: raw_hash,
+    })
+}
@@ -0,0 +1,198 @@
+use anyhow::{Context, Result, bail};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// The hash function a repository names its objects with, chosen when it is created
+/// and recorded as `extensions.objectFormat` (see
+/// [`crate::repository::hash_algorithm`]). Repositories are SHA-1 unless they say
+/// otherwise.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+impl HashAlgorithm {
+    /// The algorithm `extensions.objectFormat` and `--object-format` call `name`.
+    pub fn from_name(name: &str) -> Option<HashAlgorithm> {
+        match name.to_ascii_lowercase().as_str() {
+            "sha1" => Some(HashAlgorithm::Sha1),
+            "sha256" => Some(HashAlgorithm::Sha256),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    /// The length of a raw hash, as trees, indexes and packs store ids.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(self) -> usize {
+        match self {
+            HashAlgorithm::Sha1 => 20,
+            HashAlgorithm::Sha256 => 32,
+        }
+    }
+
+    /// The length of an id in hex, as refs and the command line show it.
+    pub fn hex_len(self) -> usize {
+        2 * self.len()
+    }
+
+    /// The all-zero id that stands for "no object", e.g. the old value of a ref being
+    /// created.
+    pub fn null_id(self) -> ObjectId {
+        match self {
+            HashAlgorithm::Sha1 => ObjectId::Sha1([0; 20]),
+            HashAlgorithm::Sha256 => ObjectId::Sha256([0; 32]),
+        }
+    }
+
+    pub fn hasher(self) -> Hasher {
+        match self {
+            HashAlgorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+
+    /// The hash of `data`, e.g. of a file that ends in its own checksum.
+    pub fn digest(self, data: &[u8]) -> ObjectId {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finish()
+    }
+
+    /// Whether `hex` is a full id of this algorithm.
+    pub fn is_hex_id(self, hex: &str) -> bool {
+        hex.len() == self.hex_len() && hex.bytes().all(|b| b.is_ascii_hexdigit())
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match HashAlgorithm::from_name(name) {
+            Some(algorithm) => Ok(algorithm),
+            None => bail!("unknown hash algorithm '{name}'"),
+        }
+    }
+}
+
+/// A running hash of one of the [`HashAlgorithm`]s; data can also be written to it.
+#[derive(Clone)]
+pub enum Hasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha1(hasher) => hasher.update(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    pub fn finish(self) -> ObjectId {
+        match self {
+            Hasher::Sha1(hasher) => ObjectId::Sha1(hasher.finalize().into()),
+            Hasher::Sha256(hasher) => ObjectId::Sha256(hasher.finalize().into()),
+        }
+    }
+}
+
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The name of an object: the hash of its header and content, as written by
+/// [`crate::object_write`]. Displayed (and parsed) as the lowercase hex form used in
+/// refs, trees' text forms and on the command line.
+///
+/// The hash is SHA-1 or SHA-256, whichever the repository uses, but callers only
+/// display, parse, compare and shorten ids, so that the bytes stay an implementation
+/// detail of this type.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ObjectId {
+    Sha1([u8; 20]),
+    Sha256([u8; 32]),
+}
+
+impl ObjectId {
+    /// The id whose raw hash of `algorithm` is `bytes`, as stored in trees, indexes
+    /// and packs.
+    pub fn from_bytes(algorithm: HashAlgorithm, bytes: &[u8]) -> Result<ObjectId> {
+        Ok(match algorithm {
+            HashAlgorithm::Sha1 => ObjectId::Sha1(bytes.try_into()?),
+            HashAlgorithm::Sha256 => ObjectId::Sha256(bytes.try_into()?),
+        })
+    }
+
+    /// The raw hash, as it is stored in tree objects.
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            ObjectId::Sha1(hash) => hash,
+            ObjectId::Sha256(hash) => hash,
+        }
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        match self {
+            ObjectId::Sha1(_) => HashAlgorithm::Sha1,
+            ObjectId::Sha256(_) => HashAlgorithm::Sha256,
+        }
+    }
+
+    /// The first `len` hex digits of the id, at least 4 and at most all of them, as
+    /// `--abbrev=<len>` shows it.
+    pub fn short(&self, len: usize) -> String {
+        let mut hex = self.to_string();
+        hex.truncate(len.clamp(4, hex.len()));
+        hex
+    }
+}
+
+impl FromStr for ObjectId {
+    type Err = anyhow::Error;
+
+    /// Parse a full hex object id of either algorithm, told apart by length;
+    /// abbreviated ones have to be expanded first, as
+    /// [`crate::object_read::full_object_id`] does.
+    fn from_str(hex: &str) -> Result<Self> {
+        let algorithm = match hex.len() {
+            40 => HashAlgorithm::Sha1,
+            64 => HashAlgorithm::Sha256,
+            _ => bail!("invalid object id {hex}"),
+        };
+        let bytes = hex::decode(hex).with_context(|| format!("invalid object id {hex}"))?;
+        ObjectId::from_bytes(algorithm, &bytes)
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(self.as_bytes()))
+    }
+}
+
+impl fmt::Debug for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ObjectId({self})")
+    }
+}
@@ -1,32 +1,71 @@
-use anyhow::{Context, Result, anyhow, bail};
+use crate::commit_read::Commit;
+use crate::config::Config;
+use crate::ident::Ident;
+use crate::object_id::{Hasher, ObjectId};
+use crate::odb::{Odb, OdbReader};
+use crate::pack::PackOdb;
+use crate::repository::{Repository, hash_algorithm_in};
+use crate::rev_parse::rev_parse;
+use crate::transfer::loose_object_path;
+use anyhow::{Context, Result, anyhow, bail, ensure};
 use flate2::read::ZlibDecoder;
+use std::collections::HashMap;
 use std::ffi::CStr;
 use std::fs;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Cursor, ErrorKind, Read, Take, sink};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Objects whose header declares a larger size are refused before inflating.
+/// Can be overridden with the `GIT_RS_MAX_OBJECT_SIZE` environment variable (in bytes).
+pub const DEFAULT_MAX_OBJECT_SIZE: u64 = 1 << 30;
+
+/// Longest header we accept: "<kind> <size>\0" can never legitimately exceed this.
+const MAX_HEADER_LEN: u64 = 32;
+
+/// Reader over the inflated body of a loose object, bounded by the size in its header.
+pub type LooseObjectReader = Take<BufReader<VerifyingReader<ZlibDecoder<File>>>>;
+
+pub fn max_object_size() -> Result<u64> {
+    match std::env::var("GIT_RS_MAX_OBJECT_SIZE") {
+        Ok(size) => size
+            .parse()
+            .context("GIT_RS_MAX_OBJECT_SIZE must be a number of bytes"),
+        Err(_) => Ok(DEFAULT_MAX_OBJECT_SIZE),
+    }
+}
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ObjectKind {
     Blob,
     Tree,
     Commit,
+    Tag,
 }
 
-impl ObjectKind {
-    pub fn from_str(kind: &str) -> Result<Self> {
+impl FromStr for ObjectKind {
+    type Err = anyhow::Error;
+
+    fn from_str(kind: &str) -> Result<Self> {
         match kind {
             "blob" => Ok(ObjectKind::Blob),
             "tree" => Ok(ObjectKind::Tree),
             "commit" => Ok(ObjectKind::Commit),
+            "tag" => Ok(ObjectKind::Tag),
             other => Err(anyhow!("unknown object kind: {}", other)),
         }
     }
+}
 
+impl ObjectKind {
     pub fn to_str(&self) -> &str {
         match self {
             ObjectKind::Blob => "blob",
             ObjectKind::Tree => "tree",
             ObjectKind::Commit => "commit",
+            ObjectKind::Tag => "tag",
         }
     }
 
@@ -47,36 +86,149 @@ pub struct Object<R> {
 }
 
 impl Object<()> {
-    pub fn read_git_object(hash: &str) -> Result<Object<impl BufRead>> {
-        if hash.len() < 3 {
-            bail!("Hash objects len must be at least 3");
+    /// Open the object `hash` (which may be abbreviated) of the repository, loose or
+    /// packed, or from the store it was given (see [`Repository::with_odb`]).
+    pub fn read_git_object(repo: &Repository, hash: &str) -> Result<Object<OdbReader>> {
+        Self::read_git_object_with_limit(repo, hash, max_object_size()?, false)
+    }
+
+    /// Like [`Object::read_git_object`], but the object's hash is recomputed while it is
+    /// inflated and [`Object::ensure_fully_read`] fails if it doesn't match the object id.
+    pub fn read_git_object_verified(repo: &Repository, hash: &str) -> Result<Object<OdbReader>> {
+        Self::read_git_object_with_limit(repo, hash, max_object_size()?, true)
+    }
+
+    /// Like [`Object::read_git_object`], for the objects under `objects_dir` (another
+    /// repository's `.git/objects`, or a directory objects are staged in).
+    pub fn read_git_object_in(objects_dir: &Path, hash: &str) -> Result<Object<OdbReader>> {
+        Self::read_object_in(objects_dir, hash, max_object_size()?, false)
+    }
+
+    /// Open an object, refusing it if the header declares more than `max_size` bytes.
+    /// The returned reader never yields more than the declared size, so a corrupt or
+    /// malicious object can't inflate without bound.
+    pub fn read_git_object_with_limit(
+        repo: &Repository,
+        hash: &str,
+        max_size: u64,
+        verify: bool,
+    ) -> Result<Object<OdbReader>> {
+        let Some(odb) = repo.odb() else {
+            return Self::read_object_in(&repo.objects_dir(), hash, max_size, verify);
+        };
+        let hash = full_object_id(repo, hash)?;
+        let mut object = odb.read(&hash)?;
+        ensure!(
+            object.expected_size <= max_size,
+            "object {} is {} bytes, exceeding the maximum object size of {} bytes",
+            hash,
+            object.expected_size,
+            max_size
+        );
+        if verify {
+            let data = object.read_all()?;
+            let id: ObjectId = hash.parse()?;
+            let actual = Object {
+                reader: Cursor::new(&data),
+                kind: object.kind.clone(),
+                expected_size: object.expected_size,
+            }
+            .write(id.algorithm(), sink())?;
+            ensure!(
+                actual == id,
+                "object hash mismatch: expected {hash}, got {actual}"
+            );
+            let reader: Box<dyn BufRead> = Box::new(Cursor::new(data));
+            object.reader = reader.take(object.expected_size);
         }
-        let mut files = Vec::new();
-        for entry in fs::read_dir(format!(".git/objects/{}", &hash[..2]))
-            .map_err(|e| anyhow!("error reading .git/objects directory: {}", e))?
-        {
-            let entry = entry?;
-            let path = entry.path();
-            if entry
-                .file_name()
-                .into_string()
-                .map_err(|_| anyhow!("file name conversion error"))?
-                .starts_with(&hash[2..])
-                && entry.file_type()?.is_file()
-            {
-                files.push(path);
+        Ok(object)
+    }
+
+    /// The loose object `hash` under `objects_dir`, if there is one, otherwise the
+    /// packed one. A packed object is read whole, so its hash is checked right away.
+    fn read_object_in(
+        objects_dir: &Path,
+        hash: &str,
+        max_size: u64,
+        verify: bool,
+    ) -> Result<Object<OdbReader>> {
+        let hash = full_object_id_in(objects_dir, hash)?;
+        if !loose_object_path(objects_dir, &hash).is_file() {
+            let id: ObjectId = hash.parse()?;
+            let packs = packs(objects_dir)?;
+            // The entry headers give the size, so an object too big is refused before
+            // anything is inflated.
+            if let Some(size) = packs.read_size(&id)? {
+                ensure!(
+                    size <= max_size,
+                    "object {hash} is {size} bytes, exceeding the maximum object size of {max_size} bytes"
+                );
             }
+            let Some((kind, data)) = packs.read_object(&id)? else {
+                bail!("No objects found");
+            };
+            let mut object = Object {
+                reader: Cursor::new(&data),
+                kind: kind.clone(),
+                expected_size: data.len() as u64,
+            };
+            if verify {
+                let actual = object.write(id.algorithm(), sink())?;
+                ensure!(
+                    actual == id,
+                    "object hash mismatch: expected {hash}, got {actual}"
+                );
+            }
+            let size = data.len() as u64;
+            let reader: Box<dyn BufRead> = Box::new(Cursor::new(data));
+            return Ok(Object {
+                reader: reader.take(size),
+                kind,
+                expected_size: size,
+            });
         }
-        if files.is_empty() {
+        let object = Self::read_loose_object(objects_dir, &hash, max_size, verify)?;
+        let reader: Box<dyn BufRead> = Box::new(object.reader.into_inner());
+        Ok(Object {
+            reader: reader.take(object.expected_size),
+            kind: object.kind,
+            expected_size: object.expected_size,
+        })
+    }
+
+    /// Like [`Object::read_git_object_in`], but only for loose objects.
+    pub fn read_loose_object_in(
+        objects_dir: &Path,
+        hash: &str,
+    ) -> Result<Object<LooseObjectReader>> {
+        Self::read_loose_object(objects_dir, hash, max_object_size()?, false)
+    }
+
+    fn read_loose_object(
+        objects_dir: &Path,
+        hash: &str,
+        max_size: u64,
+        verify: bool,
+    ) -> Result<Object<LooseObjectReader>> {
+        let hash = full_object_id_in(objects_dir, hash)?;
+        let path = loose_object_path(objects_dir, &hash);
+        if !path.is_file() {
             bail!("No objects found");
-        } else if files.len() > 1 {
-            bail!("Multiple objects found: {}", files.len());
         }
-        let file = File::open(&files[0])?;
+        let file = File::open(path)?;
         let decoder = ZlibDecoder::new(file);
-        let mut reader = BufReader::new(decoder);
+        let expected_hash = if verify {
+            let expected: ObjectId = hash
+                .parse()
+                .with_context(|| format!("object file name {hash} is not a valid hash"))?;
+            Some(expected)
+        } else {
+            None
+        };
+        let mut reader = BufReader::new(VerifyingReader::new(decoder, expected_hash));
         let mut buf = Vec::new();
-        reader
+        (&mut reader)
+            .take(MAX_HEADER_LEN)
             .read_until(0, &mut buf)
             .context("failed to read header")?;
         let header = CStr::from_bytes_with_nul(&buf).context("header is in invalid format")?;
@@ -85,11 +237,387 @@ impl Object<()> {
             bail!("header is in invalid format");
         };
         let expected_size = size.parse::<u64>().context("object size isn't a number")?;
-        let kind = ObjectKind::from_str(kind)?;
+        ensure!(
+            expected_size <= max_size,
+            "object {} is {} bytes, exceeding the maximum object size of {} bytes",
+            hash,
+            expected_size,
+            max_size
+        );
+        let kind: ObjectKind = kind.parse()?;
         Ok(Object {
-            reader,
+            reader: reader.take(expected_size),
             kind,
             expected_size,
         })
     }
 }
+
+/// The fewest hex digits an abbreviated object id can have, as in git.
+pub const MINIMUM_ABBREV: usize = 4;
+
+/// How many hex digits object ids are abbreviated to by default: `core.abbrev`, at
+/// least [`MINIMUM_ABBREV`], or all of them for `no`; 7 if it is unset or `auto`.
+pub fn default_abbrev(repo: &Repository, config: &Config) -> Result<usize> {
+    abbrev_len(config, repo.hash_algorithm()?.hex_len())
+}
+
+/// [`default_abbrev`] for ids of `hex_len` digits.
+fn abbrev_len(config: &Config, hex_len: usize) -> Result<usize> {
+    Ok(match config.get("core.abbrev") {
+        None | Some("auto") => 7,
+        Some(value) if matches!(value.to_ascii_lowercase().as_str(), "no" | "false" | "off") => {
+            hex_len
+        }
+        Some(value) => match value.parse::<usize>() {
+            Ok(len) if len >= MINIMUM_ABBREV => len.min(hex_len),
+            _ => bail!("bad numeric config value '{value}' for 'core.abbrev': out of range"),
+        },
+    })
+}
+
+/// Expand a possibly abbreviated object id to the full id of the one object it names,
+/// loose or packed.
+///
+/// An abbreviation needs at least [`MINIMUM_ABBREV`] hex digits. One that more than
+/// one object starts with is refused with a list of them, as git gives, so that a
+/// longer one can be picked:
+///
+/// ```text
+/// short object ID 1f9d is ambiguous
+/// hint: The candidates are:
+/// hint:   1f9d725 commit 2026-10-14 - Add the parser
+/// hint:   1f9d0a8 blob
+/// ```
+pub fn full_object_id(repo: &Repository, hash: &str) -> Result<String> {
+    let Some(odb) = repo.odb() else {
+        return full_object_id_in(&repo.objects_dir(), hash);
+    };
+    if !is_abbreviation(hash, odb.algorithm()?.hex_len())? {
+        return Ok(hash.to_string());
+    }
+    match object_ids_with_prefix(repo, &hash.to_ascii_lowercase())?.as_slice() {
+        [] => bail!("No objects found"),
+        [id] => Ok(id.to_string()),
+        _ => bail!("short object ID {hash} is ambiguous"),
+    }
+}
+
+/// [`full_object_id`] for the objects under `objects_dir`.
+fn full_object_id_in(objects_dir: &Path, hash: &str) -> Result<String> {
+    if !is_abbreviation(hash, hash_algorithm_in(objects_dir)?.hex_len())? {
+        return Ok(hash.to_string());
+    }
+    let ids = ids_with_prefix(objects_dir, hash)?;
+    match ids.as_slice() {
+        [] => bail!("No objects found"),
+        [id] => Ok(id.to_string()),
+        _ => bail!(
+            "short object ID {hash} is ambiguous\nhint: The candidates are:\n{}",
+            ambiguous_candidates(objects_dir, &ids)?
+        ),
+    }
+}
+
+/// Whether `hash` is an abbreviated id rather than one of `hex_len` digits, failing
+/// if it is neither.
+fn is_abbreviation(hash: &str, hex_len: usize) -> Result<bool> {
+    if hash.len() == hex_len {
+        return Ok(false);
+    }
+    if hash.len() > hex_len || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        bail!("not a valid object name: {hash}");
+    }
+    if hash.len() < MINIMUM_ABBREV {
+        bail!(
+            "short object ID {hash} is too short: at least {MINIMUM_ABBREV} hex digits are needed"
+        );
+    }
+    Ok(true)
+}
+
+/// The ids of the objects of `repo` that start with the hex digits `prefix`, sorted;
+/// see [`ids_with_prefix`].
+pub fn object_ids_with_prefix(repo: &Repository, prefix: &str) -> Result<Vec<ObjectId>> {
+    let Some(odb) = repo.odb() else {
+        return ids_with_prefix(&repo.objects_dir(), prefix);
+    };
+    Ok(odb
+        .ids()?
+        .into_iter()
+        .filter(|id| id.to_string().starts_with(prefix))
+        .collect())
+}
+
+/// The ids of the objects under `objects_dir`, loose or packed, that start with the
+/// hex digits `prefix`, sorted.
+pub fn ids_with_prefix(objects_dir: &Path, prefix: &str) -> Result<Vec<ObjectId>> {
+    let mut ids = packs(objects_dir)?.ids_with_prefix(prefix);
+    let Some(fanout) = prefix.get(..2) else {
+        return Ok(ids);
+    };
+    let entries = match fs::read_dir(objects_dir.join(fanout)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(ids),
+        Err(e) => return Err(anyhow!("error reading .git/objects directory: {e}")),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if name.starts_with(&prefix[2..])
+            && entry.file_type()?.is_file()
+            && let Ok(id) = format!("{fanout}{name}").parse()
+        {
+            ids.push(id);
+        }
+    }
+    ids.sort();
+    ids.dedup();
+    Ok(ids)
+}
+
+/// The shortest abbreviation of `id`, of at least `min_len` hex digits, that no
+/// other object under `objects_dir` starts with.
+pub fn unique_abbrev(objects_dir: &Path, id: &ObjectId, min_len: usize) -> Result<String> {
+    let hex = id.to_string();
+    let min_len = min_len.clamp(MINIMUM_ABBREV, hex.len());
+    let others: Vec<String> = ids_with_prefix(objects_dir, &hex[..min_len])?
+        .iter()
+        .filter(|other| *other != id)
+        .map(ObjectId::to_string)
+        .collect();
+    let len = (min_len..hex.len())
+        .find(|&len| !others.iter().any(|other| other[..len] == hex[..len]))
+        .unwrap_or(hex.len());
+    Ok(hex[..len].to_string())
+}
+
+/// `hint:` lines describing the objects `ids` an abbreviation is ambiguous between:
+/// tags first, then commits, trees and blobs, each by id.
+fn ambiguous_candidates(objects_dir: &Path, ids: &[ObjectId]) -> Result<String> {
+    let git_dir = objects_dir.parent().unwrap_or(objects_dir);
+    let min_len = Config::load_repository(git_dir)
+        .and_then(|config| abbrev_len(&config, hash_algorithm_in(objects_dir)?.hex_len()))
+        .unwrap_or(7);
+    let mut candidates = Vec::new();
+    for id in ids {
+        let abbrev = unique_abbrev(objects_dir, id, min_len)?;
+        let hash = id.to_string();
+        let (rank, description) = match Object::read_git_object_in(objects_dir, &hash) {
+            Ok(mut object) => {
+                let kind = object.kind.clone();
+                let data = object.read_all().unwrap_or_default();
+                describe_candidate(&abbrev, kind, &hash, &data)
+            }
+            Err(_) => (4, format!("{abbrev} [bad object]")),
+        };
+        candidates.push((rank, id, description));
+    }
+    candidates.sort();
+    Ok(candidates
+        .iter()
+        .map(|(_, _, description)| format!("hint:   {description}"))
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+/// How an ambiguous object is listed, and where among the others.
+fn describe_candidate(abbrev: &str, kind: ObjectKind, hash: &str, data: &[u8]) -> (u8, String) {
+    match kind {
+        ObjectKind::Tag => {
+            let text = String::from_utf8_lossy(data);
+            let headers = text.split("\n\n").next().unwrap_or("");
+            let header = |key: &str| {
+                headers
+                    .lines()
+                    .find_map(|line| line.strip_prefix(key)?.strip_prefix(' '))
+            };
+            let tagger = header("tagger").and_then(|tagger| Ident::parse(tagger).ok());
+            match (header("tag"), tagger) {
+                (Some(name), Some(tagger)) => {
+                    (0, format!("{abbrev} tag {} - {name}", tagger.short_date()))
+                }
+                _ => (0, format!("{abbrev} tag [bad object]")),
+            }
+        }
+        ObjectKind::Commit => match Commit::parse(hash, data) {
+            Ok(commit) => (
+                1,
+                format!(
+                    "{abbrev} commit {} - {}",
+                    commit.author.short_date(),
+                    commit.subject()
+                ),
+            ),
+            Err(_) => (1, format!("{abbrev} commit [bad object]")),
+        },
+        ObjectKind::Tree => (2, format!("{abbrev} tree")),
+        ObjectKind::Blob => (3, format!("{abbrev} blob")),
+    }
+}
+
+/// Whether `objects_dir` has the object `hash` (a full id), loose or packed.
+pub fn has_object(objects_dir: &Path, hash: &str) -> bool {
+    loose_object_path(objects_dir, hash).is_file()
+        || packs(objects_dir).is_ok_and(|packs| packs.contains(hash))
+}
+
+/// How many bytes the object `hash` (a full id) takes up in `objects_dir`: its entry
+/// in a pack, or its loose file if no pack has it. The empty tree counts as none, as
+/// git has it built in whether it is stored or not.
+pub fn disk_size(objects_dir: &Path, hash: &str) -> Result<u64> {
+    let id: ObjectId = hash.parse()?;
+    if id == id.algorithm().digest(b"tree 0\0") {
+        return Ok(0);
+    }
+    if let Some(size) = packs(objects_dir)?.disk_size(&id)? {
+        return Ok(size);
+    }
+    let path = loose_object_path(objects_dir, hash);
+    let metadata = path
+        .metadata()
+        .with_context(|| format!("object {hash} is missing"))?;
+    Ok(metadata.len())
+}
+
+/// The packs of each `objects` directory read so far, whose indexes are read once.
+static PACKS: OnceLock<Mutex<HashMap<PathBuf, Arc<PackOdb>>>> = OnceLock::new();
+
+/// The packs under `objects_dir`. Packs added after they were first looked up for a
+/// directory aren't seen, unless [`forget_packs`] is called.
+pub fn packs(objects_dir: &Path) -> Result<Arc<PackOdb>> {
+    let mut cache = PACKS.get_or_init(Mutex::default).lock().unwrap();
+    if let Some(packs) = cache.get(objects_dir) {
+        return Ok(packs.clone());
+    }
+    let packs = Arc::new(PackOdb::open(objects_dir)?);
+    cache.insert(objects_dir.to_path_buf(), packs.clone());
+    Ok(packs)
+}
+
+/// Have the packs under `objects_dir` looked up again, so that one just added is seen.
+pub fn forget_packs(objects_dir: &Path) {
+    if let Some(cache) = PACKS.get() {
+        cache.lock().unwrap().remove(objects_dir);
+    }
+}
+
+impl Object<()> {
+    /// Read the object named by the revision `name` (see
+    /// [`crate::rev_parse::resolve_revision`]) and peel it until an object of kind
+    /// `kind` is reached: tags are followed to their target and commits to their tree. Returns the peeled object's id along with the object.
+    pub fn peel_to(
+        repo: &Repository,
+        name: &str,
+        kind: ObjectKind,
+    ) -> Result<(String, Object<OdbReader>)> {
+        let mut hash = rev_parse(repo, name)?;
+        // Tags can point at tags, but a cycle is impossible without a hash collision;
+        // the bound only guards against absurdly long chains.
+        for _ in 0..32 {
+            let mut object = Object::read_git_object(repo, &hash)?;
+            if object.kind == kind {
+                return Ok((hash, object));
+            }
+            let header = match (&object.kind, &kind) {
+                (ObjectKind::Tag, _) => "object ",
+                (ObjectKind::Commit, ObjectKind::Tree) => "tree ",
+                (actual, _) => bail!(
+                    "object {} is a {}, which can't be peeled to a {}",
+                    hash,
+                    actual.to_str(),
+                    kind.to_str()
+                ),
+            };
+            let mut first_line = String::new();
+            object
+                .reader
+                .read_line(&mut first_line)
+                .context("failed to read object header line")?;
+            let Some(target) = first_line.trim_end().strip_prefix(header) else {
+                bail!(
+                    "{} {} doesn't start with an '{}' line",
+                    object.kind.to_str(),
+                    hash,
+                    header.trim_end()
+                );
+            };
+            hash = target.to_string();
+        }
+        bail!("too many levels of tags while peeling {name}")
+    }
+}
+
+impl<R: BufRead> Object<Take<R>> {
+    /// Read the whole body, checking it matches the declared size.
+    pub fn read_all(&mut self) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(self.expected_size as usize);
+        self.reader
+            .read_to_end(&mut data)
+            .context("failed to read object")?;
+        self.ensure_fully_read()?;
+        Ok(data)
+    }
+
+    /// Check that the body was fully consumed and that the stream ends exactly there.
+    /// Call this once done reading; it catches both truncated objects and objects
+    /// carrying data past their declared size.
+    pub fn ensure_fully_read(&mut self) -> Result<()> {
+        ensure!(
+            self.reader.limit() == 0,
+            "object is truncated: {} of {} bytes missing",
+            self.reader.limit(),
+            self.expected_size
+        );
+        let trailing = self
+            .reader
+            .get_mut()
+            .fill_buf()
+            .context("failed to read to the end of the object")?;
+        ensure!(
+            trailing.is_empty(),
+            "object has trailing data past its declared size of {} bytes",
+            self.expected_size
+        );
+        Ok(())
+    }
+}
+
+/// Hashes everything read through it and, once the inner reader hits EOF, checks the
+/// digest against the expected object id. A mismatch surfaces as an `InvalidData` error
+/// from the final read, so callers only need to read the stream to its end.
+pub struct VerifyingReader<R> {
+    reader: R,
+    check: Option<(Hasher, ObjectId)>,
+}
+
+impl<R> VerifyingReader<R> {
+    pub fn new(reader: R, expected_hash: Option<ObjectId>) -> Self {
+        Self {
+            reader,
+            check: expected_hash.map(|hash| (hash.algorithm().hasher(), hash)),
+        }
+    }
+}
+
+impl<R: Read> Read for VerifyingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        if n > 0 {
+            if let Some((hasher, _)) = &mut self.check {
+                hasher.update(&buf[..n]);
+            }
+        } else if let Some((hasher, expected)) = self.check.take() {
+            let actual = hasher.finish();
+            if actual != expected {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("object hash mismatch: expected {expected}, got {actual}"),
+                ));
+            }
+        }
+        Ok(n)
+    }
+}
@@ -1,12 +1,13 @@
+use crate::object_id::{HashAlgorithm, Hasher, ObjectId};
 use crate::object_read::{Object, ObjectKind};
+use crate::odb::{LooseOdb, Odb};
+use crate::repository::Repository;
 use anyhow::{Context, Result, anyhow};
 use flate2::Compression;
 use flate2::write::ZlibEncoder;
-use sha1::{Digest, Sha1};
-use std::fs::{File, create_dir_all, rename};
+use std::fs::File;
 use std::io::{Read, Write, copy};
 use std::path::Path;
-use tempfile::NamedTempFile;
 
 impl Object<()> {
     pub(crate) fn from_blob_file(path: impl AsRef<Path>) -> Result<Object<impl Read>> {
@@ -26,11 +27,13 @@ impl<R> Object<R>
 where
     R: Read,
 {
-    pub fn write(&mut self, writer: impl Write) -> Result<[u8; 20]> {
+    /// Write the object zlib-compressed to `writer` and return its id, its hash of
+    /// `algorithm`.
+    pub fn write(&mut self, algorithm: HashAlgorithm, writer: impl Write) -> Result<ObjectId> {
         let encoder = ZlibEncoder::new(writer, Compression::default());
         let mut hash_writer = HashWriter {
             writer: encoder,
-            hasher: Sha1::new(),
+            hasher: algorithm.hasher(),
         };
         write!(
             hash_writer,
@@ -40,28 +43,27 @@ where
         )?;
         copy(&mut self.reader, &mut hash_writer)?;
         let _compressed = hash_writer.writer.finish()?;
-        let hash = hash_writer.hasher.finalize();
-        Ok(hash.into())
+        Ok(hash_writer.hasher.finish())
     }
 
-    pub fn write_as_object(&mut self) -> Result<[u8; 20]> {
-        let mut tmp_file = NamedTempFile::new()?;
-        let hash = self.write(&mut tmp_file)?;
-        let hash_enc = hex::encode(hash);
-        create_dir_all(format!(".git/objects/{}", &hash_enc[..2]))
-            .context("creating git object directory")?;
-        rename(
-            tmp_file,
-            format!(".git/objects/{}/{}", &hash_enc[..2], &hash_enc[2..]),
-        )
-        .context("renaming object")?;
-        Ok(hash)
+    /// Store the object in `repo`: as a loose object, or in the store it was given
+    /// (see [`Repository::with_odb`]).
+    pub fn write_as_object(&mut self, repo: &Repository) -> Result<ObjectId> {
+        match repo.odb() {
+            Some(odb) => odb.write(self.kind.clone(), self.expected_size, &mut self.reader),
+            None => self.write_as_object_in(&repo.objects_dir()),
+        }
+    }
+
+    /// Like [`Object::write_as_object`], into the loose objects under `objects_dir`.
+    pub fn write_as_object_in(&mut self, objects_dir: &Path) -> Result<ObjectId> {
+        LooseOdb::new(objects_dir).write(self.kind.clone(), self.expected_size, &mut self.reader)
     }
 }
 
 struct HashWriter<W> {
     writer: W,
-    hasher: Sha1,
+    hasher: Hasher,
 }
 
 impl<W> Write for HashWriter<W>
@@ -0,0 +1,284 @@
+use crate::object_id::{HashAlgorithm, ObjectId};
+use crate::object_read::{Object, ObjectKind, max_object_size};
+use crate::repository::hash_algorithm_in;
+use crate::transfer::loose_object_path;
+use anyhow::{Context, Result, bail};
+use std::collections::HashMap;
+use std::fs::{create_dir_all, read_dir, rename};
+use std::io::{BufRead, Cursor, ErrorKind, Read, Take, sink};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tempfile::NamedTempFile;
+
+/// Reader over the body of an object read from an [`Odb`], bounded by its size.
+pub type OdbReader = Take<Box<dyn BufRead>>;
+
+/// A store of objects, read and written by id, so that the code working with objects
+/// doesn't depend on where they are kept.
+///
+/// [`LooseOdb`] is the repository's own `objects` directory (or another one, such as
+/// a push's quarantine); [`crate::pack::PackOdb`] is the packs in one;
+/// [`MemoryOdb`] keeps objects in memory only. A repository reads and writes its objects
+/// through one given to it with
+/// [`Repository::with_odb`](crate::repository::Repository::with_odb), and otherwise
+/// through its `objects` directory, loose objects first and then packs.
+pub trait Odb: Send + Sync {
+    /// Open the object with the full id `hash`.
+    fn read(&self, hash: &str) -> Result<Object<OdbReader>>;
+
+    /// Whether the store has the object with the full id `hash`.
+    fn contains(&self, hash: &str) -> bool;
+
+    /// Store an object of `kind` with the `size` bytes `content` yields, and return
+    /// its id. Storing an object that is already there changes nothing.
+    fn write(&self, kind: ObjectKind, size: u64, content: &mut dyn Read) -> Result<ObjectId>;
+
+    /// The ids of all objects in the store, in ascending order.
+    fn ids(&self) -> Result<Vec<ObjectId>>;
+
+    /// The hash function the store names its objects with.
+    fn algorithm(&self) -> Result<HashAlgorithm>;
+}
+
+/// An object as [`Repository::objects`](crate::repository::Repository::objects) lists
+/// it, without its content.
+pub struct ObjectInfo {
+    pub id: ObjectId,
+    pub kind: ObjectKind,
+    pub size: u64,
+}
+
+/// Loose objects under an `objects` directory: one zlib-compressed file per object,
+/// at `<first two hex digits>/<rest of the id>`.
+pub struct LooseOdb {
+    objects_dir: PathBuf,
+}
+
+impl LooseOdb {
+    pub fn new(objects_dir: &Path) -> LooseOdb {
+        LooseOdb {
+            objects_dir: objects_dir.to_path_buf(),
+        }
+    }
+}
+
+impl Odb for LooseOdb {
+    fn read(&self, hash: &str) -> Result<Object<OdbReader>> {
+        let object = Object::read_loose_object_in(&self.objects_dir, hash)?;
+        let limit = object.reader.limit();
+        let reader: Box<dyn BufRead> = Box::new(object.reader.into_inner());
+        Ok(Object {
+            reader: reader.take(limit),
+            kind: object.kind,
+            expected_size: object.expected_size,
+        })
+    }
+
+    fn contains(&self, hash: &str) -> bool {
+        loose_object_path(&self.objects_dir, hash).is_file()
+    }
+
+    /// Written to a temporary file first and then moved into place, so readers never
+    /// see a partial object.
+    fn write(&self, kind: ObjectKind, size: u64, content: &mut dyn Read) -> Result<ObjectId> {
+        let mut tmp_file = NamedTempFile::new_in(&self.objects_dir)?;
+        let mut object = Object {
+            reader: content,
+            kind,
+            expected_size: size,
+        };
+        let id = object.write(hash_algorithm_in(&self.objects_dir)?, &mut tmp_file)?;
+        let hash = id.to_string();
+        let dir = self.objects_dir.join(&hash[..2]);
+        create_dir_all(&dir).context("creating git object directory")?;
+        rename(tmp_file, dir.join(&hash[2..])).context("renaming object")?;
+        Ok(id)
+    }
+
+    /// Files under the fan-out directories whose names don't make up an object id,
+    /// such as temporary files left by an interrupted write, are skipped.
+    fn ids(&self) -> Result<Vec<ObjectId>> {
+        let mut ids = Vec::new();
+        let fanouts = match read_dir(&self.objects_dir) {
+            Ok(fanouts) => fanouts,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(ids),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("failed to read {}", self.objects_dir.display()));
+            }
+        };
+        for fanout in fanouts {
+            let fanout = fanout?;
+            let Ok(prefix) = fanout.file_name().into_string() else {
+                continue;
+            };
+            if prefix.len() != 2 || !fanout.file_type()?.is_dir() {
+                continue;
+            }
+            for object in read_dir(fanout.path())? {
+                let object = object?;
+                let Ok(rest) = object.file_name().into_string() else {
+                    continue;
+                };
+                if let Ok(id) = format!("{prefix}{rest}").parse() {
+                    ids.push(id);
+                }
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    fn algorithm(&self) -> Result<HashAlgorithm> {
+        hash_algorithm_in(&self.objects_dir)
+    }
+}
+
+/// Objects kept in memory and gone once it is dropped, for tests and for tools that
+/// work out objects without storing them in a repository.
+#[derive(Default)]
+pub struct MemoryOdb {
+    /// What objects are named with; SHA-1 unless set otherwise.
+    pub algorithm: HashAlgorithm,
+    objects: Mutex<HashMap<ObjectId, (ObjectKind, Vec<u8>)>>,
+}
+
+impl Odb for MemoryOdb {
+    fn read(&self, hash: &str) -> Result<Object<OdbReader>> {
+        let objects = self.objects.lock().unwrap();
+        let Some((kind, data)) = objects.get(&hash.parse::<ObjectId>()?) else {
+            bail!("object {hash} not found");
+        };
+        let reader: Box<dyn BufRead> = Box::new(Cursor::new(data.clone()));
+        Ok(Object {
+            reader: reader.take(data.len() as u64),
+            kind: kind.clone(),
+            expected_size: data.len() as u64,
+        })
+    }
+
+    fn contains(&self, hash: &str) -> bool {
+        hash.parse::<ObjectId>()
+            .is_ok_and(|id| self.objects.lock().unwrap().contains_key(&id))
+    }
+
+    fn write(&self, kind: ObjectKind, size: u64, content: &mut dyn Read) -> Result<ObjectId> {
+        if size > max_object_size()? {
+            bail!("object of {size} bytes exceeds the maximum object size");
+        }
+        let mut data = Vec::with_capacity(size as usize);
+        content
+            .take(size)
+            .read_to_end(&mut data)
+            .context("failed to read object content")?;
+        if data.len() as u64 != size {
+            bail!("object content is {} bytes, not {size}", data.len());
+        }
+        let id = Object {
+            reader: Cursor::new(&data),
+            kind: kind.clone(),
+            expected_size: size,
+        }
+        .write(self.algorithm, sink())?;
+        self.objects
+            .lock()
+            .unwrap()
+            .entry(id)
+            .or_insert((kind, data));
+        Ok(id)
+    }
+
+    fn ids(&self) -> Result<Vec<ObjectId>> {
+        let mut ids: Vec<ObjectId> = self.objects.lock().unwrap().keys().copied().collect();
+        ids.sort();
+        Ok(ids)
+    }
+
+    fn algorithm(&self) -> Result<HashAlgorithm> {
+        Ok(self.algorithm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commit::CommitBuilder;
+    use crate::commit_read::{Commit, ahead_behind};
+    use crate::config::Config;
+    use crate::ident::Ident;
+    use crate::repository::Repository;
+
+    fn write_blob(odb: &dyn Odb, data: &[u8]) -> ObjectId {
+        odb.write(ObjectKind::Blob, data.len() as u64, &mut &data[..])
+            .unwrap()
+    }
+
+    #[test]
+    fn memory_odb_names_objects_as_git_does() {
+        let odb = MemoryOdb::default();
+        let id = write_blob(&odb, b"hello\n");
+        assert_eq!(id.to_string(), "ce013625030ba8dba906f756967f9e9ca394464a");
+        assert!(odb.contains(&id.to_string()));
+        assert_eq!(
+            odb.write(ObjectKind::Blob, 6, &mut &b"hello\n"[..])
+                .unwrap(),
+            id
+        );
+        assert_eq!(odb.ids().unwrap(), vec![id]);
+
+        let mut object = odb.read(&id.to_string()).unwrap();
+        assert_eq!(object.kind, ObjectKind::Blob);
+        assert_eq!(object.read_all().unwrap(), b"hello\n");
+    }
+
+    #[test]
+    fn memory_odb_refuses_content_of_the_wrong_size() {
+        let odb = MemoryOdb::default();
+        assert!(odb.write(ObjectKind::Blob, 10, &mut &b"short"[..]).is_err());
+        assert!(odb.ids().unwrap().is_empty());
+        assert!(
+            odb.read("ce013625030ba8dba906f756967f9e9ca394464a")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn repository_keeps_its_objects_in_a_memory_odb() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::at(dir.path()).with_odb(MemoryOdb::default());
+        let odb = repo.odb().unwrap();
+
+        let blob = write_blob(odb, b"hello\n");
+        let mut tree = b"100644 hello\0".to_vec();
+        tree.extend(blob.as_bytes());
+        let tree = odb
+            .write(ObjectKind::Tree, tree.len() as u64, &mut tree.as_slice())
+            .unwrap();
+        let ident = Ident::parse("A U Thor <author@example.com> 1112911993 -0700").unwrap();
+        let commit = |message: &str, parents: &[ObjectId]| {
+            parents
+                .iter()
+                .fold(CommitBuilder::new(tree.to_string()), |builder, parent| {
+                    builder.parent(parent.to_string())
+                })
+                .author(ident.clone())
+                .committer(ident.clone())
+                .message(message)
+                .write(&repo, &Config::default())
+                .unwrap()
+        };
+        let first = commit("first\n", &[]);
+        let second = commit("second\n", &[first]);
+
+        let read = Commit::read(&repo, &second.to_string()[..7]).unwrap();
+        assert_eq!(read.hash, second.to_string());
+        assert_eq!(read.parents, vec![first.to_string()]);
+        assert_eq!(read.tree, tree.to_string());
+        assert_eq!(
+            ahead_behind(&repo, &second.to_string(), &first.to_string()).unwrap(),
+            (1, 0)
+        );
+        assert_eq!(repo.objects().unwrap().count(), 4);
+        assert!(!repo.objects_dir().exists());
+    }
+}
@@ -0,0 +1,752 @@
+use crate::config::Config;
+use crate::delta::{apply_delta, delta_sizes};
+use crate::object_id::{HashAlgorithm, ObjectId};
+use crate::object_read::{Object, ObjectKind, max_object_size};
+use crate::odb::{Odb, OdbReader};
+use crate::repository::hash_algorithm_in;
+use anyhow::{Context, Result, bail, ensure};
+use flate2::Crc;
+use flate2::read::ZlibDecoder;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, read, read_dir};
+use std::io::{BufRead, BufReader, Cursor, ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Longest chain of deltas followed to reach a base object. Offset deltas always point
+/// to earlier entries, but reference deltas could form a cycle in a corrupt pack.
+const MAX_DELTA_CHAIN: usize = 10_000;
+
+/// A packfile, `objects/pack/pack-<id>.pack`, and its index `pack-<id>.idx`: many
+/// objects zlib-compressed one after the other, each stored whole or as a delta
+/// against another, with the index mapping ids (sorted, behind a fan-out table of
+/// how many start with each first byte) to where their entries begin.
+///
+/// The index is read into memory; entries are read from the pack when asked for.
+/// Ids, and the trailer both files end in, are hashes of the repository's hash
+/// function; the trailer is of the file's content and, for the index, of the pack
+/// it was made for too. Those of the index are checked when it is read, and the
+/// pack's trailer against the index's record of it, but the pack isn't hashed whole:
+/// each entry read is checked against the CRC32 a version 2 index records for it
+/// instead, so that corruption is reported as the entry it is in.
+pub struct Pack {
+    pack_path: PathBuf,
+    index: Vec<u8>,
+    /// Index version: 1 has `<offset><id>` records after the fan-out table, 2 has
+    /// separate tables of ids, CRCs and offsets, with 64-bit offsets at the end.
+    version: u32,
+    algorithm: HashAlgorithm,
+    count: usize,
+    /// The size of the pack, once [`Pack::open`] has checked it.
+    pack_size: u64,
+    /// The offset of each entry, in pack order, with its CRC32; made when first needed
+    /// to find where an entry ends.
+    by_offset: OnceLock<Vec<(u64, Option<u32>)>>,
+}
+
+/// What a pack entry holds: an object's data, or a delta that rebuilds it from the
+/// entry at an earlier offset in the same pack or from the object with some id.
+enum Entry {
+    Base(ObjectKind, Vec<u8>),
+    OffsetDelta(u64, Vec<u8>),
+    RefDelta(ObjectId, Vec<u8>),
+}
+
+/// Where a delta's base is, as [`Pack::entry_header`] reads it.
+enum Base {
+    Offset(u64),
+    Id(ObjectId),
+}
+
+impl Pack {
+    /// Read the index at `index_path`, whose ids are of `algorithm`; the pack is the
+    /// file next to it ending in `.pack`.
+    pub fn open(index_path: &Path, algorithm: HashAlgorithm) -> Result<Pack> {
+        let index =
+            read(index_path).with_context(|| format!("failed to read {}", index_path.display()))?;
+        let mut pack = Pack::from_index(index_path, index, algorithm)?;
+        pack.check_pack()?;
+        Ok(pack)
+    }
+
+    /// Check the pack's header and that its trailer is the one the index was made for.
+    fn check_pack(&mut self) -> Result<()> {
+        let name = self.pack_path.display();
+        let mut pack = self.open_pack()?;
+        let size = pack.get_ref().metadata()?.len();
+        let hash_len = self.algorithm.len();
+        let mut header = [0; 12];
+        ensure!(
+            size >= 12 + hash_len as u64
+                && pack.read_exact(&mut header).is_ok()
+                && header.starts_with(b"PACK"),
+            "{name} is not a packfile"
+        );
+        let version = be32(&header, 4);
+        ensure!(
+            matches!(version, 2 | 3),
+            "{name} is pack version {version}, which we do not understand"
+        );
+        let count = be32(&header, 8) as usize;
+        ensure!(
+            count == self.count,
+            "{name} has {count} objects, but its index has {}",
+            self.count
+        );
+        let mut trailer = vec![0; hash_len];
+        pack.seek(SeekFrom::End(-(hash_len as i64)))?;
+        pack.read_exact(&mut trailer)?;
+        let end = self.index.len();
+        let expected = &self.index[end - 2 * hash_len..end - hash_len];
+        ensure!(
+            trailer == expected,
+            "{name} doesn't match its index: its checksum is {}, but the index was made for {}",
+            hex::encode(trailer),
+            hex::encode(expected)
+        );
+        self.pack_size = size;
+        Ok(())
+    }
+
+    /// The pack whose index, named `index_path` in messages, is `index`.
+    pub fn from_index(index_path: &Path, index: Vec<u8>, algorithm: HashAlgorithm) -> Result<Pack> {
+        let hash_len = algorithm.len();
+        let (version, fanout) = match index.get(..8) {
+            Some([0xff, b't', b'O', b'c', version @ ..]) => {
+                (u32::from_be_bytes(version.try_into().unwrap()), 8)
+            }
+            _ => (1, 0),
+        };
+        ensure!(
+            matches!(version, 1 | 2),
+            "{} is index version {version}, which we do not understand",
+            index_path.display()
+        );
+        ensure!(
+            index.len() >= fanout + 256 * 4 + 2 * hash_len,
+            "{} is too short to be a pack index",
+            index_path.display()
+        );
+        // Each fanout entry counts the ids up to its first byte, so they never decrease
+        // and the last is the number of objects; lookups rely on both.
+        ensure!(
+            (0..255).all(
+                |byte| be32(&index, fanout + byte * 4) <= be32(&index, fanout + (byte + 1) * 4)
+            ),
+            "{} is a non-monotonic pack index",
+            index_path.display()
+        );
+        let count = be32(&index, fanout + 255 * 4) as usize;
+        let records = match version {
+            1 => count * (4 + hash_len),
+            _ => count * (hash_len + 8),
+        };
+        ensure!(
+            index.len() >= fanout + 256 * 4 + records + 2 * hash_len,
+            "{} is too short for its {count} objects",
+            index_path.display()
+        );
+        let (content, checksum) = index.split_at(index.len() - hash_len);
+        let actual = algorithm.digest(content);
+        ensure!(
+            actual.as_bytes() == checksum,
+            "{} is corrupt: its checksum is {}, but its content hashes to {actual}",
+            index_path.display(),
+            hex::encode(checksum)
+        );
+        Ok(Pack {
+            pack_path: index_path.with_extension("pack"),
+            index,
+            version,
+            algorithm,
+            count,
+            pack_size: 0,
+            by_offset: OnceLock::new(),
+        })
+    }
+
+    fn fanout(&self, byte: usize) -> usize {
+        let start = if self.version == 1 { 0 } else { 8 };
+        be32(&self.index, start + byte * 4) as usize
+    }
+
+    /// The id of the `n`th object, in ascending order of ids.
+    fn id_at(&self, n: usize) -> &[u8] {
+        let hash_len = self.algorithm.len();
+        let start = match self.version {
+            1 => 256 * 4 + n * (4 + hash_len) + 4,
+            _ => 8 + 256 * 4 + n * hash_len,
+        };
+        &self.index[start..start + hash_len]
+    }
+
+    fn object_id_at(&self, n: usize) -> ObjectId {
+        ObjectId::from_bytes(self.algorithm, self.id_at(n)).unwrap()
+    }
+
+    /// Where the entry of the `n`th object starts in the pack.
+    fn offset_at(&self, n: usize) -> Result<u64> {
+        let hash_len = self.algorithm.len();
+        if self.version == 1 {
+            return Ok(u64::from(be32(&self.index, 256 * 4 + n * (4 + hash_len))));
+        }
+        let offsets = 8 + 256 * 4 + self.count * (hash_len + 4);
+        let offset = be32(&self.index, offsets + n * 4);
+        if offset & 0x8000_0000 == 0 {
+            return Ok(u64::from(offset));
+        }
+        // The rest of the word is an index into the table of 64-bit offsets.
+        let large = offsets + self.count * 4 + (offset & 0x7fff_ffff) as usize * 8;
+        let Some(bytes) = self.index.get(large..large + 8) else {
+            bail!("pack index has a bad 64-bit offset");
+        };
+        Ok(u64::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// The CRC32 of the compressed entry of the `n`th object, which only version 2
+    /// indexes record.
+    fn crc_at(&self, n: usize) -> Option<u32> {
+        let crcs = 8 + 256 * 4 + self.count * self.algorithm.len();
+        (self.version == 2).then(|| be32(&self.index, crcs + n * 4))
+    }
+
+    /// Every object of the index as `(offset, id, CRC32)`, in ascending order of ids.
+    pub fn index_entries(&self) -> Result<Vec<(u64, ObjectId, Option<u32>)>> {
+        (0..self.count)
+            .map(|n| Ok((self.offset_at(n)?, self.object_id_at(n), self.crc_at(n))))
+            .collect()
+    }
+
+    /// The offset of the entry for `id`, if the pack has it.
+    pub fn find(&self, id: &ObjectId) -> Result<Option<u64>> {
+        let first = usize::from(id.as_bytes()[0]);
+        let (mut low, mut high) = match first {
+            0 => (0, self.fanout(0)),
+            _ => (self.fanout(first - 1), self.fanout(first)),
+        };
+        while low < high {
+            let middle = (low + high) / 2;
+            match self.id_at(middle).cmp(id.as_bytes()) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return self.offset_at(middle).map(Some),
+            }
+        }
+        Ok(None)
+    }
+
+    /// The pack's checksum, as its index records it.
+    pub fn checksum(&self) -> ObjectId {
+        let hash_len = self.algorithm.len();
+        let end = self.index.len() - hash_len;
+        ObjectId::from_bytes(self.algorithm, &self.index[end - hash_len..end]).unwrap()
+    }
+
+    /// The ids of the objects in the pack, in ascending order.
+    pub fn ids(&self) -> impl Iterator<Item = ObjectId> + '_ {
+        (0..self.count).map(|n| self.object_id_at(n))
+    }
+
+    /// The ids of the objects in the pack, in the order their entries are in it.
+    pub fn ids_in_pack_order(&self) -> Result<Vec<ObjectId>> {
+        let mut entries = (0..self.count)
+            .map(|n| Ok((self.offset_at(n)?, n)))
+            .collect::<Result<Vec<_>>>()?;
+        entries.sort_unstable();
+        Ok(entries
+            .into_iter()
+            .map(|(_, n)| self.object_id_at(n))
+            .collect())
+    }
+
+    /// The ids in the pack starting with the hex digits `prefix`.
+    pub fn ids_with_prefix(&self, prefix: &str) -> Vec<ObjectId> {
+        let Some(Ok(first)) = prefix.get(..2).map(|first| u8::from_str_radix(first, 16)) else {
+            return Vec::new();
+        };
+        let first = usize::from(first);
+        let start = match first {
+            0 => 0,
+            _ => self.fanout(first - 1),
+        };
+        (start..self.fanout(first))
+            .map(|n| self.object_id_at(n))
+            .filter(|id| id.to_string().starts_with(prefix))
+            .collect()
+    }
+
+    fn open_pack(&self) -> Result<BufReader<File>> {
+        let file = File::open(&self.pack_path)
+            .with_context(|| format!("failed to open {}", self.pack_path.display()))?;
+        Ok(BufReader::new(file))
+    }
+
+    /// How messages name the entry at `offset`.
+    fn entry_name(&self, offset: u64) -> String {
+        format!("entry at offset {offset} of {}", self.pack_path.display())
+    }
+
+    /// Read the type and size of the entry at `offset`, which `pack` is at, and, for a
+    /// delta, where its base is, leaving `pack` at the start of the compressed data.
+    fn entry_header(&self, pack: &mut impl Read, offset: u64) -> Result<(u8, u64, Option<Base>)> {
+        let name = || self.entry_name(offset);
+        let mut byte = read_byte(pack)?;
+        let kind = (byte >> 4) & 0x07;
+        let mut size = u64::from(byte & 0x0f);
+        let mut shift = 4;
+        while byte & 0x80 != 0 {
+            ensure!(shift < 64, "{} has a bad size", name());
+            byte = read_byte(pack)?;
+            size |= u64::from(byte & 0x7f) << shift;
+            shift += 7;
+        }
+        let base = match kind {
+            // The distance back to the base, in a base-128 encoding where each
+            // continuation adds one so that no two encodings are equal.
+            6 => {
+                let mut byte = read_byte(pack)?;
+                let mut distance = u64::from(byte & 0x7f);
+                while byte & 0x80 != 0 {
+                    ensure!(distance < 1 << 56, "{} has a bad base", name());
+                    byte = read_byte(pack)?;
+                    distance = ((distance + 1) << 7) | u64::from(byte & 0x7f);
+                }
+                let Some(base) = offset.checked_sub(distance).filter(|_| distance > 0) else {
+                    bail!("{} has a base outside the pack", name());
+                };
+                Some(Base::Offset(base))
+            }
+            7 => {
+                let mut id = vec![0; self.algorithm.len()];
+                pack.read_exact(&mut id)?;
+                Some(Base::Id(ObjectId::from_bytes(self.algorithm, &id)?))
+            }
+            1..=4 => None,
+            _ => bail!("{} has unknown type {kind}", name()),
+        };
+        Ok((kind, size, base))
+    }
+
+    /// The entries in pack order, with their CRC32s.
+    fn by_offset(&self) -> Result<&[(u64, Option<u32>)]> {
+        if let Some(entries) = self.by_offset.get() {
+            return Ok(entries);
+        }
+        let mut entries = (0..self.count)
+            .map(|n| Ok((self.offset_at(n)?, self.crc_at(n))))
+            .collect::<Result<Vec<_>>>()?;
+        entries.sort_unstable();
+        Ok(self.by_offset.get_or_init(|| entries))
+    }
+
+    /// Where the entry at `offset` is in pack order, and where the next one starts (or
+    /// the trailer).
+    fn entry_end(&self, offset: u64) -> Result<(usize, u64)> {
+        let entries = self.by_offset()?;
+        let Ok(n) = entries.binary_search_by_key(&offset, |&(offset, _)| offset) else {
+            bail!(
+                "no entry of {} starts at offset {offset}",
+                self.pack_path.display()
+            );
+        };
+        let end = match entries.get(n + 1) {
+            Some(&(next, _)) => next,
+            None => self.pack_size.saturating_sub(self.algorithm.len() as u64),
+        };
+        ensure!(end > offset, "{} is empty", self.entry_name(offset));
+        Ok((n, end))
+    }
+
+    /// The bytes of the entry at `offset`, up to where the next one starts (or the
+    /// trailer), checked against the entry's CRC32.
+    fn raw_entry(&self, offset: u64) -> Result<Vec<u8>> {
+        let (n, end) = self.entry_end(offset)?;
+        let entries = self.by_offset()?;
+        let mut pack = self.open_pack()?;
+        pack.seek(SeekFrom::Start(offset))?;
+        let mut raw = vec![0; (end - offset) as usize];
+        pack.read_exact(&mut raw)
+            .with_context(|| format!("{} is truncated", self.entry_name(offset)))?;
+        if let Some(expected) = entries[n].1 {
+            let mut crc = Crc::new();
+            crc.update(&raw);
+            ensure!(
+                crc.sum() == expected,
+                "{} is corrupt: its CRC32 is {:08x}, but the index records {expected:08x}",
+                self.entry_name(offset),
+                crc.sum()
+            );
+        }
+        Ok(raw)
+    }
+
+    fn read_entry(&self, offset: u64) -> Result<Entry> {
+        let raw = self.raw_entry(offset)?;
+        let mut pack = raw.as_slice();
+        let (kind, size, base) = self.entry_header(&mut pack, offset)?;
+        let max_size = max_object_size()?;
+        ensure!(
+            size <= max_size,
+            "{} is {size} bytes, exceeding the maximum object size of {max_size} bytes",
+            self.entry_name(offset)
+        );
+        let mut data = Vec::with_capacity(size as usize);
+        ZlibDecoder::new(pack)
+            .take(size)
+            .read_to_end(&mut data)
+            .with_context(|| format!("failed to inflate {}", self.entry_name(offset)))?;
+        ensure!(
+            data.len() as u64 == size,
+            "{} is truncated",
+            self.entry_name(offset)
+        );
+        Ok(match base {
+            None => Entry::Base(entry_kind(kind), data),
+            Some(Base::Offset(base)) => Entry::OffsetDelta(base, data),
+            Some(Base::Id(id)) => Entry::RefDelta(id, data),
+        })
+    }
+
+    /// The size of the object an entry rebuilds, from the start of its delta.
+    fn delta_result_size(&self, pack: BufReader<File>, offset: u64) -> Result<u64> {
+        // Two base-128 sizes of at most ten bytes each.
+        let mut start = Vec::new();
+        ZlibDecoder::new(pack)
+            .take(20)
+            .read_to_end(&mut start)
+            .with_context(|| format!("failed to inflate {}", self.entry_name(offset)))?;
+        let (_, result_size) = delta_sizes(&start)?;
+        Ok(result_size as u64)
+    }
+}
+
+/// The objects in the packs of an `objects` directory, which they are only ever read
+/// from. A delta whose base is in none of them has it read from the directory's
+/// loose objects.
+pub struct PackOdb {
+    objects_dir: PathBuf,
+    algorithm: HashAlgorithm,
+    packs: Vec<Pack>,
+    delta_bases: Mutex<DeltaBaseCache>,
+}
+
+impl PackOdb {
+    /// The packs in `objects_dir/pack`: every `.idx` file that has a `.pack` next to
+    /// it, newest first, as git looks them up. There are none if the directory is
+    /// missing.
+    pub fn open(objects_dir: &Path) -> Result<PackOdb> {
+        let algorithm = hash_algorithm_in(objects_dir)?;
+        let dir = objects_dir.join("pack");
+        let mut indexes = Vec::new();
+        match read_dir(&dir) {
+            Ok(entries) => {
+                for entry in entries {
+                    let path = entry?.path();
+                    if path.extension().is_some_and(|ext| ext == "idx")
+                        && path.with_extension("pack").is_file()
+                    {
+                        let modified = path.metadata()?.modified()?;
+                        indexes.push((modified, path));
+                    }
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", dir.display())),
+        }
+        indexes.sort_by(|a, b| b.cmp(a));
+        let packs = indexes
+            .iter()
+            .map(|(_, path)| Pack::open(path, algorithm))
+            .collect::<Result<_>>()?;
+        let config = match objects_dir.parent() {
+            Some(git_dir) => Config::load_repository(git_dir)?,
+            None => Config::default(),
+        };
+        let limit = config
+            .get_size("core.deltaBaseCacheLimit")?
+            .unwrap_or(DEFAULT_DELTA_BASE_CACHE_LIMIT);
+        Ok(PackOdb {
+            objects_dir: objects_dir.to_path_buf(),
+            algorithm,
+            packs,
+            delta_bases: Mutex::new(DeltaBaseCache::new(limit)),
+        })
+    }
+
+    /// Which of the packs has the object `id`, and the offset of its entry there.
+    fn locate(&self, id: &ObjectId) -> Result<Option<EntryKey>> {
+        for (n, pack) in self.packs.iter().enumerate() {
+            if let Some(offset) = pack.find(id)? {
+                return Ok(Some((n, offset)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The ids of the packed objects, pack by pack in the order they are looked up in,
+    /// and within each pack in the order its entries are in. An object in more than one
+    /// pack is listed each time.
+    pub fn ids_in_pack_order(&self) -> Result<Vec<ObjectId>> {
+        let mut ids = Vec::new();
+        for pack in &self.packs {
+            ids.extend(pack.ids_in_pack_order()?);
+        }
+        Ok(ids)
+    }
+
+    /// The full ids of the packed objects starting with the hex digits `prefix`.
+    pub fn ids_with_prefix(&self, prefix: &str) -> Vec<ObjectId> {
+        let mut ids: Vec<ObjectId> = self
+            .packs
+            .iter()
+            .flat_map(|pack| pack.ids_with_prefix(prefix))
+            .collect();
+        ids.sort();
+        ids.dedup();
+        ids
+    }
+
+    /// The kind and data of the object `id`, with its deltas applied; `None` if no
+    /// pack has it.
+    ///
+    /// The chain of deltas is followed until an entry whose object is in the cache of
+    /// delta bases, and every base rebuilt on the way back is cached in turn, so that
+    /// objects deltified against the same bases share the work of rebuilding them.
+    pub fn read_object(&self, id: &ObjectId) -> Result<Option<(ObjectKind, Vec<u8>)>> {
+        let Some((mut pack, mut offset)) = self.locate(id)? else {
+            return Ok(None);
+        };
+        let mut deltas = Vec::new();
+        let (kind, mut data, mut key) = loop {
+            ensure!(
+                deltas.len() < MAX_DELTA_CHAIN,
+                "delta chain of {id} is too long"
+            );
+            let key = (pack, offset);
+            if let Some((kind, data)) = self.delta_bases.lock().unwrap().get(key) {
+                break (kind, data, Some(key));
+            }
+            match self.packs[pack].read_entry(offset)? {
+                Entry::Base(kind, data) => break (kind, Arc::new(data), Some(key)),
+                Entry::OffsetDelta(base, delta) => {
+                    deltas.push((key, delta));
+                    offset = base;
+                }
+                Entry::RefDelta(base, delta) => {
+                    deltas.push((key, delta));
+                    match self.locate(&base)? {
+                        Some(found) => (pack, offset) = found,
+                        None => {
+                            let (kind, data) = self.read_loose_base(&base)?;
+                            break (kind, Arc::new(data), None);
+                        }
+                    }
+                }
+            }
+        };
+        let max_size = max_object_size()?;
+        for (delta_key, delta) in deltas.iter().rev() {
+            if let Some(key) = key {
+                self.delta_bases
+                    .lock()
+                    .unwrap()
+                    .insert(key, kind.clone(), data.clone());
+            }
+            let (_, result_size) = delta_sizes(delta)?;
+            ensure!(
+                result_size as u64 <= max_size,
+                "object {id} is {result_size} bytes, exceeding the maximum object size of \
+                 {max_size} bytes"
+            );
+            data =
+                Arc::new(apply_delta(&data, delta).with_context(|| format!("bad delta in {id}"))?);
+            key = Some(*delta_key);
+        }
+        let data = Arc::try_unwrap(data).unwrap_or_else(|data| data.to_vec());
+        Ok(Some((kind, data)))
+    }
+
+    /// The size of the object `id`, from the header of its own entry alone (and the
+    /// start of its delta); `None` if no pack has it.
+    pub fn read_size(&self, id: &ObjectId) -> Result<Option<u64>> {
+        let Some((pack, offset)) = self.locate(id)? else {
+            return Ok(None);
+        };
+        let pack = &self.packs[pack];
+        let mut reader = pack.open_pack()?;
+        reader.seek(SeekFrom::Start(offset))?;
+        match pack.entry_header(&mut reader, offset)? {
+            (_, size, None) => Ok(Some(size)),
+            (_, _, Some(_)) => pack.delta_result_size(reader, offset).map(Some),
+        }
+    }
+
+    /// The kind and size of the object `id` without rebuilding it: the kind is that
+    /// of the base at the end of its delta chain and the size the one its own delta
+    /// starts with. `None` if no pack has it.
+    pub fn read_header(&self, id: &ObjectId) -> Result<Option<(ObjectKind, u64)>> {
+        let Some((mut pack, mut offset)) = self.locate(id)? else {
+            return Ok(None);
+        };
+        let mut size = None;
+        for _ in 0..MAX_DELTA_CHAIN {
+            let current = &self.packs[pack];
+            let mut reader = current.open_pack()?;
+            reader.seek(SeekFrom::Start(offset))?;
+            let (kind, entry_size, base) = current.entry_header(&mut reader, offset)?;
+            let Some(base) = base else {
+                return Ok(Some((entry_kind(kind), size.unwrap_or(entry_size))));
+            };
+            if size.is_none() {
+                size = Some(current.delta_result_size(reader, offset)?);
+            }
+            match base {
+                Base::Offset(base) => offset = base,
+                Base::Id(base) => match self.locate(&base)? {
+                    Some(found) => (pack, offset) = found,
+                    None => {
+                        let (kind, _) = self.read_loose_base(&base)?;
+                        return Ok(Some((kind, size.unwrap_or_default())));
+                    }
+                },
+            }
+        }
+        bail!("delta chain of {id} is too long")
+    }
+
+    /// How many bytes the entry of the object `id` takes up in its pack, header and
+    /// delta base included; `None` if no pack has it.
+    pub fn disk_size(&self, id: &ObjectId) -> Result<Option<u64>> {
+        let Some((pack, offset)) = self.locate(id)? else {
+            return Ok(None);
+        };
+        let (_, end) = self.packs[pack].entry_end(offset)?;
+        Ok(Some(end - offset))
+    }
+
+    fn read_loose_base(&self, id: &ObjectId) -> Result<(ObjectKind, Vec<u8>)> {
+        let hash = id.to_string();
+        let mut object = Object::read_loose_object_in(&self.objects_dir, &hash)
+            .with_context(|| format!("delta base {hash} is missing"))?;
+        let data = object.read_all()?;
+        Ok((object.kind, data))
+    }
+}
+
+/// An entry of one of a [`PackOdb`]'s packs: the pack's place in its list, and the
+/// entry's offset in the pack.
+type EntryKey = (usize, u64);
+
+/// How many bytes of delta bases a [`PackOdb`] keeps unless `core.deltaBaseCacheLimit`
+/// says otherwise, the same as git.
+const DEFAULT_DELTA_BASE_CACHE_LIMIT: u64 = 96 << 20;
+
+/// Objects recently rebuilt as the base of a delta, by the pack and offset of their
+/// entry. Reading a tree's worth of objects from a pack goes through the same bases
+/// again and again, so keeping them saves inflating and reapplying the deltas below
+/// them each time. Once they add up to more than the limit, the least recently used
+/// are dropped.
+struct DeltaBaseCache {
+    limit: u64,
+    size: u64,
+    /// Counts uses, so that a smaller tick is an older one.
+    tick: u64,
+    entries: HashMap<EntryKey, (u64, ObjectKind, Arc<Vec<u8>>)>,
+    by_use: BTreeMap<u64, EntryKey>,
+}
+
+impl DeltaBaseCache {
+    fn new(limit: u64) -> DeltaBaseCache {
+        DeltaBaseCache {
+            limit,
+            size: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            by_use: BTreeMap::new(),
+        }
+    }
+
+    fn get(&mut self, key: EntryKey) -> Option<(ObjectKind, Arc<Vec<u8>>)> {
+        let (tick, kind, data) = self.entries.get_mut(&key)?;
+        self.by_use.remove(tick);
+        self.tick += 1;
+        *tick = self.tick;
+        self.by_use.insert(self.tick, key);
+        Some((kind.clone(), data.clone()))
+    }
+
+    fn insert(&mut self, key: EntryKey, kind: ObjectKind, data: Arc<Vec<u8>>) {
+        let size = data.len() as u64;
+        if size > self.limit || self.entries.contains_key(&key) {
+            return;
+        }
+        self.tick += 1;
+        self.size += size;
+        self.entries.insert(key, (self.tick, kind, data));
+        self.by_use.insert(self.tick, key);
+        while self.size > self.limit {
+            let Some((_, oldest)) = self.by_use.pop_first() else {
+                break;
+            };
+            if let Some((_, _, data)) = self.entries.remove(&oldest) {
+                self.size -= data.len() as u64;
+            }
+        }
+    }
+}
+
+impl Odb for PackOdb {
+    fn read(&self, hash: &str) -> Result<Object<OdbReader>> {
+        let Some((kind, data)) = self.read_object(&hash.parse()?)? else {
+            bail!("object {hash} not found");
+        };
+        let size = data.len() as u64;
+        let reader: Box<dyn BufRead> = Box::new(Cursor::new(data));
+        Ok(Object {
+            reader: reader.take(size),
+            kind,
+            expected_size: size,
+        })
+    }
+
+    fn contains(&self, hash: &str) -> bool {
+        hash.parse()
+            .is_ok_and(|id| self.locate(&id).is_ok_and(|found| found.is_some()))
+    }
+
+    fn write(&self, _kind: ObjectKind, _size: u64, _content: &mut dyn Read) -> Result<ObjectId> {
+        bail!("packs can't be written to; objects are written loose")
+    }
+
+    fn ids(&self) -> Result<Vec<ObjectId>> {
+        let mut ids: Vec<ObjectId> = self.packs.iter().flat_map(Pack::ids).collect();
+        ids.sort();
+        ids.dedup();
+        Ok(ids)
+    }
+
+    fn algorithm(&self) -> Result<HashAlgorithm> {
+        Ok(self.algorithm)
+    }
+}
+
+pub(crate) fn entry_kind(kind: u8) -> ObjectKind {
+    match kind {
+        1 => ObjectKind::Commit,
+        2 => ObjectKind::Tree,
+        3 => ObjectKind::Blob,
+        _ => ObjectKind::Tag,
+    }
+}
+
+pub(crate) fn be32(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+pub(crate) fn read_byte(reader: &mut impl Read) -> Result<u8> {
+    let mut byte = [0];
+    reader
+        .read_exact(&mut byte)
+        .context("pack entry is truncated")?;
+    Ok(byte[0])
+}
@@ -0,0 +1,155 @@
+use crate::config::Config;
+use crate::index_pack::{IndexEntry, write_index, write_pack_files};
+use crate::object_id::ObjectId;
+use crate::object_read::Object;
+use crate::pack_write::{
+    DEFAULT_DEPTH, DEFAULT_WINDOW, PackObject, find_deltas, write_order, write_pack,
+};
+use crate::repository::Repository;
+use crate::rev_list::{RevListOptions, list_revisions};
+use anyhow::{Context, Result, bail};
+use std::collections::HashSet;
+use std::io::{BufRead, stdin};
+use std::path::Path;
+
+/// How `git pack-objects` makes its pack.
+#[derive(Debug, Clone, Default)]
+pub struct PackObjectsOptions {
+    /// Read revisions from stdin instead of object ids, packing what
+    /// `git rev-list --objects` lists for them (`--revs`).
+    pub revs: bool,
+    /// Write the pack to stdout instead of to files (`--stdout`).
+    pub stdout: bool,
+    /// How many objects each is tried as a delta against (`--window`), instead of
+    /// `pack.window` or 10.
+    pub window: Option<usize>,
+    /// How long chains of deltas may get (`--depth`), instead of `pack.depth` or 50.
+    pub depth: Option<usize>,
+}
+
+/// `git pack-objects [--revs] [--window=<n>] [--depth=<n>] (--stdout | <base-name>)`:
+/// pack the objects named on stdin, one per line as `<id> [<path>]` (the way
+/// `git rev-list --objects` lists them), into `<base-name>-<checksum>.pack` with its
+/// `.idx`, or with `stdout` only build the pack, for the caller to write there.
+/// Returns the pack and its checksum.
+///
+/// Objects are stored as deltas against each other where that is much smaller, bases
+/// found as [`find_deltas`] does, and written in the order they were listed except
+/// that each base comes before its deltas, which are then offset deltas. The paths
+/// only help find bases. Deltas already in the repository's packs aren't reused: every
+/// delta is computed afresh.
+pub fn git_pack_objects(
+    repo: &Repository,
+    config: &Config,
+    base_name: Option<&str>,
+    options: &PackObjectsOptions,
+) -> Result<(Vec<u8>, ObjectId)> {
+    let mut lines = Vec::new();
+    for line in stdin().lock().lines() {
+        lines.push(line.context("failed to read from stdin")?);
+    }
+    let listed = match options.revs {
+        true => list_stdin_revisions(repo, &lines)?,
+        false => lines
+            .iter()
+            .map(|line| match line.split_once(' ') {
+                Some((id, path)) => (id.to_string(), Some(path.to_string())),
+                None => (line.clone(), None),
+            })
+            .collect(),
+    };
+
+    let (pack, entries, checksum) = build_pack(repo, config, listed, options)?;
+
+    if options.stdout {
+        return Ok((pack, checksum));
+    }
+    let Some(base_name) = base_name else {
+        bail!("usage: git pack-objects [<options>] <base-name> [< <ref-list> | < <object-list>]");
+    };
+    write_pack_files(
+        Path::new(base_name),
+        &pack,
+        &write_index(&entries, &checksum),
+        &checksum,
+    )?;
+    Ok((pack, checksum))
+}
+
+/// A pack of the objects `listed`, each with the path it was listed by if any, in
+/// that order but for bases coming before their deltas, with an index entry for each
+/// and the pack's checksum. Objects listed more than once are packed once; deltas are
+/// found within `options.window` and `options.depth` (by default `pack.window` and
+/// `pack.depth`), as [`git_pack_objects`] does.
+pub fn build_pack(
+    repo: &Repository,
+    config: &Config,
+    listed: Vec<(String, Option<String>)>,
+    options: &PackObjectsOptions,
+) -> Result<(Vec<u8>, Vec<IndexEntry>, ObjectId)> {
+    let algorithm = repo.hash_algorithm()?;
+    let mut seen = HashSet::new();
+    let mut objects = Vec::new();
+    let mut paths = Vec::new();
+    for (hash, path) in listed {
+        let Ok(id) = hash.parse::<ObjectId>() else {
+            bail!("expected object ID, got garbage:\n {hash}");
+        };
+        if !seen.insert(id) {
+            continue;
+        }
+        let mut object = Object::read_git_object(repo, &hash)
+            .with_context(|| format!("unable to read {hash}"))?;
+        objects.push(PackObject {
+            id,
+            kind: object.kind.clone(),
+            data: object.read_all()?,
+            delta: None,
+        });
+        paths.push(path);
+    }
+
+    let window = match options.window {
+        Some(window) => window,
+        None => config_count(config, "pack.window")?.unwrap_or(DEFAULT_WINDOW),
+    };
+    let depth = match options.depth {
+        Some(depth) => depth,
+        None => config_count(config, "pack.depth")?.unwrap_or(DEFAULT_DEPTH),
+    };
+    let paths: Vec<Option<&str>> = paths.iter().map(Option::as_deref).collect();
+    find_deltas(&mut objects, &paths, window, depth.min(4095));
+    write_pack(&write_order(objects), algorithm)
+}
+
+/// What `git rev-list --objects` lists for the revisions in `lines`, where `--not`
+/// hides the revisions after it (or shows them again, after another).
+fn list_stdin_revisions(
+    repo: &Repository,
+    lines: &[String],
+) -> Result<Vec<(String, Option<String>)>> {
+    let mut revisions = Vec::new();
+    let mut not = false;
+    for line in lines {
+        match line.as_str() {
+            "" => continue,
+            "--not" => not = !not,
+            option if option.starts_with('-') => bail!("not a rev '{option}'"),
+            revision if not => match revision.strip_prefix('^') {
+                Some(shown) => revisions.push(shown.to_string()),
+                None => revisions.push(format!("^{revision}")),
+            },
+            revision => revisions.push(revision.to_string()),
+        }
+    }
+    let options = RevListOptions {
+        objects: true,
+        ..RevListOptions::default()
+    };
+    list_revisions(repo, &revisions, &[], &options)
+}
+
+/// The count `key` is set to, if it is.
+fn config_count(config: &Config, key: &str) -> Result<Option<usize>> {
+    Ok(config.get_size(key)?.map(|count| count as usize))
+}
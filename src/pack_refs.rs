@@ -0,0 +1,73 @@
+use crate::object_read::{Object, ObjectKind};
+use crate::refs::{
+    PackedRef, loose_ref_names, read_packed_refs, remove_ref_file, write_packed_refs,
+};
+use crate::repository::{Repository, git_path};
+use crate::transfer::tag_target;
+use anyhow::{Context, Result};
+use std::fs::read_to_string;
+
+/// Loose refs that belong to one worktree, which stay out of `packed-refs` as it is
+/// shared by all of them.
+const PER_WORKTREE_REFS: [&str; 3] = ["refs/bisect/", "refs/worktree/", "refs/rewritten/"];
+
+/// `git pack-refs [--all] [--no-prune]`: move loose refs into `packed-refs`, the tags
+/// only unless `all` is set, and with `prune` delete the loose files packed (their
+/// reflogs are kept). Symbolic refs and refs to missing objects stay loose. An
+/// annotated tag is packed along with the object it peels to, as git does so that
+/// listing tags doesn't need to read them.
+pub fn git_pack_refs(repo: &Repository, all: bool, prune: bool) -> Result<()> {
+    let git_dir = &repo.git_dir;
+    let mut packed: Vec<PackedRef> = read_packed_refs(git_dir)?;
+    let mut packed_now = Vec::new();
+    for name in loose_ref_names(git_dir, "refs/")? {
+        if !all && !name.starts_with("refs/tags/")
+            || PER_WORKTREE_REFS
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+        {
+            continue;
+        }
+        let path = git_path(git_dir, &name);
+        let content =
+            read_to_string(&path).with_context(|| format!("failed to read {}", path.display()))?;
+        let hash = content.trim();
+        if hash.starts_with("ref:") {
+            continue;
+        }
+        let Some(peeled) = peel(repo, hash) else {
+            continue;
+        };
+        packed.retain(|packed| packed.name != name);
+        packed.push(PackedRef {
+            name: name.clone(),
+            hash: hash.to_string(),
+            peeled: Some(peeled).filter(|peeled| peeled != hash),
+        });
+        packed_now.push((name, hash.to_string()));
+    }
+    write_packed_refs(git_dir, packed)?;
+    if prune {
+        for (name, hash) in packed_now {
+            // Leave a ref that was updated in the meantime.
+            let path = git_path(git_dir, &name);
+            if read_to_string(&path).is_ok_and(|content| content.trim() == hash) {
+                remove_ref_file(git_dir, &name)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The object `hash` leads to once tags are peeled (itself if it isn't a tag), or
+/// `None` if any object on the way is missing.
+fn peel(repo: &Repository, hash: &str) -> Option<String> {
+    let mut target = hash.to_string();
+    loop {
+        let mut object = Object::read_git_object(repo, &target).ok()?;
+        if object.kind != ObjectKind::Tag {
+            return Some(target);
+        }
+        target = tag_target(&object.read_all().ok()?)?;
+    }
+}
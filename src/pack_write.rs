@@ -0,0 +1,282 @@
+use crate::delta::{DeltaIndex, create_delta};
+use crate::index_pack::IndexEntry;
+use crate::object_id::{HashAlgorithm, ObjectId};
+use crate::object_read::{Object, ObjectKind};
+use crate::repository::Repository;
+use crate::transfer::objects_to_send;
+use anyhow::Result;
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+
+/// An object to write into a pack, with a delta that rebuilds it from the object
+/// `base` if it is to be stored as one.
+pub struct PackObject {
+    pub id: ObjectId,
+    pub kind: ObjectKind,
+    pub data: Vec<u8>,
+    pub delta: Option<(ObjectId, Vec<u8>)>,
+}
+
+/// How many of the objects sorted before each one [`find_deltas`] tries as its base,
+/// as git's `pack.window` defaults to.
+pub const DEFAULT_WINDOW: usize = 10;
+
+/// How long [`find_deltas`] lets chains of deltas get, as git's `pack.depth` defaults
+/// to.
+pub const DEFAULT_DEPTH: usize = 50;
+
+/// Store objects of `objects` as deltas against others of them where that saves
+/// enough, looking for bases as `git pack-objects` does. The objects are sorted by
+/// kind, then by a hash of the end of their `paths` (so that versions of a file, and
+/// files of the same kind, are near each other), then largest first, and each is
+/// tried against the `window` sorted before it, keeping the smallest delta. A delta
+/// must come under half the object's size (less an id), or under the delta it
+/// already has, scaled down the deeper its base is in a chain, which may not get
+/// longer than `depth`. An object that already has a delta keeps it unless one is
+/// found that is smaller.
+pub fn find_deltas(
+    objects: &mut [PackObject],
+    paths: &[Option<&str>],
+    window: usize,
+    depth: usize,
+) {
+    let hash_len = objects
+        .first()
+        .map_or(0, |object| object.id.algorithm().len());
+    let index: HashMap<ObjectId, usize> = objects
+        .iter()
+        .enumerate()
+        .map(|(n, object)| (object.id, n))
+        .collect();
+    let mut depths = vec![0; objects.len()];
+    for (n, object) in objects.iter().enumerate() {
+        if let Some((base, _)) = &object.delta
+            && index.contains_key(base)
+        {
+            depths[n] = 1;
+        }
+    }
+    let mut order: Vec<usize> = (0..objects.len()).collect();
+    order.sort_by(|&a, &b| {
+        let key = |n: usize| {
+            (
+                std::cmp::Reverse(entry_type(&objects[n].kind)),
+                std::cmp::Reverse(name_hash(paths.get(n).copied().flatten())),
+                std::cmp::Reverse(objects[n].data.len()),
+            )
+        };
+        key(a).cmp(&key(b)).then(a.cmp(&b))
+    });
+
+    // Each base's index is kept while it is in the window, and the deltas found are
+    // only stored once all are, as the indexes borrow the objects' data.
+    let mut indexes: VecDeque<(usize, DeltaIndex)> = VecDeque::new();
+    let mut found: Vec<(usize, ObjectId, Vec<u8>)> = Vec::new();
+    for &target in &order {
+        let target_size = objects[target].data.len();
+        let mut best: Option<(usize, Vec<u8>)> = None;
+        for (source, index) in indexes.iter().rev() {
+            let source = *source;
+            if objects[source].kind != objects[target].kind || depths[source] >= depth {
+                continue;
+            }
+            let (limit, ref_depth) = match (&best, &objects[target].delta) {
+                (Some((_, delta)), _) | (None, Some((_, delta))) => (delta.len(), depths[target]),
+                (None, None) => ((target_size / 2).saturating_sub(hash_len), 1),
+            };
+            let limit = limit * (depth - depths[source]) / (depth - ref_depth.min(depth) + 1);
+            let source_size = objects[source].data.len();
+            if limit == 0
+                || target_size.saturating_sub(source_size) >= limit
+                || target_size < source_size / 32
+            {
+                continue;
+            }
+            let Some(delta) = index.delta(&objects[target].data, limit) else {
+                continue;
+            };
+            depths[target] = depths[source] + 1;
+            best = Some((source, delta));
+        }
+        if let Some((source, delta)) = best {
+            found.push((target, objects[source].id, delta));
+        }
+        if window > 0 {
+            if indexes.len() == window {
+                indexes.pop_front();
+            }
+            indexes.push_back((target, DeltaIndex::new(&objects[target].data)));
+        }
+    }
+    drop(indexes);
+    for (target, base, delta) in found {
+        objects[target].delta = Some((base, delta));
+    }
+}
+
+/// What git sorts objects by to bring similarly named ones together: the last
+/// characters of `path` (whitespace aside) count most, so that `.c` files sort near
+/// each other.
+fn name_hash(path: Option<&str>) -> u32 {
+    let mut hash: u32 = 0;
+    for byte in path.unwrap_or_default().bytes() {
+        if !byte.is_ascii_whitespace() {
+            hash = (hash >> 2).wrapping_add(u32::from(byte) << 24);
+        }
+    }
+    hash
+}
+
+/// `objects` in the order a pack should hold them: as they are, except that the base
+/// of a delta comes before it, so that it can be stored as an offset delta.
+pub fn write_order(objects: Vec<PackObject>) -> Vec<PackObject> {
+    let index: HashMap<ObjectId, usize> = objects
+        .iter()
+        .enumerate()
+        .map(|(n, object)| (object.id, n))
+        .collect();
+    let mut order = Vec::with_capacity(objects.len());
+    let mut placed = vec![false; objects.len()];
+    for n in 0..objects.len() {
+        let mut chain = vec![n];
+        while let Some((base, _)) = &objects[*chain.last().unwrap()].delta
+            && let Some(&base) = index.get(base)
+            && !placed[base]
+            && !chain.contains(&base)
+        {
+            chain.push(base);
+        }
+        for n in chain.into_iter().rev() {
+            if !placed[n] {
+                placed[n] = true;
+                order.push(n);
+            }
+        }
+    }
+    let mut objects: Vec<Option<PackObject>> = objects.into_iter().map(Some).collect();
+    order
+        .into_iter()
+        .map(|n| objects[n].take().unwrap())
+        .collect()
+}
+
+/// `objects` as a version 2 pack, with an index entry for each, in order, and the
+/// pack's checksum. An object with a delta is stored as an offset delta if its base
+/// comes before it in the pack, and otherwise as a reference delta, whose base the
+/// pack may not have at all: such a pack is thin, for a repository that has the
+/// bases to complete.
+pub fn write_pack(
+    objects: &[PackObject],
+    algorithm: HashAlgorithm,
+) -> Result<(Vec<u8>, Vec<IndexEntry>, ObjectId)> {
+    let mut pack = b"PACK\0\0\0\x02".to_vec();
+    pack.extend((objects.len() as u32).to_be_bytes());
+    let mut offsets: HashMap<ObjectId, u64> = HashMap::new();
+    let mut entries = Vec::with_capacity(objects.len());
+    for object in objects {
+        let offset = pack.len() as u64;
+        let (kind, data) = match &object.delta {
+            None => (entry_type(&object.kind), &object.data),
+            Some((base, delta)) if offsets.contains_key(base) => (6, delta),
+            Some((_, delta)) => (7, delta),
+        };
+        pack.extend(entry_header(kind, data.len()));
+        match (&object.delta, kind) {
+            (Some((base, _)), 6) => pack.extend(offset_encoding(offset - offsets[base])),
+            (Some((base, _)), _) => pack.extend(base.as_bytes()),
+            (None, _) => {}
+        }
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data)?;
+        pack.extend(encoder.finish()?);
+
+        let mut crc = Crc::new();
+        crc.update(&pack[offset as usize..]);
+        offsets.insert(object.id, offset);
+        entries.push(IndexEntry {
+            offset,
+            crc: crc.sum(),
+            id: object.id,
+        });
+    }
+    let checksum = algorithm.digest(&pack);
+    pack.extend(checksum.as_bytes());
+    Ok((pack, entries, checksum))
+}
+
+/// How an offset delta gives the distance back to its base: base-128, most
+/// significant group first, each continuation adding one.
+fn offset_encoding(mut distance: u64) -> Vec<u8> {
+    let mut bytes = vec![(distance & 0x7f) as u8];
+    distance >>= 7;
+    while distance > 0 {
+        distance -= 1;
+        bytes.push(0x80 | (distance & 0x7f) as u8);
+        distance >>= 7;
+    }
+    bytes.reverse();
+    bytes
+}
+
+/// The header of a pack entry of type `kind` holding `size` bytes once inflated: the
+/// type in bits 4-6 of the first byte, the size below it and on in base-128, least
+/// significant group first.
+pub(crate) fn entry_header(kind: u8, mut size: usize) -> Vec<u8> {
+    let mut header = Vec::new();
+    let mut byte = (kind << 4) | (size & 0x0f) as u8;
+    size >>= 4;
+    while size > 0 {
+        header.push(byte | 0x80);
+        byte = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+    header.push(byte);
+    header
+}
+
+pub(crate) fn entry_type(kind: &ObjectKind) -> u8 {
+    match kind {
+        ObjectKind::Commit => 1,
+        ObjectKind::Tree => 2,
+        ObjectKind::Blob => 3,
+        ObjectKind::Tag => 4,
+    }
+}
+
+/// A thin pack of what a repository that has `haves` lacks of `tips` (see
+/// [`objects_to_send`]), as a push sends it. A tree or blob that replaces another at
+/// the same path is stored as a delta against it, when that is less than half its
+/// size, since the receiving repository has the old one.
+pub fn thin_pack(
+    repo: &Repository,
+    tips: &[String],
+    haves: &[String],
+    algorithm: HashAlgorithm,
+) -> Result<Vec<u8>> {
+    let mut objects = Vec::new();
+    for (hash, base) in objects_to_send(repo, tips, haves)? {
+        let mut object = Object::read_git_object(repo, &hash)?;
+        let data = object.read_all()?;
+        let delta = match base {
+            Some(base) => {
+                let base_data = Object::read_git_object(repo, &base)?.read_all()?;
+                let delta = create_delta(&base_data, &data);
+                match delta.len() < data.len() / 2 {
+                    true => Some((base.parse()?, delta)),
+                    false => None,
+                }
+            }
+            None => None,
+        };
+        objects.push(PackObject {
+            id: hash.parse()?,
+            kind: object.kind,
+            data,
+            delta,
+        });
+    }
+    let (pack, _, _) = write_pack(&objects, algorithm)?;
+    Ok(pack)
+}
@@ -0,0 +1,92 @@
+use crate::checkout::{clear_worktree_path, create_worktree_entry, write_worktree_file};
+use crate::config::Config;
+use crate::diff::DiffSide;
+use crate::repository::Repository;
+use anyhow::{Result, bail};
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// How few files are written one by one even with several workers, as git's
+/// `checkout.thresholdForParallelism` defaults to.
+const DEFAULT_THRESHOLD: usize = 100;
+
+/// Write `files`, paths in the order of the tree they come from with what belongs at
+/// each, into the working directory, replacing whatever is in the way as
+/// [`write_worktree_file`] does.
+///
+/// `checkout.workers` threads write the files (as many as there are CPUs if it is
+/// unset or less than 1), unless there are fewer than
+/// `checkout.thresholdForParallelism` (100) of them. The directories the files go in
+/// are created first, in order, on this thread, so that workers only ever create
+/// files, each in a directory that already exists. A file a worker finds already
+/// created, because its path names the same one as another of `files` (as paths
+/// differing only in case do on a case-insensitive filesystem), is written again
+/// once the workers are done, so that one of the colliding files is left whole.
+pub fn write_worktree_files(
+    repo: &Repository,
+    config: &Config,
+    files: &[(&str, &DiffSide)],
+) -> Result<()> {
+    let workers = checkout_workers(config)?;
+    let threshold = config
+        .get_size("checkout.thresholdForParallelism")?
+        .map_or(DEFAULT_THRESHOLD, |threshold| threshold as usize);
+    if workers <= 1 || files.len() < threshold {
+        return files
+            .iter()
+            .try_for_each(|(path, side)| write_worktree_file(repo, Path::new(path), side));
+    }
+
+    for (path, _) in files {
+        clear_worktree_path(repo, Path::new(path))?;
+    }
+    let next = AtomicUsize::new(0);
+    let collided = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers.min(files.len()))
+            .map(|_| {
+                scope.spawn(|| -> Result<()> {
+                    while let Some((path, side)) = files.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        match create_worktree_entry(repo, Path::new(path), side) {
+                            Err(e)
+                                if e.downcast_ref::<io::Error>()
+                                    .is_some_and(|e| e.kind() == ErrorKind::AlreadyExists) =>
+                            {
+                                collided.lock().unwrap().push((*path, *side));
+                            }
+                            result => result?,
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .try_for_each(|handle| handle.join().expect("checkout worker panicked"))
+    })?;
+    let mut collided = collided.into_inner().unwrap();
+    collided.sort_by_key(|(path, _)| *path);
+    for (path, side) in collided {
+        write_worktree_file(repo, Path::new(path), side)?;
+    }
+    Ok(())
+}
+
+/// How many threads `checkout.workers` asks for.
+fn checkout_workers(config: &Config) -> Result<usize> {
+    let cpus = || thread::available_parallelism().map_or(1, |n| n.get());
+    let Some(workers) = config.get("checkout.workers") else {
+        return Ok(cpus());
+    };
+    match workers.parse::<i64>() {
+        Ok(workers) if workers < 1 => Ok(cpus()),
+        Ok(workers) => Ok(workers as usize),
+        Err(_) => {
+            bail!("bad numeric config value '{workers}' for 'checkout.workers': invalid unit")
+        }
+    }
+}
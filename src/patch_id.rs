@@ -0,0 +1,326 @@
+use crate::commit_read::Commit;
+use crate::diff::{DiffSide, TreeChange, diff_lines, is_binary, make_hunks, split_lines};
+use crate::object_id::{HashAlgorithm, Hasher, ObjectId};
+use crate::object_read::Object;
+use crate::repository::Repository;
+use crate::submodule::subproject_content;
+use anyhow::{Context, Result};
+use std::io::BufRead;
+
+/// A *patch ID* identifies a change independent of where it was applied: the hash (of
+/// the repository's hash function) of its diff with whitespace and line numbers removed. Two commits with the same patch ID
+/// introduce the same change, which is how `cherry` and `rebase` recognise commits that
+/// were already picked upstream.
+///
+/// For each changed file the following are hashed, all with whitespace stripped:
+///
+///     diff --git a/<path> b/<path>
+///     new file mode <mode> | deleted file mode <mode> | old mode <m> / new mode <m>
+///     --- a/<path> | --- /dev/null
+///     +++ b/<path> | +++ /dev/null
+///     every hunk line (with its ' ', '-' or '+' prefix), but not the @@ headers
+///
+/// Binary files contribute their two object ids instead of hunks. This is the "stable"
+/// variant: every file is hashed separately and the digests are summed, so the result
+/// doesn't depend on file order.
+pub fn commit_patch_id(repo: &Repository, commit: &Commit) -> Result<Option<ObjectId>> {
+    let parent_tree = match commit.parents.as_slice() {
+        [] => None,
+        [parent] => Some(Commit::read(repo, parent)?.tree),
+        // Merges don't have a single diff to identify them by.
+        _ => return Ok(None),
+    };
+    let old = parent_tree.map(|t| t.parse()).transpose()?;
+    let new = commit.tree.parse()?;
+    let changes = crate::diff::diff_trees(repo, old.as_ref(), Some(&new))?;
+    if changes.is_empty() {
+        return Ok(None);
+    }
+    let mut id = PatchId::new(new.algorithm());
+    for change in &changes {
+        hash_change(repo, &mut id, change)?;
+        id.flush_file();
+    }
+    Ok(Some(id.finish()))
+}
+
+fn hash_change(repo: &Repository, id: &mut PatchId, change: &TreeChange) -> Result<()> {
+    let path = &change.path;
+    id.update_line(format!("diff --git a/{path} b/{path}").as_bytes());
+    match (&change.old, &change.new) {
+        (None, Some(new)) => id.update_line(format!("new file mode {:0>6}", new.mode).as_bytes()),
+        (Some(old), None) => {
+            id.update_line(format!("deleted file mode {:0>6}", old.mode).as_bytes())
+        }
+        (Some(old), Some(new)) if old.mode != new.mode => {
+            id.update_line(format!("old mode {:0>6}", old.mode).as_bytes());
+            id.update_line(format!("new mode {:0>6}", new.mode).as_bytes());
+        }
+        _ => {}
+    }
+    let read_side = |side: Option<&DiffSide>| -> Result<Vec<u8>> {
+        match side {
+            Some(side) if side.mode == "160000" => Ok(subproject_content(&side.hash)),
+            Some(side) => Object::read_git_object(repo, &side.hash.to_string())?.read_all(),
+            None => Ok(Vec::new()),
+        }
+    };
+    let old_data = read_side(change.old.as_ref())?;
+    let new_data = read_side(change.new.as_ref())?;
+    if is_binary(&old_data) || is_binary(&new_data) {
+        let null = id.algorithm.null_id();
+        let hex_or_null = |side: Option<&DiffSide>| side.map_or(null, |side| side.hash).to_string();
+        id.update_line(hex_or_null(change.old.as_ref()).as_bytes());
+        id.update_line(hex_or_null(change.new.as_ref()).as_bytes());
+        return Ok(());
+    }
+    if change.old.is_some() {
+        id.update_line(format!("--- a/{path}").as_bytes());
+    } else {
+        id.update_line(b"--- /dev/null");
+    }
+    if change.new.is_some() {
+        id.update_line(format!("+++ b/{path}").as_bytes());
+    } else {
+        id.update_line(b"+++ /dev/null");
+    }
+    let old_lines = split_lines(&old_data);
+    let new_lines = split_lines(&new_data);
+    let ops = diff_lines(&old_lines, &new_lines);
+    for hunk in make_hunks(&ops, 3) {
+        for (prefix, line) in hunk.lines(&old_lines, &new_lines) {
+            id.update_line(&[prefix]);
+            id.update_line(line);
+        }
+    }
+    Ok(())
+}
+
+/// Accumulates a stable patch ID: a running hash per file whose digests are added
+/// together, byte by byte with carry, when each file is flushed.
+pub struct PatchId {
+    algorithm: HashAlgorithm,
+    hasher: Hasher,
+    sum: Vec<u8>,
+}
+
+impl PatchId {
+    pub fn new(algorithm: HashAlgorithm) -> PatchId {
+        PatchId {
+            algorithm,
+            hasher: algorithm.hasher(),
+            sum: vec![0; algorithm.len()],
+        }
+    }
+
+    /// Hash `data` with all ASCII whitespace removed.
+    pub fn update_line(&mut self, data: &[u8]) {
+        let stripped: Vec<u8> = data.iter().copied().filter(|b| !is_space(*b)).collect();
+        self.hasher.update(&stripped);
+    }
+
+    /// Hash `data` as is.
+    pub fn update_raw(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+    }
+
+    /// Finish the current file's digest and add it to the sum.
+    pub fn flush_file(&mut self) {
+        let hasher = std::mem::replace(&mut self.hasher, self.algorithm.hasher());
+        let digest = hasher.finish();
+        let mut carry = 0u16;
+        for (sum, &byte) in self.sum.iter_mut().zip(digest.as_bytes()) {
+            carry += *sum as u16 + byte as u16;
+            *sum = carry as u8;
+            carry >>= 8;
+        }
+    }
+
+    pub fn finish(self) -> ObjectId {
+        ObjectId::from_bytes(self.algorithm, &self.sum).unwrap()
+    }
+}
+
+/// Compute patch IDs for diffs read from `input`, as produced by `git log -p`,
+/// `git show` or `git format-patch`, mirroring `git patch-id`.
+///
+/// Each patch is attributed to the commit id on the `commit <id>` or `From <id>` line
+/// preceding it (all zeros if there is none), of the length of the current
+/// repository's ids (SHA-1's outside a repository). Only the diff itself is hashed; commit
+/// messages, `index` lines and hunk line numbers are ignored. With `stable`, files are
+/// hashed independently and summed, matching [`commit_patch_id`]; otherwise the whole
+/// patch is one running hash, like git's default. `verbatim` keeps whitespace and,
+/// as in git, implies `stable`.
+pub fn patch_ids_from_diff(
+    repo: &Repository,
+    mut input: impl BufRead,
+    stable: bool,
+    verbatim: bool,
+) -> Result<Vec<(ObjectId, String)>> {
+    let algorithm = repo.hash_algorithm()?;
+    let stable = stable || verbatim;
+    let mut ids = Vec::new();
+    let null = algorithm.null_id().to_string();
+    let mut commit = null.clone();
+    loop {
+        let patch = read_one_patch(&mut input, algorithm, stable, verbatim)?;
+        if patch.len > 0 {
+            ids.push((patch.id, commit));
+        }
+        if patch.at_eof {
+            break;
+        }
+        commit = patch.next_commit.unwrap_or_else(|| null.clone());
+    }
+    Ok(ids)
+}
+
+struct ParsedPatch {
+    id: ObjectId,
+    /// Bytes hashed; zero when no diff was found.
+    len: usize,
+    next_commit: Option<String>,
+    at_eof: bool,
+}
+
+/// The commit id a `commit <id>`, `From <id> ...` or bare `<id>` line starts with.
+fn commit_id_in(line: &str, algorithm: HashAlgorithm) -> Option<String> {
+    let rest = line
+        .strip_prefix("commit ")
+        .or_else(|| line.strip_prefix("From "))
+        .unwrap_or(line);
+    let id = rest.get(..algorithm.hex_len())?;
+    id.bytes()
+        .all(|b| b.is_ascii_hexdigit())
+        .then(|| id.to_ascii_lowercase())
+}
+
+/// Line counts on each side from a `@@ -a[,b] +c[,d] @@` header.
+fn hunk_line_counts(line: &str) -> (i64, i64) {
+    let mut parts = line.split(' ').skip(1);
+    let mut count = |prefix: char| -> i64 {
+        parts
+            .next()
+            .and_then(|range| range.strip_prefix(prefix))
+            .map_or(1, |range| match range.split_once(',') {
+                Some((_, len)) => len.parse().unwrap_or(1),
+                None => 1,
+            })
+    };
+    let before = count('-');
+    let after = count('+');
+    (before, after)
+}
+
+fn read_one_patch(
+    input: &mut impl BufRead,
+    algorithm: HashAlgorithm,
+    stable: bool,
+    verbatim: bool,
+) -> Result<ParsedPatch> {
+    let mut id = PatchId::new(algorithm);
+    let mut len = 0;
+    let mut next_commit = None;
+    let mut at_eof = false;
+    // Lines still expected on each side of the current hunk; -1 while in a file header.
+    let (mut before, mut after) = (-1i64, -1i64);
+    let mut is_binary = false;
+    let (mut pre_hash, mut post_hash) = (String::new(), String::new());
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        if input
+            .read_until(b'\n', &mut buf)
+            .context("failed to read diff")?
+            == 0
+        {
+            at_eof = true;
+            break;
+        }
+        let line = String::from_utf8_lossy(&buf);
+        if line.starts_with("\\ ") && line.len() > 12 {
+            if verbatim {
+                id.update_raw(&buf);
+            }
+            continue;
+        }
+        if let Some(commit) = commit_id_in(&line, algorithm) {
+            next_commit = Some(commit);
+            break;
+        }
+        // Anything before the first diff, like the commit message, is ignored.
+        if len == 0 && !line.starts_with("diff ") {
+            continue;
+        }
+        if before == -1 {
+            if line.starts_with("GIT binary patch") || line.starts_with("Binary files") {
+                is_binary = true;
+                before = 0;
+                id.update_raw(pre_hash.as_bytes());
+                id.update_raw(post_hash.as_bytes());
+                if stable {
+                    id.flush_file();
+                }
+                continue;
+            } else if let Some(hashes) = line.strip_prefix("index ") {
+                if let Some((pre, rest)) = hashes.split_once("..") {
+                    pre_hash = pre.to_string();
+                    post_hash = rest.split([' ', '\n']).next().unwrap_or("").to_string();
+                }
+                continue;
+            } else if line.starts_with("--- ") {
+                before = 1;
+                after = 1;
+            } else if !line.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                break;
+            }
+        }
+        if is_binary {
+            if line.starts_with("diff ") {
+                is_binary = false;
+                before = -1;
+            }
+            continue;
+        }
+        if before == 0 && after == 0 {
+            if line.starts_with("@@ -") {
+                (before, after) = hunk_line_counts(&line);
+                continue;
+            }
+            if !line.starts_with("diff ") {
+                break;
+            }
+            // The next file's header.
+            if stable {
+                id.flush_file();
+            }
+            before = -1;
+            after = -1;
+        }
+        if line.starts_with(['-', ' ']) {
+            before -= 1;
+        }
+        if line.starts_with(['+', ' ']) {
+            after -= 1;
+        }
+        len += if verbatim {
+            id.update_raw(&buf);
+            buf.len()
+        } else {
+            let hashed = buf.iter().filter(|b| !is_space(**b)).count();
+            id.update_line(&buf);
+            hashed
+        };
+    }
+    id.flush_file();
+    Ok(ParsedPatch {
+        id: id.finish(),
+        len,
+        next_commit,
+        at_eof,
+    })
+}
+
+fn is_space(b: u8) -> bool {
+    b.is_ascii_whitespace() || b == 0x0b
+}
@@ -0,0 +1,440 @@
+use anyhow::{Context, Result, bail, ensure};
+use std::io::{self, ErrorKind, Read, Write};
+
+/// The most data one pkt-line can carry: 65520 bytes less its four-byte length.
+pub const MAX_DATA_LEN: usize = 65516;
+
+/// A pkt-line: a line of data, or one of the special packets that end a message
+/// (`0000`), separate its sections (`0001`) or end a stateless response (`0002`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    Data(Vec<u8>),
+    Flush,
+    Delim,
+    ResponseEnd,
+}
+
+impl Packet {
+    /// The text of a data packet, without its line ending, or `None` for a special
+    /// packet.
+    pub fn text(&self) -> Result<Option<&str>> {
+        let Packet::Data(line) = self else {
+            return Ok(None);
+        };
+        let text = std::str::from_utf8(line).context("protocol error: line isn't UTF-8")?;
+        Ok(Some(text.strip_suffix('\n').unwrap_or(text)))
+    }
+}
+
+/// Reads pkt-lines one at a time from a stream: each a four-digit hex length, counting
+/// itself, and then that much data, or one of the special lengths 0 to 2.
+///
+/// A data packet starting `ERR ` is the other side giving up, and is read as an
+/// error.
+pub struct PacketReader<R> {
+    reader: R,
+}
+
+impl<R: Read> PacketReader<R> {
+    pub fn new(reader: R) -> PacketReader<R> {
+        PacketReader { reader }
+    }
+
+    /// The next packet, or `None` if the stream ends before one starts.
+    pub fn read_packet(&mut self) -> Result<Option<Packet>> {
+        let mut length = [0; 4];
+        match self.reader.read(&mut length[..1]) {
+            Ok(0) => return Ok(None),
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::Interrupted => return self.read_packet(),
+            Err(e) => return Err(e).context("failed to read a pkt-line"),
+        }
+        self.read_exact(&mut length[1..])?;
+        let length = std::str::from_utf8(&length)
+            .ok()
+            .filter(|length| length.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|length| usize::from_str_radix(length, 16).ok())
+            .context("protocol error: bad line length character")?;
+        let packet = match length {
+            0 => Packet::Flush,
+            1 => Packet::Delim,
+            2 => Packet::ResponseEnd,
+            3 => bail!("protocol error: bad line length 3"),
+            _ => {
+                let mut line = vec![0; length - 4];
+                self.read_exact(&mut line)?;
+                if let Some(message) = line.strip_prefix(b"ERR ") {
+                    bail!(
+                        "remote error: {}",
+                        String::from_utf8_lossy(message).trim_end()
+                    );
+                }
+                Packet::Data(line)
+            }
+        };
+        Ok(Some(packet))
+    }
+
+    /// The text of the next packet, if it is a data packet (see [`Packet::text`]):
+    /// `None` at a special one or the end of the stream.
+    pub fn read_line(&mut self) -> Result<Option<String>> {
+        let packet = self.read_packet()?;
+        Ok(match &packet {
+            Some(packet) => packet.text()?.map(str::to_string),
+            None => None,
+        })
+    }
+
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<()> {
+        self.reader
+            .read_exact(buffer)
+            .context("protocol error: the remote hung up unexpectedly")
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// Writes pkt-lines to a stream.
+pub struct PacketWriter<W> {
+    writer: W,
+}
+
+impl<W: Write> PacketWriter<W> {
+    pub fn new(writer: W) -> PacketWriter<W> {
+        PacketWriter { writer }
+    }
+
+    /// Frame `data` as a data packet, which it must fit in.
+    pub fn write_data(&mut self, data: &[u8]) -> Result<()> {
+        ensure!(
+            data.len() <= MAX_DATA_LEN,
+            "protocol error: impossibly long line of {} bytes",
+            data.len()
+        );
+        write!(self.writer, "{:04x}", data.len() + 4)?;
+        self.writer.write_all(data)?;
+        Ok(())
+    }
+
+    /// Write `line`, which should end in a newline, as a data packet.
+    pub fn write_line(&mut self, line: &str) -> Result<()> {
+        self.write_data(line.as_bytes())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.write_all(b"0000")?)
+    }
+
+    pub fn delim(&mut self) -> Result<()> {
+        Ok(self.writer.write_all(b"0001")?)
+    }
+
+    pub fn response_end(&mut self) -> Result<()> {
+        Ok(self.writer.write_all(b"0002")?)
+    }
+
+    /// Write `data` unframed, as a pack follows the commands of a push.
+    pub fn write_raw(&mut self, data: &[u8]) -> Result<()> {
+        Ok(self.writer.write_all(data)?)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// What a side-band multiplexed stream carries, which the band at the start of each
+/// data packet says: data on band 1, progress on band 2, a fatal error on band 3.
+///
+/// Read as a plain stream of band 1's data up to the flush (or delimiter) that ends
+/// it, with progress printed to stderr as `remote: ...` along the way and an error
+/// ending the stream with `remote error: ...`.
+pub struct SidebandReader<R> {
+    packets: PacketReader<R>,
+    data: Vec<u8>,
+    position: usize,
+    done: bool,
+}
+
+impl<R: Read> SidebandReader<R> {
+    pub fn new(packets: PacketReader<R>) -> SidebandReader<R> {
+        SidebandReader {
+            packets,
+            data: Vec::new(),
+            position: 0,
+            done: false,
+        }
+    }
+
+    /// Read the next data packet into `self.data`, or find the stream's end.
+    fn fill(&mut self) -> Result<()> {
+        while !self.done && self.position == self.data.len() {
+            let Some(Packet::Data(line)) = self.packets.read_packet()? else {
+                self.done = true;
+                break;
+            };
+            match line.split_first() {
+                Some((1, _)) => {
+                    self.data = line;
+                    self.position = 1;
+                }
+                Some((2, message)) => print_progress(message),
+                Some((3, message)) => bail!(
+                    "remote error: {}",
+                    String::from_utf8_lossy(message).trim_end()
+                ),
+                _ => bail!("protocol error: bad band #{}", line.first().unwrap_or(&0)),
+            }
+        }
+        Ok(())
+    }
+
+    /// The packets after the multiplexed stream.
+    pub fn into_inner(self) -> PacketReader<R> {
+        self.packets
+    }
+}
+
+impl<R: Read> Read for SidebandReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.fill()
+            .map_err(|e| io::Error::other(format!("{e:#}")))?;
+        let available = &self.data[self.position..];
+        let n = available.len().min(buffer.len());
+        buffer[..n].copy_from_slice(&available[..n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// Print what the other side said of its progress, each line (or carriage-return
+/// update) as `remote: ...`, as git does.
+pub fn print_progress(message: &[u8]) {
+    for line in String::from_utf8_lossy(message).split_inclusive(['\n', '\r']) {
+        eprint!("remote: {line}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reader(data: &[u8]) -> PacketReader<&[u8]> {
+        PacketReader::new(data)
+    }
+
+    fn packets(data: &[u8]) -> Result<Vec<Packet>> {
+        let mut reader = reader(data);
+        let mut packets = Vec::new();
+        while let Some(packet) = reader.read_packet()? {
+            packets.push(packet);
+        }
+        Ok(packets)
+    }
+
+    /// A side-band packet of `band` carrying `data`.
+    fn band(band: u8, data: &[u8]) -> Vec<u8> {
+        let mut line = vec![band];
+        line.extend_from_slice(data);
+        let mut writer = PacketWriter::new(Vec::new());
+        writer.write_data(&line).unwrap();
+        writer.into_inner()
+    }
+
+    #[test]
+    fn data_is_framed_with_its_length_in_hex() {
+        let mut writer = PacketWriter::new(Vec::new());
+        writer.write_line("hello\n").unwrap();
+        writer.write_data(b"").unwrap();
+        writer.write_data(&[0; 12]).unwrap();
+        let written = writer.into_inner();
+        assert_eq!(&written[..14], b"000ahello\n0004");
+        assert_eq!(&written[14..18], b"0010");
+        assert_eq!(written.len(), 18 + 12);
+    }
+
+    #[test]
+    fn special_packets_are_written_as_their_lengths() {
+        let mut writer = PacketWriter::new(Vec::new());
+        writer.flush().unwrap();
+        writer.delim().unwrap();
+        writer.response_end().unwrap();
+        writer.write_raw(b"PACK").unwrap();
+        assert_eq!(writer.into_inner(), b"000000010002PACK");
+    }
+
+    #[test]
+    fn packets_read_back_as_written() {
+        let mut writer = PacketWriter::new(Vec::new());
+        writer.write_line("command=ls-refs\n").unwrap();
+        writer.delim().unwrap();
+        writer.write_data(&[0, 1, 2, 255]).unwrap();
+        writer.flush().unwrap();
+        writer.response_end().unwrap();
+        assert_eq!(
+            packets(&writer.into_inner()).unwrap(),
+            [
+                Packet::Data(b"command=ls-refs\n".to_vec()),
+                Packet::Delim,
+                Packet::Data(vec![0, 1, 2, 255]),
+                Packet::Flush,
+                Packet::ResponseEnd,
+            ]
+        );
+    }
+
+    #[test]
+    fn the_longest_packet_fits_and_a_longer_one_is_refused() {
+        let mut writer = PacketWriter::new(Vec::new());
+        writer.write_data(&vec![b'x'; MAX_DATA_LEN]).unwrap();
+        let written = writer.into_inner();
+        assert_eq!(&written[..4], b"fff0");
+        assert_eq!(
+            packets(&written).unwrap(),
+            [Packet::Data(vec![b'x'; MAX_DATA_LEN])]
+        );
+
+        let mut writer = PacketWriter::new(Vec::new());
+        let error = writer
+            .write_data(&vec![b'x'; MAX_DATA_LEN + 1])
+            .unwrap_err();
+        assert!(error.to_string().contains("impossibly long line"));
+        assert!(writer.into_inner().is_empty());
+    }
+
+    #[test]
+    fn hex_lengths_are_read_in_either_case() {
+        assert_eq!(
+            packets(b"000Aabcde\n000aabcde\n").unwrap(),
+            [
+                Packet::Data(b"abcde\n".to_vec()),
+                Packet::Data(b"abcde\n".to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn the_end_of_the_stream_before_a_packet_is_no_packet() {
+        assert_eq!(reader(b"").read_packet().unwrap(), None);
+        assert_eq!(reader(b"").read_line().unwrap(), None);
+    }
+
+    #[test]
+    fn malformed_lengths_are_protocol_errors() {
+        let message = |data: &[u8]| format!("{:#}", packets(data).unwrap_err());
+        assert!(message(b"00g1").contains("bad line length character"));
+        assert!(message(b"+001").contains("bad line length character"));
+        assert!(message(b"0003").contains("bad line length 3"));
+    }
+
+    #[test]
+    fn a_stream_cut_short_is_a_hang_up() {
+        for data in [&b"00"[..], b"000ahel"] {
+            let error = format!("{:#}", packets(data).unwrap_err());
+            assert!(error.contains("hung up unexpectedly"), "{error}");
+        }
+    }
+
+    #[test]
+    fn an_err_packet_is_the_remote_giving_up() {
+        let mut writer = PacketWriter::new(Vec::new());
+        writer.write_line("ERR access denied\n").unwrap();
+        let error = packets(&writer.into_inner()).unwrap_err();
+        assert_eq!(error.to_string(), "remote error: access denied");
+    }
+
+    #[test]
+    fn lines_are_read_without_their_newline() {
+        let mut reader = reader(b"000ahello\n0009world0000");
+        assert_eq!(reader.read_line().unwrap().as_deref(), Some("hello"));
+        assert_eq!(reader.read_line().unwrap().as_deref(), Some("world"));
+        assert_eq!(reader.read_line().unwrap(), None);
+        assert_eq!(reader.read_line().unwrap(), None);
+    }
+
+    #[test]
+    fn text_is_only_that_of_utf8_data_packets() {
+        assert_eq!(Packet::Flush.text().unwrap(), None);
+        assert_eq!(Packet::Delim.text().unwrap(), None);
+        assert_eq!(Packet::Data(b"a\n".to_vec()).text().unwrap(), Some("a"));
+        assert!(Packet::Data(vec![0xff, b'\n']).text().is_err());
+    }
+
+    #[test]
+    fn sideband_reads_band_one_as_a_stream_up_to_the_flush() {
+        let mut data = band(1, b"PACK");
+        data.extend(band(2, b"Counting objects: 1\r"));
+        data.extend(band(1, b"more"));
+        data.extend(band(2, b"done.\n"));
+        data.extend(band(1, b""));
+        data.extend(band(1, b"!"));
+        data.extend_from_slice(b"0000000aafter\n");
+
+        let mut sideband = SidebandReader::new(reader(&data));
+        let mut content = Vec::new();
+        sideband.read_to_end(&mut content).unwrap();
+        assert_eq!(content, b"PACKmore!");
+        // Once the stream has ended, it stays ended.
+        assert_eq!(sideband.read(&mut [0; 8]).unwrap(), 0);
+        let mut rest = sideband.into_inner();
+        assert_eq!(rest.read_line().unwrap().as_deref(), Some("after"));
+        assert_eq!(rest.read_packet().unwrap(), None);
+    }
+
+    #[test]
+    fn sideband_hands_out_a_packet_across_small_reads() {
+        let mut data = band(1, b"abcdefg");
+        data.extend(band(1, b"hi"));
+        data.extend_from_slice(b"0000");
+        let mut sideband = SidebandReader::new(reader(&data));
+        let mut buffer = [0; 3];
+        let mut reads = Vec::new();
+        loop {
+            let n = sideband.read(&mut buffer).unwrap();
+            if n == 0 {
+                break;
+            }
+            reads.push(buffer[..n].to_vec());
+        }
+        assert_eq!(reads, [&b"abc"[..], b"def", b"g", b"hi"]);
+    }
+
+    #[test]
+    fn sideband_ends_at_a_delimiter_or_the_end_of_the_stream() {
+        let mut data = band(1, b"x");
+        data.extend_from_slice(b"0001");
+        data.extend(band(1, b"y"));
+        let mut sideband = SidebandReader::new(reader(&data));
+        let mut content = Vec::new();
+        sideband.read_to_end(&mut content).unwrap();
+        assert_eq!(content, b"x");
+
+        let mut sideband = SidebandReader::new(sideband.into_inner());
+        let mut content = Vec::new();
+        sideband.read_to_end(&mut content).unwrap();
+        assert_eq!(content, b"y");
+    }
+
+    #[test]
+    fn sideband_band_three_is_a_fatal_error() {
+        let mut data = band(1, b"PA");
+        data.extend(band(3, b"pack-objects died\n"));
+        data.extend(band(1, b"CK"));
+        let mut sideband = SidebandReader::new(reader(&data));
+        let mut content = Vec::new();
+        let error = sideband.read_to_end(&mut content).unwrap_err();
+        assert_eq!(error.to_string(), "remote error: pack-objects died");
+        assert_eq!(content, b"PA");
+    }
+
+    #[test]
+    fn sideband_refuses_unknown_bands() {
+        for data in [band(4, b"what"), b"0004".to_vec()] {
+            let mut sideband = SidebandReader::new(reader(&data));
+            let error = sideband.read_to_end(&mut Vec::new()).unwrap_err();
+            assert!(error.to_string().contains("bad band #"), "{error}");
+        }
+    }
+}
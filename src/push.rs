@@ -0,0 +1,624 @@
+use crate::commit_read::ahead_behind;
+use crate::config::Config;
+use crate::gpg::sign_buffer;
+use crate::hooks::Hooks;
+use crate::http::send_pack;
+use crate::ident::{Role, resolve_ident};
+use crate::object_read::has_object;
+use crate::pack_write::thin_pack;
+use crate::receive::{
+    ReceiveCommand, ReceiveRequest, check_capabilities, push_cert_nonce, receive_pack,
+};
+use crate::refs::{
+    current_branch, delete_ref, expand_ref, list_refs, shorten_ref, symbolic_ref_target, update_ref,
+};
+use crate::remote::{Refspec, Remote, RemoteRefs, get_remote};
+use crate::repository::Repository;
+use anyhow::{Result, bail};
+use std::io::Write;
+
+#[derive(Debug, Default)]
+pub struct PushOptions {
+    /// Update remote refs even when it isn't a fast-forward, as if every refspec
+    /// started with `+`.
+    pub force: bool,
+    /// Update all refs or none: if the remote would refuse any of them, change nothing.
+    pub atomic: bool,
+    /// Strings for the remote's hooks, from `--push-option`; if there are none, the
+    /// `push.pushOption` values (an empty one clearing those before it).
+    pub push_options: Vec<String>,
+    /// Whether to sign the push: `true`, `false` or `if-asked` (only if the remote
+    /// accepts signed pushes). Defaults to `push.gpgSign`, else `false`.
+    pub signed: Option<String>,
+    /// Don't run the `pre-push` hook.
+    pub no_verify: bool,
+}
+
+/// One remote ref a push changes.
+struct PushUpdate {
+    /// The local ref pushed (for display), or `None` when deleting.
+    src: Option<String>,
+    dst: String,
+    old_hash: Option<String>,
+    new_hash: Option<String>,
+    status: PushStatus,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PushStatus {
+    UpToDate,
+    New,
+    Deleted,
+    FastForward,
+    Forced,
+    /// Refused before contacting the remote, e.g. `non-fast-forward`.
+    Rejected(&'static str),
+    /// Not sent because another ref of an atomic push was rejected.
+    AtomicFailed,
+    /// Refused by the remote, with its reason.
+    RemoteRejected(String),
+}
+
+/// `git push`: update refs of a remote (by default the current branch's push remote)
+/// from local refs as `refspecs` say, sending the objects they need.
+///
+/// Without refspecs, `remote.<name>.push` or `push.default` decides what is pushed.
+/// An update that isn't a fast-forward is refused unless forced. Remote-tracking refs
+/// are updated to match what was pushed.
+///
+/// A remote that is a local repository receives the push directly (see
+/// [`receive_pack`]); one with an `http://` URL is sent the commands and a thin pack
+/// of the objects it lacks over the smart HTTP protocol (see [`send_pack`]), and
+/// reports which updates it made. The status of each update is reported on `err`.
+pub fn git_push(
+    repo: &Repository,
+    config: &Config,
+    err: &mut impl Write,
+    remote: Option<&str>,
+    refspecs: &[String],
+    options: &PushOptions,
+) -> Result<()> {
+    let remote_name = match remote {
+        Some(name) => name.to_string(),
+        None => default_push_remote(repo, config)?,
+    };
+    let remote = get_remote(config, &remote_name)?;
+    let push_options =
+        match options.push_options.is_empty() {
+            true => config.get_all("push.pushOption").into_iter().fold(
+                Vec::new(),
+                |mut values, value| {
+                    match value.is_empty() {
+                        true => values.clear(),
+                        false => values.push(value.to_string()),
+                    }
+                    values
+                },
+            ),
+            false => options.push_options.clone(),
+        };
+    let remote_refs = remote.query_push(repo)?;
+    let url = remote.push_url().unwrap_or("");
+    let nonce = match &remote_refs.git_dir {
+        Some(remote_git_dir) => {
+            let remote_config = Config::load_repository(remote_git_dir)?;
+            check_capabilities(&remote_config, options.atomic, !push_options.is_empty())?;
+            push_cert_nonce(remote_git_dir, &remote_config)
+        }
+        None => {
+            let has = |capability: &str| remote_refs.capabilities.iter().any(|c| c == capability);
+            if !push_options.is_empty() && !has("push-options") {
+                bail!("the receiving end does not support push options");
+            }
+            if options.atomic && !has("atomic") {
+                bail!("the receiving end does not support --atomic push");
+            }
+            remote_refs
+                .capabilities
+                .iter()
+                .find_map(|c| c.strip_prefix("push-cert="))
+                .map(str::to_string)
+        }
+    };
+    let signed = options
+        .signed
+        .as_deref()
+        .or_else(|| config.get("push.gpgSign"))
+        .unwrap_or("false");
+    let sign = match signed {
+        "true" | "yes" | "on" | "1" if nonce.is_none() => {
+            bail!("the receiving end does not support --signed push")
+        }
+        "true" | "yes" | "on" | "1" => true,
+        "if-asked" => nonce.is_some(),
+        "false" | "no" | "off" | "0" => false,
+        _ => bail!("invalid value for --signed: '{signed}'"),
+    };
+
+    let refspecs = match refspecs {
+        [] => default_refspecs(repo, config, &remote, &remote_refs)?,
+        _ => refspecs
+            .iter()
+            .map(|spec| Refspec::parse(spec))
+            .collect::<Result<_>>()?,
+    };
+    let null = repo.hash_algorithm()?.null_id().to_string();
+    let mut updates = Vec::new();
+    for refspec in &refspecs {
+        updates.extend(resolve_refspec(repo, refspec, &remote_refs, options.force)?);
+    }
+
+    if !options.no_verify {
+        let lines: String = updates
+            .iter()
+            .filter(|u| !matches!(u.status, PushStatus::Rejected(_) | PushStatus::UpToDate))
+            .map(|u| {
+                format!(
+                    "{} {} {} {}\n",
+                    u.src.as_deref().unwrap_or("(delete)"),
+                    u.new_hash.as_deref().unwrap_or(&null),
+                    u.dst,
+                    u.old_hash.as_deref().unwrap_or(&null)
+                )
+            })
+            .collect();
+        let hooks = Hooks::new(config, repo);
+        if !hooks.run("pre-push", &[&remote.name, url], &lines, &[])? {
+            bail!("failed to push some refs to '{url}'");
+        }
+    }
+
+    if updates.iter().all(|u| u.status == PushStatus::UpToDate) {
+        writeln!(err, "Everything up-to-date")?;
+        return Ok(());
+    }
+    let rejected = updates
+        .iter()
+        .any(|u| matches!(u.status, PushStatus::Rejected(_)));
+    if options.atomic && rejected {
+        for update in &mut updates {
+            if !matches!(
+                update.status,
+                PushStatus::Rejected(_) | PushStatus::UpToDate
+            ) {
+                update.status = PushStatus::AtomicFailed;
+            }
+        }
+    } else {
+        let sent: Vec<&mut PushUpdate> = updates
+            .iter_mut()
+            .filter(|u| !matches!(u.status, PushStatus::Rejected(_) | PushStatus::UpToDate))
+            .collect();
+        if sent.is_empty() {
+            // Nothing left to send once every update was rejected here.
+            return report_push(repo, err, url, &updates);
+        }
+        let commands: Vec<ReceiveCommand> = sent
+            .iter()
+            .map(|u| ReceiveCommand {
+                ref_name: u.dst.clone(),
+                old_hash: u.old_hash.clone(),
+                new_hash: u.new_hash.clone(),
+            })
+            .collect();
+        let push_cert = match &nonce {
+            Some(nonce) if sign => Some(push_certificate(
+                repo,
+                config,
+                url,
+                nonce,
+                &push_options,
+                &commands,
+            )?),
+            _ => None,
+        };
+        let request = ReceiveRequest {
+            objects: &repo.objects_dir(),
+            commands,
+            atomic: options.atomic,
+            push_options,
+            push_cert,
+            nonce,
+        };
+        let results = match &remote_refs.git_dir {
+            Some(remote_git_dir) => receive_pack(repo, remote_git_dir, &request)?,
+            None => {
+                let algorithm = repo.hash_algorithm()?;
+                let tips: Vec<String> = request
+                    .commands
+                    .iter()
+                    .filter_map(|command| command.new_hash.clone())
+                    .collect();
+                let pack = match tips.is_empty() {
+                    true => None,
+                    false => {
+                        let haves: Vec<String> = remote_refs.refs.values().cloned().collect();
+                        Some(thin_pack(repo, &tips, &haves, algorithm)?)
+                    }
+                };
+                send_pack(
+                    url,
+                    &remote_refs.capabilities,
+                    &request,
+                    pack.as_deref(),
+                    algorithm,
+                )?
+            }
+        };
+        for (update, result) in sent.into_iter().zip(results) {
+            match result {
+                Ok(()) => update_tracking_ref(repo, config, &remote, update)?,
+                Err(reason) => update.status = PushStatus::RemoteRejected(reason),
+            }
+        }
+    }
+
+    report_push(repo, err, url, &updates)
+}
+
+/// Write the status of each update to `err` and fail if any was rejected, with advice
+/// on why.
+fn report_push(
+    repo: &Repository,
+    err: &mut impl Write,
+    url: &str,
+    updates: &[PushUpdate],
+) -> Result<()> {
+    let (failed, done): (Vec<&PushUpdate>, Vec<&PushUpdate>) = updates.iter().partition(|u| {
+        matches!(
+            u.status,
+            PushStatus::Rejected(_) | PushStatus::AtomicFailed | PushStatus::RemoteRejected(_)
+        )
+    });
+    // As in git, the refs that were updated come first and the failures last.
+    writeln!(err, "To {url}")?;
+    for update in done.iter().chain(&failed) {
+        if let Some(line) = status_line(update) {
+            writeln!(err, "{line}")?;
+        }
+    }
+    if failed.is_empty() {
+        return Ok(());
+    }
+    if let Some(hint) = rejection_hint(repo, &failed)? {
+        for line in hint.lines() {
+            writeln!(err, "hint: {line}")?;
+        }
+    }
+    bail!("failed to push some refs to '{url}'");
+}
+
+/// A push certificate for `commands`, signed with `user.signingKey` (or else the
+/// committer identity) the way `git push --signed` makes them.
+fn push_certificate(
+    repo: &Repository,
+    config: &Config,
+    url: &str,
+    nonce: &str,
+    push_options: &[String],
+    commands: &[ReceiveCommand],
+) -> Result<String> {
+    let pusher = resolve_ident(Role::Committer, config)?;
+    let key = match config.get("user.signingKey") {
+        Some(key) => key.to_string(),
+        None => format!("{} <{}>", pusher.name, pusher.email),
+    };
+    let mut cert =
+        format!("certificate version 0.1\npusher {pusher}\npushee {url}\nnonce {nonce}\n");
+    for option in push_options {
+        cert.push_str(&format!("push-option {option}\n"));
+    }
+    cert.push('\n');
+    for command in commands {
+        cert.push_str(&command.line(repo.hash_algorithm()?));
+        cert.push('\n');
+    }
+    let signature = sign_buffer(config, cert.as_bytes(), &key)?;
+    Ok(cert + &signature)
+}
+
+/// The remote a push goes to without one given: `branch.<name>.pushRemote`, then
+/// `remote.pushDefault`, then the current branch's remote, then `origin`.
+fn default_push_remote(repo: &Repository, config: &Config) -> Result<String> {
+    let branch = current_branch(repo)?;
+    let branch_value = |key: &str| {
+        let branch = branch.as_deref()?;
+        config.get(&format!("branch.{branch}.{key}"))
+    };
+    Ok(branch_value("pushRemote")
+        .or_else(|| config.get("remote.pushDefault"))
+        .or_else(|| branch_value("remote"))
+        .unwrap_or("origin")
+        .to_string())
+}
+
+/// What to push without refspecs on the command line: `remote.<name>.push`, or
+/// else by `push.default` (`simple` if unset).
+fn default_refspecs(
+    repo: &Repository,
+    config: &Config,
+    remote: &Remote,
+    remote_refs: &RemoteRefs,
+) -> Result<Vec<Refspec>> {
+    if !remote.push.is_empty() {
+        return Ok(remote.push.clone());
+    }
+    let mode = config.get("push.default").unwrap_or("simple");
+    if mode == "matching" {
+        return Ok(list_refs(repo, "refs/heads/")?
+            .into_iter()
+            .filter(|(name, _)| remote_refs.refs.contains_key(name))
+            .map(|(name, _)| Refspec {
+                force: false,
+                src: name.clone(),
+                dst: Some(name),
+            })
+            .collect());
+    }
+    if mode == "nothing" {
+        bail!("You didn't specify any refspecs to push, and push.default is \"nothing\".");
+    }
+    let Some(branch) = current_branch(repo)? else {
+        bail!("You are not currently on a branch.");
+    };
+    let src = format!("refs/heads/{branch}");
+    let dst = match mode {
+        "simple" | "current" => src.clone(),
+        "upstream" | "tracking" => {
+            let merge = config.get(&format!("branch.{branch}.merge"));
+            let Some(merge) = merge else {
+                bail!("The current branch {branch} has no upstream branch.");
+            };
+            merge.to_string()
+        }
+        _ => bail!("bad push.default value '{mode}'"),
+    };
+    Ok(vec![Refspec {
+        force: false,
+        src,
+        dst: Some(dst),
+    }])
+}
+
+impl PushUpdate {
+    /// Update `dst` on the remote to `new_hash` (`None` to delete it), pushed from
+    /// the local ref `src`.
+    fn new(
+        repo: &Repository,
+        src: Option<String>,
+        dst: String,
+        new_hash: Option<String>,
+        remote_refs: &RemoteRefs,
+        force: bool,
+    ) -> PushUpdate {
+        let old_hash = remote_refs.refs.get(&dst).cloned();
+        let status = classify_push(repo, &dst, old_hash.as_deref(), new_hash.as_deref(), force);
+        PushUpdate {
+            src,
+            dst,
+            old_hash,
+            new_hash,
+            status,
+        }
+    }
+}
+
+/// The updates a refspec stands for; `force` forces them even without a `+`.
+fn resolve_refspec(
+    repo: &Repository,
+    refspec: &Refspec,
+    remote_refs: &RemoteRefs,
+    force: bool,
+) -> Result<Vec<PushUpdate>> {
+    let force = force || refspec.force;
+    if refspec.src.contains('*') {
+        let mut updates = Vec::new();
+        for (name, hash) in list_refs(repo, "refs/")? {
+            if let Some(dst) = refspec.map_to_destination(&name) {
+                updates.push(PushUpdate::new(
+                    repo,
+                    Some(name),
+                    dst,
+                    Some(hash),
+                    remote_refs,
+                    force,
+                ));
+            }
+        }
+        return Ok(updates);
+    }
+    if refspec.src.is_empty() {
+        let dst = refspec.dst.as_deref().unwrap_or("");
+        let Some(dst) = remote_ref_name(dst, remote_refs) else {
+            bail!("unable to delete '{dst}': remote ref does not exist");
+        };
+        return Ok(vec![PushUpdate::new(
+            repo,
+            None,
+            dst,
+            None,
+            remote_refs,
+            force,
+        )]);
+    }
+    let (src, hash) = match expand_ref(repo, &refspec.src)? {
+        Some((name, hash)) => (name, hash),
+        None if refspec.dst.is_some()
+            && repo.hash_algorithm()?.is_hex_id(&refspec.src)
+            && has_object(&repo.objects_dir(), &refspec.src) =>
+        {
+            (refspec.src.clone(), refspec.src.clone())
+        }
+        None => bail!("src refspec {} does not match any", refspec.src),
+    };
+    let dst = match refspec.dst.as_deref() {
+        // Pushing HEAD updates the branch of the same name as the current one.
+        None if src == "HEAD" => match symbolic_ref_target(repo, "HEAD")? {
+            branch if branch.starts_with("refs/heads/") => branch,
+            _ => bail!("HEAD is detached; say which remote ref to push it to"),
+        },
+        None => src.clone(),
+        Some(dst) if dst.starts_with("refs/") => dst.to_string(),
+        Some(dst) => match remote_ref_name(dst, remote_refs) {
+            Some(existing) => existing,
+            None if src.starts_with("refs/heads/") => format!("refs/heads/{dst}"),
+            None if src.starts_with("refs/tags/") => format!("refs/tags/{dst}"),
+            None => bail!(
+                "The destination you provided is not a full refname (i.e.,\n\
+                 starting with \"refs/\"). Unable to push to '{dst}'."
+            ),
+        },
+    };
+    Ok(vec![PushUpdate::new(
+        repo,
+        Some(src),
+        dst,
+        Some(hash),
+        remote_refs,
+        force,
+    )])
+}
+
+/// The full name of the remote ref `name` refers to, if it exists.
+fn remote_ref_name(name: &str, remote_refs: &RemoteRefs) -> Option<String> {
+    [
+        name.to_string(),
+        format!("refs/heads/{name}"),
+        format!("refs/tags/{name}"),
+    ]
+    .into_iter()
+    .find(|candidate| remote_refs.refs.contains_key(candidate))
+}
+
+fn classify_push(
+    repo: &Repository,
+    dst: &str,
+    old_hash: Option<&str>,
+    new_hash: Option<&str>,
+    force: bool,
+) -> PushStatus {
+    let (old_hash, new_hash) = match (old_hash, new_hash) {
+        (_, None) => return PushStatus::Deleted,
+        (None, Some(_)) => return PushStatus::New,
+        (Some(old_hash), Some(new_hash)) => (old_hash, new_hash),
+    };
+    if old_hash == new_hash {
+        PushStatus::UpToDate
+    } else if force {
+        PushStatus::Forced
+    } else if dst.starts_with("refs/tags/") {
+        PushStatus::Rejected("already exists")
+    } else if !has_object(&repo.objects_dir(), old_hash) {
+        PushStatus::Rejected("fetch first")
+    } else {
+        match ahead_behind(repo, new_hash, old_hash) {
+            Ok((_, 0)) => PushStatus::FastForward,
+            _ => PushStatus::Rejected("non-fast-forward"),
+        }
+    }
+}
+
+/// Record a successful update in the remote-tracking ref that fetching `update.dst`
+/// would store it in, if any.
+fn update_tracking_ref(
+    repo: &Repository,
+    config: &Config,
+    remote: &Remote,
+    update: &PushUpdate,
+) -> Result<()> {
+    let Some(tracking_ref) = remote.tracking_ref(&update.dst) else {
+        return Ok(());
+    };
+    match &update.new_hash {
+        Some(new_hash) => update_ref(repo, config, &tracking_ref, new_hash, "update by push"),
+        None => delete_ref(repo, &tracking_ref),
+    }
+}
+
+/// The line git prints for an update, e.g. ` * [new branch]      main -> main`.
+fn status_line(update: &PushUpdate) -> Option<String> {
+    let abbrev = |hash: &Option<String>| hash.as_deref().map_or("", |h| &h[..7]).to_string();
+    let (code, summary, message) = match &update.status {
+        PushStatus::UpToDate => return None,
+        PushStatus::New => {
+            let summary = if update.dst.starts_with("refs/tags/") {
+                "[new tag]"
+            } else if update.dst.starts_with("refs/heads/") {
+                "[new branch]"
+            } else {
+                "[new reference]"
+            };
+            ('*', summary.to_string(), None)
+        }
+        PushStatus::Deleted => ('-', "[deleted]".to_string(), None),
+        PushStatus::FastForward => (
+            ' ',
+            format!("{}..{}", abbrev(&update.old_hash), abbrev(&update.new_hash)),
+            None,
+        ),
+        PushStatus::Forced => (
+            '+',
+            format!(
+                "{}...{}",
+                abbrev(&update.old_hash),
+                abbrev(&update.new_hash)
+            ),
+            Some("forced update".to_string()),
+        ),
+        PushStatus::Rejected(reason) => ('!', "[rejected]".to_string(), Some(reason.to_string())),
+        PushStatus::AtomicFailed => (
+            '!',
+            "[rejected]".to_string(),
+            Some("atomic push failed".to_string()),
+        ),
+        PushStatus::RemoteRejected(reason) => {
+            ('!', "[remote rejected]".to_string(), Some(reason.clone()))
+        }
+    };
+    let dst = shorten_ref(&update.dst);
+    let mut line = match &update.src {
+        Some(src) if update.status != PushStatus::Deleted => {
+            format!(" {code} {summary:<17} {} -> {dst}", shorten_ref(src))
+        }
+        _ => format!(" {code} {summary:<17} {dst}"),
+    };
+    if let Some(message) = message {
+        line.push_str(&format!(" ({message})"));
+    }
+    Some(line)
+}
+
+/// git's advice on how to get a refused update through, for the first kind of
+/// rejection that has any.
+fn rejection_hint(repo: &Repository, failed: &[&PushUpdate]) -> Result<Option<&'static str>> {
+    let current = current_branch(repo)?.map(|branch| format!("refs/heads/{branch}"));
+    for update in failed {
+        let hint = match update.status {
+            PushStatus::Rejected("non-fast-forward") if update.src == current => {
+                "Updates were rejected because the tip of your current branch is behind\n\
+                 its remote counterpart. Integrate the remote changes (e.g.\n\
+                 'git pull ...') before pushing again.\n\
+                 See the 'Note about fast-forwards' in 'git push --help' for details."
+            }
+            PushStatus::Rejected("non-fast-forward") => {
+                "Updates were rejected because a pushed branch tip is behind its remote\n\
+                 counterpart. Check out this branch and integrate the remote changes\n\
+                 (e.g. 'git pull ...') before pushing again.\n\
+                 See the 'Note about fast-forwards' in 'git push --help' for details."
+            }
+            PushStatus::Rejected("fetch first") => {
+                "Updates were rejected because the remote contains work that you do\n\
+                 not have locally. This is usually caused by another repository pushing\n\
+                 to the same ref. You may want to first integrate the remote changes\n\
+                 (e.g., 'git pull ...') before pushing again.\n\
+                 See the 'Note about fast-forwards' in 'git push --help' for details."
+            }
+            PushStatus::Rejected("already exists") => {
+                "Updates were rejected because the tag already exists in the remote."
+            }
+            _ => continue,
+        };
+        return Ok(Some(hint));
+    }
+    Ok(None)
+}
@@ -0,0 +1,560 @@
+use crate::commit_read::is_ancestor_in;
+use crate::config::Config;
+use crate::gpg::{PGP_SIGNATURE_START, SignatureCheck, verify_signed_buffer};
+use crate::hooks::Hooks;
+use crate::ident::now_and_timezone;
+use crate::object_id::HashAlgorithm;
+use crate::object_read::{Object, ObjectKind};
+use crate::odb::{LooseOdb, Odb};
+use crate::refs::{RefTransaction, ref_namespace, symbolic_ref_target_in};
+use crate::repository::{Repository, git_path, hash_algorithm_in};
+use crate::transfer::{copy_objects_into, find_missing_object, migrate_objects};
+use anyhow::{Context, Result, bail};
+use sha1::{Digest, Sha1};
+use std::path::Path;
+
+/// A ref update a push asks for: `ref_name` from `old_hash` to `new_hash`, where
+/// `None` stands for a ref that doesn't exist before (creation) or after (deletion).
+#[derive(Debug, Clone)]
+pub struct ReceiveCommand {
+    pub ref_name: String,
+    pub old_hash: Option<String>,
+    pub new_hash: Option<String>,
+}
+
+impl ReceiveCommand {
+    /// `<old> <new> <ref>`, the command as it's sent and listed in push certificates,
+    /// with the null id of `algorithm` for a ref that doesn't exist.
+    pub fn line(&self, algorithm: HashAlgorithm) -> String {
+        let null = algorithm.null_id().to_string();
+        format!(
+            "{} {} {}",
+            self.old_hash.as_deref().unwrap_or(&null),
+            self.new_hash.as_deref().unwrap_or(&null),
+            self.ref_name
+        )
+    }
+}
+
+/// Everything a pushing client sends to the receiving repository.
+#[derive(Debug)]
+pub struct ReceiveRequest<'a> {
+    /// The `objects` directory the pushed objects are copied from.
+    pub objects: &'a Path,
+    pub commands: Vec<ReceiveCommand>,
+    /// Apply all commands or none of them.
+    pub atomic: bool,
+    /// Strings given with `push --push-option`, passed on to the receiving hooks.
+    pub push_options: Vec<String>,
+    /// A signed push certificate listing the commands, from `push --signed`.
+    pub push_cert: Option<String>,
+    /// The nonce the repository handed out for this push (see [`push_cert_nonce`]),
+    /// which the certificate must repeat.
+    pub nonce: Option<String>,
+}
+
+/// The receiving side of a push into the repository at `git_dir`, like
+/// `git receive-pack`: store the pushed objects and update the refs, returning for
+/// each command whether it was applied or why it was refused.
+///
+/// Push options and atomic pushes are only accepted if the repository advertises
+/// them (`receive.advertisePushOptions`, `receive.advertiseAtomic`), as in git.
+///
+/// A push certificate is stored as a blob, so that it stays on record, and every
+/// command is refused unless its signature is good and it carries the nonce handed
+/// out for this push.
+///
+/// Before any ref is touched the `pre-receive` hook gets all the commands, as
+/// `<old> <new> <ref>` lines, and can refuse the whole push, then the `update` hook
+/// (arguments `<ref> <old> <new>`) can refuse each command. The push options and
+/// what was found about the certificate are passed to `pre-receive` in the
+/// environment, as git's `GIT_PUSH_OPTION_*` and `GIT_PUSH_CERT*` variables.
+///
+/// With `GIT_NAMESPACE` set, the refs pushed are those of that namespace (see
+/// [`ref_namespace`]): hooks and messages name them as the client does, while the
+/// refs updated, and checked against HEAD, are the namespaced ones.
+///
+/// The pushed objects are first received into a quarantine directory inside
+/// `objects`, which the hooks are pointed at (`GIT_QUARANTINE_PATH`), and only moved
+/// into the repository once they are complete and some command was accepted.
+pub fn receive_pack(
+    repo: &Repository,
+    git_dir: &Path,
+    request: &ReceiveRequest,
+) -> Result<Vec<Result<(), String>>> {
+    let config = Config::load_repository(git_dir)?;
+    check_capabilities(&config, request.atomic, !request.push_options.is_empty())?;
+    let cert = match &request.push_cert {
+        Some(cert) => Some(check_push_cert(git_dir, &config, request, cert)?),
+        None => None,
+    };
+    if let Some(reason) = cert.as_ref().and_then(PushCertCheck::rejection) {
+        remote_error(&reason);
+        let refused = Err("invalid push certificate".to_string());
+        return Ok(vec![refused; request.commands.len()]);
+    }
+
+    // Pushed objects are kept apart until the push is accepted, so that a refused one
+    // leaves nothing behind.
+    let objects = git_path(git_dir, "objects");
+    let algorithm = hash_algorithm_in(&objects)?;
+    let quarantine = tempfile::Builder::new()
+        .prefix("tmp_objdir-incoming-")
+        .tempdir_in(&objects)
+        .with_context(|| {
+            format!(
+                "unable to create temporary object directory in {}",
+                objects.display()
+            )
+        })?;
+    let tips: Vec<String> = request
+        .commands
+        .iter()
+        .filter_map(|command| command.new_hash.clone())
+        .collect();
+    copy_objects_into(repo, request.objects, quarantine.path(), &[&objects], &tips)?;
+    if let Some(missing) = find_missing_object(repo, quarantine.path(), &objects, &tips)? {
+        remote_error(&format!("object {missing} is missing"));
+        let refused = Err("missing necessary objects".to_string());
+        return Ok(vec![refused; request.commands.len()]);
+    }
+
+    let hooks = Hooks::for_receive(&config, git_dir);
+    // Hooks see the quarantined objects as if they were in the repository already.
+    let quarantine_path = quarantine.path().canonicalize()?.display().to_string();
+    let env = vec![
+        ("GIT_DIR".to_string(), ".".to_string()),
+        ("GIT_QUARANTINE_PATH".to_string(), quarantine_path.clone()),
+        ("GIT_OBJECT_DIRECTORY".to_string(), quarantine_path),
+        (
+            "GIT_ALTERNATE_OBJECT_DIRECTORIES".to_string(),
+            objects.canonicalize()?.display().to_string(),
+        ),
+    ];
+    let mut hook_env = env.clone();
+    // A client always sends push options, maybe none, to a repository that accepts them.
+    if config
+        .get_bool("receive.advertisePushOptions")?
+        .unwrap_or(false)
+    {
+        hook_env.push((
+            "GIT_PUSH_OPTION_COUNT".to_string(),
+            request.push_options.len().to_string(),
+        ));
+        for (i, option) in request.push_options.iter().enumerate() {
+            hook_env.push((format!("GIT_PUSH_OPTION_{i}"), option.clone()));
+        }
+    }
+    if let Some(cert) = &cert {
+        hook_env.extend(cert.env());
+    }
+    let lines: String = request
+        .commands
+        .iter()
+        .map(|command| command.line(algorithm) + "\n")
+        .collect();
+    if !hooks.run("pre-receive", &[], &lines, &hook_env)? {
+        let refused = Err("pre-receive hook declined".to_string());
+        return Ok(vec![refused; request.commands.len()]);
+    }
+    let namespace = ref_namespace()?;
+    let policy = RefPolicy::load(git_dir, &config, &namespace)?;
+    let quarantine_odb = LooseOdb::new(quarantine.path());
+    let repository_odb = LooseOdb::new(&objects);
+    let mut results = Vec::new();
+    for command in &request.commands {
+        if let Err(reason) = policy.check(&[&quarantine_odb, &repository_odb], command)? {
+            results.push(Err(reason));
+            continue;
+        }
+        let null = algorithm.null_id().to_string();
+        let old_hash = command.old_hash.as_deref().unwrap_or(&null);
+        let new_hash = command.new_hash.as_deref().unwrap_or(&null);
+        let args = [command.ref_name.as_str(), old_hash, new_hash];
+        results.push(if hooks.run("update", &args, "", &env)? {
+            Ok(())
+        } else {
+            remote_error(&format!("hook declined to update {}", command.ref_name));
+            Err("hook declined".to_string())
+        });
+    }
+
+    if request.atomic && results.iter().any(Result::is_err) {
+        for result in &mut results {
+            if result.is_ok() {
+                *result = Err("atomic push failure".to_string());
+            }
+        }
+    }
+    if results.iter().all(Result::is_err) {
+        return Ok(results);
+    }
+    migrate_objects(quarantine.path(), &objects)?;
+
+    if request.atomic {
+        let mut transaction = RefTransaction::new(git_dir);
+        for command in &request.commands {
+            queue_command(&mut transaction, &namespace, command);
+        }
+        let result = transaction.commit(&config).map_err(|e| {
+            remote_error(&format!("{e:#}"));
+            "atomic transaction failed".to_string()
+        });
+        return Ok(vec![result; request.commands.len()]);
+    }
+    for (command, result) in request.commands.iter().zip(&mut results) {
+        if result.is_err() {
+            continue;
+        }
+        let mut transaction = RefTransaction::new(git_dir);
+        queue_command(&mut transaction, &namespace, command);
+        *result = transaction.commit(&config).map_err(|e| {
+            remote_error(&format!("{e:#}"));
+            "failed to update ref".to_string()
+        });
+    }
+    Ok(results)
+}
+
+/// A `receive.deny*` setting: `refuse` (or true), `warn`, `ignore` (or false) or
+/// `updateInstead`, or unset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Deny {
+    Unconfigured,
+    Ignore,
+    Warn,
+    Refuse,
+    UpdateInstead,
+}
+
+impl Deny {
+    fn get(config: &Config, key: &str) -> Result<Deny> {
+        let Some(value) = config.get(key) else {
+            return Ok(Deny::Unconfigured);
+        };
+        Ok(match value.to_ascii_lowercase().as_str() {
+            "ignore" | "false" | "no" | "off" | "0" => Deny::Ignore,
+            "warn" => Deny::Warn,
+            "refuse" | "true" | "yes" | "on" | "1" => Deny::Refuse,
+            "updateinstead" => Deny::UpdateInstead,
+            _ => bail!("bad {key} value '{value}'"),
+        })
+    }
+}
+
+const REFUSE_CURRENT_BRANCH_HELP: &str = "\
+By default, updating the current branch in a non-bare repository
+is denied, because it will make the index and work tree inconsistent
+with what you pushed, and will require 'git reset --hard' to match
+the work tree to HEAD.
+
+You can set the 'receive.denyCurrentBranch' configuration variable
+to 'ignore' or 'warn' in the remote repository to allow pushing into
+its current branch; however, this is not recommended unless you
+arranged to update its work tree to match what you pushed in some
+other way.
+
+To squelch this message and still keep the default behaviour, set
+'receive.denyCurrentBranch' configuration variable to 'refuse'.";
+
+const REFUSE_DELETE_CURRENT_HELP: &str = "\
+By default, deleting the current branch is denied, because the next
+'git clone' won't result in any file checked out, causing confusion.
+
+You can set 'receive.denyDeleteCurrent' configuration variable to
+'warn' or 'ignore' in the remote repository to allow deleting the
+current branch, with or without a warning message.
+
+To squelch this message, you can set it to 'refuse'.";
+
+/// Which ref updates a repository refuses to receive, from its configuration.
+struct RefPolicy {
+    /// The branch HEAD points at.
+    head: String,
+    /// The prefix of the refs updated, from [`ref_namespace`].
+    namespace: String,
+    bare: bool,
+    /// `receive.denyCurrentBranch`: updates of the checked out branch of a non-bare
+    /// repository, refused by default.
+    current_branch: Deny,
+    /// `receive.denyDeleteCurrent`: deleting the branch HEAD points at, refused by
+    /// default.
+    delete_current: Deny,
+    /// `receive.denyDeletes`: deleting any branch.
+    deletes: bool,
+    /// `receive.denyNonFastForwards`: updating a branch to a commit that doesn't
+    /// contain the old one, even when the push is forced.
+    non_fast_forwards: bool,
+}
+
+impl RefPolicy {
+    fn load(git_dir: &Path, config: &Config, namespace: &str) -> Result<RefPolicy> {
+        Ok(RefPolicy {
+            head: symbolic_ref_target_in(git_dir, "HEAD")?,
+            namespace: namespace.to_string(),
+            bare: config.get_bool("core.bare")?.unwrap_or(false),
+            current_branch: Deny::get(config, "receive.denyCurrentBranch")?,
+            delete_current: Deny::get(config, "receive.denyDeleteCurrent")?,
+            deletes: config.get_bool("receive.denyDeletes")?.unwrap_or(false),
+            non_fast_forwards: config
+                .get_bool("receive.denyNonFastForwards")?
+                .unwrap_or(false),
+        })
+    }
+
+    /// Whether the policy allows `command`, checked in git's order; the inner error is
+    /// the reason it's refused, after the remote's explanation was printed.
+    /// Commits are read from the first of `odbs` that has them.
+    fn check(&self, odbs: &[&dyn Odb], command: &ReceiveCommand) -> Result<Result<(), String>> {
+        let name = command.ref_name.as_str();
+        let is_branch = name.starts_with("refs/heads/");
+        let is_head = format!("{}{name}", self.namespace) == self.head;
+        if !self.bare && is_head {
+            match self.current_branch {
+                Deny::Ignore => {}
+                Deny::Warn => remote_warning("updating the current branch"),
+                Deny::Refuse | Deny::Unconfigured => {
+                    remote_error(&format!("refusing to update checked out branch: {name}"));
+                    if self.current_branch == Deny::Unconfigured {
+                        remote_error(REFUSE_CURRENT_BRANCH_HELP);
+                    }
+                    return Ok(Err("branch is currently checked out".to_string()));
+                }
+                Deny::UpdateInstead => {
+                    remote_error("receive.denyCurrentBranch=updateInstead isn't supported yet");
+                    return Ok(Err("branch is currently checked out".to_string()));
+                }
+            }
+        }
+        match (&command.old_hash, &command.new_hash) {
+            (Some(_), None) => {
+                if self.deletes && is_branch {
+                    remote_error(&format!("denying ref deletion for {name}"));
+                    return Ok(Err("deletion prohibited".to_string()));
+                }
+                if is_head {
+                    match self.delete_current {
+                        Deny::Ignore => {}
+                        Deny::Warn => remote_warning("deleting the current branch"),
+                        _ => {
+                            if self.delete_current == Deny::Unconfigured {
+                                remote_error(REFUSE_DELETE_CURRENT_HELP);
+                            }
+                            remote_error(&format!("refusing to delete the current branch: {name}"));
+                            return Ok(
+                                Err("deletion of the current branch prohibited".to_string()),
+                            );
+                        }
+                    }
+                }
+            }
+            (Some(old_hash), Some(new_hash))
+                if self.non_fast_forwards
+                    && is_branch
+                    && !is_ancestor_in(odbs, old_hash, new_hash)? =>
+            {
+                remote_error(&format!(
+                    "denying non-fast-forward {name} (you should pull first)"
+                ));
+                return Ok(Err("non-fast-forward".to_string()));
+            }
+            _ => {}
+        }
+        Ok(Ok(()))
+    }
+}
+
+/// Print `message` as an error from the receiving end, the way it reaches the user
+/// there: every line prefixed with `remote: `, the first with `error: ` too.
+fn remote_error(message: &str) {
+    remote_message("error", message);
+}
+
+fn remote_warning(message: &str) {
+    remote_message("warning", message);
+}
+
+fn remote_message(level: &str, message: &str) {
+    for (i, line) in message.lines().enumerate() {
+        match i {
+            0 => eprintln!("remote: {level}: {line}"),
+            _ => eprintln!("remote: {line}"),
+        }
+    }
+}
+
+/// Fail unless the repository whose configuration is `config` supports atomic pushes
+/// and push options, if they are wanted. A client checks this before sending anything.
+pub fn check_capabilities(config: &Config, atomic: bool, push_options: bool) -> Result<()> {
+    if push_options
+        && !config
+            .get_bool("receive.advertisePushOptions")?
+            .unwrap_or(false)
+    {
+        bail!("the receiving end does not support push options");
+    }
+    if atomic && config.get_bool("receive.advertiseAtomic")? == Some(false) {
+        bail!("the receiving end does not support --atomic push");
+    }
+    Ok(())
+}
+
+/// The nonce to hand a client for signing a push certificate, or `None` if signed
+/// pushes aren't accepted (`receive.certNonceSeed` is unset). Like git's, it is the
+/// time followed by an HMAC of the repository path and time keyed with the seed, so
+/// it can't be forged or reused for another repository.
+pub fn push_cert_nonce(git_dir: &Path, config: &Config) -> Option<String> {
+    let seed = config.get("receive.certNonceSeed")?;
+    let (stamp, _) = now_and_timezone();
+    let mac = hmac_sha1(
+        seed.as_bytes(),
+        format!("{}:{stamp}", git_dir.display()).as_bytes(),
+    );
+    Some(format!("{stamp}-{}", hex::encode(mac)))
+}
+
+/// What checking a push certificate found.
+struct PushCertCheck {
+    /// The blob the certificate was stored as.
+    blob: String,
+    signature: SignatureCheck,
+    /// The nonce the certificate carries.
+    nonce: Option<String>,
+    /// How that nonce compares to the one handed out: `MISSING` from the
+    /// certificate, `UNSOLICITED` (none was handed out), `OK` or `BAD`.
+    nonce_status: &'static str,
+}
+
+impl PushCertCheck {
+    /// Why the certificate isn't accepted, if it isn't.
+    fn rejection(&self) -> Option<String> {
+        let blob = &self.blob;
+        let signer = self
+            .signature
+            .signer
+            .as_deref()
+            .unwrap_or("an unknown signer");
+        let key = self.signature.key.as_deref().unwrap_or("unknown");
+        match self.signature.status {
+            'N' => Some(format!("push certificate {blob} is not signed")),
+            'G' | 'U' if self.nonce_status == "OK" => None,
+            'G' | 'U' => Some(format!(
+                "push certificate {blob} from {signer} (key {key}) has nonce status {}",
+                self.nonce_status
+            )),
+            status => Some(format!(
+                "push certificate {blob} from {signer} (key {key}) has an invalid signature ({status})"
+            )),
+        }
+    }
+
+    /// The `GIT_PUSH_CERT*` variables receiving hooks get, as in git.
+    fn env(&self) -> Vec<(String, String)> {
+        let mut env = vec![
+            ("GIT_PUSH_CERT".to_string(), self.blob.clone()),
+            (
+                "GIT_PUSH_CERT_SIGNER".to_string(),
+                self.signature.signer.clone().unwrap_or_default(),
+            ),
+            (
+                "GIT_PUSH_CERT_KEY".to_string(),
+                self.signature.key.clone().unwrap_or_default(),
+            ),
+            (
+                "GIT_PUSH_CERT_STATUS".to_string(),
+                self.signature.status.to_string(),
+            ),
+        ];
+        if let Some(nonce) = &self.nonce {
+            env.push(("GIT_PUSH_CERT_NONCE".to_string(), nonce.clone()));
+            env.push((
+                "GIT_PUSH_CERT_NONCE_STATUS".to_string(),
+                self.nonce_status.to_string(),
+            ));
+        }
+        env
+    }
+}
+
+/// Record a push certificate as a blob and check its signature and nonce, failing
+/// if it doesn't list the pushed commands.
+fn check_push_cert(
+    git_dir: &Path,
+    config: &Config,
+    request: &ReceiveRequest,
+    cert: &str,
+) -> Result<PushCertCheck> {
+    let mut blob = Object {
+        reader: cert.as_bytes(),
+        kind: ObjectKind::Blob,
+        expected_size: cert.len() as u64,
+    };
+    let blob = blob
+        .write_as_object_in(&git_path(git_dir, "objects"))?
+        .to_string();
+
+    let (payload, signature) = cert.split_at(cert.find(PGP_SIGNATURE_START).unwrap_or(cert.len()));
+    let (headers, commands) = payload.split_once("\n\n").unwrap_or((payload, ""));
+    let algorithm = hash_algorithm_in(&git_path(git_dir, "objects"))?;
+    let expected: Vec<String> = request
+        .commands
+        .iter()
+        .map(|command| command.line(algorithm))
+        .collect();
+    if commands.lines().ne(expected.iter().map(String::as_str)) {
+        bail!("push certificate {blob} does not match the pushed commands");
+    }
+
+    let nonce = headers.lines().find_map(|line| line.strip_prefix("nonce "));
+    let nonce_status = match (nonce, request.nonce.as_deref()) {
+        (None, _) => "MISSING",
+        (Some(_), None) => "UNSOLICITED",
+        (Some(nonce), Some(expected)) if nonce == expected => "OK",
+        (Some(_), Some(_)) => "BAD",
+    };
+    let signature = if signature.is_empty() {
+        SignatureCheck {
+            status: 'N',
+            signer: None,
+            key: None,
+        }
+    } else {
+        verify_signed_buffer(config, payload, signature)?
+    };
+    Ok(PushCertCheck {
+        blob,
+        signature,
+        nonce: nonce.map(str::to_string),
+        nonce_status,
+    })
+}
+
+/// HMAC-SHA1 (RFC 2104) of `message` keyed with `key`.
+fn hmac_sha1(key: &[u8], message: &[u8]) -> [u8; 20] {
+    const BLOCK_SIZE: usize = 64;
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..20].copy_from_slice(&Sha1::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.map(|b| b ^ byte);
+    let inner = Sha1::new()
+        .chain_update(pad(0x36))
+        .chain_update(message)
+        .finalize();
+    Sha1::new()
+        .chain_update(pad(0x5c))
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+fn queue_command(transaction: &mut RefTransaction, namespace: &str, command: &ReceiveCommand) {
+    transaction.update(
+        &format!("{namespace}{}", command.ref_name),
+        command.old_hash.as_deref(),
+        command.new_hash.as_deref(),
+        "push",
+    );
+}
@@ -0,0 +1,741 @@
+use crate::config::Config;
+use crate::ident::{Role, resolve_ident};
+use crate::repository::{Repository, git_path, hash_algorithm_in};
+use anyhow::{Context, Result, bail};
+use std::collections::HashSet;
+use std::env;
+use std::fs::{
+    OpenOptions, create_dir_all, read_dir, read_to_string, remove_dir, remove_file, rename, write,
+};
+use std::io::{ErrorKind, Write};
+use std::mem::take;
+use std::path::{Path, PathBuf};
+
+/// Places a short ref name is looked up, in the order `git rev-parse` uses.
+const REF_SEARCH_PATHS: [&str; 6] = [
+    "{}",
+    "refs/{}",
+    "refs/tags/{}",
+    "refs/heads/{}",
+    "refs/remotes/{}",
+    "refs/remotes/{}/HEAD",
+];
+
+/// Resolve a ref name to the object id it points to.
+///
+/// Accepts `HEAD`, full ref names like `refs/heads/main`, and short names like `main`,
+/// `v1.0` or `origin/main`, which are searched for under `refs/`, `refs/tags/`,
+/// `refs/heads/` and `refs/remotes/`. Symbolic refs (`ref: refs/heads/main`) are
+/// followed. Returns `None` if no ref with that name exists, or it is unborn.
+pub fn resolve_ref(repo: &Repository, name: &str) -> Result<Option<String>> {
+    Ok(expand_ref(repo, name)?.map(|(_, hash)| hash))
+}
+
+/// Resolve the full ref name `name` (e.g. `HEAD`) in the repository at `git_dir`.
+pub fn resolve_ref_in(git_dir: &Path, name: &str) -> Result<Option<String>> {
+    read_ref_in(git_dir, name, 0)
+}
+
+/// Like [`resolve_ref`], but also returns the full name of the ref that was found,
+/// e.g. `refs/remotes/origin/main` for `origin/main`.
+pub fn expand_ref(repo: &Repository, name: &str) -> Result<Option<(String, String)>> {
+    if name.is_empty() || name.contains("..") || name.starts_with('/') {
+        return Ok(None);
+    }
+    for pattern in REF_SEARCH_PATHS {
+        let ref_name = pattern.replace("{}", name);
+        if let Some(hash) = read_ref(repo, &ref_name, 0)? {
+            return Ok(Some((ref_name, hash)));
+        }
+    }
+    Ok(None)
+}
+
+/// Every ref under `prefix` (e.g. `refs/tags/`) with the object id it points to,
+/// sorted by name. Symbolic refs are followed; dangling ones are left out.
+pub fn list_refs(repo: &Repository, prefix: &str) -> Result<Vec<(String, String)>> {
+    list_refs_in(&repo.git_dir, prefix)
+}
+
+/// Like [`list_refs`], for the repository at `git_dir` (e.g. a local remote).
+pub fn list_refs_in(git_dir: &Path, prefix: &str) -> Result<Vec<(String, String)>> {
+    let mut refs = Vec::new();
+    let loose = loose_ref_names(git_dir, prefix)?;
+    for name in &loose {
+        if let Some(hash) = read_ref_in(git_dir, name, 0)? {
+            refs.push((name.clone(), hash));
+        }
+    }
+    // A loose ref takes the place of a packed one of the same name.
+    let loose: HashSet<&String> = loose.iter().collect();
+    let prefix = format!("{}/", prefix.trim_end_matches('/'));
+    for packed in read_packed_refs(git_dir)? {
+        if packed.name.starts_with(&prefix) && !loose.contains(&packed.name) {
+            refs.push((packed.name, packed.hash));
+        }
+    }
+    refs.sort();
+    Ok(refs)
+}
+
+/// The names of the ref files under `prefix`, in no particular order.
+pub fn loose_ref_names(git_dir: &Path, prefix: &str) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    let mut dirs = vec![prefix.trim_end_matches('/').to_string()];
+    while let Some(dir) = dirs.pop() {
+        let path = git_path(git_dir, &dir);
+        let entries = match read_dir(&path) {
+            Ok(entries) => entries,
+            Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) => {
+                continue;
+            }
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        for entry in entries {
+            let entry = entry.with_context(|| format!("failed to read {}", path.display()))?;
+            let name = format!("{dir}/{}", entry.file_name().to_string_lossy());
+            if entry.file_type()?.is_dir() {
+                dirs.push(name);
+            } else if !name.ends_with(".lock") {
+                names.push(name);
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// A ref recorded in `packed-refs`: its name, the object id it has and, for an
+/// annotated tag, the object the tag leads to once peeled.
+pub struct PackedRef {
+    pub name: String,
+    pub hash: String,
+    pub peeled: Option<String>,
+}
+
+/// The header `packed-refs` files are written with: every annotated tag has its
+/// peeled value recorded, and the refs are sorted by name.
+const PACKED_REFS_HEADER: &str = "# pack-refs with: peeled fully-peeled sorted \n";
+
+/// The refs of `<git_dir>/packed-refs`, in the order they are in the file; none if
+/// there is no such file.
+///
+/// Refs are packed into this one file by `git pack-refs` (and `git gc`, and clones)
+/// so that repositories with many refs don't need a file for each. A loose ref file
+/// of the same name takes precedence over a packed ref, so a ref is updated by
+/// writing a loose file and only needs removing from here when it is deleted.
+pub fn read_packed_refs(git_dir: &Path) -> Result<Vec<PackedRef>> {
+    let path = git_path(git_dir, "packed-refs");
+    let content = match read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+    };
+    let algorithm = hash_algorithm_in(&git_path(git_dir, "objects"))?;
+    let mut refs: Vec<PackedRef> = Vec::new();
+    for line in content.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+        if let Some(peeled) = line.strip_prefix('^') {
+            match refs.last_mut() {
+                Some(last) if algorithm.is_hex_id(peeled) && last.peeled.is_none() => {
+                    last.peeled = Some(peeled.to_string());
+                }
+                _ => bail!("unexpected line in {}: {line}", path.display()),
+            }
+            continue;
+        }
+        match line.split_once(' ') {
+            Some((hash, name)) if algorithm.is_hex_id(hash) && !name.is_empty() => {
+                refs.push(PackedRef {
+                    name: name.to_string(),
+                    hash: hash.to_string(),
+                    peeled: None,
+                });
+            }
+            _ => bail!("unexpected line in {}: {line}", path.display()),
+        }
+    }
+    Ok(refs)
+}
+
+/// Replace `<git_dir>/packed-refs` with `refs`, sorted by name, through its lock
+/// file; the file is removed if there are none.
+pub fn write_packed_refs(git_dir: &Path, mut refs: Vec<PackedRef>) -> Result<()> {
+    let lock = lock_ref(git_dir, "packed-refs")?;
+    if refs.is_empty() {
+        let path = git_path(git_dir, "packed-refs");
+        let result = match remove_file(&path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("failed to delete {}", path.display()))
+            }
+            _ => Ok(()),
+        };
+        let _ = remove_file(&lock);
+        return result;
+    }
+    refs.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut content = PACKED_REFS_HEADER.to_string();
+    for packed in &refs {
+        content.push_str(&format!("{} {}\n", packed.hash, packed.name));
+        if let Some(peeled) = &packed.peeled {
+            content.push_str(&format!("^{peeled}\n"));
+        }
+    }
+    commit_ref_lock(git_dir, "packed-refs", &lock, &content)
+}
+
+/// Where the refs of the namespace named by `GIT_NAMESPACE` (e.g. `foo/bar`) live:
+/// `refs/namespaces/foo/refs/namespaces/bar/`, or nothing without a namespace.
+///
+/// A repository serving fetches and pushes shows its clients only the refs of the
+/// namespace, without this prefix, as if they were all it had, so that several
+/// repositories can be hosted from one object store. Local commands still see every
+/// ref under its full name.
+pub fn ref_namespace() -> Result<String> {
+    let namespace = env::var("GIT_NAMESPACE").unwrap_or_default();
+    let prefix: String = namespace
+        .split('/')
+        .filter(|component| !component.is_empty())
+        .map(|component| format!("refs/namespaces/{component}/"))
+        .collect();
+    if !prefix.is_empty() && !is_valid_ref_name(prefix.trim_end_matches('/')) {
+        bail!("bad git namespace path \"{namespace}\"");
+    }
+    Ok(prefix)
+}
+
+/// The branch HEAD points at (e.g. `main`), or `None` when HEAD is detached.
+pub fn current_branch(repo: &Repository) -> Result<Option<String>> {
+    let path = repo.git_path("HEAD");
+    let head =
+        read_to_string(&path).with_context(|| format!("failed to read {}", path.display()))?;
+    Ok(head
+        .trim()
+        .strip_prefix("ref: refs/heads/")
+        .map(str::to_string))
+}
+
+/// The ref a branch tracks, from `branch.<name>.remote` and `branch.<name>.merge`:
+/// `refs/remotes/<remote>/<branch>`, or the local ref itself when the remote is `.`.
+pub fn upstream_ref(config: &Config, branch: &str) -> Option<String> {
+    let remote = config.get(&format!("branch.{branch}.remote"))?;
+    let merge = config.get(&format!("branch.{branch}.merge"))?;
+    if remote == "." {
+        return Some(merge.to_string());
+    }
+    let merged_branch = merge.strip_prefix("refs/heads/").unwrap_or(merge);
+    Some(format!("refs/remotes/{remote}/{merged_branch}"))
+}
+
+/// The shortest unambiguous way to write a full ref name the way git abbreviates it
+/// in messages: `refs/heads/main` becomes `main`, `refs/remotes/origin/main`
+/// becomes `origin/main`.
+pub fn shorten_ref(ref_name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"]
+        .iter()
+        .find_map(|prefix| ref_name.strip_prefix(prefix))
+        .unwrap_or(ref_name)
+}
+
+/// Set `ref_name` (e.g. `refs/heads/main` or `HEAD`) to `new_hash`, recording the
+/// change in its reflog with `message`. A symbolic ref is followed and its target
+/// updated instead, and the update is logged for HEAD too when HEAD points at the ref.
+/// A name `git check-ref-format` would reject, such as one a remote advertised with
+/// `..` in it, is refused rather than written outside `refs/`.
+pub fn update_ref(
+    repo: &Repository,
+    config: &Config,
+    ref_name: &str,
+    new_hash: &str,
+    message: &str,
+) -> Result<()> {
+    if !is_valid_ref_name(ref_name) {
+        bail!("refusing to update ref with bad name '{ref_name}'");
+    }
+    let git_dir = &repo.git_dir;
+    let ref_name = symbolic_ref_target_in(git_dir, ref_name)?;
+    let old_hash = read_ref(repo, &ref_name, 0)?;
+    write_ref_file(git_dir, &ref_name, &format!("{new_hash}\n"))?;
+    log_ref_update(
+        git_dir,
+        config,
+        &ref_name,
+        old_hash.as_deref(),
+        new_hash,
+        message,
+    )
+}
+
+/// Delete `ref_name` along with its reflog. Directories left empty below the ref's
+/// category (e.g. `refs/remotes/origin/` but not `refs/remotes/`) are removed too.
+pub fn delete_ref(repo: &Repository, ref_name: &str) -> Result<()> {
+    delete_ref_in(&repo.git_dir, ref_name)
+}
+
+fn delete_ref_in(git_dir: &Path, ref_name: &str) -> Result<()> {
+    let mut packed = read_packed_refs(git_dir)?;
+    if packed.iter().any(|packed| packed.name == ref_name) {
+        packed.retain(|packed| packed.name != ref_name);
+        write_packed_refs(git_dir, packed)?;
+    }
+    for base in ["", "logs/"] {
+        remove_ref_file(git_dir, &format!("{base}{ref_name}"))?;
+    }
+    Ok(())
+}
+
+/// Remove `<git_dir>/<path>`, a loose ref or reflog, if it is there, and the
+/// directories left empty below the ref's category (e.g. `refs/remotes/origin/` but
+/// not `refs/remotes/`).
+pub fn remove_ref_file(git_dir: &Path, path: &str) -> Result<()> {
+    let file = git_path(git_dir, path);
+    match remove_file(&file) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("failed to delete {}", file.display())),
+    }
+    let (base, ref_name) = match path.strip_prefix("logs/") {
+        Some(ref_name) => ("logs/", ref_name),
+        None => ("", path),
+    };
+    for dir in Path::new(ref_name).ancestors().skip(1) {
+        if dir.components().count() <= 2
+            || remove_dir(git_path(git_dir, &format!("{base}{}", dir.display()))).is_err()
+        {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Ref updates applied together, in the repository at `git_dir`: every ref is locked
+/// and checked against the value it is expected to have before any is changed, so
+/// either all updates happen or none do.
+///
+/// Locks taken by [`RefTransaction::prepare`] are released if the transaction is
+/// dropped without being committed.
+pub struct RefTransaction {
+    git_dir: PathBuf,
+    updates: Vec<RefTransactionUpdate>,
+    /// One per update once the transaction is prepared.
+    locked: Vec<LockedRef>,
+    prepared: bool,
+}
+
+struct RefTransactionUpdate {
+    ref_name: String,
+    /// The value the ref must have; `None` if it must not exist. Only checked with
+    /// `check_old`.
+    old_hash: Option<String>,
+    check_old: bool,
+    /// `None` deletes the ref. Ignored with `verify_only`, which leaves the ref as it
+    /// is.
+    new_hash: Option<String>,
+    verify_only: bool,
+    message: String,
+}
+
+/// A ref locked for an update: the ref symbolic refs led to, its lock file and the
+/// value it had when locked.
+struct LockedRef {
+    ref_name: String,
+    lock: PathBuf,
+    current: Option<String>,
+}
+
+impl RefTransaction {
+    pub fn new(git_dir: &Path) -> RefTransaction {
+        RefTransaction {
+            git_dir: git_dir.to_path_buf(),
+            updates: Vec::new(),
+            locked: Vec::new(),
+            prepared: false,
+        }
+    }
+
+    /// Queue changing `ref_name` from `old_hash` to `new_hash` (`None` for a ref that
+    /// doesn't exist before or after).
+    pub fn update(
+        &mut self,
+        ref_name: &str,
+        old_hash: Option<&str>,
+        new_hash: Option<&str>,
+        message: &str,
+    ) {
+        self.queue(ref_name, Some(old_hash), new_hash, false, message);
+    }
+
+    /// Queue setting `ref_name` to `new_hash` (`None` to delete it) whatever its value.
+    pub fn update_unchecked(&mut self, ref_name: &str, new_hash: Option<&str>, message: &str) {
+        self.queue(ref_name, None, new_hash, false, message);
+    }
+
+    /// Queue checking that `ref_name` has the value `old_hash` (`None` for none),
+    /// without changing it.
+    pub fn verify(&mut self, ref_name: &str, old_hash: Option<&str>) {
+        self.queue(ref_name, Some(old_hash), None, true, "");
+    }
+
+    fn queue(
+        &mut self,
+        ref_name: &str,
+        old_hash: Option<Option<&str>>,
+        new_hash: Option<&str>,
+        verify_only: bool,
+        message: &str,
+    ) {
+        self.updates.push(RefTransactionUpdate {
+            ref_name: ref_name.to_string(),
+            old_hash: old_hash.flatten().map(str::to_string),
+            check_old: old_hash.is_some(),
+            new_hash: new_hash.map(str::to_string),
+            verify_only,
+            message: message.to_string(),
+        });
+    }
+
+    /// Lock and check every ref, so that committing can no longer fail for a ref that
+    /// is in use or doesn't have its expected value. On failure nothing stays locked.
+    pub fn prepare(&mut self) -> Result<()> {
+        if self.prepared {
+            return Ok(());
+        }
+        let result = self.lock_all();
+        if result.is_err() {
+            self.release();
+        }
+        self.prepared = result.is_ok();
+        result
+    }
+
+    fn lock_all(&mut self) -> Result<()> {
+        let git_dir = self.git_dir.as_path();
+        let mut names = HashSet::new();
+        for update in &self.updates {
+            if !names.insert(update.ref_name.as_str()) {
+                bail!("multiple updates for ref '{}' not allowed", update.ref_name);
+            }
+        }
+        for update in &self.updates {
+            let name = &update.ref_name;
+            if !is_valid_ref_name(name) {
+                bail!("refusing to update ref with bad name '{name}'");
+            }
+            let ref_name = symbolic_ref_target_in(git_dir, name)?;
+            let lock = lock_ref(git_dir, &ref_name)?;
+            self.locked.push(LockedRef {
+                ref_name: ref_name.clone(),
+                lock,
+                current: None,
+            });
+            let current = read_ref_in(git_dir, &ref_name, 0)?;
+            if update.check_old && current != update.old_hash {
+                match (&current, &update.old_hash) {
+                    (Some(current), Some(expected)) => {
+                        bail!("cannot lock ref '{name}': is at {current} but expected {expected}")
+                    }
+                    (Some(_), None) => bail!("cannot lock ref '{name}': reference already exists"),
+                    _ => bail!("cannot lock ref '{name}': unable to resolve reference '{name}'"),
+                }
+            }
+            if let Some(locked) = self.locked.last_mut() {
+                locked.current = current;
+            }
+        }
+        Ok(())
+    }
+
+    /// Prepare the transaction if that hasn't been done, then apply all updates.
+    /// Nothing is changed if any ref can't be locked or doesn't have its expected
+    /// value.
+    pub fn commit(mut self, config: &Config) -> Result<()> {
+        self.prepare()?;
+        let locked = take(&mut self.locked);
+        for (i, (locked_ref, update)) in locked.iter().zip(&self.updates).enumerate() {
+            if let Err(e) = self.apply(config, locked_ref, update) {
+                for rest in &locked[i..] {
+                    let _ = remove_file(&rest.lock);
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(
+        &self,
+        config: &Config,
+        locked: &LockedRef,
+        update: &RefTransactionUpdate,
+    ) -> Result<()> {
+        let git_dir = self.git_dir.as_path();
+        let LockedRef {
+            ref_name,
+            lock,
+            current,
+        } = locked;
+        match &update.new_hash {
+            _ if update.verify_only => {
+                remove_file(lock).with_context(|| format!("failed to remove {}", lock.display()))
+            }
+            Some(new_hash) => {
+                commit_ref_lock(git_dir, ref_name, lock, &format!("{new_hash}\n"))?;
+                log_ref_update(
+                    git_dir,
+                    config,
+                    ref_name,
+                    current.as_deref(),
+                    new_hash,
+                    &update.message,
+                )
+            }
+            None => {
+                delete_ref_in(git_dir, ref_name)?;
+                remove_file(lock).with_context(|| format!("failed to remove {}", lock.display()))
+            }
+        }
+    }
+
+    /// Remove the locks taken so far.
+    fn release(&mut self) {
+        for locked in take(&mut self.locked) {
+            let _ = remove_file(&locked.lock);
+        }
+    }
+}
+
+impl Drop for RefTransaction {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+/// Point HEAD directly at `commit_hash`, detaching it from any branch.
+pub fn detach_head(
+    repo: &Repository,
+    config: &Config,
+    commit_hash: &str,
+    message: &str,
+) -> Result<()> {
+    let git_dir = &repo.git_dir;
+    let old_hash = read_ref(repo, "HEAD", 0)?;
+    write_ref_file(git_dir, "HEAD", &format!("{commit_hash}\n"))?;
+    append_reflog(
+        git_dir,
+        config,
+        "HEAD",
+        old_hash.as_deref(),
+        commit_hash,
+        message,
+    )
+}
+
+/// Point HEAD at `refs/heads/<branch>`, re-attaching it if it was detached.
+pub fn attach_head(repo: &Repository, config: &Config, branch: &str, message: &str) -> Result<()> {
+    let ref_name = format!("refs/heads/{branch}");
+    let old_hash = read_ref(repo, "HEAD", 0)?;
+    let Some(new_hash) = read_ref(repo, &ref_name, 0)? else {
+        bail!("branch '{branch}' does not exist");
+    };
+    let git_dir = &repo.git_dir;
+    write_ref_file(git_dir, "HEAD", &format!("ref: {ref_name}\n"))?;
+    append_reflog(
+        git_dir,
+        config,
+        "HEAD",
+        old_hash.as_deref(),
+        &new_hash,
+        message,
+    )
+}
+
+/// Log an update of `ref_name` (a ref that isn't symbolic), and for HEAD too when
+/// HEAD points at it.
+fn log_ref_update(
+    git_dir: &Path,
+    config: &Config,
+    ref_name: &str,
+    old_hash: Option<&str>,
+    new_hash: &str,
+    message: &str,
+) -> Result<()> {
+    append_reflog(git_dir, config, ref_name, old_hash, new_hash, message)?;
+    if ref_name != "HEAD" && symbolic_ref_target_in(git_dir, "HEAD")? == ref_name {
+        append_reflog(git_dir, config, "HEAD", old_hash, new_hash, message)?;
+    }
+    Ok(())
+}
+
+/// Add an entry to `<git_dir>/logs/<ref_name>`. Like git with `core.logAllRefUpdates`
+/// unset, logs are only started for HEAD and branch, remote-tracking and notes refs,
+/// and not at all in a bare repository; other refs are logged only if they already
+/// have one. `always` starts logs for every ref and `false` for none.
+fn append_reflog(
+    git_dir: &Path,
+    config: &Config,
+    ref_name: &str,
+    old_hash: Option<&str>,
+    new_hash: &str,
+    message: &str,
+) -> Result<()> {
+    let path = git_path(git_dir, &format!("logs/{ref_name}"));
+    let should_create = match config.get("core.logAllRefUpdates") {
+        Some(value) if value.eq_ignore_ascii_case("always") => true,
+        Some(_) if config.get_bool("core.logAllRefUpdates")? == Some(false) => false,
+        None if config.get_bool("core.bare")?.unwrap_or(false) => false,
+        _ => {
+            ref_name == "HEAD"
+                || ["refs/heads/", "refs/remotes/", "refs/notes/"]
+                    .iter()
+                    .any(|prefix| ref_name.starts_with(prefix))
+        }
+    };
+    if !should_create && !path.exists() {
+        return Ok(());
+    }
+    let committer = resolve_ident(Role::Committer, config)?;
+    let null = hash_algorithm_in(&git_path(git_dir, "objects"))?
+        .null_id()
+        .to_string();
+    let old_hash = old_hash.unwrap_or(&null);
+    let message = message.lines().next().unwrap_or("");
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent).with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    let mut log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    // Like git, an entry without a message has no tab either.
+    let message = if message.is_empty() {
+        String::new()
+    } else {
+        format!("\t{message}")
+    };
+    writeln!(log, "{old_hash} {new_hash} {committer}{message}")
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// The ref that `ref_name` ends up at after following symbolic refs; `ref_name`
+/// itself if it isn't symbolic (or doesn't exist).
+pub fn symbolic_ref_target(repo: &Repository, ref_name: &str) -> Result<String> {
+    symbolic_ref_target_in(&repo.git_dir, ref_name)
+}
+
+pub fn symbolic_ref_target_in(git_dir: &Path, ref_name: &str) -> Result<String> {
+    let mut ref_name = ref_name.to_string();
+    for _ in 0..=5 {
+        let path = git_path(git_dir, &ref_name);
+        let content = match read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(ref_name),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        match content.trim().strip_prefix("ref: ") {
+            Some(target) => ref_name = target.trim().to_string(),
+            None => return Ok(ref_name),
+        }
+    }
+    bail!("symbolic ref {ref_name} is nested too deeply");
+}
+
+/// Make `ref_name` a symbolic ref pointing at `target`, such as HEAD or
+/// `refs/remotes/origin/HEAD`, whether or not `target` exists yet.
+pub fn write_symbolic_ref(repo: &Repository, ref_name: &str, target: &str) -> Result<()> {
+    write_ref_file(&repo.git_dir, ref_name, &format!("ref: {target}\n"))
+}
+
+/// Replace `<git_dir>/<ref_name>` with `content` through a `.lock` file, as git does,
+/// so readers never see a half-written ref and concurrent writers fail instead of
+/// racing.
+fn write_ref_file(git_dir: &Path, ref_name: &str, content: &str) -> Result<()> {
+    let lock = lock_ref(git_dir, ref_name)?;
+    commit_ref_lock(git_dir, ref_name, &lock, content)
+}
+
+/// Take the lock on `ref_name` by creating its `.lock` file, which must not exist.
+fn lock_ref(git_dir: &Path, ref_name: &str) -> Result<PathBuf> {
+    let path = git_path(git_dir, ref_name);
+    let lock_path = PathBuf::from(format!("{}.lock", path.display()));
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent).with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    match OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&lock_path)
+    {
+        Ok(_) => Ok(lock_path),
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            bail!("unable to create '{}': File exists", lock_path.display())
+        }
+        Err(e) => Err(e).with_context(|| format!("failed to create {}", lock_path.display())),
+    }
+}
+
+/// Write `content` to the taken `lock` of `ref_name` and move it into place.
+fn commit_ref_lock(git_dir: &Path, ref_name: &str, lock: &Path, content: &str) -> Result<()> {
+    let path = git_path(git_dir, ref_name);
+    if let Err(e) = write(lock, content) {
+        let _ = remove_file(lock);
+        return Err(e).with_context(|| format!("failed to write {}", lock.display()));
+    }
+    rename(lock, &path).with_context(|| format!("failed to update {}", path.display()))
+}
+
+/// Whether `name` is acceptable as (the part after `refs/` of) a ref name, following
+/// the rules of `git check-ref-format`.
+pub fn is_valid_ref_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(['.', '-', '/'])
+        && !name.ends_with(['.', '/'])
+        && !name.ends_with(".lock")
+        && !name.contains("..")
+        && !name.contains("/.")
+        && !name.contains(".lock/")
+        && !name.contains("@{")
+        && !name.contains("//")
+        && !name
+            .chars()
+            .any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c))
+}
+
+fn read_ref(repo: &Repository, ref_name: &str, depth: usize) -> Result<Option<String>> {
+    read_ref_in(&repo.git_dir, ref_name, depth)
+}
+
+fn read_ref_in(git_dir: &Path, ref_name: &str, depth: usize) -> Result<Option<String>> {
+    if depth > 5 {
+        bail!("symbolic ref {ref_name} is nested too deeply");
+    }
+    let path = git_path(git_dir, ref_name).display().to_string();
+    let content = match read_to_string(&path) {
+        Ok(content) => content,
+        Err(e)
+            if matches!(
+                e.kind(),
+                ErrorKind::NotFound | ErrorKind::IsADirectory | ErrorKind::NotADirectory
+            ) =>
+        {
+            if !ref_name.starts_with("refs/") {
+                return Ok(None);
+            }
+            let packed = read_packed_refs(git_dir)?.into_iter();
+            return Ok(packed
+                .filter(|packed| packed.name == ref_name)
+                .map(|packed| packed.hash)
+                .next());
+        }
+        Err(e) => return Err(e).with_context(|| format!("failed to read {path}")),
+    };
+    let content = content.trim();
+    if let Some(target) = content.strip_prefix("ref: ") {
+        return read_ref_in(git_dir, target.trim(), depth + 1);
+    }
+    if !hash_algorithm_in(&git_path(git_dir, "objects"))?.is_hex_id(content) {
+        bail!("{path} does not contain a valid object id");
+    }
+    Ok(Some(content.to_string()))
+}
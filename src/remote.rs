@@ -0,0 +1,593 @@
+use crate::commit_read::ahead_behind;
+use crate::config::Config;
+use crate::http::{is_http_url, ls_refs, receive_pack_refs};
+use crate::refs::{
+    delete_ref, list_refs, list_refs_in, ref_namespace, shorten_ref, symbolic_ref_target,
+};
+use crate::repository::{Repository, git_path, work_tree_git_dir};
+use anyhow::{Context, Result, bail};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+/// A refspec such as `+refs/heads/*:refs/remotes/origin/*`: which refs to transfer
+/// (`src`) and where to store them (`dst`), with at most one `*` in each side.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Refspec {
+    /// A leading `+`: update the destination even if it isn't a fast-forward.
+    pub force: bool,
+    pub src: String,
+    pub dst: Option<String>,
+}
+
+impl Refspec {
+    pub fn parse(spec: &str) -> Result<Refspec> {
+        let (force, spec) = match spec.strip_prefix('+') {
+            Some(rest) => (true, rest),
+            None => (false, spec),
+        };
+        let (src, dst) = match spec.split_once(':') {
+            Some((src, dst)) => (src, Some(dst.to_string())),
+            None => (spec, None),
+        };
+        let stars = |s: &str| s.matches('*').count();
+        if stars(src) > 1 || dst.as_deref().is_some_and(|dst| stars(dst) != stars(src)) {
+            bail!("invalid refspec '{spec}'");
+        }
+        Ok(Refspec {
+            force,
+            src: src.to_string(),
+            dst,
+        })
+    }
+
+    /// Where the source ref `name` is stored, if this refspec covers it.
+    pub fn map_to_destination(&self, name: &str) -> Option<String> {
+        map_glob(&self.src, self.dst.as_deref()?, name)
+    }
+
+    /// The source ref stored as `name`, if this refspec covers it.
+    pub fn map_to_source(&self, name: &str) -> Option<String> {
+        map_glob(self.dst.as_deref()?, &self.src, name)
+    }
+}
+
+/// Match `name` against `from` (with an optional `*`) and substitute into `to`.
+fn map_glob(from: &str, to: &str, name: &str) -> Option<String> {
+    match from.split_once('*') {
+        Some((prefix, suffix)) => {
+            let matched = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+            Some(to.replacen('*', matched, 1))
+        }
+        None => (name == from).then(|| to.to_string()),
+    }
+}
+
+/// A remote as configured by `remote.<name>.*`.
+#[derive(Debug, Clone)]
+pub struct Remote {
+    pub name: String,
+    pub urls: Vec<String>,
+    /// `remote.<name>.pushurl`; empty if pushes go to `urls`.
+    pub push_urls: Vec<String>,
+    pub fetch: Vec<Refspec>,
+    pub push: Vec<Refspec>,
+}
+
+impl Remote {
+    /// The remote called `name`, or `None` if nothing about it is configured.
+    pub fn get(config: &Config, name: &str) -> Result<Option<Remote>> {
+        if !remote_names(config).iter().any(|n| n == name) {
+            return Ok(None);
+        }
+        let values = |key: &str| {
+            config
+                .get_all(&format!("remote.{name}.{key}"))
+                .into_iter()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+        let refspecs = |key: &str| -> Result<Vec<Refspec>> {
+            values(key).iter().map(|s| Refspec::parse(s)).collect()
+        };
+        Ok(Some(Remote {
+            name: name.to_string(),
+            urls: values("url"),
+            push_urls: values("pushurl"),
+            fetch: refspecs("fetch")?,
+            push: refspecs("push")?,
+        }))
+    }
+
+    /// The tracking ref (e.g. `refs/remotes/origin/main`) the remote's `branch` is
+    /// fetched into.
+    pub fn tracking_ref(&self, branch: &str) -> Option<String> {
+        self.fetch
+            .iter()
+            .find_map(|spec| spec.map_to_destination(branch))
+    }
+
+    /// The local tracking refs fetched from this remote, by the remote ref they track.
+    /// Symbolic refs such as `refs/remotes/origin/HEAD` aren't fetched into and are
+    /// left out.
+    fn tracking_refs(&self, repo: &Repository) -> Result<BTreeMap<String, String>> {
+        let mut refs = BTreeMap::new();
+        for (name, _) in list_refs(repo, "refs/")? {
+            if symbolic_ref_target(repo, &name)? != name {
+                continue;
+            }
+            if let Some(src) = self.fetch.iter().find_map(|spec| spec.map_to_source(&name)) {
+                refs.insert(src, name);
+            }
+        }
+        Ok(refs)
+    }
+
+    /// The URLs pushes go to: the `pushurl`s, or else the `url`s.
+    pub fn push_urls(&self) -> &[String] {
+        match self.push_urls.is_empty() {
+            true => &self.urls,
+            false => &self.push_urls,
+        }
+    }
+
+    /// The URL pushes go to: the first `pushurl`, or else the first `url`.
+    pub fn push_url(&self) -> Option<&str> {
+        self.push_urls
+            .first()
+            .or(self.urls.first())
+            .map(String::as_str)
+    }
+
+    /// Read the remote repository's HEAD branch and refs, from the repository itself if
+    /// it is local, or else over the smart HTTP protocol (see [`ls_refs`]).
+    ///
+    /// With `GIT_NAMESPACE` set, the remote shows the refs and HEAD of that namespace
+    /// as its own (see [`ref_namespace`]).
+    pub fn query(&self, repo: &Repository) -> Result<RemoteRefs> {
+        self.query_url(repo, self.urls.first().context("remote has no URL")?)
+    }
+
+    /// Like [`Remote::query`], for the repository pushes go to; one reached over HTTP
+    /// is asked what its `git-receive-pack` has (see [`receive_pack_refs`]).
+    pub fn query_push(&self, repo: &Repository) -> Result<RemoteRefs> {
+        let url = self.push_url().context("remote has no URL")?;
+        match is_http_url(url) {
+            true => receive_pack_refs(url),
+            false => self.query_url(repo, url),
+        }
+    }
+
+    fn query_url(&self, repo: &Repository, url: &str) -> Result<RemoteRefs> {
+        if is_http_url(url) {
+            return ls_refs(url, repo.hash_algorithm()?);
+        }
+        let Some(git_dir) = local_git_dir(&repo.work_tree, url) else {
+            bail!(
+                "cannot query remote '{}' at {url}: only local and HTTP repositories are \
+                 supported",
+                self.name
+            );
+        };
+        let namespace = ref_namespace()?;
+        let head = read_to_string(git_path(&git_dir, &format!("{namespace}HEAD")))
+            .ok()
+            .and_then(|head| {
+                let target = head.trim().strip_prefix("ref: ")?;
+                target.strip_prefix(&namespace).map(str::to_string)
+            });
+        let refs = list_refs_in(&git_dir, &format!("{namespace}refs/"))?
+            .into_iter()
+            .filter_map(|(name, hash)| Some((name.strip_prefix(&namespace)?.to_string(), hash)))
+            .collect();
+        Ok(RemoteRefs {
+            refs,
+            git_dir: Some(git_dir),
+            head,
+            peeled: BTreeMap::new(),
+            capabilities: Vec::new(),
+        })
+    }
+}
+
+/// The refs a remote repository has.
+pub struct RemoteRefs {
+    /// The remote's git directory, where its objects can be read; `None` for a remote
+    /// reached over HTTP.
+    pub git_dir: Option<PathBuf>,
+    /// The ref the remote's HEAD points at, e.g. `refs/heads/main`.
+    pub head: Option<String>,
+    /// Every ref under `refs/` with the object it points to, by name.
+    pub refs: BTreeMap<String, String>,
+    /// What the annotated tags among `refs` peel to, for a remote reached over HTTP
+    /// (a local one's tags are peeled by reading them).
+    pub peeled: BTreeMap<String, String>,
+    /// What the `git-receive-pack` of a remote reached over HTTP says it can do, for
+    /// [`Remote::query_push`]; empty otherwise.
+    pub capabilities: Vec<String>,
+}
+
+/// Names of all configured remotes, in the order they first appear in config.
+pub fn remote_names(config: &Config) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for entry in config.entries() {
+        let Some(rest) = entry.key.strip_prefix("remote.") else {
+            continue;
+        };
+        let Some((name, _)) = rest.rsplit_once('.') else {
+            continue;
+        };
+        if !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+    }
+    names
+}
+
+/// The git directory of a remote URL that names a local repository (either a path,
+/// relative to `base` if it isn't absolute, or a `file://` URL), or `None` for
+/// network URLs and missing repositories.
+pub fn local_git_dir(base: &Path, url: &str) -> Option<PathBuf> {
+    let path = match url.strip_prefix("file://") {
+        Some(path) => path,
+        None if url.contains("://") => return None,
+        // `host:path` is scp-like syntax for ssh, unless a slash comes first.
+        None if url
+            .find(':')
+            .is_some_and(|colon| !url[..colon].contains('/')) =>
+        {
+            return None;
+        }
+        None => url,
+    };
+    let path = base.join(path);
+    if let Some(git_dir) = work_tree_git_dir(&path) {
+        return Some(git_dir);
+    }
+    (path.join("HEAD").is_file() && path.join("objects").is_dir()).then_some(path)
+}
+
+/// `git remote`: the configured remotes, in the order they are configured.
+pub fn git_remote_list(config: &Config) -> Result<Vec<Remote>> {
+    let mut remotes = Vec::new();
+    for name in remote_names(config) {
+        remotes.extend(Remote::get(config, &name)?);
+    }
+    Ok(remotes)
+}
+
+/// `remote` as `git remote -v` shows it: its fetch URL, then each URL it pushes to.
+pub fn format_remote_verbose(remote: &Remote) -> String {
+    let name = &remote.name;
+    let fetch_url = remote.urls.first().map(String::as_str).unwrap_or("");
+    let mut out = format!("{name}\t{fetch_url} (fetch)\n");
+    for url in remote.push_urls() {
+        writeln!(out, "{name}\t{url} (push)").unwrap();
+    }
+    out
+}
+
+/// The remote called `name`, failing the way git does if it isn't configured.
+pub fn get_remote(config: &Config, name: &str) -> Result<Remote> {
+    match Remote::get(config, name)? {
+        Some(remote) => Ok(remote),
+        None => bail!("'{name}' does not appear to be a git repository"),
+    }
+}
+
+/// What `git remote show` tells of a remote, for [`format_remote_show`].
+#[derive(Debug)]
+pub struct RemoteShow {
+    pub remote: Remote,
+    /// Whether the remote was read; if not, nothing is known about its branches.
+    pub queried: bool,
+    /// The remote's HEAD branch, `(unknown)` or `(not queried)`.
+    pub head: String,
+    /// The remote's branches with how they are tracked (`tracked`, `new (...)`,
+    /// `stale (...)`), or the tracked ones without their state if not queried.
+    pub branches: Vec<(String, String)>,
+    /// The local branches that pull from the remote: whether each rebases, and
+    /// the remote branches it merges.
+    pub pulls: BTreeMap<String, (bool, Vec<String>)>,
+    /// What pushes go where (see [`Pushes`]).
+    pub pushes: Pushes,
+}
+
+/// The local refs a push to a remote updates, as `git remote show` lists them.
+#[derive(Debug)]
+pub enum Pushes {
+    /// Without querying the remote, only the configured push refspecs are known;
+    /// none means matching branches are pushed.
+    NotQueried(Vec<Refspec>),
+    /// Each local branch pushed, whether it is forced, the remote branch it goes to
+    /// and what the push would do (`create`, `up to date`, `fast-forwardable` or
+    /// `local out of date`).
+    Queried(Vec<(String, bool, String, &'static str)>),
+}
+
+/// `git remote show [-n] <name>`: a remote's URLs and how local branches relate to
+/// it. Unless `no_query`, the remote is read to find new and stale branches and
+/// whether pushes would fast-forward.
+pub fn git_remote_show(
+    repo: &Repository,
+    config: &Config,
+    name: &str,
+    no_query: bool,
+) -> Result<RemoteShow> {
+    let remote = get_remote(config, name)?;
+    let heads = if no_query {
+        None
+    } else {
+        Some(remote.query(repo)?)
+    };
+    let tracking = remote.tracking_refs(repo)?;
+
+    let head = match &heads {
+        None => "(not queried)".to_string(),
+        Some(heads) => match &heads.head {
+            Some(head) if heads.refs.contains_key(head) => shorten_ref(head).to_string(),
+            _ => "(unknown)".to_string(),
+        },
+    };
+
+    let mut branches: Vec<(String, String)> = Vec::new();
+    match &heads {
+        None => {
+            branches.extend(
+                tracking
+                    .keys()
+                    .map(|src| (shorten_ref(src).to_string(), String::new())),
+            );
+        }
+        Some(heads) => {
+            for branch in heads.refs.keys().filter(|r| r.starts_with("refs/heads/")) {
+                let Some(tracking_ref) = remote.tracking_ref(branch) else {
+                    continue;
+                };
+                let state = if tracking.values().any(|t| *t == tracking_ref) {
+                    "tracked".to_string()
+                } else {
+                    format!("new (next fetch will store in remotes/{name})")
+                };
+                branches.push((shorten_ref(branch).to_string(), state));
+            }
+            for (src, tracking_ref) in &tracking {
+                if !heads.refs.contains_key(src) {
+                    let state = "stale (use 'git remote prune' to remove)".to_string();
+                    branches.push((tracking_ref.clone(), state));
+                }
+            }
+        }
+    }
+    branches.sort();
+
+    Ok(RemoteShow {
+        pulls: pull_config(config, name)?,
+        pushes: push_config(repo, &remote, heads.as_ref())?,
+        queried: heads.is_some(),
+        remote,
+        head,
+        branches,
+    })
+}
+
+/// `show` as `git remote show` prints it.
+pub fn format_remote_show(show: &RemoteShow) -> String {
+    let remote = &show.remote;
+    let mut out = format!("* remote {}\n", remote.name);
+    let fetch_url = remote
+        .urls
+        .first()
+        .map(String::as_str)
+        .unwrap_or("(no URL)");
+    writeln!(out, "  Fetch URL: {fetch_url}").unwrap();
+    for url in remote.push_urls() {
+        writeln!(out, "  Push  URL: {url}").unwrap();
+    }
+    writeln!(out, "  HEAD branch: {}", show.head).unwrap();
+
+    if !show.branches.is_empty() {
+        let noun = if show.branches.len() == 1 {
+            "branch"
+        } else {
+            "branches"
+        };
+        let suffix = if show.queried {
+            ""
+        } else {
+            " (status not queried)"
+        };
+        writeln!(out, "  Remote {noun}:{suffix}").unwrap();
+        let width = show
+            .branches
+            .iter()
+            .map(|(n, _)| n.len())
+            .max()
+            .unwrap_or(0);
+        for (branch, state) in &show.branches {
+            if state.is_empty() {
+                writeln!(out, "    {branch}").unwrap();
+            } else {
+                writeln!(out, "    {branch:<width$} {state}").unwrap();
+            }
+        }
+    }
+
+    if !show.pulls.is_empty() {
+        let noun = if show.pulls.len() == 1 {
+            "branch"
+        } else {
+            "branches"
+        };
+        writeln!(out, "  Local {noun} configured for 'git pull':").unwrap();
+        let width = show.pulls.keys().map(String::len).max().unwrap_or(0);
+        let any_rebase = show.pulls.values().any(|(rebase, _)| *rebase);
+        for (branch, (rebase, merges)) in &show.pulls {
+            if *rebase {
+                writeln!(
+                    out,
+                    "    {branch:<width$} rebases onto remote {}",
+                    merges[0]
+                )
+                .unwrap();
+                continue;
+            }
+            // Line "merges" up with "rebases onto" when both appear.
+            let pad = if any_rebase { " " } else { "" };
+            writeln!(
+                out,
+                "    {branch:<width$} {pad}merges with remote {}",
+                merges[0]
+            )
+            .unwrap();
+            for merge in &merges[1..] {
+                writeln!(out, "    {:<width$} {pad}   and with remote {merge}", "").unwrap();
+            }
+        }
+    }
+
+    match &show.pushes {
+        Pushes::NotQueried(specs) if specs.is_empty() => {
+            out.push_str("  Local ref configured for 'git push' (status not queried):\n");
+            out.push_str("    (matching) pushes to (matching)\n");
+        }
+        Pushes::NotQueried(specs) => {
+            let noun = if specs.len() == 1 { "ref" } else { "refs" };
+            writeln!(
+                out,
+                "  Local {noun} configured for 'git push' (status not queried):"
+            )
+            .unwrap();
+            let width = specs.iter().map(|s| s.src.len()).max().unwrap_or(0);
+            for spec in specs {
+                let verb = if spec.force { "forces" } else { "pushes" };
+                let dst = spec.dst.as_deref().unwrap_or(&spec.src);
+                writeln!(out, "    {:<width$} {verb} to {dst}", spec.src).unwrap();
+            }
+        }
+        Pushes::Queried(pushes) if pushes.is_empty() => {}
+        Pushes::Queried(pushes) => {
+            let noun = if pushes.len() == 1 { "ref" } else { "refs" };
+            writeln!(out, "  Local {noun} configured for 'git push':").unwrap();
+            let src_width = pushes.iter().map(|p| p.0.len()).max().unwrap_or(0);
+            let dst_width = pushes.iter().map(|p| p.2.len()).max().unwrap_or(0);
+            for (src, force, dst, status) in pushes {
+                let verb = if *force { "forces" } else { "pushes" };
+                writeln!(
+                    out,
+                    "    {src:<src_width$} {verb} to {dst:<dst_width$} ({status})"
+                )
+                .unwrap();
+            }
+        }
+    }
+    out
+}
+
+/// The local branches configured to pull from the remote `name`, for the "Local
+/// branches configured for 'git pull'" section.
+fn pull_config(config: &Config, name: &str) -> Result<BTreeMap<String, (bool, Vec<String>)>> {
+    let mut branches: BTreeMap<String, (bool, Vec<String>)> = BTreeMap::new();
+    for entry in config.entries() {
+        let Some(branch) = entry
+            .key
+            .strip_prefix("branch.")
+            .and_then(|rest| rest.strip_suffix(".remote"))
+        else {
+            continue;
+        };
+        if config.get(&entry.key) != Some(name) {
+            continue;
+        }
+        let merges: Vec<String> = config
+            .get_all(&format!("branch.{branch}.merge"))
+            .into_iter()
+            .map(|merge| shorten_ref(merge).to_string())
+            .collect();
+        if merges.is_empty() {
+            continue;
+        }
+        let rebase = config
+            .get(&format!("branch.{branch}.rebase"))
+            .is_some_and(|value| value != "false");
+        if rebase && merges.len() > 1 {
+            bail!("invalid branch.{branch}.merge; cannot rebase onto > 1 branch");
+        }
+        branches.insert(branch.to_string(), (rebase, merges));
+    }
+    Ok(branches)
+}
+
+/// What a push to `remote` updates, for the "Local refs configured for 'git push'"
+/// section. Without configured push refspecs, branches are pushed to the remote
+/// branch of the same name.
+fn push_config(repo: &Repository, remote: &Remote, heads: Option<&RemoteRefs>) -> Result<Pushes> {
+    let Some(heads) = heads else {
+        return Ok(Pushes::NotQueried(remote.push.clone()));
+    };
+
+    let mut pushes = Vec::new();
+    for (local_ref, local_hash) in list_refs(repo, "refs/heads/")? {
+        let target = if remote.push.is_empty() {
+            heads
+                .refs
+                .contains_key(&local_ref)
+                .then(|| (false, local_ref.clone()))
+        } else {
+            remote.push.iter().find_map(|spec| {
+                let dst = match &spec.dst {
+                    Some(_) => spec.map_to_destination(&local_ref)?,
+                    None => map_glob(&spec.src, &spec.src, &local_ref)?,
+                };
+                Some((spec.force, dst))
+            })
+        };
+        let Some((force, remote_ref)) = target else {
+            continue;
+        };
+        let status = match heads.refs.get(&remote_ref) {
+            None => "create",
+            Some(remote_hash) if *remote_hash == local_hash => "up to date",
+            Some(remote_hash) => match ahead_behind(repo, &local_hash, remote_hash) {
+                Ok((_, 0)) => "fast-forwardable",
+                _ => "local out of date",
+            },
+        };
+        pushes.push((
+            shorten_ref(&local_ref).to_string(),
+            force,
+            shorten_ref(&remote_ref).to_string(),
+            status,
+        ));
+    }
+    Ok(Pushes::Queried(pushes))
+}
+
+/// `git remote prune [--dry-run] <name>`: delete tracking refs whose branch no longer
+/// exists on the remote, or with `dry_run` only find them. Returns the refs, short
+/// (`origin/topic`), in order.
+pub fn git_remote_prune(
+    repo: &Repository,
+    config: &Config,
+    name: &str,
+    dry_run: bool,
+) -> Result<Vec<String>> {
+    let remote = get_remote(config, name)?;
+    let heads = remote.query(repo)?;
+    let stale: Vec<String> = remote
+        .tracking_refs(repo)?
+        .into_iter()
+        .filter(|(src, _)| !heads.refs.contains_key(src))
+        .map(|(_, tracking_ref)| tracking_ref)
+        .collect();
+    if !dry_run {
+        for tracking_ref in &stale {
+            delete_ref(repo, tracking_ref)?;
+        }
+    }
+    Ok(stale
+        .iter()
+        .map(|tracking_ref| shorten_ref(tracking_ref).to_string())
+        .collect())
+}
@@ -0,0 +1,181 @@
+use crate::diff::{DiffSide, is_binary};
+use crate::object_read::Object;
+use crate::repository::Repository;
+use anyhow::Result;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+/// Similarity scores are out of this, as in git's diffcore.
+const MAX_SCORE: u64 = 60000;
+
+/// How similar a deleted and an added file have to be to count as a rename: git's
+/// default of 50%.
+const MINIMUM_SCORE: u64 = MAX_SCORE / 2;
+
+/// How many of the most similar files are kept as candidates for a source.
+const CANDIDATES: usize = 4;
+
+/// The file among `sources` that the file `destination` at `path` was most likely
+/// copied or renamed from, as `git log --follow` finds it: an identical one if there
+/// is one, preferably one that was deleted and with the same name, or else the most
+/// similar one, if at least 50% similar. Each source is a path and file of the old
+/// tree, and whether the new one deleted it.
+///
+/// Similarity is the share of the larger file made of content from the other,
+/// counted in lines (or 64-byte spans of longer ones) as git's diffcore counts it;
+/// only regular files are compared by content.
+pub fn rename_source(
+    repo: &Repository,
+    sources: &[(String, DiffSide, bool)],
+    path: &str,
+    destination: &DiffSide,
+) -> Result<Option<String>> {
+    if destination.mode == "160000" {
+        return Ok(None);
+    }
+    let basename = |path: &str| path.rsplit('/').next().unwrap_or(path).to_string();
+    let mut exact: Option<(u8, &str)> = None;
+    for (source_path, source, deleted) in sources {
+        let same_kind = source.mode == destination.mode
+            || is_regular_file(&source.mode) && is_regular_file(&destination.mode);
+        if source.hash != destination.hash || !same_kind {
+            continue;
+        }
+        let score = u8::from(*deleted) + u8::from(basename(source_path) == basename(path));
+        if exact.is_none_or(|(best, _)| score > best) {
+            exact = Some((score, source_path));
+        }
+    }
+    if let Some((_, source_path)) = exact {
+        return Ok(Some(source_path.to_string()));
+    }
+
+    // Like git, keep the best few candidates in the order they were found, then take
+    // the best of them that was deleted, or failing that the best of them.
+    let mut destination = Candidate::new(destination);
+    let mut best: Vec<(u64, bool, &str, bool)> = Vec::new();
+    for (source_path, source, deleted) in sources {
+        let score = similarity(repo, &mut Candidate::new(source), &mut destination)?;
+        let candidate = (
+            score,
+            basename(source_path) == basename(path),
+            source_path.as_str(),
+            *deleted,
+        );
+        if best.len() < CANDIDATES {
+            best.push(candidate);
+            continue;
+        }
+        let rank = |n: usize| (best[n].0, best[n].1);
+        let worst =
+            (1..CANDIDATES).fold(0, |worst, n| if rank(n) < rank(worst) { n } else { worst });
+        if (candidate.0, candidate.1) > rank(worst) {
+            best[worst] = candidate;
+        }
+    }
+    best.retain(|candidate| candidate.0 >= MINIMUM_SCORE);
+    best.sort_by_key(|candidate| Reverse((candidate.0, candidate.1)));
+    let chosen = best.iter().find(|candidate| candidate.3).or(best.first());
+    Ok(chosen.map(|candidate| candidate.2.to_string()))
+}
+
+fn is_regular_file(mode: &str) -> bool {
+    mode == "100644" || mode == "100755"
+}
+
+/// A file being compared, with its size and content's spans read once they are
+/// needed.
+struct Candidate<'a> {
+    side: &'a DiffSide,
+    size: Option<u64>,
+    spans: Option<Vec<(u32, u64)>>,
+}
+
+impl Candidate<'_> {
+    fn new(side: &DiffSide) -> Candidate<'_> {
+        Candidate {
+            side,
+            size: None,
+            spans: None,
+        }
+    }
+
+    fn size(&mut self, repo: &Repository) -> Result<u64> {
+        if self.size.is_none() {
+            self.size =
+                Some(Object::read_git_object(repo, &self.side.hash.to_string())?.expected_size);
+        }
+        Ok(self.size.unwrap_or(0))
+    }
+
+    fn spans(&mut self, repo: &Repository) -> Result<&[(u32, u64)]> {
+        if self.spans.is_none() {
+            let data = Object::read_git_object(repo, &self.side.hash.to_string())?.read_all()?;
+            self.spans = Some(count_spans(&data));
+        }
+        Ok(self.spans.as_deref().unwrap_or_default())
+    }
+}
+
+/// The content of `data` as hashes of its lines, with lines longer than 64 bytes cut
+/// into 64-byte spans, and how many bytes have each hash, sorted by hash. A CR before
+/// an LF isn't counted in text, and an unfinished last line isn't counted at all.
+fn count_spans(data: &[u8]) -> Vec<(u32, u64)> {
+    const HASH_BASE: u32 = 107927;
+    let is_text = !is_binary(data);
+    let mut counts: BTreeMap<u32, u64> = BTreeMap::new();
+    let (mut accum1, mut accum2, mut len) = (0u32, 0u32, 0u64);
+    for (i, &c) in data.iter().enumerate() {
+        if is_text && c == b'\r' && data.get(i + 1) == Some(&b'\n') {
+            continue;
+        }
+        let old1 = accum1;
+        accum1 = (accum1 << 7) ^ (accum2 >> 25);
+        accum2 = (accum2 << 7) ^ (old1 >> 25);
+        accum1 = accum1.wrapping_add(u32::from(c));
+        len += 1;
+        if len < 64 && c != b'\n' {
+            continue;
+        }
+        let hash = accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASH_BASE;
+        *counts.entry(hash).or_default() += len;
+        (accum1, accum2, len) = (0, 0, 0);
+    }
+    counts.into_iter().collect()
+}
+
+/// How much of `destination` comes from `source`, out of [`MAX_SCORE`]; 0 if their
+/// sizes are too far apart to reach [`MINIMUM_SCORE`], without comparing content.
+fn similarity(
+    repo: &Repository,
+    source: &mut Candidate,
+    destination: &mut Candidate,
+) -> Result<u64> {
+    if !is_regular_file(&source.side.mode) || !is_regular_file(&destination.side.mode) {
+        return Ok(0);
+    }
+    let (source_size, destination_size) = (source.size(repo)?, destination.size(repo)?);
+    let max_size = source_size.max(destination_size);
+    let base_size = source_size.min(destination_size);
+    if max_size * (MAX_SCORE - MINIMUM_SCORE) < (max_size - base_size) * MAX_SCORE || max_size == 0
+    {
+        return Ok(0);
+    }
+    let destination_spans = destination.spans(repo)?.to_vec();
+    let mut copied = 0;
+    let mut rest = destination_spans.as_slice();
+    for &(hash, count) in source.spans(repo)? {
+        while let Some((&(other, _), tail)) = rest.split_first()
+            && other < hash
+        {
+            rest = tail;
+        }
+        if let Some((&(other, other_count), tail)) = rest.split_first()
+            && other == hash
+        {
+            copied += count.min(other_count);
+            rest = tail;
+        }
+    }
+    Ok(copied * MAX_SCORE / max_size)
+}
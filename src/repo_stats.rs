@@ -0,0 +1,320 @@
+use crate::config::Config;
+use crate::object_read::{ObjectKind, default_abbrev, disk_size, packs, unique_abbrev};
+use crate::odb::{LooseOdb, Odb};
+use crate::repository::Repository;
+use crate::rev_list::human_size;
+use crate::revwalk::RevWalk;
+use crate::transfer::loose_object_path;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs::read_dir;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// What `repo-stats` shows.
+#[derive(Debug, Clone)]
+pub struct RepoStatsOptions {
+    /// How many of the largest blobs, trees and paths to list (`--top`).
+    pub top: usize,
+}
+
+impl Default for RepoStatsOptions {
+    fn default() -> Self {
+        RepoStatsOptions { top: 10 }
+    }
+}
+
+/// One object of the store, as `repo-stats` counts it.
+struct Stat {
+    kind: ObjectKind,
+    size: u64,
+    disk: u64,
+}
+
+/// How many objects there are and their sizes, inflated and on disk.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Totals {
+    pub count: usize,
+    pub size: u64,
+    pub disk: u64,
+}
+
+impl Totals {
+    fn of<'a>(stats: impl Iterator<Item = &'a Stat>) -> Totals {
+        stats.fold(Totals::default(), |totals, stat| Totals {
+            count: totals.count + 1,
+            size: totals.size + stat.size,
+            disk: totals.disk + stat.disk,
+        })
+    }
+}
+
+/// A blob or tree among the largest: its abbreviated id, sizes, and the path it is
+/// reached at (`/` for a root tree), if anything reachable has it.
+#[derive(Debug, Clone)]
+pub struct LargeObject {
+    pub id: String,
+    pub size: u64,
+    pub disk: u64,
+    pub path: Option<String>,
+}
+
+/// A path among those whose blobs add up to the most over history.
+#[derive(Debug, Clone)]
+pub struct PathHistory {
+    pub path: String,
+    pub size: u64,
+    pub disk: u64,
+    pub versions: usize,
+}
+
+/// What `repo-stats` reports, for [`format_repo_stats`].
+#[derive(Debug, Clone)]
+pub struct RepoStats {
+    /// The objects of each kind, commits, trees, blobs and tags in that order.
+    pub kinds: Vec<(ObjectKind, Totals)>,
+    pub total: Totals,
+    /// How many loose objects there are, and the bytes of their files.
+    pub loose: (usize, u64),
+    /// How many packed objects there are, in how many packs, and the bytes of the
+    /// `.pack` files.
+    pub packed: (usize, usize, u64),
+    pub largest_blobs: Vec<LargeObject>,
+    pub largest_trees: Vec<LargeObject>,
+    pub largest_paths: Vec<PathHistory>,
+}
+
+/// `git repo-stats [--top=<n>]`: find what takes up space in the repository. Every
+/// object stored is counted by kind, at its size and the space it takes up on disk
+/// (its pack entry, which may be a delta, or else its loose file), and the loose
+/// objects are set against the packed ones. Then the largest blobs and trees are
+/// found with the paths a [`RevWalk`] of every ref reaches them at (none if
+/// nothing reachable has them), and the paths whose blobs add up to the most over all
+/// reachable history, with how many versions there are of each.
+pub fn git_repo_stats(
+    repo: &Repository,
+    config: &Config,
+    options: &RepoStatsOptions,
+) -> Result<RepoStats> {
+    let objects_dir = repo.objects_dir();
+    let mut stats = HashMap::new();
+    for info in repo.objects()? {
+        let info = info?;
+        let hash = info.id.to_string();
+        let disk = disk_size(&objects_dir, &hash)?;
+        let stat = Stat {
+            kind: info.kind,
+            size: info.size,
+            disk,
+        };
+        stats.insert(hash, stat);
+    }
+    let kinds = [
+        ObjectKind::Commit,
+        ObjectKind::Tree,
+        ObjectKind::Blob,
+        ObjectKind::Tag,
+    ]
+    .into_iter()
+    .map(|kind| {
+        let totals = Totals::of(stats.values().filter(|stat| stat.kind == kind));
+        (kind, totals)
+    })
+    .collect();
+
+    let loose = LooseOdb::new(&objects_dir).ids()?;
+    let mut loose_disk = 0;
+    for id in &loose {
+        let path = loose_object_path(&objects_dir, &id.to_string());
+        loose_disk += path.metadata()?.len();
+    }
+    let packed = packs(&objects_dir)?.ids()?.len();
+    let (pack_count, pack_disk) = pack_files(&objects_dir.join("pack"))?;
+
+    let mut walk = RevWalk {
+        objects: true,
+        ..RevWalk::default()
+    };
+    walk.push_refs(repo)?;
+    let reached = walk.walk(repo)?;
+    let mut paths: HashMap<&str, &str> = HashMap::new();
+    let mut history: HashMap<&str, (u64, u64, usize)> = HashMap::new();
+    for object in &reached {
+        let (Some(stat), Some(path)) = (stats.get(&object.hash), &object.name) else {
+            continue;
+        };
+        if matches!(object.kind, ObjectKind::Tree | ObjectKind::Blob) {
+            paths.insert(&object.hash, path);
+        }
+        if object.kind == ObjectKind::Blob {
+            let entry = history.entry(path).or_default();
+            entry.0 += stat.size;
+            entry.1 += stat.disk;
+            entry.2 += 1;
+        }
+    }
+
+    let abbrev = default_abbrev(repo, config)?;
+    let largest = |kind: ObjectKind| -> Result<Vec<LargeObject>> {
+        let mut largest: Vec<(&String, &Stat)> =
+            stats.iter().filter(|(_, stat)| stat.kind == kind).collect();
+        largest.sort_by(|a, b| b.1.size.cmp(&a.1.size).then(a.0.cmp(b.0)));
+        largest
+            .into_iter()
+            .take(options.top)
+            .map(|(hash, stat)| {
+                Ok(LargeObject {
+                    id: unique_abbrev(&objects_dir, &hash.parse()?, abbrev)?,
+                    size: stat.size,
+                    disk: stat.disk,
+                    path: paths.get(hash.as_str()).map(|path| match *path {
+                        "" => "/".to_string(),
+                        path => path.to_string(),
+                    }),
+                })
+            })
+            .collect()
+    };
+    let largest_blobs = largest(ObjectKind::Blob)?;
+    let largest_trees = largest(ObjectKind::Tree)?;
+
+    let mut history: Vec<_> = history.into_iter().collect();
+    history.sort_by(|a, b| b.1.0.cmp(&a.1.0).then(a.0.cmp(b.0)));
+    let largest_paths = history
+        .into_iter()
+        .take(options.top)
+        .map(|(path, (size, disk, versions))| PathHistory {
+            path: path.to_string(),
+            size,
+            disk,
+            versions,
+        })
+        .collect();
+
+    Ok(RepoStats {
+        kinds,
+        total: Totals::of(stats.values()),
+        loose: (loose.len(), loose_disk),
+        packed: (packed, pack_count, pack_disk),
+        largest_blobs,
+        largest_trees,
+        largest_paths,
+    })
+}
+
+/// `stats` as `git repo-stats` prints them: a table of the objects by kind, how much
+/// is loose and packed, then the largest blobs, trees and paths.
+pub fn format_repo_stats(stats: &RepoStats) -> String {
+    let mut out = String::from("Objects:\n");
+    writeln!(
+        out,
+        "  {:<8} {:>8} {:>12} {:>12}",
+        "kind", "count", "size", "on disk"
+    )
+    .unwrap();
+    let row = |out: &mut String, label: &str, totals: &Totals| {
+        writeln!(
+            out,
+            "  {label:<8} {:>8} {:>12} {:>12}",
+            totals.count,
+            human_size(totals.size),
+            human_size(totals.disk)
+        )
+        .unwrap();
+    };
+    for (kind, totals) in &stats.kinds {
+        row(&mut out, &format!("{}s", kind.to_str()), totals);
+    }
+    row(&mut out, "total", &stats.total);
+
+    let (loose, loose_disk) = stats.loose;
+    let (packed, pack_count, pack_disk) = stats.packed;
+    out.push_str("\nStorage:\n");
+    writeln!(out, "  loose:  {loose} objects, {}", human_size(loose_disk)).unwrap();
+    writeln!(
+        out,
+        "  packed: {packed} objects, {} in {pack_count} {}",
+        human_size(pack_disk),
+        match pack_count {
+            1 => "pack",
+            _ => "packs",
+        }
+    )
+    .unwrap();
+    if loose + packed > 0 {
+        writeln!(
+            out,
+            "  {} of objects and {} of space are packed",
+            percent(packed as u64, (loose + packed) as u64),
+            percent(pack_disk, loose_disk + pack_disk),
+        )
+        .unwrap();
+    }
+
+    for (label, largest) in [
+        ("blobs", &stats.largest_blobs),
+        ("trees", &stats.largest_trees),
+    ] {
+        writeln!(out, "\nLargest {label}:").unwrap();
+        for object in largest {
+            let line = format!(
+                "  {:>12} {:>12}  {}  {}",
+                human_size(object.size),
+                human_size(object.disk),
+                object.id,
+                object.path.as_deref().unwrap_or("")
+            );
+            writeln!(out, "{}", line.trim_end()).unwrap();
+        }
+    }
+
+    out.push_str("\nLargest paths over history:\n");
+    for path in &stats.largest_paths {
+        writeln!(
+            out,
+            "  {:>12} {:>12}  {} {}  {}",
+            human_size(path.size),
+            human_size(path.disk),
+            path.versions,
+            match path.versions {
+                1 => "version",
+                _ => "versions",
+            },
+            path.path
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// How many packs `pack_dir` has (each a `.pack` with its `.idx`), and how many bytes
+/// their `.pack` files add up to.
+fn pack_files(pack_dir: &Path) -> Result<(usize, u64)> {
+    let entries = match read_dir(pack_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok((0, 0)),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", pack_dir.display())),
+    };
+    let (mut count, mut bytes) = (0, 0);
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "pack") && path.with_extension("idx").is_file()
+        {
+            count += 1;
+            bytes += path.metadata()?.len();
+        }
+    }
+    Ok((count, bytes))
+}
+
+/// `part` as a percentage of `whole`, to one decimal place.
+fn percent(part: u64, whole: u64) -> String {
+    match whole {
+        0 => "0.0%".to_string(),
+        _ => {
+            let tenths = part * 1000 / whole;
+            format!("{}.{}%", tenths / 10, tenths % 10)
+        }
+    }
+}
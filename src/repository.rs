@@ -0,0 +1,473 @@
+use crate::config::Config;
+use crate::object_id::HashAlgorithm;
+use crate::object_read::{has_object, packs};
+use crate::odb::{LooseOdb, ObjectInfo, Odb};
+use anyhow::{Context, Result, bail};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs::{canonicalize, read_to_string, symlink_metadata};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// A repository a command works on: where its git directory and work tree are, and
+/// where in the work tree the command was run. Commands are given one, so a program
+/// can work on any number of repositories at once, from any current directory.
+#[derive(Clone)]
+pub struct Repository {
+    /// The git directory.
+    pub git_dir: PathBuf,
+    /// The top of the work tree, which the paths of the index and of trees are
+    /// relative to.
+    pub work_tree: PathBuf,
+    /// Where the command was run below the top of the work tree (`dir/sub/`), or `""`.
+    pub prefix: String,
+    /// The store objects are read from and written to instead of the `objects`
+    /// directory, if one was given.
+    odb: Option<Arc<dyn Odb>>,
+}
+
+impl Repository {
+    /// The repository the current directory is in, as git finds it:
+    ///
+    /// - `GIT_DIR` names the git directory, and `GIT_WORK_TREE` (by default the current
+    ///   directory) the work tree.
+    /// - Otherwise the current directory and then each of its parents is looked in for
+    ///   a `.git`: the git directory itself, or a file pointing to the one a linked
+    ///   worktree or submodule has.
+    ///
+    /// Paths given on the command line are found through [`Repository::pathspec`] and
+    /// [`Repository::file`]. Without any `.git`, the current directory's is assumed,
+    /// which commands then fail to read.
+    ///
+    /// A repository someone else owns is refused unless `safe.directory` allows it; see
+    /// [`ensure_valid_ownership`].
+    pub fn discover() -> Result<Repository> {
+        let cwd = env::current_dir().context("failed to read the current directory")?;
+        let Some(git_dir) = env::var_os("GIT_DIR") else {
+            return Repository::discover_from(&cwd);
+        };
+        let git_dir = cwd.join(git_dir);
+        let git_dir = match symlink_metadata(&git_dir) {
+            Ok(metadata) if metadata.is_file() => read_gitfile(&git_dir)?,
+            _ => git_dir,
+        };
+        let work_tree = match env::var_os("GIT_WORK_TREE") {
+            Some(work_tree) => canonicalize(cwd.join(&work_tree)).with_context(|| {
+                format!("failed to resolve GIT_WORK_TREE {}", work_tree.display())
+            })?,
+            None => cwd.clone(),
+        };
+        Ok(Repository {
+            prefix: prefix_in(&work_tree, &cwd),
+            git_dir,
+            work_tree,
+            odb: None,
+        })
+    }
+
+    /// The repository the directory `dir` (an absolute path) is in, found by looking
+    /// in it and then in each of its parents for a `.git`, as [`Repository::discover`]
+    /// does without `GIT_DIR`.
+    pub fn discover_from(dir: &Path) -> Result<Repository> {
+        let Some(work_tree) = dir
+            .ancestors()
+            .find(|dir| symlink_metadata(dir.join(".git")).is_ok())
+        else {
+            return Ok(Repository::at(dir));
+        };
+        let dot_git = work_tree.join(".git");
+        let git_dir = match symlink_metadata(&dot_git)? {
+            metadata if metadata.is_file() => read_gitfile(&dot_git)?,
+            _ => dot_git,
+        };
+        ensure_valid_ownership(work_tree, &git_dir)?;
+        Ok(Repository {
+            git_dir,
+            work_tree: work_tree.to_path_buf(),
+            prefix: prefix_in(work_tree, dir),
+            odb: None,
+        })
+    }
+
+    /// The repository with its work tree at `work_tree` and its git directory in `.git`
+    /// there, as `init` and `clone` create one, whether or not it exists yet.
+    pub fn at(work_tree: &Path) -> Repository {
+        Repository {
+            git_dir: work_tree.join(".git"),
+            work_tree: work_tree.to_path_buf(),
+            prefix: String::new(),
+            odb: None,
+        }
+    }
+
+    /// The repository with its objects in `odb` rather than its `objects` directory:
+    /// everything that reads or writes objects by id (`Object::read_git_object`,
+    /// `Object::write_as_object`, `full_object_id` and what is built on them) goes
+    /// through it. Refs, the index and config still live in the git directory, and
+    /// what works on the object files themselves (packing, `gc`, fetching and
+    /// pushing) still uses the `objects` directory.
+    pub fn with_odb(self, odb: impl Odb + 'static) -> Repository {
+        Repository {
+            odb: Some(Arc::new(odb)),
+            ..self
+        }
+    }
+
+    /// The store given with [`Repository::with_odb`], if any.
+    pub fn odb(&self) -> Option<&dyn Odb> {
+        self.odb.as_deref()
+    }
+
+    /// Where `path` (as in `refs/heads/main` or `HEAD`) is in the git directory; see
+    /// [`git_path`].
+    pub fn git_path(&self, path: &str) -> PathBuf {
+        git_path(&self.git_dir, path)
+    }
+
+    /// The repository's `objects` directory.
+    pub fn objects_dir(&self) -> PathBuf {
+        self.git_path("objects")
+    }
+
+    /// The hash function the repository names its objects with: its store's, or see
+    /// [`hash_algorithm_in`].
+    pub fn hash_algorithm(&self) -> Result<HashAlgorithm> {
+        match &self.odb {
+            Some(odb) => odb.algorithm(),
+            None => hash_algorithm_in(&self.objects_dir()),
+        }
+    }
+
+    /// Whether the repository has the object `hash` (a full id), loose or packed, or in
+    /// its store.
+    pub fn has_object(&self, hash: &str) -> bool {
+        match &self.odb {
+            Some(odb) => odb.contains(hash),
+            None => has_object(&self.objects_dir(), hash),
+        }
+    }
+
+    /// Where the file `path`, relative to the top of the work tree as the paths of the
+    /// index and of trees are, is.
+    pub fn path(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.work_tree.join(path)
+    }
+
+    /// Every object of the repository (or of its store) with its kind and size, in
+    /// ascending order of id, for commands that go over the whole store rather than
+    /// what is reachable from refs. Only each object's header is read, and for a
+    /// packed delta the start of the delta.
+    pub fn objects(&self) -> Result<Box<dyn Iterator<Item = Result<ObjectInfo>>>> {
+        self.objects_in(false)
+    }
+
+    /// Like [`Repository::objects`], but in the order they are stored in, which is
+    /// the cheapest to read them in: the loose objects, then those of each pack (newest
+    /// first) in the order of their entries, an object stored more than once listed
+    /// where it is first found.
+    pub fn objects_in_storage_order(&self) -> Result<Box<dyn Iterator<Item = Result<ObjectInfo>>>> {
+        self.objects_in(true)
+    }
+
+    fn objects_in(
+        &self,
+        storage_order: bool,
+    ) -> Result<Box<dyn Iterator<Item = Result<ObjectInfo>>>> {
+        if let Some(odb) = self.odb.clone() {
+            return Ok(Box::new(odb.ids()?.into_iter().map(move |id| {
+                let object = odb.read(&id.to_string())?;
+                Ok(ObjectInfo {
+                    id,
+                    kind: object.kind,
+                    size: object.expected_size,
+                })
+            })));
+        }
+        let objects_dir = self.objects_dir();
+        let loose = LooseOdb::new(&objects_dir);
+        let packed = packs(&objects_dir)?;
+        let mut ids = loose.ids()?;
+        match storage_order {
+            true => {
+                ids.extend(packed.ids_in_pack_order()?);
+                let mut seen = HashSet::new();
+                ids.retain(|id| seen.insert(*id));
+            }
+            false => {
+                ids.extend(packed.ids()?);
+                ids.sort();
+                ids.dedup();
+            }
+        }
+        Ok(Box::new(ids.into_iter().map(move |id| {
+            let hash = id.to_string();
+            let (kind, size) = match loose.contains(&hash) {
+                true => {
+                    let object = loose.read(&hash)?;
+                    (object.kind, object.expected_size)
+                }
+                false => packed
+                    .read_header(&id)?
+                    .with_context(|| format!("object {hash} disappeared"))?,
+            };
+            Ok(ObjectInfo { id, kind, size })
+        })))
+    }
+
+    /// The path from the top of the work tree that `path`, given relative to the
+    /// directory the command was run in (or absolute, within the work tree), names:
+    /// `.` for the top itself. Given at the top, a relative path is kept as it is.
+    pub fn pathspec(&self, path: &str) -> Result<String> {
+        let mut parts: Vec<&str> = Vec::new();
+        let relative = match path.strip_prefix('/') {
+            Some(_) => match Path::new(path).strip_prefix(&self.work_tree) {
+                Ok(relative) => relative.to_str().unwrap_or(path),
+                Err(_) => bail!(
+                    "{path}: '{path}' is outside repository at '{}'",
+                    self.work_tree.display()
+                ),
+            },
+            None if self.prefix.is_empty() => return Ok(path.to_string()),
+            None => {
+                parts.extend(self.prefix.split_terminator('/'));
+                path
+            }
+        };
+        for part in relative.split('/') {
+            match part {
+                "" | "." => {}
+                ".." if parts.pop().is_some() => {}
+                ".." => bail!(
+                    "{path}: '{path}' is outside repository at '{}'",
+                    self.work_tree.display()
+                ),
+                _ => parts.push(part),
+            }
+        }
+        Ok(match parts.is_empty() {
+            true => ".".to_string(),
+            false => parts.join("/"),
+        })
+    }
+
+    /// How `path`, from the top of the work tree, is reached from the directory the
+    /// command was run in, as git shows paths there: `sub/f` is `f` from `sub/` and
+    /// `../f` from `other/`, and the directory itself is `./`.
+    pub fn relative(&self, path: &str) -> String {
+        let mut rest = path;
+        let mut up = 0;
+        for dir in self.prefix.split_terminator('/') {
+            match rest
+                .strip_prefix(dir)
+                .and_then(|rest| rest.strip_prefix('/'))
+            {
+                Some(below) if up == 0 => rest = below,
+                _ => up += 1,
+            }
+        }
+        match format!("{}{rest}", "../".repeat(up)) {
+            relative if relative.is_empty() => "./".to_string(),
+            relative => relative,
+        }
+    }
+
+    /// Where the file `path`, given relative to the directory the command was run in,
+    /// is from the top of the work tree.
+    pub fn file(&self, path: &Path) -> PathBuf {
+        Path::new(&self.prefix).join(path)
+    }
+}
+
+/// Where `cwd` is below `work_tree`, as [`Repository::prefix`] has it.
+fn prefix_in(work_tree: &Path, cwd: &Path) -> String {
+    let mut prefix = String::new();
+    if let Ok(below) = cwd.strip_prefix(work_tree) {
+        for component in below {
+            prefix.push_str(&component.to_string_lossy());
+            prefix.push('/');
+        }
+    }
+    prefix
+}
+
+/// Refuse the repository with the work tree `work_tree` and git directory `git_dir`
+/// unless the current user owns them both (and the `.git` file between them, if
+/// there is one), or the work tree is listed in `safe.directory`, as git has done
+/// since 2.35.2 so that a repository planted by another user in a shared directory
+/// can't run its hooks or config-named commands as whoever wanders into it.
+///
+/// `safe.directory` is only read from the system and global config and the
+/// environment, never from a repository's own. Each value is a path (`~/` for the
+/// home directory), `*` for every repository, or empty to forget the values before
+/// it. Running as root via `sudo`, the user `SUDO_UID` names counts as the owner too.
+pub fn ensure_valid_ownership(work_tree: &Path, git_dir: &Path) -> Result<()> {
+    let Some(uid) = current_uid() else {
+        return Ok(());
+    };
+    let owned = |path: &Path| {
+        symlink_metadata(path).is_ok_and(|metadata| {
+            metadata.uid() == uid
+                || (uid == 0
+                    && env::var("SUDO_UID").is_ok_and(|sudo| sudo == metadata.uid().to_string()))
+        })
+    };
+    let dot_git = work_tree.join(".git");
+    let gitfile_owned = !symlink_metadata(&dot_git).is_ok_and(|m| m.is_file()) || owned(&dot_git);
+    if owned(work_tree) && owned(git_dir) && gitfile_owned {
+        return Ok(());
+    }
+
+    let work_tree = canonicalize(work_tree)
+        .with_context(|| format!("failed to resolve {}", work_tree.display()))?;
+    let config = Config::load_protected()?;
+    let mut safe = false;
+    for value in config.get_all("safe.directory") {
+        safe = match value {
+            "" => false,
+            "*" => true,
+            _ => {
+                safe || value
+                    .strip_prefix("~/")
+                    .and_then(|rest| env::home_dir().map(|home| home.join(rest)))
+                    .unwrap_or_else(|| PathBuf::from(value))
+                    .as_path()
+                    .components()
+                    .eq(work_tree.components())
+            }
+        };
+    }
+    if !safe {
+        let dir = work_tree.display();
+        bail!(
+            "detected dubious ownership in repository at '{dir}'\n\
+             To add an exception for this directory, call:\n\
+             \n\
+             \tgit config --global --add safe.directory {dir}"
+        );
+    }
+    Ok(())
+}
+
+/// The effective user id of this process, from `/proc/self/status`; `None` where
+/// there is no such file to read it from.
+fn current_uid() -> Option<u32> {
+    let status = read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find_map(|line| line.strip_prefix("Uid:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// The git directory of the work tree at `path`: its `.git` directory, or the one a
+/// `.git` file points to. `None` if `path` has neither.
+pub fn work_tree_git_dir(path: &Path) -> Option<PathBuf> {
+    let dot_git = path.join(".git");
+    if symlink_metadata(&dot_git).ok()?.is_dir() {
+        return Some(dot_git);
+    }
+    read_gitfile(&dot_git).ok()
+}
+
+/// The directory a `.git` file (`gitdir: <path>`) points to; a relative path is
+/// relative to the directory holding the file.
+pub fn read_gitfile(path: &Path) -> Result<PathBuf> {
+    let content =
+        read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let Some(dir) = content.strip_prefix("gitdir: ") else {
+        bail!("invalid gitfile format: {}", path.display());
+    };
+    let dir = path.parent().unwrap_or(Path::new("")).join(dir.trim_end());
+    if !dir.is_dir() {
+        bail!("not a git repository: {}", dir.display());
+    }
+    Ok(dir)
+}
+
+/// The directory holding what all worktrees of a repository share: the one named by
+/// `<git_dir>/commondir` for a linked worktree, otherwise `git_dir` itself.
+pub fn common_dir(git_dir: &Path) -> PathBuf {
+    match read_to_string(git_dir.join("commondir")) {
+        Ok(dir) => git_dir.join(dir.trim_end()),
+        Err(_) => git_dir.to_path_buf(),
+    }
+}
+
+/// The hash function of each object store looked up so far.
+static HASH_ALGORITHMS: OnceLock<Mutex<HashMap<PathBuf, HashAlgorithm>>> = OnceLock::new();
+
+/// The hash function of the repository whose object store is `objects_dir`: SHA-1
+/// unless its config sets `extensions.objectFormat`, which like every extension needs
+/// `core.repositoryFormatVersion` 1. A directory inside a store, such as a push's
+/// quarantine, belongs to the same repository.
+pub fn hash_algorithm_in(objects_dir: &Path) -> Result<HashAlgorithm> {
+    let mut cache = HASH_ALGORITHMS.get_or_init(Mutex::default).lock().unwrap();
+    if let Some(&algorithm) = cache.get(objects_dir) {
+        return Ok(algorithm);
+    }
+    let repository = match objects_dir.parent() {
+        Some(parent) if parent.file_name() == Some("objects".as_ref()) => parent.parent(),
+        parent => parent,
+    };
+    let mut config = Config::default();
+    if let Some(repository) = repository {
+        config.read_file(&repository.join("config"))?;
+    }
+    let algorithm = match config.get("extensions.objectformat") {
+        None => HashAlgorithm::Sha1,
+        Some(_) if config.get("core.repositoryformatversion") != Some("1") => {
+            bail!("repo version is 0, but v1-only extension found:\n\tobjectformat")
+        }
+        Some(name) => HashAlgorithm::from_name(name).with_context(|| {
+            format!("unknown object format '{name}' in extensions.objectFormat")
+        })?,
+    };
+    cache.insert(objects_dir.to_path_buf(), algorithm);
+    Ok(algorithm)
+}
+
+/// Entries of the common directory that every worktree shares.
+const SHARED: [&str; 16] = [
+    "branches",
+    "common",
+    "config",
+    "gc.pid",
+    "hooks",
+    "info",
+    "logs",
+    "lost-found",
+    "objects",
+    "packed-refs",
+    "refs",
+    "remotes",
+    "rr-cache",
+    "shallow",
+    "svn",
+    "worktrees",
+];
+
+/// Parts of [`SHARED`] entries that each worktree has its own of.
+const PER_WORKTREE: [&str; 8] = [
+    "info/sparse-checkout",
+    "logs/HEAD",
+    "logs/refs/bisect",
+    "logs/refs/rewritten",
+    "logs/refs/worktree",
+    "refs/bisect",
+    "refs/rewritten",
+    "refs/worktree",
+];
+
+/// Where `path` (as in `refs/heads/main` or `HEAD`) is for the repository whose git
+/// directory is `git_dir`: in the common directory for objects, most refs, config and
+/// the like, or in `git_dir` for what is per worktree, such as `HEAD` and the index.
+pub fn git_path(git_dir: &Path, path: &str) -> PathBuf {
+    let per_worktree = PER_WORKTREE.iter().any(|prefix| {
+        path.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    });
+    let first = path.split('/').next().unwrap_or(path);
+    if !per_worktree && SHARED.contains(&first) {
+        common_dir(git_dir).join(path)
+    } else {
+        git_dir.join(path)
+    }
+}
@@ -0,0 +1,194 @@
+use crate::checkout::{
+    check_path_lengths, parse_hash, remove_worktree_file, stage_side, worktree_matches,
+};
+use crate::config::Config;
+use crate::diff::{DiffSide, TreeChange, diff_trees_to_check_out};
+use crate::index::{Index, IndexEntry, StatData};
+use crate::object_read::{Object, ObjectKind};
+use crate::parallel_checkout::write_worktree_files;
+use crate::refs::resolve_ref;
+use crate::repository::Repository;
+use anyhow::{Result, bail};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Restore `paths` to their contents in `source` (any tree-ish): in the working
+/// directory, discarding local changes, with `staged` in the index instead,
+/// unstaging changes, or in both with `staged` and `worktree`. A path names a file or
+/// everything under a directory; `.` means the whole tree.
+///
+/// Without a source, the working directory is restored from the index and the index
+/// from HEAD. Files under the given paths that are tracked but that the source
+/// doesn't have are removed, like `git restore` does; untracked files are left alone.
+pub fn git_restore(
+    repo: &Repository,
+    source: Option<&str>,
+    staged: bool,
+    worktree: bool,
+    paths: &[String],
+) -> Result<()> {
+    if paths.is_empty() {
+        bail!("you must specify path(s) to restore");
+    }
+    let worktree = worktree || !staged;
+    let mut index = Index::load(repo)?;
+    let index_files = index_files(&index);
+    let source_files = match source {
+        Some(source) => tree_files(repo, source)?,
+        None if staged => match resolve_ref(repo, "HEAD")? {
+            Some(_) => tree_files(repo, "HEAD")?,
+            None => Vec::new(),
+        },
+        None => index_files.clone(),
+    };
+
+    for path in paths {
+        if !source_files.iter().any(|f| matches_path(&f.path, path))
+            && !index_files.iter().any(|f| matches_path(&f.path, path))
+        {
+            bail!("pathspec '{path}' did not match any file(s) known to git");
+        }
+    }
+    if worktree {
+        update_paths(repo, &source_files, &index_files, paths)?;
+    }
+    if staged {
+        update_index(repo, &mut index, &source_files, paths, worktree)?;
+        index.save(repo)?;
+    }
+    Ok(())
+}
+
+/// `checkout [<tree-ish>] -- <paths>`: like [`git_restore`], but in git's overlay
+/// mode, where files under `paths` that `source` doesn't have are left alone, so
+/// every path has to name something in `source`. HEAD moves no more than with
+/// `restore`.
+///
+/// Files are checked out from the index, or from `source` into both the index and
+/// the working directory.
+pub fn git_checkout_paths(repo: &Repository, source: Option<&str>, paths: &[String]) -> Result<()> {
+    let mut index = Index::load(repo)?;
+    let source_files = match source {
+        Some(source) => tree_files(repo, source)?,
+        None => index_files(&index),
+    };
+    for path in paths {
+        if !source_files.iter().any(|f| matches_path(&f.path, path)) {
+            bail!("pathspec '{path}' did not match any file(s) known to git");
+        }
+    }
+    update_paths(repo, &source_files, &[], paths)?;
+    if source.is_some() {
+        let wanted = |file: &&TreeChange| paths.iter().any(|path| matches_path(&file.path, path));
+        for file in source_files.iter().filter(wanted) {
+            if let Some(side) = &file.new {
+                stage_side(repo, &mut index, &file.path, side)?;
+            }
+        }
+        index.save(repo)?;
+    }
+    Ok(())
+}
+
+/// Set the index entries under `paths` to those of `source_files`, removing the
+/// ones `source_files` doesn't have. With `checked_out`, the files were just written
+/// to the working directory too, and their stat data is recorded.
+fn update_index(
+    repo: &Repository,
+    index: &mut Index,
+    source_files: &[TreeChange],
+    paths: &[String],
+    checked_out: bool,
+) -> Result<()> {
+    let wanted = |path: &str| paths.iter().any(|p| matches_path(path, p));
+    let restored: HashSet<&str> = source_files
+        .iter()
+        .filter(|file| wanted(&file.path))
+        .map(|file| file.path.as_str())
+        .collect();
+    index
+        .entries
+        .retain(|entry| !wanted(&entry.path) || restored.contains(entry.path.as_str()));
+    for file in source_files.iter().filter(|file| wanted(&file.path)) {
+        let Some(side) = &file.new else { continue };
+        if checked_out {
+            stage_side(repo, index, &file.path, side)?;
+        } else {
+            let mode = u32::from_str_radix(&side.mode, 8)?;
+            index.add(IndexEntry::new(
+                &file.path,
+                mode,
+                side.hash,
+                StatData::default(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Write the files of `source_files` under `paths` to the working directory, after
+/// removing those of `old_files` under `paths` that `source_files` doesn't have.
+fn update_paths(
+    repo: &Repository,
+    source_files: &[TreeChange],
+    old_files: &[TreeChange],
+    paths: &[String],
+) -> Result<()> {
+    let wanted = |file: &&TreeChange| paths.iter().any(|path| matches_path(&file.path, path));
+    let config = Config::load(repo)?;
+    check_path_lengths(
+        repo,
+        &config,
+        source_files
+            .iter()
+            .filter(wanted)
+            .map(|file| file.path.as_str()),
+    )?;
+
+    let restored: HashSet<&str> = source_files
+        .iter()
+        .filter(wanted)
+        .map(|file| file.path.as_str())
+        .collect();
+    for file in old_files.iter().filter(wanted) {
+        if !restored.contains(file.path.as_str()) {
+            remove_worktree_file(repo, Path::new(&file.path))?;
+        }
+    }
+    let mut files = Vec::new();
+    for file in source_files.iter().filter(wanted) {
+        let Some(side) = &file.new else { continue };
+        if !worktree_matches(repo, Path::new(&file.path), Some(side))? {
+            files.push((file.path.as_str(), side));
+        }
+    }
+    write_worktree_files(repo, &config, &files)
+}
+
+/// Every stage 0 file in `index`, as additions like [`tree_files`] gives them.
+fn index_files(index: &Index) -> Vec<TreeChange> {
+    index
+        .entries
+        .iter()
+        .filter(|entry| entry.stage == 0)
+        .map(|entry| TreeChange {
+            path: entry.path.clone(),
+            old: None,
+            new: Some(DiffSide {
+                mode: format!("{:o}", entry.mode),
+                hash: entry.id,
+            }),
+        })
+        .collect()
+}
+
+/// Every file in the tree `tree_ish` names, in tree order.
+fn tree_files(repo: &Repository, tree_ish: &str) -> Result<Vec<TreeChange>> {
+    let (tree_hash, _) = Object::peel_to(repo, tree_ish, ObjectKind::Tree)?;
+    diff_trees_to_check_out(repo, None, Some(&parse_hash(&tree_hash)?))
+}
+
+fn matches_path(file: &str, path: &str) -> bool {
+    let path = path.trim_end_matches('/');
+    path == "." || file == path || file.starts_with(&format!("{path}/"))
+}
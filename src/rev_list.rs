@@ -0,0 +1,156 @@
+use crate::history::Simplification;
+use crate::object_read::disk_size;
+use crate::repository::Repository;
+use crate::revwalk::RevWalk;
+use anyhow::{Result, bail};
+use std::io::Write;
+
+/// What `git rev-list` lists and how.
+#[derive(Debug, Clone, Default)]
+pub struct RevListOptions {
+    /// Stop after this many commits (`-n`).
+    pub max_count: Option<usize>,
+    /// Print how many commits there are instead of their ids (`--count`).
+    pub count: bool,
+    /// How history limited to paths is simplified.
+    pub simplification: Simplification,
+    /// Follow only the first parent of merges (`--first-parent`).
+    pub first_parent: bool,
+    /// List the tags, trees and blobs the commits need too (`--objects`).
+    pub objects: bool,
+    /// Leave the paths off the trees and blobs `objects` lists (`--no-object-names`).
+    pub no_object_names: bool,
+    /// Print how many bytes what would be listed takes up in the repository instead
+    /// (`--disk-usage`), in bytes or, with [`DiskUsage::Human`], KiB, MiB or GiB.
+    pub disk_usage: Option<DiskUsage>,
+    /// Walk from every ref and `HEAD` as well as the revisions (`--all`).
+    pub all: bool,
+    /// Walk from every object the reflogs record as well (`--reflog`).
+    pub reflog: bool,
+    /// Find what is reachable from the reachability bitmaps of a pack where they
+    /// allow it (`--use-bitmap-index`), listing it in pack order without names.
+    pub use_bitmap_index: bool,
+}
+
+/// How `--disk-usage` shows its total.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskUsage {
+    Bytes,
+    Human,
+}
+
+/// `git rev-list [--all] [--reflog] <revision>... [-- <path>...]`: the ids of the
+/// commits reachable from the revisions (and with `all` every ref, with `reflog` what
+/// the reflogs record) but not from those starting with `^` (or the left side of
+/// `A..B`), newest first by committer date, one per line. With `paths`, only the commits that
+/// change something under them are listed, simplified as for `git log`; with
+/// `first_parent`, only the commits along the first parents of merges, as merged
+/// branches are seen from the branch they were merged into.
+///
+/// With `objects`, the revisions may name tags, trees and blobs too, and the commits
+/// are followed by everything else they need that the hidden commits don't have, as a
+/// pack of them would hold: the tags named (with their names), the trees and blobs
+/// named (with the path each was named by, e.g. `HEAD:src` as `src`), then the trees
+/// and blobs of each commit in turn, with their paths. Each is listed once, and a tree
+/// or blob is left out if a hidden commit just below the listed ones has it. `paths`
+/// limit the trees and blobs too, to those under them and the trees leading there.
+///
+/// `count` counts whatever would be listed; `disk_usage` adds up the space it takes up
+/// in the repository, packed entries at their size in the pack. With
+/// `use_bitmap_index`, what is reachable is read off a pack's bitmaps when there are
+/// some that cover it, which is much faster on a large history, though the order and
+/// names are lost (see [`RevWalk::walk_bitmaps`]). The list is written to `out`.
+pub fn git_rev_list(
+    repo: &Repository,
+    out: &mut impl Write,
+    revisions: &[String],
+    paths: &[String],
+    options: &RevListOptions,
+) -> Result<()> {
+    if revisions.is_empty() && !options.all && !options.reflog {
+        bail!("usage: git rev-list [<options>] <commit>... [--] [<path>...]");
+    }
+    let listed = list_revisions(repo, revisions, paths, options)?;
+
+    if let Some(format) = options.disk_usage {
+        let objects_dir = repo.objects_dir();
+        let mut total = 0;
+        for (hash, _) in &listed {
+            total += disk_size(&objects_dir, hash)?;
+        }
+        match format {
+            DiskUsage::Bytes => writeln!(out, "{total}")?,
+            DiskUsage::Human => writeln!(out, "{}", human_size(total))?,
+        }
+        return Ok(());
+    }
+    if options.count {
+        writeln!(out, "{}", listed.len())?;
+        return Ok(());
+    }
+    for (hash, name) in listed {
+        match name {
+            Some(name) if !options.no_object_names => writeln!(out, "{hash} {name}")?,
+            _ => writeln!(out, "{hash}")?,
+        }
+    }
+    Ok(())
+}
+
+/// What [`git_rev_list`] lists for `revisions`, in order: each commit's id, and with
+/// `options.objects` each tag, tree and blob's id with its name or path, as
+/// [`RevWalk::walk`] reaches them.
+pub fn list_revisions(
+    repo: &Repository,
+    revisions: &[String],
+    paths: &[String],
+    options: &RevListOptions,
+) -> Result<Vec<(String, Option<String>)>> {
+    let mut walk = RevWalk {
+        paths: paths.to_vec(),
+        simplification: options.simplification,
+        first_parent: options.first_parent,
+        max_count: options.max_count,
+        objects: options.objects,
+        ..RevWalk::from_revisions(revisions)
+    };
+    if options.all {
+        walk.push_refs(repo)?;
+    }
+    if options.reflog {
+        walk.push_reflogs(repo)?;
+    }
+    let reached = match options.use_bitmap_index {
+        true => walk.walk_bitmaps(repo)?,
+        false => None,
+    };
+    let reached = match reached {
+        Some(reached) => reached,
+        None => walk.walk(repo)?,
+    };
+    Ok(reached
+        .into_iter()
+        .map(|object| (object.hash, object.name))
+        .collect())
+}
+
+/// `bytes` as git shows a size to people: in GiB, MiB or KiB with two decimals, once
+/// it is over one of them, else in bytes.
+pub fn human_size(bytes: u64) -> String {
+    if bytes > 1 << 30 {
+        let hundredths = (bytes & ((1 << 30) - 1)) / 10737419;
+        format!("{}.{hundredths:02} GiB", bytes >> 30)
+    } else if bytes > 1 << 20 {
+        let rounded = bytes + 5243;
+        let hundredths = ((rounded & ((1 << 20) - 1)) * 100) >> 20;
+        format!("{}.{hundredths:02} MiB", rounded >> 20)
+    } else if bytes > 1 << 10 {
+        let rounded = bytes + 5;
+        let hundredths = ((rounded & ((1 << 10) - 1)) * 100) >> 10;
+        format!("{}.{hundredths:02} KiB", rounded >> 10)
+    } else if bytes == 1 {
+        "1 byte".to_string()
+    } else {
+        format!("{bytes} bytes")
+    }
+}
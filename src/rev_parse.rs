@@ -0,0 +1,341 @@
+use crate::commit_read::Commit;
+use crate::config::Config;
+use crate::diff::tree_entry_at;
+use crate::index::Index;
+use crate::object_read::{
+    MINIMUM_ABBREV, Object, ObjectKind, full_object_id, object_ids_with_prefix, unique_abbrev,
+};
+use crate::refs::{current_branch, expand_ref, resolve_ref, upstream_ref};
+use crate::repository::Repository;
+use anyhow::{Context, Result, anyhow, bail};
+use std::fs::read_to_string;
+use std::io::ErrorKind;
+
+/// `git rev-parse [--verify] [--short[=<length>]] <revision>...`: print the id each
+/// revision names, one per line; `^<rev>` prints `^<id>`, and `<a>..<b>` the id of
+/// `b`, then `^` and the id of `a` (either defaulting to HEAD). With `verify`, there
+/// must be exactly one plain revision. `short` (which implies `verify`) abbreviates
+/// the id to at least that many hex digits, as long as it stays unambiguous.
+pub fn git_rev_parse(
+    repo: &Repository,
+    revisions: &[String],
+    verify: bool,
+    short: Option<usize>,
+) -> Result<()> {
+    let verify = verify || short.is_some();
+    let show = |name: &str| -> Result<String> {
+        let hash = match resolve_revision(repo, name)? {
+            Some(hash) => hash,
+            None if verify => bail!("Needed a single revision"),
+            None => bail!(
+                "ambiguous argument '{name}': unknown revision or path not in the working tree.\n\
+                 Use '--' to separate paths from revisions, like this:\n\
+                 'git <command> [<revision>...] -- [<file>...]'"
+            ),
+        };
+        match short {
+            Some(len) => unique_abbrev(&repo.objects_dir(), &hash.parse()?, len),
+            None => Ok(hash),
+        }
+    };
+    if verify {
+        let [revision] = revisions else {
+            bail!("Needed a single revision");
+        };
+        println!("{}", show(revision)?);
+        return Ok(());
+    }
+    for revision in revisions {
+        if let Some((from, to)) = revision.split_once("..") {
+            let to = show(if to.is_empty() { "HEAD" } else { to })?;
+            let from = show(if from.is_empty() { "HEAD" } else { from })?;
+            println!("{to}\n^{from}");
+        } else if let Some(hidden) = revision.strip_prefix('^') {
+            println!("^{}", show(hidden)?);
+        } else {
+            println!("{}", show(revision)?);
+        }
+    }
+    Ok(())
+}
+
+/// Resolve the revision `name` to the full id of the object it names; see
+/// [`resolve_revision`] for the syntax.
+pub fn rev_parse(repo: &Repository, name: &str) -> Result<String> {
+    resolve_revision(repo, name)?.ok_or_else(|| anyhow!("not a valid object name: {name}"))
+}
+
+/// The full id of the object the revision `name` names, as gitrevisions(7) spells
+/// them, or `None` if it names none:
+///
+/// - a ref (`main`, `origin/main`, `refs/tags/v1`, `HEAD`; `@` alone is HEAD) or an
+///   object id, abbreviated to at least 4 hex digits if it stays unambiguous;
+/// - `<ref>@{<n>}`, the value the ref had `n` changes ago by its reflog (`@{<n>}`
+///   for the current branch), and `@{-<n>}`, the branch checked out `n` checkouts ago;
+/// - `<branch>@{upstream}` (or `@{u}`), the ref the branch tracks; the current
+///   branch's without a name;
+/// - `<rev>~<n>`, the `n`th first-parent ancestor, and `<rev>^<n>`, the `n`th parent
+///   (a bare `~` or `^` meaning 1, and `^0` the commit itself);
+/// - `<rev>^{<type>}`, the object peeled to a `commit`, `tree`, `blob` or `tag`, or
+///   `^{}` to whatever isn't a tag, and `^{object}` just checking it exists;
+/// - `<rev>:<path>`, the entry at `path` in the tree of `rev` (its tree with an empty
+///   path), and `:<path>` or `:<n>:<path>`, the entry at stage `n` (0 by default)
+///   of the index.
+///
+/// Names that are found but don't lead anywhere, like a path missing from a tree or a
+/// reflog entry past its end, are errors, worded as git words them.
+pub fn resolve_revision(repo: &Repository, name: &str) -> Result<Option<String>> {
+    if let Some(path) = name.strip_prefix(':') {
+        return index_entry(repo, path).map(Some);
+    }
+    if let Some((rev, path)) = name.split_once(':') {
+        let Some(tree) = resolve_suffixed(repo, rev)? else {
+            return Ok(None);
+        };
+        let Ok((tree, _)) = Object::peel_to(repo, &tree, ObjectKind::Tree) else {
+            return Ok(None);
+        };
+        let path = path.trim_end_matches('/');
+        if path.is_empty() {
+            return Ok(Some(tree));
+        }
+        return match tree_entry_at(repo, Some(&tree.parse()?), path)? {
+            Some((_, hash)) => Ok(Some(hash.to_string())),
+            None => bail!("path '{path}' does not exist in '{rev}'"),
+        };
+    }
+    resolve_suffixed(repo, name)
+}
+
+/// A revision that may end in `~<n>`, `^<n>` or `^{<type>}`, applied right to left.
+fn resolve_suffixed(repo: &Repository, name: &str) -> Result<Option<String>> {
+    if let Some(rest) = name.strip_suffix('}')
+        && let Some((base, kind)) = rest.rsplit_once("^{")
+    {
+        let Some(hash) = resolve_suffixed(repo, base)? else {
+            return Ok(None);
+        };
+        return peel(repo, &hash, kind);
+    }
+    let digits = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    for (operator, first_parent) in [('~', true), ('^', false)] {
+        let Some(base) = digits.strip_suffix(operator) else {
+            continue;
+        };
+        let count: usize = match &name[digits.len()..] {
+            "" => 1,
+            count => count.parse().context("revision number out of range")?,
+        };
+        let Some(hash) = resolve_suffixed(repo, base)? else {
+            return Ok(None);
+        };
+        let Ok(mut commit) = Commit::read(repo, &hash) else {
+            return Ok(None);
+        };
+        if first_parent {
+            for _ in 0..count {
+                let Some(parent) = commit.parents.first() else {
+                    return Ok(None);
+                };
+                commit = Commit::read(repo, parent)?;
+            }
+            return Ok(Some(commit.hash));
+        }
+        return Ok(match count {
+            0 => Some(commit.hash),
+            n => commit.parents.get(n - 1).cloned(),
+        });
+    }
+    resolve_name(repo, name)
+}
+
+/// `hash` peeled as `<rev>^{<kind>}` asks: `None` if it can't be.
+fn peel(repo: &Repository, hash: &str, kind: &str) -> Result<Option<String>> {
+    let kind = match kind {
+        "" => {
+            let mut hash = hash.to_string();
+            let mut object = Object::read_git_object(repo, &hash)?;
+            while object.kind == ObjectKind::Tag {
+                let data = object.read_all()?;
+                let text = String::from_utf8_lossy(&data);
+                let Some(target) = text.lines().next().and_then(|l| l.strip_prefix("object "))
+                else {
+                    bail!("tag {hash} doesn't start with an 'object' line");
+                };
+                hash = target.to_string();
+                object = Object::read_git_object(repo, &hash)?;
+            }
+            return Ok(Some(hash));
+        }
+        "object" => {
+            return Ok(Object::read_git_object(repo, hash)
+                .ok()
+                .map(|_| hash.to_string()));
+        }
+        "commit" => ObjectKind::Commit,
+        "tree" => ObjectKind::Tree,
+        "blob" => ObjectKind::Blob,
+        "tag" => ObjectKind::Tag,
+        _ => return Ok(None),
+    };
+    Ok(Object::peel_to(repo, hash, kind).ok().map(|(hash, _)| hash))
+}
+
+/// A ref, maybe with an `@{...}` suffix, or an object id.
+fn resolve_name(repo: &Repository, name: &str) -> Result<Option<String>> {
+    if name == "@" {
+        return resolve_ref(repo, "HEAD");
+    }
+    if let Some(rest) = name.strip_suffix('}')
+        && let Some((ref_name, spec)) = rest.split_once("@{")
+    {
+        if spec.eq_ignore_ascii_case("u") || spec.eq_ignore_ascii_case("upstream") {
+            return upstream(repo, ref_name).map(Some);
+        }
+        if let Some(n) = spec.strip_prefix('-') {
+            return match n.parse() {
+                Ok(n) if n > 0 && ref_name.is_empty() => previous_branch(repo, n),
+                _ => Ok(None),
+            };
+        }
+        // Dates (`@{yesterday}`) and `@{push}` aren't understood.
+        let Ok(n) = spec.parse() else {
+            return Ok(None);
+        };
+        return reflog_entry(repo, ref_name, n);
+    }
+    if let Some(hash) = resolve_ref(repo, name)? {
+        return Ok(Some(hash));
+    }
+    let hex_len = repo.hash_algorithm()?.hex_len();
+    if name.len() < MINIMUM_ABBREV
+        || name.len() > hex_len
+        || !name.bytes().all(|b| b.is_ascii_hexdigit())
+    {
+        return Ok(None);
+    }
+    let name = name.to_ascii_lowercase();
+    if name.len() == hex_len {
+        return Ok(Some(name));
+    }
+    if object_ids_with_prefix(repo, &name)?.is_empty() {
+        return Ok(None);
+    }
+    full_object_id(repo, &name).map(Some)
+}
+
+/// `<ref>@{<n>}`: the value `ref_name` (the current branch, or HEAD if it is
+/// detached, when empty) had `n` entries back in its reflog, `@{0}` being the latest.
+fn reflog_entry(repo: &Repository, ref_name: &str, n: usize) -> Result<Option<String>> {
+    let (shown, full_name) = match ref_name {
+        "" => match current_branch(repo)? {
+            Some(branch) => (branch.clone(), format!("refs/heads/{branch}")),
+            None => ("HEAD".to_string(), "HEAD".to_string()),
+        },
+        _ => match expand_ref(repo, ref_name)? {
+            Some((full_name, _)) => (ref_name.to_string(), full_name),
+            None => return Ok(None),
+        },
+    };
+    let entries = read_reflog(repo, &full_name)?;
+    let entries: Vec<(&str, &str)> = entries
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split(' ');
+            Some((words.next()?, words.next()?))
+        })
+        .collect();
+    if n < entries.len() {
+        return Ok(Some(entries[entries.len() - 1 - n].1.to_string()));
+    }
+    // The oldest entry still records the value the ref had before it.
+    if n == entries.len()
+        && let Some((old, _)) = entries.first()
+        && !old.bytes().all(|b| b == b'0')
+    {
+        return Ok(Some(old.to_string()));
+    }
+    bail!("log for '{shown}' only has {} entries", entries.len())
+}
+
+/// `@{-<n>}`: the `n`th last branch (or commit) HEAD was moved away from by a
+/// checkout, as recorded in its reflog.
+fn previous_branch(repo: &Repository, n: usize) -> Result<Option<String>> {
+    let log = read_reflog(repo, "HEAD")?;
+    let Some(previous) = log
+        .lines()
+        .rev()
+        .filter_map(|line| {
+            let (_, message) = line.split_once('\t')?;
+            let moves = message.strip_prefix("checkout: moving from ")?;
+            Some(moves.split_once(" to ")?.0)
+        })
+        .nth(n - 1)
+    else {
+        return Ok(None);
+    };
+    match resolve_ref(repo, &format!("refs/heads/{previous}"))? {
+        Some(hash) => Ok(Some(hash)),
+        None => resolve_revision(repo, previous),
+    }
+}
+
+/// `<branch>@{upstream}`: what the branch (the current one if `branch` is empty or
+/// `HEAD`) tracks, which must have been fetched.
+fn upstream(repo: &Repository, branch: &str) -> Result<String> {
+    let branch = match branch {
+        "" | "HEAD" => {
+            current_branch(repo)?.ok_or_else(|| anyhow!("HEAD does not point to a branch"))?
+        }
+        name => {
+            let branch = name.strip_prefix("refs/heads/").unwrap_or(name);
+            if resolve_ref(repo, &format!("refs/heads/{branch}"))?.is_none() {
+                bail!("no such branch: '{name}'");
+            }
+            branch.to_string()
+        }
+    };
+    let config = Config::load(repo)?;
+    let Some(upstream) = upstream_ref(&config, &branch) else {
+        bail!("no upstream configured for branch '{branch}'");
+    };
+    match resolve_ref(repo, &upstream)? {
+        Some(hash) => Ok(hash),
+        None => bail!(
+            "upstream branch '{}' not stored as a remote-tracking branch",
+            config
+                .get(&format!("branch.{branch}.merge"))
+                .unwrap_or_default()
+        ),
+    }
+}
+
+/// `:<path>` or `:<n>:<path>`: the id of the index entry at stage `n` for `path`.
+fn index_entry(repo: &Repository, spec: &str) -> Result<String> {
+    let (stage, path) = match spec.as_bytes() {
+        [stage @ b'0'..=b'3', b':', ..] => (stage - b'0', &spec[2..]),
+        _ => (0, spec),
+    };
+    let index = Index::load(repo)?;
+    let mut entries = index.entries.iter().filter(|entry| entry.path == path);
+    if let Some(entry) = entries.clone().find(|entry| entry.stage == stage) {
+        return Ok(entry.id.to_string());
+    }
+    if entries.next().is_some() {
+        bail!("path '{path}' is in the index, but not at stage {stage}");
+    }
+    if repo.path(path).symlink_metadata().is_ok() {
+        bail!("path '{path}' exists on disk, but not in the index");
+    }
+    bail!("path '{path}' does not exist (neither on disk nor in the index)")
+}
+
+/// The text of the reflog of `ref_name`; empty if it has none.
+fn read_reflog(repo: &Repository, ref_name: &str) -> Result<String> {
+    let path = repo.git_path(&format!("logs/{ref_name}"));
+    match read_to_string(&path) {
+        Ok(text) => Ok(text),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+    }
+}
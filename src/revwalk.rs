@@ -0,0 +1,305 @@
+use crate::bitmap::PackBitmaps;
+use crate::commit_read::{Commit, walk_commits_with};
+use crate::history::{Simplification, revision_range, simplified_history};
+use crate::object_id::ObjectId;
+use crate::object_read::{Object, ObjectKind};
+use crate::refs::{list_refs, resolve_ref, shorten_ref};
+use crate::repository::{Repository, git_path};
+use crate::rev_parse::resolve_revision;
+use crate::transfer::{TreeWalk, tag_target};
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs::{read_dir, read_to_string};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// An object a [`RevWalk`] reached: its id and kind, and for a tag the revision it
+/// was named by, for a tree or blob its path.
+#[derive(Debug, Clone)]
+pub struct ReachedObject {
+    pub hash: String,
+    pub kind: ObjectKind,
+    pub name: Option<String>,
+    /// For a tree or blob, the different one a hidden commit has at its path, which
+    /// whoever has that commit can take it as a delta against.
+    pub base: Option<String>,
+}
+
+/// A walk over the object graph, as `git rev-list` walks it: the commits reachable
+/// from `tips` but not from `hide`, and with `objects` the tags, trees and blobs they
+/// need that the hidden commits (and the hidden tags, trees and blobs) don't have.
+#[derive(Debug, Clone, Default)]
+pub struct RevWalk {
+    /// The revisions to walk from: commits, or with `objects` tags, trees and blobs.
+    pub tips: Vec<String>,
+    /// The commits whose history is left out, or with `objects` also tags, trees and
+    /// blobs left out with what they reference.
+    pub hide: Vec<String>,
+    /// Limit the walk to the commits changing these paths, and the trees and blobs to
+    /// those under them.
+    pub paths: Vec<String>,
+    /// How history limited to `paths` is simplified.
+    pub simplification: Simplification,
+    /// Follow only the first parent of merges.
+    pub first_parent: bool,
+    /// Stop after this many commits.
+    pub max_count: Option<usize>,
+    /// Reach the tags, trees and blobs too.
+    pub objects: bool,
+}
+
+impl RevWalk {
+    /// A walk of `revisions` as `git rev-list` reads them: `^A` hides what `A` reaches
+    /// and `A..B` stands for `^A B` (see [`revision_range`]).
+    pub fn from_revisions(revisions: &[String]) -> RevWalk {
+        let (tips, hide) = revision_range(revisions);
+        RevWalk {
+            tips,
+            hide,
+            ..RevWalk::default()
+        }
+    }
+
+    /// Walk from every ref and `HEAD`, as `--all` does, passing over those whose
+    /// object is missing. Refs are pushed by their short names (`v1.0` for
+    /// `refs/tags/v1.0`), which tags are reached by, where those aren't ambiguous.
+    pub fn push_refs(&mut self, repo: &Repository) -> Result<()> {
+        for (name, hash) in list_refs(repo, "refs")? {
+            if !repo.has_object(&hash) {
+                continue;
+            }
+            let short = shorten_ref(&name);
+            match resolve_revision(repo, short).ok().flatten() == Some(hash) {
+                true => self.tips.push(short.to_string()),
+                false => self.tips.push(name),
+            }
+        }
+        if let Some(hash) = resolve_ref(repo, "HEAD")?
+            && repo.has_object(&hash)
+        {
+            self.tips.push("HEAD".to_string());
+        }
+        Ok(())
+    }
+
+    /// Walk from every object a reflog has recorded, as `--reflog` does, passing over
+    /// those that are missing.
+    pub fn push_reflogs(&mut self, repo: &Repository) -> Result<()> {
+        let git_dir = &repo.git_dir;
+        let mut logs = vec![git_path(git_dir, "logs/HEAD")];
+        reflog_files(&git_path(git_dir, "logs/refs"), &mut logs)?;
+        let mut logged = HashSet::new();
+        for log in logs {
+            let text = match read_to_string(&log) {
+                Ok(text) => text,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(e).with_context(|| format!("failed to read {}", log.display()));
+                }
+            };
+            for line in text.lines() {
+                for hash in line.split(' ').take(2) {
+                    if hash.bytes().any(|b| b != b'0')
+                        && repo.has_object(hash)
+                        && logged.insert(hash.to_string())
+                    {
+                        self.tips.push(hash.to_string());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Everything the walk reaches, in the order `git rev-list` lists it: the commits
+    /// newest first by committer date (simplified as for `git log` with `paths`), then
+    /// with `objects` the tags named (with their names), the trees and blobs named
+    /// (with the path each was named by, e.g. `HEAD:src` as `src`), and then the trees
+    /// and blobs of each commit in turn, with their paths. Each object is reached
+    /// once, and a tree or blob is left out if a hidden commit just below the listed
+    /// ones has it. Nothing is reached if there are no tips.
+    pub fn walk(&self, repo: &Repository) -> Result<Vec<ReachedObject>> {
+        if self.tips.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut commit_tips = Vec::new();
+        let mut named = Vec::new();
+        for tip in &self.tips {
+            match self.objects {
+                true => peel_tip(repo, tip, &mut commit_tips, &mut named)?,
+                false => commit_tips.push(tip.clone()),
+            }
+        }
+        let mut hide = Vec::new();
+        let mut hidden_named = Vec::new();
+        for hidden in &self.hide {
+            match self.objects {
+                true => peel_tip(repo, hidden, &mut hide, &mut hidden_named)?,
+                false => hide.push(hidden.clone()),
+            }
+        }
+        let commits = simplified_history(
+            repo,
+            &commit_tips,
+            &hide,
+            &self.paths,
+            self.simplification,
+            self.first_parent,
+        )?;
+        let commits: Vec<_> = commits
+            .into_iter()
+            .take(self.max_count.unwrap_or(usize::MAX))
+            .collect();
+        let mut reached: Vec<ReachedObject> = commits
+            .iter()
+            .map(|commit| ReachedObject {
+                hash: commit.hash.clone(),
+                kind: ObjectKind::Commit,
+                name: None,
+                base: None,
+            })
+            .collect();
+        if !self.objects {
+            return Ok(reached);
+        }
+
+        let mut trees = TreeWalk::new(HashSet::new());
+        if !hide.is_empty() {
+            let hidden =
+                walk_commits_with(repo, &commit_tips, &hide, self.first_parent, |_, _| Ok(()))?
+                    .hidden;
+            let mut boundary = HashSet::new();
+            for parent in commits.iter().flat_map(|commit| &commit.parents) {
+                if hidden.contains(parent) && boundary.insert(parent) {
+                    trees.mark_known(repo, &Commit::read(repo, parent)?.tree)?;
+                }
+            }
+        }
+        for (hash, _, kind) in hidden_named {
+            match kind {
+                ObjectKind::Tag => {
+                    trees.insert_known(&hash);
+                }
+                _ => trees.mark_known(repo, &hash)?,
+            }
+        }
+        for (hash, name, kind) in named {
+            match kind {
+                ObjectKind::Tag => {
+                    if trees.insert_known(&hash) {
+                        reached.push(ReachedObject {
+                            hash,
+                            kind,
+                            name: Some(name),
+                            base: None,
+                        });
+                    }
+                }
+                _ => trees.add(repo, &hash, &name, &[])?,
+            }
+        }
+        for commit in &commits {
+            trees.add(repo, &commit.tree, "", &self.paths)?;
+        }
+        reached.extend(trees.objects.into_iter().map(|object| ReachedObject {
+            hash: object.hash,
+            kind: object.kind,
+            name: Some(object.path),
+            base: object.base,
+        }));
+        Ok(reached)
+    }
+
+    /// What [`RevWalk::walk`] reaches, found from the reachability bitmaps of a pack
+    /// (see [`PackBitmaps`]) instead of by walking, as `--use-bitmap-index` does: the
+    /// commits, and with `objects` everything else, in the order of the pack and
+    /// without names. `None` if that can't be done: without bitmaps, with `paths`,
+    /// `first_parent` or `max_count`, which bitmaps know nothing of, or if some object
+    /// reached isn't in the pack with the bitmaps.
+    pub fn walk_bitmaps(&self, repo: &Repository) -> Result<Option<Vec<ReachedObject>>> {
+        if !self.paths.is_empty() || self.first_parent || self.max_count.is_some() {
+            return Ok(None);
+        }
+        let Some(bitmaps) = PackBitmaps::open(&repo.objects_dir(), repo.hash_algorithm()?)? else {
+            return Ok(None);
+        };
+        let resolve = |revisions: &[String]| -> Result<Option<Vec<ObjectId>>> {
+            let mut ids = Vec::new();
+            for revision in revisions {
+                match resolve_revision(repo, revision).ok().flatten() {
+                    Some(hash) => ids.push(hash.parse()?),
+                    None => return Ok(None),
+                }
+            }
+            Ok(Some(ids))
+        };
+        let (Some(tips), Some(hide)) = (resolve(&self.tips)?, resolve(&self.hide)?) else {
+            return Ok(None);
+        };
+        let Some(reached) = bitmaps.reachable(repo, &tips, &hide)? else {
+            return Ok(None);
+        };
+        Ok(Some(
+            bitmaps
+                .objects(&reached)
+                .filter(|(_, kind)| self.objects || *kind == ObjectKind::Commit)
+                .map(|(id, kind)| ReachedObject {
+                    hash: id.to_string(),
+                    kind,
+                    name: None,
+                    base: None,
+                })
+                .collect(),
+        ))
+    }
+}
+
+/// Sort the revision `tip` into a commit to walk from, in `commits`, or tags, trees
+/// and blobs to list as they are, in `named` with the names they are listed by: a tag
+/// is named by the revision and peeled, and a tree or blob by the path in the
+/// revision (`HEAD:src` is `src`), if any. A revision that can't be resolved is left
+/// to the walk to report.
+fn peel_tip(
+    repo: &Repository,
+    tip: &str,
+    commits: &mut Vec<String>,
+    named: &mut Vec<(String, String, ObjectKind)>,
+) -> Result<()> {
+    let Some(mut hash) = resolve_revision(repo, tip).ok().flatten() else {
+        commits.push(tip.to_string());
+        return Ok(());
+    };
+    loop {
+        let mut object = Object::read_git_object(repo, &hash)?;
+        match object.kind {
+            ObjectKind::Commit => break commits.push(hash),
+            ObjectKind::Tag => {
+                named.push((hash.clone(), tip.to_string(), ObjectKind::Tag));
+                let target = tag_target(&object.read_all()?);
+                hash = target.with_context(|| format!("invalid tag {hash}"))?;
+            }
+            kind => {
+                let path = tip.split_once(':').map_or("", |(_, path)| path);
+                break named.push((hash, path.to_string(), kind));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Add the reflogs under `dir` to `logs`.
+fn reflog_files(dir: &Path, logs: &mut Vec<PathBuf>) -> Result<()> {
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", dir.display())),
+    };
+    for entry in entries {
+        let entry = entry?;
+        match entry.file_type()?.is_dir() {
+            true => reflog_files(&entry.path(), logs)?,
+            false => logs.push(entry.path()),
+        }
+    }
+    Ok(())
+}
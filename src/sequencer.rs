@@ -0,0 +1,688 @@
+use crate::checkout::{parse_hash, reset_worktree};
+use crate::commit::{CommitBuilder, strip_comments};
+use crate::commit_read::{Commit, walk_commits};
+use crate::config::Config;
+use crate::diff::{DiffOptions, print_tree_diff};
+use crate::index::Index;
+use crate::merge::{checkout_merge, merge_trees};
+use crate::object_read::{Object, ObjectKind};
+use crate::refs::{current_branch, delete_ref, resolve_ref, update_ref};
+use crate::repository::Repository;
+use crate::rev_parse::resolve_revision;
+use crate::status::{StatusOptions, format_long, git_status};
+use crate::worktree::index_differs;
+use crate::write_tree::git_write_tree;
+use anyhow::{Context, Result, bail};
+use std::collections::VecDeque;
+use std::fs::{create_dir_all, read_to_string, remove_dir_all, remove_file, write};
+use std::io::{Cursor, Write};
+use std::path::PathBuf;
+
+/// Where a `cherry-pick` or `revert` of several commits keeps what is left to do, so
+/// that it can stop at a conflict and carry on once the user has resolved it.
+///
+/// It holds `head` (HEAD before the first commit, empty on an unborn branch), `todo`
+/// (a `pick <commit> <subject>` or `revert ...` line for each commit still to do,
+/// the one stopped at first), `opts` (the [`SequencerOptions`] given, as a config
+/// file) and `abort-safety` (HEAD as the last pick left it), all as git has them. A
+/// single commit needs no such state: `CHERRY_PICK_HEAD` (or `REVERT_HEAD`) and
+/// `MERGE_MSG` alone say what is being done, as in git.
+fn state_dir(repo: &Repository) -> PathBuf {
+    repo.git_path("sequencer")
+}
+
+/// What [`git_cherry_pick`] and [`git_revert`] do with commits that change nothing.
+#[derive(Debug, Default, Clone)]
+pub struct SequencerOptions {
+    /// Commit a picked commit that made no changes itself (`--allow-empty`), rather
+    /// than stopping at it.
+    pub allow_empty: bool,
+    /// Commit one that makes no changes here, HEAD already having them
+    /// (`--keep-redundant-commits`); implies `allow_empty`.
+    pub keep_redundant_commits: bool,
+    /// For a merge, the number (from 1) of the parent whose side of it the changes
+    /// are taken against (`-m`).
+    pub mainline: Option<usize>,
+}
+
+/// The commits a sequence has still to pick or revert, in order.
+type Todo = VecDeque<(Action, String)>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Pick,
+    Revert,
+}
+
+impl Action {
+    fn command(self) -> &'static str {
+        match self {
+            Action::Pick => "cherry-pick",
+            Action::Revert => "revert",
+        }
+    }
+
+    /// The word for it in the `todo` file.
+    fn todo_word(self) -> &'static str {
+        match self {
+            Action::Pick => "pick",
+            Action::Revert => "revert",
+        }
+    }
+
+    /// The file naming the commit it stopped at.
+    fn head_file(self) -> &'static str {
+        match self {
+            Action::Pick => "CHERRY_PICK_HEAD",
+            Action::Revert => "REVERT_HEAD",
+        }
+    }
+}
+
+/// `git cherry-pick <commit>...`: commit the changes each commit (or each of a range
+/// `A..B`, oldest first) made, on top of HEAD, with its author and message.
+///
+/// A commit's changes are those from its parent, merged into HEAD's tree by
+/// [`merge_trees`]; a root commit's are from the empty tree, so all its files are
+/// added. A merge needs `mainline` to say which parent to take them against.
+///
+/// A commit whose changes are already in HEAD, or that made none itself, isn't
+/// committed unless `options` allow it; git's advice is printed and the pick stops
+/// there, as it does at a conflict, with `CHERRY_PICK_HEAD` and `MERGE_MSG` written
+/// for [`git_sequencer_continue`], [`git_sequencer_skip`] or [`git_sequencer_abort`]
+/// (or a plain `commit`). The exit status is then 1.
+///
+/// Nothing may be staged to start with; changes in the working tree are kept, unless
+/// the pick would overwrite them (see [`checkout_merge`]).
+pub fn git_cherry_pick(
+    repo: &Repository,
+    config: &Config,
+    out: &mut impl Write,
+    err: &mut impl Write,
+    revisions: &[String],
+    options: &SequencerOptions,
+) -> Result<i32> {
+    start(repo, config, out, err, Action::Pick, revisions, options)
+}
+
+/// `git revert <commit>...`: commit the reverse of the changes each commit made, on
+/// top of HEAD, as a commit of its own with the message `Revert "<subject>"`. Reverting
+/// a root commit removes its files. Otherwise as [`git_cherry_pick`], with
+/// `REVERT_HEAD` written at a conflict; a revert that changes nothing stops with
+/// HEAD's status.
+pub fn git_revert(
+    repo: &Repository,
+    config: &Config,
+    out: &mut impl Write,
+    err: &mut impl Write,
+    revisions: &[String],
+    options: &SequencerOptions,
+) -> Result<i32> {
+    start(repo, config, out, err, Action::Revert, revisions, options)
+}
+
+/// `git cherry-pick --continue` (or `git revert --continue`): commit the commit the
+/// sequence stopped at as the user has resolved it in the index, then go on with
+/// the rest.
+pub fn git_sequencer_continue(
+    repo: &Repository,
+    config: &Config,
+    out: &mut impl Write,
+    err: &mut impl Write,
+) -> Result<i32> {
+    let (mut todo, options) = read_state(repo)?;
+    if let Some((action, hash)) = stopped_at(repo) {
+        let unmerged = unmerged_paths(repo)?;
+        if !unmerged.is_empty() {
+            let listed: String = unmerged.iter().map(|path| format!("\nU\t{path}")).collect();
+            bail!(
+                "{}\nfatal: Exiting because of an unresolved conflict.{listed}",
+                unmerged_error("Committing")
+            );
+        }
+        let commit = Commit::read(repo, &hash)?;
+        let message = read_to_string(repo.git_path("MERGE_MSG"))
+            .map(|message| strip_comments(&message))
+            .unwrap_or_else(|_| commit.message.clone());
+        let originally_empty = is_originally_empty(repo, &commit, options.mainline)?;
+        if !commit_result(
+            repo,
+            config,
+            out,
+            err,
+            action,
+            &commit,
+            message,
+            originally_empty,
+            &options,
+        )? {
+            return Ok(1);
+        }
+    } else if todo.is_none() {
+        bail!("no cherry-pick or revert in progress");
+    }
+    // The commit stopped at heads the todo list; it is done now, one way or another.
+    if let Some(todo) = &mut todo {
+        todo.pop_front();
+    }
+    run(repo, config, out, err, todo, &options)
+}
+
+/// `git cherry-pick --skip`: drop the commit the sequence stopped at, putting the
+/// working tree and index back to HEAD, and go on with the rest.
+pub fn git_sequencer_skip(
+    repo: &Repository,
+    config: &Config,
+    out: &mut impl Write,
+    err: &mut impl Write,
+) -> Result<i32> {
+    let (mut todo, options) = read_state(repo)?;
+    if stopped_at(repo).is_none() {
+        bail!("no cherry-pick or revert in progress");
+    }
+    reset_worktree(repo, resolve_ref(repo, "HEAD")?.as_deref())?;
+    clear_stopped(repo);
+    if let Some(todo) = &mut todo {
+        todo.pop_front();
+    }
+    run(repo, config, out, err, todo, &options)
+}
+
+/// `git cherry-pick --abort`: give up, putting the branch, working tree and index
+/// back as they were before the sequence started.
+pub fn git_sequencer_abort(repo: &Repository, config: &Config) -> Result<()> {
+    let state = state_dir(repo);
+    match read_to_string(state.join("head")) {
+        Ok(head) if head.trim().is_empty() => {
+            reset_worktree(repo, None)?;
+            if let Some(branch) = current_branch(repo)? {
+                delete_ref(repo, &format!("refs/heads/{branch}"))?;
+            }
+        }
+        Ok(head) => {
+            let head = head.trim();
+            reset_worktree(repo, Some(head))?;
+            update_ref(
+                repo,
+                config,
+                "HEAD",
+                head,
+                &format!("reset: moving to {head}"),
+            )?;
+        }
+        Err(_) if stopped_at(repo).is_some() => {
+            reset_worktree(repo, resolve_ref(repo, "HEAD")?.as_deref())?
+        }
+        Err(_) => bail!("no cherry-pick or revert in progress"),
+    }
+    clear_stopped(repo);
+    if state.exists() {
+        remove_dir_all(&state).with_context(|| format!("failed to remove {}", state.display()))?;
+    }
+    Ok(())
+}
+
+fn start(
+    repo: &Repository,
+    config: &Config,
+    out: &mut impl Write,
+    err: &mut impl Write,
+    action: Action,
+    revisions: &[String],
+    options: &SequencerOptions,
+) -> Result<i32> {
+    let command = action.command();
+    let mut commits = Vec::new();
+    for revision in revisions {
+        commits.extend(resolve_commits(repo, revision)?);
+    }
+    if commits.is_empty() {
+        bail!("empty commit set passed");
+    }
+    // A single commit needs no sequence, so, as in git, it doesn't mind one stopped.
+    if commits.len() > 1 && state_dir(repo).exists() {
+        let (todo, _) = read_state(repo)?;
+        let running = match todo.as_ref().and_then(|todo| todo.front()) {
+            Some((running, _)) => running.command(),
+            None => command,
+        };
+        let skip = if stopped_at(repo).is_some() {
+            "--skip | "
+        } else {
+            ""
+        };
+        bail!(
+            "{running} is already in progress\n\
+             hint: try \"git {running} (--continue | {skip}--abort)\""
+        );
+    }
+    if !unmerged_paths(repo)?.is_empty() {
+        let doing = match action {
+            Action::Pick => "Cherry-picking",
+            Action::Revert => "Reverting",
+        };
+        bail!(unmerged_error(doing));
+    }
+    if index_differs(repo, &Index::load(repo)?)? {
+        bail!(
+            "your local changes would be overwritten by {command}.\n\
+             hint: commit your changes or stash them to proceed."
+        );
+    }
+    if let [hash] = commits.as_slice() {
+        return Ok(if pick(repo, config, out, err, action, hash, options)? {
+            0
+        } else {
+            1
+        });
+    }
+    let state = state_dir(repo);
+    create_dir_all(&state).with_context(|| format!("could not create {}", state.display()))?;
+    let head = resolve_ref(repo, "HEAD")?.unwrap_or_default();
+    write_state(repo, "head", format!("{head}\n"))?;
+    write_state(repo, "opts", format_options(options))?;
+    let todo = commits.into_iter().map(|hash| (action, hash)).collect();
+    run(repo, config, out, err, Some(todo), options)
+}
+
+/// Pick or revert each commit left in `todo` (if the sequence has one) in turn,
+/// stopping at the first that can't be committed.
+fn run(
+    repo: &Repository,
+    config: &Config,
+    out: &mut impl Write,
+    err: &mut impl Write,
+    todo: Option<Todo>,
+    options: &SequencerOptions,
+) -> Result<i32> {
+    let Some(mut todo) = todo else {
+        return Ok(0);
+    };
+    while let Some((action, hash)) = todo.front().cloned() {
+        write_state(repo, "todo", format_todo(repo, &todo)?)?;
+        let head = resolve_ref(repo, "HEAD")?.unwrap_or_default();
+        write_state(repo, "abort-safety", format!("{head}\n"))?;
+        if !pick(repo, config, out, err, action, &hash, options)? {
+            return Ok(1);
+        }
+        todo.pop_front();
+    }
+    remove_dir_all(state_dir(repo))?;
+    Ok(0)
+}
+
+/// Merge the changes of the commit `hash` (or their reverse) into HEAD and commit
+/// them; false if it stopped instead, at a conflict or an empty commit.
+fn pick(
+    repo: &Repository,
+    config: &Config,
+    out: &mut impl Write,
+    err: &mut impl Write,
+    action: Action,
+    hash: &str,
+    options: &SequencerOptions,
+) -> Result<bool> {
+    let commit = Commit::read(repo, hash)?;
+    let short = &commit.hash[..7];
+    let subject = commit.subject();
+    let parent = mainline_parent(&commit, options.mainline)?;
+    let parent_tree = match parent {
+        Some(parent) => Some(parse_hash(&Commit::read(repo, parent)?.tree)?),
+        None => None,
+    };
+    let tree = parse_hash(&commit.tree)?;
+    let (base, theirs, theirs_label, message) = match action {
+        Action::Pick => (
+            parent_tree,
+            Some(tree),
+            format!("{short} ({subject})"),
+            commit.message.clone(),
+        ),
+        Action::Revert => (
+            Some(tree),
+            parent_tree,
+            format!("parent of {short} ({subject})"),
+            revert_message(&commit, parent),
+        ),
+    };
+    let head_tree = match resolve_ref(repo, "HEAD")? {
+        Some(head) => Some(parse_hash(&Commit::read(repo, &head)?.tree)?),
+        None => None,
+    };
+    let merged = merge_trees(
+        repo,
+        base.as_ref(),
+        head_tree.as_ref(),
+        theirs.as_ref(),
+        "HEAD",
+        &theirs_label,
+    )?;
+    let conflicts = checkout_merge(repo, &merged)?;
+    for message in &merged.messages {
+        writeln!(out, "{message}")?;
+    }
+    if !conflicts.is_empty() {
+        write(
+            repo.git_path("MERGE_MSG"),
+            format!("{message}\n# Conflicts:\n#\t{}\n", conflicts.join("\n#\t")),
+        )?;
+        write(
+            repo.git_path(action.head_file()),
+            format!("{}\n", commit.hash),
+        )?;
+        let command = action.command();
+        let verb = match action {
+            Action::Pick => "apply",
+            Action::Revert => "revert",
+        };
+        writeln!(
+            err,
+            "error: could not {verb} {short}... {subject}\n\
+             hint: After resolving the conflicts, mark them with\n\
+             hint: \"git add/rm <pathspec>\", then run\n\
+             hint: \"git {command} --continue\".\n\
+             hint: You can instead skip this commit with \"git {command} --skip\".\n\
+             hint: To abort and get back to the state before \"git {command}\",\n\
+             hint: run \"git {command} --abort\"."
+        )?;
+        return Ok(false);
+    }
+    let originally_empty = base == theirs;
+    commit_result(
+        repo,
+        config,
+        out,
+        err,
+        action,
+        &commit,
+        message,
+        originally_empty,
+        options,
+    )
+}
+
+/// Commit the index as the pick or revert of `commit`, unless it leaves HEAD's tree
+/// as it is and `options` don't allow that; false if it stopped instead.
+#[allow(clippy::too_many_arguments)]
+fn commit_result(
+    repo: &Repository,
+    config: &Config,
+    out: &mut impl Write,
+    err: &mut impl Write,
+    action: Action,
+    commit: &Commit,
+    message: String,
+    originally_empty: bool,
+    options: &SequencerOptions,
+) -> Result<bool> {
+    let head = resolve_ref(repo, "HEAD")?;
+    let tree = git_write_tree(repo)?.to_string();
+    let empty = match &head {
+        Some(head) => Commit::read(repo, head)?.tree == tree,
+        None => Index::load(repo)?.entries.is_empty(),
+    };
+    let allowed = options.keep_redundant_commits || options.allow_empty && originally_empty;
+    if empty && !allowed {
+        write(repo.git_path("MERGE_MSG"), &message)?;
+        if action == Action::Pick {
+            write(
+                repo.git_path(action.head_file()),
+                format!("{}\n", commit.hash),
+            )?;
+            writeln!(
+                err,
+                "The previous cherry-pick is now empty, possibly due to conflict resolution.\n\
+                 If you wish to commit it anyway, use:\n\n    \
+                 git commit --allow-empty\n\n\
+                 Otherwise, please use 'git cherry-pick --skip'"
+            )?;
+        }
+        let status = git_status(repo, config, &StatusOptions::default())?;
+        let hints = config.get_bool("advice.statusHints")?.unwrap_or(true);
+        write!(out, "{}", format_long(repo, &status, hints)?)?;
+        return Ok(false);
+    }
+
+    let mut builder = head
+        .iter()
+        .fold(CommitBuilder::new(tree), CommitBuilder::parent)
+        .message(&message);
+    if action == Action::Pick {
+        builder = builder.author(commit.author.clone());
+    }
+    let new = builder.write(repo, config)?.to_string();
+    let subject = message.lines().next().unwrap_or("");
+    update_ref(
+        repo,
+        config,
+        "HEAD",
+        &new,
+        &format!("{}: {subject}", action.command()),
+    )?;
+    clear_stopped(repo);
+    print_summary(repo, config, out, head.as_deref(), &new)
+}
+
+/// Show the new commit as git does once it has picked one: `[<branch> <commit>]
+/// <subject>`, its author if that isn't the committer, its date and the totals of a
+/// diffstat, with the files created or deleted.
+fn print_summary(
+    repo: &Repository,
+    config: &Config,
+    out: &mut impl Write,
+    parent: Option<&str>,
+    new: &str,
+) -> Result<bool> {
+    let commit = Commit::read(repo, new)?;
+    let place = current_branch(repo)?.unwrap_or_else(|| "detached HEAD".to_string());
+    let root = if parent.is_none() {
+        " (root-commit)"
+    } else {
+        ""
+    };
+    writeln!(out, "[{place}{root} {}] {}", &new[..7], commit.subject())?;
+    let (author, committer) = (&commit.author, &commit.committer);
+    if (&author.name, &author.email) != (&committer.name, &committer.email) {
+        writeln!(out, " Author: {} <{}>", author.name, author.email)?;
+    }
+    writeln!(out, " Date: {}", author.date())?;
+    let parent = match parent {
+        Some(parent) => parent.to_string(),
+        None => Object {
+            kind: ObjectKind::Tree,
+            expected_size: 0,
+            reader: Cursor::new(Vec::new()),
+        }
+        .write_as_object(repo)?
+        .to_string(),
+    };
+    let options = DiffOptions {
+        stat: true,
+        shortstat: true,
+        summary: true,
+        ..DiffOptions::default()
+    };
+    // The diffstat goes straight to standard output, after what is written so far.
+    out.flush()?;
+    print_tree_diff(repo, config, &parent, new, &options)?;
+    Ok(true)
+}
+
+/// `Revert "<subject>"`, then which commit it reverts (and, for a merge, the parent
+/// it goes back to).
+fn revert_message(commit: &Commit, parent: Option<&str>) -> String {
+    let mut message = format!(
+        "Revert \"{}\"\n\nThis reverts commit {}",
+        commit.subject(),
+        commit.hash
+    );
+    match parent {
+        Some(parent) if commit.parents.len() > 1 => {
+            message.push_str(&format!(", reversing\nchanges made to {parent}.\n"));
+        }
+        _ => message.push_str(".\n"),
+    }
+    message
+}
+
+/// The parent whose changes to `commit` are picked: none for a root commit, and for
+/// a merge the `mainline` one it must be given.
+fn mainline_parent(commit: &Commit, mainline: Option<usize>) -> Result<Option<&str>> {
+    let hash = &commit.hash;
+    match (commit.parents.as_slice(), mainline) {
+        ([], None) => Ok(None),
+        ([parent], None) => Ok(Some(parent)),
+        ([] | [_], Some(_)) => bail!("mainline was specified but commit {hash} is not a merge."),
+        (_, None) => bail!("commit {hash} is a merge but no -m option was given."),
+        (parents, Some(n)) => match n.checked_sub(1).and_then(|n| parents.get(n)) {
+            Some(parent) => Ok(Some(parent)),
+            None => bail!("commit {hash} does not have parent {n}"),
+        },
+    }
+}
+
+/// Whether `commit` has the tree of the parent it is picked against.
+fn is_originally_empty(
+    repo: &Repository,
+    commit: &Commit,
+    mainline: Option<usize>,
+) -> Result<bool> {
+    Ok(match mainline_parent(commit, mainline)? {
+        Some(parent) => Commit::read(repo, parent)?.tree == commit.tree,
+        None => false,
+    })
+}
+
+/// The commits `revision` names: the one commit, or for `A..B` those of `B` that
+/// `A` lacks, oldest first.
+fn resolve_commits(repo: &Repository, revision: &str) -> Result<Vec<String>> {
+    let resolve = |name: &str| match resolve_revision(repo, name)? {
+        Some(hash) => Ok(Commit::read(repo, &hash)?.hash),
+        None => bail!("bad revision '{revision}'"),
+    };
+    match revision.split_once("..") {
+        Some((from, to)) => {
+            let from = resolve(if from.is_empty() { "HEAD" } else { from })?;
+            let to = resolve(if to.is_empty() { "HEAD" } else { to })?;
+            let mut commits: Vec<String> = walk_commits(repo, &[to], &[from])?
+                .into_iter()
+                .map(|commit| commit.hash)
+                .collect();
+            commits.reverse();
+            Ok(commits)
+        }
+        None => Ok(vec![resolve(revision)?]),
+    }
+}
+
+/// Each path the index holds unmerged, once.
+fn unmerged_paths(repo: &Repository) -> Result<Vec<String>> {
+    let mut paths: Vec<String> = Index::load(repo)?
+        .entries
+        .into_iter()
+        .filter(|entry| entry.stage != 0)
+        .map(|entry| entry.path)
+        .collect();
+    paths.dedup();
+    Ok(paths)
+}
+
+/// Why `doing` (`Cherry-picking`, `Committing`...) can't go ahead.
+fn unmerged_error(doing: &str) -> String {
+    format!(
+        "{doing} is not possible because you have unmerged files.\n\
+         hint: Fix them up in the work tree, and then use 'git add/rm <file>'\n\
+         hint: as appropriate to mark resolution and make a commit."
+    )
+}
+
+/// The commit a pick or revert stopped at, if one did.
+fn stopped_at(repo: &Repository) -> Option<(Action, String)> {
+    [Action::Pick, Action::Revert]
+        .into_iter()
+        .find_map(|action| {
+            let hash = read_to_string(repo.git_path(action.head_file())).ok()?;
+            Some((action, hash.trim_end().to_string()))
+        })
+}
+
+fn clear_stopped(repo: &Repository) {
+    for name in ["CHERRY_PICK_HEAD", "REVERT_HEAD", "MERGE_MSG"] {
+        let _ = remove_file(repo.git_path(name));
+    }
+}
+
+/// The todo list and options of the sequence in progress; no todo list for a single
+/// commit.
+fn read_state(repo: &Repository) -> Result<(Option<Todo>, SequencerOptions)> {
+    let state = state_dir(repo);
+    if !state.exists() {
+        return Ok((None, SequencerOptions::default()));
+    }
+    let mut todo = VecDeque::new();
+    for line in read_state_file(repo, "todo")?.lines() {
+        let mut words = line.split(' ');
+        let action = match words.next() {
+            Some("pick") => Action::Pick,
+            Some("revert") => Action::Revert,
+            _ => bail!("invalid line in the sequencer's todo list: {line}"),
+        };
+        let hash = match words.next().map(|short| resolve_revision(repo, short)) {
+            Some(Ok(Some(hash))) => hash,
+            _ => bail!("invalid line in the sequencer's todo list: {line}"),
+        };
+        todo.push_back((action, hash));
+    }
+    let opts = Config::load_file(&state.join("opts"))?;
+    let mainline = match opts.get("options.mainline") {
+        Some(n) => Some(
+            n.parse()
+                .context("invalid mainline in the sequencer's options")?,
+        ),
+        None => None,
+    };
+    let options = SequencerOptions {
+        allow_empty: opts.get_bool("options.allow-empty")?.unwrap_or(false),
+        keep_redundant_commits: opts
+            .get_bool("options.keep-redundant-commits")?
+            .unwrap_or(false),
+        mainline,
+    };
+    Ok((Some(todo), options))
+}
+
+/// A `<word> <short id> <subject>` line for each commit in `todo`, as git's list reads.
+fn format_todo(repo: &Repository, todo: &Todo) -> Result<String> {
+    let mut lines = String::new();
+    for (action, hash) in todo {
+        let commit = Commit::read(repo, hash)?;
+        let word = action.todo_word();
+        lines.push_str(&format!("{word} {} {}\n", &hash[..7], commit.subject()));
+    }
+    Ok(lines)
+}
+
+/// `options` as the config file git keeps them in.
+fn format_options(options: &SequencerOptions) -> String {
+    let mut opts = String::from("[options]\n");
+    if options.allow_empty {
+        opts.push_str("\tallow-empty = true\n");
+    }
+    if options.keep_redundant_commits {
+        opts.push_str("\tkeep-redundant-commits = true\n");
+    }
+    if let Some(n) = options.mainline {
+        opts.push_str(&format!("\tmainline = {n}\n"));
+    }
+    opts
+}
+
+fn read_state_file(repo: &Repository, name: &str) -> Result<String> {
+    let path = state_dir(repo).join(name);
+    read_to_string(&path).with_context(|| format!("could not read {}", path.display()))
+}
+
+fn write_state(repo: &Repository, name: &str, content: impl AsRef<[u8]>) -> Result<()> {
+    let path = state_dir(repo).join(name);
+    write(&path, content).with_context(|| format!("could not write {}", path.display()))
+}
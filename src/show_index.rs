@@ -0,0 +1,31 @@
+use crate::object_id::HashAlgorithm;
+use crate::pack::Pack;
+use crate::repository::Repository;
+use anyhow::{Context, Result};
+use std::io::{Read, stdin};
+use std::path::Path;
+
+/// `git show-index`: read a pack index (`.idx`) from stdin and print each object's
+/// offset in the pack and id, then for a version 2 index the CRC32 of its entry, in
+/// the index's order of ascending ids. The pack itself isn't needed.
+///
+/// The ids are of `object_format`, by default that of the repository in the current
+/// directory (SHA-1 outside one), since the index doesn't say.
+pub fn git_show_index(repo: &Repository, object_format: Option<HashAlgorithm>) -> Result<()> {
+    let algorithm = match object_format {
+        Some(algorithm) => algorithm,
+        None => repo.hash_algorithm()?,
+    };
+    let mut index = Vec::new();
+    stdin()
+        .read_to_end(&mut index)
+        .context("failed to read the index from stdin")?;
+    let pack = Pack::from_index(Path::new("standard input"), index, algorithm)?;
+    for (offset, id, crc) in pack.index_entries()? {
+        match crc {
+            Some(crc) => println!("{offset} {id} ({crc:08x})"),
+            None => println!("{offset} {id}"),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,47 @@
+use crate::commit_read::Commit;
+use crate::config::Config;
+use crate::diff::{DiffOptions, print_tree_diff};
+use crate::refs::resolve_ref;
+use crate::repository::Repository;
+use crate::rev_parse::resolve_revision;
+use anyhow::{Result, bail};
+
+/// A stash entry is a commit whose tree is the working tree as it was stashed; its
+/// first parent is the commit HEAD was at (the *base*), its second a commit of what
+/// the index held, and a third, if there is one, holds the untracked files stashed
+/// with them. `refs/stash` points at the latest entry, and its reflog lists the
+/// others, `stash@{0}` being the latest.
+///
+/// `git stash show [-p] [--stat] [<stash>]`: show the changes the entry (`stash@{0}`
+/// by default, or `stash@{<n>}` given just `n`) recorded to the working tree, the diff
+/// from its base. Without `-p` or `--stat` (in `options`, as `patch` and `stat`),
+/// `stash.showStat` (true by default) and `stash.showPatch` say what to show.
+pub fn git_stash_show(
+    repo: &Repository,
+    config: &Config,
+    stash: Option<&str>,
+    options: &DiffOptions,
+) -> Result<i32> {
+    let name = match stash {
+        None if resolve_ref(repo, "refs/stash")?.is_none() => bail!("No stash entries found."),
+        None => "refs/stash@{0}".to_string(),
+        Some(n) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => {
+            format!("refs/stash@{{{n}}}")
+        }
+        Some(name) => name.to_string(),
+    };
+    let Some(hash) = resolve_revision(repo, &name)? else {
+        bail!("{name} is not a valid reference");
+    };
+    let commit = Commit::read(repo, &hash)?;
+    let [base, _index, ..] = commit.parents.as_slice() else {
+        bail!("'{name}' is not a stash-like commit");
+    };
+
+    let mut options = options.clone();
+    if !options.stat && !options.patch {
+        options.stat = config.get_bool("stash.showStat")?.unwrap_or(true);
+        options.patch = config.get_bool("stash.showPatch")?.unwrap_or(false);
+    }
+    print_tree_diff(repo, config, base, &commit.hash, &options)
+}
@@ -0,0 +1,642 @@
+use crate::branch::{UpstreamStatus, tracking_info, upstream_status};
+use crate::checkout::parse_hash;
+use crate::config::Config;
+use crate::diff::{DiffSide, diff_trees};
+use crate::index::{Index, IndexEntry, StatData, worktree_id, worktree_mode};
+use crate::object_read::{Object, ObjectKind};
+use crate::refs::{current_branch, resolve_ref};
+use crate::repository::Repository;
+use crate::submodule::{submodule_git_dir, submodule_head};
+use anyhow::{Result, bail};
+use ignore::{WalkBuilder, WalkState};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+use std::fs::{read_to_string, symlink_metadata};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Which untracked files `status` lists, as `--untracked-files` chooses.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UntrackedFiles {
+    No,
+    /// Untracked files, and directories without any tracked file as a whole.
+    #[default]
+    Normal,
+    /// Every untracked file, even in untracked directories.
+    All,
+}
+
+impl UntrackedFiles {
+    pub fn parse(mode: &str) -> Result<Self> {
+        Ok(match mode {
+            "no" => UntrackedFiles::No,
+            "normal" => UntrackedFiles::Normal,
+            "all" => UntrackedFiles::All,
+            _ => bail!("Invalid untracked files mode '{mode}'"),
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct StatusOptions {
+    /// Only find what the short format shows: the branch's ahead/behind counts are
+    /// left out.
+    pub short: bool,
+    /// With `short`, find the branch's upstream and ahead/behind counts anyway, for
+    /// [`format_branch_header`].
+    pub branch: bool,
+    pub untracked_files: UntrackedFiles,
+}
+
+/// How a path differs between two of HEAD, the index and the working directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Deleted,
+    Modified,
+    /// A file became a symlink or submodule, or the reverse.
+    TypeChanged,
+    /// A submodule has another commit checked out than the index records.
+    NewCommits,
+}
+
+impl Change {
+    /// The letter the short format shows for this change.
+    pub fn letter(self) -> char {
+        match self {
+            Change::Added => 'A',
+            Change::Deleted => 'D',
+            Change::Modified | Change::NewCommits => 'M',
+            Change::TypeChanged => 'T',
+        }
+    }
+
+    /// The label the long format shows before the path.
+    pub fn label(self) -> &'static str {
+        match self {
+            Change::Added => "new file:",
+            Change::Deleted => "deleted:",
+            Change::Modified | Change::NewCommits => "modified:",
+            Change::TypeChanged => "typechange:",
+        }
+    }
+}
+
+/// The stages a conflicted path has in the index, as the kind of conflict git names
+/// it by: `(short code, long label)`.
+fn conflict_kind(stages: &[u8]) -> (&'static str, &'static str) {
+    let has = |stage: u8| stages.contains(&stage);
+    match (has(1), has(2), has(3)) {
+        (true, false, false) => ("DD", "both deleted:"),
+        (false, true, false) => ("AU", "added by us:"),
+        (true, false, true) => ("DU", "deleted by us:"),
+        (false, false, true) => ("UA", "added by them:"),
+        (true, true, false) => ("UD", "deleted by them:"),
+        (false, true, true) => ("AA", "both added:"),
+        _ => ("UU", "both modified:"),
+    }
+}
+
+/// Everything `status` reports, each list sorted by path.
+#[derive(Debug, Default)]
+pub struct Status {
+    /// The branch HEAD is on, if it isn't detached.
+    pub branch: Option<String>,
+    /// How the branch compares to its upstream, as `git status` words it (see
+    /// [`tracking_info`]); never found for the short format.
+    pub tracking: Option<String>,
+    /// The branch's upstream and how it compares to it, found for the short format
+    /// with [`StatusOptions::branch`].
+    pub upstream: Option<UpstreamStatus>,
+    /// The commit HEAD points at; `None` before the first commit.
+    pub head: Option<String>,
+    /// Whether a merge is in progress.
+    pub merging: bool,
+    /// The commit a cherry-pick stopped at, from `CHERRY_PICK_HEAD`; empty while a
+    /// sequence of picks is in progress, which git doesn't name a commit for.
+    pub cherry_picking: Option<String>,
+    /// The commit a revert stopped at, from `REVERT_HEAD`; empty as above.
+    pub reverting: Option<String>,
+    pub staged: Vec<(String, Change)>,
+    pub unstaged: Vec<(String, Change)>,
+    /// Each conflicted path with the stages the index has of it.
+    pub unmerged: Vec<(String, Vec<u8>)>,
+    pub untracked: Vec<String>,
+}
+
+/// `git status`: find what is staged (the index compared to HEAD), what is changed
+/// but not staged (the working directory compared to the index), unresolved
+/// conflicts, and the files that aren't tracked or ignored, for [`format_short`] or
+/// [`format_long`] to show.
+///
+/// Files whose stat data matches their index entry are taken to be unchanged without
+/// being read; those that had to be hashed and turned out unchanged get their stat
+/// data refreshed in the index, as git does, if it can be written.
+pub fn git_status(repo: &Repository, config: &Config, options: &StatusOptions) -> Result<Status> {
+    let mut index = Index::load(repo)?;
+    let head = resolve_ref(repo, "HEAD")?;
+    let head_files = match &head {
+        Some(_) => {
+            let (tree, _) = Object::peel_to(repo, "HEAD", ObjectKind::Tree)?;
+            diff_trees(repo, None, Some(&parse_hash(&tree)?))?
+        }
+        None => Vec::new(),
+    };
+    let head_files: HashMap<&str, &DiffSide> = head_files
+        .iter()
+        .filter_map(|file| Some((file.path.as_str(), file.new.as_ref()?)))
+        .collect();
+
+    let branch = current_branch(repo)?;
+    let tracking = match (&branch, options.short) {
+        (Some(branch), false) => tracking_info(repo, config, branch)?,
+        _ => None,
+    };
+    let upstream = match (&branch, options.short && options.branch) {
+        (Some(branch), true) => upstream_status(repo, config, branch)?,
+        _ => None,
+    };
+    let mut status = Status {
+        branch,
+        tracking,
+        upstream,
+        merging: repo.git_path("MERGE_HEAD").exists(),
+        cherry_picking: read_pseudo_ref(repo, "CHERRY_PICK_HEAD"),
+        reverting: read_pseudo_ref(repo, "REVERT_HEAD"),
+        ..Status::default()
+    };
+    let todo = read_to_string(repo.git_path("sequencer/todo")).unwrap_or_default();
+    match todo.split(' ').next() {
+        Some("pick") => status.cherry_picking = Some(String::new()),
+        Some("revert") => status.reverting = Some(String::new()),
+        _ => {}
+    }
+    let mut conflicts: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+    for entry in index.entries.iter().filter(|entry| entry.stage != 0) {
+        conflicts
+            .entry(entry.path.clone())
+            .or_default()
+            .push(entry.stage);
+    }
+    status.unmerged = conflicts.into_iter().collect();
+
+    let tracked: Vec<&IndexEntry> = index
+        .entries
+        .iter()
+        .filter(|entry| entry.stage == 0)
+        .collect();
+    let mut staged: BTreeMap<&str, Change> = BTreeMap::new();
+    for entry in &tracked {
+        let change = match head_files.get(entry.path.as_str()) {
+            None => Some(Change::Added),
+            Some(side) => compare_modes(&side.mode, entry.mode)
+                .or((side.hash != entry.id).then_some(Change::Modified)),
+        };
+        if let Some(change) = change {
+            staged.insert(&entry.path, change);
+        }
+    }
+    let in_index: HashSet<&str> = index
+        .entries
+        .iter()
+        .map(|entry| entry.path.as_str())
+        .collect();
+    for path in head_files.keys() {
+        if !in_index.contains(path) {
+            staged.insert(path, Change::Deleted);
+        }
+    }
+    status.staged = staged
+        .into_iter()
+        .map(|(path, change)| (path.to_string(), change))
+        .collect();
+
+    let mut refreshed = Vec::new();
+    for (position, entry) in index.entries.iter().enumerate() {
+        if entry.stage != 0 {
+            continue;
+        }
+        match worktree_change(repo, &index, entry)? {
+            WorktreeState::Unchanged(Some(stat)) => refreshed.push((position, stat)),
+            WorktreeState::Unchanged(None) => {}
+            WorktreeState::Changed(change) => status.unstaged.push((entry.path.clone(), change)),
+        }
+    }
+
+    if options.untracked_files != UntrackedFiles::No {
+        let ignore_case = config.get_bool("core.ignoreCase")?.unwrap_or(false);
+        status.untracked =
+            untracked_files(repo, &index, options.untracked_files, ignore_case, true);
+    }
+
+    if !refreshed.is_empty() {
+        for (position, stat) in refreshed {
+            index.entries[position].stat = stat;
+        }
+        // Someone else holding the index lock only means the stat data stays stale.
+        let _ = index.save(repo);
+    }
+
+    status.head = head;
+    Ok(status)
+}
+
+/// How the modes of the same path on two sides differ, if they do: a file turning
+/// into a symlink or submodule (or back) is a typechange, a change of the executable
+/// bit a modification.
+fn compare_modes(old: &str, new: u32) -> Option<Change> {
+    let new = format!("{new:o}");
+    let kind = |mode: &str| mode.get(..mode.len().saturating_sub(4)).map(str::to_string);
+    if old == new {
+        None
+    } else if kind(old) != kind(&new) {
+        Some(Change::TypeChanged)
+    } else {
+        Some(Change::Modified)
+    }
+}
+
+/// How a tracked file in the working directory compares to its index entry.
+pub(crate) enum WorktreeState {
+    /// The file matches its entry; if it had to be hashed to tell, this is
+    /// the stat data to refresh the entry with.
+    Unchanged(Option<StatData>),
+    Changed(Change),
+}
+
+/// Compare the file of `entry` to it, by its stat data if that matches and otherwise
+/// by its mode and content.
+pub(crate) fn worktree_change(
+    repo: &Repository,
+    index: &Index,
+    entry: &IndexEntry,
+) -> Result<WorktreeState> {
+    let file = repo.path(&entry.path);
+    let Ok(metadata) = symlink_metadata(&file) else {
+        return Ok(WorktreeState::Changed(Change::Deleted));
+    };
+    if metadata.is_dir() && entry.mode != 0o160000 && submodule_git_dir(&file).is_none() {
+        // A directory where a file was: the file is gone; what is in it is untracked.
+        return Ok(WorktreeState::Changed(Change::Deleted));
+    }
+    if index.is_stat_clean(repo, entry, &metadata) {
+        return Ok(WorktreeState::Unchanged(None));
+    }
+    let mode = worktree_mode(repo, &metadata, Some(entry.mode));
+    if let Some(change) = compare_modes(&format!("{:o}", entry.mode), mode) {
+        return Ok(WorktreeState::Changed(change));
+    }
+    if mode == 0o160000 {
+        // A submodule that isn't checked out counts as unchanged.
+        return Ok(match submodule_head(&file)? {
+            Some(head) if head != entry.id => WorktreeState::Changed(Change::NewCommits),
+            _ => WorktreeState::Unchanged(None),
+        });
+    }
+    if mode != 0o120000 && metadata.len() != u64::from(entry.stat.size) && entry.stat.size != 0 {
+        return Ok(WorktreeState::Changed(Change::Modified));
+    }
+    Ok(
+        match worktree_id(repo, &entry.path, mode, false)? == entry.id {
+            true => WorktreeState::Unchanged(Some(StatData::from_metadata(&metadata))),
+            false => WorktreeState::Changed(Change::Modified),
+        },
+    )
+}
+
+/// The files in the working directory that are neither in the index nor (unless not
+/// `exclude_standard`) ignored, in path order. With [`UntrackedFiles::Normal`], a
+/// directory that has no tracked files is listed as `<dir>/` instead of its files,
+/// once something in it that isn't ignored is found, and isn't walked any further.
+/// With `ignore_case`, a file is tracked if the index has it in any case.
+///
+/// The directories are read in parallel, since a large tree takes most of the time
+/// `status` needs; the results are sorted afterwards, so the order is always the
+/// same.
+pub fn untracked_files(
+    repo: &Repository,
+    index: &Index,
+    mode: UntrackedFiles,
+    ignore_case: bool,
+    exclude_standard: bool,
+) -> Vec<String> {
+    let fold = |path: &str| match ignore_case {
+        true => path.to_ascii_lowercase(),
+        false => path.to_string(),
+    };
+    let tracked: HashSet<String> = index
+        .entries
+        .iter()
+        .map(|entry| fold(&entry.path))
+        .collect();
+    let tracked_dirs: HashSet<&str> = tracked
+        .iter()
+        .flat_map(|path| path.match_indices('/').map(|(i, _)| &path[..i]))
+        .collect();
+    // Files and submodules, which count as untracked unless the index has them, and
+    // the untracked directories listed instead of what is in them.
+    let found = Mutex::new((Vec::new(), Vec::new()));
+    let (found_ref, tracked_dirs_ref) = (&found, &tracked_dirs);
+    let walker = |root: &Path| {
+        let mut builder = WalkBuilder::new(root);
+        builder.standard_filters(exclude_standard).hidden(false);
+        builder
+    };
+    walker(&repo.work_tree).build_parallel().run(|| {
+        Box::new(move |entry| {
+            let Ok(entry) = entry else {
+                return WalkState::Continue;
+            };
+            if entry.depth() == 0 {
+                return WalkState::Continue;
+            }
+            if entry.file_name() == ".git" {
+                return WalkState::Skip;
+            }
+            let path = entry.path();
+            let Some(relative) = path.strip_prefix(&repo.work_tree).unwrap_or(path).to_str() else {
+                return WalkState::Continue;
+            };
+            let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+            if !is_dir {
+                found_ref.lock().unwrap().0.push(relative.to_string());
+                return WalkState::Continue;
+            }
+            if submodule_git_dir(path).is_some() {
+                // Another repository, tracked as a whole if at all.
+                found_ref.lock().unwrap().0.push(format!("{relative}/"));
+                return WalkState::Skip;
+            }
+            if mode == UntrackedFiles::Normal && !tracked_dirs_ref.contains(fold(relative).as_str())
+            {
+                // Empty directories, and those with only ignored files, aren't listed.
+                let has_content = walker(path).build().flatten().any(|entry| {
+                    entry.depth() > 0
+                        && entry.file_name() != ".git"
+                        && (!entry.file_type().is_some_and(|t| t.is_dir())
+                            || submodule_git_dir(entry.path()).is_some())
+                });
+                if has_content {
+                    found_ref.lock().unwrap().1.push(format!("{relative}/"));
+                }
+                return WalkState::Skip;
+            }
+            WalkState::Continue
+        })
+    });
+    let (files, dirs) = found.into_inner().unwrap();
+
+    let mut untracked: Vec<String> = files
+        .into_iter()
+        .filter(|path| !tracked.contains(&fold(path.trim_end_matches('/'))))
+        .chain(dirs)
+        .collect();
+    untracked.sort();
+    untracked
+}
+
+/// The `## main...origin/main [ahead 2, behind 1]` line that `git status --short
+/// --branch` starts with: the branch, its upstream if it has one and how far apart
+/// they are.
+pub fn format_branch_header(status: &Status) -> String {
+    let Some(branch) = &status.branch else {
+        return "## HEAD (no branch)\n".to_string();
+    };
+    let mut out = match status.head {
+        Some(_) => format!("## {branch}"),
+        None => format!("## No commits yet on {branch}"),
+    };
+    if let Some(upstream) = &status.upstream {
+        write!(out, "...{}", upstream.name).unwrap();
+        match upstream.ahead_behind {
+            None => out.push_str(" [gone]"),
+            Some((0, 0)) => {}
+            Some((ahead, 0)) => write!(out, " [ahead {ahead}]").unwrap(),
+            Some((0, behind)) => write!(out, " [behind {behind}]").unwrap(),
+            Some((ahead, behind)) => write!(out, " [ahead {ahead}, behind {behind}]").unwrap(),
+        }
+    }
+    out.push('\n');
+    out
+}
+
+/// `status` as `git status --short` shows it: an `XY <path>` line for each changed
+/// path, `X` for the index and `Y` for the working directory, then `?? <path>` for
+/// each untracked one. Paths are shown relative to where the command was run.
+pub fn format_short(repo: &Repository, status: &Status) -> String {
+    let mut out = String::new();
+    let mut lines: BTreeMap<&str, (char, char)> = BTreeMap::new();
+    for (path, change) in &status.staged {
+        lines.entry(path).or_insert((' ', ' ')).0 = change.letter();
+    }
+    for (path, change) in &status.unstaged {
+        lines.entry(path).or_insert((' ', ' ')).1 = change.letter();
+    }
+    let mut conflicts: BTreeMap<&str, &str> = BTreeMap::new();
+    for (path, stages) in &status.unmerged {
+        lines.remove(path.as_str());
+        conflicts.insert(path, conflict_kind(stages).0);
+    }
+    let mut all: BTreeMap<&str, String> = lines
+        .into_iter()
+        .map(|(path, (x, y))| (path, format!("{x}{y}")))
+        .collect();
+    all.extend(
+        conflicts
+            .into_iter()
+            .map(|(path, code)| (path, code.to_string())),
+    );
+    for (path, code) in all {
+        writeln!(out, "{code} {}", repo.relative(path)).unwrap();
+    }
+    for path in &status.untracked {
+        writeln!(out, "?? {}", repo.relative(path)).unwrap();
+    }
+    out
+}
+
+/// The commit id in the file `name` of the git directory, if there is one.
+fn read_pseudo_ref(repo: &Repository, name: &str) -> Option<String> {
+    let content = read_to_string(repo.git_path(name)).ok()?;
+    Some(content.trim_end().to_string()).filter(|hash| hash.len() >= 7)
+}
+
+/// `status` as `git status` shows it by default, with a line of advice here and
+/// there unless not `hints` (`advice.statusHints`). Paths are shown relative to where
+/// the command was run.
+pub fn format_long(repo: &Repository, status: &Status, hints: bool) -> Result<String> {
+    let mut out = String::new();
+    let hint = |out: &mut String, text: &str| -> Result<()> {
+        if hints {
+            writeln!(out, "  ({text})")?;
+        }
+        Ok(())
+    };
+    let head = status.head.as_deref();
+    match (&status.branch, head) {
+        (Some(branch), _) => {
+            writeln!(out, "On branch {branch}")?;
+            if let Some(info) = &status.tracking {
+                writeln!(out, "{info}\n")?;
+            }
+        }
+        (None, Some(head)) => writeln!(out, "HEAD detached at {}", &head[..7])?,
+        (None, None) => writeln!(out, "Not currently on any branch.")?,
+    }
+    let picking = match (&status.cherry_picking, &status.reverting) {
+        (Some(commit), _) => Some(("cherry-picking", "cherry-pick", "Cherry-pick", commit)),
+        (None, Some(commit)) => Some(("reverting", "revert", "Revert", commit)),
+        (None, None) => None,
+    };
+    if let Some((doing, command, name, commit)) = picking {
+        let in_sequence = commit.is_empty();
+        match in_sequence {
+            true => writeln!(out, "{name} currently in progress.")?,
+            false => writeln!(out, "You are currently {doing} commit {}.", &commit[..7])?,
+        }
+        match status.unmerged.is_empty() {
+            true if in_sequence => hint(
+                &mut out,
+                &format!("run \"git {command} --continue\" to continue"),
+            )?,
+            true => hint(
+                &mut out,
+                &format!("all conflicts fixed: run \"git {command} --continue\""),
+            )?,
+            false => hint(
+                &mut out,
+                &format!("fix conflicts and run \"git {command} --continue\""),
+            )?,
+        }
+        hint(
+            &mut out,
+            &format!("use \"git {command} --skip\" to skip this patch"),
+        )?;
+        hint(
+            &mut out,
+            &format!("use \"git {command} --abort\" to cancel the {command} operation"),
+        )?;
+        writeln!(out)?;
+    }
+    if status.merging {
+        if status.unmerged.is_empty() {
+            writeln!(out, "All conflicts fixed but you are still merging.")?;
+            hint(&mut out, "use \"git commit\" to conclude merge")?;
+        } else {
+            writeln!(out, "You have unmerged paths.")?;
+            hint(&mut out, "fix conflicts and run \"git commit\"")?;
+            hint(&mut out, "use \"git merge --abort\" to abort the merge")?;
+        }
+        writeln!(out)?;
+    }
+    if head.is_none() {
+        writeln!(out, "\nNo commits yet\n")?;
+    }
+
+    // Labels are padded to the longest one git can print in each section.
+    if !status.unmerged.is_empty() {
+        writeln!(out, "Unmerged paths:")?;
+        let deletion = status
+            .unmerged
+            .iter()
+            .any(|(_, stages)| !(stages.contains(&2) && stages.contains(&3)));
+        match deletion {
+            true => hint(
+                &mut out,
+                "use \"git add/rm <file>...\" as appropriate to mark resolution",
+            )?,
+            false => hint(&mut out, "use \"git add <file>...\" to mark resolution")?,
+        }
+        for (path, stages) in &status.unmerged {
+            let kind = conflict_kind(stages).1;
+            writeln!(out, "\t{kind:<17}{}", repo.relative(path))?;
+        }
+        writeln!(out)?;
+    }
+    if !status.staged.is_empty() {
+        writeln!(out, "Changes to be committed:")?;
+        match head {
+            Some(_) => hint(
+                &mut out,
+                "use \"git restore --staged <file>...\" to unstage",
+            )?,
+            None => hint(&mut out, "use \"git rm --cached <file>...\" to unstage")?,
+        }
+        for (path, change) in &status.staged {
+            writeln!(out, "\t{:<12}{}", change.label(), repo.relative(path))?;
+        }
+        writeln!(out)?;
+    }
+    if !status.unstaged.is_empty() {
+        writeln!(out, "Changes not staged for commit:")?;
+        match status.unstaged.iter().any(|(_, c)| *c == Change::Deleted) {
+            true => hint(
+                &mut out,
+                "use \"git add/rm <file>...\" to update what will be committed",
+            )?,
+            false => hint(
+                &mut out,
+                "use \"git add <file>...\" to update what will be committed",
+            )?,
+        }
+        hint(
+            &mut out,
+            "use \"git restore <file>...\" to discard changes in working directory",
+        )?;
+        for (path, change) in &status.unstaged {
+            let suffix = match change {
+                Change::NewCommits => " (new commits)",
+                _ => "",
+            };
+            let path = repo.relative(path);
+            writeln!(out, "\t{:<12}{path}{suffix}", change.label())?;
+        }
+        writeln!(out)?;
+    }
+    if !status.untracked.is_empty() {
+        writeln!(out, "Untracked files:")?;
+        hint(
+            &mut out,
+            "use \"git add <file>...\" to include in what will be committed",
+        )?;
+        for path in &status.untracked {
+            writeln!(out, "\t{}", repo.relative(path))?;
+        }
+        writeln!(out)?;
+    }
+
+    let staged = !status.staged.is_empty();
+    let unstaged = !status.unstaged.is_empty() || !status.unmerged.is_empty();
+    if staged {
+        return Ok(out);
+    }
+    if unstaged {
+        let advice = "use \"git add\" and/or \"git commit -a\"";
+        match hints {
+            true => writeln!(out, "no changes added to commit ({advice})")?,
+            false => writeln!(out, "no changes added to commit")?,
+        }
+    } else if !status.untracked.is_empty() {
+        match hints {
+            true => writeln!(
+                out,
+                "nothing added to commit but untracked files present (use \"git add\" to track)"
+            )?,
+            false => writeln!(out, "nothing added to commit but untracked files present")?,
+        }
+    } else if head.is_none() {
+        match hints {
+            true => writeln!(
+                out,
+                "nothing to commit (create/copy files and use \"git add\" to track)"
+            )?,
+            false => writeln!(out, "nothing to commit")?,
+        }
+    } else {
+        match hints {
+            true => writeln!(out, "nothing to commit, working tree clean")?,
+            false => writeln!(out, "nothing to commit")?,
+        }
+    }
+    Ok(out)
+}
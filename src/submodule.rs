@@ -0,0 +1,32 @@
+use crate::object_id::ObjectId;
+use crate::refs::resolve_ref_in;
+use crate::repository::work_tree_git_dir;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// The repository of the submodule checked out at `path`: its `.git` directory, or
+/// the one a `.git` file (`gitdir: <dir>`) points to. `None` if `path` isn't one.
+pub fn submodule_git_dir(path: &Path) -> Option<PathBuf> {
+    work_tree_git_dir(path)
+}
+
+/// The commit checked out in the submodule at `path`, which a tree records as a
+/// gitlink (mode 160000). `None` if `path` isn't a submodule or its HEAD is unborn.
+pub fn submodule_head(path: &Path) -> Result<Option<ObjectId>> {
+    let Some(git_dir) = submodule_git_dir(path) else {
+        return Ok(None);
+    };
+    let Some(head) = resolve_ref_in(&git_dir, "HEAD")? else {
+        return Ok(None);
+    };
+    let hash = head
+        .parse()
+        .with_context(|| format!("bad HEAD in submodule {}", path.display()))?;
+    Ok(Some(hash))
+}
+
+/// What a gitlink is shown as in a diff: the commit lives in another repository, so
+/// there is no blob to compare, only this line.
+pub fn subproject_content(hash: &ObjectId) -> Vec<u8> {
+    format!("Subproject commit {hash}\n").into_bytes()
+}
@@ -0,0 +1,435 @@
+use crate::add::git_add;
+use crate::checkout::{parse_hash, write_worktree_file};
+use crate::commit::CommitBuilder;
+use crate::commit_read::{Commit, ahead_behind, merge_base, walk_commits};
+use crate::config::Config;
+use crate::diff::{DiffOptions, diff_trees_to_check_out, print_tree_diff};
+use crate::ls_tree::read_tree_entries;
+use crate::merge::{checkout_merge, merge_trees};
+use crate::object_id::ObjectId;
+use crate::object_read::{Object, ObjectKind};
+use crate::refs::{is_valid_ref_name, resolve_ref, update_ref};
+use crate::repository::Repository;
+use crate::worktree::is_dirty;
+use crate::write_tree::git_write_tree;
+use anyhow::{Context, Result, bail};
+use std::collections::{HashMap, HashSet};
+use std::fs::write;
+use std::io::{Cursor, Write};
+use std::path::Path;
+
+/// `git subtree add --prefix=<prefix> <commit>`: bring the tree of `commit` (usually
+/// fetched from another repository) into the directory `prefix`, with a merge commit
+/// whose second parent is `commit`, so its history becomes part of HEAD's.
+///
+/// The message (`message`, or `Add '<prefix>/' from commit '<commit>'`) is followed
+/// by `git-subtree-dir`, `git-subtree-mainline` and `git-subtree-split` lines, which
+/// [`git_subtree_split`] later uses to join up with the added history.
+///
+/// The working tree has to be clean to start with, as the added files are staged
+/// and committed on top of the index.
+pub fn git_subtree_add(
+    repo: &Repository,
+    config: &Config,
+    err: &mut impl Write,
+    prefix: &str,
+    commit: &str,
+    message: Option<&str>,
+) -> Result<()> {
+    let prefix = prefix.strip_suffix('/').unwrap_or(prefix);
+    if repo.path(prefix).exists() {
+        bail!("prefix '{prefix}' already exists.");
+    }
+    if is_dirty(repo)? {
+        bail!("working tree has modifications.  Cannot add.");
+    }
+    let Ok(commit) = Commit::read(repo, commit) else {
+        bail!("'{commit}' does not refer to a commit");
+    };
+    let Some(head) = resolve_ref(repo, "HEAD")? else {
+        bail!("cannot add a subtree to an unborn branch");
+    };
+    for change in diff_trees_to_check_out(repo, None, Some(&parse_hash(&commit.tree)?))? {
+        if let Some(new) = &change.new {
+            write_worktree_file(repo, &Path::new(prefix).join(&change.path), new)?;
+        }
+    }
+    git_add(repo, &[prefix.to_string()])?;
+    let tree = git_write_tree(repo)?.to_string();
+    let subject = match message {
+        Some(message) => message.to_string(),
+        None => format!("Add '{prefix}/' from commit '{}'", commit.hash),
+    };
+    let message = format!(
+        "{subject}\n\ngit-subtree-dir: {prefix}\ngit-subtree-mainline: {head}\n\
+         git-subtree-split: {}\n",
+        commit.hash
+    );
+    let mut added = CommitBuilder::new(tree).parent(&head).message(message);
+    if head != commit.hash {
+        added = added.parent(&commit.hash);
+    }
+    let added = added.write(repo, config)?.to_string();
+    update_ref(
+        repo,
+        config,
+        "HEAD",
+        &added,
+        &format!("reset: moving to {added}"),
+    )?;
+    writeln!(err, "Added dir '{prefix}'")?;
+    Ok(())
+}
+
+/// `git subtree split --prefix=<prefix> [<commit>]`: extract the history of the
+/// directory `prefix` as of `commit` into synthetic commits that have that
+/// directory's tree at their root, and write the newest one to `out`. With `branch`,
+/// that branch is created (or fast-forwarded) to it, which is reported on `err`.
+///
+/// Each commit that has the directory is copied with the directory's tree, keeping
+/// its author, committer and message, and its parents replaced by their copies. A
+/// commit that leaves the directory as one of those copies has it is not copied but
+/// stands for that copy. History brought in by [`git_subtree_add`] is recognised from
+/// the `git-subtree-*` lines of its message, and used unchanged, so splitting again
+/// after more changes extends the earlier split history rather than forking it; this
+/// is what keeps the split identical to what `git subtree split` makes.
+pub fn git_subtree_split(
+    repo: &Repository,
+    config: &Config,
+    out: &mut impl Write,
+    err: &mut impl Write,
+    prefix: &str,
+    commit: &str,
+    branch: Option<&str>,
+) -> Result<()> {
+    let prefix = prefix.strip_suffix('/').unwrap_or(prefix);
+    if let Some(branch) = branch
+        && !is_valid_ref_name(&format!("heads/{branch}"))
+    {
+        bail!("'{branch}' does not look like a ref");
+    }
+    let Ok(tip) = Commit::read(repo, commit) else {
+        bail!("'{commit}' does not refer to a commit");
+    };
+    let mut split = Split {
+        config,
+        prefix,
+        copies: HashMap::new(),
+        no_tree: HashSet::new(),
+        latest: None,
+    };
+    split.find_existing_splits(repo, &tip.hash)?;
+    split.process(repo, tip)?;
+    let Some(latest) = split.latest else {
+        bail!("no new revisions were found");
+    };
+
+    if let Some(branch) = branch {
+        let ref_name = format!("refs/heads/{branch}");
+        let action = match resolve_ref(repo, &ref_name)? {
+            Some(old) if ahead_behind(repo, &old, &latest)?.0 != 0 => {
+                bail!("branch '{branch}' is not an ancestor of commit '{latest}'.");
+            }
+            Some(_) => "Updated",
+            None => "Created",
+        };
+        update_ref(repo, config, &ref_name, &latest, "subtree split")?;
+        writeln!(err, "{action} branch '{branch}'")?;
+    }
+    writeln!(out, "{latest}")?;
+    Ok(())
+}
+
+/// `git subtree merge --prefix=<prefix> <commit>`: merge the changes `commit` (a later
+/// commit of the history [`git_subtree_add`] brought in) made since it was last merged
+/// into the directory `prefix`, with a merge commit whose second parent is `commit`.
+///
+/// This is git's `merge -Xsubtree=<prefix>`: the merge base and `commit`, whose trees
+/// have the subdirectory's content at their root, are shifted into `prefix` and then
+/// merged with HEAD's tree by [`merge_trees`]. The message is `message`, or `Merge
+/// commit '<commit>'`; the changes merged are shown as a diffstat. A conflict is left
+/// for the user to resolve and commit, with `MERGE_HEAD` and `MERGE_MSG` written for
+/// [`git_commit`](crate::commit::git_commit), and the exit status is then 1.
+pub fn git_subtree_merge(
+    repo: &Repository,
+    config: &Config,
+    out: &mut impl Write,
+    prefix: &str,
+    commit: &str,
+    message: Option<&str>,
+) -> Result<i32> {
+    let prefix = prefix.strip_suffix('/').unwrap_or(prefix);
+    let Ok(commit) = Commit::read(repo, commit) else {
+        bail!("'{commit}' does not refer to a commit");
+    };
+    if !repo.path(prefix).exists() {
+        bail!("'{prefix}' does not exist; use 'git subtree add'");
+    }
+    if is_dirty(repo)? {
+        bail!("working tree has modifications.  Cannot add.");
+    }
+    let Some(head) = resolve_ref(repo, "HEAD")? else {
+        bail!("cannot merge a subtree into an unborn branch");
+    };
+    let base = merge_base(repo, &head, &commit.hash)?;
+    if base.as_deref() == Some(commit.hash.as_str()) {
+        writeln!(out, "Already up to date.")?;
+        return Ok(0);
+    }
+    let base = match &base {
+        Some(base) => Some(shift_tree(repo, &Commit::read(repo, base)?.tree, prefix)?),
+        None => None,
+    };
+    let theirs = shift_tree(repo, &commit.tree, prefix)?;
+    let head_commit = Commit::read(repo, &head)?;
+    let merged = merge_trees(
+        repo,
+        base.as_ref(),
+        Some(&parse_hash(&head_commit.tree)?),
+        Some(&theirs),
+        "HEAD",
+        &commit.hash,
+    )?;
+    let conflicts = checkout_merge(repo, &merged)?;
+    for message in &merged.messages {
+        writeln!(out, "{message}")?;
+    }
+    let message = match message {
+        Some(message) => format!("{message}\n"),
+        None => format!("Merge commit '{}'\n", commit.hash),
+    };
+    if !conflicts.is_empty() {
+        write(repo.git_path("MERGE_HEAD"), format!("{}\n", commit.hash))?;
+        write(
+            repo.git_path("MERGE_MSG"),
+            format!("{message}\n# Conflicts:\n#\t{}\n", conflicts.join("\n#\t")),
+        )?;
+        writeln!(
+            out,
+            "Automatic merge failed; fix conflicts and then commit the result."
+        )?;
+        return Ok(1);
+    }
+    let tree = git_write_tree(repo)?.to_string();
+    let merge = CommitBuilder::new(tree)
+        .parent(&head)
+        .parent(&commit.hash)
+        .message(message)
+        .write(repo, config)?
+        .to_string();
+    update_ref(
+        repo,
+        config,
+        "HEAD",
+        &merge,
+        &format!("merge {}: Merge made by the 'ort' strategy.", commit.hash),
+    )?;
+    writeln!(out, "Merge made by the 'ort' strategy.")?;
+    let options = DiffOptions {
+        stat: true,
+        summary: true,
+        ..DiffOptions::default()
+    };
+    // The diffstat goes straight to standard output, after what is written so far.
+    out.flush()?;
+    print_tree_diff(repo, config, &head, &merge, &options)?;
+    Ok(0)
+}
+
+/// A tree that has `tree` at the directory `prefix` and nothing else, the shape
+/// `-Xsubtree` gives a side of the merge.
+fn shift_tree(repo: &Repository, tree: &str, prefix: &str) -> Result<ObjectId> {
+    let mut tree = parse_hash(tree)?;
+    for name in prefix.rsplit('/').filter(|name| !name.is_empty()) {
+        let mut data = format!("40000 {name}\0").into_bytes();
+        data.extend(tree.as_bytes());
+        tree = Object {
+            kind: ObjectKind::Tree,
+            expected_size: data.len() as u64,
+            reader: Cursor::new(data),
+        }
+        .write_as_object(repo)?;
+    }
+    Ok(tree)
+}
+
+/// The state of a `subtree split` as it works through history.
+struct Split<'a> {
+    config: &'a Config,
+    prefix: &'a str,
+    /// The split commit each commit maps to: its copy, the copy standing for it, or
+    /// (for commits recorded by `subtree add`) the subtree history itself.
+    copies: HashMap<String, String>,
+    /// Commits without the directory that map to nothing.
+    no_tree: HashSet<String>,
+    /// The split commit found for the last commit processed.
+    latest: Option<String>,
+}
+
+impl Split<'_> {
+    /// Map the commits named by earlier `subtree add` (and `split --rejoin`) merges
+    /// into the directory: the mainline commit and the added one both to the added
+    /// one, and a squashed commit to the commit it squashed.
+    fn find_existing_splits(&mut self, repo: &Repository, tip: &str) -> Result<()> {
+        let dir_line = |line: &str| {
+            line.strip_prefix("git-subtree-dir: ")
+                .is_some_and(|dir| dir.trim_end_matches('/') == self.prefix)
+        };
+        for commit in walk_commits(repo, &[tip.to_string()], &[])? {
+            if !commit.message.lines().any(dir_line) {
+                continue;
+            }
+            let mut main = None;
+            let mut sub = None;
+            for line in commit.message.lines() {
+                let mut words = line.split_ascii_whitespace();
+                match (words.next(), words.next()) {
+                    (Some("git-subtree-mainline:"), Some(hash)) => main = Some(hash.to_string()),
+                    (Some("git-subtree-split:"), Some(hash)) => {
+                        let Ok(split) = Commit::read(repo, hash) else {
+                            bail!(
+                                "could not rev-parse split hash {hash} from commit {}",
+                                commit.hash
+                            );
+                        };
+                        sub = Some(split.hash);
+                    }
+                    _ => {}
+                }
+            }
+            match (main, sub) {
+                (None, Some(sub)) => {
+                    self.copies.insert(commit.hash, sub);
+                }
+                (Some(main), Some(sub)) => {
+                    self.copies.insert(main, sub.clone());
+                    self.copies.insert(sub.clone(), sub);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Find the split commit for `tip` and, first, for all of its history that isn't
+    /// mapped yet, parents before children.
+    fn process(&mut self, repo: &Repository, tip: Commit) -> Result<()> {
+        let mut stack = vec![(tip, false)];
+        while let Some((commit, parents_done)) = stack.pop() {
+            if self.copies.contains_key(&commit.hash) || self.no_tree.contains(&commit.hash) {
+                continue;
+            }
+            if parents_done {
+                self.process_commit(repo, &commit)?;
+                continue;
+            }
+            let pending: Vec<String> = commit
+                .parents
+                .iter()
+                .filter(|p| !self.copies.contains_key(*p) && !self.no_tree.contains(*p))
+                .cloned()
+                .collect();
+            stack.push((commit, true));
+            for parent in pending.into_iter().rev() {
+                stack.push((Commit::read(repo, &parent)?, false));
+            }
+        }
+        Ok(())
+    }
+
+    fn process_commit(&mut self, repo: &Repository, commit: &Commit) -> Result<()> {
+        let new_parents: Vec<String> = commit
+            .parents
+            .iter()
+            .filter_map(|parent| self.copies.get(parent).cloned())
+            .collect();
+        let Some(tree) = subtree_of(repo, &commit.tree, self.prefix)? else {
+            self.no_tree.insert(commit.hash.clone());
+            // Like git, a commit that dropped the directory stands for itself.
+            if !new_parents.is_empty() {
+                self.copies.insert(commit.hash.clone(), commit.hash.clone());
+            }
+            return Ok(());
+        };
+        let copy = copy_or_skip(repo, self.config, commit, &tree, &new_parents)?;
+        self.copies.insert(commit.hash.clone(), copy.clone());
+        self.latest = Some(copy);
+        Ok(())
+    }
+}
+
+/// The split commit for `commit`, whose directory has the tree `tree`: a parent's
+/// split commit with that same tree if it can stand in without losing history, or
+/// else a copy of `commit` with that tree on top of `new_parents`.
+fn copy_or_skip(
+    repo: &Repository,
+    config: &Config,
+    commit: &Commit,
+    tree: &str,
+    new_parents: &[String],
+) -> Result<String> {
+    let mut identical: Option<&str> = None;
+    let mut nonidentical: Option<&str> = None;
+    let mut parents: Vec<&str> = Vec::new();
+    let mut must_copy = false;
+    for parent in new_parents {
+        if Commit::read(repo, parent)?.tree == tree {
+            match identical {
+                Some(current) => {
+                    let (current_only, parent_only) = ahead_behind(repo, current, parent)?;
+                    if current_only == 0 {
+                        identical = Some(parent);
+                    } else if parent_only != 0 {
+                        // No common history; the commit must be copied.
+                        must_copy = true;
+                    }
+                }
+                None => identical = Some(parent),
+            }
+        } else {
+            nonidentical = Some(parent);
+        }
+        // Several parents may have been split to the same commit.
+        if !parents.contains(&parent.as_str()) {
+            parents.push(parent);
+        }
+    }
+    if let (Some(identical), Some(nonidentical)) = (identical, nonidentical)
+        && ahead_behind(repo, nonidentical, identical)?.0 != 0
+    {
+        // The other parent's history has to be kept.
+        must_copy = true;
+    }
+    if let Some(identical) = identical
+        && !must_copy
+    {
+        return Ok(identical.to_string());
+    }
+    let copy = parents
+        .iter()
+        .fold(CommitBuilder::new(tree), |copy, parent| {
+            copy.parent(*parent)
+        })
+        .author(commit.author.clone())
+        .committer(commit.committer.clone())
+        .message(&commit.message)
+        .write(repo, config)
+        .with_context(|| format!("can't copy commit {}", commit.hash))?;
+    Ok(copy.to_string())
+}
+
+/// The tree at `path` within `tree`, if there is a directory there.
+fn subtree_of(repo: &Repository, tree: &str, path: &str) -> Result<Option<String>> {
+    let mut tree = tree.to_string();
+    for name in path.split('/').filter(|name| !name.is_empty()) {
+        let entries = read_tree_entries(repo, Object::read_git_object(repo, &tree)?)?;
+        match entries
+            .into_iter()
+            .find(|e| e.name == name && e.mode == "40000")
+        {
+            Some(entry) => tree = entry.hash.to_string(),
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(tree))
+}
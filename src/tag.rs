@@ -0,0 +1,106 @@
+use crate::config::Config;
+use crate::ident::{Role, resolve_ident};
+use crate::mailinfo::clean_message;
+use crate::object_read::{Object, ObjectKind};
+use crate::refs::{RefTransaction, delete_ref, is_valid_ref_name, list_refs, resolve_ref};
+use crate::repository::Repository;
+use crate::rev_parse::rev_parse;
+use crate::wildmatch::wildmatch;
+use anyhow::{Result, anyhow, bail};
+use std::fmt::Write;
+use std::io::Cursor;
+
+/// `git tag [-l] [<pattern>...]`: the names of the tags, sorted; with patterns, only
+/// those matching one of them (`*` matching `/` too).
+pub fn git_tag_list(repo: &Repository, patterns: &[String]) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for (ref_name, _) in list_refs(repo, "refs/tags/")? {
+        let name = &ref_name["refs/tags/".len()..];
+        if patterns.is_empty() || patterns.iter().any(|p| wildmatch(p, name, false, false)) {
+            names.push(name.to_string());
+        }
+    }
+    Ok(names)
+}
+
+/// `git tag [-f] <name> [<object>]`, or with a message `git tag -a -m <message> <name>
+/// [<object>]`: tag the object (HEAD by default), with just a ref under `refs/tags/`
+/// (a *lightweight* tag) or, given a message, a ref to an *annotated tag object*
+/// (see [`crate::mktag::git_mktag`]) naming the object, its type, the tag's name,
+/// the tagger and the message. The message is cleaned up as git does: `#` comment
+/// lines, trailing whitespace and extra blank lines are removed.
+///
+/// An existing tag is only replaced with `force`, in which case the value it had is
+/// reported.
+pub fn git_tag_create(
+    repo: &Repository,
+    config: &Config,
+    name: &str,
+    object: Option<&str>,
+    message: Option<&str>,
+    force: bool,
+) -> Result<()> {
+    let ref_name = format!("refs/tags/{name}");
+    if !is_valid_ref_name(&ref_name) {
+        bail!("'{name}' is not a valid tag name.");
+    }
+    let object = object.unwrap_or("HEAD");
+    let target = rev_parse(repo, object)
+        .map_err(|_| anyhow!("Failed to resolve '{object}' as a valid ref."))?;
+    let previous = resolve_ref(repo, &ref_name)?;
+    if previous.is_some() && !force {
+        bail!("tag '{name}' already exists");
+    }
+
+    let hash = match message {
+        Some(message) => {
+            let message: String = message
+                .lines()
+                .filter(|line| !line.starts_with('#'))
+                .map(|line| format!("{line}\n"))
+                .collect();
+            let message = clean_message(&message);
+            let kind = Object::read_git_object(repo, &target)?.kind;
+            let tagger = resolve_ident(Role::Committer, config)?;
+            let mut payload = String::new();
+            writeln!(payload, "object {target}")?;
+            writeln!(payload, "type {}", kind.to_str())?;
+            writeln!(payload, "tag {name}")?;
+            write!(payload, "tagger {tagger}\n\n{message}")?;
+            let mut object = Object {
+                kind: ObjectKind::Tag,
+                expected_size: payload.len() as u64,
+                reader: Cursor::new(payload),
+            };
+            object.write_as_object(repo)?.to_string()
+        }
+        None => target,
+    };
+
+    let mut transaction = RefTransaction::new(&repo.git_dir);
+    transaction.update(&ref_name, previous.as_deref(), Some(&hash), "");
+    transaction.commit(config)?;
+    if let Some(previous) = previous
+        && previous != hash
+    {
+        println!("Updated tag '{name}' (was {})", &previous[..7]);
+    }
+    Ok(())
+}
+
+/// `git tag -d <name>...`: delete the tags, reporting what each pointed at. Every
+/// tag is tried; returns the exit status, 1 if any didn't exist.
+pub fn git_tag_delete(repo: &Repository, names: &[String]) -> Result<i32> {
+    let mut status = 0;
+    for name in names {
+        let ref_name = format!("refs/tags/{name}");
+        let Some(hash) = resolve_ref(repo, &ref_name)? else {
+            eprintln!("error: tag '{name}' not found.");
+            status = 1;
+            continue;
+        };
+        delete_ref(repo, &ref_name)?;
+        println!("Deleted tag '{name}' (was {})", &hash[..7]);
+    }
+    Ok(status)
+}
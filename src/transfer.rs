@@ -0,0 +1,313 @@
+use crate::commit_read::Commit;
+use crate::ls_tree::read_tree_entries;
+use crate::object_read::{Object, ObjectKind, has_object, packs};
+use crate::odb::{LooseOdb, Odb};
+use crate::repository::{Repository, hash_algorithm_in};
+use crate::revwalk::RevWalk;
+use anyhow::{Context, Result, bail, ensure};
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, create_dir_all, read_dir, remove_file, rename};
+use std::io;
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+/// Copy every object reachable from `tips` that `to` doesn't have from `from` (both
+/// `objects` directories), returning how many were copied. Objects are copied as
+/// loose objects, packed ones being unpacked.
+///
+/// An object `to` already has is assumed to come with everything it references, the
+/// way a repository's history is always complete, so the walk doesn't descend into it.
+pub fn copy_objects(repo: &Repository, from: &Path, to: &Path, tips: &[String]) -> Result<usize> {
+    copy_objects_into(repo, from, to, &[], tips)
+}
+
+/// [`copy_objects`], also leaving out the objects (and what they reference) that one
+/// of the `present` directories has, e.g. the repository a quarantine directory
+/// receives objects for.
+///
+/// Both stores have to name objects with the same hash function, as there is no
+/// converting between them.
+pub fn copy_objects_into(
+    repo: &Repository,
+    from: &Path,
+    to: &Path,
+    present: &[&Path],
+    tips: &[String],
+) -> Result<usize> {
+    let (source, target) = (hash_algorithm_in(from)?, hash_algorithm_in(to)?);
+    ensure!(
+        source == target,
+        "mismatched algorithms: can't copy objects of a {} repository into a {} one",
+        source.name(),
+        target.name()
+    );
+    let mut seen = HashSet::new();
+    let mut pending: Vec<String> = tips.to_vec();
+    let mut copied = 0;
+    while let Some(hash) = pending.pop() {
+        if !seen.insert(hash.clone())
+            || std::iter::once(to)
+                .chain(present.iter().copied())
+                .any(|dir| has_object(dir, &hash))
+        {
+            continue;
+        }
+        let source = loose_object_path(from, &hash);
+        if source.is_file() {
+            copy_object_file(&source, &loose_object_path(to, &hash))?;
+        } else if let Some((kind, data)) = packs(from)?.read_object(&hash.parse()?)? {
+            LooseOdb::new(to).write(kind, data.len() as u64, &mut data.as_slice())?;
+        } else {
+            bail!("object {hash} is missing from {}", from.display());
+        }
+        pending.extend(referenced_objects(repo, from, &hash)?);
+        copied += 1;
+    }
+    Ok(copied)
+}
+
+/// The first object reachable from `tips` that is neither in `incoming` nor in
+/// `objects_dir`, if any: objects in `incoming` are followed to what they reference,
+/// while those in `objects_dir` are taken to be complete.
+pub fn find_missing_object(
+    repo: &Repository,
+    incoming: &Path,
+    objects_dir: &Path,
+    tips: &[String],
+) -> Result<Option<String>> {
+    let mut seen = HashSet::new();
+    let mut pending: Vec<String> = tips.to_vec();
+    while let Some(hash) = pending.pop() {
+        if !seen.insert(hash.clone()) || has_object(objects_dir, &hash) {
+            continue;
+        }
+        if !has_object(incoming, &hash) {
+            return Ok(Some(hash));
+        }
+        pending.extend(referenced_objects(repo, incoming, &hash)?);
+    }
+    Ok(None)
+}
+
+/// The objects of the repository that another one which has `haves` (ids, those the
+/// repository doesn't have being ignored) needs to have `tips` as well: what a
+/// [`RevWalk`] of `tips` hiding `haves` reaches, as `git rev-list --objects <tips>
+/// --not <haves>` lists it. Each tree or blob comes with the one the other repository
+/// has at the same path, if there is a different one, as the base for a delta.
+pub fn objects_to_send(
+    repo: &Repository,
+    tips: &[String],
+    haves: &[String],
+) -> Result<Vec<(String, Option<String>)>> {
+    let walk = RevWalk {
+        tips: tips.to_vec(),
+        hide: haves
+            .iter()
+            .filter(|hash| repo.has_object(hash))
+            .cloned()
+            .collect(),
+        objects: true,
+        ..RevWalk::default()
+    };
+    Ok(walk
+        .walk(repo)?
+        .into_iter()
+        .map(|object| (object.hash, object.base))
+        .collect())
+}
+
+/// A tree or blob [`TreeWalk`] reached: its id and kind, its path from the top of the
+/// tree it was reached from, and the object known at that path, if there is a
+/// different one.
+pub struct TreeObject {
+    pub hash: String,
+    pub kind: ObjectKind,
+    pub path: String,
+    pub base: Option<String>,
+}
+
+/// The trees and blobs of a set of trees that aren't known already (to the other side
+/// of a push, say), in the order `git rev-list --objects` lists them: each tree before
+/// what it holds, depth first, and nothing twice. Submodule commits are left out,
+/// since they live in another repository.
+pub struct TreeWalk {
+    known: HashSet<String>,
+    /// By path, the first tree or blob marked known at it.
+    bases: HashMap<String, String>,
+    pub objects: Vec<TreeObject>,
+}
+
+impl TreeWalk {
+    /// A walk that leaves out the objects in `known`.
+    pub fn new(known: HashSet<String>) -> TreeWalk {
+        TreeWalk {
+            known,
+            bases: HashMap::new(),
+            objects: Vec::new(),
+        }
+    }
+
+    /// Record the object `hash` as known, returning whether it wasn't already.
+    pub fn insert_known(&mut self, hash: &str) -> bool {
+        self.known.insert(hash.to_string())
+    }
+
+    /// Record the tree (or blob) `hash` and everything in it as known, each tree and
+    /// blob as the base for what [`TreeWalk::add`] reaches at the same path.
+    pub fn mark_known(&mut self, repo: &Repository, hash: &str) -> Result<()> {
+        self.mark_tree(repo, hash, "")
+    }
+
+    fn mark_tree(&mut self, repo: &Repository, hash: &str, path: &str) -> Result<()> {
+        self.bases
+            .entry(path.to_string())
+            .or_insert_with(|| hash.to_string());
+        if !self.known.insert(hash.to_string()) {
+            return Ok(());
+        }
+        let object = Object::read_git_object(repo, hash)?;
+        if object.kind != ObjectKind::Tree {
+            return Ok(());
+        }
+        for entry in read_tree_entries(repo, object)? {
+            if entry.mode != "160000" {
+                self.mark_tree(repo, &entry.hash.to_string(), &sub_path(path, &entry.name))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the tree (or blob) `hash` at `path`, and what it holds, to `self.objects`
+    /// unless they are known. With `paths`, only what is under one of them (from the
+    /// top of the tree `path` is in) is added below `hash`, with the trees leading to
+    /// them.
+    pub fn add(
+        &mut self,
+        repo: &Repository,
+        hash: &str,
+        path: &str,
+        paths: &[String],
+    ) -> Result<()> {
+        if !self.known.insert(hash.to_string()) {
+            return Ok(());
+        }
+        let object = Object::read_git_object(repo, hash)?;
+        self.objects.push(TreeObject {
+            hash: hash.to_string(),
+            kind: object.kind.clone(),
+            path: path.to_string(),
+            base: self.bases.get(path).cloned(),
+        });
+        if object.kind != ObjectKind::Tree {
+            return Ok(());
+        }
+        for entry in read_tree_entries(repo, object)? {
+            let entry_path = sub_path(path, &entry.name);
+            if entry.mode != "160000" && within_paths(&entry_path, paths) {
+                self.add(repo, &entry.hash.to_string(), &entry_path, paths)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `path` is under one of `paths`, or leads to one; any path is with none.
+fn within_paths(path: &str, paths: &[String]) -> bool {
+    let below = |path: &str, dir: &str| {
+        path.strip_prefix(dir)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    };
+    paths.is_empty()
+        || paths.iter().any(|limit| {
+            let limit = limit.trim_end_matches('/');
+            limit == "." || below(path, limit) || below(limit, path)
+        })
+}
+
+/// `name` inside the directory `path`, which is empty for the top of the tree.
+fn sub_path(path: &str, name: &str) -> String {
+    match path {
+        "" => name.to_string(),
+        _ => format!("{path}/{name}"),
+    }
+}
+
+/// Move every loose object in `from` into `to`, both `objects` directories, keeping
+/// the copy `to` already has of an object.
+pub fn migrate_objects(from: &Path, to: &Path) -> Result<()> {
+    for fanout in read_dir(from).with_context(|| format!("failed to read {}", from.display()))? {
+        let fanout = fanout?;
+        let name = fanout.file_name();
+        if name.len() != 2 || !fanout.file_type()?.is_dir() {
+            continue;
+        }
+        let dir = to.join(&name);
+        create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+        for object in read_dir(fanout.path())? {
+            let object = object?;
+            let destination = dir.join(object.file_name());
+            if destination.is_file() {
+                remove_file(object.path())?;
+                continue;
+            }
+            rename(object.path(), &destination)
+                .with_context(|| format!("failed to move object to {}", destination.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Where the loose object `hash` lives under `objects_dir`.
+pub fn loose_object_path(objects_dir: &Path, hash: &str) -> PathBuf {
+    objects_dir.join(&hash[..2]).join(&hash[2..])
+}
+
+/// The objects `hash` points at directly: a commit's tree and parents, a tree's
+/// entries (except submodule commits, which live in another repository) and a tag's
+/// target.
+fn referenced_objects(repo: &Repository, objects_dir: &Path, hash: &str) -> Result<Vec<String>> {
+    let mut object = Object::read_git_object_in(objects_dir, hash)?;
+    Ok(match object.kind {
+        ObjectKind::Blob => Vec::new(),
+        ObjectKind::Tree => read_tree_entries(repo, object)?
+            .into_iter()
+            .filter(|entry| entry.mode != "160000")
+            .map(|entry| entry.hash.to_string())
+            .collect(),
+        ObjectKind::Commit => {
+            let commit = Commit::parse(hash, &object.read_all()?)
+                .with_context(|| format!("invalid commit {hash}"))?;
+            let mut objects = commit.parents;
+            objects.push(commit.tree);
+            objects
+        }
+        ObjectKind::Tag => {
+            let target = tag_target(&object.read_all()?);
+            vec![target.with_context(|| format!("invalid tag {hash}"))?]
+        }
+    })
+}
+
+/// The object a tag object's data says it points at.
+pub fn tag_target(data: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(data);
+    let line = text.lines().next()?;
+    line.strip_prefix("object ").map(str::to_string)
+}
+
+/// Copy a loose object file into place through a temporary file, so that a
+/// concurrent reader never sees a partial object.
+fn copy_object_file(source: &Path, destination: &Path) -> Result<()> {
+    let dir = destination
+        .parent()
+        .context("object path has no directory")?;
+    create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    let mut temp = NamedTempFile::new_in(dir)?;
+    let mut file =
+        File::open(source).with_context(|| format!("failed to read {}", source.display()))?;
+    io::copy(&mut file, &mut temp)
+        .with_context(|| format!("failed to copy {}", source.display()))?;
+    temp.persist(destination)
+        .with_context(|| format!("failed to write {}", destination.display()))?;
+    Ok(())
+}
@@ -0,0 +1,185 @@
+use crate::config::Config;
+use crate::refs::{RefTransaction, is_valid_ref_name};
+use crate::repository::Repository;
+use crate::rev_parse::rev_parse;
+use anyhow::{Context, Result, anyhow, bail};
+use std::io::{BufRead, stdin};
+
+/// `git update-ref <ref> <new-value> [<old-value>]`, or with `new_value` `None`,
+/// `git update-ref -d <ref> [<old-value>]`: set (or delete) `ref_name`, which must have
+/// the value `old_value` if one is given; the zero id stands for a ref that must not
+/// exist yet.
+pub fn git_update_ref(
+    repo: &Repository,
+    config: &Config,
+    ref_name: &str,
+    new_value: Option<&str>,
+    old_value: Option<&str>,
+    message: &str,
+) -> Result<()> {
+    let value =
+        |value: &str| parse_value(repo, value).map_err(|_| anyhow!("{value}: not a valid SHA1"));
+    let new_hash = new_value.map(value).transpose()?.flatten();
+    let mut transaction = RefTransaction::new(&repo.git_dir);
+    match old_value {
+        Some(old_value) => {
+            let old_hash = value(old_value)?;
+            transaction.update(ref_name, old_hash.as_deref(), new_hash.as_deref(), message);
+        }
+        None => transaction.update_unchecked(ref_name, new_hash.as_deref(), message),
+    }
+    match new_value {
+        Some(_) => transaction
+            .commit(config)
+            .map_err(|e| anyhow!("update_ref failed for ref '{ref_name}': {e:#}")),
+        None => transaction.commit(config),
+    }
+}
+
+/// Where `git update-ref --stdin` is in the transaction it builds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Updates are being queued, and committed at the end of input.
+    Open,
+    /// After `start`: updates are being queued, and dropped unless committed.
+    Started,
+    /// After `prepare`: the refs are locked, and the transaction can only be
+    /// committed or aborted.
+    Prepared,
+    /// After `commit` or `abort`; only `start` may follow.
+    Closed,
+}
+
+/// `git update-ref --stdin`: read ref updates, one command per line, and apply them
+/// as one transaction, so that all of them happen or none do:
+///
+///     update <ref> <new-value> [<old-value>]
+///     create <ref> <new-value>
+///     delete <ref> [<old-value>]
+///     verify <ref> [<old-value>]
+///
+/// Without an old value, `update` and `delete` don't check what the ref is at, while
+/// `verify` checks it doesn't exist; an empty or zero value stands for no ref. Updates
+/// are committed at the end of input, unless `start`, `prepare`, `commit` and `abort`
+/// control the transaction explicitly: `start` begins one (left uncommitted at the
+/// end of input), `prepare` locks and checks its refs, `commit` applies it and `abort`
+/// drops it, each answering `<command>: ok` on stdout. After `commit` or `abort`, a
+/// new transaction can be started.
+pub fn git_update_ref_stdin(repo: &Repository, config: &Config, message: &str) -> Result<()> {
+    let git_dir = &repo.git_dir;
+    let mut transaction = RefTransaction::new(git_dir);
+    let mut state = State::Open;
+    for line in stdin().lock().lines() {
+        let line = line.context("failed to read stdin")?;
+        let Some((command, args)) = line.split_once(' ') else {
+            state = match (line.as_str(), state) {
+                ("start", State::Started | State::Prepared) => {
+                    bail!("cannot restart ongoing transaction")
+                }
+                ("start", State::Open) => State::Started,
+                ("start", State::Closed) => {
+                    transaction = RefTransaction::new(git_dir);
+                    State::Started
+                }
+                ("prepare" | "commit" | "abort", State::Closed) => bail!("transaction is closed"),
+                ("prepare", State::Prepared) => bail!("prepared transactions can only be closed"),
+                ("prepare", _) => {
+                    transaction.prepare()?;
+                    State::Prepared
+                }
+                ("commit", _) => {
+                    std::mem::replace(&mut transaction, RefTransaction::new(git_dir))
+                        .commit(config)?;
+                    State::Closed
+                }
+                ("abort", _) => {
+                    transaction = RefTransaction::new(git_dir);
+                    State::Closed
+                }
+                _ => bail!("unknown command: {line}"),
+            };
+            println!("{line}: ok");
+            continue;
+        };
+        if !matches!(command, "update" | "create" | "delete" | "verify") {
+            bail!("unknown command: {line}");
+        }
+        match state {
+            State::Prepared => bail!("prepared transactions can only be closed"),
+            State::Closed => bail!("transaction is closed"),
+            State::Open | State::Started => {}
+        }
+        queue_command(repo, &mut transaction, command, args, message)?;
+    }
+    match state {
+        State::Open => transaction.commit(config),
+        // Dropping the transaction aborts it.
+        State::Started | State::Prepared | State::Closed => Ok(()),
+    }
+}
+
+/// Parse the arguments of an `update`, `create`, `delete` or `verify` line and queue
+/// the update it asks for.
+fn queue_command(
+    repo: &Repository,
+    transaction: &mut RefTransaction,
+    command: &str,
+    args: &str,
+    message: &str,
+) -> Result<()> {
+    let arity = if command == "update" { 3 } else { 2 };
+    let mut args = args.splitn(arity + 1, ' ');
+    let ref_name = args.next().unwrap_or("");
+    if ref_name.is_empty() {
+        bail!("{command}: missing <ref>");
+    }
+    if !is_valid_ref_name(ref_name) {
+        bail!("invalid ref format: {ref_name}");
+    }
+    let value = |which: &str, value: &str| {
+        parse_value(repo, value)
+            .map_err(|_| anyhow!("{command} {ref_name}: invalid <{which}>: {value}"))
+    };
+    let new_value = match command {
+        "update" | "create" => match args.next() {
+            Some(new_value) => Some(value("newvalue", new_value)?),
+            None => bail!("{command} {ref_name}: missing <newvalue>"),
+        },
+        _ => None,
+    };
+    let old_value = args
+        .next()
+        .map(|old_value| value("oldvalue", old_value))
+        .transpose()?;
+    if let Some(extra) = args.next() {
+        bail!("{command} {ref_name}: extra input: {extra}");
+    }
+    match (command, new_value, old_value) {
+        ("update", Some(new_hash), Some(old_hash)) => {
+            transaction.update(ref_name, old_hash.as_deref(), new_hash.as_deref(), message)
+        }
+        ("update", Some(new_hash), None) => {
+            transaction.update_unchecked(ref_name, new_hash.as_deref(), message)
+        }
+        ("create", Some(None), _) => bail!("create {ref_name}: zero <newvalue>"),
+        ("create", Some(new_hash), _) => {
+            transaction.update(ref_name, None, new_hash.as_deref(), message)
+        }
+        ("delete", _, Some(None)) => bail!("delete {ref_name}: zero <oldvalue>"),
+        ("delete", _, Some(old_hash)) => {
+            transaction.update(ref_name, old_hash.as_deref(), None, message)
+        }
+        ("delete", _, None) => transaction.update_unchecked(ref_name, None, message),
+        (_, _, old_hash) => transaction.verify(ref_name, old_hash.flatten().as_deref()),
+    }
+    Ok(())
+}
+
+/// The full object id `value` names, or `None` for the zero id or an empty value,
+/// either of which stands for a ref that doesn't exist.
+fn parse_value(repo: &Repository, value: &str) -> Result<Option<String>> {
+    if value.is_empty() || value == repo.hash_algorithm()?.null_id().to_string() {
+        return Ok(None);
+    }
+    Ok(Some(rev_parse(repo, value)?))
+}
@@ -0,0 +1,185 @@
+use crate::attr::{AttrValue, Attributes};
+use crate::config::Config;
+use anyhow::{Result, bail};
+use regex_automata::Input;
+use regex_automata::meta::Regex;
+use regex_automata::util::syntax;
+
+/// How many bytes of a function line a hunk header shows.
+const FUNCNAME_LEN: usize = 80;
+
+/// How the files whose `diff` attribute names a driver (`diff=<driver>`) are diffed,
+/// from the `diff.<driver>.*` settings; `-diff` and `diff` act as drivers too.
+#[derive(Debug, Default)]
+pub struct DiffDriver {
+    /// Whether the files are binary (`-diff`, `diff.<driver>.binary`) or text (`diff`)
+    /// whatever they hold; `None` to tell from their content.
+    pub binary: Option<bool>,
+    /// `diff.<driver>.command`: a program showing the change instead of a patch.
+    pub command: Option<String>,
+    /// The lines of `diff.<driver>.xfuncname` (or `funcname`), in order.
+    funcname: Vec<FuncnamePattern>,
+}
+
+/// One line of a driver's funcname setting: a regex, and whether a line it matches
+/// is not a function line (a `!` prefix).
+#[derive(Debug)]
+struct FuncnamePattern {
+    negate: bool,
+    regex: Regex,
+}
+
+impl DiffDriver {
+    /// The driver for `path`, as its `diff` attribute selects it.
+    pub fn for_path(config: &Config, attributes: &Attributes, path: &str) -> Result<DiffDriver> {
+        let name = match attributes.get(path, "diff")? {
+            None => return Ok(DiffDriver::default()),
+            Some(AttrValue::Unset) => {
+                return Ok(DiffDriver {
+                    binary: Some(true),
+                    ..DiffDriver::default()
+                });
+            }
+            Some(AttrValue::Set) => {
+                return Ok(DiffDriver {
+                    binary: Some(false),
+                    ..DiffDriver::default()
+                });
+            }
+            Some(AttrValue::Value(name)) => name,
+        };
+        let get = |key: &str| config.get(&format!("diff.{name}.{key}"));
+        let funcname = match (get("xfuncname"), get("funcname")) {
+            (Some(patterns), _) => parse_funcname(patterns, true)?,
+            (None, Some(patterns)) => parse_funcname(patterns, false)?,
+            (None, None) => Vec::new(),
+        };
+        Ok(DiffDriver {
+            binary: config.get_bool(&format!("diff.{name}.binary"))?,
+            command: get("command").map(str::to_string),
+            funcname,
+        })
+    }
+
+    /// The line a hunk header names as the function it is in: the last of `lines`
+    /// before the hunk that the driver's patterns take for one, or else that starts
+    /// with a letter, `_` or `$`, as git's default has it. A pattern with a group
+    /// shows what the first group matched, otherwise the whole match. Either way
+    /// it is cut to 80 bytes, then trailing whitespace is dropped.
+    pub fn function_line<'a>(&self, lines: &[&'a [u8]]) -> Option<&'a [u8]> {
+        let line = lines
+            .iter()
+            .rev()
+            .find_map(|line| self.function_name(line))?;
+        let line = &line[..line.len().min(FUNCNAME_LEN)];
+        let end = line
+            .iter()
+            .rposition(|b| !b.is_ascii_whitespace())
+            .map_or(0, |end| end + 1);
+        Some(&line[..end])
+    }
+
+    /// Whether `line` starts a function, for function context; see
+    /// [`DiffDriver::function_line`].
+    pub fn is_function_line(&self, line: &[u8]) -> bool {
+        self.function_name(line).is_some()
+    }
+
+    /// What a hunk header shows of `line` if it is a function line: all of it by
+    /// default, or what the first of the driver's patterns that matches it (without
+    /// its line ending) picks out of it; `None` if none does or that one is negated.
+    fn function_name<'a>(&self, line: &'a [u8]) -> Option<&'a [u8]> {
+        if self.funcname.is_empty() {
+            let first = line.first()?;
+            return (first.is_ascii_alphabetic() || *first == b'_' || *first == b'$')
+                .then_some(line);
+        }
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        for pattern in &self.funcname {
+            let mut captures = pattern.regex.create_captures();
+            pattern
+                .regex
+                .search_captures(&Input::new(line), &mut captures);
+            if !captures.is_match() {
+                continue;
+            }
+            if pattern.negate {
+                return None;
+            }
+            let span = captures.get_group(1).or_else(|| captures.get_group(0))?;
+            return Some(&line[span.range()]);
+        }
+        None
+    }
+}
+
+/// The patterns of a funcname setting, one per line: extended regular expressions
+/// for `xfuncname`, basic ones for `funcname`.
+fn parse_funcname(patterns: &str, extended: bool) -> Result<Vec<FuncnamePattern>> {
+    let syntax = syntax::Config::new().unicode(false).utf8(false);
+    patterns
+        .split('\n')
+        .map(|line| {
+            let (negate, pattern) = match line.strip_prefix('!') {
+                Some(pattern) => (true, pattern),
+                None => (false, line),
+            };
+            let pattern = match extended {
+                true => pattern.to_string(),
+                false => basic_to_extended(pattern),
+            };
+            let Ok(regex) = Regex::builder().syntax(syntax).build(&pattern) else {
+                bail!("Invalid regexp to look for hunk header: {line}");
+            };
+            Ok(FuncnamePattern { negate, regex })
+        })
+        .collect()
+}
+
+/// A POSIX basic regular expression as an extended one: `\(`, `\)`, `\{`, `\}`, `\|`,
+/// `\+` and `\?` are the operators, and the same characters unescaped are literal.
+fn basic_to_extended(pattern: &str) -> String {
+    let mut out = String::new();
+    let mut chars = pattern.chars();
+    let mut in_brackets = false;
+    while let Some(c) = chars.next() {
+        match c {
+            _ if in_brackets => {
+                in_brackets = c != ']';
+                out.push(c);
+            }
+            '[' => {
+                in_brackets = true;
+                out.push(c);
+                // A `]` right after the opening bracket (or its `^`) is literal.
+                let mut next = chars.clone();
+                match next.next() {
+                    Some('^') if next.next() == Some(']') => {
+                        out.push_str("^]");
+                        chars.nth(1);
+                    }
+                    Some(']') => {
+                        out.push(']');
+                        chars.next();
+                    }
+                    _ => {}
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c @ ('(' | ')' | '{' | '}' | '|' | '+' | '?')) => out.push(c),
+                Some(c) => {
+                    out.push('\\');
+                    out.push(c);
+                }
+                None => out.push_str("\\\\"),
+            },
+            '(' | ')' | '{' | '}' | '|' | '+' | '?' => {
+                out.push('\\');
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
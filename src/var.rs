@@ -0,0 +1,40 @@
+use crate::config::Config;
+use crate::ident::{Role, git_editor, git_pager, resolve_ident};
+use anyhow::{Result, bail};
+
+/// The logical variables `git var` knows about.
+pub const VARIABLES: [&str; 4] = [
+    "GIT_AUTHOR_IDENT",
+    "GIT_COMMITTER_IDENT",
+    "GIT_EDITOR",
+    "GIT_PAGER",
+];
+
+/// Value of one of the logical [`VARIABLES`].
+pub fn git_var(config: &Config, variable: &str) -> Result<String> {
+    Ok(match variable {
+        "GIT_AUTHOR_IDENT" => resolve_ident(Role::Author, config)?.to_string(),
+        "GIT_COMMITTER_IDENT" => resolve_ident(Role::Committer, config)?.to_string(),
+        "GIT_EDITOR" => git_editor(config),
+        "GIT_PAGER" => git_pager(config),
+        other => bail!("unknown variable {other}"),
+    })
+}
+
+/// Every config entry as `key=value`, then each logical variable that resolves.
+pub fn git_var_list(config: &Config) -> Vec<String> {
+    let mut out: Vec<String> = config
+        .entries()
+        .iter()
+        .map(|e| match &e.value {
+            Some(value) => format!("{}={}", e.key, value),
+            None => e.key.clone(),
+        })
+        .collect();
+    for variable in VARIABLES {
+        if let Ok(value) = git_var(config, variable) {
+            out.push(format!("{variable}={value}"));
+        }
+    }
+    out
+}
@@ -0,0 +1,257 @@
+use crate::config::Config;
+use anyhow::{Result, bail};
+
+/// Which whitespace problems count as errors, from `core.whitespace`: a comma-separated
+/// list of rules, each turned off by a leading `-`, plus `tabwidth=<n>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WhitespaceRule {
+    /// Whitespace at the end of a line (`blank-at-eol`).
+    pub blank_at_eol: bool,
+    /// Blank lines added at the end of a file (`blank-at-eof`).
+    pub blank_at_eof: bool,
+    /// A space right before a tab in the indentation (`space-before-tab`).
+    pub space_before_tab: bool,
+    /// Indentation with `tab_width` or more spaces where a tab would do
+    /// (`indent-with-non-tab`).
+    pub indent_with_non_tab: bool,
+    /// Any tab in the indentation (`tab-in-indent`).
+    pub tab_in_indent: bool,
+    /// Don't count a carriage return at the end of a line as trailing whitespace
+    /// (`cr-at-eol`).
+    pub cr_at_eol: bool,
+    /// A last line without a newline (`incomplete-line`).
+    pub incomplete_line: bool,
+    pub tab_width: usize,
+}
+
+impl Default for WhitespaceRule {
+    /// git's default: `blank-at-eol,space-before-tab,blank-at-eof`.
+    fn default() -> Self {
+        WhitespaceRule {
+            blank_at_eol: true,
+            blank_at_eof: true,
+            space_before_tab: true,
+            indent_with_non_tab: false,
+            tab_in_indent: false,
+            cr_at_eol: false,
+            incomplete_line: false,
+            tab_width: 8,
+        }
+    }
+}
+
+/// The problems found in one line, or in the lines added at the end of a file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WhitespaceErrors {
+    pub blank_at_eol: bool,
+    pub blank_at_eof: bool,
+    pub space_before_tab: bool,
+    pub indent_with_non_tab: bool,
+    pub tab_in_indent: bool,
+    pub incomplete_line: bool,
+}
+
+impl WhitespaceRule {
+    pub fn from_config(config: &Config) -> Result<WhitespaceRule> {
+        match config.get("core.whitespace") {
+            Some(value) => WhitespaceRule::parse(value),
+            None => Ok(WhitespaceRule::default()),
+        }
+    }
+
+    /// Apply the rules in `value` on top of the default ones. Unknown rules are
+    /// ignored, as git does, so that newer ones don't break older versions.
+    pub fn parse(value: &str) -> Result<WhitespaceRule> {
+        let mut rule = WhitespaceRule::default();
+        for name in value
+            .split([',', ' ', '\t'])
+            .filter(|name| !name.is_empty())
+        {
+            if let Some(width) = name.strip_prefix("tabwidth=") {
+                match width.parse() {
+                    Ok(width @ 1..64) => rule.tab_width = width,
+                    _ => eprintln!("warning: tabwidth {width} out of range"),
+                }
+                continue;
+            }
+            let (name, on) = match name.strip_prefix('-') {
+                Some(name) => (name, false),
+                None => (name, true),
+            };
+            match name {
+                "trailing-space" => {
+                    rule.blank_at_eol = on;
+                    rule.blank_at_eof = on;
+                }
+                "blank-at-eol" => rule.blank_at_eol = on,
+                "blank-at-eof" => rule.blank_at_eof = on,
+                "space-before-tab" => rule.space_before_tab = on,
+                "indent-with-non-tab" => rule.indent_with_non_tab = on,
+                "tab-in-indent" => rule.tab_in_indent = on,
+                "cr-at-eol" => rule.cr_at_eol = on,
+                "incomplete-line" => rule.incomplete_line = on,
+                _ => {}
+            }
+        }
+        if rule.tab_in_indent && rule.indent_with_non_tab {
+            bail!("cannot enforce both tab-in-indent and indent-with-non-tab");
+        }
+        Ok(rule)
+    }
+
+    /// The problems in `line` (with its newline, if it has one).
+    pub fn check(&self, line: &[u8]) -> WhitespaceErrors {
+        let mut errors = WhitespaceErrors::default();
+        let content = match line.strip_suffix(b"\n") {
+            Some(content) => content,
+            None => {
+                errors.incomplete_line = self.incomplete_line;
+                line
+            }
+        };
+        if self.blank_at_eol {
+            errors.blank_at_eol = self
+                .without_cr(content)
+                .last()
+                .is_some_and(u8::is_ascii_whitespace);
+        }
+        let mut written = 0;
+        let mut indent = 0;
+        while indent < content.len() && matches!(content[indent], b' ' | b'\t') {
+            if content[indent] == b'\t' {
+                errors.space_before_tab |= self.space_before_tab && written < indent;
+                written = indent + 1;
+            }
+            indent += 1;
+        }
+        errors.indent_with_non_tab = self.indent_with_non_tab && indent - written >= self.tab_width;
+        errors.tab_in_indent = self.tab_in_indent && content[..indent].contains(&b'\t');
+        errors
+    }
+
+    /// `line` with the problems these rules can fix fixed: trailing whitespace
+    /// dropped, spaces in the indentation turned into tabs (or the other way round
+    /// for `tab-in-indent`), and a missing newline added.
+    pub fn fix(&self, line: &[u8]) -> Vec<u8> {
+        let (content, has_newline) = match line.strip_suffix(b"\n") {
+            Some(content) => (content, true),
+            None => (line, false),
+        };
+        let mut end = content.len();
+        let mut cr = false;
+        if self.blank_at_eol {
+            cr = self.cr_at_eol && content.ends_with(b"\r");
+            end = self.without_cr(content).len();
+            while end > 0 && content[end - 1].is_ascii_whitespace() {
+                end -= 1;
+            }
+        }
+        let content = &content[..end];
+
+        let (mut last_tab, mut last_space) = (None, None);
+        let mut fix_spaces = false;
+        for (i, &byte) in content.iter().enumerate() {
+            match byte {
+                b'\t' => {
+                    last_tab = Some(i);
+                    fix_spaces |= self.space_before_tab && last_space.is_some();
+                }
+                b' ' => {
+                    last_space = Some(i);
+                    let run = last_tab.map_or(i + 1, |tab| i - tab);
+                    fix_spaces |= self.indent_with_non_tab && run >= self.tab_width;
+                }
+                _ => break,
+            }
+        }
+        let mut out = Vec::with_capacity(line.len());
+        let rest = if fix_spaces {
+            // Between the start and the end of the fixed indentation spaces and tabs
+            // are mixed; a full tab width of spaces becomes a tab, and spaces right
+            // before a tab go away.
+            let last = match (last_tab, last_space) {
+                (tab, Some(space)) if self.indent_with_non_tab && tab < Some(space) => space + 1,
+                (tab, _) => tab.map_or(0, |tab| tab + 1),
+            };
+            let mut spaces = 0;
+            for &byte in &content[..last] {
+                if byte != b' ' {
+                    spaces = 0;
+                    out.push(byte);
+                } else {
+                    spaces += 1;
+                    if spaces == self.tab_width {
+                        out.push(b'\t');
+                        spaces = 0;
+                    }
+                }
+            }
+            out.extend(std::iter::repeat_n(b' ', spaces));
+            &content[last..]
+        } else if let Some(tab) = last_tab.filter(|_| self.tab_in_indent) {
+            for &byte in &content[..=tab] {
+                if byte == b'\t' {
+                    out.push(b' ');
+                    while out.len() % self.tab_width != 0 {
+                        out.push(b' ');
+                    }
+                } else {
+                    out.push(byte);
+                }
+            }
+            &content[tab + 1..]
+        } else {
+            content
+        };
+        out.extend_from_slice(rest);
+        if cr {
+            out.push(b'\r');
+        }
+        if has_newline || self.incomplete_line {
+            out.push(b'\n');
+        }
+        out
+    }
+
+    fn without_cr<'a>(&self, content: &'a [u8]) -> &'a [u8] {
+        match content.strip_suffix(b"\r") {
+            Some(content) if self.cr_at_eol => content,
+            _ => content,
+        }
+    }
+}
+
+impl WhitespaceErrors {
+    pub fn is_empty(&self) -> bool {
+        *self == WhitespaceErrors::default()
+    }
+
+    /// git's description of the problems, e.g. `trailing whitespace, tab in indent`.
+    pub fn description(&self) -> String {
+        let mut parts = Vec::new();
+        if self.blank_at_eol {
+            parts.push("trailing whitespace");
+        }
+        if self.blank_at_eof {
+            parts.push("new blank line at EOF");
+        }
+        if self.space_before_tab {
+            parts.push("space before tab in indent");
+        }
+        if self.indent_with_non_tab {
+            parts.push("indent with spaces");
+        }
+        if self.tab_in_indent {
+            parts.push("tab in indent");
+        }
+        if self.incomplete_line {
+            parts.push("no newline at the end of file");
+        }
+        parts.join(", ")
+    }
+}
+
+/// Whether `line` is nothing but whitespace.
+pub fn is_blank_line(line: &[u8]) -> bool {
+    line.iter().all(u8::is_ascii_whitespace)
+}
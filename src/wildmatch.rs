@@ -0,0 +1,195 @@
+/// Match `text` against the shell glob `pattern` the way git's `wildmatch` does.
+///
+/// `*` matches any run of characters and `?` any one, except that with `pathname`
+/// neither crosses a `/`; there `**` between slashes (or at either end) matches any
+/// number of directories. `[...]` matches a character class, with ranges, `!` or `^`
+/// to negate and POSIX classes like `[:alpha:]`. A backslash quotes the next
+/// character. With `ignore_case`, ASCII letters match either case.
+pub fn wildmatch(pattern: &str, text: &str, pathname: bool, ignore_case: bool) -> bool {
+    let flags = Flags {
+        pathname,
+        ignore_case,
+    };
+    dowild(pattern.as_bytes(), text.as_bytes(), flags) == Outcome::Match
+}
+
+#[derive(Clone, Copy)]
+struct Flags {
+    pathname: bool,
+    ignore_case: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Match,
+    NoMatch,
+    /// The text ran out: no later starting point for an enclosing `*` can match either.
+    AbortAll,
+    /// A `*` would have to match a `/`; only an enclosing `**` can still succeed.
+    AbortToStarstar,
+}
+
+fn dowild(pattern: &[u8], mut text: &[u8], flags: Flags) -> Outcome {
+    let fold = |c: u8| match flags.ignore_case {
+        true => c.to_ascii_lowercase(),
+        false => c,
+    };
+    let mut p = 0;
+    while p < pattern.len() {
+        let p_ch = pattern[p];
+        let Some(&t_ch) = text.first() else {
+            if p_ch != b'*' {
+                return Outcome::AbortAll;
+            }
+            return stars(pattern, p, text, flags);
+        };
+        match p_ch {
+            b'\\' if p + 1 < pattern.len() => {
+                p += 1;
+                if fold(t_ch) != fold(pattern[p]) {
+                    return Outcome::NoMatch;
+                }
+            }
+            b'?' => {
+                if flags.pathname && t_ch == b'/' {
+                    return Outcome::NoMatch;
+                }
+            }
+            b'*' => return stars(pattern, p, text, flags),
+            b'[' => match match_class(&pattern[p + 1..], t_ch, flags) {
+                Some((true, len)) if !(flags.pathname && t_ch == b'/') => p += len,
+                Some(_) => return Outcome::NoMatch,
+                // An unterminated class can never match.
+                None => return Outcome::AbortAll,
+            },
+            _ => {
+                if fold(t_ch) != fold(p_ch) {
+                    return Outcome::NoMatch;
+                }
+            }
+        }
+        p += 1;
+        text = &text[1..];
+    }
+    match text.is_empty() {
+        true => Outcome::Match,
+        false => Outcome::NoMatch,
+    }
+}
+
+/// Match the `*` or `**` at `pattern[p]`, and the rest of the pattern after it.
+fn stars(pattern: &[u8], mut p: usize, text: &[u8], flags: Flags) -> Outcome {
+    let start = p;
+    while p < pattern.len() && pattern[p] == b'*' {
+        p += 1;
+    }
+    let match_slash = if !flags.pathname {
+        true
+    } else if p - start >= 2 {
+        // `**` is only special as a whole path component.
+        let after_slash = start == 0 || pattern[start - 1] == b'/';
+        let before_slash = p == pattern.len() || pattern[p] == b'/';
+        if after_slash && before_slash {
+            // `**/` also matches no directories at all.
+            if p < pattern.len() && dowild(&pattern[p + 1..], text, flags) == Outcome::Match {
+                return Outcome::Match;
+            }
+            true
+        } else {
+            false
+        }
+    } else {
+        false
+    };
+    let rest = &pattern[p..];
+    if rest.is_empty() {
+        if !match_slash && text.contains(&b'/') {
+            return Outcome::AbortToStarstar;
+        }
+        return Outcome::Match;
+    }
+    let mut text = text;
+    loop {
+        let matched = dowild(rest, text, flags);
+        if matched != Outcome::NoMatch {
+            if !match_slash || matched != Outcome::AbortToStarstar {
+                return matched;
+            }
+        } else if !match_slash && text.first() == Some(&b'/') {
+            return Outcome::AbortToStarstar;
+        }
+        match text.split_first() {
+            Some((_, next)) => text = next,
+            None => return Outcome::AbortAll,
+        }
+    }
+}
+
+/// Match `ch` against the character class whose text (after the `[`) starts
+/// `class`: whether it matched, and the index of the closing `]` in `class` plus one
+/// (so that skipping that much from the `[` lands on the `]`). `None` if the class
+/// isn't closed.
+fn match_class(class: &[u8], ch: u8, flags: Flags) -> Option<(bool, usize)> {
+    let mut i = 0;
+    let negated = matches!(class.first(), Some(b'!' | b'^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    let alternatives = |ch: u8| match flags.ignore_case {
+        true => [ch.to_ascii_lowercase(), ch.to_ascii_uppercase()],
+        false => [ch, ch],
+    };
+    loop {
+        let mut c = *class.get(i)?;
+        if c == b']' && !first {
+            break;
+        }
+        first = false;
+        if c == b'\\' {
+            i += 1;
+            c = *class.get(i)?;
+        } else if c == b'[' && class.get(i + 1) == Some(&b':') {
+            let name_start = i + 2;
+            let name_len = class[name_start..].windows(2).position(|w| w == b":]")?;
+            let name = &class[name_start..name_start + name_len];
+            matched |= alternatives(ch)
+                .iter()
+                .any(|&ch| posix_class(name, ch, flags.ignore_case));
+            i = name_start + name_len + 2;
+            continue;
+        }
+        if class.get(i + 1) == Some(&b'-') && class.get(i + 2).is_some_and(|&end| end != b']') {
+            let mut end = class[i + 2];
+            i += 2;
+            if end == b'\\' {
+                i += 1;
+                end = *class.get(i)?;
+            }
+            matched |= alternatives(ch).iter().any(|ch| (c..=end).contains(ch));
+        } else {
+            matched |= alternatives(ch).contains(&c);
+        }
+        i += 1;
+    }
+    Some((matched != negated, i + 1))
+}
+
+fn posix_class(name: &[u8], ch: u8, ignore_case: bool) -> bool {
+    match name {
+        b"alnum" => ch.is_ascii_alphanumeric(),
+        b"alpha" => ch.is_ascii_alphabetic(),
+        b"blank" => ch == b' ' || ch == b'\t',
+        b"cntrl" => ch.is_ascii_control(),
+        b"digit" => ch.is_ascii_digit(),
+        b"graph" => ch.is_ascii_graphic(),
+        b"lower" => ch.is_ascii_lowercase() || (ignore_case && ch.is_ascii_uppercase()),
+        b"print" => ch.is_ascii_graphic() || ch == b' ',
+        b"punct" => ch.is_ascii_punctuation(),
+        b"space" => ch.is_ascii_whitespace() || ch == 0x0b,
+        b"upper" => ch.is_ascii_uppercase() || (ignore_case && ch.is_ascii_lowercase()),
+        b"xdigit" => ch.is_ascii_hexdigit(),
+        _ => false,
+    }
+}
@@ -0,0 +1,59 @@
+use crate::checkout::parse_hash;
+use crate::diff::diff_trees;
+use crate::index::Index;
+use crate::object_read::{Object, ObjectKind};
+use crate::refs::resolve_ref;
+use crate::repository::Repository;
+use crate::status::{WorktreeState, worktree_change};
+use anyhow::Result;
+
+/// Whether the index or the working directory differs from HEAD, as `git diff-index
+/// --quiet HEAD` tells: a change staged or not, or a conflict. Untracked files don't
+/// count, nor do ignored ones that are tracked.
+///
+/// The index is compared to HEAD's tree first, then each tracked file to its entry,
+/// taking a file whose stat data matches to be unchanged without reading it (see
+/// [`Index::is_stat_clean`]); the comparison stops at the first difference.
+pub fn is_dirty(repo: &Repository) -> Result<bool> {
+    let index = Index::load(repo)?;
+    if index_differs(repo, &index)? {
+        return Ok(true);
+    }
+    for entry in &index.entries {
+        if let WorktreeState::Changed(_) = worktree_change(repo, &index, entry)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Whether `index` differs from HEAD, as `git diff-index --cached --quiet HEAD`
+/// tells: a change staged, or a conflict. The working directory isn't looked at.
+pub fn index_differs(repo: &Repository, index: &Index) -> Result<bool> {
+    if index.entries.iter().any(|entry| entry.stage != 0) {
+        return Ok(true);
+    }
+    let head_files = match resolve_ref(repo, "HEAD")? {
+        Some(_) => {
+            let (tree, _) = Object::peel_to(repo, "HEAD", ObjectKind::Tree)?;
+            diff_trees(repo, None, Some(&parse_hash(&tree)?))?
+        }
+        None => Vec::new(),
+    };
+    if head_files.len() != index.entries.len() {
+        return Ok(true);
+    }
+    // Both are sorted by path, as git sorts them.
+    for (file, entry) in head_files.iter().zip(&index.entries) {
+        let Some(side) = &file.new else {
+            return Ok(true);
+        };
+        if file.path != entry.path
+            || side.hash != entry.id
+            || side.mode != format!("{:o}", entry.mode)
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
@@ -1,5 +1,9 @@
 use crate::hash_object::git_hash_object;
+use crate::index::Index;
+use crate::object_id::ObjectId;
 use crate::object_read::{Object, ObjectKind};
+use crate::repository::Repository;
+use crate::submodule::{submodule_git_dir, submodule_head};
 use anyhow::{Context, Result, bail};
 use ignore::WalkBuilder;
 use std::cmp::Ordering;
@@ -15,17 +19,17 @@ use std::path::Path;
 ///
 /// The raw (uncompressed) format of a tree object is a concatenation of entries:
 ///
-///     "<file mode> <file name>\0<20-byte binary object id>"
+///     "<file mode> <file name>\0<binary object id>"
 ///
 /// - `<file mode>`: ASCII digits like `100644` (normal file), `100755` (executable), or `40000` (directory)
 /// - `<file name>`: the file or directory name (no path separators)
-/// - `<20-byte binary object id>`: raw SHA-1/SHA-256 bytes of the referenced blob or tree
+/// - `<binary object id>`: raw SHA-1 (20 bytes) or SHA-256 (32 bytes) hash of the referenced blob or tree
 ///
 /// The full uncompressed content begins with the header:
 ///     "tree <size>\0<entries>"
 ///
 /// Steps:
-/// 1. Construct the concatenated entry list from directory contents.
+/// 1. Construct the concatenated entry list from the index entries of a directory.
 /// 2. Prefix it with `"tree <size>\0"`, where `<size>` is the total byte length of entries.
 /// 3. Compute the SHA-1 (or SHA-256) hash of this uncompressed data.
 /// 4. Hex-encode the hash to get the tree’s object ID.
@@ -47,13 +51,33 @@ use std::path::Path;
 /// Note: Tree objects form a hierarchy — a commit object references
 ///       the root tree, which may reference subtrees and blobs recursively.
 ///
-pub fn git_write_tree() -> Result<[u8; 20]> {
-    let Some(hash) = git_write_tree_with_path(Path::new("."))? else {
-        bail!("empty git repo")
+/// The tree is built from the index, so it records what `add` staged; an empty index
+/// gives the empty tree.
+pub fn git_write_tree(repo: &Repository) -> Result<ObjectId> {
+    git_write_tree_prefix(repo, "")
+}
+
+/// Like [`git_write_tree`], but write only the tree of the directory `prefix` (e.g.
+/// `lib/` or `lib`), as `git write-tree --prefix` does for subtree workflows. An
+/// empty prefix means the whole tree; one that isn't a directory with something to
+/// record is an error.
+pub fn git_write_tree_prefix(repo: &Repository, prefix: &str) -> Result<ObjectId> {
+    let prefix = prefix.strip_suffix('/').unwrap_or(prefix);
+    let valid = prefix
+        .split('/')
+        .all(|part| !matches!(part, "" | "." | ".." | ".git"));
+    let hash = match valid || prefix.is_empty() {
+        true => Index::load(repo)?.write_tree(repo, prefix)?,
+        false => None,
     };
-    Ok(hash)
+    hash.with_context(|| format!("git-write-tree: prefix {prefix} not found"))
 }
-pub fn git_write_tree_with_path(path: &Path) -> Result<Option<[u8; 20]>> {
+
+/// Snapshot the directory `path` of the working tree as it is, without going
+/// through the index, and return its tree (`None` if there is nothing to record).
+/// Ignored files are left out, and a submodule is recorded as the commit it has
+/// checked out.
+pub fn git_write_tree_with_path(repo: &Repository, path: &Path) -> Result<Option<ObjectId>> {
     let walker = WalkBuilder::new(path)
         .max_depth(Some(1))
         .hidden(false)
@@ -69,42 +93,41 @@ pub fn git_write_tree_with_path(path: &Path) -> Result<Option<[u8; 20]>> {
             }
         })
         .collect();
-    // In Git for directories we add / at the end for sorting
     entries.sort_unstable_by(|a, b| {
-        let af = a.file_name().as_encoded_bytes();
-        let bf = b.file_name().as_encoded_bytes();
-        let min_len = af.len().min(bf.len());
-        match af[..min_len].cmp(&bf[..min_len]) {
-            Ordering::Equal => {}
-            other => return other,
-        }
-        let a1 = af
-            .get(min_len)
-            .copied()
-            .or(a.path().is_dir().then_some(b'/'));
-        let b1 = bf
-            .get(min_len)
-            .copied()
-            .or(b.path().is_dir().then_some(b'/'));
-        a1.cmp(&b1)
+        compare_tree_entry_names(
+            a.file_name().as_encoded_bytes(),
+            is_tree_dir(a.path()),
+            b.file_name().as_encoded_bytes(),
+            is_tree_dir(b.path()),
+        )
     });
     let mut out = Vec::new();
     for entry in entries {
         let path = entry.path();
-        let hash = if path.is_dir() {
-            let Some(hash) = git_write_tree_with_path(path)? else {
+        let mut mode = get_mode_for_entry(&entry.metadata().context("reading metadata")?);
+        let hash = if is_tree_dir(path) {
+            let Some(hash) = git_write_tree_with_path(repo, path)? else {
                 continue;
             };
             hash
+        } else if path.is_dir() {
+            // A submodule is recorded as the commit it has checked out.
+            mode = "160000";
+            match submodule_head(path)? {
+                Some(hash) => hash,
+                None => bail!(
+                    "'{}/' does not have a commit checked out",
+                    path.strip_prefix(&repo.work_tree).unwrap_or(path).display()
+                ),
+            }
         } else {
-            git_hash_object(path, true)?
+            git_hash_object(repo, path, ObjectKind::Blob, true)?
         };
-        let mode = get_mode_for_entry(&entry.metadata().context("reading metadata")?);
         out.extend_from_slice(mode.as_bytes());
         out.push(b' ');
         out.extend_from_slice(entry.file_name().as_encoded_bytes());
         out.push(0);
-        out.extend(hash);
+        out.extend(hash.as_bytes());
     }
     if out.is_empty() {
         Ok(None)
@@ -114,11 +137,30 @@ pub fn git_write_tree_with_path(path: &Path) -> Result<Option<[u8; 20]>> {
             expected_size: out.len() as u64,
             reader: Cursor::new(out),
         };
-        let hash = object.write_as_object()?;
+        let hash = object.write_as_object(repo)?;
         Ok(Some(hash))
     }
 }
 
+/// Order tree entries the way Git does: by name, except that directories sort as if
+/// their name ended with `/`.
+pub fn compare_tree_entry_names(a: &[u8], a_is_dir: bool, b: &[u8], b_is_dir: bool) -> Ordering {
+    let min_len = a.len().min(b.len());
+    match a[..min_len].cmp(&b[..min_len]) {
+        Ordering::Equal => {}
+        other => return other,
+    }
+    let a1 = a.get(min_len).copied().or(a_is_dir.then_some(b'/'));
+    let b1 = b.get(min_len).copied().or(b_is_dir.then_some(b'/'));
+    a1.cmp(&b1)
+}
+
+/// Whether `path` is a directory that is stored as a tree, rather than a submodule
+/// with its own repository, which sorts and is recorded like a file.
+pub fn is_tree_dir(path: &Path) -> bool {
+    path.is_dir() && submodule_git_dir(path).is_none()
+}
+
 pub fn get_mode_for_entry(meta: &Metadata) -> &'static str {
     if meta.is_dir() {
         "40000"